@@ -13,7 +13,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use undermoon::broker::{
     configure_app, JsonFileStorage, JsonMetaReplicator, MemBrokerConfig, MemBrokerService,
-    MetaStorage, MetaStoreError, MetaSyncError,
+    MetaStorage, MetaStoreError, MetaSyncError, ReconcileResult, ReporterCountQuorum,
 };
 
 fn gen_conf() -> MemBrokerConfig {
@@ -65,6 +65,9 @@ fn gen_conf() -> MemBrokerConfig {
         sync_meta_interval: NonZeroU64::new(
             s.get::<u64>("sync_meta_interval").unwrap_or_else(|_| 0),
         ),
+        reconcile_interval: NonZeroU64::new(
+            s.get::<u64>("reconcile_interval").unwrap_or_else(|_| 0),
+        ),
         debug,
     }
 }
@@ -109,6 +112,29 @@ async fn sync_meta_to_replicas(service: Arc<MemBrokerService>, interval: Duratio
     }
 }
 
+async fn reconcile_proxies(service: Arc<MemBrokerService>, interval: Duration) {
+    loop {
+        Delay::new(interval).await;
+        trace!("periodically reconcile the metadata of proxies");
+        let ReconcileResult {
+            reconciled_addresses,
+            failed_addresses,
+        } = service.reconcile_proxies().await;
+        if !reconciled_addresses.is_empty() {
+            info!(
+                "reconciled the metadata of proxies: {:?}",
+                reconciled_addresses
+            );
+        }
+        if !failed_addresses.is_empty() {
+            warn!(
+                "failed to reconcile the metadata of proxies: {:?}",
+                failed_addresses
+            );
+        }
+    }
+}
+
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
@@ -117,6 +143,7 @@ async fn main() -> std::io::Result<()> {
     let address = config.address.clone();
     let update_file_interval = config.update_meta_file_interval;
     let sync_meta_interval = config.sync_meta_interval;
+    let reconcile_interval = config.reconcile_interval;
 
     let meta_storage = Arc::new(JsonFileStorage::new(config.meta_filename.clone()));
     let meta_store = if config.recover_from_meta_file {
@@ -132,8 +159,16 @@ async fn main() -> std::io::Result<()> {
     let meta_replicator = JsonMetaReplicator::new(config.replica_addresses.clone(), http_client);
     let meta_replicator = Arc::new(meta_replicator);
 
-    let service = MemBrokerService::new(config, meta_storage, meta_replicator, meta_store)
-        .map_err(meta_error_to_io_error)?;
+    let failure_quorum = Arc::new(ReporterCountQuorum::new(config.failure_quorum));
+
+    let service = MemBrokerService::new(
+        config,
+        meta_storage,
+        meta_replicator,
+        failure_quorum,
+        meta_store,
+    )
+    .map_err(meta_error_to_io_error)?;
     let service = Arc::new(service);
 
     if let Some(interval) = update_file_interval {
@@ -148,6 +183,12 @@ async fn main() -> std::io::Result<()> {
         actix_rt::spawn(sync_meta_to_replicas(service.clone(), interval));
     }
 
+    if let Some(interval) = reconcile_interval {
+        info!("start periodically reconciling the metadata of proxies");
+        let interval = Duration::from_secs(interval.get());
+        actix_rt::spawn(reconcile_proxies(service.clone(), interval));
+    }
+
     HttpServer::new(move || {
         let service = service.clone();
         App::new()
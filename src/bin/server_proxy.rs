@@ -11,7 +11,7 @@ use std::cmp::min;
 use std::env;
 use std::error::Error;
 use std::num::NonZeroUsize;
-use std::sync::atomic::{AtomicI64, AtomicU64};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64};
 use std::sync::Arc;
 use std::time::Duration;
 use string_error::into_err;
@@ -19,10 +19,21 @@ use undermoon::common::config::ClusterConfig;
 use undermoon::common::track::TrackedFutureRegistry;
 use undermoon::protocol::SimpleRedisClientFactory;
 use undermoon::proxy::backend::DefaultConnFactory;
+use undermoon::proxy::deadletter::DeadLetters;
 use undermoon::proxy::executor::SharedForwardHandler;
+use undermoon::proxy::keyspec::parse_key_specs;
 use undermoon::proxy::manager::MetaMap;
-use undermoon::proxy::service::{ServerProxyConfig, ServerProxyService};
+use undermoon::proxy::memory::MemoryBudget;
+use undermoon::proxy::service::{
+    parse_backend_max_in_flight_overrides, parse_db_byte_rate_quotas, parse_db_key_prefixes,
+    parse_default_clusters, parse_invalidation_channels, parse_rename_commands, ServerProxyConfig,
+    ServerProxyService, CROSS_SLOT_SPLIT_COMMANDS, DEFAULT_ACL_USER_RULES,
+    DEFAULT_DURABLE_WRITE_TIMEOUT, DEFAULT_HEDGED_READ_MAX_LAG, DEFAULT_LISTEN_BACKLOG,
+    DEFAULT_MAINTENANCE_MESSAGE, DEFAULT_MAX_COMMAND_ARGS, DEFAULT_READ_CACHE_MAX_ENTRIES,
+    DEFAULT_READ_CACHE_MAX_VALUE_SIZE, DEFAULT_SHUTDOWN_DRAIN_TIMEOUT,
+};
 use undermoon::proxy::slowlog::SlowRequestLogger;
+use undermoon::proxy::traffic::TrafficStats;
 use undermoon::MAX_REDIRECTIONS;
 
 fn gen_conf() -> Result<(ServerProxyConfig, ClusterConfig), &'static str> {
@@ -62,11 +73,144 @@ fn gen_conf() -> Result<(ServerProxyConfig, ClusterConfig), &'static str> {
     }
     let max_redirections = NonZeroUsize::new(max_redirections);
 
+    let shadow_address = s
+        .get::<String>("shadow_address")
+        .ok()
+        .filter(|address| !address.is_empty());
+    let shadow_percentage = s.get::<u64>("shadow_percentage").unwrap_or_else(|_| 0);
+    if shadow_percentage > 100 {
+        return Err("shadow_percentage");
+    }
+
+    let admin_address = s
+        .get::<String>("admin_address")
+        .ok()
+        .filter(|address| !address.is_empty());
+
+    let debug_allowlist = s
+        .get::<String>("debug_allowlist")
+        .unwrap_or_else(|_| "".to_string())
+        .split(',')
+        .map(|sub_cmd| sub_cmd.trim().to_uppercase())
+        .filter(|sub_cmd| !sub_cmd.is_empty())
+        .collect();
+
+    let read_cache_prefixes = s
+        .get::<String>("read_cache_prefixes")
+        .unwrap_or_else(|_| "".to_string())
+        .split(',')
+        .map(|prefix| prefix.trim().to_string())
+        .filter(|prefix| !prefix.is_empty())
+        .collect();
+
+    let active_redirection = s
+        .get::<bool>("active_redirection")
+        .unwrap_or_else(|_| false);
+    // Without this field, the active redirection mode splits all of them as before.
+    let default_split_commands = if active_redirection {
+        CROSS_SLOT_SPLIT_COMMANDS.join(",")
+    } else {
+        "".to_string()
+    };
+    let cross_slot_split_commands = s
+        .get::<String>("cross_slot_split_commands")
+        .unwrap_or_else(|_| default_split_commands)
+        .split(',')
+        .map(|cmd| cmd.trim().to_uppercase())
+        .filter(|cmd| !cmd.is_empty())
+        .filter(|cmd| {
+            let supported = CROSS_SLOT_SPLIT_COMMANDS.contains(&cmd.as_str());
+            if !supported {
+                warn!("ignore unsupported cross_slot_split_commands: {}", cmd);
+            }
+            supported
+        })
+        .collect();
+
+    let ip_default_clusters = parse_default_clusters(
+        &s.get::<String>("ip_default_clusters")
+            .unwrap_or_else(|_| "".to_string()),
+    )
+    .map_err(|err| {
+        error!("{}", err);
+        "ip_default_clusters"
+    })?;
+    let client_name_default_clusters = parse_default_clusters(
+        &s.get::<String>("client_name_default_clusters")
+            .unwrap_or_else(|_| "".to_string()),
+    )
+    .map_err(|err| {
+        error!("{}", err);
+        "client_name_default_clusters"
+    })?;
+
+    let rename_commands = parse_rename_commands(
+        &s.get::<String>("rename_commands")
+            .unwrap_or_else(|_| "".to_string()),
+    )
+    .map_err(|err| {
+        error!("{}", err);
+        "rename_commands"
+    })?;
+
+    let key_specs = parse_key_specs(
+        &s.get::<String>("key_specs")
+            .unwrap_or_else(|_| "".to_string()),
+    )
+    .map_err(|err| {
+        error!("{}", err);
+        "key_specs"
+    })?;
+
+    let invalidation_channels = parse_invalidation_channels(
+        &s.get::<String>("invalidation_channels")
+            .unwrap_or_else(|_| "".to_string()),
+    )
+    .map_err(|err| {
+        error!("{}", err);
+        "invalidation_channels"
+    })?;
+
+    let backend_max_in_flight_overrides = parse_backend_max_in_flight_overrides(
+        &s.get::<String>("backend_max_in_flight_overrides")
+            .unwrap_or_else(|_| "".to_string()),
+    )
+    .map_err(|err| {
+        error!("{}", err);
+        "backend_max_in_flight_overrides"
+    })?;
+
+    let db_byte_rate_quotas = parse_db_byte_rate_quotas(
+        &s.get::<String>("db_byte_rate_quotas")
+            .unwrap_or_else(|_| "".to_string()),
+    )
+    .map_err(|err| {
+        error!("{}", err);
+        "db_byte_rate_quotas"
+    })?;
+
+    let db_key_prefixes = parse_db_key_prefixes(
+        &s.get::<String>("db_key_prefixes")
+            .unwrap_or_else(|_| "".to_string()),
+    )
+    .map_err(|err| {
+        error!("{}", err);
+        "db_key_prefixes"
+    })?;
+
     let config = ServerProxyConfig {
         address: address.clone(),
         announce_address: s
             .get::<String>("announce_address")
             .unwrap_or_else(|_| address),
+        admin_address,
+        listen_backlog: s
+            .get::<i32>("listen_backlog")
+            .unwrap_or_else(|_| DEFAULT_LISTEN_BACKLOG),
+        listen_reuse_address: s
+            .get::<bool>("listen_reuse_address")
+            .unwrap_or_else(|_| true),
+        listen_reuse_port: s.get::<bool>("listen_reuse_port").unwrap_or_else(|_| false),
         auto_select_cluster: s
             .get::<bool>("auto_select_cluster")
             .unwrap_or_else(|_| true),
@@ -78,6 +222,21 @@ fn gen_conf() -> Result<(ServerProxyConfig, ClusterConfig), &'static str> {
         slowlog_sample_rate: AtomicU64::new(
             s.get::<u64>("slowlog_sample_rate").unwrap_or_else(|_| 1000),
         ),
+        slowlog_flush_file: s
+            .get::<String>("slowlog_flush_file")
+            .ok()
+            .filter(|path| !path.is_empty()),
+        slowlog_flush_interval: s.get::<u64>("slowlog_flush_interval").unwrap_or_else(|_| 0),
+        slowlog_flush_file_max_size: s
+            .get::<u64>("slowlog_flush_file_max_size")
+            .unwrap_or_else(|_| 0),
+        latency_monitor_threshold: AtomicU64::new(
+            s.get::<u64>("latency_monitor_threshold")
+                .unwrap_or_else(|_| 0),
+        ),
+        slowlog_latency_window: AtomicU64::new(
+            s.get::<u64>("slowlog_latency_window").unwrap_or_else(|_| 0),
+        ),
         thread_number,
         session_channel_size: s
             .get::<usize>("session_channel_size")
@@ -100,10 +259,100 @@ fn gen_conf() -> Result<(ServerProxyConfig, ClusterConfig), &'static str> {
             .get::<usize>("session_batch_max_time")
             .unwrap_or_else(|_| 400_000),
         session_batch_buf,
-        active_redirection: s
-            .get::<bool>("active_redirection")
-            .unwrap_or_else(|_| false),
+        active_redirection,
         max_redirections,
+        shadow_address,
+        shadow_percentage: AtomicU64::new(shadow_percentage),
+        shadow_write_enabled: s
+            .get::<bool>("shadow_write_enabled")
+            .unwrap_or_else(|_| false),
+        memory_soft_limit: s.get::<usize>("memory_soft_limit").unwrap_or_else(|_| 0),
+        memory_hard_limit: s.get::<usize>("memory_hard_limit").unwrap_or_else(|_| 0),
+        migration_read_your_writes_window: s
+            .get::<u64>("migration_read_your_writes_window")
+            .unwrap_or_else(|_| 0),
+        migration_stuck_timeout: s
+            .get::<u64>("migration_stuck_timeout")
+            .unwrap_or_else(|_| 60_000),
+        warm_backend_connections: s
+            .get::<bool>("warm_backend_connections")
+            .unwrap_or_else(|_| false),
+        client_command_timeout: s.get::<u64>("client_command_timeout").unwrap_or_else(|_| 0),
+        backend_response_timeout: s
+            .get::<u64>("backend_response_timeout")
+            .unwrap_or_else(|_| 0),
+        debug_allowlist,
+        ip_default_clusters,
+        client_name_default_clusters,
+        rename_commands,
+        max_command_args: AtomicU64::new(
+            s.get::<u64>("max_command_args")
+                .unwrap_or_else(|_| DEFAULT_MAX_COMMAND_ARGS),
+        ),
+        hedged_read_delay: AtomicU64::new(s.get::<u64>("hedged_read_delay").unwrap_or_else(|_| 0)),
+        hedged_read_max_lag: s
+            .get::<u64>("hedged_read_max_lag")
+            .unwrap_or_else(|_| DEFAULT_HEDGED_READ_MAX_LAG),
+        failover_retry_timeout: AtomicU64::new(
+            s.get::<u64>("failover_retry_timeout").unwrap_or_else(|_| 0),
+        ),
+        shutdown_passthrough: s
+            .get::<bool>("shutdown_passthrough")
+            .unwrap_or_else(|_| false),
+        shutdown_drain_timeout: s
+            .get::<u64>("shutdown_drain_timeout")
+            .unwrap_or_else(|_| DEFAULT_SHUTDOWN_DRAIN_TIMEOUT),
+        cross_slot_split_commands,
+        durable_write_timeout: AtomicU64::new(
+            s.get::<u64>("durable_write_timeout")
+                .unwrap_or_else(|_| DEFAULT_DURABLE_WRITE_TIMEOUT),
+        ),
+        stale_topology_timeout: s.get::<u64>("stale_topology_timeout").unwrap_or_else(|_| 0),
+        readonly_on_stale_topology: s
+            .get::<bool>("readonly_on_stale_topology")
+            .unwrap_or_else(|_| false),
+        key_specs,
+        acl_user_rules: s
+            .get::<String>("acl_user_rules")
+            .unwrap_or_else(|_| DEFAULT_ACL_USER_RULES.to_string()),
+        deadletter_sink: s
+            .get::<String>("deadletter_sink")
+            .unwrap_or_else(|_| "".to_string()),
+        invalidation_channels,
+        backend_max_in_flight: s
+            .get::<usize>("backend_max_in_flight")
+            .unwrap_or_else(|_| 0),
+        backend_max_in_flight_overrides,
+        backend_flap_threshold: s
+            .get::<usize>("backend_flap_threshold")
+            .unwrap_or_else(|_| 0),
+        backend_flap_window: s
+            .get::<u64>("backend_flap_window")
+            .unwrap_or_else(|_| 10000),
+        backend_quarantine_duration: s
+            .get::<u64>("backend_quarantine_duration")
+            .unwrap_or_else(|_| 30000),
+        maintenance_mode: AtomicBool::new(
+            s.get::<bool>("maintenance_mode").unwrap_or_else(|_| false),
+        ),
+        maintenance_message: ArcSwap::new(Arc::new(
+            s.get::<String>("maintenance_message")
+                .unwrap_or_else(|_| DEFAULT_MAINTENANCE_MESSAGE.to_string()),
+        )),
+        read_cache_prefixes,
+        read_cache_ttl: s.get::<u64>("read_cache_ttl").unwrap_or_else(|_| 0),
+        read_cache_max_value_size: s
+            .get::<usize>("read_cache_max_value_size")
+            .unwrap_or_else(|_| DEFAULT_READ_CACHE_MAX_VALUE_SIZE),
+        read_cache_max_entries: s
+            .get::<usize>("read_cache_max_entries")
+            .unwrap_or_else(|_| DEFAULT_READ_CACHE_MAX_ENTRIES),
+        db_byte_rate_quotas: ArcSwap::new(Arc::new(db_byte_rate_quotas)),
+        command_log: AtomicBool::new(s.get::<bool>("command_log").unwrap_or_else(|_| false)),
+        monitor_enabled: AtomicBool::new(
+            s.get::<bool>("monitor_enabled").unwrap_or_else(|_| false),
+        ),
+        db_key_prefixes,
     };
 
     let mut cluster_config = ClusterConfig::default();
@@ -113,6 +362,10 @@ fn gen_conf() -> Result<(ServerProxyConfig, ClusterConfig), &'static str> {
         "migration_max_blocking_time",
         "migration_scan_interval",
         "migration_scan_count",
+        "migration_wait_aof_timeout",
+        "migration_dst_ops_threshold",
+        "migration_throttle_delay",
+        "migration_blocking_queue_alert_len",
     ];
     for field in cluster_fields.iter() {
         if let Ok(value) = s.get::<String>(*field) {
@@ -143,20 +396,27 @@ fn main() -> Result<(), Box<dyn Error>> {
     let slow_request_logger = Arc::new(SlowRequestLogger::new(config.clone()));
     let meta_map = Arc::new(ArcSwap::new(Arc::new(MetaMap::empty())));
     let future_registry = Arc::new(TrackedFutureRegistry::default());
+    let memory_budget = Arc::new(MemoryBudget::from_config(&config));
+    let traffic_stats = Arc::new(TrafficStats::default());
 
     let forward_handler = SharedForwardHandler::new(
         config.clone(),
         cluster_config,
         Arc::new(client_factory),
         slow_request_logger.clone(),
+        memory_budget.clone(),
+        traffic_stats.clone(),
         meta_map,
         Arc::new(DefaultConnFactory::default()),
         future_registry.clone(),
+        Arc::new(DeadLetters::from_config(&config)),
     );
     let server = ServerProxyService::new(
         config.clone(),
         forward_handler,
         slow_request_logger,
+        memory_budget,
+        traffic_stats,
         future_registry,
     );
 
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+// Decides whether a proxy has failed from the failure reports
+// which are not expired yet.
+pub trait FailureQuorum {
+    // `reports` is reporter_id => report time in seconds.
+    fn is_failed(&self, proxy_address: &str, reports: &HashMap<String, i64>) -> bool;
+}
+
+// The default strategy.
+// A proxy is considered failed when at least `quorum` reporters reported it.
+pub struct ReporterCountQuorum {
+    quorum: u64,
+}
+
+impl ReporterCountQuorum {
+    pub fn new(quorum: u64) -> Self {
+        Self { quorum }
+    }
+}
+
+impl FailureQuorum for ReporterCountQuorum {
+    fn is_failed(&self, _proxy_address: &str, reports: &HashMap<String, i64>) -> bool {
+        reports.len() >= self.quorum as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::store::MetaStore;
+    use super::*;
+    use chrono::Utc;
+
+    const FAILED_PROXY: &str = "127.0.0.1:7001";
+
+    fn gen_store() -> MetaStore {
+        let mut store = MetaStore::default();
+        for i in 1..=3 {
+            let proxy_address = format!("127.0.0.1:70{:02}", i);
+            let node_addresses = [
+                format!("127.0.0.1:60{:02}", i * 2),
+                format!("127.0.0.1:60{:02}", i * 2 + 1),
+            ];
+            store
+                .add_proxy(proxy_address, node_addresses, None)
+                .unwrap();
+        }
+        store
+    }
+
+    // Inject synthetic failure reports without going through `add_failure`
+    // so that the report time can be specified.
+    fn inject_reports(store: &mut MetaStore, address: &str, reports: &[(&str, i64)]) {
+        let reporter_map = store
+            .failures
+            .entry(address.to_string())
+            .or_insert_with(HashMap::new);
+        for (reporter_id, report_time) in reports.iter() {
+            reporter_map.insert(reporter_id.to_string(), *report_time);
+        }
+    }
+
+    struct TrustedReporterQuorum;
+
+    impl FailureQuorum for TrustedReporterQuorum {
+        fn is_failed(&self, _proxy_address: &str, reports: &HashMap<String, i64>) -> bool {
+            reports.contains_key("trusted")
+        }
+    }
+
+    #[test]
+    fn test_reporter_count_quorum() {
+        let mut store = gen_store();
+        let now = Utc::now().timestamp();
+        inject_reports(&mut store, FAILED_PROXY, &[("r1", now), ("r2", now)]);
+
+        let ttl = chrono::Duration::max_value();
+        let quorum = ReporterCountQuorum::new(2);
+        assert_eq!(
+            store.get_failures_with_quorum(ttl, &quorum),
+            vec![FAILED_PROXY.to_string()]
+        );
+        let quorum = ReporterCountQuorum::new(3);
+        assert!(store.get_failures_with_quorum(ttl, &quorum).is_empty());
+    }
+
+    #[test]
+    fn test_expired_reports() {
+        let mut store = gen_store();
+        let now = Utc::now().timestamp();
+        inject_reports(&mut store, FAILED_PROXY, &[("r1", now), ("r2", now - 3600)]);
+
+        let ttl = chrono::Duration::seconds(60);
+        let quorum = ReporterCountQuorum::new(2);
+        assert!(store.get_failures_with_quorum(ttl, &quorum).is_empty());
+        let quorum = ReporterCountQuorum::new(1);
+        assert_eq!(
+            store.get_failures_with_quorum(ttl, &quorum),
+            vec![FAILED_PROXY.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_custom_quorum() {
+        let mut store = gen_store();
+        let now = Utc::now().timestamp();
+        inject_reports(&mut store, FAILED_PROXY, &[("r1", now), ("r2", now)]);
+        inject_reports(&mut store, "127.0.0.1:7002", &[("trusted", now)]);
+
+        let ttl = chrono::Duration::max_value();
+        assert_eq!(
+            store.get_failures_with_quorum(ttl, &TrustedReporterQuorum),
+            vec!["127.0.0.1:7002".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_unknown_proxy() {
+        let mut store = gen_store();
+        let now = Utc::now().timestamp();
+        inject_reports(&mut store, "127.0.0.1:9999", &[("r1", now)]);
+
+        let ttl = chrono::Duration::max_value();
+        let quorum = ReporterCountQuorum::new(1);
+        assert!(store.get_failures_with_quorum(ttl, &quorum).is_empty());
+    }
+}
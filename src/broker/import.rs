@@ -0,0 +1,723 @@
+use super::store::{
+    ChunkRolePosition, ChunkStore, ClusterStore, MetaStore, MetaStoreError, NODES_PER_PROXY,
+};
+use crate::common::cluster::{ClusterName, Range, RangeList, SlotRange, SlotRangeTag};
+use crate::common::config::ClusterConfig;
+use crate::common::utils::SLOT_NUM;
+use crate::protocol::{
+    Array, BulkStr, PooledRedisClientFactory, RedisClient, RedisClientFactory, Resp, RespVec,
+};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+// The topology of an existing Redis Cluster read from one of its nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedTopology {
+    pub seed_address: String,
+    pub masters: Vec<ImportedMaster>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedMaster {
+    pub node_id: String,
+    pub address: String,
+    pub slots: SlotRange,
+    pub replicas: Vec<String>,
+}
+
+// The proxy serving one of the masters.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ImportProxy {
+    pub proxy_address: String,
+    pub host: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Connection(String),
+    InvalidReply(String),
+    IncompleteSlots(RangeList),
+    UnsupportedTopology(String),
+    MetaStore(MetaStoreError),
+}
+
+impl ImportError {
+    pub fn to_code(&self) -> &str {
+        match self {
+            Self::Connection(_) => "SEED_NODE_CONNECTION_FAILED",
+            Self::InvalidReply(_) => "INVALID_SEED_NODE_REPLY",
+            Self::IncompleteSlots(_) => "INCOMPLETE_SLOTS",
+            Self::UnsupportedTopology(_) => "UNSUPPORTED_TOPOLOGY",
+            Self::MetaStore(err) => err.to_code(),
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::Connection(msg) | Self::InvalidReply(msg) | Self::UnsupportedTopology(msg) => {
+                msg.clone()
+            }
+            Self::IncompleteSlots(missing) => format!("slots not covered: {}", missing),
+            Self::MetaStore(err) => err.to_string(),
+        }
+    }
+}
+
+impl From<MetaStoreError> for ImportError {
+    fn from(err: MetaStoreError) -> Self {
+        ImportError::MetaStore(err)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_code())
+    }
+}
+
+impl Error for ImportError {
+    fn cause(&self) -> Option<&dyn Error> {
+        None
+    }
+}
+
+impl Serialize for ImportError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("ImportError", 2)?;
+        state.serialize_field("error", &self.to_string())?;
+        state.serialize_field("message", &self.message())?;
+        state.end()
+    }
+}
+
+pub async fn import_redis_cluster(seed_address: String) -> Result<ImportedTopology, ImportError> {
+    let timeout = Duration::from_secs(3);
+    let client_factory = PooledRedisClientFactory::new(1, timeout);
+    let mut client = client_factory
+        .create_client(seed_address.clone())
+        .await
+        .map_err(|err| ImportError::Connection(format!("{}: {}", seed_address, err)))?;
+
+    let cmd = vec![b"CLUSTER".to_vec(), b"NODES".to_vec()];
+    let nodes_reply = client
+        .execute_single(cmd)
+        .await
+        .map_err(|err| ImportError::Connection(format!("{}: {}", seed_address, err)))?;
+    let nodes = match nodes_reply {
+        Resp::Bulk(BulkStr::Str(s)) => {
+            parse_cluster_nodes(&String::from_utf8_lossy(&s)).map_err(ImportError::InvalidReply)?
+        }
+        other => {
+            return Err(ImportError::InvalidReply(format!(
+                "unexpected CLUSTER NODES reply: {:?}",
+                other
+            )))
+        }
+    };
+    let cmd = vec![b"CLUSTER".to_vec(), b"SLOTS".to_vec()];
+    let slots_reply = client
+        .execute_single(cmd)
+        .await
+        .map_err(|err| ImportError::Connection(format!("{}: {}", seed_address, err)))?;
+    let slot_owners = parse_cluster_slots(&slots_reply).map_err(ImportError::InvalidReply)?;
+
+    build_topology(seed_address, nodes, slot_owners)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ClusterNode {
+    node_id: String,
+    address: String,
+    flags: Vec<String>,
+    master_id: Option<String>,
+    connected: bool,
+    slots: Vec<Range>,
+    // The slots in the form of [slot->-node] or [slot-<-node].
+    migrating_slots: Vec<String>,
+}
+
+impl ClusterNode {
+    fn is_master(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "master")
+    }
+}
+
+// <id> <ip:port@cport[,hostname]> <flags> <master> <ping-sent> <pong-recv> <config-epoch> <link-state> <slot> ...
+fn parse_cluster_nodes(s: &str) -> Result<Vec<ClusterNode>, String> {
+    let mut nodes = vec![];
+    for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.len() < 8 {
+            return Err(format!("invalid CLUSTER NODES line: {}", line));
+        }
+        // The cluster bus port and the hostname are not needed.
+        let address = fields[1]
+            .split(|c| c == '@' || c == ',')
+            .next()
+            .unwrap_or("")
+            .to_string();
+        let master_id = match fields[3] {
+            "-" => None,
+            master_id => Some(master_id.to_string()),
+        };
+        let mut slots = vec![];
+        let mut migrating_slots = vec![];
+        for slot in fields[8..].iter() {
+            if slot.starts_with('[') {
+                migrating_slots.push(slot.to_string());
+                continue;
+            }
+            slots.push(parse_slot_range(slot).ok_or_else(|| format!("invalid slot: {}", slot))?);
+        }
+        nodes.push(ClusterNode {
+            node_id: fields[0].to_string(),
+            address,
+            flags: fields[2].split(',').map(ToString::to_string).collect(),
+            master_id,
+            connected: fields[7] == "connected",
+            slots,
+            migrating_slots,
+        });
+    }
+    Ok(nodes)
+}
+
+fn parse_slot_range(s: &str) -> Option<Range> {
+    let mut it = s.splitn(2, '-');
+    let start = it.next()?.parse::<usize>().ok()?;
+    let end = match it.next() {
+        Some(end) => end.parse::<usize>().ok()?,
+        None => start,
+    };
+    if start > end || end >= SLOT_NUM {
+        return None;
+    }
+    Some(Range(start, end))
+}
+
+// Returns (slot range, master address).
+fn parse_cluster_slots(resp: &RespVec) -> Result<Vec<(Range, String)>, String> {
+    let invalid = || format!("invalid CLUSTER SLOTS reply: {:?}", resp);
+    let entries = match resp {
+        Resp::Arr(Array::Arr(entries)) => entries,
+        _ => return Err(invalid()),
+    };
+    let mut slot_owners = vec![];
+    for entry in entries.iter() {
+        let elements = match entry {
+            Resp::Arr(Array::Arr(elements)) if elements.len() >= 3 => elements,
+            _ => return Err(invalid()),
+        };
+        let int = |resp: &RespVec| match resp {
+            Resp::Integer(n) => btoi::btou::<usize>(n).ok(),
+            _ => None,
+        };
+        let start = int(&elements[0]).ok_or_else(invalid)?;
+        let end = int(&elements[1]).ok_or_else(invalid)?;
+        if start > end || end >= SLOT_NUM {
+            return Err(invalid());
+        }
+        let address = match &elements[2] {
+            Resp::Arr(Array::Arr(node)) if node.len() >= 2 => {
+                let ip = match &node[0] {
+                    Resp::Bulk(BulkStr::Str(ip)) => String::from_utf8_lossy(ip).to_string(),
+                    _ => return Err(invalid()),
+                };
+                let port = int(&node[1]).ok_or_else(invalid)?;
+                format!("{}:{}", ip, port)
+            }
+            _ => return Err(invalid()),
+        };
+        slot_owners.push((Range(start, end), address));
+    }
+    Ok(slot_owners)
+}
+
+fn build_topology(
+    seed_address: String,
+    nodes: Vec<ClusterNode>,
+    slot_owners: Vec<(Range, String)>,
+) -> Result<ImportedTopology, ImportError> {
+    let mut warnings = vec![];
+    for node in nodes.iter() {
+        let bad_flags: Vec<&str> = node
+            .flags
+            .iter()
+            .map(String::as_str)
+            .filter(|flag| ["fail", "fail?", "handshake", "noaddr"].contains(flag))
+            .collect();
+        if !bad_flags.is_empty() {
+            warnings.push(format!(
+                "node {} {} has flags {}",
+                node.node_id,
+                node.address,
+                bad_flags.join(",")
+            ));
+        }
+        if !node.connected {
+            warnings.push(format!(
+                "node {} {} is disconnected",
+                node.node_id, node.address
+            ));
+        }
+        if !node.migrating_slots.is_empty() {
+            warnings.push(format!(
+                "node {} {} has slots in migration: {}",
+                node.node_id,
+                node.address,
+                node.migrating_slots.join(" ")
+            ));
+        }
+    }
+
+    let mut slot_map: Vec<Option<&str>> = vec![None; SLOT_NUM];
+    for node in nodes.iter().filter(|node| node.is_master()) {
+        for range in node.slots.iter() {
+            for owner in slot_map[range.start()..=range.end()].iter_mut() {
+                if let Some(other) = owner {
+                    if *other != node.address {
+                        warnings.push(format!(
+                            "slots {}-{} are owned by both {} and {}",
+                            range.start(),
+                            range.end(),
+                            other,
+                            node.address
+                        ));
+                        break;
+                    }
+                }
+                *owner = Some(node.address.as_str());
+            }
+        }
+    }
+
+    // CLUSTER SLOTS could differ from CLUSTER NODES when the addresses are announced differently.
+    for (range, address) in slot_owners.iter() {
+        let matched = slot_map[range.start()..=range.end()]
+            .iter()
+            .all(|owner| *owner == Some(address.as_str()));
+        if !matched {
+            warnings.push(format!(
+                "CLUSTER SLOTS shows slots {}-{} on {} which is different from CLUSTER NODES",
+                range.start(),
+                range.end(),
+                address
+            ));
+        }
+    }
+
+    let missing: Vec<Range> = (0..SLOT_NUM)
+        .filter(|slot| slot_map[*slot].is_none())
+        .map(|slot| Range(slot, slot))
+        .collect();
+    if !missing.is_empty() {
+        return Err(ImportError::IncompleteSlots(RangeList::merge(vec![
+            RangeList::new(missing),
+        ])));
+    }
+
+    let mut replicas: HashMap<&str, Vec<String>> = HashMap::new();
+    for node in nodes.iter().filter(|node| !node.is_master()) {
+        if let Some(master_id) = node.master_id.as_ref() {
+            replicas
+                .entry(master_id.as_str())
+                .or_insert_with(Vec::new)
+                .push(node.address.clone());
+        }
+    }
+
+    let mut masters: Vec<ImportedMaster> = nodes
+        .iter()
+        .filter(|node| node.is_master() && !node.slots.is_empty())
+        .map(|node| {
+            let replicas = replicas.remove(node.node_id.as_str()).unwrap_or_default();
+            if replicas.is_empty() {
+                warnings.push(format!(
+                    "master {} {} has no replica",
+                    node.node_id, node.address
+                ));
+            }
+            ImportedMaster {
+                node_id: node.node_id.clone(),
+                address: node.address.clone(),
+                slots: SlotRange {
+                    range_list: RangeList::merge(vec![RangeList::new(node.slots.clone())]),
+                    tag: SlotRangeTag::None,
+                },
+                replicas,
+            }
+        })
+        .collect();
+    masters.sort_by(|a, b| a.address.cmp(&b.address));
+
+    Ok(ImportedTopology {
+        seed_address,
+        masters,
+        warnings,
+    })
+}
+
+pub struct MetaStoreImport<'a> {
+    store: &'a mut MetaStore,
+}
+
+impl<'a> MetaStoreImport<'a> {
+    pub fn new(store: &'a mut MetaStore) -> Self {
+        Self { store }
+    }
+
+    // Every two masters with their first replicas are placed in one chunk
+    // served by the two proxies given for them in order.
+    // Like the chunks created by the broker, each proxy has one master
+    // and the replica of the other master.
+    // Either the whole cluster is imported or nothing is changed.
+    pub fn import_cluster(
+        &mut self,
+        cluster_name: String,
+        topo: &ImportedTopology,
+        proxies: Vec<ImportProxy>,
+    ) -> Result<(), ImportError> {
+        let name = ClusterName::try_from(cluster_name.as_str())
+            .map_err(|_| MetaStoreError::InvalidClusterName)?;
+        if self.store.clusters.contains_key(&name) {
+            return Err(MetaStoreError::AlreadyExisted.into());
+        }
+        if topo.masters.len() % 2 != 0 {
+            return Err(ImportError::UnsupportedTopology(format!(
+                "the number of masters {} should be even",
+                topo.masters.len()
+            )));
+        }
+        if proxies.len() != topo.masters.len() {
+            return Err(ImportError::UnsupportedTopology(format!(
+                "{} proxies are required, one for each master",
+                topo.masters.len()
+            )));
+        }
+        let unique_addresses: HashSet<&String> =
+            proxies.iter().map(|proxy| &proxy.proxy_address).collect();
+        if unique_addresses.len() != proxies.len() {
+            return Err(MetaStoreError::InvalidProxyAddress.into());
+        }
+        let mut replicas = vec![];
+        for master in topo.masters.iter() {
+            match master.replicas.first() {
+                Some(replica) => replicas.push(replica.clone()),
+                None => {
+                    return Err(ImportError::UnsupportedTopology(format!(
+                        "master {} has no replica",
+                        master.address
+                    )))
+                }
+            }
+        }
+
+        // Any failure in the middle leaves the copy behind.
+        let mut store = self.store.clone();
+        let mut chunks = vec![];
+        for (i, pair) in proxies.chunks(2).enumerate() {
+            let (a, b) = (2 * i, 2 * i + 1);
+            let nodes_list = [
+                [topo.masters[a].address.clone(), replicas[b].clone()],
+                [topo.masters[b].address.clone(), replicas[a].clone()],
+            ];
+            let mut hosts = vec![];
+            for (proxy, nodes) in pair.iter().zip(nodes_list.iter()) {
+                let host = add_import_proxy(&mut store, proxy, nodes)?;
+                hosts.push(host);
+            }
+            chunks.push(ChunkStore {
+                role_position: ChunkRolePosition::Normal,
+                stable_slots: [
+                    Some(topo.masters[a].slots.clone()),
+                    Some(topo.masters[b].slots.clone()),
+                ],
+                migrating_slots: [vec![], vec![]],
+                proxy_addresses: [pair[0].proxy_address.clone(), pair[1].proxy_address.clone()],
+                hosts: [hosts[0].clone(), hosts[1].clone()],
+                node_addresses: [
+                    nodes_list[0][0].clone(),
+                    nodes_list[0][1].clone(),
+                    nodes_list[1][0].clone(),
+                    nodes_list[1][1].clone(),
+                ],
+            });
+        }
+
+        for proxy in proxies.iter() {
+            if let Some(resource) = store.all_proxies.get_mut(&proxy.proxy_address) {
+                resource.cluster = Some(name.clone());
+            }
+        }
+        let epoch = store.bump_global_epoch();
+        let cluster_store = ClusterStore {
+            epoch,
+            name: name.clone(),
+            chunks,
+            config: ClusterConfig::default(),
+        };
+        store.clusters.insert(name, cluster_store);
+
+        *self.store = store;
+        Ok(())
+    }
+}
+
+// The proxies added before but not used yet are reused. Returns the host of the proxy.
+fn add_import_proxy(
+    store: &mut MetaStore,
+    proxy: &ImportProxy,
+    nodes: &[String; NODES_PER_PROXY],
+) -> Result<String, MetaStoreError> {
+    match store.all_proxies.get(&proxy.proxy_address) {
+        Some(existing) if existing.cluster.is_some() => return Err(MetaStoreError::InUse),
+        Some(existing) if &existing.node_addresses != nodes => {
+            return Err(MetaStoreError::AlreadyExisted)
+        }
+        Some(_) => (),
+        None => store.add_proxy(
+            proxy.proxy_address.clone(),
+            nodes.clone(),
+            proxy.host.clone(),
+        )?,
+    }
+    store
+        .all_proxies
+        .get(&proxy.proxy_address)
+        .map(|resource| resource.host.clone())
+        .ok_or(MetaStoreError::ProxyNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::cluster::Role;
+
+    const CLUSTER_NODES: &str = "\
+07c37dfeb235213a872192d90877d0cd55635b91 127.0.0.1:30004@31004 slave e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 0 1426238317239 4 connected
+67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 127.0.0.1:30002@31002 master - 0 1426238316232 2 connected 5461-10922
+292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f 127.0.0.1:30003@31003 master - 0 1426238318243 3 connected 10923-16383
+e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:30001@31001,host1 myself,master - 0 0 1 connected 0-5460
+";
+
+    fn gen_slot_owners() -> Vec<(Range, String)> {
+        vec![
+            (Range(0, 5460), "127.0.0.1:30001".to_string()),
+            (Range(5461, 10922), "127.0.0.1:30002".to_string()),
+            (Range(10923, 16383), "127.0.0.1:30003".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_parse_cluster_nodes() {
+        let nodes = parse_cluster_nodes(CLUSTER_NODES).unwrap();
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(nodes[0].address, "127.0.0.1:30004");
+        assert!(!nodes[0].is_master());
+        assert_eq!(
+            nodes[0].master_id.as_deref(),
+            Some("e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca")
+        );
+        assert_eq!(nodes[3].address, "127.0.0.1:30001");
+        assert!(nodes[3].is_master());
+        assert_eq!(nodes[3].slots, vec![Range(0, 5460)]);
+
+        let nodes = parse_cluster_nodes(
+            "id 127.0.0.1:30001@31001 master - 0 0 1 connected 1 3-4 [5->-xxx]",
+        )
+        .unwrap();
+        assert_eq!(nodes[0].slots, vec![Range(1, 1), Range(3, 4)]);
+        assert_eq!(nodes[0].migrating_slots, vec!["[5->-xxx]".to_string()]);
+
+        assert!(parse_cluster_nodes("id 127.0.0.1:30001 master").is_err());
+        assert!(
+            parse_cluster_nodes("id 127.0.0.1:30001 master - 0 0 1 connected 0-16384").is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_cluster_slots() {
+        let node = |ip: &str, port: &str| {
+            Resp::Arr(Array::Arr(vec![
+                Resp::Bulk(BulkStr::Str(ip.as_bytes().to_vec())),
+                Resp::Integer(port.as_bytes().to_vec()),
+            ]))
+        };
+        let resp = Resp::Arr(Array::Arr(vec![Resp::Arr(Array::Arr(vec![
+            Resp::Integer(b"0".to_vec()),
+            Resp::Integer(b"5460".to_vec()),
+            node("127.0.0.1", "30001"),
+            node("127.0.0.1", "30004"),
+        ]))]));
+        let slot_owners = parse_cluster_slots(&resp).unwrap();
+        assert_eq!(
+            slot_owners,
+            vec![(Range(0, 5460), "127.0.0.1:30001".to_string())]
+        );
+        assert!(parse_cluster_slots(&Resp::Simple(b"OK".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_build_topology() {
+        let nodes = parse_cluster_nodes(CLUSTER_NODES).unwrap();
+        let topo = build_topology("127.0.0.1:30001".to_string(), nodes, gen_slot_owners()).unwrap();
+        assert_eq!(topo.masters.len(), 3);
+        let master = &topo.masters[0];
+        assert_eq!(master.address, "127.0.0.1:30001");
+        assert_eq!(master.replicas, vec!["127.0.0.1:30004".to_string()]);
+        assert_eq!(master.slots.range_list.get_ranges(), &[Range(0, 5460)]);
+        // The other two masters have no replica.
+        assert_eq!(topo.warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_build_topology_with_discrepancies() {
+        let nodes = parse_cluster_nodes(CLUSTER_NODES).unwrap();
+        let mut slot_owners = gen_slot_owners();
+        slot_owners[1].1 = "10.0.0.1:30002".to_string();
+        let topo = build_topology("127.0.0.1:30001".to_string(), nodes, slot_owners).unwrap();
+        assert!(topo
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("10.0.0.1:30002")));
+
+        let lines: Vec<&str> = CLUSTER_NODES
+            .lines()
+            .filter(|line| !line.contains("30003"))
+            .collect();
+        let nodes = parse_cluster_nodes(&lines.join("\n")).unwrap();
+        match build_topology("127.0.0.1:30001".to_string(), nodes, gen_slot_owners()) {
+            Err(ImportError::IncompleteSlots(missing)) => {
+                assert_eq!(missing.get_ranges(), &[Range(10923, 16383)])
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn gen_imported_topology() -> ImportedTopology {
+        let master = |port: usize, start: usize, end: usize| ImportedMaster {
+            node_id: format!("node{}", port),
+            address: format!("127.0.0.1:{}", port),
+            slots: SlotRange {
+                range_list: RangeList::from_single_range(Range(start, end)),
+                tag: SlotRangeTag::None,
+            },
+            replicas: vec![format!("127.0.0.1:{}", port + 10)],
+        };
+        ImportedTopology {
+            seed_address: "127.0.0.1:30001".to_string(),
+            masters: vec![
+                master(30001, 0, 4095),
+                master(30002, 4096, 8191),
+                master(30003, 8192, 12287),
+                master(30004, 12288, 16383),
+            ],
+            warnings: vec![],
+        }
+    }
+
+    fn gen_import_proxies(num: usize) -> Vec<ImportProxy> {
+        (0..num)
+            .map(|i| ImportProxy {
+                proxy_address: format!("127.0.0.1:{}", 7000 + i),
+                host: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_import_cluster() {
+        let mut store = MetaStore::default();
+        let topo = gen_imported_topology();
+        store
+            .import_cluster("mycluster".to_string(), &topo, gen_import_proxies(4))
+            .unwrap();
+        assert_eq!(store.all_proxies.len(), 4);
+        assert!(store.all_proxies.values().all(|proxy| proxy
+            .cluster
+            .as_ref()
+            .map(ToString::to_string)
+            == Some("mycluster".to_string())));
+
+        let cluster = store.get_cluster_by_name("mycluster", 0).unwrap();
+        let nodes = cluster.get_nodes();
+        assert_eq!(nodes.len(), 8);
+
+        let master = nodes
+            .iter()
+            .find(|node| node.get_address() == "127.0.0.1:30001")
+            .unwrap();
+        assert_eq!(master.get_role(), Role::Master);
+        assert_eq!(master.get_proxy_address(), "127.0.0.1:7000");
+        assert_eq!(
+            master.get_slots()[0].range_list.get_ranges(),
+            &[Range(0, 4095)]
+        );
+        // The replica of 30001 is served by the proxy of the other master in the chunk.
+        let peers = master.get_repl_meta().get_peers();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].node_address, "127.0.0.1:30011");
+        assert_eq!(peers[0].proxy_address, "127.0.0.1:7001");
+
+        let replica = nodes
+            .iter()
+            .find(|node| node.get_address() == "127.0.0.1:30011")
+            .unwrap();
+        assert_eq!(replica.get_role(), Role::Replica);
+        assert!(replica.get_slots().is_empty());
+    }
+
+    #[test]
+    fn test_import_invalid_cluster() {
+        let mut store = MetaStore::default();
+        let mut topo = gen_imported_topology();
+
+        match store.import_cluster("mycluster".to_string(), &topo, gen_import_proxies(3)) {
+            Err(ImportError::UnsupportedTopology(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        topo.masters[3].replicas.clear();
+        match store.import_cluster("mycluster".to_string(), &topo, gen_import_proxies(4)) {
+            Err(ImportError::UnsupportedTopology(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        topo.masters.pop();
+        match store.import_cluster("mycluster".to_string(), &topo, gen_import_proxies(3)) {
+            Err(ImportError::UnsupportedTopology(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // Nothing is left behind by the failed imports.
+        assert!(store.all_proxies.is_empty());
+        assert!(store.clusters.is_empty());
+        assert_eq!(store.global_epoch, 0);
+    }
+
+    #[test]
+    fn test_import_existing_cluster() {
+        let mut store = MetaStore::default();
+        let topo = gen_imported_topology();
+        store
+            .import_cluster("mycluster".to_string(), &topo, gen_import_proxies(4))
+            .unwrap();
+        match store.import_cluster("mycluster".to_string(), &topo, gen_import_proxies(4)) {
+            Err(ImportError::MetaStore(MetaStoreError::AlreadyExisted)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // The proxies are already in use.
+        match store.import_cluster("othercluster".to_string(), &topo, gen_import_proxies(4)) {
+            Err(ImportError::MetaStore(MetaStoreError::InUse)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(store.clusters.len(), 1);
+    }
+}
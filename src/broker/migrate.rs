@@ -1,13 +1,130 @@
 use super::store::{
     ClusterStore, MetaStore, MetaStoreError, MigrationMetaStore, MigrationSlotRangeStore,
-    MigrationSlots, CHUNK_NODE_NUM,
+    MigrationSlots, CHUNK_NODE_NUM, CHUNK_PARTS,
 };
 use crate::common::cluster::ClusterName;
-use crate::common::cluster::{MigrationTaskMeta, Range, RangeList, SlotRange, SlotRangeTag};
+use crate::common::cluster::{
+    MigrationTaskMeta, Range, RangeList, RangeMap, SlotRange, SlotRangeTag,
+};
 use crate::common::utils::SLOT_NUM;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::cmp::min;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 
+// A slot range move proposed by the user.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MigrationMove {
+    pub src_node_address: String,
+    pub dst_node_address: String,
+    pub range_list: RangeList,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationPlanError {
+    EmptyPlan,
+    MigrationRunning,
+    UnknownNode {
+        move_index: usize,
+        address: String,
+    },
+    SameNode {
+        move_index: usize,
+    },
+    InvalidSlots {
+        move_index: usize,
+        range_list: RangeList,
+    },
+    OverlappingSlots {
+        move_index: usize,
+        range_list: RangeList,
+    },
+    SlotsNotOwned {
+        move_index: usize,
+        range_list: RangeList,
+    },
+    UncoveredSlots(RangeList),
+}
+
+impl MigrationPlanError {
+    pub fn to_code(&self) -> &str {
+        match self {
+            Self::EmptyPlan => "EMPTY_PLAN",
+            Self::MigrationRunning => "MIGRATION_RUNNING",
+            Self::UnknownNode { .. } => "UNKNOWN_NODE",
+            Self::SameNode { .. } => "SAME_SRC_AND_DST",
+            Self::InvalidSlots { .. } => "INVALID_SLOTS",
+            Self::OverlappingSlots { .. } => "OVERLAPPING_SLOTS",
+            Self::SlotsNotOwned { .. } => "SLOTS_NOT_OWNED_BY_SRC",
+            Self::UncoveredSlots(_) => "SLOTS_NOT_COVERED",
+        }
+    }
+
+    fn get_move_index(&self) -> Option<usize> {
+        match self {
+            Self::EmptyPlan | Self::MigrationRunning | Self::UncoveredSlots(_) => None,
+            Self::UnknownNode { move_index, .. }
+            | Self::SameNode { move_index }
+            | Self::InvalidSlots { move_index, .. }
+            | Self::OverlappingSlots { move_index, .. }
+            | Self::SlotsNotOwned { move_index, .. } => Some(*move_index),
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::EmptyPlan => "no slot is moved".to_string(),
+            Self::MigrationRunning => "another migration is still running".to_string(),
+            Self::UnknownNode { address, .. } => {
+                format!("{} is not a master of the cluster", address)
+            }
+            Self::SameNode { .. } => "the source and the destination are the same".to_string(),
+            Self::InvalidSlots { range_list, .. } => format!("invalid slots: {}", range_list),
+            Self::OverlappingSlots { range_list, .. } => {
+                format!("slots moved more than once: {}", range_list)
+            }
+            Self::SlotsNotOwned { range_list, .. } => {
+                format!("slots not owned by the source: {}", range_list)
+            }
+            Self::UncoveredSlots(range_list) => format!("slots not covered: {}", range_list),
+        }
+    }
+}
+
+impl Serialize for MigrationPlanError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("MigrationPlanError", 3)?;
+        state.serialize_field("error", self.to_code())?;
+        state.serialize_field("message", &self.message())?;
+        state.serialize_field("move_index", &self.get_move_index())?;
+        state.end()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationPlanImpact {
+    pub migrated_slots: usize,
+    pub task_num: usize,
+    // Started at once, limited by `migration_limit` and the migrating out tasks of each master.
+    pub running_task_num: usize,
+    pub pending_task_num: usize,
+    pub estimated_rounds: usize,
+    // master node address => slot number after the plan is done
+    pub slots_after: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationPlanValidation {
+    pub valid: bool,
+    pub full_coverage: bool,
+    pub exceeds_migration_limit: bool,
+    pub impact: MigrationPlanImpact,
+    pub errors: Vec<MigrationPlanError>,
+}
+
 pub struct MetaStoreMigrate<'a> {
     store: &'a mut MetaStore,
 }
@@ -436,6 +553,169 @@ impl<'a> MetaStoreMigrate<'a> {
         migration_slots
     }
 
+    pub fn rebalance_slots(
+        &mut self,
+        cluster_name: String,
+        tolerance: usize,
+        dry_run: bool,
+    ) -> Result<Vec<SlotRange>, MetaStoreError> {
+        let cluster_name = ClusterName::try_from(cluster_name.as_str())
+            .map_err(|_| MetaStoreError::InvalidClusterName)?;
+
+        let mut planned = match self.store.clusters.get(&cluster_name) {
+            None => return Err(MetaStoreError::ClusterNotFound),
+            Some(cluster) => cluster.clone(),
+        };
+
+        Self::check_running_tasks(&planned)?;
+
+        let mut migration_slots = Self::remove_slots_to_rebalance(&mut planned, tolerance);
+        if migration_slots.is_empty() || dry_run {
+            return Ok(Self::migration_slots_to_slot_ranges(
+                &planned,
+                &migration_slots,
+            ));
+        }
+
+        let new_epoch = self.store.bump_global_epoch();
+        for slots in migration_slots.iter_mut() {
+            slots.meta.epoch = new_epoch;
+        }
+        Self::assign_dst_slots(&mut planned, migration_slots.clone());
+        planned.set_epoch(new_epoch);
+
+        Self::print_migration_slot(&planned, &migration_slots);
+        let slot_ranges = Self::migration_slots_to_slot_ranges(&planned, &migration_slots);
+        self.store.clusters.insert(cluster_name, planned);
+        Ok(slot_ranges)
+    }
+
+    // Moves slots from the masters above the target slot number
+    // to the ones below it. Returns nothing if the difference between
+    // the largest and the smallest master is within the tolerance.
+    fn remove_slots_to_rebalance(
+        cluster: &mut ClusterStore,
+        tolerance: usize,
+    ) -> Vec<MigrationSlots> {
+        let slot_nums: Vec<usize> = cluster
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.stable_slots.iter())
+            .map(|slots| {
+                slots
+                    .as_ref()
+                    .map(|slots| slots.get_range_list().get_slots_num())
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let (max_num, min_num) = match (slot_nums.iter().max(), slot_nums.iter().min()) {
+            (Some(max_num), Some(min_num)) => (*max_num, *min_num),
+            _ => return vec![],
+        };
+        if max_num - min_num <= tolerance {
+            return vec![];
+        }
+
+        let master_num = slot_nums.len();
+        let average = SLOT_NUM / master_num;
+        let remainder = SLOT_NUM - average * master_num;
+
+        // The masters with the most slots keep the remainder to move as few slots as possible.
+        let mut order: Vec<usize> = (0..master_num).collect();
+        order.sort_by_key(|i| std::cmp::Reverse(slot_nums[*i]));
+        let mut targets = vec![average; master_num];
+        for i in order.into_iter().take(remainder) {
+            targets[i] += 1;
+        }
+
+        let mut surplus: Vec<(usize, usize)> = (0..master_num)
+            .filter(|i| slot_nums[*i] > targets[*i])
+            .map(|i| (i, slot_nums[i] - targets[i]))
+            .collect();
+        let mut deficit: Vec<(usize, usize)> = (0..master_num)
+            .filter(|i| slot_nums[*i] < targets[*i])
+            .map(|i| (i, targets[i] - slot_nums[i]))
+            .collect();
+
+        let mut migration_slots = vec![];
+        let (mut src_cursor, mut dst_cursor) = (0, 0);
+        while src_cursor < surplus.len() && dst_cursor < deficit.len() {
+            let (src_index, src_num) = surplus[src_cursor];
+            let (dst_index, dst_num) = deficit[dst_cursor];
+            let num = min(src_num, dst_num);
+
+            let src_slots = cluster
+                .chunks
+                .get_mut(src_index / 2)
+                .and_then(|chunk| chunk.stable_slots[src_index % 2].as_mut())
+                .expect("remove_slots_to_rebalance: source should have slots");
+            let ranges = Self::take_slots_from_end(src_slots.get_mut_range_list(), num);
+            migration_slots.push(MigrationSlots {
+                meta: MigrationMetaStore {
+                    epoch: 0,
+                    src_chunk_index: src_index / 2,
+                    src_chunk_part: src_index % 2,
+                    dst_chunk_index: dst_index / 2,
+                    dst_chunk_part: dst_index % 2,
+                },
+                ranges,
+            });
+
+            surplus[src_cursor].1 -= num;
+            deficit[dst_cursor].1 -= num;
+            if surplus[src_cursor].1 == 0 {
+                src_cursor += 1;
+            }
+            if deficit[dst_cursor].1 == 0 {
+                dst_cursor += 1;
+            }
+        }
+
+        migration_slots
+    }
+
+    fn take_slots_from_end(range_list: &mut RangeList, mut num: usize) -> Vec<Range> {
+        let mut taken = vec![];
+        while num > 0 {
+            let ranges = range_list.get_mut_ranges();
+            let last = match ranges.last_mut() {
+                Some(last) => last,
+                None => break,
+            };
+            let last_num = last.end() - last.start() + 1;
+            if last_num <= num {
+                let range = ranges.pop().expect("take_slots_from_end");
+                taken.push(range);
+                num -= last_num;
+            } else {
+                let end = last.end();
+                let start = end - num + 1;
+                *last.end_mut() -= num;
+                taken.push(Range(start, end));
+                num = 0;
+            }
+        }
+        taken
+    }
+
+    fn migration_slots_to_slot_ranges(
+        cluster: &ClusterStore,
+        migration_slots: &[MigrationSlots],
+    ) -> Vec<SlotRange> {
+        migration_slots
+            .iter()
+            .map(|slots| {
+                MigrationSlotRangeStore {
+                    range_list: RangeList::new(slots.ranges.clone()),
+                    is_migrating: true,
+                    meta: slots.meta.clone(),
+                }
+                .to_slot_range(&cluster.chunks)
+            })
+            .collect()
+    }
+
     pub fn commit_migration(&mut self, task: MigrationTaskMeta) -> Result<(), MetaStoreError> {
         let new_epoch = self.store.bump_global_epoch();
 
@@ -451,6 +731,19 @@ impl<'a> MetaStoreMigrate<'a> {
             SlotRangeTag::Importing(meta) => meta.epoch,
         };
 
+        Self::commit_migration_task(cluster, &task.slot_range.range_list, task_epoch)?;
+        cluster.set_epoch(new_epoch);
+
+        Self::check_slots_balance(cluster);
+        Ok(())
+    }
+
+    // Moves the slots of the migration task to the stable slots of the destination.
+    fn commit_migration_task(
+        cluster: &mut ClusterStore,
+        range_list: &RangeList,
+        task_epoch: u64,
+    ) -> Result<(), MetaStoreError> {
         let (src_chunk_index, src_chunk_part) = cluster
             .chunks
             .iter()
@@ -468,7 +761,7 @@ impl<'a> MetaStoreMigrate<'a> {
                     .map(move |slot_range_store| (i, j, slot_range_store))
             })
             .find(|(_, _, slot_range_store)| {
-                slot_range_store.range_list == task.slot_range.range_list
+                slot_range_store.range_list == *range_list
                     && slot_range_store.meta.epoch == task_epoch
                     && slot_range_store.is_migrating
             })
@@ -492,7 +785,7 @@ impl<'a> MetaStoreMigrate<'a> {
                     .map(move |slot_range_store| (i, j, slot_range_store))
             })
             .find(|(_, _, slot_range_store)| {
-                slot_range_store.range_list == task.slot_range.range_list
+                slot_range_store.range_list == *range_list
                     && slot_range_store.meta.epoch == task_epoch
                     && !slot_range_store.is_migrating
             })
@@ -511,7 +804,7 @@ impl<'a> MetaStoreMigrate<'a> {
             for migrating_slots in chunk.migrating_slots.iter_mut() {
                 migrating_slots.retain(|slot_range_store| {
                     !(slot_range_store.is_migrating
-                        && slot_range_store.range_list == task.slot_range.range_list
+                        && slot_range_store.range_list == *range_list
                         && slot_range_store.meta == meta)
                 })
             }
@@ -529,7 +822,7 @@ impl<'a> MetaStoreMigrate<'a> {
                             .position(|slot_range_store| {
                                 !slot_range_store.is_migrating
                                     && slot_range_store.meta == meta
-                                    && slot_range_store.range_list == task.slot_range.range_list
+                                    && slot_range_store.range_list == *range_list
                             })
                             .map(|index| (j, migrating_slots.remove(index).range_list))
                     });
@@ -553,13 +846,266 @@ impl<'a> MetaStoreMigrate<'a> {
         }
 
         Self::compact_slots(cluster);
-        cluster.set_epoch(new_epoch);
-
-        Self::check_slots_balance(cluster);
         Ok(())
     }
 
-    fn check_running_tasks(cluster: &mut ClusterStore) -> Result<(), MetaStoreError> {
+    // Checks the plan against the cluster without changing anything.
+    // The valid moves are planned on a copy of the cluster in the same way as the real migrations
+    // so the impact is estimated by them even if some others are invalid.
+    pub fn validate_migration_plan(
+        cluster: &ClusterStore,
+        moves: &[MigrationMove],
+        migration_limit: u64,
+    ) -> MigrationPlanValidation {
+        let mut errors = vec![];
+        if moves.is_empty() {
+            errors.push(MigrationPlanError::EmptyPlan);
+        }
+        let migration_running = Self::check_running_tasks(cluster).is_err();
+        if migration_running {
+            errors.push(MigrationPlanError::MigrationRunning);
+        }
+
+        let mut planned = cluster.clone();
+        let migration_slots = Self::remove_slots_to_plan(&mut planned, moves, &mut errors);
+        let task_num = migration_slots.len();
+        let migrated_slots = migration_slots
+            .iter()
+            .map(|slots| RangeList::new(slots.ranges.clone()).get_slots_num())
+            .sum();
+        Self::assign_dst_slots(&mut planned, migration_slots);
+
+        // The slots being migrated belong to the destination after the plan is done.
+        let masters = Self::get_master_addresses(&planned);
+        let mut slots_after: HashMap<String, usize> = HashMap::new();
+        let mut covered = vec![false; SLOT_NUM];
+        for (master_index, chunk_part) in planned
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.stable_slots.iter().zip(chunk.migrating_slots.iter()))
+            .enumerate()
+        {
+            let (stable_slots, migrating_slots) = chunk_part;
+            let importing = migrating_slots
+                .iter()
+                .filter(|slots| !slots.is_migrating)
+                .map(|slots| &slots.range_list);
+            let mut slot_num = 0;
+            for range_list in stable_slots
+                .iter()
+                .map(|slots| slots.get_range_list())
+                .chain(importing)
+            {
+                slot_num += range_list.get_slots_num();
+                for range in range_list.get_ranges().iter() {
+                    for slot in covered.iter_mut().take(range.end() + 1).skip(range.start()) {
+                        *slot = true;
+                    }
+                }
+            }
+            slots_after.insert(masters[master_index].clone(), slot_num);
+        }
+
+        let uncovered = slots_to_range_list((0..SLOT_NUM).filter(|s| !covered[*s]));
+        let full_coverage = uncovered.get_ranges().is_empty();
+        if !full_coverage {
+            errors.push(MigrationPlanError::UncoveredSlots(uncovered));
+        }
+
+        let (running_task_num, estimated_rounds) = if migration_running {
+            (0, 0)
+        } else {
+            Self::run_migration_rounds(&mut planned, migration_limit)
+        };
+
+        let impact = MigrationPlanImpact {
+            migrated_slots,
+            task_num,
+            running_task_num,
+            pending_task_num: task_num - running_task_num,
+            estimated_rounds,
+            slots_after,
+        };
+
+        MigrationPlanValidation {
+            valid: errors.is_empty(),
+            full_coverage,
+            exceeds_migration_limit: migration_limit != 0 && task_num as u64 > migration_limit,
+            impact,
+            errors,
+        }
+    }
+
+    // The master index is `chunk_index * 2 + chunk_part`.
+    fn get_master_addresses(cluster: &ClusterStore) -> Vec<String> {
+        cluster
+            .chunks
+            .iter()
+            .flat_map(|chunk| {
+                (0..CHUNK_PARTS).map(move |chunk_part| {
+                    let node_index = MigrationSlotRangeStore::chunk_part_to_node_index(
+                        chunk_part,
+                        chunk.role_position,
+                    );
+                    chunk.node_addresses[node_index].clone()
+                })
+            })
+            .collect()
+    }
+
+    // Removes the slots of the valid moves from the stable slots of the sources
+    // like `remove_slots_to_rebalance` and reports the invalid ones.
+    fn remove_slots_to_plan(
+        cluster: &mut ClusterStore,
+        moves: &[MigrationMove],
+        errors: &mut Vec<MigrationPlanError>,
+    ) -> Vec<MigrationSlots> {
+        let masters = Self::get_master_addresses(cluster);
+        let mut moved = vec![false; SLOT_NUM];
+        let mut migration_slots = vec![];
+        for (move_index, m) in moves.iter().enumerate() {
+            let src = masters.iter().position(|a| *a == m.src_node_address);
+            let dst = masters.iter().position(|a| *a == m.dst_node_address);
+            let (src, dst) = match (src, dst) {
+                (Some(src), Some(dst)) => (src, dst),
+                (None, _) => {
+                    errors.push(MigrationPlanError::UnknownNode {
+                        move_index,
+                        address: m.src_node_address.clone(),
+                    });
+                    continue;
+                }
+                (_, None) => {
+                    errors.push(MigrationPlanError::UnknownNode {
+                        move_index,
+                        address: m.dst_node_address.clone(),
+                    });
+                    continue;
+                }
+            };
+            if src == dst {
+                errors.push(MigrationPlanError::SameNode { move_index });
+                continue;
+            }
+
+            let mut range_list = m.range_list.clone();
+            range_list.compact();
+            if range_list.get_ranges().is_empty()
+                || range_list
+                    .get_ranges()
+                    .iter()
+                    .any(|range| range.end() >= SLOT_NUM)
+            {
+                errors.push(MigrationPlanError::InvalidSlots {
+                    move_index,
+                    range_list,
+                });
+                continue;
+            }
+
+            let slots: Vec<usize> = range_list
+                .get_ranges()
+                .iter()
+                .flat_map(|range| range.start()..=range.end())
+                .collect();
+            let overlapping = slots_to_range_list(slots.iter().cloned().filter(|s| moved[*s]));
+            if !overlapping.get_ranges().is_empty() {
+                errors.push(MigrationPlanError::OverlappingSlots {
+                    move_index,
+                    range_list: overlapping,
+                });
+                continue;
+            }
+
+            let src_slots = &mut cluster.chunks[src / CHUNK_PARTS].stable_slots[src % CHUNK_PARTS];
+            let owned = src_slots
+                .as_ref()
+                .map(|slots| RangeMap::from(slots.get_range_list()));
+            let not_owned = slots_to_range_list(slots.iter().cloned().filter(|s| {
+                !owned
+                    .as_ref()
+                    .map(|owned| owned.contains_slot(*s))
+                    .unwrap_or(false)
+            }));
+            if !not_owned.get_ranges().is_empty() {
+                errors.push(MigrationPlanError::SlotsNotOwned {
+                    move_index,
+                    range_list: not_owned,
+                });
+                continue;
+            }
+
+            let moving = RangeMap::from(&range_list);
+            let kept = src_slots
+                .as_ref()
+                .map(|slots| {
+                    slots_to_range_list(
+                        slots
+                            .get_range_list()
+                            .get_ranges()
+                            .iter()
+                            .flat_map(|range| range.start()..=range.end())
+                            .filter(|s| !moving.contains_slot(*s)),
+                    )
+                })
+                .unwrap_or_else(|| RangeList::new(vec![]));
+            *src_slots = if kept.get_ranges().is_empty() {
+                None
+            } else {
+                Some(SlotRange {
+                    range_list: kept,
+                    tag: SlotRangeTag::None,
+                })
+            };
+
+            for slot in slots.into_iter() {
+                moved[slot] = true;
+            }
+            migration_slots.push(MigrationSlots {
+                meta: MigrationMetaStore {
+                    epoch: cluster.epoch,
+                    src_chunk_index: src / CHUNK_PARTS,
+                    src_chunk_part: src % CHUNK_PARTS,
+                    dst_chunk_index: dst / CHUNK_PARTS,
+                    dst_chunk_part: dst % CHUNK_PARTS,
+                },
+                ranges: range_list.get_ranges().to_vec(),
+            });
+        }
+        migration_slots
+    }
+
+    // Starts the migrations allowed by `limit_migration` and commits them round by round.
+    // Returns (the migrations in the first round, the number of rounds).
+    fn run_migration_rounds(cluster: &mut ClusterStore, migration_limit: u64) -> (usize, usize) {
+        let mut first_round_num = None;
+        let mut rounds = 0;
+        loop {
+            let running: Vec<(RangeList, u64)> = cluster
+                .limit_migration(migration_limit)
+                .chunks
+                .iter()
+                .flat_map(|chunk| chunk.migrating_slots.iter())
+                .flat_map(|slots| slots.iter())
+                .filter(|slots| slots.is_migrating)
+                .map(|slots| (slots.range_list.clone(), slots.meta.epoch))
+                .collect();
+            if running.is_empty() {
+                break;
+            }
+            first_round_num.get_or_insert(running.len());
+            rounds += 1;
+            for (range_list, epoch) in running.iter() {
+                if let Err(err) = Self::commit_migration_task(cluster, range_list, *epoch) {
+                    error!("failed to commit the planned migration: {:?}", err);
+                    return (first_round_num.unwrap_or(0), rounds);
+                }
+            }
+        }
+        (first_round_num.unwrap_or(0), rounds)
+    }
+
+    fn check_running_tasks(cluster: &ClusterStore) -> Result<(), MetaStoreError> {
         let running_migration = cluster
             .chunks
             .iter()
@@ -571,3 +1117,9 @@ impl<'a> MetaStoreMigrate<'a> {
         Ok(())
     }
 }
+
+fn slots_to_range_list<It: Iterator<Item = usize>>(slots: It) -> RangeList {
+    let mut range_list = RangeList::new(slots.map(|slot| Range(slot, slot)).collect());
+    range_list.compact();
+    range_list
+}
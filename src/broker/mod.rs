@@ -1,14 +1,21 @@
+mod failure;
+mod import;
 mod migrate;
 mod persistence;
+mod progress;
 mod query;
+mod reconcile;
 mod recovery;
 mod replication;
 mod resource;
+mod scale;
 mod service;
 mod store;
 mod update;
 
+pub use self::failure::{FailureQuorum, ReporterCountQuorum};
 pub use self::persistence::{JsonFileStorage, MetaStorage, MetaSyncError};
+pub use self::reconcile::ReconcileResult;
 pub use self::replication::{JsonMetaReplicator, MetaReplicator};
 pub use self::service::{
     configure_app, MemBrokerConfig, MemBrokerService, ReplicaAddresses, MEM_BROKER_API_VERSION,
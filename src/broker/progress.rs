@@ -0,0 +1,135 @@
+use super::store::{MigrationInfo, MigrationState};
+use crate::common::cluster::{MigrationTaskMeta, MigrationTaskProgress};
+use crate::protocol::{
+    Array, BulkStr, PooledRedisClientFactory, RedisClient, RedisClientFactory, Resp, RespVec,
+};
+use futures::future;
+use std::collections::{HashMap, HashSet};
+use std::str;
+use std::time::Duration;
+
+// Attach the progress reported by the source proxies to the running migrations.
+// The migrations of the proxies failing to reply are left without progress.
+pub async fn attach_migration_progress(migrations: &mut [MigrationInfo]) {
+    let proxy_addresses: HashSet<String> = migrations
+        .iter()
+        .filter(|migration| migration.state == MigrationState::Running)
+        .filter_map(|migration| migration.slot_range.tag.get_migration_meta())
+        .map(|meta| meta.src_proxy_address.clone())
+        .collect();
+    if proxy_addresses.is_empty() {
+        return;
+    }
+
+    let timeout = Duration::from_secs(1);
+    let client_factory = PooledRedisClientFactory::new(1, timeout);
+
+    let futs: Vec<_> = proxy_addresses
+        .into_iter()
+        .map(|address| fetch_proxy_progress(address, &client_factory))
+        .collect();
+    let progresses: HashMap<MigrationTaskMeta, MigrationTaskProgress> = future::join_all(futs)
+        .await
+        .into_iter()
+        .filter_map(Result::ok)
+        .flatten()
+        .collect();
+
+    for migration in migrations.iter_mut() {
+        let meta = MigrationTaskMeta {
+            cluster_name: migration.cluster_name.clone(),
+            slot_range: migration.slot_range.clone(),
+        };
+        migration.progress = progresses.get(&meta).cloned();
+    }
+}
+
+async fn fetch_proxy_progress(
+    address: String,
+    client_factory: &PooledRedisClientFactory,
+) -> Result<Vec<(MigrationTaskMeta, MigrationTaskProgress)>, String> {
+    let mut client = client_factory
+        .create_client(address.clone())
+        .await
+        .map_err(|err| {
+            error!(
+                "Failed to create client for fetching migration progress: {} {}",
+                address, err
+            );
+            address.clone()
+        })?;
+
+    let cmd = vec![b"UMCTL".to_vec(), b"INFOPROGRESS".to_vec()];
+    let resp = client.execute_single(cmd).await.map_err(|err| {
+        error!("Failed to send UMCTL INFOPROGRESS: {} {}", address, err);
+        address.clone()
+    })?;
+
+    parse_progress_reply(&resp).ok_or_else(|| {
+        error!("Invalid UMCTL INFOPROGRESS reply: {} {:?}", address, resp);
+        address
+    })
+}
+
+fn parse_progress_reply(resp: &RespVec) -> Option<Vec<(MigrationTaskMeta, MigrationTaskProgress)>> {
+    let arr = match resp {
+        Resp::Arr(Array::Arr(arr)) => arr,
+        _ => return None,
+    };
+    let mut progresses = vec![];
+    for element in arr.iter() {
+        let line = match element {
+            Resp::Bulk(BulkStr::Str(s)) => str::from_utf8(s).ok()?,
+            _ => return None,
+        };
+        let mut it = line
+            .split(' ')
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .into_iter()
+            .peekable();
+        let meta = MigrationTaskMeta::from_strings(&mut it)?;
+        let progress = MigrationTaskProgress::from_strings(&mut it)?;
+        progresses.push((meta, progress));
+    }
+    Some(progresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::cluster::{MigrationMeta, SlotRangeTag};
+
+    #[test]
+    fn test_parse_progress_reply() {
+        let line = b"mycluster MIGRATING 1 233-666 7799 127.0.0.1:6000 127.0.0.1:7000 127.0.0.1:6001 127.0.0.1:7001 SCANNING 1024 3".to_vec();
+        let resp = Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Str(line))]));
+        let progresses = parse_progress_reply(&resp).unwrap();
+        assert_eq!(progresses.len(), 1);
+
+        let (meta, progress) = &progresses[0];
+        assert_eq!(meta.cluster_name.to_string(), "mycluster");
+        assert_eq!(
+            meta.slot_range.tag,
+            SlotRangeTag::Migrating(MigrationMeta {
+                epoch: 7799,
+                src_proxy_address: "127.0.0.1:6000".to_string(),
+                src_node_address: "127.0.0.1:7000".to_string(),
+                dst_proxy_address: "127.0.0.1:6001".to_string(),
+                dst_node_address: "127.0.0.1:7001".to_string(),
+            })
+        );
+        assert_eq!(progress.state, "SCANNING");
+        assert_eq!(progress.migrated_keys, 1024);
+        assert_eq!(progress.idle_ms, 3);
+    }
+
+    #[test]
+    fn test_parse_invalid_progress_reply() {
+        let line = b"mycluster MIGRATING 1 233-666 7799 127.0.0.1:6000 127.0.0.1:7000 127.0.0.1:6001 127.0.0.1:7001 SCANNING".to_vec();
+        let resp = Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Str(line))]));
+        assert!(parse_progress_reply(&resp).is_none());
+        let resp = Resp::Error(b"ERR".to_vec());
+        assert!(parse_progress_reply(&resp).is_none());
+    }
+}
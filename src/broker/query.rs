@@ -1,5 +1,6 @@
 use super::store::{
-    ChunkRolePosition, ClusterStore, HostProxy, MetaStore, CHUNK_HALF_NODE_NUM, CHUNK_NODE_NUM,
+    ChunkRolePosition, ClusterStore, HostProxy, MetaStore, MetaStoreError, MigrationInfo,
+    MigrationState, CHUNK_HALF_NODE_NUM, CHUNK_NODE_NUM,
 };
 use crate::common::cluster::{Cluster, Node, PeerProxy, Proxy, ReplMeta, ReplPeer};
 use crate::common::cluster::{ClusterName, Role};
@@ -142,6 +143,59 @@ impl<'a> MetaStoreQuery<'a> {
         Some(Self::cluster_store_to_cluster(&cluster_store))
     }
 
+    pub fn get_migrations(
+        &self,
+        cluster_name: Option<&str>,
+        migration_limit: u64,
+    ) -> Result<Vec<MigrationInfo>, MetaStoreError> {
+        let cluster_name = match cluster_name {
+            Some(name) => {
+                Some(ClusterName::try_from(name).map_err(|_| MetaStoreError::InvalidClusterName)?)
+            }
+            None => None,
+        };
+
+        let mut migrations = vec![];
+        for (name, cluster_store) in self.store.clusters.iter() {
+            if let Some(cluster_name) = cluster_name.as_ref() {
+                if name != cluster_name {
+                    continue;
+                }
+            }
+
+            // Only the migrations not filtered out by `limit_migration`
+            // will be sent to the server proxies.
+            let running_store = cluster_store.limit_migration(migration_limit);
+            for (chunk, running_chunk) in
+                cluster_store.chunks.iter().zip(running_store.chunks.iter())
+            {
+                for (migrating_slots, running_slots) in chunk
+                    .migrating_slots
+                    .iter()
+                    .zip(running_chunk.migrating_slots.iter())
+                {
+                    for slot_range_store in migrating_slots.iter() {
+                        if !slot_range_store.is_migrating {
+                            continue;
+                        }
+                        let state = if running_slots.contains(slot_range_store) {
+                            MigrationState::Running
+                        } else {
+                            MigrationState::Pending
+                        };
+                        migrations.push(MigrationInfo {
+                            cluster_name: name.clone(),
+                            slot_range: slot_range_store.to_slot_range(&cluster_store.chunks),
+                            state,
+                            progress: None,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(migrations)
+    }
+
     fn cluster_store_to_cluster(cluster_store: &ClusterStore) -> Cluster {
         let cluster_name = cluster_store.name.clone();
 
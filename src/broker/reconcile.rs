@@ -0,0 +1,151 @@
+use super::recovery::fetch_proxy_epoch;
+use crate::common::cluster::Proxy;
+use crate::coordinator::{ProxyMetaRespSender, ProxyMetaSender};
+use crate::protocol::RedisClientFactory;
+use futures::future;
+use std::sync::Arc;
+
+#[derive(Debug, Default)]
+pub struct ReconcileResult {
+    pub reconciled_addresses: Vec<String>,
+    pub failed_addresses: Vec<String>,
+}
+
+// Pushes the metadata again to the proxies whose epoch lags behind the broker,
+// e.g. the proxies missed an update or just restarted.
+// The client connections are kept across the reconciliation rounds.
+pub struct ProxyReconciler<F: RedisClientFactory> {
+    client_factory: Arc<F>,
+    sender: ProxyMetaRespSender<F>,
+}
+
+impl<F: RedisClientFactory> ProxyReconciler<F> {
+    pub fn new(client_factory: Arc<F>) -> Self {
+        let sender = ProxyMetaRespSender::new(client_factory.clone());
+        Self {
+            client_factory,
+            sender,
+        }
+    }
+
+    pub async fn reconcile_proxies(&self, proxies: Vec<Proxy>) -> ReconcileResult {
+        let futs: Vec<_> = proxies
+            .into_iter()
+            .map(|proxy| self.reconcile_proxy(proxy))
+            .collect();
+        let results = future::join_all(futs).await;
+
+        let mut result = ReconcileResult::default();
+        for res in results.into_iter() {
+            match res {
+                Ok(Some(address)) => result.reconciled_addresses.push(address),
+                Ok(None) => (),
+                Err(address) => result.failed_addresses.push(address),
+            }
+        }
+        result
+    }
+
+    async fn reconcile_proxy(&self, proxy: Proxy) -> Result<Option<String>, String> {
+        let address = proxy.get_address().to_string();
+        let epoch = proxy.get_epoch();
+        let proxy_epoch = fetch_proxy_epoch(address.clone(), self.client_factory.as_ref()).await?;
+        if proxy_epoch >= epoch {
+            return Ok(None);
+        }
+
+        info!(
+            "reconcile proxy {} from epoch {} to {}",
+            address, proxy_epoch, epoch
+        );
+        if let Err(err) = self.sender.send_meta(proxy).await {
+            error!("failed to reconcile proxy {}: {:?}", address, err);
+            return Err(address);
+        }
+        Ok(Some(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PooledRedisClientFactory, Resp};
+    use crate::test_support::{FakeBackendFunc, FakeRedisClientFactory};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    fn gen_proxy(address: &str, epoch: u64) -> Proxy {
+        Proxy::new(
+            address.to_string(),
+            epoch,
+            vec![],
+            vec![],
+            vec![],
+            HashMap::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_lagging_proxies() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args[1].clone()));
+            match (address, args[1].as_str()) {
+                ("127.0.0.1:6000", "GETEPOCH") => Resp::Integer(b"3".to_vec()),
+                ("127.0.0.1:6001", "GETEPOCH") => Resp::Integer(b"7".to_vec()),
+                ("127.0.0.1:6002", "GETEPOCH") => Resp::Integer(b"3".to_vec()),
+                ("127.0.0.1:6002", "SETCLUSTER") => Resp::Error(b"ERR invalid meta".to_vec()),
+                _ => Resp::Simple(b"OK".to_vec()),
+            }
+        });
+        let reconciler = ProxyReconciler::new(Arc::new(FakeRedisClientFactory::new(backend)));
+
+        let proxies = vec![
+            gen_proxy("127.0.0.1:6000", 7),
+            gen_proxy("127.0.0.1:6001", 7),
+            gen_proxy("127.0.0.1:6002", 7),
+        ];
+        let result = reconciler.reconcile_proxies(proxies).await;
+        assert_eq!(result.reconciled_addresses, vec!["127.0.0.1:6000"]);
+        assert_eq!(result.failed_addresses, vec!["127.0.0.1:6002"]);
+
+        let mut received = received.lock().unwrap().clone();
+        received.sort();
+        let expected: Vec<(String, String)> = vec![
+            ("127.0.0.1:6000", "GETEPOCH"),
+            ("127.0.0.1:6000", "SETCLUSTER"),
+            ("127.0.0.1:6000", "SETREPL"),
+            // The proxy already up to date is left as it is.
+            ("127.0.0.1:6001", "GETEPOCH"),
+            ("127.0.0.1:6002", "GETEPOCH"),
+            ("127.0.0.1:6002", "SETCLUSTER"),
+            ("127.0.0.1:6002", "SETREPL"),
+        ]
+        .into_iter()
+        .map(|(address, cmd)| (address.to_string(), cmd.to_string()))
+        .collect();
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_unreachable_proxy() {
+        let client_factory = PooledRedisClientFactory::new(1, Duration::from_secs(1));
+        let reconciler = ProxyReconciler::new(Arc::new(client_factory));
+        // Nothing listens on port 1.
+        let address = "127.0.0.1:1".to_string();
+        let result = reconciler
+            .reconcile_proxies(vec![gen_proxy(&address, 7)])
+            .await;
+        assert!(result.reconciled_addresses.is_empty());
+        assert_eq!(result.failed_addresses, vec![address]);
+
+        let result = reconciler.reconcile_proxies(vec![]).await;
+        assert!(result.reconciled_addresses.is_empty());
+        assert!(result.failed_addresses.is_empty());
+    }
+}
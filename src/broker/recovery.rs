@@ -36,16 +36,16 @@ pub async fn fetch_largest_epoch(proxy_addresses: Vec<String>) -> EpochFetchResu
     }
 }
 
-async fn fetch_proxy_epoch(
+pub async fn fetch_proxy_epoch<F: RedisClientFactory>(
     address: String,
-    client_factory: &PooledRedisClientFactory,
+    client_factory: &F,
 ) -> Result<u64, String> {
     let mut client = client_factory
         .create_client(address.clone())
         .await
         .map_err(|err| {
             error!(
-                "Failed to create client for broker recovery: {} {}",
+                "Failed to create client for fetching epoch: {} {}",
                 address, err
             );
             address.clone()
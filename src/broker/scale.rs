@@ -0,0 +1,209 @@
+use super::migrate::MetaStoreMigrate;
+use super::query::MetaStoreQuery;
+use super::store::{MetaStore, MetaStoreError, MigrationState, NODES_PER_PROXY};
+use super::update::MetaStoreUpdate;
+use crate::common::cluster::ClusterName;
+use crate::protocol::{PooledRedisClientFactory, RedisClient, RedisClientFactory};
+use futures::future;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+// Only the latest jobs are kept.
+const MAX_SCALE_OUT_JOBS: usize = 64;
+
+pub struct ScaleOutProxy {
+    pub proxy_address: String,
+    pub nodes: [String; NODES_PER_PROXY],
+    pub host: Option<String>,
+}
+
+// The new proxies are added to the cluster and the slots are migrated to them in one job.
+// The job id is the epoch of its migrations so that they could be told apart
+// from the other migrations of the cluster.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ScaleOutJob {
+    pub job_id: u64,
+    pub cluster_name: ClusterName,
+    pub proxy_addresses: Vec<String>,
+    pub migration_num: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScaleOutProgress {
+    pub job_id: u64,
+    pub cluster_name: ClusterName,
+    pub proxy_addresses: Vec<String>,
+    pub migration_num: usize,
+    pub done: usize,
+    // Limited by `migration_limit`.
+    pub running: usize,
+    pub pending: usize,
+    pub finished: bool,
+}
+
+pub struct MetaStoreScale<'a> {
+    store: &'a mut MetaStore,
+}
+
+impl<'a> MetaStoreScale<'a> {
+    pub fn new(store: &'a mut MetaStore) -> Self {
+        Self { store }
+    }
+
+    // Either all the proxies are added with the migrations started or nothing is changed.
+    pub fn scale_out(
+        &mut self,
+        cluster_name: String,
+        proxies: Vec<ScaleOutProxy>,
+    ) -> Result<ScaleOutJob, MetaStoreError> {
+        let name = ClusterName::try_from(cluster_name.as_str())
+            .map_err(|_| MetaStoreError::InvalidClusterName)?;
+        let proxy_addresses: Vec<String> = proxies
+            .iter()
+            .map(|proxy| proxy.proxy_address.clone())
+            .collect();
+        let unique_addresses: HashSet<&String> = proxy_addresses.iter().collect();
+        if unique_addresses.len() != proxy_addresses.len() {
+            return Err(MetaStoreError::InvalidProxyAddress);
+        }
+
+        // Any failure in the middle leaves the copy behind.
+        let mut store = self.store.clone();
+        for proxy in proxies.into_iter() {
+            let ScaleOutProxy {
+                proxy_address,
+                nodes,
+                host,
+            } = proxy;
+            match store.all_proxies.get(&proxy_address) {
+                // Added before but not used yet.
+                Some(existing)
+                    if existing.cluster.is_none() && existing.node_addresses == nodes =>
+                {
+                    continue
+                }
+                Some(existing) if existing.cluster.is_some() => return Err(MetaStoreError::InUse),
+                Some(_) => return Err(MetaStoreError::AlreadyExisted),
+                None => store.add_proxy(proxy_address, nodes, host)?,
+            }
+        }
+        MetaStoreUpdate::new(&mut store)
+            .add_nodes_from_proxies(cluster_name.clone(), &proxy_addresses)?;
+        MetaStoreMigrate::new(&mut store).migrate_slots(cluster_name)?;
+
+        let cluster = store
+            .clusters
+            .get(&name)
+            .ok_or_else(|| MetaStoreError::ClusterNotFound)?;
+        let migration_num = cluster
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.migrating_slots.iter())
+            .flatten()
+            .filter(|slot_range_store| slot_range_store.is_migrating)
+            .count();
+        let job = ScaleOutJob {
+            job_id: cluster.epoch,
+            cluster_name: name,
+            proxy_addresses,
+            migration_num,
+        };
+
+        store.scale_out_jobs.insert(job.job_id, job.clone());
+        while store.scale_out_jobs.len() > MAX_SCALE_OUT_JOBS {
+            let oldest = match store.scale_out_jobs.keys().min() {
+                Some(job_id) => *job_id,
+                None => break,
+            };
+            store.scale_out_jobs.remove(&oldest);
+        }
+
+        *self.store = store;
+        Ok(job)
+    }
+}
+
+pub fn get_scale_out_progress(
+    store: &MetaStore,
+    cluster_name: &str,
+    job_id: u64,
+    migration_limit: u64,
+) -> Result<ScaleOutProgress, MetaStoreError> {
+    let job = match store.scale_out_jobs.get(&job_id) {
+        Some(job) if job.cluster_name.to_string() == cluster_name => job.clone(),
+        _ => return Err(MetaStoreError::JobNotFound),
+    };
+
+    let mut running = 0;
+    let mut pending = 0;
+    let migrations =
+        MetaStoreQuery::new(store).get_migrations(Some(cluster_name), migration_limit)?;
+    for migration in migrations.iter() {
+        let epoch = migration
+            .slot_range
+            .tag
+            .get_migration_meta()
+            .map(|meta| meta.epoch);
+        if epoch != Some(job_id) {
+            continue;
+        }
+        match migration.state {
+            MigrationState::Running => running += 1,
+            MigrationState::Pending => pending += 1,
+        }
+    }
+
+    let ScaleOutJob {
+        job_id,
+        cluster_name,
+        proxy_addresses,
+        migration_num,
+    } = job;
+    let remaining = running + pending;
+    Ok(ScaleOutProgress {
+        job_id,
+        cluster_name,
+        proxy_addresses,
+        migration_num,
+        done: migration_num.saturating_sub(remaining),
+        running,
+        pending,
+        finished: remaining == 0,
+    })
+}
+
+// Returns the addresses not replying PING.
+pub async fn check_reachable(addresses: Vec<String>) -> Vec<String> {
+    let timeout = Duration::from_secs(1);
+    let client_factory = PooledRedisClientFactory::new(1, timeout);
+
+    let futs: Vec<_> = addresses
+        .into_iter()
+        .map(|address| ping(address, &client_factory))
+        .collect();
+    future::join_all(futs)
+        .await
+        .into_iter()
+        .filter_map(|res| res.err())
+        .collect()
+}
+
+// Any reply including the errors like NOAUTH means it's reachable.
+async fn ping(address: String, client_factory: &PooledRedisClientFactory) -> Result<(), String> {
+    let mut client = client_factory
+        .create_client(address.clone())
+        .await
+        .map_err(|err| {
+            error!("Failed to create client for PING: {} {}", address, err);
+            address.clone()
+        })?;
+    client
+        .execute_single(vec![b"PING".to_vec()])
+        .await
+        .map(|_| ())
+        .map_err(|err| {
+            error!("Failed to send PING: {} {}", address, err);
+            address.clone()
+        })
+}
@@ -1,15 +1,22 @@
+use super::failure::FailureQuorum;
+use super::import::{import_redis_cluster, ImportError, ImportProxy, ImportedTopology};
+use super::migrate::{MigrationMove, MigrationPlanValidation};
 use super::persistence::{MetaStorage, MetaSyncError};
+use super::progress::attach_migration_progress;
 use super::replication::MetaReplicator;
 use super::resource::ResourceChecker;
-use super::store::{MetaStore, MetaStoreError, CHUNK_HALF_NODE_NUM};
+use super::scale::{check_reachable, ScaleOutJob, ScaleOutProgress, ScaleOutProxy};
+use super::store::{MetaStore, MetaStoreError, MigrationInfo, CHUNK_HALF_NODE_NUM};
+use crate::broker::reconcile::{ProxyReconciler, ReconcileResult};
 use crate::broker::recovery::{fetch_largest_epoch, EpochFetchResult};
-use crate::common::cluster::{Cluster, ClusterName, MigrationTaskMeta, Node, Proxy};
+use crate::common::cluster::{Cluster, ClusterName, MigrationTaskMeta, Node, Proxy, SlotRange};
 use crate::common::version::UNDERMOON_VERSION;
 use crate::coordinator::http_mani_broker::ReplaceProxyResponse;
 use crate::coordinator::http_meta_broker::{
     ClusterNamesPayload, ClusterPayload, FailedProxiesPayload, FailuresPayload,
     ProxyAddressesPayload, ProxyPayload,
 };
+use crate::protocol::PooledRedisClientFactory;
 use actix_http::ResponseBuilder;
 use actix_web::dev::Service;
 use actix_web::{error, http, web, HttpRequest, HttpResponse, Responder};
@@ -17,6 +24,7 @@ use arc_swap::ArcSwap;
 use std::collections::HashMap;
 use std::num::NonZeroU64;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 pub const MEM_BROKER_API_VERSION: &str = "/api/v2";
 
@@ -58,6 +66,7 @@ pub fn configure_app(cfg: &mut web::ServiceConfig, service: Arc<MemBrokerService
             .route("/version", web::get().to(get_version))
             .route("/metadata", web::get().to(get_all_metadata))
             .route("/metadata", web::put().to(restore_metadata))
+            .route("/metadata/snapshot", web::get().to(get_metadata_snapshot))
             // Broker api
             .route("/clusters/names", web::get().to(get_cluster_names))
             .route(
@@ -97,11 +106,17 @@ pub fn configure_app(cfg: &mut web::ServiceConfig, service: Arc<MemBrokerService
                 "/clusters/migrations/shrink/{cluster_name}/{node_number}",
                 web::post().to(migrate_slots_to_scale_down),
             )
+            .route("/clusters/migrations", web::get().to(get_migrations))
             .route("/clusters/migrations/expand/{cluster_name}", web::post().to(migrate_slots))
+            .route("/clusters/migrations/rebalance/{cluster_name}", web::post().to(rebalance_slots))
+            .route("/clusters/migrations/validate/{cluster_name}", web::post().to(validate_migration_plan))
             .route("/clusters/config/{cluster_name}", web::patch().to(change_config))
             .route("/clusters/balance/{cluster_name}", web::put().to(balance_masters))
+            .route("/clusters/{cluster_name}/scale-out", web::post().to(scale_out))
+            .route("/clusters/{cluster_name}/scale-out/{job_id}", web::get().to(get_scale_out_progress))
 
             .route("/proxies/meta", web::post().to(add_proxy))
+            .route("/redis_cluster/import", web::post().to(import_topology))
             .route(
                 "/proxies/meta/{proxy_address}",
                 web::delete().to(remove_proxy),
@@ -127,6 +142,7 @@ pub struct MemBrokerConfig {
     pub update_meta_file_interval: Option<NonZeroU64>,
     pub replica_addresses: ReplicaAddresses,
     pub sync_meta_interval: Option<NonZeroU64>,
+    pub reconcile_interval: Option<NonZeroU64>,
     pub debug: bool,
 }
 
@@ -148,6 +164,8 @@ pub struct MemBrokerService {
     store: Arc<RwLock<MetaStore>>,
     meta_storage: Arc<dyn MetaStorage + Send + Sync + 'static>,
     meta_replicator: Arc<dyn MetaReplicator + Send + Sync + 'static>,
+    failure_quorum: Arc<dyn FailureQuorum + Send + Sync + 'static>,
+    reconciler: ProxyReconciler<PooledRedisClientFactory>,
 }
 
 impl MemBrokerService {
@@ -155,6 +173,7 @@ impl MemBrokerService {
         config: MemBrokerConfig,
         meta_storage: Arc<dyn MetaStorage + Send + Sync + 'static>,
         meta_replicator: Arc<dyn MetaReplicator + Send + Sync + 'static>,
+        failure_quorum: Arc<dyn FailureQuorum + Send + Sync + 'static>,
         last_meta_store: Option<MetaStore>,
     ) -> Result<Self, MetaStoreError> {
         info!("config: {:?}", config);
@@ -164,11 +183,14 @@ impl MemBrokerService {
             meta_store.restore(last)?;
         }
 
+        let client_factory = PooledRedisClientFactory::new(1, Duration::from_secs(1));
         let service = Self {
             config,
             store: Arc::new(RwLock::new(meta_store)),
             meta_storage,
             meta_replicator,
+            failure_quorum,
+            reconciler: ProxyReconciler::new(Arc::new(client_factory)),
         };
         Ok(service)
     }
@@ -242,6 +264,20 @@ impl MemBrokerService {
             .get_cluster_by_name(name, migration_limit)
     }
 
+    pub async fn get_migrations(
+        &self,
+        cluster_name: Option<&str>,
+    ) -> Result<Vec<MigrationInfo>, MetaStoreError> {
+        let migration_limit = self.config.migration_limit;
+        let mut migrations = self
+            .store
+            .read()
+            .expect("MemBrokerService::get_migrations")
+            .get_migrations(cluster_name, migration_limit)?;
+        attach_migration_progress(&mut migrations).await;
+        Ok(migrations)
+    }
+
     pub fn add_proxy(&self, proxy_resource: ProxyResourcePayload) -> Result<(), MetaStoreError> {
         let ProxyResourcePayload {
             proxy_address,
@@ -254,6 +290,18 @@ impl MemBrokerService {
             .add_proxy(proxy_address, nodes, host)
     }
 
+    pub fn import_cluster(
+        &self,
+        cluster_name: String,
+        topo: &ImportedTopology,
+        proxies: Vec<ImportProxy>,
+    ) -> Result<(), ImportError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::import_cluster")
+            .import_cluster(cluster_name, topo, proxies)
+    }
+
     pub fn add_cluster(&self, cluster_name: String, node_num: usize) -> Result<(), MetaStoreError> {
         self.store
             .write()
@@ -359,13 +407,80 @@ impl MemBrokerService {
             .migrate_slots_to_scale_down(cluster_name, new_node_num)
     }
 
+    pub fn rebalance_slots(
+        &self,
+        cluster_name: String,
+        tolerance: usize,
+        dry_run: bool,
+    ) -> Result<Vec<SlotRange>, MetaStoreError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::rebalance_slots")
+            .rebalance_slots(cluster_name, tolerance, dry_run)
+    }
+
+    pub fn validate_migration_plan(
+        &self,
+        cluster_name: String,
+        moves: Vec<MigrationMove>,
+    ) -> Result<MigrationPlanValidation, MetaStoreError> {
+        let migration_limit = self.config.migration_limit;
+        self.store
+            .read()
+            .expect("MemBrokerService::validate_migration_plan")
+            .validate_migration_plan(cluster_name, &moves, migration_limit)
+    }
+
+    pub async fn scale_out(
+        &self,
+        cluster_name: String,
+        proxies: Vec<ProxyResourcePayload>,
+    ) -> Result<ScaleOutJob, MetaStoreError> {
+        let addresses = proxies
+            .iter()
+            .flat_map(|proxy| {
+                std::iter::once(proxy.proxy_address.clone()).chain(proxy.nodes.iter().cloned())
+            })
+            .collect();
+        let unreachable = check_reachable(addresses).await;
+        if !unreachable.is_empty() {
+            return Err(MetaStoreError::Unreachable {
+                addresses: unreachable,
+            });
+        }
+
+        let proxies = proxies
+            .into_iter()
+            .map(|proxy| ScaleOutProxy {
+                proxy_address: proxy.proxy_address,
+                nodes: proxy.nodes,
+                host: proxy.host,
+            })
+            .collect();
+        self.store
+            .write()
+            .expect("MemBrokerService::scale_out")
+            .scale_out(cluster_name, proxies)
+    }
+
+    pub fn get_scale_out_progress(
+        &self,
+        cluster_name: &str,
+        job_id: u64,
+    ) -> Result<ScaleOutProgress, MetaStoreError> {
+        let migration_limit = self.config.migration_limit;
+        self.store
+            .read()
+            .expect("MemBrokerService::get_scale_out_progress")
+            .get_scale_out_progress(cluster_name, job_id, migration_limit)
+    }
+
     pub fn get_failures(&self) -> Vec<String> {
         let failure_ttl = chrono::Duration::seconds(self.config.failure_ttl as i64);
-        let failure_quorum = self.config.failure_quorum;
         self.store
             .write()
             .expect("MemBrokerService::get_failures")
-            .get_failures(failure_ttl, failure_quorum)
+            .get_failures_with_quorum(failure_ttl, self.failure_quorum.as_ref())
     }
 
     pub fn add_failure(&self, address: String, reporter_id: String) {
@@ -428,6 +543,22 @@ impl MemBrokerService {
         Ok(failed_addresses)
     }
 
+    pub async fn reconcile_proxies(&self) -> ReconcileResult {
+        let proxies = {
+            let migration_limit = self.config.migration_limit;
+            let store = self
+                .store
+                .read()
+                .expect("MemBrokerService::reconcile_proxies");
+            store
+                .get_proxies()
+                .into_iter()
+                .filter_map(|address| store.get_proxy_by_address(&address, migration_limit))
+                .collect()
+        };
+        self.reconciler.reconcile_proxies(proxies).await
+    }
+
     pub fn check_metadata(&self) -> Result<(), MetaStore> {
         self.store
             .read()
@@ -447,6 +578,16 @@ async fn get_all_metadata(state: ServiceState) -> impl Responder {
     web::Json(metadata)
 }
 
+async fn get_metadata_snapshot(state: ServiceState) -> Result<HttpResponse, MetaStoreError> {
+    let json = state.get_all_data().to_stable_json().map_err(|err| {
+        error!("failed to dump metadata: {:?}", err);
+        MetaStoreError::SyncError(MetaSyncError::Json)
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json))
+}
+
 async fn restore_metadata(
     (meta_store, state): (web::Json<MetaStore>, ServiceState),
 ) -> Result<&'static str, MetaStoreError> {
@@ -491,6 +632,23 @@ async fn get_cluster_by_name(
     web::Json(ClusterPayload { cluster })
 }
 
+#[derive(Deserialize)]
+struct MigrationFilter {
+    cluster_name: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MigrationsPayload {
+    migrations: Vec<MigrationInfo>,
+}
+
+async fn get_migrations(
+    (web::Query(filter), state): (web::Query<MigrationFilter>, ServiceState),
+) -> Result<web::Json<MigrationsPayload>, MetaStoreError> {
+    let migrations = state.get_migrations(filter.cluster_name.as_deref()).await?;
+    Ok(web::Json(MigrationsPayload { migrations }))
+}
+
 async fn get_failures(state: ServiceState) -> impl Responder {
     let addresses = state.get_failures();
     web::Json(FailuresPayload { addresses })
@@ -511,6 +669,37 @@ async fn add_proxy(
     Ok(res)
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct ImportRedisClusterPayload {
+    seed_address: String,
+    cluster_name: String,
+    proxies: Vec<ImportProxy>,
+}
+
+// Reads the topology from the seed node and adds it to the meta store as a new cluster.
+async fn import_topology(
+    (payload, state): (web::Json<ImportRedisClusterPayload>, ServiceState),
+) -> Result<web::Json<ImportedTopology>, ImportError> {
+    let ImportRedisClusterPayload {
+        seed_address,
+        cluster_name,
+        proxies,
+    } = payload.into_inner();
+    let topo = import_redis_cluster(seed_address).await?;
+    for warning in topo.warnings.iter() {
+        warn!(
+            "importing redis cluster from {}: {}",
+            topo.seed_address, warning
+        );
+    }
+    state.import_cluster(cluster_name, &topo, proxies)?;
+    state
+        .trigger_update()
+        .await
+        .map_err(MetaStoreError::SyncError)?;
+    Ok(web::Json(topo))
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct CreateClusterPayload {
     node_number: usize,
@@ -671,6 +860,88 @@ async fn migrate_slots_to_scale_down(
     Ok(res)
 }
 
+#[derive(Deserialize)]
+struct RebalanceQuery {
+    dry_run: Option<bool>,
+    tolerance: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RebalancePayload {
+    dry_run: bool,
+    migrations: Vec<SlotRange>,
+}
+
+async fn rebalance_slots(
+    (path, web::Query(query), state): (
+        web::Path<(String,)>,
+        web::Query<RebalanceQuery>,
+        ServiceState,
+    ),
+) -> Result<web::Json<RebalancePayload>, MetaStoreError> {
+    let (cluster_name,) = path.into_inner();
+    let dry_run = query.dry_run.unwrap_or(false);
+    let tolerance = query.tolerance.unwrap_or(1);
+    let migrations = state.rebalance_slots(cluster_name, tolerance, dry_run)?;
+    if !dry_run && !migrations.is_empty() {
+        state.trigger_update().await?;
+    }
+    Ok(web::Json(RebalancePayload {
+        dry_run,
+        migrations,
+    }))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MigrationPlanPayload {
+    moves: Vec<MigrationMove>,
+}
+
+async fn validate_migration_plan(
+    (path, payload, state): (
+        web::Path<(String,)>,
+        web::Json<MigrationPlanPayload>,
+        ServiceState,
+    ),
+) -> Result<web::Json<MigrationPlanValidation>, MetaStoreError> {
+    let (cluster_name,) = path.into_inner();
+    let MigrationPlanPayload { moves } = payload.into_inner();
+    let validation = state.validate_migration_plan(cluster_name, moves)?;
+    Ok(web::Json(validation))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ScaleOutPayload {
+    proxies: Vec<ProxyResourcePayload>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ScaleOutJobPayload {
+    job_id: u64,
+}
+
+async fn scale_out(
+    (path, payload, state): (
+        web::Path<(String,)>,
+        web::Json<ScaleOutPayload>,
+        ServiceState,
+    ),
+) -> Result<web::Json<ScaleOutJobPayload>, MetaStoreError> {
+    let (cluster_name,) = path.into_inner();
+    let ScaleOutPayload { proxies } = payload.into_inner();
+    let job = state.scale_out(cluster_name, proxies).await?;
+    state.trigger_update().await?;
+    Ok(web::Json(ScaleOutJobPayload { job_id: job.job_id }))
+}
+
+async fn get_scale_out_progress(
+    (path, state): (web::Path<(String, u64)>, ServiceState),
+) -> Result<web::Json<ScaleOutProgress>, MetaStoreError> {
+    let (cluster_name, job_id) = path.into_inner();
+    let progress = state.get_scale_out_progress(&cluster_name, job_id)?;
+    Ok(web::Json(progress))
+}
+
 async fn add_failure(
     (path, state): (web::Path<(String, String)>, ServiceState),
 ) -> Result<&'static str, MetaStoreError> {
@@ -738,6 +1009,8 @@ impl error::ResponseError for MetaStoreError {
             MetaStoreError::InvalidMigrationTask => http::StatusCode::BAD_REQUEST,
             MetaStoreError::InvalidProxyAddress => http::StatusCode::BAD_REQUEST,
             MetaStoreError::MigrationTaskNotFound => http::StatusCode::NOT_FOUND,
+            MetaStoreError::JobNotFound => http::StatusCode::NOT_FOUND,
+            MetaStoreError::Unreachable { .. } => http::StatusCode::BAD_REQUEST,
             MetaStoreError::MigrationRunning => http::StatusCode::CONFLICT,
             MetaStoreError::InvalidConfig { .. } => http::StatusCode::BAD_REQUEST,
             MetaStoreError::SlotsAlreadyEven => http::StatusCode::BAD_REQUEST,
@@ -751,3 +1024,19 @@ impl error::ResponseError for MetaStoreError {
         ResponseBuilder::new(self.status_code()).json(self)
     }
 }
+
+impl error::ResponseError for ImportError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            ImportError::Connection(_) => http::StatusCode::BAD_GATEWAY,
+            ImportError::InvalidReply(_) => http::StatusCode::BAD_GATEWAY,
+            ImportError::IncompleteSlots(_) => http::StatusCode::CONFLICT,
+            ImportError::UnsupportedTopology(_) => http::StatusCode::BAD_REQUEST,
+            ImportError::MetaStore(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        ResponseBuilder::new(self.status_code()).json(self)
+    }
+}
@@ -1,17 +1,23 @@
-use super::migrate::MetaStoreMigrate;
+use super::failure::{FailureQuorum, ReporterCountQuorum};
+use super::import::{ImportError, ImportProxy, ImportedTopology, MetaStoreImport};
+use super::migrate::{MetaStoreMigrate, MigrationMove, MigrationPlanValidation};
 use super::persistence::MetaSyncError;
 use super::query::MetaStoreQuery;
+use super::scale::{
+    get_scale_out_progress, MetaStoreScale, ScaleOutJob, ScaleOutProgress, ScaleOutProxy,
+};
 use super::update::MetaStoreUpdate;
 use crate::common::cluster::ClusterName;
 use crate::common::cluster::{
-    Cluster, MigrationMeta, MigrationTaskMeta, Node, Proxy, Range, RangeList, SlotRange,
-    SlotRangeTag,
+    Cluster, MigrationMeta, MigrationTaskMeta, MigrationTaskProgress, Node, Proxy, Range,
+    RangeList, SlotRange, SlotRangeTag,
 };
 use crate::common::config::ClusterConfig;
 use crate::common::version::UNDERMOON_MEM_BROKER_META_VERSION;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::cmp::max;
 use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
 
@@ -109,7 +115,7 @@ impl MigrationSlotRangeStore {
         }
     }
 
-    fn chunk_part_to_node_index(chunk_part: usize, role_position: ChunkRolePosition) -> usize {
+    pub fn chunk_part_to_node_index(chunk_part: usize, role_position: ChunkRolePosition) -> usize {
         match (chunk_part, role_position) {
             (0, ChunkRolePosition::SecondChunkMaster) => 3,
             (1, ChunkRolePosition::FirstChunkMaster) => 1,
@@ -240,6 +246,24 @@ impl ClusterStore {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationState {
+    // Sent to the server proxies.
+    Running,
+    // Held back by `migration_limit`.
+    Pending,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MigrationInfo {
+    pub cluster_name: ClusterName,
+    pub slot_range: SlotRange,
+    pub state: MigrationState,
+    // Only the running migrations have the progress reported by the source proxies.
+    pub progress: Option<MigrationTaskProgress>,
+}
+
 #[derive(Debug, Clone)]
 pub struct MigrationSlots {
     pub ranges: Vec<Range>,
@@ -257,6 +281,9 @@ pub struct MetaStore {
     pub failed_proxies: HashSet<String>,
     // failed_proxy_address => reporter_id => time,
     pub failures: HashMap<String, HashMap<String, i64>>,
+    // job_id => job
+    #[serde(default)]
+    pub scale_out_jobs: HashMap<u64, ScaleOutJob>,
 }
 
 impl Default for MetaStore {
@@ -268,6 +295,7 @@ impl Default for MetaStore {
             all_proxies: HashMap::new(),
             failed_proxies: HashSet::new(),
             failures: HashMap::new(),
+            scale_out_jobs: HashMap::new(),
         }
     }
 }
@@ -284,6 +312,16 @@ impl MetaStore {
         Ok(())
     }
 
+    // Unlike the persisted json, the output is stable for diffing:
+    // the object keys are sorted and so are the failed proxies.
+    pub fn to_stable_json(&self) -> Result<String, serde_json::Error> {
+        let mut value = serde_json::to_value(self)?;
+        if let Some(serde_json::Value::Array(failed_proxies)) = value.get_mut("failed_proxies") {
+            failed_proxies.sort_by(|a, b| a.as_str().cmp(&b.as_str()));
+        }
+        serde_json::to_string_pretty(&value)
+    }
+
     pub fn get_global_epoch(&self) -> u64 {
         self.global_epoch
     }
@@ -325,6 +363,14 @@ impl MetaStore {
         MetaStoreQuery::new(self).get_cluster_by_name(cluster_name, migration_limit)
     }
 
+    pub fn get_migrations(
+        &self,
+        cluster_name: Option<&str>,
+        migration_limit: u64,
+    ) -> Result<Vec<MigrationInfo>, MetaStoreError> {
+        MetaStoreQuery::new(self).get_migrations(cluster_name, migration_limit)
+    }
+
     pub fn add_failure(&mut self, address: String, reporter_id: String) {
         MetaStoreUpdate::new(self).add_failure(address, reporter_id)
     }
@@ -333,6 +379,15 @@ impl MetaStore {
         &mut self,
         falure_ttl: chrono::Duration,
         failure_quorum: u64,
+    ) -> Vec<String> {
+        let quorum = ReporterCountQuorum::new(failure_quorum);
+        self.get_failures_with_quorum(falure_ttl, &quorum)
+    }
+
+    pub fn get_failures_with_quorum(
+        &mut self,
+        falure_ttl: chrono::Duration,
+        failure_quorum: &dyn FailureQuorum,
     ) -> Vec<String> {
         MetaStoreUpdate::new(self).get_failures(falure_ttl, failure_quorum)
     }
@@ -394,10 +449,64 @@ impl MetaStore {
         MetaStoreMigrate::new(self).migrate_slots_to_scale_down(cluster_name, new_node_num)
     }
 
+    pub fn rebalance_slots(
+        &mut self,
+        cluster_name: String,
+        tolerance: usize,
+        dry_run: bool,
+    ) -> Result<Vec<SlotRange>, MetaStoreError> {
+        MetaStoreMigrate::new(self).rebalance_slots(cluster_name, tolerance, dry_run)
+    }
+
+    pub fn validate_migration_plan(
+        &self,
+        cluster_name: String,
+        moves: &[MigrationMove],
+        migration_limit: u64,
+    ) -> Result<MigrationPlanValidation, MetaStoreError> {
+        let cluster_name = ClusterName::try_from(cluster_name.as_str())
+            .map_err(|_| MetaStoreError::InvalidClusterName)?;
+        let cluster = self
+            .clusters
+            .get(&cluster_name)
+            .ok_or_else(|| MetaStoreError::ClusterNotFound)?;
+        Ok(MetaStoreMigrate::validate_migration_plan(
+            cluster,
+            moves,
+            migration_limit,
+        ))
+    }
+
     pub fn commit_migration(&mut self, task: MigrationTaskMeta) -> Result<(), MetaStoreError> {
         MetaStoreMigrate::new(self).commit_migration(task)
     }
 
+    pub fn scale_out(
+        &mut self,
+        cluster_name: String,
+        proxies: Vec<ScaleOutProxy>,
+    ) -> Result<ScaleOutJob, MetaStoreError> {
+        MetaStoreScale::new(self).scale_out(cluster_name, proxies)
+    }
+
+    pub fn import_cluster(
+        &mut self,
+        cluster_name: String,
+        topo: &ImportedTopology,
+        proxies: Vec<ImportProxy>,
+    ) -> Result<(), ImportError> {
+        MetaStoreImport::new(self).import_cluster(cluster_name, topo, proxies)
+    }
+
+    pub fn get_scale_out_progress(
+        &self,
+        cluster_name: &str,
+        job_id: u64,
+        migration_limit: u64,
+    ) -> Result<ScaleOutProgress, MetaStoreError> {
+        get_scale_out_progress(self, cluster_name, job_id, migration_limit)
+    }
+
     pub fn get_free_proxies(&self) -> Vec<HostProxy> {
         MetaStoreQuery::new(&self).get_free_proxies()
     }
@@ -474,6 +583,10 @@ pub enum MetaStoreError {
     InvalidProxyAddress,
     MigrationTaskNotFound,
     MigrationRunning,
+    JobNotFound,
+    Unreachable {
+        addresses: Vec<String>,
+    },
     InvalidConfig {
         key: String,
         value: String,
@@ -504,6 +617,8 @@ impl MetaStoreError {
             Self::InvalidProxyAddress => "INVALID_PROXY_ADDRESS",
             Self::MigrationTaskNotFound => "MIGRATION_TASK_NOT_FOUND",
             Self::MigrationRunning => "MIGRATION_RUNNING",
+            Self::JobNotFound => "JOB_NOT_FOUND",
+            Self::Unreachable { .. } => "UNREACHABLE",
             Self::InvalidConfig { .. } => "INVALID_CONFIG",
             Self::SlotsAlreadyEven => "SLOTS_ALREADY_EVEN",
             Self::SyncError(err) => err.to_code(),
@@ -546,6 +661,12 @@ impl Serialize for MetaStoreError {
                 state.serialize_field("message", &error)?;
                 state.end()
             }
+            Self::Unreachable { addresses } => {
+                let mut state = serializer.serialize_struct("MetaStoreError", 2)?;
+                state.serialize_field("error", &error_code)?;
+                state.serialize_field("addresses", &addresses)?;
+                state.end()
+            }
             _ => {
                 let mut state = serializer.serialize_struct("MetaStoreError", 1)?;
                 state.serialize_field("error", &error_code)?;
@@ -558,6 +679,7 @@ impl Serialize for MetaStoreError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::broker::migrate::MigrationPlanError;
     use crate::common::cluster::Role;
     use crate::common::config::CompressionStrategy;
     use crate::common::utils::SLOT_NUM;
@@ -578,6 +700,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_stable_json() {
+        let mut store = MetaStore::default();
+        add_testing_proxies(&mut store, 4, 3);
+        for i in (1..=4).rev() {
+            store.failed_proxies.insert(format!("127.0.0.{}:7001", i));
+        }
+
+        let json = store.to_stable_json().unwrap();
+        assert_eq!(json, store.clone().to_stable_json().unwrap());
+        let restored: MetaStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.to_stable_json().unwrap(), json);
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let failed_proxies: Vec<&str> = value["failed_proxies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|address| address.as_str().unwrap())
+            .collect();
+        assert_eq!(
+            failed_proxies,
+            vec![
+                "127.0.0.1:7001",
+                "127.0.0.2:7001",
+                "127.0.0.3:7001",
+                "127.0.0.4:7001"
+            ]
+        );
+        let first = json.find("\"127.0.0.1:7001\"").unwrap();
+        assert!(first < json.find("\"127.0.0.4:7003\"").unwrap());
+    }
+
     #[test]
     fn test_add_and_remove_proxy() {
         let migration_limit = 0;
@@ -851,6 +1006,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_deterministic_allocation() {
+        let gen_store = |reversed: bool| {
+            let mut proxies = vec![];
+            for host_index in 1..=5 {
+                for i in 1..=3 {
+                    proxies.push((host_index, i));
+                }
+            }
+            if reversed {
+                proxies.reverse();
+            }
+            let mut store = MetaStore::default();
+            for (host_index, i) in proxies.into_iter() {
+                let proxy_address = format!("127.0.0.{}:70{:02}", host_index, i);
+                let node_addresses = [
+                    format!("127.0.0.{}:60{:02}", host_index, i * 2),
+                    format!("127.0.0.{}:60{:02}", host_index, i * 2 + 1),
+                ];
+                store
+                    .add_proxy(proxy_address, node_addresses, None)
+                    .unwrap();
+            }
+            store.add_cluster("testcluster".to_string(), 8).unwrap();
+            store
+        };
+
+        let get_allocation = |store: &MetaStore| {
+            let cluster_name = ClusterName::try_from("testcluster").unwrap();
+            store
+                .clusters
+                .get(&cluster_name)
+                .unwrap()
+                .chunks
+                .iter()
+                .map(|chunk| {
+                    (
+                        chunk.proxy_addresses.clone(),
+                        chunk.node_addresses.clone(),
+                        chunk.stable_slots.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let allocation = get_allocation(&gen_store(false));
+        assert_eq!(allocation, get_allocation(&gen_store(false)));
+        assert_eq!(allocation, get_allocation(&gen_store(true)));
+    }
+
     #[test]
     fn test_failures() {
         let migration_limit = 0;
@@ -1747,6 +1952,432 @@ mod tests {
         assert_eq!(migrating_masters, 4);
     }
 
+    #[test]
+    fn test_get_migrations() {
+        let mut store = MetaStore::default();
+        add_testing_proxies(&mut store, 4, 3);
+
+        let cluster_name = CLUSTER_NAME.to_string();
+        assert!(store.get_migrations(None, 1).unwrap().is_empty());
+        store.add_cluster(cluster_name.clone(), 4).unwrap();
+        store.auto_add_nodes(cluster_name.clone(), 4).unwrap();
+        store.migrate_slots(cluster_name).unwrap();
+
+        let migrations = store.get_migrations(None, 1).unwrap();
+        assert_eq!(migrations.len(), 2);
+        let running = migrations
+            .iter()
+            .filter(|m| m.state == MigrationState::Running)
+            .count();
+        assert_eq!(running, 1);
+        for migration in migrations.iter() {
+            assert_eq!(migration.cluster_name.to_string(), CLUSTER_NAME);
+            assert!(migration.slot_range.tag.is_migrating());
+        }
+
+        let migrations = store.get_migrations(None, 0).unwrap();
+        assert!(migrations
+            .iter()
+            .all(|m| m.state == MigrationState::Running));
+
+        let migrations = store.get_migrations(Some(CLUSTER_NAME), 0).unwrap();
+        assert_eq!(migrations.len(), 2);
+        let migrations = store.get_migrations(Some("another_cluster"), 0).unwrap();
+        assert!(migrations.is_empty());
+    }
+
+    fn gen_scale_out_proxy(host_index: usize) -> ScaleOutProxy {
+        ScaleOutProxy {
+            proxy_address: format!("127.0.0.{}:7001", host_index),
+            nodes: [
+                format!("127.0.0.{}:6002", host_index),
+                format!("127.0.0.{}:6003", host_index),
+            ],
+            host: None,
+        }
+    }
+
+    #[test]
+    fn test_scale_out() {
+        let migration_limit = 1;
+        let mut store = MetaStore::default();
+        add_testing_proxies(&mut store, 3, 1);
+        let cluster_name = CLUSTER_NAME.to_string();
+        store.add_cluster(cluster_name.clone(), 4).unwrap();
+        assert_eq!(store.get_free_proxies().len(), 1);
+
+        // Nothing is changed on failure.
+        let epoch = store.get_global_epoch();
+        let err = store
+            .scale_out(cluster_name.clone(), vec![gen_scale_out_proxy(4)])
+            .unwrap_err();
+        assert_eq!(err, MetaStoreError::InvalidNodeNum);
+        let err = store
+            .scale_out(
+                "another_cluster".to_string(),
+                vec![gen_scale_out_proxy(4), gen_scale_out_proxy(5)],
+            )
+            .unwrap_err();
+        assert_eq!(err, MetaStoreError::ClusterNotFound);
+        let err = store
+            .scale_out(
+                cluster_name.clone(),
+                vec![gen_scale_out_proxy(4), gen_scale_out_proxy(4)],
+            )
+            .unwrap_err();
+        assert_eq!(err, MetaStoreError::InvalidProxyAddress);
+        assert_eq!(store.get_global_epoch(), epoch);
+        assert_eq!(store.get_proxies().len(), 3);
+
+        let job = store
+            .scale_out(
+                cluster_name.clone(),
+                vec![gen_scale_out_proxy(4), gen_scale_out_proxy(5)],
+            )
+            .unwrap();
+        assert_eq!(job.job_id, store.get_global_epoch());
+        assert!(job.migration_num > 1);
+        // The free proxy not specified is not used.
+        assert_eq!(store.get_free_proxies().len(), 1);
+
+        let progress = store
+            .get_scale_out_progress(CLUSTER_NAME, job.job_id, migration_limit)
+            .unwrap();
+        assert_eq!(progress.migration_num, job.migration_num);
+        assert_eq!(progress.running, 1);
+        assert_eq!(progress.pending, job.migration_num - 1);
+        assert_eq!(progress.done, 0);
+        assert!(!progress.finished);
+
+        let err = store
+            .scale_out(
+                cluster_name.clone(),
+                vec![gen_scale_out_proxy(6), gen_scale_out_proxy(7)],
+            )
+            .unwrap_err();
+        assert_eq!(err, MetaStoreError::MigrationRunning);
+        assert!(!store.all_proxies.contains_key("127.0.0.6:7001"));
+
+        loop {
+            let migrations = store
+                .get_migrations(Some(CLUSTER_NAME), migration_limit)
+                .unwrap();
+            let running: Vec<_> = migrations
+                .into_iter()
+                .filter(|m| m.state == MigrationState::Running)
+                .collect();
+            if running.is_empty() {
+                break;
+            }
+            for migration in running.into_iter() {
+                let task_meta = MigrationTaskMeta {
+                    cluster_name: migration.cluster_name,
+                    slot_range: migration.slot_range,
+                };
+                store.commit_migration(task_meta).unwrap();
+            }
+        }
+
+        let progress = store
+            .get_scale_out_progress(CLUSTER_NAME, job.job_id, migration_limit)
+            .unwrap();
+        assert_eq!(progress.done, job.migration_num);
+        assert!(progress.finished);
+        let cluster = store
+            .get_cluster_by_name(CLUSTER_NAME, migration_limit)
+            .unwrap();
+        check_cluster_slots(cluster, 8);
+        check_cluster_and_proxy(&store);
+
+        let err = store
+            .get_scale_out_progress("another_cluster", job.job_id, migration_limit)
+            .unwrap_err();
+        assert_eq!(err, MetaStoreError::JobNotFound);
+        store.remove_cluster(cluster_name).unwrap();
+        let err = store
+            .get_scale_out_progress(CLUSTER_NAME, job.job_id, migration_limit)
+            .unwrap_err();
+        assert_eq!(err, MetaStoreError::JobNotFound);
+    }
+
+    fn get_master_slot_nums(store: &MetaStore) -> Vec<usize> {
+        let cluster_name = ClusterName::try_from(CLUSTER_NAME).unwrap();
+        store
+            .clusters
+            .get(&cluster_name)
+            .unwrap()
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.stable_slots.iter())
+            .map(|slots| {
+                slots
+                    .as_ref()
+                    .map(|slots| slots.get_range_list().get_slots_num())
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rebalance_slots() {
+        let mut store = init_migration_test_store(4, 3, 8, 0);
+        let cluster_name = CLUSTER_NAME.to_string();
+
+        let migrations = store
+            .rebalance_slots(cluster_name.clone(), 1, false)
+            .unwrap();
+        assert!(migrations.is_empty());
+
+        // Move 1000 slots from the second master to the first one.
+        {
+            let name = ClusterName::try_from(CLUSTER_NAME).unwrap();
+            let cluster = store.clusters.get_mut(&name).unwrap();
+            let chunk = &mut cluster.chunks[0];
+            let first = chunk.stable_slots[0].as_mut().unwrap().get_mut_range_list();
+            let end = first.get_ranges().last().unwrap().end();
+            *first.get_mut_ranges().last_mut().unwrap().end_mut() += 1000;
+            let second = chunk.stable_slots[1].as_mut().unwrap().get_mut_range_list();
+            assert_eq!(second.get_ranges()[0].start(), end + 1);
+            second.get_mut_ranges()[0].0 += 1000;
+        }
+        let slot_nums = get_master_slot_nums(&store);
+        assert_eq!(slot_nums, vec![5096, 3096, 4096, 4096]);
+
+        let epoch = store.get_global_epoch();
+        let migrations = store
+            .rebalance_slots(cluster_name.clone(), 2000, false)
+            .unwrap();
+        assert!(migrations.is_empty());
+
+        let migrations = store
+            .rebalance_slots(cluster_name.clone(), 1, true)
+            .unwrap();
+        assert_eq!(migrations.len(), 1);
+        assert_eq!(migrations[0].get_range_list().get_slots_num(), 1000);
+        assert_eq!(store.get_global_epoch(), epoch);
+        assert_eq!(get_master_slot_nums(&store), slot_nums);
+        assert!(store.get_migrations(None, 0).unwrap().is_empty());
+
+        let migrations = store
+            .rebalance_slots(cluster_name.clone(), 1, false)
+            .unwrap();
+        assert_eq!(migrations.len(), 1);
+        assert!(store.get_global_epoch() > epoch);
+        assert_eq!(get_master_slot_nums(&store), vec![4096, 3096, 4096, 4096]);
+        assert_eq!(store.get_migrations(None, 0).unwrap().len(), 1);
+
+        let err = store.rebalance_slots(cluster_name, 1, true).unwrap_err();
+        assert_eq!(err, MetaStoreError::MigrationRunning);
+    }
+
+    fn get_master_addresses_and_slots(store: &MetaStore) -> Vec<(String, RangeList)> {
+        let cluster_name = ClusterName::try_from(CLUSTER_NAME).unwrap();
+        store
+            .clusters
+            .get(&cluster_name)
+            .unwrap()
+            .chunks
+            .iter()
+            .flat_map(|chunk| {
+                (0..CHUNK_PARTS).map(move |chunk_part| {
+                    let node_index = MigrationSlotRangeStore::chunk_part_to_node_index(
+                        chunk_part,
+                        chunk.role_position,
+                    );
+                    let slots = chunk.stable_slots[chunk_part]
+                        .as_ref()
+                        .map(|slots| slots.to_range_list())
+                        .unwrap_or_else(|| RangeList::new(vec![]));
+                    (chunk.node_addresses[node_index].clone(), slots)
+                })
+            })
+            .collect()
+    }
+
+    fn gen_move(src: &str, dst: &str, start: usize, end: usize) -> MigrationMove {
+        MigrationMove {
+            src_node_address: src.to_string(),
+            dst_node_address: dst.to_string(),
+            range_list: RangeList::from_single_range(Range(start, end)),
+        }
+    }
+
+    #[test]
+    fn test_validate_migration_plan() {
+        let store = init_migration_test_store(4, 3, 8, 0);
+        let cluster_name = CLUSTER_NAME.to_string();
+        let masters = get_master_addresses_and_slots(&store);
+        assert_eq!(masters.len(), 4);
+        let (m0, m1, m2) = (&masters[0].0, &masters[1].0, &masters[2].0);
+        let m0_start = masters[0].1.get_ranges()[0].start();
+        let m1_start = masters[1].1.get_ranges()[0].start();
+        let epoch = store.get_global_epoch();
+
+        let moves = vec![gen_move(m0, m1, m0_start, m0_start + 999)];
+        let validation = store
+            .validate_migration_plan(cluster_name.clone(), &moves, 0)
+            .unwrap();
+        assert!(validation.valid);
+        assert!(validation.full_coverage);
+        assert!(!validation.exceeds_migration_limit);
+        assert!(validation.errors.is_empty());
+        let impact = &validation.impact;
+        assert_eq!(impact.migrated_slots, 1000);
+        assert_eq!(impact.task_num, 1);
+        assert_eq!(impact.running_task_num, 1);
+        assert_eq!(impact.pending_task_num, 0);
+        assert_eq!(impact.estimated_rounds, 1);
+        assert_eq!(impact.slots_after[m0], 3096);
+        assert_eq!(impact.slots_after[m1], 5096);
+
+        // Nothing is changed.
+        assert_eq!(store.get_global_epoch(), epoch);
+        assert_eq!(get_master_slot_nums(&store), vec![4096, 4096, 4096, 4096]);
+        assert!(store.get_migrations(None, 0).unwrap().is_empty());
+
+        let moves = vec![
+            gen_move(m0, m1, m0_start, m0_start + 9),
+            gen_move(m0, m2, m0_start + 10, m0_start + 19),
+            gen_move(m1, m2, m1_start, m1_start + 9),
+        ];
+        // Without the limit all of them start at once.
+        let validation = store
+            .validate_migration_plan(cluster_name.clone(), &moves, 0)
+            .unwrap();
+        assert!(validation.valid);
+        assert!(!validation.exceeds_migration_limit);
+        assert_eq!(validation.impact.task_num, 3);
+        assert_eq!(validation.impact.running_task_num, 3);
+        assert_eq!(validation.impact.pending_task_num, 0);
+        assert_eq!(validation.impact.estimated_rounds, 1);
+
+        // Two moves from the same master can't run at the same time.
+        let validation = store
+            .validate_migration_plan(cluster_name.clone(), &moves, 2)
+            .unwrap();
+        assert!(validation.valid);
+        assert!(validation.exceeds_migration_limit);
+        assert_eq!(validation.impact.running_task_num, 2);
+        assert_eq!(validation.impact.pending_task_num, 1);
+        assert_eq!(validation.impact.estimated_rounds, 2);
+
+        let validation = store
+            .validate_migration_plan(cluster_name.clone(), &moves, 1)
+            .unwrap();
+        assert!(validation.valid);
+        assert!(validation.exceeds_migration_limit);
+        assert_eq!(validation.impact.running_task_num, 1);
+        assert_eq!(validation.impact.pending_task_num, 2);
+        assert_eq!(validation.impact.estimated_rounds, 3);
+
+        let err = store
+            .validate_migration_plan("invalid_cluster".to_string(), &moves, 0)
+            .unwrap_err();
+        assert_eq!(err, MetaStoreError::ClusterNotFound);
+    }
+
+    #[test]
+    fn test_validate_invalid_migration_plan() {
+        let mut store = init_migration_test_store(4, 3, 8, 0);
+        let cluster_name = CLUSTER_NAME.to_string();
+        let masters = get_master_addresses_and_slots(&store);
+        let (m0, m1) = (&masters[0].0, &masters[1].0);
+        let m0_start = masters[0].1.get_ranges()[0].start();
+        let m1_start = masters[1].1.get_ranges()[0].start();
+
+        let validation = store
+            .validate_migration_plan(cluster_name.clone(), &[], 0)
+            .unwrap();
+        assert!(!validation.valid);
+        assert!(validation.full_coverage);
+        assert_eq!(validation.errors, vec![MigrationPlanError::EmptyPlan]);
+
+        let moves = vec![
+            gen_move(m0, m1, m0_start, m0_start + 9),
+            gen_move("127.0.0.1:1234", m1, m0_start, m0_start + 9),
+            gen_move(m0, m0, m0_start, m0_start + 9),
+            gen_move(m0, m1, SLOT_NUM, SLOT_NUM + 1),
+            gen_move(m0, m1, m0_start + 5, m0_start + 14),
+            gen_move(m0, m1, m1_start, m1_start + 9),
+        ];
+        let validation = store
+            .validate_migration_plan(cluster_name.clone(), &moves, 0)
+            .unwrap();
+        assert!(!validation.valid);
+        assert!(validation.full_coverage);
+        assert_eq!(
+            validation.errors,
+            vec![
+                MigrationPlanError::UnknownNode {
+                    move_index: 1,
+                    address: "127.0.0.1:1234".to_string(),
+                },
+                MigrationPlanError::SameNode { move_index: 2 },
+                MigrationPlanError::InvalidSlots {
+                    move_index: 3,
+                    range_list: RangeList::from_single_range(Range(SLOT_NUM, SLOT_NUM + 1)),
+                },
+                MigrationPlanError::OverlappingSlots {
+                    move_index: 4,
+                    range_list: RangeList::from_single_range(Range(m0_start + 5, m0_start + 9)),
+                },
+                MigrationPlanError::SlotsNotOwned {
+                    move_index: 5,
+                    range_list: RangeList::from_single_range(Range(m1_start, m1_start + 9)),
+                },
+            ]
+        );
+        // Only the valid move is counted.
+        assert_eq!(validation.impact.migrated_slots, 10);
+        assert_eq!(validation.impact.task_num, 1);
+
+        let moves = vec![gen_move(m0, m1, m0_start, m0_start + 9)];
+        {
+            let name = ClusterName::try_from(CLUSTER_NAME).unwrap();
+            let cluster = store.clusters.get_mut(&name).unwrap();
+            let range_list = cluster.chunks[1].stable_slots[1]
+                .as_mut()
+                .unwrap()
+                .get_mut_range_list();
+            *range_list.get_mut_ranges().last_mut().unwrap().end_mut() -= 1;
+        }
+        let validation = store
+            .validate_migration_plan(cluster_name, &moves, 0)
+            .unwrap();
+        assert!(!validation.valid);
+        assert!(!validation.full_coverage);
+        assert_eq!(validation.errors.len(), 1);
+        assert_eq!(validation.errors[0].to_code(), "SLOTS_NOT_COVERED");
+    }
+
+    #[test]
+    fn test_validate_migration_plan_with_running_migration() {
+        let mut store = init_migration_test_store(4, 3, 4, 0);
+        let cluster_name = CLUSTER_NAME.to_string();
+        store.auto_add_nodes(cluster_name.clone(), 4).unwrap();
+        store.migrate_slots(cluster_name.clone()).unwrap();
+
+        let masters = get_master_addresses_and_slots(&store);
+        let m0_start = masters[0].1.get_ranges()[0].start();
+        let moves = vec![gen_move(
+            &masters[0].0,
+            &masters[1].0,
+            m0_start,
+            m0_start + 9,
+        )];
+        let validation = store
+            .validate_migration_plan(cluster_name, &moves, 0)
+            .unwrap();
+        assert!(!validation.valid);
+        // The migrating slots still belong to the source.
+        assert!(validation.full_coverage);
+        assert_eq!(
+            validation.errors,
+            vec![MigrationPlanError::MigrationRunning]
+        );
+        assert_eq!(validation.impact.running_task_num, 0);
+    }
+
     // Docs examples:
     #[test]
     fn test_one_proxy_per_host() {
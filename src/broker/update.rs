@@ -1,3 +1,4 @@
+use super::failure::FailureQuorum;
 use super::query::MetaStoreQuery;
 use super::store::{
     ChunkRolePosition, ChunkStore, ClusterStore, HostProxy, MetaStore, MetaStoreError,
@@ -142,7 +143,7 @@ impl<'a> MetaStoreUpdate<'a> {
     pub fn get_failures(
         &mut self,
         falure_ttl: chrono::Duration,
-        failure_quorum: u64,
+        failure_quorum: &dyn FailureQuorum,
     ) -> Vec<String> {
         let now = Utc::now();
         for reporter_map in self.store.failures.values_mut() {
@@ -160,7 +161,7 @@ impl<'a> MetaStoreUpdate<'a> {
         self.store
             .failures
             .iter()
-            .filter(|(_, v)| v.len() >= failure_quorum as usize)
+            .filter(|(address, reports)| failure_quorum.is_failed(address, reports))
             .filter_map(|(address, _)| {
                 if all_proxies.contains_key(address) {
                     Some(address.clone())
@@ -325,6 +326,9 @@ impl<'a> MetaStoreUpdate<'a> {
                 }
             }
         }
+        self.store
+            .scale_out_jobs
+            .retain(|_, job| job.cluster_name != cluster_name);
 
         self.store.bump_global_epoch();
         Ok(())
@@ -358,9 +362,52 @@ impl<'a> MetaStoreUpdate<'a> {
     ) -> Result<Vec<Node>, MetaStoreError> {
         let cluster_name = ClusterName::try_from(cluster_name.as_str())
             .map_err(|_| MetaStoreError::InvalidClusterName)?;
+        self.check_no_migration(&cluster_name)?;
 
-        match self.store.clusters.get(&cluster_name) {
-            None => return Err(MetaStoreError::ClusterNotFound),
+        if num % 4 != 0 {
+            return Err(MetaStoreError::InvalidNodeNum);
+        }
+        let proxy_num = NonZeroUsize::new(num / 2).ok_or_else(|| MetaStoreError::InvalidNodeNum)?;
+
+        let proxy_resource_arr = self.generate_free_chunks(proxy_num)?;
+        self.add_chunks(cluster_name, proxy_resource_arr)
+    }
+
+    // Unlike `auto_add_nodes`, the new nodes only come from the specified free proxies
+    // and all of them are used.
+    pub fn add_nodes_from_proxies(
+        &mut self,
+        cluster_name: String,
+        proxy_addresses: &[String],
+    ) -> Result<Vec<Node>, MetaStoreError> {
+        let cluster_name = ClusterName::try_from(cluster_name.as_str())
+            .map_err(|_| MetaStoreError::InvalidClusterName)?;
+        self.check_no_migration(&cluster_name)?;
+
+        if proxy_addresses.len() % 2 != 0 {
+            return Err(MetaStoreError::InvalidNodeNum);
+        }
+        let proxy_num = NonZeroUsize::new(proxy_addresses.len())
+            .ok_or_else(|| MetaStoreError::InvalidNodeNum)?;
+
+        let specified: HashSet<&String> = proxy_addresses.iter().collect();
+        let mut host_proxies = self.generate_free_host_proxies();
+        for proxies in host_proxies.values_mut() {
+            proxies.retain(|proxy_address| specified.contains(proxy_address));
+        }
+        host_proxies.retain(|_, proxies| !proxies.is_empty());
+        let free_num: usize = host_proxies.values().map(|proxies| proxies.len()).sum();
+        if free_num != specified.len() {
+            return Err(MetaStoreError::InUse);
+        }
+
+        let proxy_resource_arr = self.allocate_free_chunks(host_proxies, proxy_num)?;
+        self.add_chunks(cluster_name, proxy_resource_arr)
+    }
+
+    fn check_no_migration(&self, cluster_name: &ClusterName) -> Result<(), MetaStoreError> {
+        match self.store.clusters.get(cluster_name) {
+            None => Err(MetaStoreError::ClusterNotFound),
             Some(cluster) => {
                 if cluster
                     .chunks
@@ -369,16 +416,19 @@ impl<'a> MetaStoreUpdate<'a> {
                 {
                     return Err(MetaStoreError::MigrationRunning);
                 }
+                Ok(())
             }
-        };
-
-        if num % 4 != 0 {
-            return Err(MetaStoreError::InvalidNodeNum);
         }
-        let proxy_num = NonZeroUsize::new(num / 2).ok_or_else(|| MetaStoreError::InvalidNodeNum)?;
+    }
 
-        let proxy_resource_arr = self.generate_free_chunks(proxy_num)?;
+    // Returns the added nodes.
+    fn add_chunks(
+        &mut self,
+        cluster_name: ClusterName,
+        proxy_resource_arr: Vec<[ProxyResource; CHUNK_HALF_NODE_NUM]>,
+    ) -> Result<Vec<Node>, MetaStoreError> {
         let mut chunks = Self::proxy_resource_to_chunk_store(proxy_resource_arr, false);
+        let num = chunks.len() * CHUNK_NODE_NUM;
 
         let new_epoch = self.store.bump_global_epoch();
 
@@ -484,9 +534,16 @@ impl<'a> MetaStoreUpdate<'a> {
         &self,
         proxy_num: NonZeroUsize,
     ) -> Result<Vec<[ProxyResource; CHUNK_HALF_NODE_NUM]>, MetaStoreError> {
-        let mut host_proxies = self.generate_free_host_proxies();
+        let host_proxies = self.generate_free_host_proxies();
+        self.allocate_free_chunks(host_proxies, proxy_num)
+    }
 
-        host_proxies = Self::remove_redundant_chunks(host_proxies, proxy_num)?;
+    fn allocate_free_chunks(
+        &self,
+        host_proxies: HashMap<String, Vec<String>>,
+        proxy_num: NonZeroUsize,
+    ) -> Result<Vec<[ProxyResource; CHUNK_HALF_NODE_NUM]>, MetaStoreError> {
+        let host_proxies = Self::remove_redundant_chunks(host_proxies, proxy_num)?;
 
         let link_table = self.build_link_table();
 
@@ -527,6 +584,11 @@ impl<'a> MetaStoreUpdate<'a> {
                 .or_insert_with(Vec::new)
                 .push(proxy_address);
         }
+        // Sort them to make the allocation reproducible regardless of the insertion order.
+        // The proxies are popped from the back so the smaller addresses will be used first.
+        for proxies in host_proxies.values_mut() {
+            proxies.sort_by(|a, b| b.cmp(a));
+        }
         host_proxies
     }
 
@@ -553,9 +615,15 @@ impl<'a> MetaStoreUpdate<'a> {
         let mut new_proxy_pairs = vec![];
         while new_proxy_pairs.len() * 2 < expected_num.get() {
             let (first_host, first_address) = {
+                // Break the ties with the host name so that the allocation is deterministic.
                 let (max_host, max_proxy_host) = host_proxies
                     .iter_mut()
-                    .max_by_key(|(_host, proxies)| proxies.len())
+                    .max_by(|(host1, proxies1), (host2, proxies2)| {
+                        proxies1
+                            .len()
+                            .cmp(&proxies2.len())
+                            .then_with(|| host2.cmp(host1))
+                    })
                     .expect("allocate_chunk: invalid state. cannot find any host");
                 (
                     max_host.clone(),
@@ -624,15 +692,17 @@ impl<'a> MetaStoreUpdate<'a> {
             .max()
             .unwrap_or(0);
 
-        for proxies in host_proxies.values_mut() {
-            if proxies.len() == max_proxy_num {
-                // Only remove proxies in the host which as too many proxies.
-                while max_proxy_num * 2 > free_proxy_num {
-                    proxies.pop();
-                    free_proxy_num -= 1;
-                    max_proxy_num -= 1;
-                }
-                break;
+        let max_host = host_proxies
+            .iter()
+            .filter(|(_, proxies)| proxies.len() == max_proxy_num)
+            .map(|(host, _)| host.clone())
+            .min();
+        if let Some(proxies) = max_host.and_then(|host| host_proxies.get_mut(&host)) {
+            // Only remove proxies in the host which as too many proxies.
+            while max_proxy_num * 2 > free_proxy_num {
+                proxies.pop();
+                free_proxy_num -= 1;
+                max_proxy_num -= 1;
             }
         }
 
@@ -917,7 +987,8 @@ impl<'a> MetaStoreUpdate<'a> {
             .map(|proxies| proxies.len())
             .expect("second_host_cmp: get back host");
         // Need to reverse it as we want the host with maximum proxies.
-        host2_free.cmp(&host1_free)
+        // Fall back to the host name to make it deterministic.
+        host2_free.cmp(&host1_free).then_with(|| host1.cmp(host2))
     }
 
     pub fn balance_masters(&mut self, cluster_name: String) -> Result<(), MetaStoreError> {
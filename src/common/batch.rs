@@ -15,7 +15,7 @@ use std::time::Duration;
 // with some optimization which might not be for general purpose:
 // - Reset timer instead of setting `clock` to None for better performance.
 // - Has two different timeout to avoid triggering the real timer too many times.
-// - Flush if there's only one item even it's not timed out yet for non-pipeline requests.
+// - Optionally flush if there's only one item even it's not timed out yet for non-pipeline requests.
 
 pub trait TryChunksTimeoutStreamExt: Stream {
     fn try_chunks_timeout(
@@ -23,11 +23,18 @@ pub trait TryChunksTimeoutStreamExt: Stream {
         capacity: NonZeroUsize,
         min_duration: Duration,
         max_duration: Duration,
+        flush_single_item: bool,
     ) -> TryChunksTimeout<Self>
     where
         Self: Sized,
     {
-        TryChunksTimeout::new(self, capacity, min_duration, max_duration)
+        TryChunksTimeout::new(
+            self,
+            capacity,
+            min_duration,
+            max_duration,
+            flush_single_item,
+        )
     }
 }
 impl<T: ?Sized> TryChunksTimeoutStreamExt for T where T: Stream {}
@@ -47,6 +54,9 @@ pub struct TryChunksTimeout<St: Stream> {
     max_duration: Duration,
     last_flush_time: coarsetime::Instant,
     flush_size: usize, // Make it to be able to learn from the real pipeline number.
+    // Only for the client requests where one item in a read means a non-pipeline client.
+    // The backend tasks of many sessions should still be batched.
+    flush_single_item: bool,
 }
 
 impl<St: Stream> TryChunksTimeout<St>
@@ -58,6 +68,7 @@ where
         capacity: NonZeroUsize,
         min_duration: Duration,
         max_duration: Duration,
+        flush_single_item: bool,
     ) -> TryChunksTimeout<St> {
         TryChunksTimeout {
             stream: stream.fuse(),
@@ -68,6 +79,7 @@ where
             max_duration,
             last_flush_time: coarsetime::Instant::now(),
             flush_size: capacity.get(),
+            flush_single_item,
         }
     }
 
@@ -129,6 +141,13 @@ impl<St: Stream> Stream for TryChunksTimeout<St> {
                 return Poll::Pending;
             }
 
+            // The read only yields one item for non-pipeline requests.
+            // Don't learn from it so that the pipeline batches are not affected.
+            if self.flush_single_item && start_empty && self.items.len() == 1 {
+                *self.as_mut().project().last_flush_time = coarsetime::Instant::recent();
+                return Poll::Ready(Some(self.take()));
+            }
+
             // Learn from the last flush size.
             if self.items.len() >= self.flush_size {
                 return self.flush(coarsetime::Instant::recent());
@@ -191,7 +210,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use futures::{stream, StreamExt};
+    use futures::{stream, FutureExt, StreamExt};
     use std::iter;
     use std::time::Duration;
 
@@ -202,6 +221,7 @@ mod tests {
                 NonZeroUsize::new(5).unwrap(),
                 Duration::new(1, 0),
                 Duration::new(1, 0),
+                false,
             )
             .collect::<Vec<_>>();
         assert_eq!(vec![vec![5]], results.await);
@@ -217,6 +237,7 @@ mod tests {
             NonZeroUsize::new(5).unwrap(),
             Duration::new(1, 0),
             Duration::new(1, 0),
+            false,
         );
         assert_eq!(
             vec![vec![0, 1, 2, 3, 4], vec![5, 6, 7, 8, 9]],
@@ -224,6 +245,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn single_message_flushed_without_timeout() {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let mut chunk_stream = TryChunksTimeout::new(
+            receiver,
+            NonZeroUsize::new(5).unwrap(),
+            Duration::new(10, 0),
+            Duration::new(10, 0),
+            true,
+        );
+
+        sender.unbounded_send(1).unwrap();
+        assert_eq!(chunk_stream.next().now_or_never(), Some(Some(vec![1])));
+
+        // Pipelined items still wait for the batch.
+        sender.unbounded_send(2).unwrap();
+        sender.unbounded_send(3).unwrap();
+        assert_eq!(chunk_stream.next().now_or_never(), None);
+    }
+
+    #[test]
+    fn single_message_batched_without_flag() {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let mut chunk_stream = TryChunksTimeout::new(
+            receiver,
+            NonZeroUsize::new(5).unwrap(),
+            Duration::new(10, 0),
+            Duration::new(10, 0),
+            false,
+        );
+
+        sender.unbounded_send(1).unwrap();
+        assert_eq!(chunk_stream.next().now_or_never(), None);
+    }
+
     #[tokio::test]
     async fn message_early_exit() {
         let iter = vec![1, 2, 3, 4].into_iter();
@@ -234,6 +290,7 @@ mod tests {
             NonZeroUsize::new(5).unwrap(),
             Duration::new(1, 0),
             Duration::new(1, 0),
+            false,
         );
         assert_eq!(
             vec![vec![1, 2, 3, 4]],
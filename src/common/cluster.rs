@@ -518,6 +518,39 @@ impl MigrationTaskMeta {
     }
 }
 
+// Reported by the source proxy of the migration.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MigrationTaskProgress {
+    pub state: String,
+    pub migrated_keys: u64,
+    // Milliseconds since the last progress.
+    pub idle_ms: u64,
+}
+
+impl MigrationTaskProgress {
+    pub fn into_strings(self) -> Vec<String> {
+        vec![
+            self.state,
+            self.migrated_keys.to_string(),
+            self.idle_ms.to_string(),
+        ]
+    }
+
+    pub fn from_strings<It>(it: &mut Peekable<It>) -> Option<Self>
+    where
+        It: Iterator<Item = String>,
+    {
+        let state = it.next()?;
+        let migrated_keys = it.next()?.parse::<u64>().ok()?;
+        let idle_ms = it.next()?.parse::<u64>().ok()?;
+        Some(Self {
+            state,
+            migrated_keys,
+            idle_ms,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ReplPeer {
     pub node_address: String,
@@ -67,6 +67,22 @@ impl ClusterConfig {
                 "migration_scan_count",
                 self.migration_config.scan_count.to_string(),
             ),
+            (
+                "migration_wait_aof_timeout",
+                self.migration_config.wait_aof_timeout.to_string(),
+            ),
+            (
+                "migration_dst_ops_threshold",
+                self.migration_config.dst_ops_threshold.to_string(),
+            ),
+            (
+                "migration_throttle_delay",
+                self.migration_config.throttle_delay.to_string(),
+            ),
+            (
+                "migration_blocking_queue_alert_len",
+                self.migration_config.blocking_queue_alert_len.to_string(),
+            ),
         ]
         .into_iter()
         .map(|(k, v)| (k.to_string(), v))
@@ -143,6 +159,23 @@ pub struct MigrationConfig {
     pub max_blocking_time: u64,
     pub scan_interval: u64,
     pub scan_count: u64,
+    // In milliseconds. 0 disables WAITAOF before committing the migration.
+    #[serde(default)]
+    pub wait_aof_timeout: u64,
+    // The scanning is delayed by `throttle_delay` milliseconds
+    // when the instantaneous_ops_per_sec of the destination exceeds it.
+    // 0 disables the throttling.
+    #[serde(default)]
+    pub dst_ops_threshold: u64,
+    #[serde(default)]
+    pub throttle_delay: u64,
+    // Warn about the commands blocked by the migration when the queue gets this long.
+    #[serde(default = "default_blocking_queue_alert_len")]
+    pub blocking_queue_alert_len: u64,
+}
+
+fn default_blocking_queue_alert_len() -> u64 {
+    10000
 }
 
 impl MigrationConfig {
@@ -176,6 +209,30 @@ impl MigrationConfig {
                 }
                 self.scan_count = v;
             }
+            "wait_aof_timeout" => {
+                let v = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.wait_aof_timeout = v;
+            }
+            "dst_ops_threshold" => {
+                let v = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.dst_ops_threshold = v;
+            }
+            "throttle_delay" => {
+                let v = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.throttle_delay = v;
+            }
+            "blocking_queue_alert_len" => {
+                let v = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.blocking_queue_alert_len = v;
+            }
             _ => return Err(ConfigError::FieldNotFound),
         }
         Ok(())
@@ -189,6 +246,10 @@ impl Default for MigrationConfig {
             max_blocking_time: 10_000,       // 10 seconds waiting for switch
             scan_interval: 500,              // 500 microseconds
             scan_count: 16,
+            wait_aof_timeout: 0,
+            dst_ops_threshold: 0,
+            throttle_delay: 0,
+            blocking_queue_alert_len: default_blocking_queue_alert_len(),
         }
     }
 }
@@ -198,6 +259,10 @@ pub struct AtomicMigrationConfig {
     max_blocking_time: AtomicU64,
     scan_interval: AtomicU64,
     scan_count: AtomicU64,
+    wait_aof_timeout: AtomicU64,
+    dst_ops_threshold: AtomicU64,
+    throttle_delay: AtomicU64,
+    blocking_queue_alert_len: AtomicU64,
 }
 
 impl Default for AtomicMigrationConfig {
@@ -213,6 +278,10 @@ impl AtomicMigrationConfig {
             max_blocking_time: AtomicU64::new(config.max_blocking_time),
             scan_interval: AtomicU64::new(config.scan_interval),
             scan_count: AtomicU64::new(config.scan_count),
+            wait_aof_timeout: AtomicU64::new(config.wait_aof_timeout),
+            dst_ops_threshold: AtomicU64::new(config.dst_ops_threshold),
+            throttle_delay: AtomicU64::new(config.throttle_delay),
+            blocking_queue_alert_len: AtomicU64::new(config.blocking_queue_alert_len),
         }
     }
 
@@ -231,6 +300,22 @@ impl AtomicMigrationConfig {
     pub fn get_scan_count(&self) -> u64 {
         self.scan_count.load(Ordering::SeqCst)
     }
+
+    pub fn get_wait_aof_timeout(&self) -> u64 {
+        self.wait_aof_timeout.load(Ordering::SeqCst)
+    }
+
+    pub fn get_dst_ops_threshold(&self) -> u64 {
+        self.dst_ops_threshold.load(Ordering::SeqCst)
+    }
+
+    pub fn get_throttle_delay(&self) -> u64 {
+        self.throttle_delay.load(Ordering::SeqCst)
+    }
+
+    pub fn get_blocking_queue_alert_len(&self) -> u64 {
+        self.blocking_queue_alert_len.load(Ordering::SeqCst)
+    }
 }
 
 #[derive(Debug)]
@@ -265,5 +350,37 @@ mod tests {
             .set_field("migration_scan_count", "666")
             .unwrap();
         assert_eq!(cluster_config.migration_config.scan_count, 666);
+
+        cluster_config
+            .set_field("migration_wait_aof_timeout", "500")
+            .unwrap();
+        assert_eq!(cluster_config.migration_config.wait_aof_timeout, 500);
+
+        cluster_config
+            .set_field("migration_dst_ops_threshold", "50000")
+            .unwrap();
+        cluster_config
+            .set_field("migration_throttle_delay", "100")
+            .unwrap();
+        assert_eq!(cluster_config.migration_config.dst_ops_threshold, 50000);
+        assert_eq!(cluster_config.migration_config.throttle_delay, 100);
+
+        cluster_config
+            .set_field("migration_blocking_queue_alert_len", "233")
+            .unwrap();
+        assert_eq!(
+            cluster_config.migration_config.blocking_queue_alert_len,
+            233
+        );
+        let atomic_config = AtomicMigrationConfig::from_config(cluster_config.migration_config);
+        assert_eq!(atomic_config.get_blocking_queue_alert_len(), 233);
+    }
+
+    #[test]
+    fn test_wait_aof_timeout_defaults_to_disabled() {
+        let json = r#"{"max_migration_time":10800,"max_blocking_time":10000,"scan_interval":500,"scan_count":16}"#;
+        let migration_config: MigrationConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(migration_config.wait_aof_timeout, 0);
+        assert_eq!(migration_config.blocking_queue_alert_len, 10000);
     }
 }
@@ -599,6 +599,18 @@ mod tests {
             "mycluster",
             "migration_scan_count",
             "16",
+            "mycluster",
+            "migration_wait_aof_timeout",
+            "0",
+            "mycluster",
+            "migration_dst_ops_threshold",
+            "0",
+            "mycluster",
+            "migration_throttle_delay",
+            "0",
+            "mycluster",
+            "migration_blocking_queue_alert_len",
+            "10000",
             "othercluster",
             "compression_strategy",
             "disabled",
@@ -614,6 +626,18 @@ mod tests {
             "othercluster",
             "migration_scan_count",
             "16",
+            "othercluster",
+            "migration_wait_aof_timeout",
+            "0",
+            "othercluster",
+            "migration_dst_ops_threshold",
+            "0",
+            "othercluster",
+            "migration_throttle_delay",
+            "0",
+            "othercluster",
+            "migration_blocking_queue_alert_len",
+            "10000",
         ];
         result_args.sort();
         full_args.sort();
@@ -772,6 +796,18 @@ mod tests {
             "cluster_name",
             "migration_scan_count",
             "16",
+            "cluster_name",
+            "migration_wait_aof_timeout",
+            "0",
+            "cluster_name",
+            "migration_dst_ops_threshold",
+            "0",
+            "cluster_name",
+            "migration_throttle_delay",
+            "0",
+            "cluster_name",
+            "migration_blocking_queue_alert_len",
+            "10000",
         ]
         .into_iter()
         .map(|s| s.to_string());
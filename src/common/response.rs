@@ -5,9 +5,44 @@ pub const NOT_READY_FOR_SWITCHING_REPLY: &str = "NOT_READY_FOR_SWITCHING";
 pub const TASK_NOT_FOUND: &str = "TASK_NOT_FOUND";
 pub const ERR_NOT_THE_SAME_SLOT: &str = "ERR_MULTI_SLOTS slots of the keys are not the same";
 pub const ERR_CLUSTER_NOT_FOUND: &str = "ERR_CLUSTER_NOT_FOUND";
+pub const ERR_NO_DATABASE_SELECTED: &str = "ERR no database selected";
+pub const ERR_MONITOR_DISABLED: &str =
+    "ERR MONITOR is disabled on this proxy. Set `monitor_enabled` to enable it";
 pub const ERR_BACKEND_CONNECTION: &str = "ERR_BACKEND_CONNECTION";
+pub const ERR_BACKEND_QUARANTINED: &str = "ERR_BACKEND_QUARANTINED backend is flapping:";
 pub const ERR_MOVED: &str = "MOVED";
 pub const CMD_NOT_SUPPORTED: &str = "ERR_COMMAND_NOT_SUPPORTED";
+pub const ERR_NO_WRITE_TO_WAIT: &str = "ERR no write command in this connection to wait for";
+pub const ERR_MANAGED_BY_BROKER: &str = "ERR This command is managed by the undermoon broker";
+pub const ERR_ADMIN_PORT_ONLY: &str = "ERR this command is only allowed on the admin port";
+pub const ERR_DEBUG_NOT_ALLOWED: &str = "ERR this DEBUG subcommand is not allowed by the proxy";
+pub const ERR_MIGRATE_SAME_HOST: &str =
+    "ERR MIGRATE to this proxy won't move the keys since their nodes are decided by their slots";
+pub const ERR_COMMAND_TIMEOUT: &str = "ERR command timeout";
+pub const ERR_TOO_MANY_ARGUMENTS: &str = "ERR too many arguments";
+pub const ERR_NO_KEY_ARGUMENTS: &str = "ERR The command has no key arguments";
+pub const ERR_SHUTDOWN_NOT_SUPPORTED: &str =
+    "ERR SHUTDOWN is not supported on the undermoon proxy; use the admin interface";
+pub const ERR_DURABLE_WRITE_TIMEOUT: &str =
+    "NOREPLICAS the write is done on the master but not acknowledged by any replica in time";
+pub const ERR_NOPROTO: &str = "NOPROTO sorry, this protocol version is not supported.";
+pub const ERR_INVALID_CLIENT_NAME: &str =
+    "ERR Client names cannot contain spaces, newlines or special characters.";
+pub const RESET_REPLY: &str = "RESET";
+pub const ERR_OOM: &str = "OOM command not allowed when the buffered memory exceeds the limit";
 pub const ERR_TOO_MANY_REDIRECTIONS: &str = "ERR_TOO_MANY_REDIRECTIONS";
+pub const ERR_HTTP_REQUEST: &str =
+    "ERR this is a redis port of the undermoon proxy and HTTP is not supported";
+pub const ERR_SORT_BY_CROSS_SLOT: &str =
+    "ERR BY option of SORT denied since the keys formed by the pattern may be in different slots";
+pub const ERR_SORT_GET_CROSS_SLOT: &str =
+    "ERR GET option of SORT denied since the keys formed by the pattern may be in different slots";
+pub const ERR_INVALID_SLOT: &str = "ERR Invalid slot";
+pub const ERR_INVALID_SLOT_OR_NUMBER_OF_KEYS: &str = "ERR Invalid slot or number of keys";
+pub const ERR_DB_QUOTA_EXCEEDED: &str =
+    "ERR byte rate quota of the database exceeded, try again later";
+pub const ERR_TOPOLOGY_STALE: &str = "ERR cluster topology stale, writes disabled";
+pub const ERR_FLUSH_CACHE_DURING_MIGRATION: &str =
+    "ERR can't flush the cache during migration, try again later";
 pub const MIGRATING_FINISHED: &str = "MIGRATING_FINISHED";
 pub const MIGRATION_TASK_NOT_FOUND: &str = "MIGRATION_TASK_NOT_FOUND";
@@ -4,6 +4,7 @@ use crate::protocol::{BinSafeStr, RespVec};
 use crc16::{State, XMODEM};
 use futures::{stream, Stream};
 use std::cmp::min;
+use std::collections::HashMap;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::str;
 
@@ -135,6 +136,15 @@ pub fn change_bulk_str(resp: &mut RespVec, data: Vec<u8>) -> bool {
     }
 }
 
+// Returns the size of the payload without the protocol overhead.
+pub fn get_resp_data_size<T: AsRef<[u8]>>(resp: &Resp<T>) -> usize {
+    match resp {
+        Resp::Error(s) | Resp::Simple(s) | Resp::Bulk(BulkStr::Str(s)) => s.as_ref().len(),
+        Resp::Arr(Array::Arr(resps)) => resps.iter().map(get_resp_data_size).sum(),
+        Resp::Integer(_) | Resp::Bulk(BulkStr::Nil) | Resp::Arr(Array::Nil) => 0,
+    }
+}
+
 pub fn gen_moved(slot: usize, addr: String) -> String {
     format!("{} {} {}", ERR_MOVED, slot, addr)
 }
@@ -173,6 +183,22 @@ pub fn same_slot<'a, It: Iterator<Item = &'a [u8]>>(mut key_iter: It) -> bool {
     true
 }
 
+// Groups the keys by slot in the order of their first appearance.
+// Duplicate keys are kept.
+pub fn group_by_slot<'a, It: Iterator<Item = &'a [u8]>>(key_iter: It) -> Vec<Vec<&'a [u8]>> {
+    let mut slot_indices: HashMap<usize, usize> = HashMap::new();
+    let mut groups: Vec<Vec<&'a [u8]>> = vec![];
+    for key in key_iter {
+        let slot = generate_slot(key);
+        let index = *slot_indices.entry(slot).or_insert_with(|| {
+            groups.push(vec![]);
+            groups.len() - 1
+        });
+        groups[index].push(key);
+    }
+    groups
+}
+
 pub fn pretty_print_bytes(data: &[u8]) -> String {
     match str::from_utf8(data) {
         Ok(s) => s.to_string(),
@@ -250,6 +276,18 @@ pub fn byte_to_uppercase(b: u8) -> u8 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_get_resp_data_size() {
+        let resp: RespVec = Resp::Arr(Array::Arr(vec![
+            Resp::Bulk(BulkStr::Str(b"SET".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"key".to_vec())),
+            Resp::Bulk(BulkStr::Nil),
+            Resp::Integer(b"233".to_vec()),
+            Resp::Arr(Array::Arr(vec![Resp::Simple(b"OK".to_vec())])),
+        ]));
+        assert_eq!(get_resp_data_size(&resp), 8);
+    }
+
     #[test]
     fn test_get_hash_tag() {
         assert_eq!(
@@ -282,6 +320,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_group_by_slot() {
+        let keys: Vec<&[u8]> = vec![b"{a}1", b"{b}1", b"{a}2", b"{a}1", b"{b}1"];
+        assert_eq!(
+            group_by_slot(keys.into_iter()),
+            vec![
+                vec![&b"{a}1"[..], &b"{a}2"[..], &b"{a}1"[..]],
+                vec![&b"{b}1"[..], &b"{b}1"[..]],
+            ]
+        );
+        assert!(group_by_slot(vec![].into_iter()).is_empty());
+    }
+
     #[test]
     fn test_byte_to_uppercase() {
         assert_eq!(byte_to_uppercase(b'@'), b'@');
@@ -4,7 +4,8 @@ use crate::common::response;
 use crate::common::track::TrackedFutureRegistry;
 use crate::common::utils::resolve_first_address;
 use crate::protocol::{Array, BulkStr, Resp, RespPacket, RespVec};
-use crate::proxy::command::{new_command_pair, CmdType, Command, TaskReply};
+use crate::proxy::command::{new_command_pair, CmdType, Command, DataCmdType, TaskReply};
+use crate::proxy::memory::MemoryBudget;
 use crate::proxy::session::{handle_session, CmdHandler, CmdReplyFuture};
 use crate::proxy::slowlog::Slowlog;
 use futures::{FutureExt, StreamExt};
@@ -78,6 +79,7 @@ impl ApiService {
                     future_registry.clone(),
                 )),
                 sock,
+                Arc::new(MemoryBudget::new(0, 0)),
                 SESSION_CHANNEL_SIZE,
                 SESSION_BATCH_MIN_TIME,
                 SESSION_BATCH_MAX_TIME,
@@ -230,4 +232,12 @@ impl CmdHandler for CoordCmdHandler {
     }
 
     fn handle_slowlog(&self, _request: Box<RespPacket>, _slowlog: Slowlog) {}
+
+    fn handle_traffic(
+        &self,
+        _data_cmd_type: DataCmdType,
+        _request_size: usize,
+        _reply_size: usize,
+    ) {
+    }
 }
@@ -10,3 +10,6 @@ mod migration;
 mod recover;
 pub mod service;
 mod sync;
+
+pub(crate) use self::core::ProxyMetaSender;
+pub(crate) use self::sync::ProxyMetaRespSender;
@@ -12,5 +12,7 @@ pub mod migration;
 pub mod protocol;
 pub mod proxy;
 pub mod replication;
+#[cfg(test)]
+pub mod test_support;
 
 pub use self::migration::MAX_REDIRECTIONS;
@@ -1,6 +1,8 @@
 use super::scan_task::{RedisScanImportingTask, RedisScanMigratingTask};
 use super::task::{ImportingTask, MigratingTask, MigrationError, MigrationState, SwitchArg};
-use crate::common::cluster::{ClusterName, MigrationTaskMeta, RangeList, SlotRangeTag};
+use crate::common::cluster::{
+    ClusterName, MigrationTaskMeta, MigrationTaskProgress, RangeList, SlotRangeTag,
+};
 use crate::common::config::{AtomicMigrationConfig, ClusterConfig};
 use crate::common::proto::{ClusterConfigMap, ProxyClusterMap};
 use crate::common::track::TrackedFutureRegistry;
@@ -12,6 +14,7 @@ use crate::proxy::backend::{CmdTask, CmdTaskFactory, ReqTask};
 use crate::proxy::blocking::{BlockingHintTask, TaskBlockingControllerFactory};
 use crate::proxy::cluster::{ClusterSendError, ClusterTag};
 use crate::proxy::command::CmdTypeTuple;
+use crate::proxy::memory::MemoryBudget;
 use crate::proxy::sender::{CmdTaskSender, CmdTaskSenderFactory};
 use crate::proxy::service::ServerProxyConfig;
 use crate::proxy::slowlog::TaskEvent;
@@ -53,6 +56,7 @@ where
     proxy_sender_factory: Arc<PTSF>,
     cmd_task_factory: Arc<CTF>,
     future_registry: Arc<TrackedFutureRegistry>,
+    memory_budget: Arc<MemoryBudget>,
 }
 
 impl<RCF, TSF, PTSF, CTF> MigrationManager<RCF, TSF, PTSF, CTF>
@@ -74,6 +78,7 @@ where
         proxy_sender_factory: Arc<PTSF>,
         cmd_task_factory: Arc<CTF>,
         future_registry: Arc<TrackedFutureRegistry>,
+        memory_budget: Arc<MemoryBudget>,
     ) -> Self {
         Self {
             config,
@@ -83,6 +88,7 @@ where
             proxy_sender_factory,
             cmd_task_factory,
             future_registry,
+            memory_budget,
         }
     }
 
@@ -108,6 +114,7 @@ where
             self.proxy_sender_factory.clone(),
             self.cmd_task_factory.clone(),
             blocking_ctrl_factory,
+            self.memory_budget.clone(),
         )
     }
 
@@ -207,6 +214,10 @@ where
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
     pub fn info(&self) -> RespVec {
         let tasks = self
             .task_map
@@ -348,6 +359,7 @@ where
         proxy_sender_factory: Arc<PTSF>,
         cmd_task_factory: Arc<CTF>,
         blocking_ctrl_factory: Arc<BCF>,
+        memory_budget: Arc<MemoryBudget>,
     ) -> (Self, Vec<NewTask<T>>)
     where
         RCF: RedisClientFactory,
@@ -484,6 +496,7 @@ where
                                 sender_factory.clone(),
                                 proxy_sender_factory.clone(),
                                 cmd_task_factory.clone(),
+                                memory_budget.clone(),
                             ));
                             new_tasks.push(NewTask {
                                 cluster_name: cluster_name.clone(),
@@ -573,6 +586,35 @@ where
         metadata
     }
 
+    // Only the migrating side knows how many keys have been migrated.
+    pub fn get_progresses(&self) -> Vec<(MigrationTaskMeta, MigrationTaskProgress)> {
+        let mut progresses = vec![];
+        for tasks in self.task_map.values() {
+            for (meta, mgr_task) in tasks.iter() {
+                if let Either::Left(migrating_task) = &mgr_task.task {
+                    progresses.push((meta.clone(), migrating_task.get_progress()));
+                }
+            }
+        }
+        progresses
+    }
+
+    // Only the migrating side is checked since the importing side
+    // does nothing but waiting during the migration.
+    pub fn has_stuck_migration(&self, cluster_name: &ClusterName, stuck_timeout: u64) -> bool {
+        if stuck_timeout == 0 {
+            return false;
+        }
+        let tasks = match self.task_map.get(cluster_name) {
+            Some(tasks) => tasks,
+            None => return false,
+        };
+        tasks.values().any(|mgr_task| match &mgr_task.task {
+            Either::Left(migrating_task) => migrating_task.get_progress().idle_ms >= stuck_timeout,
+            Either::Right(_) => false,
+        })
+    }
+
     pub fn get_states(&self, cluster_name: &ClusterName) -> HashMap<RangeList, MigrationState> {
         let mut m = HashMap::new();
         if let Some(tasks) = self.task_map.get(cluster_name) {
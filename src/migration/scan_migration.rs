@@ -1,4 +1,4 @@
-use super::task::{ScanResponse, SlotRangeArray};
+use super::task::{AtomicMigrationState, ScanResponse, SlotRangeArray};
 use crate::common::cluster::SlotRange;
 use crate::common::config::AtomicMigrationConfig;
 use crate::common::future_group::{new_auto_drop_future, FutureAutoStopHandle};
@@ -9,8 +9,8 @@ use crate::common::utils::pretty_print_bytes;
 use crate::common::yield_now::YieldNow;
 use crate::migration::task::MigrationError;
 use crate::protocol::{
-    BinSafeStr, BulkStr, OptionalMulti, RedisClient, RedisClientError, RedisClientFactory, Resp,
-    RespVec,
+    Array, BinSafeStr, BulkStr, OptionalMulti, RedisClient, RedisClientError, RedisClientFactory,
+    Resp, RespVec,
 };
 use crate::proxy::backend::CmdTask;
 use atomic_option::AtomicOption;
@@ -22,12 +22,13 @@ use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub const PTTL_NO_EXPIRE: &[u8] = b"-1";
 pub const PTTL_KEY_NOT_FOUND: &[u8] = b"-2";
 pub const RESTORE_NO_EXPIRE: &[u8] = b"0";
 const BUSYKEY_ERROR: &[u8] = b"BUSYKEY";
+const LOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 pub fn pttl_to_restore_expire_time(pttl: Vec<u8>) -> Vec<u8> {
     let mut expire_time = pttl;
@@ -75,6 +76,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
         slot_range: SlotRange,
         client_factory: Arc<F>,
         config: Arc<AtomicMigrationConfig>,
+        state: Arc<AtomicMigrationState>,
     ) -> Self {
         let ranges = slot_range.to_range_list();
         let slot_ranges = SlotRangeArray::new(ranges);
@@ -87,6 +89,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
             sender.clone(),
             receiver,
             config,
+            state,
         );
 
         Self {
@@ -140,6 +143,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
         sync_tasks_sender: UnboundedSender<T>,
         sync_tasks_receiver: UnboundedReceiver<T>,
         config: Arc<AtomicMigrationConfig>,
+        state: Arc<AtomicMigrationState>,
     ) -> (MgrFut, FutureAutoStopHandle) {
         let interval = min(
             Duration::from_micros(config.get_scan_interval()),
@@ -161,6 +165,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
             sync_tasks_sender,
             sync_tasks_receiver,
             config,
+            state,
         );
 
         let (send, handle) = new_auto_drop_future(send);
@@ -177,6 +182,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
         sync_tasks_sender: UnboundedSender<T>,
         mut sync_tasks_receiver: UnboundedReceiver<T>,
         config: Arc<AtomicMigrationConfig>,
+        state: Arc<AtomicMigrationState>,
     ) -> Result<(), MigrationError> {
         const SLEEP_BATCH_TIMES: u64 = 10;
 
@@ -208,6 +214,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
         let mut scan_index = 0;
         let mut cached_dst_client = None;
         let mut sleep_count = 0;
+        let mut load_throttle = DstLoadThrottle::default();
         loop {
             let mut src_client = match client_factory.create_client(src_address.clone()).await {
                 Ok(client) => client,
@@ -251,6 +258,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
                             dst_address.clone(),
                             client_factory.clone(),
                             cmd_tasks,
+                            &state,
                         )
                         .await;
                         match res {
@@ -265,6 +273,17 @@ impl<T: CmdTask> ScanMigrationTask<T> {
                         continue;
                     }
                     None => {
+                        if let Some(mut dst_client) = cached_dst_client.take() {
+                            match load_throttle
+                                .throttle(&mut dst_client, &dst_address, &config)
+                                .await
+                            {
+                                Ok(()) => cached_dst_client = Some(dst_client),
+                                Err(err) => {
+                                    warn!("failed to check the load of {}: {:?}", dst_address, err)
+                                }
+                            }
+                        }
                         Self::scan_and_migrate_keys(
                             &slot_ranges,
                             scan_index,
@@ -273,6 +292,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
                             dst_address.clone(),
                             client_factory.clone(),
                             scan_count,
+                            &state,
                         )
                         .await
                     }
@@ -285,6 +305,18 @@ impl<T: CmdTask> ScanMigrationTask<T> {
                     }
                     Ok((new_scan_index, dst_client)) => {
                         if new_scan_index == 0 {
+                            let wait_aof_timeout = config.get_wait_aof_timeout();
+                            if wait_aof_timeout > 0 {
+                                // WAITAOF only waits for the writes sent by the same connection.
+                                // The restored data is written by the cached destination client
+                                // and the deletion by the source client.
+                                if let Some(mut dst_client) = dst_client {
+                                    Self::wait_aof(&mut dst_client, &dst_address, wait_aof_timeout)
+                                        .await?;
+                                }
+                                Self::wait_aof(&mut src_client, &src_address, wait_aof_timeout)
+                                    .await?;
+                            }
                             sync_tasks_sender.close_channel();
                             while let Some(cmd_tasks) = sync_tasks_receiver.next().await {
                                 for cmd_task in cmd_tasks.into_iter() {
@@ -312,20 +344,24 @@ impl<T: CmdTask> ScanMigrationTask<T> {
         dst_address: String,
         client_factory: Arc<F>,
         scan_count: u64,
+        state: &AtomicMigrationState,
     ) -> Result<(u64, Option<F::Client>), RedisClientError> {
         let ScanResponse { next_index, keys } =
             Self::scan_keys(src_client, index, scan_count).await?;
 
         let entries = Self::produce_entries(slot_ranges, keys, src_client).await?;
         if entries.is_empty() {
+            state.record_progress();
             return Ok((next_index, dst_client));
         }
 
         let transferred_keys: Vec<_> = entries.iter().map(|entry| entry.key.clone()).collect();
+        let keys_num = transferred_keys.len() as u64;
         let dst_client =
             Self::forward_entries(dst_address, dst_client, client_factory, entries).await;
 
         Self::delete_keys(src_client, transferred_keys).await?;
+        state.add_migrated_keys(keys_num);
         Ok((next_index, Some(dst_client)))
     }
 
@@ -336,6 +372,7 @@ impl<T: CmdTask> ScanMigrationTask<T> {
         dst_address: String,
         client_factory: Arc<F>,
         cmd_tasks: Vec<T>,
+        state: &AtomicMigrationState,
     ) -> Result<Option<F::Client>, RedisClientError> {
         let keys = cmd_tasks
             .iter()
@@ -349,13 +386,17 @@ impl<T: CmdTask> ScanMigrationTask<T> {
                 } else {
                     let transferred_keys: Vec<_> =
                         entries.iter().map(|entry| entry.key.clone()).collect();
+                    let keys_num = transferred_keys.len() as u64;
                     let dst_client =
                         Self::forward_entries(dst_address, dst_client, client_factory, entries)
                             .await;
 
                     Self::delete_keys(src_client, transferred_keys)
                         .await
-                        .map(move |()| Some(dst_client))
+                        .map(move |()| {
+                            state.add_migrated_keys(keys_num);
+                            Some(dst_client)
+                        })
                 }
             }
             Err(err) => Err(err),
@@ -505,6 +546,46 @@ impl<T: CmdTask> ScanMigrationTask<T> {
         .await
     }
 
+    // The migration fails without committing when the data is not fsynced in time.
+    // Only the Redis without WAITAOF or with AOF disabled is skipped.
+    async fn wait_aof<C: RedisClient>(
+        client: &mut C,
+        address: &str,
+        timeout: u64,
+    ) -> Result<(), MigrationError> {
+        let cmd = vec![
+            b"WAITAOF".to_vec(),
+            b"1".to_vec(),
+            b"0".to_vec(),
+            timeout.to_string().into_bytes(),
+        ];
+        let resp = client.execute_single(cmd).await.map_err(|err| {
+            error!("failed to send WAITAOF to {}: {:?}", address, err);
+            MigrationError::RedisClient(err)
+        })?;
+        match check_wait_aof_reply(&resp) {
+            WaitAofResult::Fsynced => {
+                info!("WAITAOF done on {}", address);
+                Ok(())
+            }
+            WaitAofResult::Timeout => {
+                error!("WAITAOF timeout after {}ms on {}", timeout, address);
+                Err(MigrationError::Timeout)
+            }
+            WaitAofResult::Unsupported => {
+                warn!(
+                    "WAITAOF is not available on {}: {:?}. Skip it.",
+                    address, resp
+                );
+                Ok(())
+            }
+            WaitAofResult::Failed => {
+                error!("WAITAOF failed on {}: {:?}", address, resp);
+                Err(MigrationError::RedisClient(RedisClientError::InvalidReply))
+            }
+        }
+    }
+
     async fn delete_keys<C: RedisClient>(
         client: &mut C,
         keys: Vec<BinSafeStr>,
@@ -523,8 +604,382 @@ impl<T: CmdTask> ScanMigrationTask<T> {
     }
 }
 
+// Slows down the scanning when the destination is busy.
+// The blocking requests are not delayed since the clients are waiting for them.
+#[derive(Default)]
+struct DstLoadThrottle {
+    last_check: Option<Instant>,
+    busy: bool,
+}
+
+impl DstLoadThrottle {
+    async fn throttle<C: RedisClient>(
+        &mut self,
+        client: &mut C,
+        address: &str,
+        config: &AtomicMigrationConfig,
+    ) -> Result<(), RedisClientError> {
+        let threshold = config.get_dst_ops_threshold();
+        let delay = config.get_throttle_delay();
+        if threshold == 0 || delay == 0 {
+            return Ok(());
+        }
+
+        let need_check = self.last_check.map_or(true, |last_check| {
+            last_check.elapsed() >= LOAD_CHECK_INTERVAL
+        });
+        if need_check {
+            self.last_check = Some(Instant::now());
+            let resp = client
+                .execute_single(vec![b"INFO".to_vec(), b"stats".to_vec()])
+                .await?;
+            let ops = match resp {
+                Resp::Bulk(BulkStr::Str(info)) => parse_ops_per_sec(&info),
+                _ => None,
+            };
+            let busy = ops.map_or(false, |ops| ops > threshold);
+            if busy != self.busy {
+                info!(
+                    "migration to {} throttled: {} instantaneous_ops_per_sec: {:?}",
+                    address, busy, ops
+                );
+            }
+            self.busy = busy;
+        }
+
+        if self.busy {
+            Delay::new(Duration::from_millis(delay)).await;
+        }
+        Ok(())
+    }
+}
+
+fn parse_ops_per_sec(info: &[u8]) -> Option<u64> {
+    const OPS_FIELD: &[u8] = b"instantaneous_ops_per_sec:";
+    info.split(|b| *b == b'\n')
+        .find(|line| line.starts_with(OPS_FIELD))
+        .and_then(|line| {
+            let value = &line[OPS_FIELD.len()..];
+            let value = value.strip_suffix(b"\r").unwrap_or(value);
+            btoi::btou::<u64>(value).ok()
+        })
+}
+
+#[derive(Debug, PartialEq)]
+enum WaitAofResult {
+    Fsynced,
+    Timeout,
+    // Redis before 7.2 or with AOF disabled.
+    Unsupported,
+    Failed,
+}
+
+fn check_wait_aof_reply(resp: &RespVec) -> WaitAofResult {
+    match resp {
+        Resp::Arr(Array::Arr(resps)) => match resps.get(0) {
+            Some(Resp::Integer(n)) => match btoi::btoi::<i64>(n) {
+                Ok(n) if n >= 1 => WaitAofResult::Fsynced,
+                Ok(_) => WaitAofResult::Timeout,
+                Err(_) => WaitAofResult::Failed,
+            },
+            _ => WaitAofResult::Failed,
+        },
+        Resp::Error(err) => {
+            let err = String::from_utf8_lossy(err).to_lowercase();
+            if err.starts_with("err unknown command") || err.contains("appendonly") {
+                WaitAofResult::Unsupported
+            } else {
+                WaitAofResult::Failed
+            }
+        }
+        _ => WaitAofResult::Failed,
+    }
+}
+
 impl<T: CmdTask> Drop for ScanMigrationTask<T> {
     fn drop(&mut self) {
         self.stop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::cluster::RangeList;
+    use crate::common::config::MigrationConfig;
+    use crate::migration::task::MigrationState;
+    use crate::protocol::DummyRedisClientFactory;
+    use crate::proxy::session::CmdCtx;
+    use std::convert::TryFrom;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Mutex;
+
+    // Replies by the command names and records them.
+    #[derive(Clone, Default)]
+    struct FakeClient {
+        commands: Arc<Mutex<Vec<String>>>,
+        ops_per_sec: Arc<AtomicU64>,
+        aof_fsynced: Arc<AtomicU64>,
+    }
+
+    impl FakeClient {
+        fn reply(&self, cmd: &[BinSafeStr]) -> RespVec {
+            let name = String::from_utf8_lossy(&cmd[0]).to_uppercase();
+            self.commands.lock().unwrap().push(name.clone());
+            match name.as_str() {
+                "SCAN" => Resp::Arr(Array::Arr(vec![
+                    Resp::Bulk(BulkStr::Str(b"0".to_vec())),
+                    Resp::Arr(Array::Arr(vec![
+                        Resp::Bulk(BulkStr::Str(b"key1".to_vec())),
+                        Resp::Bulk(BulkStr::Str(b"key2".to_vec())),
+                    ])),
+                ])),
+                "PTTL" => Resp::Integer(b"-1".to_vec()),
+                "DUMP" => Resp::Bulk(BulkStr::Str(b"data".to_vec())),
+                "DEL" => Resp::Integer(b"2".to_vec()),
+                "INFO" => {
+                    let info = format!(
+                        "# Stats\r\ninstantaneous_ops_per_sec:{}\r\n",
+                        self.ops_per_sec.load(Ordering::SeqCst)
+                    );
+                    Resp::Bulk(BulkStr::Str(info.into_bytes()))
+                }
+                "WAITAOF" => {
+                    let fsynced = self.aof_fsynced.load(Ordering::SeqCst);
+                    Resp::Arr(Array::Arr(vec![
+                        Resp::Integer(fsynced.to_string().into_bytes()),
+                        Resp::Integer(b"0".to_vec()),
+                    ]))
+                }
+                _ => Resp::Simple(b"OK".to_vec()),
+            }
+        }
+
+        fn get_commands(&self) -> Vec<String> {
+            self.commands.lock().unwrap().clone()
+        }
+    }
+
+    impl RedisClient for FakeClient {
+        fn execute<'s>(
+            &'s mut self,
+            command: OptionalMulti<Vec<BinSafeStr>>,
+        ) -> Pin<
+            Box<dyn Future<Output = Result<OptionalMulti<RespVec>, RedisClientError>> + Send + 's>,
+        > {
+            let reply = match command {
+                OptionalMulti::Single(cmd) => OptionalMulti::Single(self.reply(&cmd)),
+                OptionalMulti::Multi(cmds) => {
+                    OptionalMulti::Multi(cmds.iter().map(|cmd| self.reply(cmd)).collect())
+                }
+            };
+            Box::pin(async move { Ok(reply) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrated_keys_progress() {
+        let dst_client = FakeClient::default();
+        let dst_client_clone = dst_client.clone();
+        let client_factory = Arc::new(DummyRedisClientFactory::new(move || {
+            dst_client_clone.clone()
+        }));
+        let mut src_client = FakeClient::default();
+        let slot_ranges = SlotRangeArray::new(RangeList::try_from("1 0-16383").unwrap());
+        let state = AtomicMigrationState::initial_state();
+
+        let (next_index, dst) = ScanMigrationTask::<CmdCtx>::scan_and_migrate_keys(
+            &slot_ranges,
+            0,
+            None,
+            &mut src_client,
+            "127.0.0.1:6001".to_string(),
+            client_factory,
+            10,
+            &state,
+        )
+        .await
+        .unwrap();
+        assert_eq!(next_index, 0);
+        assert!(dst.is_some());
+
+        assert_eq!(
+            src_client.get_commands(),
+            vec!["SCAN", "PTTL", "DUMP", "PTTL", "DUMP", "DEL"]
+        );
+        assert_eq!(dst_client.get_commands(), vec!["RESTORE", "RESTORE"]);
+        let progress = state.get_progress();
+        assert_eq!(progress.migrated_keys, 2);
+        assert_eq!(progress.state, MigrationState::PreCheck.to_string());
+
+        Delay::new(Duration::from_millis(50)).await;
+        assert!(state.get_progress().idle_ms >= 50);
+        state.set_state(MigrationState::Scanning);
+        let progress = state.get_progress();
+        assert!(progress.idle_ms < 50);
+        assert_eq!(progress.state, MigrationState::Scanning.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_dst_load_throttle() {
+        let config = AtomicMigrationConfig::from_config(MigrationConfig {
+            dst_ops_threshold: 1000,
+            throttle_delay: 50,
+            ..MigrationConfig::default()
+        });
+        let mut client = FakeClient::default();
+        let mut throttle = DstLoadThrottle::default();
+
+        // Not busy.
+        client.ops_per_sec.store(999, Ordering::SeqCst);
+        let start = Instant::now();
+        throttle
+            .throttle(&mut client, "127.0.0.1:6001", &config)
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert!(!throttle.busy);
+        assert_eq!(client.get_commands(), vec!["INFO"]);
+
+        // The load is only checked once in LOAD_CHECK_INTERVAL.
+        client.ops_per_sec.store(5000, Ordering::SeqCst);
+        throttle
+            .throttle(&mut client, "127.0.0.1:6001", &config)
+            .await
+            .unwrap();
+        assert!(!throttle.busy);
+        assert_eq!(client.get_commands().len(), 1);
+
+        throttle.last_check = Some(Instant::now() - LOAD_CHECK_INTERVAL);
+        let start = Instant::now();
+        throttle
+            .throttle(&mut client, "127.0.0.1:6001", &config)
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(throttle.busy);
+        assert_eq!(client.get_commands().len(), 2);
+
+        // Keeps slowing down until the next check.
+        let start = Instant::now();
+        throttle
+            .throttle(&mut client, "127.0.0.1:6001", &config)
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(client.get_commands().len(), 2);
+
+        client.ops_per_sec.store(10, Ordering::SeqCst);
+        throttle.last_check = Some(Instant::now() - LOAD_CHECK_INTERVAL);
+        let start = Instant::now();
+        throttle
+            .throttle(&mut client, "127.0.0.1:6001", &config)
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert!(!throttle.busy);
+    }
+
+    #[tokio::test]
+    async fn test_dst_load_throttle_disabled() {
+        let config = AtomicMigrationConfig::default();
+        let mut client = FakeClient::default();
+        client.ops_per_sec.store(5000, Ordering::SeqCst);
+        let mut throttle = DstLoadThrottle::default();
+        throttle
+            .throttle(&mut client, "127.0.0.1:6001", &config)
+            .await
+            .unwrap();
+        assert!(!throttle.busy);
+        assert!(client.get_commands().is_empty());
+    }
+
+    #[test]
+    fn test_parse_ops_per_sec() {
+        let info = b"# Stats\r\ntotal_commands_processed:100\r\ninstantaneous_ops_per_sec:2333\r\ninstantaneous_input_kbps:1.5\r\n";
+        assert_eq!(parse_ops_per_sec(info), Some(2333));
+        assert_eq!(parse_ops_per_sec(b"# Stats\r\n"), None);
+        assert_eq!(
+            parse_ops_per_sec(b"instantaneous_ops_per_sec:abc\r\n"),
+            None
+        );
+    }
+
+    async fn run_migration_with_wait_aof(
+        client: FakeClient,
+        wait_aof_timeout: u64,
+    ) -> Result<(), MigrationError> {
+        let client_clone = client.clone();
+        let client_factory = Arc::new(DummyRedisClientFactory::new(move || client_clone.clone()));
+        let config = Arc::new(AtomicMigrationConfig::from_config(MigrationConfig {
+            wait_aof_timeout,
+            ..MigrationConfig::default()
+        }));
+        let (sender, receiver) = unbounded::<CmdCtx>();
+        ScanMigrationTask::keep_migrating(
+            "127.0.0.1:6000".to_string(),
+            "127.0.0.1:6001".to_string(),
+            SlotRangeArray::new(RangeList::try_from("1 0-16383").unwrap()),
+            client_factory,
+            sender,
+            receiver,
+            config,
+            Arc::new(AtomicMigrationState::initial_state()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_wait_aof_before_commit() {
+        let client = FakeClient::default();
+        client.aof_fsynced.store(1, Ordering::SeqCst);
+        let res = run_migration_with_wait_aof(client.clone(), 100).await;
+        assert!(res.is_ok());
+        let commands = client.get_commands();
+        let wait_aof_count = commands.iter().filter(|cmd| *cmd == "WAITAOF").count();
+        // Both the destination and the source.
+        assert_eq!(wait_aof_count, 2);
+
+        let client = FakeClient::default();
+        let res = run_migration_with_wait_aof(client.clone(), 100).await;
+        match res {
+            Err(MigrationError::Timeout) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let client = FakeClient::default();
+        let res = run_migration_with_wait_aof(client.clone(), 0).await;
+        assert!(res.is_ok());
+        assert!(!client.get_commands().contains(&"WAITAOF".to_string()));
+    }
+
+    #[test]
+    fn test_check_wait_aof_reply() {
+        let reply = |local: &[u8]| {
+            Resp::Arr(Array::Arr(vec![
+                Resp::Integer(local.to_vec()),
+                Resp::Integer(b"0".to_vec()),
+            ]))
+        };
+        assert_eq!(check_wait_aof_reply(&reply(b"1")), WaitAofResult::Fsynced);
+        assert_eq!(check_wait_aof_reply(&reply(b"0")), WaitAofResult::Timeout);
+
+        let unknown = Resp::Error(
+            b"ERR unknown command 'WAITAOF', with args beginning with: '1' '0' '100' ".to_vec(),
+        );
+        assert_eq!(check_wait_aof_reply(&unknown), WaitAofResult::Unsupported);
+        let aof_disabled = Resp::Error(
+            b"ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.".to_vec(),
+        );
+        assert_eq!(
+            check_wait_aof_reply(&aof_disabled),
+            WaitAofResult::Unsupported
+        );
+        let other = Resp::Error(b"LOADING".to_vec());
+        assert_eq!(check_wait_aof_reply(&other), WaitAofResult::Failed);
+        assert_eq!(
+            check_wait_aof_reply(&Resp::Simple(b"OK".to_vec())),
+            WaitAofResult::Failed
+        );
+    }
+}
@@ -4,7 +4,8 @@ use super::task::{
     SwitchArg,
 };
 use crate::common::cluster::{
-    ClusterName, MigrationMeta, MigrationTaskMeta, RangeMap, SlotRange, SlotRangeTag,
+    ClusterName, MigrationMeta, MigrationTaskMeta, MigrationTaskProgress, RangeMap, SlotRange,
+    SlotRangeTag,
 };
 use crate::common::config::AtomicMigrationConfig;
 use crate::common::resp_execution::keep_connecting_and_sending_cmd;
@@ -18,6 +19,7 @@ use crate::proxy::backend::{CmdTask, CmdTaskFactory, ReqTask};
 use crate::proxy::blocking::{BlockingHandle, BlockingHintTask, TaskBlockingController};
 use crate::proxy::cluster::ClusterSendError;
 use crate::proxy::command::CmdTypeTuple;
+use crate::proxy::memory::MemoryBudget;
 use crate::proxy::migration_backend::RestoreDataCmdTaskHandler;
 use crate::proxy::sender::{CmdTaskSender, CmdTaskSenderFactory};
 use crate::proxy::service::ServerProxyConfig;
@@ -38,6 +40,8 @@ use std::time::Duration;
 // (4) importing node again and finally process it.
 pub const MAX_REDIRECTIONS: usize = 4;
 
+const BLOCKING_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct RedisScanMigratingTask<RCF, T, BC>
 where
     RCF: RedisClientFactory,
@@ -76,12 +80,14 @@ where
         blocking_ctrl: Arc<BC>,
     ) -> Self {
         let (stop_signal_sender, stop_signal_receiver) = oneshot::channel();
+        let state = Arc::new(AtomicMigrationState::initial_state());
         let task = ScanMigrationTask::new(
             meta.src_node_address.clone(),
             meta.dst_node_address.clone(),
             slot_range.clone(),
             client_factory.clone(),
             mgr_config.clone(),
+            state.clone(),
         );
         let range_map = RangeMap::from(slot_range.get_range_list());
         let active_redirection = config.active_redirection;
@@ -91,7 +97,7 @@ where
             slot_range,
             range_map,
             meta,
-            state: Arc::new(AtomicMigrationState::initial_state()),
+            state,
             client_factory,
             stop_signal_sender: AtomicOption::new(Box::new(stop_signal_sender)),
             stop_signal_receiver: AtomicOption::new(Box::new(stop_signal_receiver)),
@@ -317,6 +323,32 @@ where
         Ok(())
     }
 
+    // Never ends. It should be dropped when the blocking is done.
+    async fn log_blocking_queue(&self, max_blocking_time: Duration) {
+        loop {
+            Delay::new(BLOCKING_LOG_INTERVAL).await;
+            let blocking_time = match self.blocking_ctrl.get_blocking_time() {
+                Some(blocking_time) => blocking_time,
+                None => continue,
+            };
+            let queue_len = self.blocking_ctrl.get_queue_len();
+            let msg = format!(
+                "migration {} {} has blocked {} commands for {:?}",
+                self.cluster_name,
+                self.slot_range.get_range_list(),
+                queue_len,
+                blocking_time
+            );
+            // Also warn when the blocking has taken half of `max_blocking_time`.
+            let alert_len = self.mgr_config.get_blocking_queue_alert_len();
+            if queue_len as u64 >= alert_len || blocking_time >= max_blocking_time / 2 {
+                warn!("{}", msg);
+            } else {
+                info!("{}", msg);
+            }
+        }
+    }
+
     async fn run_migration(&self) -> Result<(), MigrationError> {
         let pre_check = self.pre_check();
         let pre_block = self.pre_block();
@@ -334,9 +366,13 @@ where
         let max_blocking_time = self.mgr_config.get_max_blocking_time();
         let max_blocking_time = Duration::from_millis(max_blocking_time);
         let blocking_timeout = Delay::new(max_blocking_time);
+        let blocking = future::select(
+            Box::pin(blocking),
+            Box::pin(self.log_blocking_queue(max_blocking_time)),
+        );
 
         let res = select! {
-            () = blocking.fuse() => Ok(()),
+            _ = blocking.fuse() => Ok(()),
             () = blocking_timeout.fuse() => Err(MigrationError::Timeout),
         };
 
@@ -427,6 +463,10 @@ where
         self.state.get_state()
     }
 
+    fn get_progress(&self) -> MigrationTaskProgress {
+        self.state.get_progress()
+    }
+
     fn contains_slot(&self, slot: usize) -> bool {
         self.range_map.contains_slot(slot)
     }
@@ -512,6 +552,7 @@ where
         sender_factory: Arc<TSF>,
         proxy_sender_factory: Arc<PTSF>,
         cmd_task_factory: Arc<CTF>,
+        memory_budget: Arc<MemoryBudget>,
     ) -> Self {
         let src_sender = sender_factory.create(meta.src_node_address.clone());
         let dst_sender = sender_factory.create(meta.dst_node_address.clone());
@@ -521,6 +562,8 @@ where
             dst_sender,
             src_proxy_sender,
             cmd_task_factory.clone(),
+            Duration::from_millis(config.migration_read_your_writes_window),
+            memory_budget,
         );
         let (stop_signal_sender, stop_signal_receiver) = oneshot::channel();
         let range_map = RangeMap::from(slot_range.get_range_list());
@@ -604,6 +647,10 @@ where
         self.state.get_state()
     }
 
+    fn get_progress(&self) -> MigrationTaskProgress {
+        self.state.get_progress()
+    }
+
     fn contains_slot(&self, slot: usize) -> bool {
         self.range_map.contains_slot(slot)
     }
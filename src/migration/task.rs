@@ -1,4 +1,6 @@
-use crate::common::cluster::{MigrationTaskMeta, Range, RangeList, RangeMap};
+use crate::common::cluster::{
+    MigrationTaskMeta, MigrationTaskProgress, Range, RangeList, RangeMap,
+};
 use crate::common::utils::{generate_slot, get_resp_bytes, get_resp_strings, ThreadSafe};
 use crate::protocol::{Array, BinSafeStr, BulkStr, RedisClientError, Resp, RespSlice, RespVec};
 use crate::proxy::backend::CmdTask;
@@ -13,7 +15,8 @@ use std::io;
 use std::iter::Peekable;
 use std::pin::Pin;
 use std::str;
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub enum MgrSubCmd {
@@ -56,20 +59,30 @@ impl fmt::Display for MigrationState {
     }
 }
 
+// Besides the state, it also tracks the progress of the task.
+// Both the state switching and the scanning count as progress.
 #[derive(Debug)]
 pub struct AtomicMigrationState {
     inner: AtomicU16,
+    created: Instant,
+    // Milliseconds since `created`.
+    last_progress: AtomicU64,
+    migrated_keys: AtomicU64,
 }
 
 impl AtomicMigrationState {
     pub fn initial_state() -> Self {
         Self {
             inner: AtomicU16::new(MigrationState::PreCheck as u16),
+            created: Instant::now(),
+            last_progress: AtomicU64::new(0),
+            migrated_keys: AtomicU64::new(0),
         }
     }
 
     pub fn set_state(&self, state: MigrationState) {
         self.inner.store(state as u16, Ordering::SeqCst);
+        self.record_progress();
     }
 
     pub fn get_state(&self) -> MigrationState {
@@ -82,6 +95,26 @@ impl AtomicMigrationState {
             _ => MigrationState::SwitchCommitted,
         }
     }
+
+    pub fn record_progress(&self) {
+        let elapsed = self.created.elapsed().as_millis() as u64;
+        self.last_progress.fetch_max(elapsed, Ordering::SeqCst);
+    }
+
+    pub fn add_migrated_keys(&self, keys_num: u64) {
+        self.migrated_keys.fetch_add(keys_num, Ordering::SeqCst);
+        self.record_progress();
+    }
+
+    pub fn get_progress(&self) -> MigrationTaskProgress {
+        let last_progress = Duration::from_millis(self.last_progress.load(Ordering::SeqCst));
+        let idle = self.created.elapsed().checked_sub(last_progress);
+        MigrationTaskProgress {
+            state: self.get_state().to_string(),
+            migrated_keys: self.migrated_keys.load(Ordering::SeqCst),
+            idle_ms: idle.unwrap_or_default().as_millis() as u64,
+        }
+    }
 }
 
 pub trait MigratingTask: ThreadSafe {
@@ -98,6 +131,7 @@ pub trait MigratingTask: ThreadSafe {
         cmd_task: Self::Task,
     ) -> Result<(), ClusterSendError<BlockingHintTask<Self::Task>>>;
     fn get_state(&self) -> MigrationState;
+    fn get_progress(&self) -> MigrationTaskProgress;
     fn contains_slot(&self, slot: usize) -> bool;
     fn get_stop_handle(&self) -> Option<Box<dyn Drop + Send + Sync + 'static>>;
 }
@@ -112,6 +146,7 @@ pub trait ImportingTask: ThreadSafe {
         cmd_task: Self::Task,
     ) -> Result<(), ClusterSendError<BlockingHintTask<Self::Task>>>;
     fn get_state(&self) -> MigrationState;
+    fn get_progress(&self) -> MigrationTaskProgress;
     fn contains_slot(&self, slot: usize) -> bool;
     fn get_stop_handle(&self) -> Option<Box<dyn Drop + Send + Sync + 'static>>;
     fn handle_switch(
@@ -5,6 +5,10 @@ use std::io;
 #[derive(Debug)]
 pub enum DecodeError {
     InvalidProtocol,
+    // The client sent an HTTP request to the redis port.
+    HttpRequest,
+    // Checked by the array header before the arguments are buffered.
+    TooManyArguments,
     Io(io::Error),
 }
 
@@ -33,4 +37,41 @@ impl From<io::Error> for DecodeError {
     }
 }
 
+pub const CR: u8 = b'\r';
 pub const LF: u8 = b'\n';
+
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET /",
+    b"HEAD /",
+    b"POST ",
+    b"PUT ",
+    b"DELETE ",
+    b"PATCH ",
+    b"OPTIONS ",
+    b"CONNECT ",
+    b"TRACE ",
+];
+
+// Only checks the first few bytes so it's cheap even on the large invalid input.
+pub fn is_http_request(buf: &[u8]) -> bool {
+    HTTP_METHODS.iter().any(|method| buf.starts_with(method))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_http_request() {
+        assert!(is_http_request(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        ));
+        assert!(is_http_request(b"GET /metrics HTTP/1.1\r\n"));
+        assert!(is_http_request(b"POST /api HTTP/1.1\r\n"));
+        assert!(is_http_request(b"OPTIONS * HTTP/1.1\r\n"));
+        assert!(!is_http_request(b"*2\r\n$3\r\nGET\r\n$1\r\na\r\n"));
+        assert!(!is_http_request(b"GET a\r\n"));
+        assert!(!is_http_request(b"GE"));
+        assert!(!is_http_request(b""));
+    }
+}
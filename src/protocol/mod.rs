@@ -26,3 +26,4 @@ pub use self::resp::{
     BulkStrIndex, BulkStrSlice, BulkStrVec, IndexedResp, Resp, RespBytes, RespIndex, RespSlice,
     RespVec,
 };
+pub use self::stateless::parse_array_len;
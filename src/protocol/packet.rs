@@ -1,14 +1,14 @@
-use super::decoder::DecodeError;
+use super::decoder::{is_http_request, DecodeError};
 use super::encoder::{command_to_buf, encode_resp};
 use super::fp::{RFunctor, VFunctor};
-use super::resp::{BinSafeStr, IndexedResp, Resp, RespSlice, RespVec};
+use super::resp::{BinSafeStr, BulkStr, IndexedResp, Resp, RespSlice, RespVec};
 use super::stateless::{parse_indexed_resp, ParseError};
 use crate::common::utils::{
     array_append_front, change_bulk_array_element, change_bulk_str, get_command_element,
-    get_command_len, left_trim_array, ThreadSafe,
+    get_command_len, get_resp_data_size, left_trim_array, ThreadSafe,
 };
 use crate::protocol::EncodeError;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use std::io;
 use std::marker::PhantomData;
 use std::str;
@@ -34,6 +34,11 @@ pub trait DecodedPacket {
     fn decode(buf: &mut BytesMut, hint: Self::Hint) -> Result<Option<Self>, DecodeError>
     where
         Self: Sized;
+
+    // RESP3 push messages are not the replies of any command.
+    fn is_push(&self) -> bool {
+        false
+    }
 }
 
 pub trait FromResp {
@@ -93,6 +98,10 @@ pub trait PacketDecoder {
 pub enum RespPacket {
     Indexed(IndexedResp),
     Data(RespVec),
+    // The encoded replies written to the client as they are,
+    // e.g. the multiple replies of SSUBSCRIBE.
+    // It's not a single complete RESP so it's viewed as raw data.
+    Encoded(Bytes),
 }
 
 impl RespPacket {
@@ -104,6 +113,7 @@ impl RespPacket {
         match self {
             Self::Indexed(indexed_resp) => indexed_resp.to_resp_vec(),
             Self::Data(resp) => resp.clone(),
+            Self::Encoded(data) => Resp::Bulk(BulkStr::Str(data.to_vec())),
         }
     }
 
@@ -111,6 +121,7 @@ impl RespPacket {
         match self {
             Self::Indexed(indexed_resp) => indexed_resp.get_array_element(index),
             Self::Data(resp) => get_command_element(&resp, index),
+            Self::Encoded(_) => None,
         }
     }
 
@@ -124,6 +135,7 @@ impl RespPacket {
         match self {
             Self::Indexed(indexed_resp) => indexed_resp.get_array_len(),
             Self::Data(resp) => get_command_len(&resp),
+            Self::Encoded(_) => None,
         }
     }
 
@@ -132,10 +144,20 @@ impl RespPacket {
         str::from_utf8(element).ok()
     }
 
+    // For the owned data, this is an estimation without the protocol overhead.
+    pub fn get_data_size(&self) -> usize {
+        match self {
+            Self::Indexed(indexed_resp) => indexed_resp.get_data().len(),
+            Self::Data(resp) => get_resp_data_size(resp),
+            Self::Encoded(data) => data.len(),
+        }
+    }
+
     pub fn to_resp_slice(&self) -> RespSlice {
         match self {
             Self::Indexed(indexed_resp) => indexed_resp.to_resp_slice(),
             Self::Data(resp) => resp.as_ref().map(|a| a.as_slice()),
+            Self::Encoded(data) => Resp::Bulk(BulkStr::Str(data.as_ref())),
         }
     }
 
@@ -143,6 +165,7 @@ impl RespPacket {
         match self {
             Self::Indexed(indexed_resp) => indexed_resp.to_resp_vec(),
             Self::Data(resp) => resp,
+            Self::Encoded(data) => Resp::Bulk(BulkStr::Str(data.to_vec())),
         }
     }
 
@@ -150,6 +173,7 @@ impl RespPacket {
         let mut resp = match self {
             Self::Indexed(indexed_resp) => indexed_resp.to_resp_vec(),
             Self::Data(resp) => return change_bulk_array_element(resp, index, data),
+            Self::Encoded(_) => return false,
         };
         let success = change_bulk_array_element(&mut resp, index, data);
         if success {
@@ -167,6 +191,17 @@ impl RespPacket {
                 Some(remaining)
             }
             Self::Data(resp) => left_trim_array(resp, removed_num),
+            Self::Encoded(_) => None,
+        }
+    }
+
+    // The raw data containing RESP3 types is replaced by the RESP2 one
+    // for the clients only speaking RESP2.
+    pub fn downconvert_resp3(&mut self) {
+        if let Self::Indexed(indexed_resp) = self {
+            if indexed_resp.is_resp3() {
+                *self = Self::Data(indexed_resp.to_resp_vec());
+            }
         }
     }
 
@@ -181,6 +216,7 @@ impl RespPacket {
                 true
             }
             Self::Data(resp) => array_append_front(resp, preceding_elements),
+            Self::Encoded(_) => false,
         }
     }
 
@@ -188,6 +224,7 @@ impl RespPacket {
         let mut resp = match self {
             Self::Indexed(indexed_resp) => indexed_resp.to_resp_vec(),
             Self::Data(resp) => return change_bulk_str(resp, data),
+            Self::Encoded(_) => return false,
         };
         let success = change_bulk_str(&mut resp, data);
         if success {
@@ -282,6 +319,10 @@ impl DecodedPacket for IndexedResp {
             Ok(r) => Ok(Some(r)),
             Err(e) => match e {
                 ParseError::NotEnoughData => Ok(None),
+                // Only checked on the error path.
+                ParseError::InvalidProtocol if is_http_request(buf) => {
+                    Err(DecodeError::HttpRequest)
+                }
                 ParseError::InvalidProtocol => Err(DecodeError::InvalidProtocol),
                 ParseError::UnexpectedErr => {
                     error!("Unexpected error");
@@ -290,6 +331,10 @@ impl DecodedPacket for IndexedResp {
             },
         }
     }
+
+    fn is_push(&self) -> bool {
+        IndexedResp::is_push(self)
+    }
 }
 
 impl EncodedPacket for RespPacket {
@@ -311,6 +356,10 @@ impl EncodedPacket for RespPacket {
                 f(&b);
                 Ok((size, f))
             }
+            RespPacket::Encoded(data) => {
+                f(&data);
+                Ok((data.len(), f))
+            }
         }
     }
 
@@ -326,6 +375,13 @@ impl DecodedPacket for RespPacket {
     {
         Ok(IndexedResp::decode(buf, ())?.map(RespPacket::Indexed))
     }
+
+    fn is_push(&self) -> bool {
+        match self {
+            Self::Indexed(indexed_resp) => indexed_resp.is_push(),
+            Self::Data(_) | Self::Encoded(_) => false,
+        }
+    }
 }
 
 impl<T: EncodedPacket> EncodedPacket for Box<T> {
@@ -350,6 +406,10 @@ impl<T: DecodedPacket> DecodedPacket for Box<T> {
     {
         Ok(T::decode(buf, hint)?.map(Box::new))
     }
+
+    fn is_push(&self) -> bool {
+        (**self).is_push()
+    }
 }
 
 pub fn new_simple_packet_codec<E: EncodedPacket<Hint = ()>, D: DecodedPacket<Hint = ()>>(
@@ -497,6 +557,13 @@ impl<T: DecodedPacket> DecodedPacket for OptionalMulti<T> {
         }
         Ok(Some(OptionalMulti::Multi(packets)))
     }
+
+    fn is_push(&self) -> bool {
+        match self {
+            Self::Single(p) => p.is_push(),
+            Self::Multi(_) => false,
+        }
+    }
 }
 
 struct OptionalMultiHintState {
@@ -523,7 +590,9 @@ impl OptionalMultiHintState {
             OptionalMultiHint::Single(()) => 1,
             OptionalMultiHint::Multi(v) => v.len() + 2,
         };
-        self.state.compare_and_swap(0, n, Ordering::SeqCst) == 0
+        self.state
+            .compare_exchange(0, n, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
     }
 }
 
@@ -707,4 +776,75 @@ mod tests {
         };
         assert_eq!(response.len(), 0);
     }
+
+    #[test]
+    fn test_decode_packets_byte_by_byte() {
+        let (_, mut decoder) = new_simple_packet_codec::<RespPacket, RespPacket>();
+        let data: &[u8] = b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n*1\r\n$4\r\nPING\r\n";
+        let mut buf = BytesMut::new();
+        let mut packets = vec![];
+        for b in data.iter() {
+            buf.extend_from_slice(&[*b]);
+            while let Some(packet) = decoder.decode(&mut buf).unwrap() {
+                packets.push(packet);
+            }
+        }
+        assert!(buf.is_empty());
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].get_command_name(), Some("GET"));
+        assert_eq!(packets[0].get_array_element(1), Some(&b"foo"[..]));
+        assert_eq!(packets[1].get_command_name(), Some("PING"));
+
+        // The bulk string longer than its length should not be taken as the next command.
+        let mut buf = BytesMut::from(&b"*1\r\n$3\r\nPINGPONG\r\n"[..]);
+        assert_matches!(decoder.decode(&mut buf), Err(DecodeError::InvalidProtocol));
+    }
+
+    #[test]
+    fn test_error_reply_relayed_verbatim() {
+        let (mut encoder, mut decoder) = new_simple_packet_codec::<RespPacket, RespPacket>();
+        let reply: &[u8] =
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let mut buf = BytesMut::from(reply);
+        let packet = decoder.decode(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(
+            packet.to_resp_vec(),
+            Resp::Error(
+                b"WRONGTYPE Operation against a key holding the wrong kind of value".to_vec()
+            )
+        );
+
+        let mut out = vec![];
+        encoder
+            .encode(packet, |data| out.extend_from_slice(data))
+            .unwrap();
+        assert_eq!(out, reply);
+    }
+
+    #[test]
+    fn test_downconvert_resp3() {
+        let (mut encoder, mut decoder) = new_simple_packet_codec::<RespPacket, RespPacket>();
+        let mut buf = BytesMut::from(
+            &b"%1\r\n$1\r\na\r\n#t\r\n>2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\nk\r\n+OK\r\n"[..],
+        );
+        let mut map = decoder.decode(&mut buf).unwrap().unwrap();
+        let mut push = decoder.decode(&mut buf).unwrap().unwrap();
+        let mut ok = decoder.decode(&mut buf).unwrap().unwrap();
+        map.downconvert_resp3();
+        push.downconvert_resp3();
+        ok.downconvert_resp3();
+        assert_matches!(ok, RespPacket::Indexed(_));
+
+        let mut out = vec![];
+        for packet in vec![map, push, ok] {
+            encoder
+                .encode(packet, |data| out.extend_from_slice(data))
+                .unwrap();
+        }
+        assert_eq!(
+            out,
+            b"*2\r\n$1\r\na\r\n:1\r\n*2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\nk\r\n+OK\r\n".to_vec()
+        );
+    }
 }
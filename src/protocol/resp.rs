@@ -28,11 +28,13 @@ pub type RespIndex = Resp<DataIndex>;
 pub struct IndexedResp {
     resp: RespIndex,
     data: Bytes,
+    // Whether the data contains any RESP3 type.
+    resp3: bool,
 }
 
 impl IndexedResp {
-    pub fn new(resp: RespIndex, data: Bytes) -> Self {
-        Self { resp, data }
+    pub fn new(resp: RespIndex, data: Bytes, resp3: bool) -> Self {
+        Self { resp, data, resp3 }
     }
 
     pub fn get_array_element(&self, index: usize) -> Option<&[u8]> {
@@ -62,15 +64,39 @@ impl IndexedResp {
         self.resp.map_to_slice(&self.data)
     }
 
+    // The RESP3 types are already parsed into the RESP2 ones
+    // except that the booleans are the integers of `t` or `f`.
     pub fn to_resp_vec(&self) -> RespVec {
-        self.resp.as_ref().map(|DataIndex(s, e)| {
+        let mut resp = self.resp.as_ref().map(|DataIndex(s, e)| {
             (&self.data.get(*s..*e).expect("IndexedResp::to_resp_vec")).to_vec()
-        })
+        });
+        if self.resp3 {
+            bool_to_integer(&mut resp);
+        }
+        resp
     }
 
     pub fn get_data(&self) -> &[u8] {
         &self.data
     }
+
+    pub fn is_resp3(&self) -> bool {
+        self.resp3
+    }
+
+    // RESP3 push messages like the invalidation messages of client side caching.
+    pub fn is_push(&self) -> bool {
+        self.data.first() == Some(&b'>')
+    }
+}
+
+fn bool_to_integer(resp: &mut RespVec) {
+    match resp {
+        Resp::Integer(b) if b.as_slice() == b"t" => *b = b"1".to_vec(),
+        Resp::Integer(b) if b.as_slice() == b"f" => *b = b"0".to_vec(),
+        Resp::Arr(Array::Arr(resps)) => resps.iter_mut().for_each(bool_to_integer),
+        _ => (),
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -1,4 +1,4 @@
-use super::decoder::LF;
+use super::decoder::{CR, LF};
 use super::resp::{AdvanceIndex, ArrayIndex, BulkStrIndex, DataIndex, IndexedResp, RespIndex};
 use btoi::btoi;
 use bytes::BytesMut;
@@ -30,12 +30,36 @@ impl Error for ParseError {
 }
 
 pub fn parse_indexed_resp(buf: &mut BytesMut) -> Result<IndexedResp, ParseError> {
-    let (resp, consumed) = parse_resp(&buf)?;
+    let mut resp3 = false;
+    let (resp, consumed) = parse_value(&buf, &mut resp3)?;
     let data = buf.split_to(consumed).freeze();
-    Ok(IndexedResp::new(resp, data))
+    Ok(IndexedResp::new(resp, data, resp3))
 }
 
+// Only the header of the array is parsed so the length could be checked
+// before the elements arrive. `None` means it's not an array.
+pub fn parse_array_len(buf: &[u8]) -> Result<Option<i64>, ParseError> {
+    match buf.split_first() {
+        Some((b'*', next_buf)) => parse_len(next_buf).map(|(len, _)| Some(len)),
+        Some(_) => Ok(None),
+        None => Err(ParseError::NotEnoughData),
+    }
+}
+
+#[cfg(test)]
 pub fn parse_resp(buf: &[u8]) -> Result<(RespIndex, usize), ParseError> {
+    parse_value(buf, &mut false)
+}
+
+// The RESP3 types are parsed into the RESP2 ones with `resp3` set,
+// so that the raw data won't be sent to the RESP2 clients:
+// - map, set and push => array, the map is flattened to key value pairs.
+// - double, big number and verbatim string => bulk string.
+// - null => nil bulk string.
+// - boolean => integer indexing `t` or `f`, see `IndexedResp::to_resp2_vec`.
+// - blob error => error.
+// - attribute => skipped and the value following it is returned.
+fn parse_value(buf: &[u8], resp3: &mut bool) -> Result<(RespIndex, usize), ParseError> {
     if buf.is_empty() {
         return Err(ParseError::NotEnoughData);
     }
@@ -65,10 +89,23 @@ pub fn parse_resp(buf: &[u8]) -> Result<(RespIndex, usize), ParseError> {
             Ok((RespIndex::Error(v), 1 + consumed))
         }
         b'*' => {
-            let (mut v, consumed) = parse_array(next_buf)?;
+            let (mut v, consumed) = parse_array(next_buf, resp3)?;
             v.advance(1);
             Ok((RespIndex::Arr(v), 1 + consumed))
         }
+        // `IndexedResp::is_push` could still tell it from the array by the raw data.
+        b'>' => {
+            *resp3 = true;
+            let (mut v, consumed) = parse_array(next_buf, resp3)?;
+            v.advance(1);
+            Ok((RespIndex::Arr(v), 1 + consumed))
+        }
+        b'~' | b'%' | b',' | b'(' | b'=' | b'_' | b'#' | b'!' | b'|' => {
+            *resp3 = true;
+            let (mut v, consumed) = parse_resp3_value(prefix, next_buf, resp3)?;
+            v.advance(1);
+            Ok((v, 1 + consumed))
+        }
         prefix => {
             debug!("invalid prefix {:?}", prefix);
             Err(ParseError::InvalidProtocol)
@@ -76,20 +113,97 @@ pub fn parse_resp(buf: &[u8]) -> Result<(RespIndex, usize), ParseError> {
     }
 }
 
-fn parse_array(buf: &[u8]) -> Result<(ArrayIndex, usize), ParseError> {
-    let (len, mut consumed) = parse_len(buf)?;
+fn parse_resp3_value(
+    prefix: u8,
+    buf: &[u8],
+    resp3: &mut bool,
+) -> Result<(RespIndex, usize), ParseError> {
+    match prefix {
+        b'~' => {
+            let (v, consumed) = parse_array(buf, resp3)?;
+            Ok((RespIndex::Arr(v), consumed))
+        }
+        b'%' => {
+            let (len, consumed) = parse_map_len(buf)?;
+            let (v, elements_consumed) = parse_elements(buf, consumed, len, resp3)?;
+            Ok((RespIndex::Arr(v), elements_consumed))
+        }
+        b',' | b'(' => {
+            let (v, consumed) = parse_line(buf)?;
+            Ok((RespIndex::Bulk(BulkStrIndex::Str(v)), consumed))
+        }
+        // The `txt:` or `mkd:` format is removed.
+        b'=' => match parse_bulk_str(buf)? {
+            (BulkStrIndex::Str(DataIndex(s, e)), consumed) if e - s >= 4 => Ok((
+                RespIndex::Bulk(BulkStrIndex::Str(DataIndex(s + 4, e))),
+                consumed,
+            )),
+            _ => Err(ParseError::InvalidProtocol),
+        },
+        b'_' => {
+            let (_, consumed) = parse_line(buf)?;
+            Ok((RespIndex::Bulk(BulkStrIndex::Nil), consumed))
+        }
+        b'#' => {
+            let (v, consumed) = parse_line(buf)?;
+            match buf.get(v.to_range()) {
+                Some(b"t") | Some(b"f") => Ok((RespIndex::Integer(v), consumed)),
+                _ => Err(ParseError::InvalidProtocol),
+            }
+        }
+        b'!' => match parse_bulk_str(buf)? {
+            (BulkStrIndex::Str(v), consumed) => Ok((RespIndex::Error(v), consumed)),
+            (BulkStrIndex::Nil, _) => Err(ParseError::InvalidProtocol),
+        },
+        b'|' => {
+            let (len, consumed) = parse_map_len(buf)?;
+            let (_, attr_consumed) = parse_elements(buf, consumed, len, resp3)?;
+            let next_buf = buf
+                .get(attr_consumed..)
+                .ok_or_else(|| ParseError::InvalidProtocol)?;
+            let (mut v, consumed) = parse_value(next_buf, resp3)?;
+            v.advance(attr_consumed);
+            Ok((v, attr_consumed + consumed))
+        }
+        _ => Err(ParseError::InvalidProtocol),
+    }
+}
+
+// The number of the keys and values.
+fn parse_map_len(buf: &[u8]) -> Result<(i64, usize), ParseError> {
+    let (len, consumed) = parse_len(buf)?;
+    let len = len
+        .checked_mul(2)
+        .ok_or_else(|| ParseError::InvalidProtocol)?;
+    Ok((len, consumed))
+}
+
+fn parse_array(buf: &[u8], resp3: &mut bool) -> Result<(ArrayIndex, usize), ParseError> {
+    let (len, consumed) = parse_len(buf)?;
+    parse_elements(buf, consumed, len, resp3)
+}
+
+// Parses `len` elements starting from `consumed`.
+fn parse_elements(
+    buf: &[u8],
+    mut consumed: usize,
+    len: i64,
+    resp3: &mut bool,
+) -> Result<(ArrayIndex, usize), ParseError> {
     if len < 0 {
         return Ok((ArrayIndex::Nil, consumed));
     }
 
     let array_size = len as usize;
-    let mut array = Vec::with_capacity(array_size);
+    // The length comes from the client and the elements might not have arrived yet.
+    // Each element takes at least 3 bytes.
+    let mut array = Vec::with_capacity(array_size.min(buf.len() / 3));
 
     for _ in 0..array_size {
         let next_buf = buf
             .get(consumed..)
             .ok_or_else(|| ParseError::InvalidProtocol)?;
-        let (mut v, element_consumed) = parse_resp(next_buf)?;
+        let (mut v, element_consumed) = parse_value(next_buf, resp3)?;
         v.advance(consumed);
         consumed += element_consumed;
         array.push(v);
@@ -108,6 +222,10 @@ fn parse_bulk_str(buf: &[u8]) -> Result<(BulkStrIndex, usize), ParseError> {
     if buf.len() < consumed + content_size + 2 {
         return Err(ParseError::NotEnoughData);
     }
+    // Otherwise a wrong length would make the rest of the stream misaligned.
+    if buf[consumed + content_size..consumed + content_size + 2] != [CR, LF] {
+        return Err(ParseError::InvalidProtocol);
+    }
 
     let s = DataIndex(consumed, consumed + content_size);
     Ok((BulkStrIndex::Str(s), consumed + content_size + 2))
@@ -125,7 +243,7 @@ fn parse_len(buf: &[u8]) -> Result<(i64, usize), ParseError> {
 
 fn parse_line(buf: &[u8]) -> Result<(DataIndex, usize), ParseError> {
     let lf_index = memchr(LF, &buf).ok_or_else(|| ParseError::NotEnoughData)?;
-    if lf_index == 0 {
+    if lf_index == 0 || buf[lf_index - 1] != CR {
         return Err(ParseError::InvalidProtocol);
     }
 
@@ -138,7 +256,10 @@ fn parse_line(buf: &[u8]) -> Result<(DataIndex, usize), ParseError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::resp::{ArraySlice, BulkStrSlice, RespSlice};
+    use crate::protocol::resp::{
+        Array, ArraySlice, BulkStr, BulkStrSlice, Resp, RespSlice, RespVec,
+    };
+    use matches::assert_matches;
 
     #[test]
     fn test_parse_len_bytes() {
@@ -158,6 +279,15 @@ mod tests {
         assert!(r.is_err());
     }
 
+    #[test]
+    fn test_parse_array_len() {
+        assert_matches!(parse_array_len(b"*3\r\n$3\r\nDEL"), Ok(Some(3)));
+        assert_matches!(parse_array_len(b"*-1\r\n"), Ok(Some(-1)));
+        assert_matches!(parse_array_len(b"$3\r\nDEL\r\n"), Ok(None));
+        assert_matches!(parse_array_len(b"*3"), Err(ParseError::NotEnoughData));
+        assert_matches!(parse_array_len(b""), Err(ParseError::NotEnoughData));
+    }
+
     #[test]
     fn test_parse_line_bytes() {
         let data = b"233\r\n";
@@ -173,6 +303,9 @@ mod tests {
         let (b, l) = r.unwrap();
         assert_eq!(l, 2);
         assert_eq!(&data[b.to_range()], b"".as_ref());
+
+        assert_matches!(parse_line(b"233\r"), Err(ParseError::NotEnoughData));
+        assert_matches!(parse_line(b"233\n"), Err(ParseError::InvalidProtocol));
     }
 
     #[test]
@@ -214,15 +347,14 @@ mod tests {
         let r = parse_bulk_str(b"2\r\na\r\n");
         assert!(r.is_err());
 
-        // TODO: Support this check
-        // let r = parse_bulk_str("2\r\nabc\r\n".as_bytes());
-        // assert!(r.is_err());
+        let r = parse_bulk_str(b"2\r\nabc\r\n");
+        assert_matches!(r, Err(ParseError::InvalidProtocol));
     }
 
     #[test]
     fn test_parse_array_bytes() {
         let data = b"2\r\n$1\r\na\r\n$2\r\nbc\r\n";
-        let r = parse_array(data);
+        let r = parse_array(data, &mut false);
         assert!(r.is_ok());
         let (a, s) = r.unwrap();
         assert_eq!(s, 18);
@@ -235,24 +367,27 @@ mod tests {
             arr
         );
 
-        let r = parse_array(b"-1\r\n");
+        let r = parse_array(b"-1\r\n", &mut false);
         assert!(r.is_ok());
         let (a, s) = r.unwrap();
         assert_eq!(s, 4);
         assert_eq!(ArrayIndex::Nil, a);
 
-        let r = parse_array(b"0\r\n");
+        let r = parse_array(b"0\r\n", &mut false);
         assert!(r.is_ok());
         let (a, s) = r.unwrap();
         assert_eq!(s, 3);
         assert_eq!(ArrayIndex::Arr(vec![]), a);
 
-        let r = parse_array(b"1\r\n$2\r\na\r\n");
+        let r = parse_array(b"1\r\n$2\r\na\r\n", &mut false);
         assert!(r.is_err());
 
-        // TODO: Support this check
-        // let r = parse_array("1\r\n$2\r\nabc\r\n".as_bytes());
-        // assert!(r.is_err());
+        let r = parse_array(b"1\r\n$2\r\nabc\r\n", &mut false);
+        assert_matches!(r, Err(ParseError::InvalidProtocol));
+
+        // The elements have not arrived yet.
+        let r = parse_array(b"9223372036854775807\r\n", &mut false);
+        assert_matches!(r, Err(ParseError::NotEnoughData));
     }
 
     #[test]
@@ -300,4 +435,171 @@ mod tests {
             a.map_to_slice(data),
         );
     }
+
+    const PIPELINE: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$10\r\nbar\r\nbaz\r\n\r\n\
+        *2\r\n$3\r\nGET\r\n$0\r\n\r\n\
+        *0\r\n\
+        *-1\r\n\
+        *2\r\n*1\r\n:-12\r\n$-1\r\n\
+        +OK\r\n\
+        -ERR x\r\n";
+
+    fn parse_all(buf: &mut BytesMut) -> Vec<RespVec> {
+        let mut resps = vec![];
+        loop {
+            match parse_indexed_resp(buf) {
+                Ok(resp) => resps.push(resp.to_resp_vec()),
+                Err(ParseError::NotEnoughData) => return resps,
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_incomplete_data() {
+        let mut offset = 0;
+        while offset < PIPELINE.len() {
+            let (_, consumed) = parse_resp(&PIPELINE[offset..]).unwrap();
+            for end in offset..(offset + consumed) {
+                assert_matches!(
+                    parse_resp(&PIPELINE[offset..end]),
+                    Err(ParseError::NotEnoughData)
+                );
+            }
+            offset += consumed;
+        }
+    }
+
+    #[test]
+    fn test_parse_byte_by_byte() {
+        let expected = parse_all(&mut BytesMut::from(PIPELINE));
+        assert_eq!(expected.len(), 7);
+        assert_eq!(
+            expected[0],
+            Resp::Arr(Array::Arr(vec![
+                Resp::Bulk(BulkStr::Str(b"SET".to_vec())),
+                Resp::Bulk(BulkStr::Str(b"foo".to_vec())),
+                Resp::Bulk(BulkStr::Str(b"bar\r\nbaz\r\n".to_vec())),
+            ]))
+        );
+
+        let mut buf = BytesMut::new();
+        let mut resps = vec![];
+        for b in PIPELINE.iter() {
+            buf.extend_from_slice(&[*b]);
+            resps.extend(parse_all(&mut buf));
+        }
+        assert!(buf.is_empty());
+        assert_eq!(resps, expected);
+    }
+
+    #[test]
+    fn test_parse_split_at_any_position() {
+        let expected = parse_all(&mut BytesMut::from(PIPELINE));
+        for i in 0..=PIPELINE.len() {
+            for j in i..=PIPELINE.len() {
+                let mut buf = BytesMut::new();
+                let mut resps = vec![];
+                for chunk in &[&PIPELINE[..i], &PIPELINE[i..j], &PIPELINE[j..]] {
+                    buf.extend_from_slice(chunk);
+                    resps.extend(parse_all(&mut buf));
+                }
+                assert!(buf.is_empty());
+                assert_eq!(resps, expected, "split at {} and {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_push() {
+        let data = b">2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nkey\r\n+OK\r\n";
+        let mut buf = BytesMut::from(&data[..]);
+
+        let push = parse_indexed_resp(&mut buf).unwrap();
+        assert!(push.is_push());
+        // So that it will be downconverted for the RESP2 clients.
+        assert!(push.is_resp3());
+        assert_eq!(push.get_array_element(0), Some(&b"invalidate"[..]));
+        assert_eq!(
+            push.to_resp_slice(),
+            RespSlice::Arr(ArraySlice::Arr(vec![
+                RespSlice::Bulk(BulkStrSlice::Str(b"invalidate")),
+                RespSlice::Arr(ArraySlice::Arr(vec![RespSlice::Bulk(BulkStrSlice::Str(
+                    b"key"
+                ))])),
+            ]))
+        );
+
+        let reply = parse_indexed_resp(&mut buf).unwrap();
+        assert!(!reply.is_push());
+        assert!(!reply.is_resp3());
+        assert!(buf.is_empty());
+    }
+
+    fn parse_resp3(data: &[u8]) -> RespVec {
+        let mut buf = BytesMut::from(data);
+        let resp = parse_indexed_resp(&mut buf).unwrap();
+        assert!(resp.is_resp3());
+        assert!(buf.is_empty());
+        resp.to_resp_vec()
+    }
+
+    #[test]
+    fn test_parse_resp3() {
+        let bulk = |s: &[u8]| Resp::Bulk(BulkStr::Str(s.to_vec()));
+        assert_eq!(
+            parse_resp3(b"%2\r\n+a\r\n:1\r\n$1\r\nb\r\n:2\r\n"),
+            Resp::Arr(Array::Arr(vec![
+                Resp::Simple(b"a".to_vec()),
+                Resp::Integer(b"1".to_vec()),
+                bulk(b"b"),
+                Resp::Integer(b"2".to_vec()),
+            ]))
+        );
+        assert_eq!(
+            parse_resp3(b"~2\r\n$1\r\na\r\n$1\r\nb\r\n"),
+            Resp::Arr(Array::Arr(vec![bulk(b"a"), bulk(b"b")]))
+        );
+        assert_eq!(parse_resp3(b",3.14\r\n"), bulk(b"3.14"));
+        assert_eq!(parse_resp3(b",inf\r\n"), bulk(b"inf"));
+        assert_eq!(
+            parse_resp3(b"(3492890328409238509324850943850943825024385\r\n"),
+            bulk(b"3492890328409238509324850943850943825024385")
+        );
+        assert_eq!(parse_resp3(b"_\r\n"), Resp::Bulk(BulkStr::Nil));
+        assert_eq!(parse_resp3(b"#t\r\n"), Resp::Integer(b"1".to_vec()));
+        assert_eq!(parse_resp3(b"#f\r\n"), Resp::Integer(b"0".to_vec()));
+        assert_eq!(
+            parse_resp3(b"=15\r\ntxt:Some string\r\n"),
+            bulk(b"Some string")
+        );
+        assert_eq!(
+            parse_resp3(b"!21\r\nSYNTAX invalid syntax\r\n"),
+            Resp::Error(b"SYNTAX invalid syntax".to_vec())
+        );
+        assert_eq!(
+            parse_resp3(b"|1\r\n+ttl\r\n:3600\r\n$1\r\nv\r\n"),
+            bulk(b"v")
+        );
+        // Nested in the RESP2 array.
+        assert_eq!(
+            parse_resp3(b"*2\r\n%1\r\n+k\r\n#t\r\n~1\r\n_\r\n"),
+            Resp::Arr(Array::Arr(vec![
+                Resp::Arr(Array::Arr(vec![
+                    Resp::Simple(b"k".to_vec()),
+                    Resp::Integer(b"1".to_vec()),
+                ])),
+                Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Nil)])),
+            ]))
+        );
+
+        let mut buf = BytesMut::from(&b"*1\r\n:1\r\n"[..]);
+        assert!(!parse_indexed_resp(&mut buf).unwrap().is_resp3());
+        assert_matches!(parse_resp(b"#x\r\n"), Err(ParseError::InvalidProtocol));
+        assert_matches!(
+            parse_resp(b"=2\r\nab\r\n"),
+            Err(ParseError::InvalidProtocol)
+        );
+        assert_matches!(parse_resp(b"%1\r\n+k\r\n"), Err(ParseError::NotEnoughData));
+    }
 }
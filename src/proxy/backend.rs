@@ -1,20 +1,27 @@
 use super::command::{CommandError, CommandResult};
+use super::flapping::BackendFlapDetector;
+use super::inflight::BackendInFlight;
 use super::service::ServerProxyConfig;
 use super::slowlog::TaskEvent;
 use crate::common::batch::TryChunksTimeoutStreamExt;
+use crate::common::response::{ERR_BACKEND_CONNECTION, ERR_BACKEND_QUARANTINED};
 use crate::common::utils::{resolve_first_address, ThreadSafe};
 use crate::protocol::{
-    new_simple_packet_codec, DecodeError, EncodeError, EncodedPacket, FromResp, MonoPacket,
-    OptionalMulti, Packet, Resp, RespCodec, RespVec,
+    new_simple_packet_codec, DecodeError, DecodedPacket, EncodeError, EncodedPacket, FromResp,
+    MonoPacket, OptionalMulti, Packet, Resp, RespCodec, RespVec,
 };
 use futures::channel::mpsc;
-use futures::{select, stream, Future, FutureExt, Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use futures::{
+    future, select, stream, Future, FutureExt, Sink, SinkExt, Stream, StreamExt, TryStreamExt,
+};
 use futures_timer::Delay;
 use std::boxed::Box;
 use std::error::Error;
 use std::fmt;
 use std::io;
+use std::iter;
 use std::marker::PhantomData;
+use std::mem;
 use std::net::SocketAddr;
 use std::num::NonZeroUsize;
 use std::pin::Pin;
@@ -61,6 +68,38 @@ pub trait CmdTask: ThreadSafe {
         Self: Sized;
 
     fn log_event(&mut self, event: TaskEvent);
+
+    // The tasks with the same hint are sent through the same backend connection
+    // so that their order is kept.
+    fn get_conn_hint(&self) -> Option<usize> {
+        None
+    }
+
+    // The unknown tasks are treated as writes.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    // Not counted in the in-flight depth of the backend.
+    fn is_blocking(&self) -> bool {
+        false
+    }
+
+    // Nobody waits for the reply any more, e.g. the loser of a hedged read.
+    // The canceled reads are not sent to the backend if they are still queued.
+    fn is_canceled(&self) -> bool {
+        false
+    }
+
+    // The backend connections are shared by all the sessions.
+    // The push messages go to the session of the task waiting for the reply.
+    fn send_push(&self, _packet: Self::Pkt) {
+        debug!("drop push message from backend");
+    }
+
+    // The backend is saturated when the task is sent.
+    // The session of the task waits for it before reading more requests.
+    fn hold_back_session(&self, _in_flight: &Arc<BackendInFlight>) {}
 }
 
 pub trait IntoTask<T: CmdTask>: CmdTask {
@@ -135,6 +174,56 @@ impl<T: CmdTask> CmdTask for ReqTask<T> {
         }
     }
 
+    fn get_conn_hint(&self) -> Option<usize> {
+        match self {
+            Self::Simple(t) => t.get_conn_hint(),
+            Self::Multi(v) => v.iter().find_map(|t| t.get_conn_hint()),
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        match self {
+            Self::Simple(t) => t.is_read_only(),
+            Self::Multi(v) => v.iter().all(|t| t.is_read_only()),
+        }
+    }
+
+    fn is_blocking(&self) -> bool {
+        match self {
+            Self::Simple(t) => t.is_blocking(),
+            Self::Multi(v) => v.iter().any(|t| t.is_blocking()),
+        }
+    }
+
+    fn is_canceled(&self) -> bool {
+        match self {
+            Self::Simple(t) => t.is_canceled(),
+            Self::Multi(v) => v.iter().all(|t| t.is_canceled()),
+        }
+    }
+
+    fn send_push(&self, packet: Self::Pkt) {
+        let task = match self {
+            Self::Simple(t) => Some(t),
+            Self::Multi(v) => v.first(),
+        };
+        match (task, packet) {
+            (Some(t), OptionalMulti::Single(p)) => t.send_push(p),
+            _ => debug!("drop push message from backend"),
+        }
+    }
+
+    fn hold_back_session(&self, in_flight: &Arc<BackendInFlight>) {
+        match self {
+            Self::Simple(t) => t.hold_back_session(in_flight),
+            Self::Multi(v) => {
+                for t in v.iter() {
+                    t.hold_back_session(in_flight);
+                }
+            }
+        }
+    }
+
     fn set_result(self, result: CommandResult<Self::Pkt>) {
         match self {
             Self::Simple(t) => match result {
@@ -232,6 +321,7 @@ impl<T> BackendSendError<T> {
 pub struct BackendNode<H: CmdTaskResultHandler> {
     tx: mpsc::UnboundedSender<H::Task>,
     conn_failed: Arc<AtomicBool>,
+    in_flight: Arc<BackendInFlight>,
 }
 
 impl<H: CmdTaskResultHandler> BackendNode<H> {
@@ -240,6 +330,8 @@ impl<H: CmdTaskResultHandler> BackendNode<H> {
         handler: Arc<H>,
         config: Arc<ServerProxyConfig>,
         conn_factory: Arc<CF>,
+        in_flight: Arc<BackendInFlight>,
+        flap_detector: Arc<BackendFlapDetector>,
     ) -> (
         BackendNode<H>,
         impl Future<Output = Result<(), BackendError>> + Send,
@@ -255,13 +347,23 @@ impl<H: CmdTaskResultHandler> BackendNode<H> {
             handler,
             rx,
             conn_failed.clone(),
+            in_flight.clone(),
+            flap_detector,
             address,
             config.backend_batch_min_time,
             config.backend_batch_max_time,
             config.backend_batch_buf,
+            config.get_backend_response_timeout(),
             conn_factory,
         );
-        (Self { tx, conn_failed }, handle_backend_fut)
+        (
+            Self {
+                tx,
+                conn_failed,
+                in_flight,
+            },
+            handle_backend_fut,
+        )
     }
 
     pub fn send(&self, mut cmd_task: H::Task) -> Result<(), BackendSendError<H::Task>> {
@@ -269,9 +371,12 @@ impl<H: CmdTaskResultHandler> BackendNode<H> {
         if self.conn_failed.load(Ordering::SeqCst) {
             return Err(BackendSendError(cmd_task));
         }
+        // The task is still queued since the sessions can't wait here.
+        if !cmd_task.is_blocking() && self.in_flight.is_saturated() {
+            cmd_task.hold_back_session(&self.in_flight);
+        }
         self.tx
             .unbounded_send(cmd_task)
-            .map(|_| ())
             .map_err(|e| BackendSendError(e.into_inner()))
     }
 
@@ -280,6 +385,22 @@ impl<H: CmdTaskResultHandler> BackendNode<H> {
     }
 }
 
+// The index after the last task which could be written within the remaining capacity.
+// At least one task is written so that a connection won't get stuck.
+fn get_split_index<T: CmdTask>(tasks: &[T], remaining: usize) -> usize {
+    let mut counted = 0;
+    for (i, task) in tasks.iter().enumerate() {
+        if task.is_blocking() {
+            continue;
+        }
+        if counted >= remaining && i > 0 {
+            return i;
+        }
+        counted += 1;
+    }
+    tasks.len()
+}
+
 pub type ConnSink<T> = Pin<Box<dyn Sink<T, Error = BackendError> + Send>>;
 pub type ConnStream<T> = Pin<Box<dyn Stream<Item = Result<T, BackendError>> + Send>>;
 pub type CreateConnResult<T> = Result<(ConnSink<T>, ConnStream<T>), BackendError>;
@@ -333,7 +454,7 @@ where
         EncodeError::NotReady(_) => BackendError::InvalidState,
     });
     let reader = reader.map_err(|e| match e {
-        DecodeError::InvalidProtocol => {
+        DecodeError::InvalidProtocol | DecodeError::HttpRequest | DecodeError::TooManyArguments => {
             error!("backend: invalid protocol");
             BackendError::InvalidProtocol
         }
@@ -346,22 +467,18 @@ where
     Ok((Box::pin(writer), Box::pin(reader)))
 }
 
-const MAX_BACKEND_RETRY: usize = 3;
-
-struct RetryState<T: CmdTask> {
-    retry_times: usize,
-    tasks: Vec<T>,
-}
-
 #[allow(clippy::too_many_arguments)]
 pub async fn handle_backend<H, F>(
     handler: Arc<H>,
     task_receiver: mpsc::UnboundedReceiver<H::Task>,
     conn_failed: Arc<AtomicBool>,
+    in_flight: Arc<BackendInFlight>,
+    flap_detector: Arc<BackendFlapDetector>,
     address: String,
     backend_batch_min_time: usize,
     backend_batch_max_time: usize,
     backend_batch_buf: NonZeroUsize,
+    response_timeout: Option<Duration>,
     conn_factory: Arc<F>,
 ) -> Result<(), BackendError>
 where
@@ -377,42 +494,28 @@ where
         }
     };
 
-    let mut retry_state: Option<RetryState<H::Task>> = None;
-
     let batch_min_time = Duration::from_nanos(backend_batch_min_time as u64);
     let batch_max_time = Duration::from_nanos(backend_batch_max_time as u64);
     let mut task_receiver = task_receiver
-        .try_chunks_timeout(backend_batch_buf, batch_min_time, batch_max_time)
+        .try_chunks_timeout(backend_batch_buf, batch_min_time, batch_max_time, false)
         .fuse();
 
     loop {
+        if let Some(remaining) = flap_detector.get_quarantine_remaining() {
+            conn_failed.store(true, Ordering::SeqCst);
+            let err_msg = format!("{} {}", ERR_BACKEND_QUARANTINED, address);
+            fail_tasks_for(&mut task_receiver, remaining, &err_msg).await?;
+            continue;
+        }
+
         let (writer, reader) = match conn_factory.create_conn(sock_address).await {
             Ok(conn) => conn,
             Err(err) => {
                 conn_failed.store(true, Ordering::SeqCst);
                 error!("failed to connect: {} {:?}", address, err);
-                retry_state.take();
-
-                let mut timeout_fut = Delay::new(Duration::from_secs(1)).fuse();
-                loop {
-                    let mut tasks_fut = task_receiver.next().fuse();
-                    let tasks_opt = select! {
-                        () = timeout_fut => break,
-                        tasks_opt = tasks_fut => tasks_opt,
-                    };
-                    let tasks = match tasks_opt {
-                        Some(tasks) => tasks,
-                        None => {
-                            warn!("backend sender is closed. Exit backend connection handling.");
-                            return Err(BackendError::Canceled);
-                        }
-                    };
-                    for task in tasks.into_iter() {
-                        task.set_resp_result(Ok(Resp::Error(
-                            format!("failed to connect to {}", address).into_bytes(),
-                        )))
-                    }
-                }
+
+                let err_msg = format!("failed to connect to {}", address);
+                fail_tasks_for(&mut task_receiver, Duration::from_secs(1), &err_msg).await?;
                 continue;
             }
         };
@@ -423,8 +526,9 @@ where
             reader,
             &mut task_receiver,
             handler.clone(),
+            &in_flight,
             backend_batch_buf,
-            retry_state.take(),
+            response_timeout,
         )
         .await;
         match res {
@@ -432,40 +536,88 @@ where
                 warn!("task receiver is closed");
                 return Err(BackendError::Canceled);
             }
-            Err((err, state)) => {
+            Err(err) => {
                 error!("connection is closed: {:?}", err);
-                retry_state = state;
+                // The quarantine is checked before reconnecting.
+                flap_detector.record_disconnect();
                 continue;
             }
         }
     }
 }
 
+// Replies the error to the tasks received within the duration
+// without sending them to the backend.
+async fn fail_tasks_for<T, S>(
+    task_receiver: &mut S,
+    duration: Duration,
+    err_msg: &str,
+) -> Result<(), BackendError>
+where
+    T: CmdTask,
+    S: Stream<Item = Vec<T>> + Unpin,
+{
+    let mut timeout_fut = Delay::new(duration).fuse();
+    loop {
+        let mut tasks_fut = task_receiver.next().fuse();
+        let tasks_opt = select! {
+            () = timeout_fut => return Ok(()),
+            tasks_opt = tasks_fut => tasks_opt,
+        };
+        let tasks = match tasks_opt {
+            Some(tasks) => tasks,
+            None => {
+                warn!("backend sender is closed. Exit backend connection handling.");
+                return Err(BackendError::Canceled);
+            }
+        };
+        for task in tasks.into_iter() {
+            task.set_resp_result(Ok(Resp::Error(err_msg.as_bytes().to_vec())))
+        }
+    }
+}
+
 async fn handle_conn<H, S>(
     mut writer: ConnSink<<<H as CmdTaskResultHandler>::Task as CmdTask>::Pkt>,
     mut reader: ConnStream<<<H as CmdTaskResultHandler>::Task as CmdTask>::Pkt>,
     task_receiver: &mut S,
     handler: Arc<H>,
+    in_flight: &Arc<BackendInFlight>,
     backend_batch_buf: NonZeroUsize,
-    mut retry_state_opt: Option<RetryState<H::Task>>,
-) -> Result<(), (BackendError, Option<RetryState<H::Task>>)>
+    response_timeout: Option<Duration>,
+) -> Result<(), BackendError>
 where
     H: CmdTaskResultHandler,
     S: Stream<Item = Vec<H::Task>> + Unpin,
 {
     let mut packets = Vec::with_capacity(backend_batch_buf.get());
+    // The tasks of the last batch over the in-flight cap.
+    let mut pending_tasks = vec![];
 
     loop {
-        let (retry_times_opt, mut tasks) = match retry_state_opt.take() {
-            Some(RetryState { retry_times, tasks }) => (Some(retry_times), tasks),
-            None => {
-                let tasks = match task_receiver.next().await {
-                    Some(tasks) => tasks,
-                    None => return Ok(()),
-                };
-                (None, tasks)
+        let mut tasks = if pending_tasks.is_empty() {
+            match task_receiver.next().await {
+                Some(tasks) => tasks,
+                None => return Ok(()),
             }
+        } else {
+            mem::take(&mut pending_tasks)
         };
+        // The tasks are held here without being written when the backend is saturated.
+        in_flight.wait_for_capacity().await;
+        // The writes are still sent as redis does for the closed clients.
+        tasks.retain(|task| !(task.is_canceled() && task.is_read_only()));
+        if tasks.is_empty() {
+            continue;
+        }
+        // The depth is checked before adding the batch so that it won't overshoot the cap.
+        let split_index = get_split_index(&tasks, in_flight.get_remaining());
+        if split_index < tasks.len() {
+            pending_tasks = tasks.split_off(split_index);
+        }
+        let counted = tasks.iter().filter(|task| !task.is_blocking()).count();
+        in_flight.add(counted);
+        let _in_flight_guard = in_flight.release_on_drop(counted);
 
         for task in tasks.iter_mut() {
             task.log_event(TaskEvent::WritingQueueReceived);
@@ -481,8 +633,8 @@ where
 
         if let Err(err) = res {
             error!("backend write error: {}", err);
-            let retry_state = handle_conn_err(retry_times_opt, tasks, &err);
-            return Err((err, retry_state));
+            fail_pending_tasks(tasks.into_iter().chain(pending_tasks), &err);
+            return Err(err);
         }
 
         let mut tasks_iter = tasks.into_iter();
@@ -493,15 +645,36 @@ where
                 Some(task) => task,
                 None => break,
             };
-            let packet_res = match reader.next().await {
-                Some(pkt) => pkt,
-                None => {
-                    error!("Failed to read packet. Connection is closed.");
-                    let mut failed_tasks = vec![task];
-                    failed_tasks.extend(tasks_iter);
-                    let err = BackendError::Io(io::Error::from(io::ErrorKind::BrokenPipe));
-                    let retry_state = handle_conn_err(retry_times_opt, failed_tasks, &err);
-                    return Err((err, retry_state));
+            // Push messages could come before any reply
+            // and should not be taken as the reply of the current task.
+            let packet_res = loop {
+                // The blocking commands could wait for their own timeouts or forever.
+                let next = match response_timeout {
+                    Some(timeout) if !task.is_blocking() => {
+                        match future::select(reader.next(), Delay::new(timeout)).await {
+                            future::Either::Left((next, _)) => next,
+                            future::Either::Right(_) => {
+                                error!("backend response timeout: {:?}", timeout);
+                                // The later replies can't be matched with the tasks any more.
+                                let err = BackendError::Timeout;
+                                let tasks = iter::once(task).chain(tasks_iter).chain(pending_tasks);
+                                fail_pending_tasks(tasks, &err);
+                                return Err(err);
+                            }
+                        }
+                    }
+                    _ => reader.next().await,
+                };
+                match next {
+                    Some(Ok(pkt)) if pkt.is_push() => task.send_push(pkt),
+                    Some(pkt) => break pkt,
+                    None => {
+                        error!("Failed to read packet. Connection is closed.");
+                        let err = BackendError::Io(io::Error::from(io::ErrorKind::BrokenPipe));
+                        let tasks = iter::once(task).chain(tasks_iter).chain(pending_tasks);
+                        fail_pending_tasks(tasks, &err);
+                        return Err(err);
+                    }
                 }
             };
 
@@ -511,30 +684,14 @@ where
     }
 }
 
-fn handle_conn_err<T: CmdTask>(
-    retry_times_opt: Option<usize>,
-    tasks: Vec<T>,
-    err: &BackendError,
-) -> Option<RetryState<T>> {
-    let retry_times = retry_times_opt.unwrap_or(0);
-    if retry_times >= MAX_BACKEND_RETRY {
-        for task in tasks.into_iter() {
-            let cmd_err = match err {
-                BackendError::Io(e) => CommandError::Io(io::Error::from(e.kind())),
-                others => {
-                    error!("unexpected backend error: {:?}", others);
-                    CommandError::InnerError
-                }
-            };
-            task.set_result(Err(cmd_err));
-        }
-        None
-    } else {
-        let retry_state = RetryState {
-            retry_times: retry_times + 1,
-            tasks,
-        };
-        Some(retry_state)
+// The commands already sent may or may not have been processed by the backend
+// so it's not safe to retry them. Fail all of them in order before reconnecting
+// so that the commands queued after them still keep their order.
+fn fail_pending_tasks<T: CmdTask>(tasks: impl Iterator<Item = T>, err: &BackendError) {
+    for task in tasks {
+        task.set_resp_result(Ok(Resp::Error(
+            format!("{} connection closed: {}", ERR_BACKEND_CONNECTION, err).into_bytes(),
+        )));
     }
 }
 
@@ -546,6 +703,7 @@ pub enum BackendError {
     InvalidAddress,
     Canceled,
     InvalidState,
+    Timeout,
 }
 
 impl fmt::Display for BackendError {
@@ -566,3 +724,536 @@ impl Error for BackendError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::command::{new_command_pair, CmdReplyReceiver, Command};
+    use super::super::inflight::SaturatedBackends;
+    use super::super::reply::ReplyCommitHandler;
+    use super::super::session::CmdCtx;
+    use super::*;
+    use crate::common::cluster::ClusterName;
+    use crate::protocol::{Array, BulkStr, RespPacket};
+    use crate::test_support::gen_test_config;
+    use bytes::BytesMut;
+    use std::convert::TryFrom;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+    use tokio;
+    use tokio::time::timeout;
+
+    // The first connection gets closed after replying `first_conn_replies` commands.
+    struct FlakyConnFactory {
+        conn_count: AtomicUsize,
+        first_conn_replies: usize,
+        received: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ConnFactory for FlakyConnFactory {
+        type Pkt = RespPacket;
+
+        fn create_conn(
+            &self,
+            _addr: SocketAddr,
+        ) -> Pin<Box<dyn Future<Output = CreateConnResult<Self::Pkt>> + Send>> {
+            let max_replies = match self.conn_count.fetch_add(1, Ordering::SeqCst) {
+                0 => self.first_conn_replies,
+                _ => usize::max_value(),
+            };
+            let received = self.received.clone();
+            let (sender, receiver) = mpsc::unbounded();
+            let receiver = receiver
+                .map(move |packet: RespPacket| {
+                    let key = match packet.to_resp_vec() {
+                        Resp::Arr(Array::Arr(resps)) => match resps.get(1) {
+                            Some(Resp::Bulk(BulkStr::Str(s))) => s.clone(),
+                            _ => panic!(),
+                        },
+                        _ => panic!(),
+                    };
+                    received
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8(key.clone()).unwrap());
+                    Ok(RespPacket::Data(Resp::Bulk(BulkStr::Str(key))))
+                })
+                .take(max_replies);
+            let sink: ConnSink<RespPacket> =
+                Box::pin(sender.sink_map_err(|_| BackendError::Canceled));
+            let stream: ConnStream<RespPacket> = Box::pin(receiver);
+            Box::pin(async { Ok((sink, stream)) })
+        }
+    }
+
+    // Accepts the commands without ever replying.
+    struct StuckConnFactory;
+
+    impl ConnFactory for StuckConnFactory {
+        type Pkt = RespPacket;
+
+        fn create_conn(
+            &self,
+            _addr: SocketAddr,
+        ) -> Pin<Box<dyn Future<Output = CreateConnResult<Self::Pkt>> + Send>> {
+            let sink: ConnSink<RespPacket> =
+                Box::pin(futures::sink::drain().sink_map_err(|_| BackendError::Canceled));
+            let stream: ConnStream<RespPacket> = Box::pin(stream::pending());
+            Box::pin(async { Ok((sink, stream)) })
+        }
+    }
+
+    // Closes the connections right after receiving any command.
+    struct ClosingConnFactory;
+
+    impl ConnFactory for ClosingConnFactory {
+        type Pkt = RespPacket;
+
+        fn create_conn(
+            &self,
+            _addr: SocketAddr,
+        ) -> Pin<Box<dyn Future<Output = CreateConnResult<Self::Pkt>> + Send>> {
+            let sink: ConnSink<RespPacket> =
+                Box::pin(futures::sink::drain().sink_map_err(|_| BackendError::Canceled));
+            let stream: ConnStream<RespPacket> = Box::pin(stream::empty());
+            Box::pin(async { Ok((sink, stream)) })
+        }
+    }
+
+    // Sends an invalidation push message of the key before each reply.
+    struct PushConnFactory;
+
+    impl ConnFactory for PushConnFactory {
+        type Pkt = RespPacket;
+
+        fn create_conn(
+            &self,
+            _addr: SocketAddr,
+        ) -> Pin<Box<dyn Future<Output = CreateConnResult<Self::Pkt>> + Send>> {
+            let (sender, receiver) = mpsc::unbounded::<RespPacket>();
+            let receiver = receiver
+                .map(|packet: RespPacket| {
+                    let key = match packet.to_resp_vec() {
+                        Resp::Arr(Array::Arr(resps)) => match resps.get(1) {
+                            Some(Resp::Bulk(BulkStr::Str(s))) => s.clone(),
+                            _ => panic!(),
+                        },
+                        _ => panic!(),
+                    };
+                    let mut push =
+                        format!(">2\r\n$10\r\ninvalidate\r\n*1\r\n${}\r\n", key.len()).into_bytes();
+                    push.extend_from_slice(&key);
+                    push.extend_from_slice(b"\r\n");
+                    let push = RespPacket::decode(&mut BytesMut::from(push.as_slice()), ())
+                        .unwrap()
+                        .unwrap();
+                    let reply = RespPacket::Data(Resp::Bulk(BulkStr::Str(key)));
+                    stream::iter(vec![Ok(push), Ok(reply)])
+                })
+                .flatten();
+            let sink: ConnSink<RespPacket> =
+                Box::pin(sender.sink_map_err(|_| BackendError::Canceled));
+            let stream: ConnStream<RespPacket> = Box::pin(receiver);
+            Box::pin(async { Ok((sink, stream)) })
+        }
+    }
+
+    fn gen_test_cmd_ctx(key: &str) -> (CmdCtx, CmdReplyReceiver) {
+        gen_test_cmd_ctx_with(&["GET", key])
+    }
+
+    fn gen_test_cmd_ctx_with(args: &[&str]) -> (CmdCtx, CmdReplyReceiver) {
+        let resp = Resp::Arr(Array::Arr(
+            args.iter()
+                .map(|arg| Resp::Bulk(BulkStr::Str(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+        let cluster = ClusterName::try_from("mycluster").unwrap();
+        let cmd = Command::new(Box::new(RespPacket::from_resp_vec(resp)));
+        let (reply_sender, reply_receiver) = new_command_pair(&cmd);
+        let cmd_ctx = CmdCtx::new(cluster, cmd, reply_sender, 0, true);
+        (cmd_ctx, reply_receiver)
+    }
+
+    async fn get_reply(reply_receiver: CmdReplyReceiver) -> RespVec {
+        reply_receiver.await.unwrap().into_resp_vec()
+    }
+
+    fn is_conn_err(resp: &RespVec) -> bool {
+        match resp {
+            Resp::Error(err) => err.starts_with(ERR_BACKEND_CONNECTION.as_bytes()),
+            _ => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_fails_pending_tasks_in_order() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let conn_factory = Arc::new(FlakyConnFactory {
+            conn_count: AtomicUsize::new(0),
+            first_conn_replies: 2,
+            received: received.clone(),
+        });
+        let (tx, rx) = mpsc::unbounded();
+        let backend_fut = handle_backend(
+            Arc::new(ReplyCommitHandler),
+            rx,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(BackendInFlight::new(0)),
+            Arc::new(BackendFlapDetector::disabled("127.0.0.1:6379".to_string())),
+            "127.0.0.1:6379".to_string(),
+            1000,
+            100_000,
+            NonZeroUsize::new(50).unwrap(),
+            None,
+            conn_factory,
+        );
+
+        let mut receivers = vec![];
+        for key in ["k1", "k2", "k3", "k4"].iter() {
+            let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(key);
+            tx.unbounded_send(cmd_ctx).unwrap();
+            receivers.push(reply_receiver);
+        }
+        tokio::spawn(backend_fut);
+
+        let mut replies = vec![];
+        for reply_receiver in receivers.into_iter() {
+            replies.push(get_reply(reply_receiver).await);
+        }
+        assert_eq!(replies[0], Resp::Bulk(BulkStr::Str(b"k1".to_vec())));
+        assert_eq!(replies[1], Resp::Bulk(BulkStr::Str(b"k2".to_vec())));
+        assert!(is_conn_err(&replies[2]));
+        assert!(is_conn_err(&replies[3]));
+
+        // The commands after the reconnection should still be in order.
+        let mut receivers = vec![];
+        for key in ["k5", "k6"].iter() {
+            let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(key);
+            tx.unbounded_send(cmd_ctx).unwrap();
+            receivers.push(reply_receiver);
+        }
+        let mut replies = vec![];
+        for reply_receiver in receivers.into_iter() {
+            replies.push(get_reply(reply_receiver).await);
+        }
+        assert_eq!(
+            replies,
+            vec![
+                Resp::Bulk(BulkStr::Str(b"k5".to_vec())),
+                Resp::Bulk(BulkStr::Str(b"k6".to_vec())),
+            ]
+        );
+
+        // The failed commands should not be resent to the backend.
+        assert_eq!(*received.lock().unwrap(), vec!["k1", "k2", "k5", "k6"]);
+    }
+
+    #[tokio::test]
+    async fn test_skip_canceled_reads() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let conn_factory = Arc::new(FlakyConnFactory {
+            conn_count: AtomicUsize::new(1),
+            first_conn_replies: 0,
+            received: received.clone(),
+        });
+        let (tx, rx) = mpsc::unbounded();
+        let backend_fut = handle_backend(
+            Arc::new(ReplyCommitHandler),
+            rx,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(BackendInFlight::new(0)),
+            Arc::new(BackendFlapDetector::disabled("127.0.0.1:6379".to_string())),
+            "127.0.0.1:6379".to_string(),
+            1000,
+            100_000,
+            NonZeroUsize::new(50).unwrap(),
+            None,
+            conn_factory,
+        );
+
+        // Nobody waits for the replies of the first two.
+        let (cmd_ctx, _) = gen_test_cmd_ctx("k1");
+        tx.unbounded_send(cmd_ctx).unwrap();
+        let (cmd_ctx, _) = gen_test_cmd_ctx_with(&["SET", "k2", "v"]);
+        tx.unbounded_send(cmd_ctx).unwrap();
+        let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx("k3");
+        tx.unbounded_send(cmd_ctx).unwrap();
+        tokio::spawn(backend_fut);
+
+        let reply = get_reply(reply_receiver).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"k3".to_vec())));
+        // The canceled write is still sent.
+        assert_eq!(*received.lock().unwrap(), vec!["k2", "k3"]);
+    }
+
+    #[tokio::test]
+    async fn test_route_push_to_session() {
+        let (tx, rx) = mpsc::unbounded();
+        let backend_fut = handle_backend(
+            Arc::new(ReplyCommitHandler),
+            rx,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(BackendInFlight::new(0)),
+            Arc::new(BackendFlapDetector::disabled("127.0.0.1:6379".to_string())),
+            "127.0.0.1:6379".to_string(),
+            1000,
+            100_000,
+            NonZeroUsize::new(50).unwrap(),
+            None,
+            Arc::new(PushConnFactory),
+        );
+        tokio::spawn(backend_fut);
+
+        let (push_sender, mut push_receiver) = mpsc::unbounded();
+        let (mut cmd_ctx, reply_receiver) = gen_test_cmd_ctx("k1");
+        cmd_ctx.set_push_sender(push_sender);
+        tx.unbounded_send(cmd_ctx).unwrap();
+
+        // The push message is not taken as the reply.
+        let reply = get_reply(reply_receiver).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"k1".to_vec())));
+        let push = push_receiver.next().await.unwrap();
+        assert_eq!(
+            push,
+            Resp::Arr(Array::Arr(vec![
+                Resp::Bulk(BulkStr::Str(b"invalidate".to_vec())),
+                Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Str(b"k1".to_vec()))])),
+            ]))
+        );
+
+        // Dropped for the tasks without any session.
+        let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx("k2");
+        tx.unbounded_send(cmd_ctx).unwrap();
+        let reply = get_reply(reply_receiver).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"k2".to_vec())));
+    }
+
+    fn spawn_backend<F: ConnFactory<Pkt = RespPacket> + Send + Sync + 'static>(
+        in_flight: Arc<BackendInFlight>,
+        response_timeout: Option<Duration>,
+        conn_factory: F,
+    ) -> mpsc::UnboundedSender<CmdCtx> {
+        let (tx, rx) = mpsc::unbounded();
+        let backend_fut = handle_backend(
+            Arc::new(ReplyCommitHandler),
+            rx,
+            Arc::new(AtomicBool::new(false)),
+            in_flight,
+            Arc::new(BackendFlapDetector::disabled("127.0.0.1:6379".to_string())),
+            "127.0.0.1:6379".to_string(),
+            1000,
+            100_000,
+            NonZeroUsize::new(50).unwrap(),
+            response_timeout,
+            Arc::new(conn_factory),
+        );
+        tokio::spawn(backend_fut);
+        tx
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_backpressure() {
+        // Shared by the connections to the same backend.
+        let in_flight = Arc::new(BackendInFlight::new(1));
+        let replying_conn = spawn_backend(
+            in_flight.clone(),
+            None,
+            FlakyConnFactory {
+                conn_count: AtomicUsize::new(0),
+                first_conn_replies: usize::max_value(),
+                received: Arc::new(Mutex::new(vec![])),
+            },
+        );
+
+        // The blocking commands are not counted.
+        let blocked_conn = spawn_backend(in_flight.clone(), None, StuckConnFactory);
+        let (cmd_ctx, _blpop_receiver) = gen_test_cmd_ctx_with(&["BLPOP", "l", "0"]);
+        blocked_conn.unbounded_send(cmd_ctx).unwrap();
+        let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx("k1");
+        replying_conn.unbounded_send(cmd_ctx).unwrap();
+        let reply = timeout(Duration::from_secs(5), get_reply(reply_receiver))
+            .await
+            .unwrap();
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"k1".to_vec())));
+
+        // The slow connection reaches the cap.
+        let slow_conn = spawn_backend(
+            in_flight.clone(),
+            Some(Duration::from_millis(200)),
+            StuckConnFactory,
+        );
+        let (cmd_ctx, slow_receiver) = gen_test_cmd_ctx("k2");
+        slow_conn.unbounded_send(cmd_ctx).unwrap();
+        timeout(Duration::from_secs(5), async {
+            while in_flight.get_depth() == 0 {
+                Delay::new(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // Held in the sender queue until the slow one fails.
+        let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx("k3");
+        replying_conn.unbounded_send(cmd_ctx).unwrap();
+        let reply_fut = Box::pin(get_reply(reply_receiver));
+        let reply_fut = match future::select(reply_fut, Delay::new(Duration::from_millis(50))).await
+        {
+            future::Either::Left(_) => panic!("should be held back"),
+            future::Either::Right((_, reply_fut)) => reply_fut,
+        };
+        assert!(is_conn_err(&get_reply(slow_receiver).await));
+        let reply = timeout(Duration::from_secs(5), reply_fut).await.unwrap();
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"k3".to_vec())));
+        assert_eq!(in_flight.get_depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_batch_split() {
+        let in_flight = Arc::new(BackendInFlight::new(1));
+        let (node, backend_fut) = BackendNode::new(
+            "127.0.0.1:6379".to_string(),
+            Arc::new(ReplyCommitHandler),
+            Arc::new(gen_test_config()),
+            Arc::new(StuckConnFactory),
+            in_flight.clone(),
+            Arc::new(BackendFlapDetector::disabled("127.0.0.1:6379".to_string())),
+        );
+        tokio::spawn(backend_fut);
+
+        // Sent in the same batch but only the first one is written.
+        let (cmd_ctx, _k1_receiver) = gen_test_cmd_ctx("k1");
+        node.send(cmd_ctx).unwrap();
+        let (cmd_ctx, _k2_receiver) = gen_test_cmd_ctx("k2");
+        node.send(cmd_ctx).unwrap();
+        timeout(Duration::from_secs(5), async {
+            while in_flight.get_depth() == 0 {
+                Delay::new(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+        Delay::new(Duration::from_millis(50)).await;
+        assert_eq!(in_flight.get_depth(), 1);
+
+        // The session sending to the saturated backend is held back.
+        let saturated_backends = Arc::new(SaturatedBackends::default());
+        let (mut cmd_ctx, _k3_receiver) = gen_test_cmd_ctx("k3");
+        cmd_ctx.set_saturated_backends(saturated_backends.clone());
+        node.send(cmd_ctx).unwrap();
+        let wait_fut = Box::pin(saturated_backends.wait_for_capacity());
+        let res = future::select(wait_fut, Delay::new(Duration::from_millis(20))).await;
+        assert!(
+            matches!(res, future::Either::Right(_)),
+            "should wait for the backend"
+        );
+    }
+
+    #[test]
+    fn test_get_split_index() {
+        let tasks: Vec<_> = ["k1", "k2", "k3"]
+            .iter()
+            .map(|key| gen_test_cmd_ctx(key).0)
+            .collect();
+        assert_eq!(get_split_index(&tasks, 2), 2);
+        assert_eq!(get_split_index(&tasks, usize::max_value()), 3);
+        // At least one task is written.
+        assert_eq!(get_split_index(&tasks, 0), 1);
+
+        // The blocking ones are not counted.
+        let tasks = vec![
+            gen_test_cmd_ctx("k1").0,
+            gen_test_cmd_ctx_with(&["BLPOP", "l", "0"]).0,
+            gen_test_cmd_ctx("k2").0,
+        ];
+        assert_eq!(get_split_index(&tasks, 1), 2);
+    }
+
+    #[tokio::test]
+    async fn test_backend_response_timeout() {
+        let (tx, rx) = mpsc::unbounded();
+        let backend_fut = handle_backend(
+            Arc::new(ReplyCommitHandler),
+            rx,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(BackendInFlight::new(0)),
+            Arc::new(BackendFlapDetector::disabled("127.0.0.1:6379".to_string())),
+            "127.0.0.1:6379".to_string(),
+            1000,
+            100_000,
+            NonZeroUsize::new(50).unwrap(),
+            Some(Duration::from_millis(10)),
+            Arc::new(StuckConnFactory),
+        );
+
+        let mut receivers = vec![];
+        for key in ["k1", "k2"].iter() {
+            let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(key);
+            tx.unbounded_send(cmd_ctx).unwrap();
+            receivers.push(reply_receiver);
+        }
+        tokio::spawn(backend_fut);
+
+        for reply_receiver in receivers.into_iter() {
+            let reply = get_reply(reply_receiver).await;
+            assert!(is_conn_err(&reply));
+        }
+
+        // The blocking commands are not failed by the response timeout.
+        let tx = spawn_backend(
+            Arc::new(BackendInFlight::new(0)),
+            Some(Duration::from_millis(10)),
+            StuckConnFactory,
+        );
+        let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx_with(&["BZPOPMIN", "z", "0"]);
+        tx.unbounded_send(cmd_ctx).unwrap();
+        assert!(timeout(Duration::from_millis(100), reply_receiver)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_flapping_backend() {
+        let flap_detector = Arc::new(BackendFlapDetector::new(
+            "127.0.0.1:6379".to_string(),
+            2,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        ));
+        let conn_failed = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::unbounded();
+        let backend_fut = handle_backend(
+            Arc::new(ReplyCommitHandler),
+            rx,
+            conn_failed.clone(),
+            Arc::new(BackendInFlight::new(0)),
+            flap_detector.clone(),
+            "127.0.0.1:6379".to_string(),
+            1000,
+            100_000,
+            NonZeroUsize::new(50).unwrap(),
+            None,
+            Arc::new(ClosingConnFactory),
+        );
+        tokio::spawn(backend_fut);
+
+        for key in ["k1", "k2"].iter() {
+            let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(key);
+            tx.unbounded_send(cmd_ctx).unwrap();
+            let reply = get_reply(reply_receiver).await;
+            assert!(is_conn_err(&reply));
+        }
+
+        // Not sent to the backend during the quarantine.
+        let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx("k3");
+        tx.unbounded_send(cmd_ctx).unwrap();
+        match get_reply(reply_receiver).await {
+            Resp::Error(err) => {
+                let err = String::from_utf8(err).unwrap();
+                assert_eq!(err, format!("{} 127.0.0.1:6379", ERR_BACKEND_QUARANTINED));
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(flap_detector.is_quarantined());
+        assert!(conn_failed.load(Ordering::SeqCst));
+    }
+}
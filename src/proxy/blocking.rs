@@ -3,6 +3,8 @@ use super::backend::{
 };
 use super::cluster::ClusterTag;
 use super::command::{CommandError, CommandResult};
+use super::flapping::BackendFlapMap;
+use super::inflight::{BackendInFlight, BackendInFlightMap};
 use super::sender::{
     CachedSenderFactory, CmdTaskSender, CmdTaskSenderFactory, RRSenderGroupFactory,
     RecoverableBackendNodeFactory,
@@ -15,8 +17,9 @@ use crate::common::utils::{ThreadSafe, Wrapper};
 use crate::protocol::{Resp, RespVec};
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
-use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 pub trait TaskBlockingController: ThreadSafe {
     type Sender: BlockingCmdTaskSender;
@@ -25,6 +28,18 @@ pub trait TaskBlockingController: ThreadSafe {
     fn is_blocking(&self) -> bool;
     fn start_blocking(&self) -> BlockingHandle<Self::Sender>;
     fn stop_blocking(&self);
+    fn get_queue_len(&self) -> usize;
+    fn get_blocking_time(&self) -> Option<Duration>;
+}
+
+// Shows how the migration is affecting the clients.
+#[derive(Debug, Clone)]
+pub struct BlockingQueueStats {
+    pub address: String,
+    pub queue_len: usize,
+    // The time of the current blocking. Zero if it's not blocking.
+    pub blocking_time: Duration,
+    pub total_blocked_time: Duration,
 }
 
 pub trait TaskBlockingControllerFactory {
@@ -45,6 +60,8 @@ pub fn gen_basic_blocking_sender_factory<F: CmdTaskResultHandlerFactory, CF: Con
     reply_handler_factory: Arc<F>,
     conn_factory: Arc<CF>,
     future_registry: Arc<TrackedFutureRegistry>,
+    in_flight_map: Arc<BackendInFlightMap>,
+    flap_map: Arc<BackendFlapMap>,
 ) -> BasicBlockingSenderFactory<F, CF>
 where
     <F::Handler as CmdTaskResultHandler>::Task: CmdTask<Pkt = CF::Pkt>,
@@ -57,7 +74,9 @@ where
             reply_handler_factory,
             conn_factory,
             future_registry,
-        ),
+        )
+        .with_in_flight_map(in_flight_map)
+        .with_flap_map(flap_map),
     )
 }
 
@@ -101,6 +120,29 @@ where
         }
     }
 
+    pub fn get_blocking_stats(&self) -> Vec<BlockingQueueStats> {
+        let mut stats: Vec<_> = self
+            .ctrl_map
+            .iter()
+            .filter_map(|entry| {
+                let queue = entry.value().upgrade()?;
+                Some(BlockingQueueStats {
+                    address: entry.key().clone(),
+                    queue_len: queue.get_queue_len(),
+                    blocking_time: queue.get_blocking_time().unwrap_or_default(),
+                    total_blocked_time: queue.get_total_blocked_time(),
+                })
+            })
+            .collect();
+        stats.sort_by(|a, b| a.address.cmp(&b.address));
+        stats
+    }
+
+    // Should not be called during migration since the new queues are not blocked.
+    pub fn clear(&self) {
+        self.ctrl_map.clear();
+    }
+
     pub fn get_blocking_queue(&self, address: String) -> Arc<TaskBlockingQueue<F::Sender, BS>> {
         self.get_or_create(address)
     }
@@ -158,7 +200,9 @@ pub struct BlockingHandle<BS: BlockingCmdTaskSender> {
 
 impl<BS: BlockingCmdTaskSender> BlockingHandle<BS> {
     fn new(inner: Arc<BlockingHandleInner<BS>>) -> Self {
-        inner.blocking.fetch_add(1, Ordering::SeqCst);
+        if inner.blocking.fetch_add(1, Ordering::SeqCst) == 0 {
+            inner.start_timer();
+        }
         info!("migration start blocking");
         Self { inner }
     }
@@ -171,7 +215,12 @@ impl<BS: BlockingCmdTaskSender> Drop for BlockingHandle<BS> {
         info!("blocking handle is dropped");
         let previous = self.inner.blocking.fetch_sub(1, Ordering::SeqCst);
         if previous == 1 {
-            info!("migraition stop blocking");
+            let blocked_time = self.inner.stop_timer();
+            info!(
+                "migraition stop blocking after {:?} with {} commands queued",
+                blocked_time,
+                self.inner.queue_receiver.len()
+            );
             self.inner.release_all();
         }
     }
@@ -181,9 +230,38 @@ struct BlockingHandleInner<BS: BlockingCmdTaskSender> {
     blocking: AtomicUsize,
     queue_receiver: crossbeam_channel::Receiver<BS::Task>,
     blocking_task_sender: Arc<BS>,
+    blocking_start: Mutex<Option<Instant>>,
+    total_blocked_time_us: AtomicU64,
 }
 
 impl<BS: BlockingCmdTaskSender> BlockingHandleInner<BS> {
+    fn start_timer(&self) {
+        let mut start = self
+            .blocking_start
+            .lock()
+            .expect("BlockingHandleInner::start_timer");
+        start.get_or_insert_with(Instant::now);
+    }
+
+    fn stop_timer(&self) -> Duration {
+        let start = self
+            .blocking_start
+            .lock()
+            .expect("BlockingHandleInner::stop_timer")
+            .take();
+        let elapsed = start.map(|start| start.elapsed()).unwrap_or_default();
+        self.total_blocked_time_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        elapsed
+    }
+
+    fn get_blocking_time(&self) -> Option<Duration> {
+        self.blocking_start
+            .lock()
+            .expect("BlockingHandleInner::get_blocking_time")
+            .map(|start| start.elapsed())
+    }
+
     fn release_all(&self) {
         loop {
             let cmd_task = match self.queue_receiver.try_recv() {
@@ -231,6 +309,8 @@ where
             blocking: AtomicUsize::new(0),
             queue_receiver,
             blocking_task_sender,
+            blocking_start: Mutex::new(None),
+            total_blocked_time_us: AtomicU64::new(0),
         });
         Self {
             queue_sender,
@@ -240,6 +320,15 @@ where
         }
     }
 
+    pub fn get_total_blocked_time(&self) -> Duration {
+        let current = self.get_blocking_time().unwrap_or_default();
+        let blocked_us = self
+            .blocking_handle_inner
+            .total_blocked_time_us
+            .load(Ordering::Relaxed);
+        Duration::from_micros(blocked_us) + current
+    }
+
     fn send(&self, cmd_task: BlockingHintTask<BS::Task>) -> Result<(), BackendError> {
         // `cmd_need_blocking` is still needed even we have `self.is_blocking()` check.
         // Without it, the following case could happen:
@@ -310,6 +399,14 @@ where
     fn stop_blocking(&self) {
         self.blocking_handle_inner.release_all();
     }
+
+    fn get_queue_len(&self) -> usize {
+        self.blocking_handle_inner.queue_receiver.len()
+    }
+
+    fn get_blocking_time(&self) -> Option<Duration> {
+        self.blocking_handle_inner.get_blocking_time()
+    }
 }
 
 pub struct TaskBlockingQueueSender<S, BS>
@@ -472,6 +569,30 @@ impl<T: CmdTask> CmdTask for CounterTask<T> {
     fn log_event(&mut self, event: TaskEvent) {
         self.inner.log_event(event)
     }
+
+    fn get_conn_hint(&self) -> Option<usize> {
+        self.inner.get_conn_hint()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn is_blocking(&self) -> bool {
+        self.inner.is_blocking()
+    }
+
+    fn is_canceled(&self) -> bool {
+        self.inner.is_canceled()
+    }
+
+    fn send_push(&self, packet: Self::Pkt) {
+        self.inner.send_push(packet)
+    }
+
+    fn hold_back_session(&self, in_flight: &Arc<BackendInFlight>) {
+        self.inner.hold_back_session(in_flight)
+    }
 }
 
 pub struct BlockingHintTask<T: CmdTask> {
@@ -535,6 +656,30 @@ impl<T: CmdTask> CmdTask for BlockingHintTask<T> {
     fn log_event(&mut self, event: TaskEvent) {
         self.inner.log_event(event)
     }
+
+    fn get_conn_hint(&self) -> Option<usize> {
+        self.inner.get_conn_hint()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn is_blocking(&self) -> bool {
+        self.inner.is_blocking()
+    }
+
+    fn is_canceled(&self) -> bool {
+        self.inner.is_canceled()
+    }
+
+    fn send_push(&self, packet: Self::Pkt) {
+        self.inner.send_push(packet)
+    }
+
+    fn hold_back_session(&self, in_flight: &Arc<BackendInFlight>) {
+        self.inner.hold_back_session(in_flight)
+    }
 }
 
 impl<T: CmdTask + ClusterTag> ClusterTag for BlockingHintTask<T> {
@@ -552,3 +697,88 @@ impl<T: CmdTask + ClusterTag> IntoTask<T> for BlockingHintTask<T> {
         self.into_inner()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::session::{CmdCtx, CmdCtxBuilder};
+    use futures::FutureExt;
+
+    struct ReplySender(&'static [u8]);
+
+    impl CmdTaskSender for ReplySender {
+        type Task = CounterTask<CmdCtx>;
+
+        fn send(&self, cmd_task: Self::Task) -> Result<(), BackendError> {
+            let reply = Resp::Simple(self.0.to_vec());
+            cmd_task.into_inner().set_resp_result(Ok(reply));
+            Ok(())
+        }
+    }
+
+    struct ReplySenderFactory;
+
+    impl CmdTaskSenderFactory for ReplySenderFactory {
+        type Sender = ReplySender;
+
+        fn create(&self, _address: String) -> Self::Sender {
+            ReplySender(b"BACKEND")
+        }
+    }
+
+    struct ReleasedSender;
+
+    impl CmdTaskSender for ReleasedSender {
+        type Task = CmdCtx;
+
+        fn send(&self, cmd_task: Self::Task) -> Result<(), BackendError> {
+            cmd_task.set_resp_result(Ok(Resp::Simple(b"RELEASED".to_vec())));
+            Ok(())
+        }
+    }
+
+    impl BlockingCmdTaskSender for ReleasedSender {}
+
+    #[test]
+    fn test_blocking_stats() {
+        let blocking_map = BlockingMap::new(ReplySenderFactory, Arc::new(ReleasedSender));
+        let address = "127.0.0.1:6379".to_string();
+        let queue = blocking_map.get_blocking_queue(address.clone());
+        assert_eq!(blocking_map.get_blocking_stats()[0].queue_len, 0);
+
+        let handle = queue.start_blocking();
+        let mut replies = vec![];
+        for _ in 0..2 {
+            let (cmd_ctx, reply_fut) = CmdCtxBuilder::new(&["GET", "key"]).build();
+            queue.send(BlockingHintTask::new(cmd_ctx, true)).unwrap();
+            replies.push(reply_fut);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+
+        let stats = blocking_map.get_blocking_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].address, address);
+        assert_eq!(stats[0].queue_len, 2);
+        assert!(stats[0].blocking_time >= Duration::from_millis(10));
+        assert!(stats[0].total_blocked_time >= stats[0].blocking_time);
+        for reply_fut in replies.iter_mut() {
+            assert!(reply_fut.now_or_never().is_none());
+        }
+
+        drop(handle);
+        let stats = blocking_map.get_blocking_stats();
+        assert_eq!(stats[0].queue_len, 0);
+        assert_eq!(stats[0].blocking_time, Duration::from_secs(0));
+        assert!(stats[0].total_blocked_time >= Duration::from_millis(10));
+        for reply_fut in replies.into_iter() {
+            let reply = reply_fut.now_or_never().unwrap().unwrap();
+            assert_eq!(reply, Resp::Simple(b"RELEASED".to_vec()));
+        }
+
+        // Not blocking any more.
+        let (cmd_ctx, reply_fut) = CmdCtxBuilder::new(&["GET", "key"]).build();
+        queue.send(BlockingHintTask::new(cmd_ctx, false)).unwrap();
+        let reply = reply_fut.now_or_never().unwrap().unwrap();
+        assert_eq!(reply, Resp::Simple(b"BACKEND".to_vec()));
+    }
+}
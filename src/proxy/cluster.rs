@@ -1,14 +1,15 @@
 use super::backend::{BackendError, CmdTask, IntoTask};
 use super::sender::{CmdTaskSender, CmdTaskSenderFactory};
 use super::slot::SlotMap;
-use crate::common::cluster::{ClusterName, RangeList, SlotRange, SlotRangeTag};
+use crate::common::cluster::{ClusterName, Range, RangeList, SlotRange, SlotRangeTag};
 use crate::common::config::ClusterConfig;
 use crate::common::proto::ProxyClusterMeta;
 use crate::common::response::ERR_CLUSTER_NOT_FOUND;
-use crate::common::utils::gen_moved;
+use crate::common::utils::{gen_moved, SLOT_NUM};
 use crate::migration::task::MigrationState;
 use crate::protocol::{Array, BulkStr, Resp, RespVec};
 use crc64::crc64;
+use std::cmp::max;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
@@ -205,6 +206,80 @@ where
         self.local_clusters.keys().cloned().collect()
     }
 
+    pub fn get_local_slot_num(&self, cluster_name: &ClusterName) -> usize {
+        self.local_clusters
+            .get(cluster_name)
+            .map(|local_cluster| {
+                local_cluster
+                    .slot_ranges
+                    .values()
+                    .flatten()
+                    .map(|slot_range| slot_range.get_range_list().get_slots_num())
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    // Returns the address of the backend owning the slot in this proxy.
+    pub fn get_local_backend(&self, cluster_name: &ClusterName, slot: usize) -> Option<&str> {
+        self.local_clusters
+            .get(cluster_name)
+            .and_then(|local_cluster| local_cluster.local_backend.slot_map.get(slot))
+    }
+
+    // Returns each local backend of the cluster with one of its slots.
+    pub fn get_local_backend_slots(&self, cluster_name: &ClusterName) -> Vec<(String, usize)> {
+        self.local_clusters
+            .get(cluster_name)
+            .map(|local_cluster| {
+                local_cluster
+                    .local_backend
+                    .slot_map
+                    .get_first_slots()
+                    .into_iter()
+                    .map(|(address, slot)| (address.to_string(), slot))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Returns the other proxies owning the slots of the cluster.
+    pub fn get_remote_proxies(&self, cluster_name: &ClusterName) -> Vec<String> {
+        self.remote_clusters
+            .get(cluster_name)
+            .map(|remote_cluster| remote_cluster.slot_ranges.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Returns the address of the local backend or the other proxy owning the slot.
+    pub fn get_slot_node(&self, cluster_name: &ClusterName, slot: usize) -> Option<&str> {
+        self.get_local_backend(cluster_name, slot).or_else(|| {
+            self.remote_clusters
+                .get(cluster_name)
+                .and_then(|remote_cluster| remote_cluster.slot_map.get(slot))
+        })
+    }
+
+    pub fn get_slot_owner(
+        &self,
+        cluster_name: &ClusterName,
+        slot: usize,
+        service_address: &str,
+    ) -> Option<String> {
+        let is_local = self
+            .local_clusters
+            .get(cluster_name)
+            .map(|local_cluster| local_cluster.local_backend.slot_map.get(slot).is_some())
+            .unwrap_or(false);
+        if is_local {
+            return Some(service_address.to_string());
+        }
+        self.remote_clusters
+            .get(cluster_name)
+            .and_then(|remote_cluster| remote_cluster.slot_map.get(slot))
+            .map(ToString::to_string)
+    }
+
     pub fn gen_cluster_nodes(
         &self,
         cluster_name: ClusterName,
@@ -248,6 +323,79 @@ where
         Ok(Resp::Arr(Array::Arr(local)))
     }
 
+    pub fn gen_cluster_info(
+        &self,
+        cluster_name: ClusterName,
+        service_address: String,
+        migration_states: &HashMap<RangeList, MigrationState>,
+        migration_stuck: bool,
+    ) -> String {
+        let mut slot_ranges: HashMap<String, Vec<SlotRange>> = HashMap::new();
+        let mut epoch = 0;
+        if let Some(local_cluster) = self.local_clusters.get(&cluster_name) {
+            let slots = local_cluster
+                .slot_ranges
+                .values()
+                .cloned()
+                .flatten()
+                .collect();
+            slot_ranges.insert(service_address, slots);
+            epoch = local_cluster.epoch;
+        }
+        if let Some(remote_cluster) = self.remote_clusters.get(&cluster_name) {
+            for (addr, ranges) in remote_cluster.slot_ranges.iter() {
+                slot_ranges
+                    .entry(addr.clone())
+                    .or_insert_with(Vec::new)
+                    .extend(ranges.iter().cloned());
+            }
+            epoch = max(epoch, remote_cluster.epoch);
+        }
+        gen_cluster_info_helper(epoch, &slot_ranges, migration_states, migration_stuck)
+    }
+
+    // Includes the clusters which only have remote slots.
+    pub fn get_all_clusters(&self) -> Vec<ClusterName> {
+        let mut clusters: Vec<ClusterName> = self
+            .local_clusters
+            .keys()
+            .chain(self.remote_clusters.keys())
+            .cloned()
+            .collect();
+        clusters.sort_by_key(|cluster_name| cluster_name.to_string());
+        clusters.dedup();
+        clusters
+    }
+
+    pub fn check_topo(
+        &self,
+        cluster_name: &ClusterName,
+        migration_states: &HashMap<RangeList, MigrationState>,
+    ) -> Vec<TopoIssue> {
+        // Local slots are keyed by the redis addresses and
+        // remote slots are keyed by the peer proxy addresses.
+        let mut slot_ranges: HashMap<String, Vec<SlotRange>> = HashMap::new();
+        let mut issues = vec![];
+        if let Some(local_cluster) = self.local_clusters.get(cluster_name) {
+            slot_ranges.extend(local_cluster.slot_ranges.clone());
+            issues.extend(find_dangling_migrations(
+                &local_cluster.slot_ranges,
+                migration_states,
+            ));
+        }
+        if let Some(remote_cluster) = self.remote_clusters.get(cluster_name) {
+            for (addr, ranges) in remote_cluster.slot_ranges.iter() {
+                slot_ranges
+                    .entry(addr.clone())
+                    .or_insert_with(Vec::new)
+                    .extend(ranges.iter().cloned());
+            }
+        }
+        let mut slot_issues = check_slot_ownership(&slot_ranges, migration_states);
+        slot_issues.append(&mut issues);
+        slot_issues
+    }
+
     pub fn auto_select_cluster(&self) -> Option<ClusterName> {
         {
             let local = &self.local_clusters;
@@ -704,6 +852,161 @@ fn should_ignore_slots(
     }
 }
 
+fn gen_cluster_info_helper(
+    epoch: u64,
+    slot_ranges: &HashMap<String, Vec<SlotRange>>,
+    migration_states: &HashMap<RangeList, MigrationState>,
+    migration_stuck: bool,
+) -> String {
+    let mut assigned = vec![false; SLOT_NUM];
+    let mut cluster_size = 0;
+    for ranges in slot_ranges.values() {
+        let mut own_slots = false;
+        for slot_range in ranges {
+            if should_ignore_slots(slot_range, migration_states) {
+                continue;
+            }
+            for range in slot_range.get_range_list().get_ranges().iter() {
+                for slot in range.start()..=range.end() {
+                    if let Some(s) = assigned.get_mut(slot) {
+                        *s = true;
+                        own_slots = true;
+                    }
+                }
+            }
+        }
+        if own_slots {
+            cluster_size += 1;
+        }
+    }
+
+    let slots_assigned = assigned.iter().filter(|s| **s).count();
+    // The importing side will serve the migrating slots,
+    // so all the slots are still assigned during migration.
+    let state = if slots_assigned == SLOT_NUM && !migration_stuck {
+        "ok"
+    } else {
+        "fail"
+    };
+    let lines = vec![
+        format!("cluster_state:{}", state),
+        format!("cluster_slots_assigned:{}", slots_assigned),
+        format!("cluster_slots_ok:{}", slots_assigned),
+        "cluster_slots_pfail:0".to_string(),
+        "cluster_slots_fail:0".to_string(),
+        format!("cluster_known_nodes:{}", slot_ranges.len()),
+        format!("cluster_size:{}", cluster_size),
+        format!("cluster_current_epoch:{}", epoch),
+        format!("cluster_my_epoch:{}", epoch),
+        "cluster_stats_messages_sent:0".to_string(),
+        "cluster_stats_messages_received:0".to_string(),
+    ];
+    let mut cluster_info = lines.join("\r\n");
+    cluster_info.push_str("\r\n");
+    cluster_info
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TopoIssue {
+    Unassigned(Range),
+    // The addresses owning the same slots.
+    Overlap(Range, Vec<String>),
+    // The local slots are tagged as migrating or importing
+    // but the migration task is not running.
+    DanglingMigration(RangeList, String),
+}
+
+impl fmt::Display for TopoIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fmt_range = |range: &Range| {
+            if range.start() == range.end() {
+                range.start().to_string()
+            } else {
+                format!("{}-{}", range.start(), range.end())
+            }
+        };
+        match self {
+            Self::Unassigned(range) => write!(f, "unassigned: {}", fmt_range(range)),
+            Self::Overlap(range, addresses) => {
+                write!(f, "overlap: {} {}", fmt_range(range), addresses.join(","))
+            }
+            Self::DanglingMigration(range_list, address) => {
+                write!(f, "dangling_migration: {} {}", range_list, address)
+            }
+        }
+    }
+}
+
+fn check_slot_ownership(
+    slot_ranges: &HashMap<String, Vec<SlotRange>>,
+    migration_states: &HashMap<RangeList, MigrationState>,
+) -> Vec<TopoIssue> {
+    let mut addresses: Vec<&String> = slot_ranges.keys().collect();
+    addresses.sort();
+
+    let mut owners: Vec<Vec<&str>> = vec![vec![]; SLOT_NUM];
+    for addr in addresses.into_iter() {
+        let ranges = match slot_ranges.get(addr) {
+            Some(ranges) => ranges,
+            None => continue,
+        };
+        for slot_range in ranges.iter() {
+            if should_ignore_slots(slot_range, migration_states) {
+                continue;
+            }
+            for range in slot_range.get_range_list().get_ranges().iter() {
+                for slot in range.start()..=range.end() {
+                    if let Some(slot_owners) = owners.get_mut(slot) {
+                        slot_owners.push(addr.as_str());
+                    }
+                }
+            }
+        }
+    }
+
+    // Merge the continuous slots with the same owners.
+    let mut issues = vec![];
+    let mut start = 0;
+    for slot in 1..=SLOT_NUM {
+        if slot < SLOT_NUM && owners[slot] == owners[start] {
+            continue;
+        }
+        let range = Range(start, slot - 1);
+        match owners[start].len() {
+            0 => issues.push(TopoIssue::Unassigned(range)),
+            1 => (),
+            _ => {
+                let addresses = owners[start].iter().map(|s| s.to_string()).collect();
+                issues.push(TopoIssue::Overlap(range, addresses));
+            }
+        }
+        start = slot;
+    }
+    issues
+}
+
+fn find_dangling_migrations(
+    slot_ranges: &HashMap<String, Vec<SlotRange>>,
+    migration_states: &HashMap<RangeList, MigrationState>,
+) -> Vec<TopoIssue> {
+    let mut issues = vec![];
+    for (addr, ranges) in slot_ranges.iter() {
+        for slot_range in ranges.iter() {
+            if let SlotRangeTag::None = slot_range.tag {
+                continue;
+            }
+            let range_list = slot_range.get_range_list();
+            if !migration_states.contains_key(range_list) {
+                issues.push(TopoIssue::DanglingMigration(
+                    range_list.clone(),
+                    addr.clone(),
+                ));
+            }
+        }
+    }
+    issues
+}
+
 fn gen_cluster_slots_helper(
     slot_ranges: &HashMap<String, Vec<SlotRange>>,
     migration_states: &HashMap<RangeList, MigrationState>,
@@ -972,6 +1275,98 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_gen_cluster_info() {
+        let m = HashMap::new();
+        let slot_ranges = gen_testing_slot_ranges("127.0.0.1:5299");
+        let output = gen_cluster_info_helper(233, &slot_ranges, &m, false);
+        assert!(output.starts_with("cluster_state:fail\r\n"));
+        assert!(output.contains("cluster_slots_assigned:102\r\n"));
+        assert!(output.contains("cluster_known_nodes:1\r\n"));
+        assert!(output.contains("cluster_size:1\r\n"));
+        assert!(output.contains("cluster_current_epoch:233\r\n"));
+
+        let mut slot_ranges = HashMap::new();
+        for (i, range) in ["0-8000", "8001-16383"].iter().enumerate() {
+            slot_ranges.insert(
+                format!("127.0.0.1:500{}", i),
+                vec![SlotRange {
+                    range_list: RangeList::try_from(format!("1 {}", range).as_str()).unwrap(),
+                    tag: SlotRangeTag::None,
+                }],
+            );
+        }
+        slot_ranges.insert("127.0.0.1:5002".to_string(), vec![]);
+        let output = gen_cluster_info_helper(233, &slot_ranges, &m, false);
+        assert!(output.starts_with("cluster_state:ok\r\n"));
+        assert!(output.contains("cluster_slots_assigned:16384\r\n"));
+        assert!(output.contains("cluster_known_nodes:3\r\n"));
+        assert!(output.contains("cluster_size:2\r\n"));
+
+        let output = gen_cluster_info_helper(233, &slot_ranges, &m, true);
+        assert!(output.starts_with("cluster_state:fail\r\n"));
+        assert!(output.contains("cluster_slots_assigned:16384\r\n"));
+    }
+
+    #[test]
+    fn test_check_slot_ownership() {
+        let m = HashMap::new();
+        let mut slot_ranges = HashMap::new();
+        for (i, range) in ["0-8000", "7000-8000", "9000-16383"].iter().enumerate() {
+            slot_ranges.insert(
+                format!("127.0.0.1:500{}", i),
+                vec![SlotRange {
+                    range_list: RangeList::try_from(format!("1 {}", range).as_str()).unwrap(),
+                    tag: SlotRangeTag::None,
+                }],
+            );
+        }
+        let issues = check_slot_ownership(&slot_ranges, &m);
+        assert_eq!(
+            issues,
+            vec![
+                TopoIssue::Overlap(
+                    Range(7000, 8000),
+                    vec!["127.0.0.1:5000".to_string(), "127.0.0.1:5001".to_string()]
+                ),
+                TopoIssue::Unassigned(Range(8001, 8999)),
+            ]
+        );
+        assert_eq!(issues[1].to_string(), "unassigned: 8001-8999");
+
+        let slot_ranges = gen_testing_slot_ranges("127.0.0.1:5299");
+        let issues = check_slot_ownership(&slot_ranges, &m);
+        assert_eq!(
+            issues,
+            vec![
+                TopoIssue::Unassigned(Range(101, 299)),
+                TopoIssue::Unassigned(Range(301, 16383)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_dangling_migrations() {
+        let slot_ranges = gen_testing_migration_slot_ranges(true);
+        let range_list = RangeList::try_from("1 0-1000").unwrap();
+
+        let issues = find_dangling_migrations(&slot_ranges, &HashMap::new());
+        assert_eq!(
+            issues,
+            vec![TopoIssue::DanglingMigration(
+                range_list.clone(),
+                "127.0.0.1:5299".to_string()
+            )]
+        );
+
+        let mut m = HashMap::new();
+        m.insert(range_list, MigrationState::PreCheck);
+        assert!(find_dangling_migrations(&slot_ranges, &m).is_empty());
+        assert!(
+            find_dangling_migrations(&gen_testing_slot_ranges("127.0.0.1:5299"), &m).is_empty()
+        );
+    }
+
     #[test]
     fn test_gen_importing_cluster_slots() {
         let m = HashMap::new();
@@ -1,6 +1,9 @@
+use super::keyspec::{
+    check_sort_patterns, get_builtin_key_spec, get_geo_store_keys, get_numkeys_keys, KeySpec,
+};
 use super::slowlog::Slowlog;
 use crate::common::utils::{byte_to_uppercase, generate_slot};
-use crate::protocol::{BinSafeStr, RespPacket, RespSlice, RespVec};
+use crate::protocol::{Array, BinSafeStr, BulkStr, Resp, RespPacket, RespSlice, RespVec};
 use arrayvec::ArrayVec;
 use backtrace::Backtrace;
 use futures::channel::oneshot;
@@ -14,6 +17,7 @@ use std::io;
 use std::pin::Pin;
 use std::result::Result;
 use std::str;
+use std::time::Duration;
 
 const MAX_COMMAND_NAME_LENGTH: usize = 64;
 
@@ -34,6 +38,16 @@ pub enum CmdType {
     Config,
     Command,
     Asking,
+    Client,
+    Latency,
+    Debug,
+    Reset,
+    Hello,
+    Shutdown,
+    SSubscribe,
+    SUnsubscribe,
+    Acl,
+    Monitor,
 }
 
 impl CmdType {
@@ -62,6 +76,16 @@ impl CmdType {
             b"CONFIG" => CmdType::Config,
             b"COMMAND" => CmdType::Command,
             b"ASKING" => CmdType::Asking,
+            b"CLIENT" => CmdType::Client,
+            b"LATENCY" => CmdType::Latency,
+            b"DEBUG" => CmdType::Debug,
+            b"RESET" => CmdType::Reset,
+            b"HELLO" => CmdType::Hello,
+            b"SHUTDOWN" => CmdType::Shutdown,
+            b"SSUBSCRIBE" => CmdType::SSubscribe,
+            b"SUNSUBSCRIBE" => CmdType::SUnsubscribe,
+            b"ACL" => CmdType::Acl,
+            b"MONITOR" => CmdType::Monitor,
             _ => CmdType::Others,
         }
     }
@@ -76,23 +100,28 @@ impl CmdType {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+// The names follow the redis commands, e.g. BITFIELD_RO.
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum DataCmdType {
     // String commands
     APPEND,
     BITCOUNT,
     BITFIELD,
+    BITFIELD_RO,
     BITOP,
     BITPOS,
     DECR,
     DECRBY,
     GET,
     GETBIT,
+    GETDEL,
     GETRANGE,
     GETSET,
     INCR,
     INCRBY,
     INCRBYFLOAT,
+    LCS,
     MGET,
     MSET,
     MSETNX,
@@ -116,28 +145,75 @@ pub enum DataCmdType {
     RPOPLPUSH,
     LREM,
     LTRIM,
+    LMPOP,
     // Hash commands
     HDEL,
+    HRANDFIELD,
+    HEXPIRE,
+    HPEXPIRE,
+    HEXPIREAT,
+    HPEXPIREAT,
+    HEXPIRETIME,
+    HPEXPIRETIME,
+    HTTL,
+    HPTTL,
+    HPERSIST,
+    HGETEX,
+    HGETDEL,
+    HSETEX,
     // Set commands
+    SINTERCARD,
+    SMISMEMBER,
     SMOVE,
     SPOP,
+    SRANDMEMBER,
     SREM,
     // Sorted Set commands
     ZPOPMAX,
     ZPOPMIN,
+    ZMPOP,
+    ZRANDMEMBER,
     ZREM,
     ZREMRANGEBYLEX,
     ZREMRANGEBYRANK,
     ZREMRANGEBYSCORE,
+    // HyperLogLog commands
+    PFADD,
+    PFCOUNT,
+    PFMERGE,
+    // Stream commands
+    XGROUP,
+    // Geo commands
+    GEOADD,
+    GEODIST,
+    GEOHASH,
+    GEOPOS,
+    GEORADIUS,
+    GEORADIUSBYMEMBER,
+    GEOSEARCH,
+    GEOSEARCHSTORE,
     // Key commands
+    SORT,
+    SORT_RO,
     EXPIRE,
     EXPIREAT,
     PEXPIRE,
     PEXPIREAT,
+    EXPIRETIME,
+    PEXPIRETIME,
+    PERSIST,
+    TTL,
+    PTTL,
     MOVE,
     RENAME,
     RENAMENX,
     UNLINK,
+    DUMP,
+    RESTORE,
+    MIGRATE,
+    // Replication commands
+    WAIT,
+    WAITAOF,
     Others,
 }
 
@@ -160,13 +236,16 @@ impl DataCmdType {
             b"APPEND" => DataCmdType::APPEND,
             b"BITCOUNT" => DataCmdType::BITCOUNT,
             b"BITFIELD" => DataCmdType::BITFIELD,
+            b"BITFIELD_RO" => DataCmdType::BITFIELD_RO,
             b"BITOP" => DataCmdType::BITOP,
             b"BITPOS" => DataCmdType::BITPOS,
             b"DECR" => DataCmdType::DECR,
             b"DECRBY" => DataCmdType::DECRBY,
             b"GET" => DataCmdType::GET,
             b"GETBIT" => DataCmdType::GETBIT,
+            b"GETDEL" => DataCmdType::GETDEL,
             b"GETRANGE" => DataCmdType::GETRANGE,
+            b"LCS" => DataCmdType::LCS,
             b"GETSET" => DataCmdType::GETSET,
             b"INCR" => DataCmdType::INCR,
             b"INCRBY" => DataCmdType::INCRBY,
@@ -188,29 +267,72 @@ impl DataCmdType {
             b"BLPOP" => DataCmdType::BLPOP,
             b"BRPOP" => DataCmdType::BRPOP,
             b"BRPOPLPUSH" => DataCmdType::BRPOPLPUSH,
+            b"SORT" => DataCmdType::SORT,
+            b"SORT_RO" => DataCmdType::SORT_RO,
             b"EXPIRE" => DataCmdType::EXPIRE,
             b"EXPIREAT" => DataCmdType::EXPIREAT,
             b"PEXPIRE" => DataCmdType::PEXPIRE,
             b"PEXPIREAT" => DataCmdType::PEXPIREAT,
+            b"EXPIRETIME" => DataCmdType::EXPIRETIME,
+            b"PEXPIRETIME" => DataCmdType::PEXPIRETIME,
+            b"PERSIST" => DataCmdType::PERSIST,
+            b"TTL" => DataCmdType::TTL,
+            b"PTTL" => DataCmdType::PTTL,
             b"HDEL" => DataCmdType::HDEL,
+            b"HRANDFIELD" => DataCmdType::HRANDFIELD,
+            b"HEXPIRE" => DataCmdType::HEXPIRE,
+            b"HPEXPIRE" => DataCmdType::HPEXPIRE,
+            b"HEXPIREAT" => DataCmdType::HEXPIREAT,
+            b"HPEXPIREAT" => DataCmdType::HPEXPIREAT,
+            b"HEXPIRETIME" => DataCmdType::HEXPIRETIME,
+            b"HPEXPIRETIME" => DataCmdType::HPEXPIRETIME,
+            b"HTTL" => DataCmdType::HTTL,
+            b"HPTTL" => DataCmdType::HPTTL,
+            b"HPERSIST" => DataCmdType::HPERSIST,
+            b"HGETEX" => DataCmdType::HGETEX,
+            b"HGETDEL" => DataCmdType::HGETDEL,
+            b"HSETEX" => DataCmdType::HSETEX,
             b"LPOP" => DataCmdType::LPOP,
             b"RPOP" => DataCmdType::RPOP,
             b"RPOPLPUSH" => DataCmdType::RPOPLPUSH,
             b"LREM" => DataCmdType::LREM,
             b"LTRIM" => DataCmdType::LTRIM,
+            b"LMPOP" => DataCmdType::LMPOP,
             b"MOVE" => DataCmdType::MOVE,
             b"RENAME" => DataCmdType::RENAME,
             b"RENAMENX" => DataCmdType::RENAMENX,
+            b"SINTERCARD" => DataCmdType::SINTERCARD,
+            b"SMISMEMBER" => DataCmdType::SMISMEMBER,
             b"SMOVE" => DataCmdType::SMOVE,
             b"SPOP" => DataCmdType::SPOP,
+            b"SRANDMEMBER" => DataCmdType::SRANDMEMBER,
             b"SREM" => DataCmdType::SREM,
             b"UNLINK" => DataCmdType::UNLINK,
+            b"DUMP" => DataCmdType::DUMP,
+            b"RESTORE" => DataCmdType::RESTORE,
+            b"MIGRATE" => DataCmdType::MIGRATE,
             b"ZPOPMAX" => DataCmdType::ZPOPMAX,
             b"ZPOPMIN" => DataCmdType::ZPOPMIN,
+            b"ZMPOP" => DataCmdType::ZMPOP,
+            b"ZRANDMEMBER" => DataCmdType::ZRANDMEMBER,
             b"ZREM" => DataCmdType::ZREM,
             b"ZREMRANGEBYLEX" => DataCmdType::ZREMRANGEBYLEX,
             b"ZREMRANGEBYRANK" => DataCmdType::ZREMRANGEBYRANK,
             b"ZREMRANGEBYSCORE" => DataCmdType::ZREMRANGEBYSCORE,
+            b"GEOADD" => DataCmdType::GEOADD,
+            b"GEODIST" => DataCmdType::GEODIST,
+            b"GEOHASH" => DataCmdType::GEOHASH,
+            b"GEOPOS" => DataCmdType::GEOPOS,
+            b"GEORADIUS" => DataCmdType::GEORADIUS,
+            b"GEORADIUSBYMEMBER" => DataCmdType::GEORADIUSBYMEMBER,
+            b"GEOSEARCH" => DataCmdType::GEOSEARCH,
+            b"GEOSEARCHSTORE" => DataCmdType::GEOSEARCHSTORE,
+            b"WAIT" => DataCmdType::WAIT,
+            b"WAITAOF" => DataCmdType::WAITAOF,
+            b"XGROUP" => DataCmdType::XGROUP,
+            b"PFADD" => DataCmdType::PFADD,
+            b"PFCOUNT" => DataCmdType::PFCOUNT,
+            b"PFMERGE" => DataCmdType::PFMERGE,
             _ => DataCmdType::Others,
         }
     }
@@ -235,12 +357,20 @@ pub fn requires_blocking_migration(data_cmd_type: DataCmdType) -> bool {
         DataCmdType::EVALSHA => true,
         DataCmdType::EXPIRE => true,
         DataCmdType::EXPIREAT => true,
+        DataCmdType::GETDEL => true,
         DataCmdType::HDEL => true,
+        DataCmdType::HEXPIRE => true,
+        DataCmdType::HPEXPIRE => true,
+        DataCmdType::HEXPIREAT => true,
+        DataCmdType::HPEXPIREAT => true,
+        DataCmdType::HGETEX => true,
+        DataCmdType::HGETDEL => true,
         DataCmdType::LPOP => true,
         DataCmdType::RPOP => true,
         DataCmdType::RPOPLPUSH => true,
         DataCmdType::LREM => true,
         DataCmdType::LTRIM => true,
+        DataCmdType::LMPOP => true,
         DataCmdType::MOVE => true,
         DataCmdType::PEXPIRE => true,
         DataCmdType::PEXPIREAT => true,
@@ -252,6 +382,7 @@ pub fn requires_blocking_migration(data_cmd_type: DataCmdType) -> bool {
         DataCmdType::UNLINK => true,
         DataCmdType::ZPOPMAX => true,
         DataCmdType::ZPOPMIN => true,
+        DataCmdType::ZMPOP => true,
         DataCmdType::ZREM => true,
         DataCmdType::ZREMRANGEBYLEX => true,
         DataCmdType::ZREMRANGEBYRANK => true,
@@ -260,30 +391,175 @@ pub fn requires_blocking_migration(data_cmd_type: DataCmdType) -> bool {
     }
 }
 
+// Follows the `readonly` flag of the redis command table.
+// The commands not listed here including the unknown ones are treated as writes.
+pub fn is_read_only_cmd(cmd_name: &[u8]) -> bool {
+    let mut stack_cmd_name = ArrayVec::<[u8; MAX_COMMAND_NAME_LENGTH]>::new();
+    for b in cmd_name {
+        if stack_cmd_name.try_push(byte_to_uppercase(*b)).is_err() {
+            return false;
+        }
+    }
+    let cmd_name: &[u8] = &stack_cmd_name;
+
+    match cmd_name {
+        // String commands
+        b"BITCOUNT" | b"BITFIELD_RO" | b"BITPOS" | b"GET" | b"GETBIT" | b"GETRANGE" | b"LCS"
+        | b"MGET" | b"STRLEN" | b"SUBSTR" => true,
+        // Key commands
+        b"DUMP" | b"EXISTS" | b"EXPIRETIME" | b"KEYS" | b"OBJECT" | b"PEXPIRETIME" | b"PTTL"
+        | b"RANDOMKEY" | b"SCAN" | b"SORT_RO" | b"TOUCH" | b"TTL" | b"TYPE" | b"DBSIZE" => true,
+        // Hash commands
+        b"HEXISTS" | b"HEXPIRETIME" | b"HGET" | b"HGETALL" | b"HKEYS" | b"HLEN" | b"HMGET"
+        | b"HPEXPIRETIME" | b"HPTTL" | b"HRANDFIELD" | b"HSCAN" | b"HSTRLEN" | b"HTTL"
+        | b"HVALS" => true,
+        // List commands
+        b"LINDEX" | b"LLEN" | b"LPOS" | b"LRANGE" => true,
+        // Set commands
+        b"SCARD" | b"SDIFF" | b"SINTER" | b"SINTERCARD" | b"SISMEMBER" | b"SMEMBERS"
+        | b"SMISMEMBER" | b"SRANDMEMBER" | b"SSCAN" | b"SUNION" => true,
+        // Sorted set commands
+        b"ZCARD" | b"ZCOUNT" | b"ZDIFF" | b"ZINTER" | b"ZINTERCARD" | b"ZLEXCOUNT" | b"ZMSCORE"
+        | b"ZRANDMEMBER" | b"ZRANGE" | b"ZRANGEBYLEX" | b"ZRANGEBYSCORE" | b"ZRANK"
+        | b"ZREVRANGE" | b"ZREVRANGEBYLEX" | b"ZREVRANGEBYSCORE" | b"ZREVRANK" | b"ZSCAN"
+        | b"ZSCORE" | b"ZUNION" => true,
+        // Stream commands
+        b"XINFO" | b"XLEN" | b"XPENDING" | b"XRANGE" | b"XREAD" | b"XREVRANGE" => true,
+        // HyperLogLog commands
+        b"PFCOUNT" => true,
+        // Geo commands
+        b"GEODIST"
+        | b"GEOHASH"
+        | b"GEOPOS"
+        | b"GEORADIUS_RO"
+        | b"GEORADIUSBYMEMBER_RO"
+        | b"GEOSEARCH" => true,
+        // Scripting commands
+        b"EVAL_RO" | b"EVALSHA_RO" | b"FCALL_RO" => true,
+        _ => false,
+    }
+}
+
+// The blocking commands without their own `DataCmdType`.
+const BLOCKING_COMMANDS: [&str; 5] = ["BLMOVE", "BLMPOP", "BZMPOP", "BZPOPMAX", "BZPOPMIN"];
+
+// The client command timeout and the backend response timeout are not applied to these commands
+// and they are not counted in the in-flight depth of the backends.
+pub fn has_own_timeout(cmd: &Command) -> bool {
+    match cmd.get_data_cmd_type() {
+        DataCmdType::BLPOP
+        | DataCmdType::BRPOP
+        | DataCmdType::BRPOPLPUSH
+        | DataCmdType::WAIT
+        | DataCmdType::WAITAOF => return true,
+        DataCmdType::Others => (),
+        _ => return false,
+    }
+    let cmd_name = match cmd.get_command_element(0) {
+        Some(cmd_name) => cmd_name,
+        None => return false,
+    };
+    if BLOCKING_COMMANDS
+        .iter()
+        .any(|name| cmd_name.eq_ignore_ascii_case(name.as_bytes()))
+    {
+        return true;
+    }
+    // XREAD and XREADGROUP only block with the BLOCK option before STREAMS.
+    let options_start = if cmd_name.eq_ignore_ascii_case(b"XREAD") {
+        1
+    } else if cmd_name.eq_ignore_ascii_case(b"XREADGROUP") {
+        // Skip GROUP group consumer.
+        4
+    } else {
+        return false;
+    };
+    (options_start..cmd.get_command_len().unwrap_or(0))
+        .filter_map(|i| cmd.get_command_element(i))
+        .take_while(|option| !option.eq_ignore_ascii_case(b"STREAMS"))
+        .any(|option| option.eq_ignore_ascii_case(b"BLOCK"))
+}
+
+// The only commands allowed after entering the subscribe mode.
+const SUBSCRIBE_MODE_COMMANDS: [&str; 9] = [
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "SSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "PING",
+    "QUIT",
+    "RESET",
+];
+
+// DEBUG subcommands taking a key as the second argument.
+const DEBUG_KEY_SUB_COMMANDS: [&str; 5] =
+    ["OBJECT", "SDSLEN", "DIGEST-VALUE", "LISTPACK", "QUICKLIST"];
+
+// DEBUG subcommands toggling the server behaviors for testing.
+// They are sent to all the local backends instead of the owner of slot 0.
+const DEBUG_BROADCAST_SUB_COMMANDS: [&str; 4] = [
+    "SET-ACTIVE-EXPIRE",
+    "SET-SKIP-CHECKSUM-VALIDATION",
+    "SET-DISABLE-DENY-SCRIPTS",
+    "QUICKLIST-PACKED-THRESHOLD",
+];
+
+pub fn is_debug_broadcast_sub_command(sub_cmd: &str) -> bool {
+    DEBUG_BROADCAST_SUB_COMMANDS
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(sub_cmd))
+}
+
 #[derive(Debug)]
 struct CommandInfo {
     cmd_type: CmdType,
     data_cmd_type: DataCmdType,
+    key_spec: KeySpec,
     slot: Option<usize>,
+    read_only: bool,
 }
 
 impl CommandInfo {
     fn new(packet: &RespPacket) -> Self {
         let cmd_type = CmdType::from_packet(&packet);
         let data_cmd_type = DataCmdType::from_packet(&packet);
-        let slot = Self::get_key(data_cmd_type, packet).map(generate_slot);
+        let key_spec = get_builtin_key_spec(data_cmd_type);
+        let read_only = packet.get_array_element(0).map_or(false, is_read_only_cmd);
+        let slot = match cmd_type {
+            // DEBUG subcommands without key are sent to the node owning slot 0.
+            CmdType::Debug => {
+                Some(Self::get_key(cmd_type, &key_spec, packet).map_or(0, generate_slot))
+            }
+            _ => Self::get_key(cmd_type, &key_spec, packet).map(generate_slot),
+        };
         Self {
             cmd_type,
             data_cmd_type,
+            key_spec,
             slot,
+            read_only,
         }
     }
 
-    fn get_key(data_cmd_type: DataCmdType, packet: &RespPacket) -> Option<&[u8]> {
-        match data_cmd_type {
-            DataCmdType::EVAL | DataCmdType::EVALSHA => packet.get_array_element(3),
-            _ => packet.get_array_element(1),
+    fn get_key<'a>(
+        cmd_type: CmdType,
+        key_spec: &KeySpec,
+        packet: &'a RespPacket,
+    ) -> Option<&'a [u8]> {
+        if cmd_type == CmdType::Debug {
+            let sub_cmd = packet.get_array_element(1)?;
+            return if DEBUG_KEY_SUB_COMMANDS
+                .iter()
+                .any(|c| c.as_bytes().eq_ignore_ascii_case(sub_cmd))
+            {
+                packet.get_array_element(2)
+            } else {
+                None
+            };
         }
+        key_spec.get_first_key(packet)
     }
 }
 
@@ -331,6 +607,18 @@ impl Command {
         self.request.change_bulk_array_element(index, data)
     }
 
+    // Replaces the command name and reclassifies the command.
+    pub fn rename(&mut self, cmd_name: &str) -> bool {
+        if !self
+            .request
+            .change_bulk_array_element(0, cmd_name.as_bytes().to_vec())
+        {
+            return false;
+        }
+        self.info = CommandInfo::new(&self.request);
+        true
+    }
+
     pub fn extract_inner_cmd(&mut self, removed_num: usize) -> Option<usize> {
         let remaining = self.request.left_trim_cmd(removed_num)?;
         self.info = CommandInfo::new(&self.request);
@@ -354,12 +642,374 @@ impl Command {
     }
 
     pub fn get_key(&self) -> Option<&[u8]> {
-        CommandInfo::get_key(self.get_data_cmd_type(), &self.request)
+        CommandInfo::get_key(self.get_type(), &self.info.key_spec, &self.request)
+    }
+
+    // Used by the commands unknown to the proxy with the key specs from the config.
+    pub fn set_key_spec(&mut self, key_spec: KeySpec) {
+        self.info.slot = key_spec.get_first_key(&self.request).map(generate_slot);
+        self.info.key_spec = key_spec;
     }
 
     pub fn get_slot(&self) -> Option<usize> {
         self.info.slot
     }
+
+    // Prepends the prefix to all the keys so the slot is changed too.
+    pub fn add_key_prefix(&mut self, prefix: &[u8]) {
+        // The keys are the slices of the packet so they're located by their addresses.
+        let keys: Vec<(usize, Vec<u8>)> = {
+            let keys = self.get_all_keys();
+            let len = self.get_command_len().unwrap_or(0);
+            (0..len)
+                .filter_map(|i| self.get_command_element(i).map(|element| (i, element)))
+                .filter(|(_, element)| {
+                    keys.iter()
+                        .any(|key| key.as_ptr() == element.as_ptr() && key.len() == element.len())
+                })
+                .map(|(i, key)| {
+                    let mut prefixed_key = prefix.to_vec();
+                    prefixed_key.extend_from_slice(key);
+                    (i, prefixed_key)
+                })
+                .collect()
+        };
+        for (i, key) in keys {
+            self.change_element(i, key);
+        }
+        let key = CommandInfo::get_key(self.get_type(), &self.info.key_spec, &self.request);
+        if let Some(slot) = key.map(generate_slot) {
+            self.info.slot = Some(slot);
+        }
+    }
+
+    // Management commands which are only allowed on the admin port if it's enabled.
+    pub fn is_admin_cmd(&self) -> bool {
+        match self.get_type() {
+            CmdType::UmCtl | CmdType::Config => true,
+            CmdType::Client => self
+                .get_command_element(1)
+                .map(|sub_cmd| sub_cmd.eq_ignore_ascii_case(b"KILL"))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    // `SET key value GET` replies the old value instead of OK.
+    pub fn has_set_get_option(&self) -> bool {
+        if self.get_data_cmd_type() != DataCmdType::SET {
+            return false;
+        }
+        let len = self.get_command_len().unwrap_or(0);
+        (3..len).any(|i| {
+            self.get_command_element(i)
+                .map(|opt| opt.eq_ignore_ascii_case(b"GET"))
+                .unwrap_or(false)
+        })
+    }
+
+    // `WAIT 0 <timeout>` never blocks since no replica needs to ack.
+    pub fn is_zero_replica_wait(&self) -> bool {
+        if self.get_data_cmd_type() != DataCmdType::WAIT || self.get_command_len() != Some(3) {
+            return false;
+        }
+        let num_replicas = self
+            .get_command_element(1)
+            .and_then(|n| btoi::btoi::<i64>(n).ok());
+        let timeout = self
+            .get_command_element(2)
+            .and_then(|n| btoi::btoi::<i64>(n).ok());
+        match (num_replicas, timeout) {
+            (Some(0), Some(timeout)) => timeout >= 0,
+            _ => false,
+        }
+    }
+
+    pub fn is_allowed_in_subscribe_mode(&self) -> bool {
+        match self.get_command_name() {
+            Some(cmd_name) => SUBSCRIBE_MODE_COMMANDS
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(cmd_name)),
+            None => false,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.info.read_only || self.is_bitfield_get_only()
+    }
+
+    // The commands without keys don't depend on the slot ownership
+    // so they are not writes here.
+    pub fn is_keyed_write(&self) -> bool {
+        self.get_slot().is_some() && !self.is_read_only()
+    }
+
+    // BITFIELD key [GET encoding offset] [SET encoding offset value]
+    //   [INCRBY encoding offset increment] [OVERFLOW WRAP|SAT|FAIL]
+    // OVERFLOW only changes the following SET and INCRBY.
+    pub fn is_bitfield_get_only(&self) -> bool {
+        if self.get_data_cmd_type() != DataCmdType::BITFIELD {
+            return false;
+        }
+        let len = self.get_command_len().unwrap_or(0);
+        let mut i = 2;
+        while i < len {
+            let op = match self.get_command_element(i) {
+                Some(op) => op,
+                None => return false,
+            };
+            i += if op.eq_ignore_ascii_case(b"GET") {
+                3
+            } else if op.eq_ignore_ascii_case(b"OVERFLOW") {
+                2
+            } else {
+                return false;
+            };
+        }
+        i == len
+    }
+
+    // Used to route the commands without keys.
+    pub fn set_slot(&mut self, slot: usize) {
+        self.info.slot = Some(slot);
+    }
+
+    // For the commands like `SINTERCARD numkeys key [key ...] [options]`.
+    pub fn get_numkeys_keys(&self, numkeys_index: usize) -> Result<Vec<&[u8]>, &'static str> {
+        get_numkeys_keys(&self.request, numkeys_index)
+    }
+
+    // SMOVE source destination member
+    pub fn get_smove_keys(&self) -> Option<(&[u8], &[u8])> {
+        if self.get_data_cmd_type() != DataCmdType::SMOVE {
+            return None;
+        }
+        let src = self.get_command_element(1)?;
+        let dst = self.get_command_element(2)?;
+        Some((src, dst))
+    }
+
+    // LCS key1 key2 [LEN] [IDX] [MINMATCHLEN min-match-len] [WITHMATCHLEN]
+    pub fn get_lcs_keys(&self) -> Option<(&[u8], &[u8])> {
+        if self.get_data_cmd_type() != DataCmdType::LCS {
+            return None;
+        }
+        let key1 = self.get_command_element(1)?;
+        let key2 = self.get_command_element(2)?;
+        Some((key1, key2))
+    }
+
+    // Returns (source key, destination key) for the GEO commands writing to another key.
+    pub fn get_geo_store_keys(&self) -> Option<(&[u8], &[u8])> {
+        match self.get_data_cmd_type() {
+            DataCmdType::GEOSEARCHSTORE => {
+                let dst = self.get_command_element(1)?;
+                let src = self.get_command_element(2)?;
+                Some((src, dst))
+            }
+            // GEORADIUS key longitude latitude radius unit [options]
+            DataCmdType::GEORADIUS => get_geo_store_keys(&self.request, 1, 6),
+            // GEORADIUSBYMEMBER key member radius unit [options]
+            DataCmdType::GEORADIUSBYMEMBER => get_geo_store_keys(&self.request, 1, 5),
+            _ => None,
+        }
+    }
+
+    // Checks that the keys formed by the BY and GET patterns of SORT
+    // are in the same slot as the sorted key.
+    pub fn check_sort_patterns(&self) -> Result<(), &'static str> {
+        match self.get_data_cmd_type() {
+            DataCmdType::SORT | DataCmdType::SORT_RO => check_sort_patterns(&self.request),
+            _ => Ok(()),
+        }
+    }
+
+    // All the keys of the command, used by COMMAND GETKEYS.
+    pub fn get_all_keys(&self) -> Vec<&[u8]> {
+        match self.get_type() {
+            CmdType::Others => self.info.key_spec.get_keys(&self.request),
+            CmdType::Debug => self.get_key().into_iter().collect(),
+            // The other commands are handled by the proxy itself.
+            _ => vec![],
+        }
+    }
+}
+// COMMAND GETKEYS command [arg ...]
+pub fn parse_command_getkeys(cmd: &Command) -> Option<Command> {
+    let len = cmd.get_command_len()?;
+    if len < 3 {
+        return None;
+    }
+    let elements = (2..len)
+        .filter_map(|i| cmd.get_command_element(i))
+        .map(|e| Resp::Bulk(BulkStr::Str(e.to_vec())))
+        .collect();
+    let packet = RespPacket::Data(Resp::Arr(Array::Arr(elements)));
+    Some(Command::new(Box::new(packet)))
+}
+
+pub const INFO_SECTIONS: [&str; 6] = [
+    "server",
+    "clients",
+    "memory",
+    "stats",
+    "replication",
+    "keyspace",
+];
+
+// INFO [section [section ...]]
+// Returns the selected sections in the order of INFO_SECTIONS.
+// The unknown sections are ignored like redis.
+pub fn parse_info_sections(cmd: &Command) -> Vec<&'static str> {
+    let len = cmd.get_command_len().unwrap_or(0);
+    let selected: Vec<String> = (1..len)
+        .filter_map(|i| cmd.get_command_element(i))
+        .map(|section| String::from_utf8_lossy(section).to_lowercase())
+        .collect();
+    let all = selected.is_empty()
+        || selected
+            .iter()
+            .any(|s| s == "all" || s == "default" || s == "everything");
+    INFO_SECTIONS
+        .iter()
+        .filter(|section| all || selected.iter().any(|s| s == *section))
+        .cloned()
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct HelloArgs<'a> {
+    pub protover: Option<i64>,
+    // (username, password)
+    pub auth: Option<(&'a [u8], &'a [u8])>,
+    pub client_name: Option<&'a [u8]>,
+}
+
+// HELLO [protover [AUTH username password] [SETNAME clientname]]
+pub fn parse_hello(cmd: &Command) -> Result<HelloArgs, String> {
+    let mut args = HelloArgs {
+        protover: None,
+        auth: None,
+        client_name: None,
+    };
+    let protover = match cmd.get_command_element(1) {
+        Some(protover) => protover,
+        None => return Ok(args),
+    };
+    args.protover = match btoi::btoi::<i64>(protover) {
+        Ok(protover) => Some(protover),
+        Err(_) => return Err("ERR Protocol version is not an integer or out of range".to_string()),
+    };
+
+    let len = cmd.get_command_len().unwrap_or(0);
+    let mut i = 2;
+    while i < len {
+        let option = cmd.get_command_element(i).unwrap_or(b"");
+        let option_err = || {
+            format!(
+                "ERR Syntax error in HELLO option '{}'",
+                String::from_utf8_lossy(option)
+            )
+        };
+        if option.eq_ignore_ascii_case(b"AUTH") {
+            let username = cmd.get_command_element(i + 1).ok_or_else(option_err)?;
+            let password = cmd.get_command_element(i + 2).ok_or_else(option_err)?;
+            args.auth = Some((username, password));
+            i += 3;
+        } else if option.eq_ignore_ascii_case(b"SETNAME") {
+            args.client_name = Some(cmd.get_command_element(i + 1).ok_or_else(option_err)?);
+            i += 2;
+        } else {
+            return Err(option_err());
+        }
+    }
+    Ok(args)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MigrateArgs<'a> {
+    pub address: String,
+    pub keys: Vec<&'a [u8]>,
+    pub copy: bool,
+    pub replace: bool,
+    // Applied to each step of talking to the destination.
+    pub timeout: Duration,
+    // The arguments of AUTH sent to the destination, either the password
+    // or the username and the password.
+    pub auth: Vec<&'a [u8]>,
+}
+
+// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+//     [AUTH password | AUTH2 username password] [KEYS key [key ...]]
+pub fn parse_migrate(cmd: &Command) -> Result<MigrateArgs, String> {
+    let syntax_err = || "ERR syntax error".to_string();
+    let len = cmd.get_command_len().unwrap_or(0);
+    if len < 6 {
+        return Err("ERR wrong number of arguments for 'migrate' command".to_string());
+    }
+    let get_str = |i| {
+        cmd.get_command_element(i)
+            .and_then(|e| str::from_utf8(e).ok())
+            .ok_or_else(syntax_err)
+    };
+    let address = format!("{}:{}", get_str(1)?, get_str(2)?);
+    if get_str(4)? != "0" {
+        return Err("ERR the proxy only has the db 0".to_string());
+    }
+    // Zero is the default timeout of redis.
+    let timeout = match get_str(5)?.parse::<u64>() {
+        Ok(0) => 1000,
+        Ok(timeout) => timeout,
+        Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+    };
+
+    let mut args = MigrateArgs {
+        address,
+        keys: vec![],
+        copy: false,
+        replace: false,
+        timeout: Duration::from_millis(timeout),
+        auth: vec![],
+    };
+    let mut i = 6;
+    while i < len {
+        let option = cmd.get_command_element(i).ok_or_else(syntax_err)?;
+        if option.eq_ignore_ascii_case(b"COPY") {
+            args.copy = true;
+        } else if option.eq_ignore_ascii_case(b"REPLACE") {
+            args.replace = true;
+        } else if option.eq_ignore_ascii_case(b"AUTH") {
+            args.auth = cmd.get_command_element(i + 1).into_iter().collect();
+            i += 1;
+        } else if option.eq_ignore_ascii_case(b"AUTH2") {
+            args.auth = (i + 1..i + 3)
+                .filter_map(|j| cmd.get_command_element(j))
+                .collect();
+            i += 2;
+        } else if option.eq_ignore_ascii_case(b"KEYS") {
+            args.keys = (i + 1..len)
+                .filter_map(|j| cmd.get_command_element(j))
+                .collect();
+            break;
+        } else {
+            return Err(syntax_err());
+        }
+        i += 1;
+    }
+    if i > len {
+        return Err(syntax_err());
+    }
+
+    let key = cmd.get_command_element(3).ok_or_else(syntax_err)?;
+    match (key.is_empty(), args.keys.is_empty()) {
+        (false, true) => args.keys.push(key),
+        (true, false) => (),
+        _ => return Err(
+            "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string"
+                .to_string(),
+        ),
+    }
+    Ok(args)
 }
 
 pub struct TaskReply {
@@ -386,6 +1036,10 @@ impl TaskReply {
         (request, packet, slowlog)
     }
 
+    pub fn get_resp_slice(&self) -> RespSlice {
+        self.packet.to_resp_slice()
+    }
+
     pub fn into_resp_vec(self) -> RespVec {
         let (_, packet, _) = self.into_inner();
         packet.into_resp_vec()
@@ -428,6 +1082,13 @@ impl CmdReplySender {
         }
     }
 
+    // The receiver is dropped.
+    pub fn is_canceled(&self) -> bool {
+        self.reply_sender
+            .as_ref()
+            .map_or(true, |reply_sender| reply_sender.is_canceled())
+    }
+
     fn try_send(&mut self, res: TaskResult) -> Option<Result<(), CommandError>> {
         // Must not send twice.
         match self.reply_sender.take() {
@@ -450,7 +1111,10 @@ impl CmdReplySender {
 // Make sure that result will always be sent back
 impl Drop for CmdReplySender {
     fn drop(&mut self) {
-        self.try_send(Err(CommandError::Dropped));
+        // Nobody waits for the reply of the canceled commands.
+        if !self.is_canceled() {
+            self.try_send(Err(CommandError::Dropped));
+        }
     }
 }
 
@@ -479,6 +1143,7 @@ pub enum CommandError {
     Dropped,
     Canceled,
     InnerError,
+    Timeout,
 }
 
 impl Clone for CommandError {
@@ -492,6 +1157,7 @@ impl Clone for CommandError {
             Self::Dropped => Self::Dropped,
             Self::Canceled => Self::Canceled,
             Self::InnerError => Self::InnerError,
+            Self::Timeout => Self::Timeout,
         }
     }
 }
@@ -518,12 +1184,25 @@ impl Error for CommandError {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::{Array, BulkStr, Resp};
+    use crate::protocol::{new_simple_packet_codec, PacketDecoder, PacketEncoder};
+    use bytes::BytesMut;
 
     #[test]
     fn test_parse_cmd_type() {
         assert_eq!(CmdType::from_cmd_name(b"pInG"), CmdType::Ping);
         assert_eq!(CmdType::from_cmd_name(b"get"), CmdType::Others);
+        assert_eq!(CmdType::from_cmd_name(b"client"), CmdType::Client);
+        assert_eq!(CmdType::from_cmd_name(b"latency"), CmdType::Latency);
+        assert_eq!(CmdType::from_cmd_name(b"reset"), CmdType::Reset);
+        assert_eq!(CmdType::from_cmd_name(b"Hello"), CmdType::Hello);
+        assert_eq!(CmdType::from_cmd_name(b"shutdown"), CmdType::Shutdown);
+        assert_eq!(CmdType::from_cmd_name(b"ssubscribe"), CmdType::SSubscribe);
+        assert_eq!(
+            CmdType::from_cmd_name(b"SUnsubscribe"),
+            CmdType::SUnsubscribe
+        );
+        assert_eq!(CmdType::from_cmd_name(b"acl"), CmdType::Acl);
+        assert_eq!(CmdType::from_cmd_name(b"monitor"), CmdType::Monitor);
     }
 
     #[test]
@@ -553,4 +1232,790 @@ mod tests {
         assert_eq!(cmd.get_type(), CmdType::Others);
         assert_eq!(cmd.get_data_cmd_type(), DataCmdType::GET);
     }
+
+    fn gen_cmd(cmd: &str) -> Command {
+        let resps = cmd
+            .split_whitespace()
+            .map(|e| Resp::Bulk(BulkStr::Str(e.as_bytes().to_vec())))
+            .collect();
+        Command::new(Box::new(RespPacket::Data(Resp::Arr(Array::Arr(resps)))))
+    }
+
+    #[test]
+    fn test_expiration_cmd_routing() {
+        let get_cmd = gen_cmd("GET somekey");
+        let cmds = [
+            ("EXPIRE somekey 10", DataCmdType::EXPIRE),
+            ("EXPIRE somekey 10 NX", DataCmdType::EXPIRE),
+            ("pexpire somekey 10000 XX", DataCmdType::PEXPIRE),
+            ("EXPIREAT somekey 1893456000 GT", DataCmdType::EXPIREAT),
+            ("PEXPIREAT somekey 1893456000000 LT", DataCmdType::PEXPIREAT),
+            ("EXPIRETIME somekey", DataCmdType::EXPIRETIME),
+            ("PEXPIRETIME somekey", DataCmdType::PEXPIRETIME),
+            ("PERSIST somekey", DataCmdType::PERSIST),
+            ("TTL somekey", DataCmdType::TTL),
+            ("PTTL somekey", DataCmdType::PTTL),
+        ];
+        for (cmd_str, data_cmd_type) in cmds.iter() {
+            let cmd = gen_cmd(cmd_str);
+            assert_eq!(cmd.get_data_cmd_type(), *data_cmd_type);
+            assert_eq!(cmd.get_key(), Some(&b"somekey"[..]));
+            assert_eq!(cmd.get_slot(), get_cmd.get_slot());
+        }
+
+        // The options are passed through untouched.
+        let cmd = gen_cmd("EXPIRE somekey 10 NX");
+        assert_eq!(cmd.get_command_len(), Some(4));
+        assert_eq!(cmd.get_command_element(3), Some(&b"NX"[..]));
+    }
+
+    #[test]
+    fn test_string_range_cmd_args() {
+        let cmds = [
+            ("BITCOUNT mykey", DataCmdType::BITCOUNT),
+            ("BITCOUNT mykey 1 -1", DataCmdType::BITCOUNT),
+            ("bitcount mykey 5 30 BIT", DataCmdType::BITCOUNT),
+            ("BITCOUNT mykey 0 0 BYTE", DataCmdType::BITCOUNT),
+            ("BITPOS mykey 1", DataCmdType::BITPOS),
+            ("BITPOS mykey 0 2 -1 BYTE", DataCmdType::BITPOS),
+            ("bitpos mykey 1 7 15 BIT", DataCmdType::BITPOS),
+            ("GETRANGE mykey 0 -1", DataCmdType::GETRANGE),
+            ("GETRANGE mykey -3 -1", DataCmdType::GETRANGE),
+        ];
+        for (cmd_str, data_cmd_type) in cmds.iter() {
+            let cmd = gen_cmd(cmd_str);
+            assert_eq!(cmd.get_type(), CmdType::Others);
+            assert_eq!(cmd.get_data_cmd_type(), *data_cmd_type);
+            assert!(cmd.is_read_only());
+            // The range arguments should never be used as keys.
+            assert_eq!(cmd.get_key(), Some(&b"mykey"[..]));
+            assert_eq!(cmd.get_slot(), Some(generate_slot(b"mykey")));
+        }
+    }
+
+    #[test]
+    fn test_hash_field_expiration_cmd_key() {
+        let cmds = [
+            ("HEXPIRE myhash 10 NX FIELDS 2 f1 f2", DataCmdType::HEXPIRE),
+            ("hpexpire myhash 10000 FIELDS 1 f1", DataCmdType::HPEXPIRE),
+            (
+                "HEXPIREAT myhash 1700000000 FIELDS 1 f1",
+                DataCmdType::HEXPIREAT,
+            ),
+            (
+                "HPEXPIREAT myhash 1700000000000 GT FIELDS 1 f1",
+                DataCmdType::HPEXPIREAT,
+            ),
+            ("HEXPIRETIME myhash FIELDS 1 f1", DataCmdType::HEXPIRETIME),
+            ("HPEXPIRETIME myhash FIELDS 1 f1", DataCmdType::HPEXPIRETIME),
+            ("HTTL myhash FIELDS 3 f1 f2 f3", DataCmdType::HTTL),
+            ("HPTTL myhash FIELDS 1 f1", DataCmdType::HPTTL),
+            ("HPERSIST myhash FIELDS 1 f1", DataCmdType::HPERSIST),
+            ("HGETEX myhash EX 60 FIELDS 2 f1 f2", DataCmdType::HGETEX),
+            ("HGETEX myhash PERSIST FIELDS 1 f1", DataCmdType::HGETEX),
+            ("HGETDEL myhash FIELDS 1 f1", DataCmdType::HGETDEL),
+            (
+                "HSETEX myhash FNX EX 60 FIELDS 1 f1 v1",
+                DataCmdType::HSETEX,
+            ),
+        ];
+        for (cmd_str, data_cmd_type) in cmds.iter() {
+            let cmd = gen_cmd(cmd_str);
+            assert_eq!(cmd.get_type(), CmdType::Others);
+            assert_eq!(cmd.get_data_cmd_type(), *data_cmd_type);
+            // The fields are never used as keys.
+            assert_eq!(cmd.get_key(), Some(&b"myhash"[..]));
+            assert_eq!(cmd.get_slot(), Some(generate_slot(b"myhash")));
+
+            // The FIELDS section is forwarded verbatim.
+            let args: Vec<&[u8]> = cmd_str.split_whitespace().map(str::as_bytes).collect();
+            assert_eq!(cmd.get_command_len(), Some(args.len()));
+            for (i, arg) in args.iter().enumerate() {
+                assert_eq!(cmd.get_command_element(i), Some(*arg));
+            }
+        }
+
+        assert!(gen_cmd("HTTL myhash FIELDS 1 f1").is_read_only());
+        assert!(gen_cmd("HPEXPIRETIME myhash FIELDS 1 f1").is_read_only());
+        assert!(!gen_cmd("HEXPIRE myhash 10 FIELDS 1 f1").is_read_only());
+        assert!(!gen_cmd("HGETDEL myhash FIELDS 1 f1").is_read_only());
+        // Expiring the last field deletes the hash.
+        assert!(requires_blocking_migration(DataCmdType::HEXPIRE));
+        assert!(requires_blocking_migration(DataCmdType::HGETDEL));
+        assert!(!requires_blocking_migration(DataCmdType::HTTL));
+    }
+
+    #[test]
+    fn test_random_member_cmd_key() {
+        let cmds = [
+            ("HRANDFIELD myhash", DataCmdType::HRANDFIELD),
+            ("hrandfield myhash -5 WITHVALUES", DataCmdType::HRANDFIELD),
+            ("SRANDMEMBER myhash", DataCmdType::SRANDMEMBER),
+            ("SRANDMEMBER myhash 3", DataCmdType::SRANDMEMBER),
+            ("ZRANDMEMBER myhash", DataCmdType::ZRANDMEMBER),
+            ("ZRANDMEMBER myhash 2 WITHSCORES", DataCmdType::ZRANDMEMBER),
+        ];
+        for (cmd_str, data_cmd_type) in cmds.iter() {
+            let cmd = gen_cmd(cmd_str);
+            assert_eq!(cmd.get_data_cmd_type(), *data_cmd_type);
+            assert_eq!(cmd.get_key(), Some(&b"myhash"[..]));
+            assert_eq!(cmd.get_slot(), Some(generate_slot(b"myhash")));
+        }
+
+        let cmd = gen_cmd("ZRANDMEMBER myhash 2 WITHSCORES");
+        assert_eq!(cmd.get_command_element(2), Some(&b"2"[..]));
+        assert_eq!(cmd.get_command_element(3), Some(&b"WITHSCORES"[..]));
+    }
+
+    #[test]
+    fn test_dump_restore_binary_payload() {
+        // The serialized payload could contain any bytes including CRLF.
+        let payload = b"\x00\xc0\x01\r\n\t\x00\xf6\x8a\xb6z\x85\x87r\xc4";
+        let mut data = format!(
+            "*6\r\n$7\r\nRESTORE\r\n$7\r\nsomekey\r\n$1\r\n0\r\n${}\r\n",
+            payload.len()
+        )
+        .into_bytes();
+        data.extend_from_slice(payload);
+        data.extend_from_slice(b"\r\n$7\r\nREPLACE\r\n$6\r\nABSTTL\r\n");
+
+        let (mut encoder, mut decoder) =
+            new_simple_packet_codec::<Box<RespPacket>, Box<RespPacket>>();
+        let mut buf = BytesMut::from(data.as_slice());
+        let packet = decoder.decode(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+
+        let cmd = Command::new(packet);
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::RESTORE);
+        assert_eq!(cmd.get_key(), Some(&b"somekey"[..]));
+        assert_eq!(cmd.get_slot(), gen_cmd("DUMP somekey").get_slot());
+        assert_eq!(cmd.get_command_element(3), Some(&payload[..]));
+        assert_eq!(cmd.get_command_element(4), Some(&b"REPLACE"[..]));
+        assert_eq!(cmd.get_command_element(5), Some(&b"ABSTTL"[..]));
+
+        let mut output = vec![];
+        encoder
+            .encode(cmd.into_packet(), |d| output.extend_from_slice(d))
+            .unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_wait_cmd() {
+        for cmd_str in ["WAIT 1 100", "WAITAOF 1 0 100"].iter() {
+            let mut cmd = gen_cmd(cmd_str);
+            assert_eq!(cmd.get_key(), None);
+            assert_eq!(cmd.get_slot(), None);
+            cmd.set_slot(233);
+            assert_eq!(cmd.get_slot(), Some(233));
+        }
+        assert_eq!(gen_cmd("wait 0 0").get_data_cmd_type(), DataCmdType::WAIT);
+        assert_eq!(
+            gen_cmd("waitaof 0 1 0").get_data_cmd_type(),
+            DataCmdType::WAITAOF
+        );
+    }
+
+    #[test]
+    fn test_debug_cmd() {
+        let cmd = gen_cmd("DEBUG object somekey");
+        assert_eq!(cmd.get_type(), CmdType::Debug);
+        assert_eq!(cmd.get_key(), Some(&b"somekey"[..]));
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"somekey")));
+
+        let cmd = gen_cmd("DEBUG QUICKLIST-PACKED-THRESHOLD 100");
+        assert_eq!(cmd.get_key(), None);
+        assert_eq!(cmd.get_slot(), Some(0));
+
+        let cmd = gen_cmd("DEBUG");
+        assert_eq!(cmd.get_key(), None);
+        assert_eq!(cmd.get_slot(), Some(0));
+    }
+
+    #[test]
+    fn test_zadd_options() {
+        let cmd = gen_cmd("ZADD {a}zset NX GT CH INCR 1.5 member");
+        assert_eq!(cmd.get_key(), Some(b"{a}zset".as_ref()));
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"a")));
+
+        let cmd = gen_cmd("ZADD XX 1 a 2 b");
+        assert_eq!(cmd.get_key(), Some(b"XX".as_ref()));
+    }
+
+    #[test]
+    fn test_getset_and_set_get() {
+        let cmd = gen_cmd("GETSET {a}key value");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::GETSET);
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"a")));
+        assert!(!cmd.has_set_get_option());
+
+        let cmd = gen_cmd("SET {a}key value EX 10 get");
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"a")));
+        assert!(cmd.has_set_get_option());
+
+        assert!(!gen_cmd("SET key value NX").has_set_get_option());
+        assert!(!gen_cmd("SET key GET").has_set_get_option());
+    }
+
+    #[test]
+    fn test_collection_cmd_key() {
+        for cmd in &[
+            "LPUSH {a}list x y",
+            "SADD {a}list x y",
+            "HSET {a}list field value",
+        ] {
+            let cmd = gen_cmd(cmd);
+            assert_eq!(cmd.get_type(), CmdType::Others);
+            assert_eq!(cmd.get_data_cmd_type(), DataCmdType::Others);
+            assert_eq!(cmd.get_key(), Some(b"{a}list".as_ref()));
+            assert_eq!(cmd.get_slot(), Some(generate_slot(b"a")));
+        }
+        // The values are never taken as the key.
+        let cmd = gen_cmd("HSET {a}hash {b}field value");
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"a")));
+    }
+
+    #[test]
+    fn test_parse_info_sections() {
+        assert_eq!(
+            parse_info_sections(&gen_cmd("INFO")),
+            INFO_SECTIONS.to_vec()
+        );
+        assert_eq!(
+            parse_info_sections(&gen_cmd("INFO everything")),
+            INFO_SECTIONS.to_vec()
+        );
+        assert_eq!(
+            parse_info_sections(&gen_cmd("INFO Replication server")),
+            vec!["server", "replication"]
+        );
+        assert!(parse_info_sections(&gen_cmd("INFO unknown")).is_empty());
+    }
+
+    #[test]
+    fn test_command_getkeys() {
+        let check = |cmd: &str, keys: &[&str]| {
+            let cmd = parse_command_getkeys(&gen_cmd(cmd)).unwrap();
+            let expected: Vec<&[u8]> = keys.iter().map(|k| k.as_bytes()).collect();
+            assert_eq!(cmd.get_all_keys(), expected);
+        };
+        check("COMMAND GETKEYS GET key", &["key"]);
+        check("COMMAND GETKEYS set key value EX 10", &["key"]);
+        check("COMMAND GETKEYS MGET a b c", &["a", "b", "c"]);
+        check("COMMAND GETKEYS MSET a 1 b 2", &["a", "b"]);
+        check("COMMAND GETKEYS DEL a b", &["a", "b"]);
+        check("COMMAND GETKEYS BLPOP a b 0", &["a", "b"]);
+        check("COMMAND GETKEYS RPOPLPUSH a b", &["a", "b"]);
+        check("COMMAND GETKEYS SMOVE a b member", &["a", "b"]);
+        check("COMMAND GETKEYS EVAL script 2 a b arg", &["a", "b"]);
+        check("COMMAND GETKEYS SINTERCARD 2 a b LIMIT 1", &["a", "b"]);
+        check("COMMAND GETKEYS ZMPOP 1 a MIN", &["a"]);
+        check("COMMAND GETKEYS GEORADIUS a 0 0 1 km STORE b", &["a", "b"]);
+        check(
+            "COMMAND GETKEYS GEOSEARCHSTORE b a FROMMEMBER m BYRADIUS 1 km",
+            &["b", "a"],
+        );
+        check("COMMAND GETKEYS XGROUP CREATE s g $", &["s"]);
+        check("COMMAND GETKEYS PFMERGE d s1 s2", &["d", "s1", "s2"]);
+
+        // Keyless commands
+        check("COMMAND GETKEYS EVAL script 0", &[]);
+        check("COMMAND GETKEYS PING", &[]);
+        check("COMMAND GETKEYS ECHO hello", &[]);
+        check("COMMAND GETKEYS WAIT 1 0", &[]);
+
+        assert!(parse_command_getkeys(&gen_cmd("COMMAND GETKEYS")).is_none());
+    }
+
+    #[test]
+    fn test_stream_group_cmd_key() {
+        for cmd in &[
+            "XGROUP CREATE {a}stream group $ MKSTREAM",
+            "XGROUP create {a}stream group 0 MKSTREAM ENTRIESREAD 3",
+            "XGROUP SETID {a}stream group 0",
+            "XGROUP DESTROY {a}stream group",
+            "XGROUP CREATECONSUMER {a}stream group consumer",
+            "XGROUP DELCONSUMER {a}stream group consumer",
+            "XACK {a}stream group 1526569495631-0",
+            "XCLAIM {a}stream group consumer 3600000 1526569498055-0 JUSTID",
+            "XAUTOCLAIM {a}stream group consumer 3600000 0-0 COUNT 25",
+            "XPENDING {a}stream group IDLE 9000 - + 10 consumer",
+        ] {
+            let cmd = gen_cmd(cmd);
+            assert_eq!(cmd.get_key(), Some(b"{a}stream".as_ref()));
+            assert_eq!(cmd.get_slot(), Some(generate_slot(b"a")));
+        }
+        // The group and consumer names are never taken as the key.
+        let cmd = gen_cmd("XGROUP CREATE {a}stream {b}group $");
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"a")));
+
+        let cmd = gen_cmd("XGROUP HELP");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::XGROUP);
+        assert_eq!(cmd.get_slot(), None);
+    }
+
+    #[test]
+    fn test_parse_hello() {
+        let cmd = gen_cmd("HELLO");
+        assert_eq!(
+            parse_hello(&cmd),
+            Ok(HelloArgs {
+                protover: None,
+                auth: None,
+                client_name: None,
+            })
+        );
+        let cmd = gen_cmd("HELLO 2 auth default mycluster SETNAME myapp");
+        assert_eq!(
+            parse_hello(&cmd),
+            Ok(HelloArgs {
+                protover: Some(2),
+                auth: Some((b"default".as_ref(), b"mycluster".as_ref())),
+                client_name: Some(b"myapp".as_ref()),
+            })
+        );
+        assert!(parse_hello(&gen_cmd("HELLO two")).is_err());
+        assert!(parse_hello(&gen_cmd("HELLO 2 AUTH default")).is_err());
+        assert!(parse_hello(&gen_cmd("HELLO 2 SETNAME")).is_err());
+        assert_eq!(
+            parse_hello(&gen_cmd("HELLO 2 xxx")),
+            Err("ERR Syntax error in HELLO option 'xxx'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_has_own_timeout() {
+        for cmd in [
+            "BLPOP l 0",
+            "WAIT 1 0",
+            "BZPOPMIN z 0",
+            "bzpopmax z 0",
+            "BLMOVE a b LEFT RIGHT 0",
+            "BLMPOP 0 1 l LEFT",
+            "BZMPOP 0 1 z MIN",
+            "XREAD COUNT 1 BLOCK 0 STREAMS s $",
+            "XREADGROUP GROUP g c BLOCK 0 STREAMS s >",
+        ]
+        .iter()
+        {
+            assert!(has_own_timeout(&gen_cmd(cmd)), "{}", cmd);
+        }
+        for cmd in [
+            "GET a",
+            "ZPOPMIN z",
+            "XREAD COUNT 1 STREAMS s $",
+            "XREAD STREAMS BLOCK $",
+            "XREADGROUP GROUP BLOCK c STREAMS s >",
+        ]
+        .iter()
+        {
+            assert!(!has_own_timeout(&gen_cmd(cmd)), "{}", cmd);
+        }
+    }
+
+    #[test]
+    fn test_parse_migrate() {
+        let cmd = gen_cmd("MIGRATE 127.0.0.1 5299 key 0 1000 COPY AUTH pw");
+        let args = parse_migrate(&cmd).unwrap();
+        assert_eq!(args.address, "127.0.0.1:5299");
+        assert_eq!(args.keys, vec![b"key".as_ref()]);
+        assert!(args.copy);
+        assert!(!args.replace);
+        assert_eq!(args.timeout, Duration::from_millis(1000));
+        assert_eq!(args.auth, vec![b"pw".as_ref()]);
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"key")));
+
+        let mut cmd = gen_cmd("MIGRATE 127.0.0.1 5299 _ 0 0 REPLACE AUTH2 user pw KEYS a{1} b{1}");
+        assert!(cmd.change_element(3, vec![]));
+        let cmd = Command::new(Box::new(cmd.get_packet()));
+        let args = parse_migrate(&cmd).unwrap();
+        assert_eq!(args.keys, vec![b"a{1}".as_ref(), b"b{1}".as_ref()]);
+        assert!(args.replace);
+        assert_eq!(args.timeout, Duration::from_millis(1000));
+        assert_eq!(args.auth, vec![b"user".as_ref(), b"pw".as_ref()]);
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"a{1}")));
+
+        assert!(parse_migrate(&gen_cmd("MIGRATE 127.0.0.1 5299 key 1 1000")).is_err());
+        assert!(parse_migrate(&gen_cmd("MIGRATE 127.0.0.1 5299 key 0 abc")).is_err());
+        assert!(parse_migrate(&gen_cmd("MIGRATE 127.0.0.1 5299 key 0 1000 AUTH")).is_err());
+        assert!(parse_migrate(&gen_cmd("MIGRATE 127.0.0.1 5299 key 0 1000 KEYS a")).is_err());
+        assert!(parse_migrate(&gen_cmd("MIGRATE 127.0.0.1 5299 key 0 1000 FAST")).is_err());
+        assert!(parse_migrate(&gen_cmd("MIGRATE 127.0.0.1 5299 key 0")).is_err());
+    }
+
+    #[test]
+    fn test_subscribe_mode_commands() {
+        assert!(gen_cmd("subscribe channel").is_allowed_in_subscribe_mode());
+        assert!(gen_cmd("PUNSUBSCRIBE").is_allowed_in_subscribe_mode());
+        assert!(gen_cmd("ping").is_allowed_in_subscribe_mode());
+        assert!(gen_cmd("RESET").is_allowed_in_subscribe_mode());
+        assert!(!gen_cmd("GET key").is_allowed_in_subscribe_mode());
+        assert!(!gen_cmd("PUBLISH channel msg").is_allowed_in_subscribe_mode());
+    }
+
+    #[test]
+    fn test_keyed_write() {
+        assert!(gen_cmd("SET key value").is_keyed_write());
+        assert!(gen_cmd("hset key field value").is_keyed_write());
+        assert!(!gen_cmd("GET key").is_keyed_write());
+        assert!(!gen_cmd("hgetall key").is_keyed_write());
+        assert!(!gen_cmd("LRANGE key 0 -1").is_keyed_write());
+        assert!(!gen_cmd("PING").is_keyed_write());
+    }
+
+    fn check_read_only(reads: &[&str], writes: &[&str]) {
+        for cmd_str in reads.iter() {
+            assert!(gen_cmd(cmd_str).is_read_only(), "{}", cmd_str);
+        }
+        for cmd_str in writes.iter() {
+            assert!(!gen_cmd(cmd_str).is_read_only(), "{}", cmd_str);
+        }
+    }
+
+    #[test]
+    fn test_read_only_string_cmds() {
+        check_read_only(
+            &[
+                "GET key",
+                "mget a b",
+                "STRLEN key",
+                "GETRANGE key 0 -1",
+                "SUBSTR key 0 -1",
+                "GETBIT key 1",
+                "BITCOUNT key",
+                "BITPOS key 1",
+                "LCS a b",
+                "BITFIELD_RO key GET u8 0",
+                "BITFIELD key GET u8 0",
+            ],
+            &[
+                "SET key value",
+                "GETSET key value",
+                "GETDEL key",
+                "GETEX key EX 1",
+                "INCR key",
+                "APPEND key value",
+                "SETBIT key 1 1",
+                "BITOP AND dst a b",
+                "BITFIELD key SET u8 0 1",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_read_only_key_cmds() {
+        check_read_only(
+            &[
+                "EXISTS key",
+                "TYPE key",
+                "TTL key",
+                "PTTL key",
+                "EXPIRETIME key",
+                "PEXPIRETIME key",
+                "DUMP key",
+                "OBJECT ENCODING key",
+                "TOUCH key",
+                "SORT_RO key",
+                "SCAN 0",
+                "KEYS *",
+                "RANDOMKEY",
+            ],
+            &[
+                "DEL key",
+                "UNLINK key",
+                "EXPIRE key 1",
+                "PERSIST key",
+                "RENAME a b",
+                "RESTORE key 0 data",
+                "SORT key STORE dst",
+                "COPY a b",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_read_only_hash_cmds() {
+        check_read_only(
+            &[
+                "HGET h f",
+                "HMGET h f1 f2",
+                "HGETALL h",
+                "HKEYS h",
+                "HVALS h",
+                "HLEN h",
+                "HEXISTS h f",
+                "HSTRLEN h f",
+                "HSCAN h 0",
+                "HRANDFIELD h",
+                "HTTL h FIELDS 1 f",
+            ],
+            &[
+                "HSET h f v",
+                "HMSET h f v",
+                "HDEL h f",
+                "HINCRBY h f 1",
+                "HGETDEL h FIELDS 1 f",
+                "HGETEX h EX 1 FIELDS 1 f",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_read_only_list_cmds() {
+        check_read_only(
+            &["LRANGE l 0 -1", "LINDEX l 0", "LLEN l", "LPOS l e"],
+            &[
+                "LPUSH l e",
+                "RPOP l",
+                "LSET l 0 e",
+                "LTRIM l 0 1",
+                "LMOVE a b LEFT RIGHT",
+                "BLPOP l 0",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_read_only_set_cmds() {
+        check_read_only(
+            &[
+                "SMEMBERS s",
+                "SCARD s",
+                "SISMEMBER s m",
+                "SMISMEMBER s m1 m2",
+                "SRANDMEMBER s",
+                "SSCAN s 0",
+                "SINTER a b",
+                "SUNION a b",
+                "SDIFF a b",
+                "SINTERCARD 2 a b",
+            ],
+            &[
+                "SADD s m",
+                "SREM s m",
+                "SPOP s",
+                "SMOVE a b m",
+                "SINTERSTORE dst a b",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_read_only_sorted_set_cmds() {
+        check_read_only(
+            &[
+                "ZRANGE z 0 -1",
+                "ZRANGEBYSCORE z 0 1",
+                "ZREVRANGE z 0 -1",
+                "ZCARD z",
+                "ZCOUNT z 0 1",
+                "ZSCORE z m",
+                "ZMSCORE z m1 m2",
+                "ZRANK z m",
+                "ZSCAN z 0",
+                "ZRANDMEMBER z",
+                "ZUNION 2 a b",
+            ],
+            &[
+                "ZADD z 1 m",
+                "ZINCRBY z 1 m",
+                "ZREM z m",
+                "ZPOPMIN z",
+                "ZRANGESTORE dst z 0 -1",
+                "ZUNIONSTORE dst 2 a b",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_read_only_other_cmds() {
+        check_read_only(
+            &[
+                "XLEN s",
+                "XRANGE s - +",
+                "XREAD STREAMS s 0",
+                "XPENDING s g",
+                "PFCOUNT a b",
+                "GEOPOS g m",
+                "GEOSEARCH g FROMMEMBER m BYRADIUS 1 km",
+                "GEORADIUS_RO g 0 0 1 km",
+                "EVAL_RO script 1 key",
+                "FCALL_RO f 1 key",
+            ],
+            &[
+                "XADD s * f v",
+                "XREADGROUP GROUP g c STREAMS s >",
+                "XACK s g 0-1",
+                "PFADD h e",
+                "PFMERGE dst a b",
+                "GEOADD g 0 0 m",
+                "GEORADIUS g 0 0 1 km",
+                "GEOSEARCHSTORE dst g FROMMEMBER m BYRADIUS 1 km",
+                "EVAL script 1 key",
+                "PUBLISH channel msg",
+                "UNKNOWNCMD key",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut cmd = gen_cmd("secret_keys a");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::Others);
+        assert!(cmd.rename("KEYS"));
+        assert_eq!(cmd.get_command_name(), Some("KEYS"));
+        assert_eq!(cmd.get_command_element(1), Some(b"a".as_ref()));
+
+        let mut cmd = gen_cmd("my_get somekey");
+        assert!(cmd.rename("GET"));
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::GET);
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"somekey")));
+    }
+
+    #[test]
+    fn test_geo_store_keys() {
+        let cmd = gen_cmd("GEOSEARCHSTORE dst src FROMMEMBER m BYRADIUS 1 km");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::GEOSEARCHSTORE);
+        assert_eq!(cmd.get_geo_store_keys(), Some((&b"src"[..], &b"dst"[..])));
+
+        let cmd = gen_cmd("georadius src 15 37 200 km store dst");
+        assert_eq!(cmd.get_geo_store_keys(), Some((&b"src"[..], &b"dst"[..])));
+
+        let cmd = gen_cmd("GEORADIUSBYMEMBER src m 200 km COUNT 2 STOREDIST dst");
+        assert_eq!(cmd.get_geo_store_keys(), Some((&b"src"[..], &b"dst"[..])));
+
+        let cmd = gen_cmd("GEORADIUS src 15 37 200 km STORE dst1 STOREDIST dst2");
+        assert_eq!(cmd.get_geo_store_keys(), Some((&b"src"[..], &b"dst2"[..])));
+        let cmd = gen_cmd("GEORADIUS src 15 37 200 km STORE store STORE dst");
+        assert_eq!(cmd.get_geo_store_keys(), Some((&b"src"[..], &b"dst"[..])));
+
+        let cmd = gen_cmd("GEORADIUS src 15 37 200 km WITHDIST");
+        assert_eq!(cmd.get_geo_store_keys(), None);
+        let cmd = gen_cmd("GEORADIUS src 15 37 200 km STORE");
+        assert_eq!(cmd.get_geo_store_keys(), None);
+        let cmd = gen_cmd("GEOADD src 15 37 m");
+        assert_eq!(cmd.get_geo_store_keys(), None);
+    }
+
+    #[test]
+    fn test_admin_cmd() {
+        assert!(gen_cmd("UMCTL SETCLUSTER").is_admin_cmd());
+        assert!(gen_cmd("config set slowlog_sample_rate 1").is_admin_cmd());
+        assert!(gen_cmd("CLIENT kill 127.0.0.1:6000").is_admin_cmd());
+        assert!(!gen_cmd("CLIENT NO-EVICT on").is_admin_cmd());
+        assert!(!gen_cmd("UMFORWARD 1 GET key").is_admin_cmd());
+        assert!(!gen_cmd("GET key").is_admin_cmd());
+    }
+
+    #[test]
+    fn test_numkeys_keys() {
+        let cmd = gen_cmd("SINTERCARD 2 {a}1 {a}2 LIMIT 10");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::SINTERCARD);
+        assert_eq!(cmd.get_key(), Some(&b"{a}1"[..]));
+        assert_eq!(
+            cmd.get_numkeys_keys(1),
+            Ok(vec![&b"{a}1"[..], &b"{a}2"[..]])
+        );
+
+        // LIMIT should not be treated as a key.
+        let cmd = gen_cmd("sintercard 1 key LIMIT 10");
+        assert_eq!(cmd.get_numkeys_keys(1), Ok(vec![&b"key"[..]]));
+        let cmd = gen_cmd("SINTERCARD 3 k1 k2");
+        assert!(cmd.get_numkeys_keys(1).is_err());
+        let cmd = gen_cmd("SINTERCARD 0 k1");
+        assert!(cmd.get_numkeys_keys(1).is_err());
+        let cmd = gen_cmd("SINTERCARD abc k1");
+        assert!(cmd.get_numkeys_keys(1).is_err());
+
+        let cmd = gen_cmd("ZMPOP 2 {a}1 {a}2 MIN COUNT 3");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::ZMPOP);
+        assert_eq!(cmd.get_key(), Some(&b"{a}1"[..]));
+        assert_eq!(
+            cmd.get_numkeys_keys(1),
+            Ok(vec![&b"{a}1"[..], &b"{a}2"[..]])
+        );
+        // MIN should not be treated as a key.
+        let cmd = gen_cmd("zmpop 1 key MIN");
+        assert_eq!(cmd.get_numkeys_keys(1), Ok(vec![&b"key"[..]]));
+        let cmd = gen_cmd("ZMPOP 2 key MIN");
+        assert!(cmd.get_numkeys_keys(1).is_ok());
+        let cmd = gen_cmd("ZMPOP 3 key MIN");
+        assert!(cmd.get_numkeys_keys(1).is_err());
+
+        let cmd = gen_cmd("LMPOP 2 k1 k2 LEFT");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::LMPOP);
+        assert_eq!(cmd.get_key(), Some(&b"k1"[..]));
+        assert_eq!(cmd.get_numkeys_keys(1), Ok(vec![&b"k1"[..], &b"k2"[..]]));
+    }
+
+    #[test]
+    fn test_smove_keys() {
+        let cmd = gen_cmd("SMOVE src dst member");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::SMOVE);
+        assert_eq!(cmd.get_key(), Some(&b"src"[..]));
+        assert_eq!(cmd.get_smove_keys(), Some((&b"src"[..], &b"dst"[..])));
+
+        assert_eq!(gen_cmd("SMOVE src").get_smove_keys(), None);
+        assert_eq!(gen_cmd("SREM src dst").get_smove_keys(), None);
+
+        let cmd = gen_cmd("SMISMEMBER myset m1 m2");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::SMISMEMBER);
+        assert_eq!(cmd.get_key(), Some(&b"myset"[..]));
+        assert!(cmd.is_read_only());
+    }
+
+    #[test]
+    fn test_bitfield() {
+        let cmd = gen_cmd("BITFIELD key OVERFLOW SAT SET u8 0 200 INCRBY u8 0 100 GET u4 0");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::BITFIELD);
+        assert_eq!(cmd.get_key(), Some(&b"key"[..]));
+        assert!(!cmd.is_bitfield_get_only());
+        assert!(cmd.is_keyed_write());
+
+        let cmd = gen_cmd("bitfield key GET u8 0 OVERFLOW FAIL GET i4 8");
+        assert!(cmd.is_bitfield_get_only());
+        assert!(!cmd.is_keyed_write());
+        assert!(!gen_cmd("BITFIELD key GET u8").is_bitfield_get_only());
+
+        let cmd = gen_cmd("BITFIELD_RO key GET u8 0");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::BITFIELD_RO);
+        assert_eq!(cmd.get_key(), Some(&b"key"[..]));
+        assert!(cmd.is_read_only());
+    }
+
+    #[test]
+    fn test_lcs_keys() {
+        let cmd = gen_cmd("LCS key1 key2 IDX MINMATCHLEN 4 WITHMATCHLEN");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::LCS);
+        assert_eq!(cmd.get_key(), Some(&b"key1"[..]));
+        assert_eq!(cmd.get_lcs_keys(), Some((&b"key1"[..], &b"key2"[..])));
+        assert_eq!(cmd.get_all_keys(), vec![&b"key1"[..], &b"key2"[..]]);
+        assert!(cmd.is_read_only());
+
+        assert_eq!(gen_cmd("LCS key1").get_lcs_keys(), None);
+        assert_eq!(gen_cmd("SMOVE src dst m").get_lcs_keys(), None);
+    }
+
+    #[test]
+    fn test_hyperloglog_keys() {
+        let cmd = gen_cmd("PFMERGE dst src1 src2");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::PFMERGE);
+        assert_eq!(cmd.get_key(), Some(&b"dst"[..]));
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"dst")));
+        assert_eq!(
+            cmd.get_all_keys(),
+            vec![&b"dst"[..], &b"src1"[..], &b"src2"[..]]
+        );
+        assert!(cmd.is_keyed_write());
+
+        let cmd = gen_cmd("PFCOUNT a b");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::PFCOUNT);
+        assert_eq!(cmd.get_all_keys(), vec![&b"a"[..], &b"b"[..]]);
+        assert!(cmd.is_read_only());
+
+        let cmd = gen_cmd("PFADD hll e1 e2");
+        assert_eq!(cmd.get_data_cmd_type(), DataCmdType::PFADD);
+        assert_eq!(cmd.get_all_keys(), vec![&b"hll"[..]]);
+        assert!(cmd.is_keyed_write());
+    }
 }
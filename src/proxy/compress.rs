@@ -66,6 +66,7 @@ impl<C: CompressionStrategyConfig> CmdCompressor<C> {
             DataCmdType::APPEND
             | DataCmdType::BITCOUNT
             | DataCmdType::BITFIELD
+            | DataCmdType::BITFIELD_RO
             | DataCmdType::BITOP
             | DataCmdType::BITPOS
             | DataCmdType::DECR
@@ -139,7 +140,10 @@ impl<C: CompressionStrategyConfig> CmdReplyDecompressor<C> {
 
         let data_cmd_type = cmd_ctx.get_data_cmd_type();
         match data_cmd_type {
-            DataCmdType::GET | DataCmdType::GETSET => {
+            DataCmdType::SET if !cmd_ctx.get_cmd().has_set_get_option() => {
+                Err(CompressionError::UnsupportedCmdType)
+            }
+            DataCmdType::GET | DataCmdType::GETSET | DataCmdType::SET => {
                 let compressed = if let Resp::Bulk(BulkStr::Str(s)) = packet.to_resp_slice() {
                     let compressed = match zstd::decode_all(s) {
                         Ok(c) => c,
@@ -328,6 +332,55 @@ mod tests {
         assert_eq!(v, b"value");
     }
 
+    #[test]
+    fn test_enabled_for_set_with_get_option() {
+        let config = DummyConfig {
+            strategy: CompressionStrategy::SetGetOnly,
+        };
+        let compressor = CmdCompressor::new(config);
+        let mut cmd_ctx = gen_cmd_ctx(vec![
+            "SET".to_string(),
+            "key".to_string(),
+            "value".to_string(),
+            "GET".to_string(),
+        ]);
+        compressor.try_compressing_cmd_ctx(&mut cmd_ctx).unwrap();
+        let comppressed_value = cmd_ctx.get_cmd().get_command_element(2).unwrap().to_vec();
+
+        let config = DummyConfig {
+            strategy: CompressionStrategy::SetGetOnly,
+        };
+        let decompressor = CmdReplyDecompressor::new(config);
+        let mut reply_packet = gen_bulk_str_packet(comppressed_value);
+        decompressor
+            .decompress(&cmd_ctx, &mut reply_packet)
+            .unwrap();
+        let v = match reply_packet {
+            RespPacket::Data(Resp::Bulk(BulkStr::Str(v))) => v,
+            _ => panic!(),
+        };
+        assert_eq!(v, b"value");
+
+        let mut nil_packet = RespPacket::Data(Resp::Bulk(BulkStr::Nil));
+        decompressor.decompress(&cmd_ctx, &mut nil_packet).unwrap();
+        assert!(matches!(
+            nil_packet,
+            RespPacket::Data(Resp::Bulk(BulkStr::Nil))
+        ));
+
+        // Plain SET replies OK and is left untouched.
+        let cmd_ctx = gen_cmd_ctx(vec![
+            "SET".to_string(),
+            "key".to_string(),
+            "value".to_string(),
+        ]);
+        let mut ok_packet = RespPacket::Data(Resp::Simple(b"OK".to_vec()));
+        let err = decompressor
+            .decompress(&cmd_ctx, &mut ok_packet)
+            .unwrap_err();
+        assert!(matches!(err, CompressionError::UnsupportedCmdType));
+    }
+
     #[test]
     fn test_enabled_for_mset_mget() {
         let (comppressed_value_one, comppressed_value_two) = {
@@ -0,0 +1,248 @@
+use super::cluster::ClusterTag;
+use super::command::Command;
+use super::service::ServerProxyConfig;
+use super::session::CmdCtx;
+use crate::common::cluster::ClusterName;
+use crate::common::utils::ThreadSafe;
+use chrono::Utc;
+use std::sync::{Arc, Mutex};
+
+// Large keys are truncated.
+const MAX_ARG_LEN: usize = 128;
+const MAX_ARG_NUM: usize = 32;
+
+pub const DEADLETTER_SINK_LOG: &str = "log";
+
+// A command which could not be delivered to any backend
+// and only got an error reply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    // In seconds.
+    pub timestamp: i64,
+    pub cluster_name: ClusterName,
+    pub command: Vec<String>,
+    pub reason: String,
+}
+
+pub trait DeadLetterSink: ThreadSafe {
+    fn put(&self, letter: DeadLetter);
+}
+
+// Writes the dead letters to the log with the target `deadletter`.
+pub struct LogDeadLetterSink;
+
+impl DeadLetterSink for LogDeadLetterSink {
+    fn put(&self, letter: DeadLetter) {
+        warn!(
+            target: "deadletter",
+            "dropped command: cluster={} reason={} command={}",
+            letter.cluster_name,
+            letter.reason,
+            letter.command.join(" ")
+        );
+    }
+}
+
+// Disabled by default.
+#[derive(Default)]
+pub struct DeadLetters {
+    sink: Option<Box<dyn DeadLetterSink>>,
+}
+
+impl DeadLetters {
+    pub fn new(sink: Box<dyn DeadLetterSink>) -> Self {
+        Self { sink: Some(sink) }
+    }
+
+    pub fn from_config(config: &ServerProxyConfig) -> Self {
+        match config.deadletter_sink.as_str() {
+            DEADLETTER_SINK_LOG => Self::new(Box::new(LogDeadLetterSink)),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    // The command is consumed by sending so `cmd_ctx` keeps a slot
+    // to be filled with the command when it gets an error reply.
+    // Nothing is copied when the sink is disabled or the command succeeds.
+    pub fn draft(&self, cmd_ctx: &mut CmdCtx) -> DeadLetterDraft {
+        let slot = if self.is_enabled() {
+            let slot = Arc::new(DeadLetterSlot::default());
+            cmd_ctx.set_dead_letter_slot(slot.clone());
+            Some((cmd_ctx.get_cluster_name().clone(), slot))
+        } else {
+            None
+        };
+        DeadLetterDraft {
+            dead_letters: self,
+            slot,
+        }
+    }
+
+    fn put(&self, cluster_name: &ClusterName, command: Vec<String>, reason: String) {
+        if let Some(sink) = self.sink.as_ref() {
+            sink.put(DeadLetter {
+                timestamp: Utc::now().timestamp(),
+                cluster_name: cluster_name.clone(),
+                command,
+                reason,
+            });
+        }
+    }
+}
+
+// Filled by the command on the error reply.
+#[derive(Debug, Default)]
+pub struct DeadLetterSlot {
+    command: Mutex<Option<Vec<String>>>,
+}
+
+impl DeadLetterSlot {
+    pub fn fill(&self, cmd: &Command) {
+        let mut command = self.command.lock().expect("DeadLetterSlot::fill");
+        if command.is_none() {
+            *command = Some(format_command(cmd));
+        }
+    }
+
+    fn take(&self) -> Option<Vec<String>> {
+        self.command.lock().expect("DeadLetterSlot::take").take()
+    }
+}
+
+pub struct DeadLetterDraft<'a> {
+    dead_letters: &'a DeadLetters,
+    slot: Option<(ClusterName, Arc<DeadLetterSlot>)>,
+}
+
+impl<'a> DeadLetterDraft<'a> {
+    pub fn put(&self, reason: String) {
+        if let Some((cluster_name, slot)) = self.slot.as_ref() {
+            // Empty if the command is dropped without a reply.
+            let command = slot.take().unwrap_or_default();
+            self.dead_letters.put(cluster_name, command, reason);
+        }
+    }
+}
+
+// Only the command name and the keys are kept. The values are redacted.
+fn format_command(cmd: &Command) -> Vec<String> {
+    let len = cmd.get_command_len().unwrap_or(0);
+    let keys = cmd.get_all_keys();
+    let is_key = |arg: &[u8]| keys.iter().any(|key| std::ptr::eq(*key, arg));
+    let mut command: Vec<String> = (0..len.min(MAX_ARG_NUM))
+        .filter_map(|i| cmd.get_command_element(i).map(|arg| (i, arg)))
+        .map(|(i, arg)| {
+            if i != 0 && !is_key(arg) {
+                return format!("<redacted {} bytes>", arg.len());
+            }
+            let s = String::from_utf8_lossy(&arg[..arg.len().min(MAX_ARG_LEN)]);
+            if arg.len() > MAX_ARG_LEN {
+                format!("{}...({} bytes)", s, arg.len())
+            } else {
+                s.to_string()
+            }
+        })
+        .collect();
+    if len > MAX_ARG_NUM {
+        command.push(format!("...({} more arguments)", len - MAX_ARG_NUM));
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Array, BulkStr, Resp, RespPacket};
+    use crate::test_support::{gen_test_config, FakeBackendFunc, TestProxy};
+    use tokio;
+
+    #[derive(Clone, Default)]
+    struct VecDeadLetterSink {
+        letters: Arc<Mutex<Vec<DeadLetter>>>,
+    }
+
+    impl DeadLetterSink for VecDeadLetterSink {
+        fn put(&self, letter: DeadLetter) {
+            self.letters.lock().unwrap().push(letter);
+        }
+    }
+
+    fn gen_cmd(args: Vec<Vec<u8>>) -> Command {
+        let args = args
+            .into_iter()
+            .map(|arg| Resp::Bulk(BulkStr::Str(arg)))
+            .collect();
+        let packet = RespPacket::from_resp_vec(Resp::Arr(Array::Arr(args)));
+        Command::new(Box::new(packet))
+    }
+
+    #[test]
+    fn test_format_command() {
+        let cmd = gen_cmd(vec![b"SET".to_vec(), b"k".to_vec(), vec![b'v'; 200]]);
+        assert_eq!(
+            format_command(&cmd),
+            vec!["SET", "k", "<redacted 200 bytes>"]
+        );
+
+        let cmd = gen_cmd(vec![
+            b"MSET".to_vec(),
+            b"k1".to_vec(),
+            b"v1".to_vec(),
+            vec![b'k'; 200],
+            b"v2".to_vec(),
+        ]);
+        let command = format_command(&cmd);
+        assert_eq!(command.len(), 5);
+        assert_eq!(command[..3], ["MSET", "k1", "<redacted 2 bytes>"]);
+        assert!(command[3].starts_with(&"k".repeat(MAX_ARG_LEN)));
+        assert!(command[3].ends_with("...(200 bytes)"));
+        assert_eq!(command[4], "<redacted 2 bytes>");
+
+        let mut args = vec![b"DEL".to_vec()];
+        args.extend((0..40).map(|i| i.to_string().into_bytes()));
+        let command = format_command(&gen_cmd(args));
+        assert_eq!(command.len(), MAX_ARG_NUM + 1);
+        assert_eq!(command[MAX_ARG_NUM - 1], "30");
+        assert_eq!(command[MAX_ARG_NUM], "...(9 more arguments)");
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let sink = VecDeadLetterSink::default();
+        let dead_letters = Arc::new(DeadLetters::new(Box::new(sink.clone())));
+        let proxy = TestProxy::with_dead_letters(gen_test_config(), backend, dead_letters);
+        let mut client = proxy.connect();
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-8000",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client.send_when_ready(&["SET", "b", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        assert!(sink.letters.lock().unwrap().is_empty());
+
+        // The slot 15495 of "a" is not covered by any node.
+        let reply = client.send(&["SET", "a", "secret"]).await;
+        matches::assert_matches!(reply, Resp::Error(_));
+        let letters = sink.letters.lock().unwrap().clone();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].cluster_name.to_string(), "mydb");
+        assert_eq!(letters[0].command, vec!["SET", "a", "<redacted 6 bytes>"]);
+        assert!(letters[0].reason.contains("SlotNotCovered"));
+    }
+}
@@ -1,33 +1,186 @@
 use super::backend::{CmdTask, CmdTaskFactory, ConnFactory};
-use super::cluster::{ClusterMetaError, ClusterTag};
-use super::command::{CmdReplyReceiver, CmdType, DataCmdType, TaskResult};
+use super::cluster::{ClusterMetaError, ClusterTag, DEFAULT_CLUSTER};
+use super::command::{
+    is_debug_broadcast_sub_command, parse_command_getkeys, parse_hello, parse_info_sections,
+    parse_migrate, CmdReplyReceiver, CmdType, Command, DataCmdType, MigrateArgs, TaskResult,
+};
 use super::compress::{CmdCompressor, CompressionError, CompressionStrategyMetaMapConfig};
+use super::deadletter::DeadLetters;
+use super::latency::{latest_to_resp, samples_to_resp, LatencyEvent};
 use super::manager::{MetaManager, SharedMetaMap};
+use super::memory::MemoryBudget;
+use super::monitor::CommandMonitor;
+use super::pause::{parse_client_pause, ClientPause};
+use super::pubsub::gen_replies_packet;
+use super::readcache::ReadCache;
 use super::service::ServerProxyConfig;
-use super::session::{CmdCtx, CmdCtxFactory, CmdCtxHandler, CmdReplyFuture};
+use super::session::{CmdCtx, CmdCtxFactory, CmdCtxHandler, CmdReplyFuture, SessionState};
 use super::slowlog::{slowlogs_to_resp, SlowRequestLogger};
+use super::traffic::TrafficStats;
 use crate::common::cluster::ClusterName;
 use crate::common::config::ClusterConfig;
 use crate::common::proto::ProxyClusterMeta;
 use crate::common::response;
 use crate::common::track::TrackedFutureRegistry;
 use crate::common::utils::{
-    change_bulk_array_element, generate_slot, same_slot, str_ascii_case_insensitive_eq,
+    bytes_ascii_case_insensitive_eq, change_bulk_array_element, gen_moved, generate_slot,
+    group_by_slot, pretty_print_bytes, same_slot, str_ascii_case_insensitive_eq, SLOT_NUM,
 };
 use crate::common::version::UNDERMOON_VERSION;
 use crate::migration::manager::SwitchError;
+use crate::migration::scan_migration::{pttl_to_restore_expire_time, PTTL_KEY_NOT_FOUND};
 use crate::migration::task::parse_switch_command;
 use crate::migration::task::MgrSubCmd;
-use crate::protocol::{Array, BulkStr, RedisClientFactory, Resp, RespPacket, RespVec, VFunctor};
+use crate::protocol::{
+    Array, BinSafeStr, BulkStr, RedisClient, RedisClientFactory, Resp, RespPacket, RespVec,
+    VFunctor,
+};
 use crate::replication::replicator::ReplicatorMeta;
 use atoi::atoi;
 use btoi::btou;
-use futures::future;
+use futures::future::{self, BoxFuture};
+use futures::{Future, FutureExt};
 use futures_timer::Delay;
 use std::convert::TryFrom;
 use std::str;
 use std::sync::{self, Arc};
 use std::time::Duration;
+use tokio::time;
+
+const CLIENT_SUB_COMMANDS: &[&str] = &[
+    "DURABLE", "GETNAME", "HELP", "INFO", "LIST", "NO-EVICT", "NO-TOUCH", "PAUSE", "SETNAME",
+    "UNPAUSE",
+];
+const LATENCY_SUB_COMMANDS: &[&str] = &["HISTORY", "LATEST", "RESET"];
+// The cluster topology is managed by the broker and coordinator.
+const CLUSTER_ADMIN_SUB_COMMANDS: &[&str] = &[
+    "ADDSLOTS",
+    "ADDSLOTSRANGE",
+    "BUMPEPOCH",
+    "DELSLOTS",
+    "DELSLOTSRANGE",
+    "FAILOVER",
+    "FLUSHSLOTS",
+    "FORGET",
+    "MEET",
+    "REPLICATE",
+    "RESET",
+    "SAVECONFIG",
+    "SET-CONFIG-EPOCH",
+    "SETSLOT",
+];
+
+// The lines of the HELP subcommands except the first and the last ones.
+const CLIENT_HELP: &[&str] = &[
+    "DURABLE (ON|OFF)",
+    "    Follow each write with an implicit `WAIT 1 <durable_write_timeout>`.",
+    "GETNAME",
+    "    Return the name of the current connection.",
+    "INFO",
+    "    Return information about the current client connection.",
+    "LIST",
+    "    Return information about the client connections of this proxy.",
+    "NO-EVICT (ON|OFF)",
+    "    Only recorded since the backend connections are shared.",
+    "NO-TOUCH (ON|OFF)",
+    "    Only recorded since the backend connections are shared.",
+    "PAUSE <timeout> [WRITE|ALL]",
+    "    Hold the data commands of all the clients, or only the writes, for <timeout> milliseconds.",
+    "SETNAME <name>",
+    "    Assign the name to the current connection, which could also select the cluster.",
+    "UNPAUSE",
+    "    Release the commands held by CLIENT PAUSE.",
+];
+const CONFIG_HELP: &[&str] = &[
+    "GET <field>",
+    "    Return the value of the server proxy config field.",
+    "SET <field> <value>",
+    "    Change the value of the mutable server proxy config field.",
+];
+const CLUSTER_HELP: &[&str] = &[
+    "COUNTKEYSINSLOT <slot>",
+    "    Return the number of keys in <slot> from the backend owning it.",
+    "GETKEYSINSLOT <slot> <count>",
+    "    Return at most <count> keys in <slot> from the backend owning it.",
+    "INFO",
+    "    Return information about the cluster.",
+    "KEYSLOT <key>",
+    "    Return the hash slot for <key>.",
+    "NODES",
+    "    Return the cluster configuration seen by the proxy.",
+    "SLOTS",
+    "    Return the mapping of the hash slots to the proxies.",
+];
+const UMCTL_HELP: &[&str] = &[
+    "CHECKTOPO",
+    "    Check whether the topology of the clusters is consistent.",
+    "DEBUG FUTURE",
+    "    Return the running futures.",
+    "FLUSHCACHE",
+    "    Recreate the backend connections and rebuild the routing from the current metadata.",
+    "GETEPOCH",
+    "    Return the epoch of the metadata.",
+    "INFO",
+    "    Return the metadata of the proxy.",
+    "INFOMGR",
+    "    Return the finished migration tasks.",
+    "INFOPROGRESS",
+    "    Return the progress of the migrating tasks.",
+    "INFOREPL",
+    "    Return the replication metadata.",
+    "LISTCLUSTER",
+    "    Return the names of the clusters.",
+    "PRECHECK|PRESWITCH|FINALSWITCH <migration args>",
+    "    Sent between the proxies during migration.",
+    "SETCLUSTER <cluster args>",
+    "    Set the metadata of the clusters. Sent by the coordinator.",
+    "SETREPL <replication args>",
+    "    Set the replication metadata. Sent by the coordinator.",
+    "SLOWLOG (GET [<count>]|RESET|HELP)",
+    "    Return or remove the slow logs of the proxy.",
+];
+const ACL_HELP: &[&str] = &[
+    "CAT",
+    "    List the command categories.",
+    "GETUSER <username>",
+    "    Get the rules of the user. Only the user \"default\" exists.",
+    "LIST",
+    "    Show the rules of the users.",
+    "USERS",
+    "    List the usernames.",
+    "WHOAMI",
+    "    Return the username of AUTH or HELLO, or \"default\" if none.",
+];
+const ACL_DEFAULT_USER: &str = "default";
+const ACL_CATEGORIES: [&str; 21] = [
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+const UMCTL_SLOWLOG_HELP: &[&str] = &[
+    "GET [<count>]",
+    "    Return the latest <count> slow logs, or all of them.",
+    "RESET",
+    "    Remove all the slow logs.",
+];
 
 pub struct SharedForwardHandler<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> {
     handler: sync::Arc<ForwardHandler<F, C>>,
@@ -50,14 +203,18 @@ where
     F: RedisClientFactory,
     C: ConnFactory<Pkt = RespPacket>,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: Arc<ServerProxyConfig>,
         cluster_config: ClusterConfig,
         client_factory: Arc<F>,
         slow_request_logger: Arc<SlowRequestLogger>,
+        memory_budget: Arc<MemoryBudget>,
+        traffic_stats: Arc<TrafficStats>,
         meta_map: SharedMetaMap<C>,
         conn_factory: Arc<C>,
         future_registry: Arc<TrackedFutureRegistry>,
+        dead_letters: Arc<DeadLetters>,
     ) -> Self {
         Self {
             handler: sync::Arc::new(ForwardHandler::new(
@@ -65,9 +222,12 @@ where
                 cluster_config,
                 client_factory,
                 slow_request_logger,
+                memory_budget,
+                traffic_stats,
                 meta_map,
                 conn_factory,
                 future_registry,
+                dead_letters,
             )),
         }
     }
@@ -82,10 +242,26 @@ where
         &self,
         cmd_ctx: CmdCtx,
         reply_receiver: CmdReplyReceiver,
-        session_cluster_name: &sync::RwLock<ClusterName>,
+        session_state: &SessionState,
     ) -> CmdReplyFuture {
         self.handler
-            .handle_cmd_ctx(cmd_ctx, reply_receiver, session_cluster_name)
+            .handle_cmd_ctx(cmd_ctx, reply_receiver, session_state)
+    }
+
+    fn get_slot_node(&self, cluster_name: &ClusterName, slot: usize) -> Option<String> {
+        self.handler.get_slot_node(cluster_name, slot)
+    }
+
+    fn get_slot_owner(&self, cluster_name: &ClusterName, slot: usize) -> Option<String> {
+        self.handler.get_slot_owner(cluster_name, slot)
+    }
+
+    fn get_client_pause(&self) -> Option<&ClientPause> {
+        self.handler.get_client_pause()
+    }
+
+    fn get_command_monitor(&self) -> Option<&CommandMonitor> {
+        self.handler.get_command_monitor()
     }
 }
 
@@ -93,8 +269,17 @@ pub struct ForwardHandler<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket
     config: Arc<ServerProxyConfig>,
     manager: MetaManager<F, C>,
     slow_request_logger: Arc<SlowRequestLogger>,
+    memory_budget: Arc<MemoryBudget>,
+    traffic_stats: Arc<TrafficStats>,
     compressor: CmdCompressor<CompressionStrategyMetaMapConfig<C>>,
     future_registry: Arc<TrackedFutureRegistry>,
+    // For the dedicated connections of SSUBSCRIBE.
+    conn_factory: Arc<C>,
+    read_cache: ReadCache,
+    client_pause: ClientPause,
+    command_monitor: CommandMonitor,
+    // For MIGRATE.
+    client_factory: Arc<F>,
 }
 
 impl<F, C> ForwardHandler<F, C>
@@ -102,28 +287,42 @@ where
     F: RedisClientFactory,
     C: ConnFactory<Pkt = RespPacket>,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: Arc<ServerProxyConfig>,
         cluster_config: ClusterConfig,
         client_factory: Arc<F>,
         slow_request_logger: Arc<SlowRequestLogger>,
+        memory_budget: Arc<MemoryBudget>,
+        traffic_stats: Arc<TrafficStats>,
         meta_map: SharedMetaMap<C>,
         conn_factory: Arc<C>,
         future_registry: Arc<TrackedFutureRegistry>,
+        dead_letters: Arc<DeadLetters>,
     ) -> Self {
+        let read_cache = ReadCache::from_config(&config);
         Self {
             config: config.clone(),
             manager: MetaManager::new(
                 config,
                 cluster_config,
-                client_factory,
-                conn_factory,
+                client_factory.clone(),
+                conn_factory.clone(),
                 meta_map.clone(),
                 future_registry.clone(),
+                dead_letters,
+                memory_budget.clone(),
             ),
             slow_request_logger,
+            memory_budget,
+            traffic_stats,
             compressor: CmdCompressor::new(CompressionStrategyMetaMapConfig::new(meta_map)),
             future_registry,
+            conn_factory,
+            read_cache,
+            client_pause: ClientPause::default(),
+            command_monitor: CommandMonitor::default(),
+            client_factory,
         }
     }
 }
@@ -133,9 +332,23 @@ where
     F: RedisClientFactory,
     C: ConnFactory<Pkt = RespPacket>,
 {
-    fn handle_auth(&self, mut cmd_ctx: CmdCtx, session_cluster_name: &sync::RwLock<ClusterName>) {
-        let key = cmd_ctx.get_key();
-        let cluster = match key {
+    // AUTH [username] password. The password is the cluster name.
+    fn handle_auth(&self, mut cmd_ctx: CmdCtx, session_state: &SessionState) {
+        let cmd = cmd_ctx.get_cmd();
+        let (username, password) = match cmd.get_command_element(2) {
+            Some(password) => (cmd.get_command_element(1), Some(password)),
+            None => (None, cmd.get_command_element(1)),
+        };
+        let username = match username.map(str::from_utf8) {
+            Some(Ok(username)) => Some(username.to_string()),
+            Some(Err(_)) => {
+                return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                    String::from("Invalid username").into_bytes(),
+                )))
+            }
+            None => None,
+        };
+        let cluster = match password {
             None => {
                 return cmd_ctx.set_resp_result(Ok(Resp::Error(
                     String::from("Missing cluster name").into_bytes(),
@@ -159,13 +372,292 @@ where
             }
         };
 
-        *session_cluster_name
-            .write()
-            .expect("ForwardHandler::handle_auth") = cluster_name.clone();
+        session_state.set_cluster_name(cluster_name.clone());
+        session_state.set_username(username);
         cmd_ctx.set_cluster_name(cluster_name);
         cmd_ctx.set_resp_result(Ok(Resp::Simple(String::from("OK").into_bytes())));
     }
 
+    fn handle_client(&self, cmd_ctx: CmdCtx, session_state: &SessionState) {
+        let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 1) {
+            Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd),
+            None => return,
+        };
+
+        let sub_cmd = sub_cmd.to_uppercase();
+
+        if sub_cmd.eq("NO-EVICT") || sub_cmd.eq("NO-TOUCH") || sub_cmd.eq("DURABLE") {
+            let enabled = match cmd_ctx.get_cmd().get_command_element(2) {
+                Some(flag) if bytes_ascii_case_insensitive_eq(flag, b"on") => true,
+                Some(flag) if bytes_ascii_case_insensitive_eq(flag, b"off") => false,
+                _ => {
+                    return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                        String::from("ERR syntax error").into_bytes(),
+                    )))
+                }
+            };
+            if sub_cmd.eq("NO-EVICT") {
+                session_state.set_no_evict(enabled);
+            } else if sub_cmd.eq("NO-TOUCH") {
+                session_state.set_no_touch(enabled);
+            } else {
+                session_state.set_durable_writes(enabled);
+            }
+            cmd_ctx.set_resp_result(Ok(Resp::Simple(
+                response::OK_REPLY.to_string().into_bytes(),
+            )));
+        } else if sub_cmd.eq("SETNAME") {
+            let client_name = match cmd_ctx.get_cmd().get_command_element(2) {
+                Some(name) => parse_client_name(name),
+                None => None,
+            };
+            let client_name = match client_name {
+                Some(client_name) => client_name,
+                None => {
+                    return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                        response::ERR_INVALID_CLIENT_NAME.to_string().into_bytes(),
+                    )))
+                }
+            };
+            self.set_client_name(session_state, client_name);
+            cmd_ctx.set_resp_result(Ok(Resp::Simple(
+                response::OK_REPLY.to_string().into_bytes(),
+            )));
+        } else if sub_cmd.eq("GETNAME") {
+            let name = session_state
+                .get_client_name()
+                .map(|name| BulkStr::Str(name.into_bytes()))
+                .unwrap_or(BulkStr::Nil);
+            cmd_ctx.set_resp_result(Ok(Resp::Bulk(name)));
+        } else if sub_cmd.eq("HELP") {
+            cmd_ctx.set_resp_result(Ok(gen_help_reply("CLIENT", CLIENT_HELP)));
+        } else if sub_cmd.eq("INFO") {
+            let info = format!("{}\n", session_state.gen_client_info());
+            cmd_ctx.set_resp_result(Ok(Resp::Bulk(BulkStr::Str(info.into_bytes()))));
+        } else if sub_cmd.eq("LIST") {
+            let list: String = self
+                .traffic_stats
+                .get_clients()
+                .iter()
+                .map(|state| format!("{}\n", state.gen_client_info()))
+                .collect();
+            cmd_ctx.set_resp_result(Ok(Resp::Bulk(BulkStr::Str(list.into_bytes()))));
+        } else if sub_cmd.eq("PAUSE") {
+            let resp = match parse_client_pause(cmd_ctx.get_cmd()) {
+                Ok((mode, timeout)) => {
+                    self.client_pause.pause(mode, timeout);
+                    Resp::Simple(response::OK_REPLY.to_string().into_bytes())
+                }
+                Err(err) => Resp::Error(err.into_bytes()),
+            };
+            cmd_ctx.set_resp_result(Ok(resp));
+        } else if sub_cmd.eq("UNPAUSE") {
+            self.client_pause.unpause();
+            cmd_ctx.set_resp_result(Ok(Resp::Simple(
+                response::OK_REPLY.to_string().into_bytes(),
+            )));
+        } else {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                format!(
+                    "ERR unknown subcommand '{}'. Supported subcommands: {}",
+                    sub_cmd,
+                    CLIENT_SUB_COMMANDS.join(", ")
+                )
+                .into_bytes(),
+            )));
+        }
+    }
+
+    // With multiple clusters, the session still using the default cluster
+    // which does not exist would otherwise get a confusing cluster not found error.
+    fn is_database_unselected(&self, cmd_ctx: &CmdCtx, session_state: &SessionState) -> bool {
+        !session_state.is_cluster_selected()
+            && cmd_ctx.get_cluster_name().as_str() == DEFAULT_CLUSTER
+            && !self.manager.cluster_exists(cmd_ctx.get_cluster_name())
+    }
+
+    fn handle_monitor(&self, cmd_ctx: CmdCtx, session_state: &SessionState) {
+        if !self.config.is_monitor_enabled() {
+            return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_MONITOR_DISABLED.to_string().into_bytes(),
+            )));
+        }
+        warn!(
+            "MONITOR is started by session {}. It slows down all the sessions of the proxy",
+            session_state.get_session_id()
+        );
+        self.command_monitor.add(
+            session_state.get_session_id(),
+            session_state.get_monitor_sender(),
+        );
+        cmd_ctx.set_resp_result(Ok(Resp::Simple(
+            response::OK_REPLY.to_string().into_bytes(),
+        )));
+    }
+
+    fn set_client_name(&self, session_state: &SessionState, client_name: String) {
+        if let Some(cluster_name) = self.config.get_client_name_default_cluster(&client_name) {
+            session_state.set_default_cluster_name(cluster_name);
+        }
+        session_state.set_client_name(Some(client_name));
+    }
+
+    // Only RESP2 is supported. AUTH takes the password as the cluster name like AUTH.
+    // The username is only shown by ACL WHOAMI.
+    fn handle_hello(&self, mut cmd_ctx: CmdCtx, session_state: &SessionState) {
+        let args = match parse_hello(cmd_ctx.get_cmd()) {
+            Ok(args) => args,
+            Err(err) => return cmd_ctx.set_resp_result(Ok(Resp::Error(err.into_bytes()))),
+        };
+        if args.protover.map_or(false, |protover| protover != 2) {
+            return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_NOPROTO.to_string().into_bytes(),
+            )));
+        }
+
+        let auth = match args.auth {
+            Some((username, password)) => match (
+                str::from_utf8(username),
+                str::from_utf8(password)
+                    .ok()
+                    .and_then(|password| ClusterName::try_from(password).ok()),
+            ) {
+                (Ok(username), Some(cluster_name)) => Some((username.to_string(), cluster_name)),
+                _ => {
+                    return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                        String::from("Invalid cluster name").into_bytes(),
+                    )))
+                }
+            },
+            None => None,
+        };
+        let client_name = match args.client_name.map(parse_client_name) {
+            Some(Some(client_name)) => Some(client_name),
+            Some(None) => {
+                return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                    response::ERR_INVALID_CLIENT_NAME.to_string().into_bytes(),
+                )))
+            }
+            None => None,
+        };
+
+        if let Some((username, cluster_name)) = auth {
+            session_state.set_cluster_name(cluster_name.clone());
+            session_state.set_username(Some(username));
+            cmd_ctx.set_cluster_name(cluster_name);
+        }
+        if let Some(client_name) = client_name {
+            self.set_client_name(session_state, client_name);
+        }
+
+        let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.to_string().into_bytes()));
+        let int = |n: usize| Resp::Integer(n.to_string().into_bytes());
+        let session_id = cmd_ctx.get_session_id();
+        cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(vec![
+            bulk("server"),
+            bulk("undermoon"),
+            bulk("version"),
+            bulk(UNDERMOON_VERSION),
+            bulk("proto"),
+            int(2),
+            bulk("id"),
+            int(session_id),
+            bulk("mode"),
+            bulk("cluster"),
+            bulk("role"),
+            bulk("master"),
+            bulk("modules"),
+            Resp::Arr(Array::Arr(vec![])),
+        ]))));
+    }
+
+    fn handle_command(&self, cmd_ctx: CmdCtx) {
+        let is_getkeys = cmd_ctx
+            .get_cmd()
+            .get_command_element(1)
+            .map_or(false, |sub_cmd| sub_cmd.eq_ignore_ascii_case(b"GETKEYS"));
+        if !is_getkeys {
+            cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(vec![]))));
+            return;
+        }
+
+        let mut cmd = match parse_command_getkeys(cmd_ctx.get_cmd()) {
+            Some(cmd) => cmd,
+            None => {
+                return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                    String::from("ERR wrong number of arguments for 'command|getkeys' command")
+                        .into_bytes(),
+                )))
+            }
+        };
+        self.config.apply_key_spec(&mut cmd);
+        let keys: Vec<RespVec> = cmd
+            .get_all_keys()
+            .into_iter()
+            .map(|key| Resp::Bulk(BulkStr::Str(key.to_vec())))
+            .collect();
+        if keys.is_empty() {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_NO_KEY_ARGUMENTS.to_string().into_bytes(),
+            )))
+        } else {
+            cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(keys))))
+        }
+    }
+
+    fn handle_latency(&self, cmd_ctx: CmdCtx) {
+        let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 1) {
+            Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd),
+            None => return,
+        };
+
+        let sub_cmd = sub_cmd.to_uppercase();
+        let monitor = self.slow_request_logger.get_latency_monitor();
+
+        if sub_cmd.eq("LATEST") {
+            cmd_ctx.set_resp_result(Ok(latest_to_resp(monitor.get_latest())));
+        } else if sub_cmd.eq("HISTORY") {
+            let samples = match cmd_ctx.get_cmd().get_command_element(2) {
+                Some(name) => str::from_utf8(name)
+                    .ok()
+                    .and_then(LatencyEvent::from_name)
+                    .map(|event| monitor.get_samples(event))
+                    .unwrap_or_else(Vec::new),
+                None => {
+                    return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                        String::from("ERR wrong number of arguments for 'latency|history' command")
+                            .into_bytes(),
+                    )))
+                }
+            };
+            cmd_ctx.set_resp_result(Ok(samples_to_resp(samples)));
+        } else if sub_cmd.eq("RESET") {
+            let cmd = cmd_ctx.get_cmd();
+            let arg_num = cmd.get_command_len().unwrap_or(0);
+            let events: Vec<LatencyEvent> = (2..arg_num)
+                .filter_map(|i| cmd.get_command_element(i))
+                .filter_map(|name| str::from_utf8(name).ok())
+                .filter_map(LatencyEvent::from_name)
+                .collect();
+            // Unknown events are ignored but should not lead to resetting all the events.
+            let count = if events.is_empty() && arg_num > 2 {
+                0
+            } else {
+                monitor.reset(&events)
+            };
+            cmd_ctx.set_resp_result(Ok(Resp::Integer(count.to_string().into_bytes())));
+        } else {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                format!(
+                    "ERR unknown subcommand '{}'. Supported subcommands: {}",
+                    sub_cmd,
+                    LATENCY_SUB_COMMANDS.join(", ")
+                )
+                .into_bytes(),
+            )));
+        }
+    }
+
     fn handle_cluster(&self, cmd_ctx: CmdCtx) {
         let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 1) {
             Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd),
@@ -185,6 +677,17 @@ where
                 Ok(resp) => cmd_ctx.set_resp_result(Ok(resp)),
                 Err(s) => cmd_ctx.set_resp_result(Ok(Resp::Error(s.into_bytes()))),
             }
+        } else if str_ascii_case_insensitive_eq(&sub_cmd, "info") {
+            let cluster_info = self
+                .manager
+                .gen_cluster_info(cmd_ctx.get_cluster_name().clone());
+            cmd_ctx.set_resp_result(Ok(Resp::Bulk(BulkStr::Str(cluster_info.into_bytes()))))
+        } else if str_ascii_case_insensitive_eq(&sub_cmd, "help") {
+            cmd_ctx.set_resp_result(Ok(gen_help_reply("CLUSTER", CLUSTER_HELP)))
+        } else if str_ascii_case_insensitive_eq(&sub_cmd, "countkeysinslot")
+            || str_ascii_case_insensitive_eq(&sub_cmd, "getkeysinslot")
+        {
+            self.handle_cluster_keys_in_slot(cmd_ctx, &sub_cmd)
         } else if str_ascii_case_insensitive_eq(&sub_cmd, "keyslot") {
             match cmd_ctx.get_cmd().get_command_element(2) {
                 Some(key) => {
@@ -196,6 +699,13 @@ where
                         .set_resp_result(Ok(Resp::Error(String::from("Missing key").into_bytes())));
                 }
             }
+        } else if CLUSTER_ADMIN_SUB_COMMANDS
+            .iter()
+            .any(|admin_cmd| str_ascii_case_insensitive_eq(&sub_cmd, admin_cmd))
+        {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_MANAGED_BY_BROKER.to_string().into_bytes(),
+            )));
         } else {
             cmd_ctx.set_resp_result(Ok(Resp::Error(
                 String::from("Unsupported sub command").into_bytes(),
@@ -203,6 +713,189 @@ where
         }
     }
 
+    // CLUSTER COUNTKEYSINSLOT <slot>
+    // CLUSTER GETKEYSINSLOT <slot> <count>
+    // Forwarded as it is to the backend owning the slot.
+    fn handle_cluster_keys_in_slot(&self, mut cmd_ctx: CmdCtx, sub_cmd: &str) {
+        let get_keys = str_ascii_case_insensitive_eq(sub_cmd, "getkeysinslot");
+        let (arg_num, err) = if get_keys {
+            (4, response::ERR_INVALID_SLOT_OR_NUMBER_OF_KEYS)
+        } else {
+            (3, response::ERR_INVALID_SLOT)
+        };
+        if cmd_ctx.get_cmd().get_command_len() != Some(arg_num) {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                format!(
+                    "ERR wrong number of arguments for 'cluster|{}' command",
+                    sub_cmd.to_lowercase()
+                )
+                .into_bytes(),
+            )));
+            return;
+        }
+
+        let slot = cmd_ctx
+            .get_cmd()
+            .get_command_element(2)
+            .and_then(|slot| btou::<usize>(slot).ok())
+            .filter(|slot| *slot < SLOT_NUM);
+        let count_valid = !get_keys
+            || cmd_ctx
+                .get_cmd()
+                .get_command_element(3)
+                .and_then(|count| btou::<u64>(count).ok())
+                .is_some();
+        let slot = match slot {
+            Some(slot) if count_valid => slot,
+            _ => {
+                cmd_ctx.set_resp_result(Ok(Resp::Error(err.to_string().into_bytes())));
+                return;
+            }
+        };
+
+        cmd_ctx.set_slot(slot);
+        self.manager.send_to_slot_owner(cmd_ctx);
+    }
+
+    fn handle_debug(&self, cmd_ctx: CmdCtx, reply_receiver: CmdReplyReceiver) -> CmdReplyFuture {
+        let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 1) {
+            Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd),
+            None => return CmdReplyFuture::Left(reply_receiver),
+        };
+
+        let sub_cmd = sub_cmd.to_uppercase();
+        if !self.config.debug_allowlist.contains(&sub_cmd) {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_DEBUG_NOT_ALLOWED.to_string().into_bytes(),
+            )));
+        } else if is_debug_broadcast_sub_command(&sub_cmd) {
+            return CmdReplyFuture::Right(Box::pin(
+                self.handle_debug_broadcast(cmd_ctx, reply_receiver),
+            ));
+        } else {
+            self.manager.send(cmd_ctx);
+        }
+        CmdReplyFuture::Left(reply_receiver)
+    }
+
+    // Sends the DEBUG toggle to every node of the cluster including the replicas,
+    // and replies OK only if all of them succeed.
+    // This proxy sends it to its local masters and their replicas,
+    // and forwards it with UMFORWARD to the other proxies owning slots
+    // which do the same without forwarding it again.
+    async fn handle_debug_broadcast(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+    ) -> TaskResult {
+        let cluster_name = cmd_ctx.get_cluster_name().clone();
+        let backend_slots = self.manager.get_local_backend_slots(&cluster_name);
+        let forwarded = cmd_ctx.get_redirection_times().is_some();
+        let proxies = if forwarded {
+            vec![]
+        } else {
+            self.manager.get_remote_proxies(&cluster_name)
+        };
+        if backend_slots.is_empty() && proxies.is_empty() {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_CLUSTER_NOT_FOUND.to_string().into_bytes(),
+            )));
+            return reply_receiver.await;
+        }
+
+        let (masters, _) = self.manager.get_cluster_replication(&cluster_name);
+        let mut replicas: Vec<String> = masters
+            .into_iter()
+            .filter(|master| {
+                backend_slots
+                    .iter()
+                    .any(|(address, _)| address == &master.master_node_address)
+            })
+            .flat_map(|master| master.replicas.into_iter())
+            .map(|replica| replica.node_address)
+            .collect();
+        replicas.sort();
+        replicas.dedup();
+
+        let args: Vec<Vec<u8>> = (0..)
+            .map(|i| cmd_ctx.get_cmd().get_command_element(i))
+            .take_while(Option::is_some)
+            .flatten()
+            .map(|arg| arg.to_vec())
+            .collect();
+        let mut forwarded_args = vec![b"UMFORWARD".to_vec(), b"0".to_vec()];
+        forwarded_args.extend(args.iter().cloned());
+
+        let resp = cmd_ctx.get_cmd().get_packet().into_resp_vec();
+        let factory = CmdCtxFactory::default();
+        let mut futs: Vec<BoxFuture<DebugResult>> = vec![];
+        for (address, slot) in backend_slots.into_iter() {
+            let (mut sub_cmd_ctx, fut) =
+                factory.create_with_ctx(cmd_ctx.get_context(), resp.clone());
+            sub_cmd_ctx.set_slot(slot);
+            self.manager.send_to_slot_owner(sub_cmd_ctx);
+            futs.push(
+                fut.map(move |res| (address, false, res.map_err(|err| format!("{:?}", err))))
+                    .boxed(),
+            );
+        }
+        for address in replicas.into_iter() {
+            futs.push(
+                self.send_debug_to(address, args.clone())
+                    .map(|(address, res)| (address, false, res))
+                    .boxed(),
+            );
+        }
+        for address in proxies.into_iter() {
+            futs.push(
+                self.send_debug_to(address, forwarded_args.clone())
+                    .map(|(address, res)| (address, true, res))
+                    .boxed(),
+            );
+        }
+
+        for (address, is_proxy, sub_result) in future::join_all(futs).await.into_iter() {
+            let node = if is_proxy { "proxy" } else { "backend" };
+            let err_str = match sub_result {
+                Ok(Resp::Simple(_)) => continue,
+                // The error of a backend forwarded by another proxy already has its address.
+                Ok(Resp::Error(err)) if is_proxy && has_backend_address(&err) => {
+                    String::from_utf8_lossy(&err).to_string()
+                }
+                Ok(Resp::Error(err)) => {
+                    format!("{} ({} {})", String::from_utf8_lossy(&err), node, address)
+                }
+                Ok(others) => format!("unexpected reply from {} {}: {:?}", node, address, others),
+                Err(err) => format!("ERR failed to send to {}: {}", address, err),
+            };
+            cmd_ctx.set_resp_result(Ok(Resp::Error(err_str.into_bytes())));
+            return reply_receiver.await;
+        }
+
+        cmd_ctx.set_resp_result(Ok(Resp::Simple(
+            response::OK_REPLY.to_string().into_bytes(),
+        )));
+        reply_receiver.await
+    }
+
+    fn send_debug_to(
+        &self,
+        address: String,
+        args: Vec<Vec<u8>>,
+    ) -> impl Future<Output = (String, Result<RespVec, String>)> + Send + 'static {
+        let client_factory = self.client_factory.clone();
+        async move {
+            let res = match client_factory.create_client(address.clone()).await {
+                Ok(mut client) => client
+                    .execute_single(args)
+                    .await
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+            (address, res)
+        }
+    }
+
     fn get_sub_command(cmd_ctx: CmdCtx, index: usize) -> Option<(CmdCtx, String)> {
         let sub_cmd = match cmd_ctx.get_cmd().get_command_element(index) {
             None => {
@@ -224,6 +917,54 @@ where
         Some((cmd_ctx, sub_cmd))
     }
 
+    // The proxy has no users. AUTH only selects the cluster
+    // and ACL WHOAMI returns the username it takes.
+    fn handle_acl(&self, cmd_ctx: CmdCtx, session_state: &SessionState) {
+        let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 1) {
+            Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd),
+            None => return,
+        };
+
+        let sub_cmd = sub_cmd.to_uppercase();
+        let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.as_bytes().to_vec()));
+        let rules = &self.config.acl_user_rules;
+        let reply = match sub_cmd.as_str() {
+            "WHOAMI" => bulk(
+                &session_state
+                    .get_username()
+                    .unwrap_or_else(|| ACL_DEFAULT_USER.to_string()),
+            ),
+            "USERS" => Resp::Arr(Array::Arr(vec![bulk(ACL_DEFAULT_USER)])),
+            "LIST" => Resp::Arr(Array::Arr(vec![bulk(&format!(
+                "user {} {}",
+                ACL_DEFAULT_USER,
+                redact_acl_passwords(rules)
+            ))])),
+            "CAT" if cmd_ctx.get_cmd().get_command_element(2).is_some() => {
+                Resp::Error(b"ERR ACL CAT <category> is not supported by the proxy".to_vec())
+            }
+            "CAT" => Resp::Arr(Array::Arr(ACL_CATEGORIES.iter().map(|c| bulk(c)).collect())),
+            "GETUSER" => match cmd_ctx.get_cmd().get_command_element(2) {
+                Some(username) if username == ACL_DEFAULT_USER.as_bytes() => {
+                    gen_acl_user_reply(rules)
+                }
+                Some(_) => Resp::Bulk(BulkStr::Nil),
+                None => {
+                    Resp::Error(b"ERR wrong number of arguments for 'acl|getuser' command".to_vec())
+                }
+            },
+            "HELP" => gen_help_reply("ACL", ACL_HELP),
+            _ => Resp::Error(
+                format!(
+                    "ERR ACL {} is not supported since the proxy has no users",
+                    sub_cmd
+                )
+                .into_bytes(),
+            ),
+        };
+        cmd_ctx.set_resp_result(Ok(reply));
+    }
+
     fn handle_umctl(&self, cmd_ctx: CmdCtx) {
         let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 1) {
             Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd),
@@ -250,6 +991,8 @@ where
             self.handle_umctl_info_repl(cmd_ctx);
         } else if sub_cmd.eq("INFOMGR") {
             self.handle_umctl_info_migration(cmd_ctx);
+        } else if sub_cmd.eq("INFOPROGRESS") {
+            self.handle_umctl_info_progress(cmd_ctx);
         } else if sub_cmd.eq(MgrSubCmd::PreCheck.as_str()) {
             self.handle_umctl_mgr_cmd(cmd_ctx, MgrSubCmd::PreCheck);
         } else if sub_cmd.eq(MgrSubCmd::PreSwitch.as_str()) {
@@ -262,6 +1005,12 @@ where
             self.handle_umctl_debug(cmd_ctx);
         } else if sub_cmd.eq("GETEPOCH") {
             self.handle_umctl_get_epoch(cmd_ctx);
+        } else if sub_cmd.eq("CHECKTOPO") {
+            self.handle_umctl_check_topo(cmd_ctx);
+        } else if sub_cmd.eq("FLUSHCACHE") {
+            self.handle_umctl_flush_cache(cmd_ctx);
+        } else if sub_cmd.eq("HELP") {
+            cmd_ctx.set_resp_result(Ok(gen_help_reply("UMCTL", UMCTL_HELP)));
         } else {
             cmd_ctx.set_resp_result(Ok(Resp::Error(
                 String::from("Invalid sub command").into_bytes(),
@@ -378,6 +1127,20 @@ where
         cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(packet))))
     }
 
+    fn handle_umctl_info_progress(&self, cmd_ctx: CmdCtx) {
+        let progresses = self.manager.get_migration_progresses();
+        let packet: Vec<RespVec> = progresses
+            .into_iter()
+            .map(|(meta, progress)| {
+                let mut strs = meta.into_strings();
+                strs.extend(progress.into_strings());
+                strs.join(" ")
+            })
+            .map(|s| Resp::Bulk(BulkStr::Str(s.into_bytes())))
+            .collect();
+        cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(packet))))
+    }
+
     fn handle_umctl_slowlog(&self, cmd_ctx: CmdCtx) {
         let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 2) {
             Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd),
@@ -397,6 +1160,8 @@ where
         } else if sub_cmd.eq("RESET") {
             self.slow_request_logger.reset();
             cmd_ctx.set_resp_result(Ok(Resp::Simple(String::from("OK").into_bytes())));
+        } else if sub_cmd.eq("HELP") {
+            cmd_ctx.set_resp_result(Ok(gen_help_reply("UMCTL SLOWLOG", UMCTL_SLOWLOG_HELP)));
         } else {
             cmd_ctx.set_resp_result(Ok(Resp::Error(
                 "invalid slowlog sub-command".to_string().into_bytes(),
@@ -434,6 +1199,62 @@ where
         cmd_ctx.set_resp_result(Ok(Resp::Integer(epoch.to_string().into_bytes())))
     }
 
+    fn handle_umctl_flush_cache(&self, cmd_ctx: CmdCtx) {
+        match self.manager.flush_cache() {
+            Ok(()) => {
+                self.read_cache.clear();
+                info!("flushed the backend connections and the routing caches");
+                cmd_ctx.set_resp_result(Ok(Resp::Simple(String::from("OK").into_bytes())))
+            }
+            Err(_) => cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_FLUSH_CACHE_DURING_MIGRATION
+                    .to_string()
+                    .into_bytes(),
+            ))),
+        }
+    }
+
+    fn handle_umctl_check_topo(&self, cmd_ctx: CmdCtx) {
+        let cluster_name = match cmd_ctx.get_cmd().get_command_element(2) {
+            None => None,
+            Some(name) => match str::from_utf8(name)
+                .ok()
+                .and_then(|name| ClusterName::try_from(name).ok())
+            {
+                Some(cluster_name) => Some(cluster_name),
+                None => {
+                    return cmd_ctx.set_resp_result(Ok(Resp::Error(
+                        String::from("Invalid cluster name").into_bytes(),
+                    )))
+                }
+            },
+        };
+
+        let reports = self
+            .manager
+            .check_topo(cluster_name)
+            .into_iter()
+            .map(|(cluster_name, issues)| {
+                let status = if issues.is_empty() {
+                    "ok"
+                } else {
+                    "inconsistent"
+                };
+                let mut lines = vec![
+                    format!("cluster: {}", cluster_name),
+                    format!("status: {}", status),
+                ];
+                lines.extend(issues.iter().map(|issue| issue.to_string()));
+                let elements = lines
+                    .into_iter()
+                    .map(|s| Resp::Bulk(BulkStr::Str(s.into_bytes())))
+                    .collect();
+                Resp::Arr(Array::Arr(elements))
+            })
+            .collect();
+        cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(reports))))
+    }
+
     fn handle_config(&self, cmd_ctx: CmdCtx) {
         let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 1) {
             Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd.to_uppercase()),
@@ -472,6 +1293,8 @@ where
                     cmd_ctx.set_resp_result(Ok(Resp::Error(format!("{:?}", err).into_bytes())))
                 }
             }
+        } else if sub_cmd.eq("HELP") {
+            cmd_ctx.set_resp_result(Ok(gen_help_reply("CONFIG", CONFIG_HELP)))
         } else {
             cmd_ctx.set_resp_result(Ok(Resp::Error(
                 "invalid config sub-command".to_string().into_bytes(),
@@ -480,21 +1303,108 @@ where
     }
 
     fn handle_data_cmd(&self, cmd_ctx: CmdCtx, reply_receiver: CmdReplyReceiver) -> CmdReplyFuture {
-        match cmd_ctx.get_data_cmd_type() {
-            DataCmdType::MGET => {
-                CmdReplyFuture::Right(Box::pin(self.handle_mget(cmd_ctx, reply_receiver)))
-            }
-            DataCmdType::MSET => {
-                CmdReplyFuture::Right(Box::pin(self.handle_mset(cmd_ctx, reply_receiver)))
-            }
-            DataCmdType::DEL if cmd_ctx.get_cmd().get_command_element(2).is_some() => {
-                CmdReplyFuture::Right(Box::pin(self.handle_multi_int_cmd(
-                    cmd_ctx,
-                    reply_receiver,
-                    "DEL",
-                )))
+        self.with_read_cache(cmd_ctx, reply_receiver, |cmd_ctx, reply_receiver| {
+            self.forward_data_cmd(cmd_ctx, reply_receiver)
+        })
+    }
+
+    // Serves the GET of the cached keys locally
+    // and invalidates the cached keys of the writes.
+    fn with_read_cache<'a, Fwd>(
+        &'a self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+        forward: Fwd,
+    ) -> CmdReplyFuture<'a>
+    where
+        Fwd: FnOnce(CmdCtx, CmdReplyReceiver) -> CmdReplyFuture<'a>,
+    {
+        let read_cache = &self.read_cache;
+        if !read_cache.is_enabled() {
+            return forward(cmd_ctx, reply_receiver);
+        }
+
+        let cluster_name = cmd_ctx.get_cluster_name().clone();
+        let cmd = cmd_ctx.get_cmd();
+        if cmd.get_data_cmd_type() == DataCmdType::GET {
+            let key = cmd
+                .get_key()
+                .filter(|key| read_cache.is_cached_key(key))
+                .map(|key| key.to_vec());
+            let key = match key {
+                Some(key) => key,
+                None => return forward(cmd_ctx, reply_receiver),
+            };
+            if let Some(value) = read_cache.get(&cluster_name, &key) {
+                cmd_ctx.set_resp_result(Ok(Resp::Bulk(BulkStr::Str(value))));
+                return CmdReplyFuture::Left(reply_receiver);
             }
-            DataCmdType::EXISTS if cmd_ctx.get_cmd().get_command_element(2).is_some() => {
+            let version = read_cache.get_version();
+            let fut = forward(cmd_ctx, reply_receiver);
+            return CmdReplyFuture::Right(Box::pin(async move {
+                let res = fut.await;
+                if let Ok(reply) = &res {
+                    // Nil and the errors are not cached.
+                    if let Resp::Bulk(BulkStr::Str(value)) = reply.get_resp_slice() {
+                        read_cache.insert(cluster_name, key, value, version);
+                    }
+                }
+                res
+            }));
+        }
+
+        if !cmd.is_keyed_write() {
+            return forward(cmd_ctx, reply_receiver);
+        }
+        let keys: Vec<Vec<u8>> = cmd
+            .get_all_keys()
+            .into_iter()
+            .filter(|key| read_cache.is_cached_key(key))
+            .map(|key| key.to_vec())
+            .collect();
+        if keys.is_empty() {
+            return forward(cmd_ctx, reply_receiver);
+        }
+        // The GET sent before the write is done could still get the old value,
+        // so the keys are invalidated again after the write.
+        read_cache.invalidate(&cluster_name, &keys);
+        let fut = forward(cmd_ctx, reply_receiver);
+        CmdReplyFuture::Right(Box::pin(async move {
+            let res = fut.await;
+            read_cache.invalidate(&cluster_name, &keys);
+            res
+        }))
+    }
+
+    fn forward_data_cmd(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+    ) -> CmdReplyFuture {
+        match cmd_ctx.get_data_cmd_type() {
+            DataCmdType::WAIT if cmd_ctx.get_cmd().is_zero_replica_wait() => {
+                self.handle_zero_replica_wait(cmd_ctx);
+                CmdReplyFuture::Left(reply_receiver)
+            }
+            DataCmdType::MGET => {
+                CmdReplyFuture::Right(Box::pin(self.handle_mget(cmd_ctx, reply_receiver)))
+            }
+            DataCmdType::MSET => {
+                CmdReplyFuture::Right(Box::pin(self.handle_mset(cmd_ctx, reply_receiver)))
+            }
+            DataCmdType::GETDEL | DataCmdType::DEL if self.config.has_invalidation_channels() => {
+                CmdReplyFuture::Right(Box::pin(
+                    self.handle_invalidating_cmd(cmd_ctx, reply_receiver),
+                ))
+            }
+            DataCmdType::DEL if cmd_ctx.get_cmd().get_command_element(2).is_some() => {
+                CmdReplyFuture::Right(Box::pin(self.handle_multi_int_cmd(
+                    cmd_ctx,
+                    reply_receiver,
+                    "DEL",
+                )))
+            }
+            DataCmdType::EXISTS if cmd_ctx.get_cmd().get_command_element(2).is_some() => {
                 CmdReplyFuture::Right(Box::pin(self.handle_multi_int_cmd(
                     cmd_ctx,
                     reply_receiver,
@@ -506,17 +1416,281 @@ where
                     self.handle_list_blocking_commands(cmd_ctx, reply_receiver),
                 ))
             }
+            DataCmdType::GEORADIUS
+            | DataCmdType::GEORADIUSBYMEMBER
+            | DataCmdType::GEOSEARCHSTORE
+            | DataCmdType::SMOVE
+            | DataCmdType::LCS => self.handle_src_dst_cmd(cmd_ctx, reply_receiver),
+            DataCmdType::SINTERCARD | DataCmdType::LMPOP | DataCmdType::ZMPOP => {
+                self.handle_numkeys_cmd(cmd_ctx, reply_receiver, 1)
+            }
+            DataCmdType::MIGRATE => {
+                CmdReplyFuture::Right(Box::pin(self.handle_migrate(cmd_ctx, reply_receiver)))
+            }
+            DataCmdType::SORT | DataCmdType::SORT_RO => {
+                self.handle_sort_cmd(cmd_ctx, reply_receiver)
+            }
+            DataCmdType::PFCOUNT | DataCmdType::PFMERGE => {
+                self.handle_same_slot_cmd(cmd_ctx, reply_receiver)
+            }
+            _ => match self.manager.prepare_failover_retry(&cmd_ctx) {
+                Some(retry) => {
+                    self.handle_single_key_data_cmd(cmd_ctx);
+                    CmdReplyFuture::Right(Box::pin(retry.retry_on_failure(reply_receiver)))
+                }
+                None => {
+                    self.handle_single_key_data_cmd(cmd_ctx);
+                    CmdReplyFuture::Left(reply_receiver)
+                }
+            },
+        }
+    }
+
+    // Replies without forwarding it since it returns immediately on redis.
+    // The replicas of the backend of the last write are counted
+    // from the acked offsets polled by `ROLE`, which are at most one second old.
+    // Without any write in this connection, there's nothing to be acked.
+    fn handle_zero_replica_wait(&self, cmd_ctx: CmdCtx) {
+        let replica_num = match cmd_ctx.get_slot() {
+            Some(slot) => self
+                .manager
+                .get_replica_ack_num(cmd_ctx.get_cluster_name(), slot),
+            None => 0,
+        };
+        cmd_ctx.set_resp_result(Ok(Resp::Integer(replica_num.to_string().into_bytes())))
+    }
+
+    // For CLIENT DURABLE ON.
+    // WAIT only waits for the writes sent from the same connection,
+    // so the write and the implicit WAIT are sent through the same backend connection.
+    // The multi-key writes are forwarded as a whole instead of being split.
+    async fn handle_durable_write(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+    ) -> TaskResult {
+        let slot = match cmd_ctx.get_slot() {
+            Some(slot) if same_slot(cmd_ctx.get_cmd().get_all_keys().into_iter()) => slot,
             _ => {
-                self.handle_single_key_data_cmd(cmd_ctx);
-                CmdReplyFuture::Left(reply_receiver)
+                cmd_ctx.set_resp_result(Ok(Resp::Error(
+                    response::ERR_NOT_THE_SAME_SLOT.to_string().into_bytes(),
+                )));
+                return reply_receiver.await;
+            }
+        };
+
+        // The WAIT is only sent after the write is replied
+        // so that it's queued behind the write on the backend connection.
+        let conn_hint = cmd_ctx.get_session_id();
+        let factory = CmdCtxFactory::default();
+        let resp = cmd_ctx.get_cmd().get_packet().into_resp_vec();
+        let (mut write_cmd_ctx, write_fut) = factory.create_with_ctx(cmd_ctx.get_context(), resp);
+        write_cmd_ctx.set_conn_hint(conn_hint);
+        self.handle_single_key_data_cmd(write_cmd_ctx);
+        let reply = match write_fut.await {
+            // The failed write doesn't need to be waited.
+            Ok(Resp::Error(err)) => {
+                cmd_ctx.set_resp_result(Ok(Resp::Error(err)));
+                return reply_receiver.await;
             }
+            Ok(reply) => reply,
+            Err(err) => return Err(err),
+        };
+
+        let timeout = self.config.get_durable_write_timeout().to_string();
+        let resp = Resp::Arr(Array::Arr(vec![
+            Resp::Bulk(BulkStr::Str(b"WAIT".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"1".to_vec())),
+            Resp::Bulk(BulkStr::Str(timeout.into_bytes())),
+        ]));
+        let (mut wait_cmd_ctx, wait_fut) = factory.create_with_ctx(cmd_ctx.get_context(), resp);
+        wait_cmd_ctx.set_slot(slot);
+        wait_cmd_ctx.set_conn_hint(conn_hint);
+        self.manager.send(wait_cmd_ctx);
+
+        let res = match wait_fut.await {
+            Ok(Resp::Integer(n)) if n.as_slice() != b"0" => Ok(reply),
+            Ok(Resp::Integer(_)) => Ok(Resp::Error(
+                response::ERR_DURABLE_WRITE_TIMEOUT.to_string().into_bytes(),
+            )),
+            Ok(Resp::Error(err)) => Ok(Resp::Error(
+                format!(
+                    "NOREPLICAS failed to wait for the replicas: {}",
+                    String::from_utf8_lossy(&err)
+                )
+                .into_bytes(),
+            )),
+            Ok(other) => Ok(Resp::Error(
+                format!("NOREPLICAS unexpected WAIT reply: {:?}", other).into_bytes(),
+            )),
+            Err(err) => Err(err),
+        };
+        cmd_ctx.set_resp_result(res);
+        reply_receiver.await
+    }
+
+    // All the channels should be in the same slot owned by a backend of this proxy.
+    // The clients are redirected to the other proxies for the remote slots.
+    fn handle_ssubscribe(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+        session_state: &SessionState,
+    ) -> CmdReplyFuture {
+        let channels = get_channels(cmd_ctx.get_cmd());
+        if channels.is_empty() {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                b"ERR wrong number of arguments for 'ssubscribe' command".to_vec(),
+            )));
+            return CmdReplyFuture::Left(reply_receiver);
+        }
+        let slot = match cmd_ctx.get_slot() {
+            Some(slot) if same_slot(channels.iter().map(|c| c.as_slice())) => slot,
+            _ => {
+                cmd_ctx.set_resp_result(Ok(Resp::Error(
+                    response::ERR_NOT_THE_SAME_SLOT.to_string().into_bytes(),
+                )));
+                return CmdReplyFuture::Left(reply_receiver);
+            }
+        };
+
+        let cluster_name = cmd_ctx.get_cluster_name().clone();
+        let address = match self.manager.get_local_backend(&cluster_name, slot) {
+            Some(address) => address,
+            None => {
+                let err = match self.manager.get_slot_owner(&cluster_name, slot) {
+                    Some(owner) => gen_moved(slot, owner),
+                    None => response::ERR_CLUSTER_NOT_FOUND.to_string(),
+                };
+                cmd_ctx.set_resp_result(Ok(Resp::Error(err.into_bytes())));
+                return CmdReplyFuture::Left(reply_receiver);
+            }
+        };
+
+        let subscription = session_state.get_sharded_subscription();
+        let conn_factory = self.conn_factory.clone();
+        let fut = async move {
+            let packet = match subscription
+                .subscribe(conn_factory, address, channels)
+                .await
+            {
+                Ok(replies) => gen_replies_packet(replies),
+                Err(err) => Box::new(RespPacket::from_resp_vec(err)),
+            };
+            cmd_ctx.set_result(Ok(packet));
+            reply_receiver.await
+        };
+        CmdReplyFuture::Right(Box::pin(fut))
+    }
+
+    fn handle_sunsubscribe(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+        session_state: &SessionState,
+    ) -> CmdReplyFuture {
+        let channels = get_channels(cmd_ctx.get_cmd());
+        let channels = if channels.is_empty() {
+            None
+        } else {
+            Some(channels)
+        };
+        let subscription = session_state.get_sharded_subscription();
+        let fut = async move {
+            let packet = match subscription.unsubscribe(channels).await {
+                Ok(replies) => gen_replies_packet(replies),
+                Err(err) => Box::new(RespPacket::from_resp_vec(err)),
+            };
+            cmd_ctx.set_result(Ok(packet));
+            reply_receiver.await
+        };
+        CmdReplyFuture::Right(Box::pin(fut))
+    }
+
+    fn handle_src_dst_cmd(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+    ) -> CmdReplyFuture {
+        // The destination key can't be forwarded to another node,
+        // so this check is needed even in active redirection mode.
+        let cmd = cmd_ctx.get_cmd();
+        let src_dst_keys = match cmd.get_data_cmd_type() {
+            DataCmdType::SMOVE => cmd.get_smove_keys(),
+            DataCmdType::LCS => cmd.get_lcs_keys(),
+            _ => cmd.get_geo_store_keys(),
+        };
+        let in_same_slot = match src_dst_keys {
+            Some((src, dst)) => same_slot(vec![src, dst].into_iter()),
+            None => true,
+        };
+        if !in_same_slot {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_NOT_THE_SAME_SLOT.to_string().into_bytes(),
+            )));
+        } else {
+            self.handle_single_key_data_cmd(cmd_ctx);
+        }
+        CmdReplyFuture::Left(reply_receiver)
+    }
+
+    fn handle_sort_cmd(&self, cmd_ctx: CmdCtx, reply_receiver: CmdReplyReceiver) -> CmdReplyFuture {
+        // The backend can only access the keys of the slots it owns.
+        match cmd_ctx.get_cmd().check_sort_patterns() {
+            Ok(()) => self.handle_single_key_data_cmd(cmd_ctx),
+            Err(err) => cmd_ctx.set_resp_result(Ok(Resp::Error(err.to_string().into_bytes()))),
         }
+        CmdReplyFuture::Left(reply_receiver)
+    }
+
+    // The multi-key commands which are always forwarded as a whole,
+    // even in active redirection mode.
+    fn handle_same_slot_cmd(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+    ) -> CmdReplyFuture {
+        let keys = cmd_ctx.get_cmd().get_all_keys();
+        // The commands without any key get the error from the backend.
+        if !keys.is_empty() && !same_slot(keys.into_iter()) {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_NOT_THE_SAME_SLOT.to_string().into_bytes(),
+            )));
+        } else {
+            self.handle_single_key_data_cmd(cmd_ctx);
+        }
+        CmdReplyFuture::Left(reply_receiver)
+    }
+
+    fn handle_numkeys_cmd(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+        numkeys_index: usize,
+    ) -> CmdReplyFuture {
+        // The whole command is forwarded so the keys can't be split
+        // even in active redirection mode.
+        let in_same_slot = match cmd_ctx.get_cmd().get_numkeys_keys(numkeys_index) {
+            Ok(keys) => same_slot(keys.into_iter()),
+            Err(err) => {
+                cmd_ctx.set_resp_result(Ok(Resp::Error(err.to_string().into_bytes())));
+                return CmdReplyFuture::Left(reply_receiver);
+            }
+        };
+        if !in_same_slot {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_NOT_THE_SAME_SLOT.to_string().into_bytes(),
+            )));
+        } else {
+            self.handle_single_key_data_cmd(cmd_ctx);
+        }
+        CmdReplyFuture::Left(reply_receiver)
     }
 
     async fn handle_mget(&self, cmd_ctx: CmdCtx, reply_receiver: CmdReplyReceiver) -> TaskResult {
         let arg_len = cmd_ctx.get_cmd().get_command_len().unwrap_or(0);
 
-        if !self.config.active_redirection {
+        if !self.config.allows_cross_slot_split("MGET") {
             let in_same_slot =
                 same_slot((1..arg_len).filter_map(|i| cmd_ctx.get_cmd().get_command_element(i)));
             if !in_same_slot {
@@ -572,7 +1746,7 @@ where
     async fn handle_mset(&self, cmd_ctx: CmdCtx, reply_receiver: CmdReplyReceiver) -> TaskResult {
         let arg_len = cmd_ctx.get_cmd().get_command_len().unwrap_or(0);
 
-        if !self.config.active_redirection {
+        if !self.config.allows_cross_slot_split("MSET") {
             let in_same_slot = same_slot(
                 (0..(arg_len / 2)).filter_map(|i| cmd_ctx.get_cmd().get_command_element(2 * i + 1)),
             );
@@ -635,7 +1809,207 @@ where
         reply_receiver.await
     }
 
-    // DEL and EXISTS
+    // DUMP the keys, RESTORE them on the destination and DEL them unless COPY is given.
+    // The destination should be another proxy or redis since the node of a key
+    // in this proxy is decided by its slot.
+    async fn handle_migrate(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+    ) -> TaskResult {
+        let args = match parse_migrate(cmd_ctx.get_cmd()) {
+            Ok(args) => args,
+            Err(err) => {
+                cmd_ctx.set_resp_result(Ok(Resp::Error(err.into_bytes())));
+                return reply_receiver.await;
+            }
+        };
+        if args.address == self.config.address || args.address == self.config.announce_address {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_MIGRATE_SAME_HOST.to_string().into_bytes(),
+            )));
+            return reply_receiver.await;
+        }
+        if !same_slot(args.keys.iter().cloned()) {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_NOT_THE_SAME_SLOT.to_string().into_bytes(),
+            )));
+            return reply_receiver.await;
+        }
+
+        let resp = match self.migrate_keys(&cmd_ctx, &args).await {
+            Ok(resp) => resp,
+            Err(err) => Resp::Error(err.into_bytes()),
+        };
+        cmd_ctx.set_resp_result(Ok(resp));
+        reply_receiver.await
+    }
+
+    async fn migrate_keys(
+        &self,
+        cmd_ctx: &CmdCtx,
+        args: &MigrateArgs<'_>,
+    ) -> Result<RespVec, String> {
+        let send_local = |args: Vec<Vec<u8>>| {
+            let elements = args
+                .into_iter()
+                .map(|arg| Resp::Bulk(BulkStr::Str(arg)))
+                .collect();
+            let (sub_cmd_ctx, fut) = CmdCtxFactory::default()
+                .create_with_ctx(cmd_ctx.get_context(), Resp::Arr(Array::Arr(elements)));
+            self.handle_single_key_data_cmd(sub_cmd_ctx);
+            fut
+        };
+
+        // The payloads are binary and only passed through.
+        // The expiration is kept by restoring the keys with their PTTL.
+        let mut dumped = vec![];
+        for key in args.keys.iter() {
+            let dump_fut = send_local(vec![b"DUMP".to_vec(), key.to_vec()]);
+            let pttl_fut = send_local(vec![b"PTTL".to_vec(), key.to_vec()]);
+            let (dump_res, pttl_res) = future::join(dump_fut, pttl_fut).await;
+            let payload = match dump_res.map_err(|err| format!("ERR failed to DUMP: {:?}", err))? {
+                Resp::Bulk(BulkStr::Str(payload)) => payload,
+                Resp::Bulk(BulkStr::Nil) => continue,
+                Resp::Error(err) => return Err(String::from_utf8_lossy(&err).to_string()),
+                others => return Err(format!("ERR unexpected reply from DUMP: {:?}", others)),
+            };
+            let pttl = match pttl_res.map_err(|err| format!("ERR failed to PTTL: {:?}", err))? {
+                Resp::Integer(pttl) => pttl,
+                Resp::Error(err) => return Err(String::from_utf8_lossy(&err).to_string()),
+                others => return Err(format!("ERR unexpected reply from PTTL: {:?}", others)),
+            };
+            // Expired after the DUMP.
+            if pttl.as_slice() == PTTL_KEY_NOT_FOUND {
+                continue;
+            }
+            dumped.push((key.to_vec(), pttl_to_restore_expire_time(pttl), payload));
+        }
+        if dumped.is_empty() {
+            return Ok(Resp::Simple(b"NOKEY".to_vec()));
+        }
+
+        let address = args.address.clone();
+        let target_err = |err: String| format!("ERR Target instance replied with error: {}", err);
+        let mut client = match time::timeout(
+            args.timeout,
+            self.client_factory.create_client(address.clone()),
+        )
+        .await
+        {
+            Ok(Ok(client)) => client,
+            Ok(Err(err)) => {
+                return Err(format!(
+                    "IOERR error or timeout connecting to {}: {}",
+                    address, err
+                ))
+            }
+            Err(_) => {
+                return Err(format!(
+                    "IOERR error or timeout connecting to {}: timeout",
+                    address
+                ))
+            }
+        };
+        if !args.auth.is_empty() {
+            let mut auth_cmd = vec![b"AUTH".to_vec()];
+            auth_cmd.extend(args.auth.iter().map(|arg| arg.to_vec()));
+            if let Resp::Error(err) =
+                execute_with_timeout(&mut client, auth_cmd, args.timeout).await?
+            {
+                return Err(target_err(pretty_print_bytes(&err)));
+            }
+        }
+        for (key, expire_time, payload) in dumped.iter() {
+            let mut restore_cmd = vec![b"RESTORE".to_vec(), key.clone(), expire_time.clone()];
+            restore_cmd.push(payload.clone());
+            if args.replace {
+                restore_cmd.push(b"REPLACE".to_vec());
+            }
+            if let Resp::Error(err) =
+                execute_with_timeout(&mut client, restore_cmd, args.timeout).await?
+            {
+                return Err(target_err(pretty_print_bytes(&err)));
+            }
+        }
+
+        // Only the restored keys are deleted. They are in the same slot.
+        if !args.copy {
+            let mut del_cmd = vec![b"DEL".to_vec()];
+            del_cmd.extend(dumped.into_iter().map(|(key, _, _)| key));
+            let reply = send_local(del_cmd)
+                .await
+                .map_err(|err| format!("ERR failed to DEL: {:?}", err))?;
+            if let Resp::Error(err) = reply {
+                return Err(String::from_utf8_lossy(&err).to_string());
+            }
+        }
+        Ok(Resp::Simple(response::OK_REPLY.to_string().into_bytes()))
+    }
+
+    // Publishes the deleted keys with the configured prefixes by SPUBLISH
+    // only after the backend replied to the delete.
+    async fn handle_invalidating_cmd(
+        &self,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+    ) -> TaskResult {
+        let cmd = cmd_ctx.get_cmd();
+        let is_multi_del =
+            cmd.get_data_cmd_type() == DataCmdType::DEL && cmd.get_command_element(2).is_some();
+        // Created before the delete consumes the context but only sent after it.
+        let factory = CmdCtxFactory::default();
+        let publishes: Vec<_> = cmd
+            .get_all_keys()
+            .into_iter()
+            .filter_map(|key| {
+                let channel = self.config.get_invalidation_channel(key)?;
+                let resp = Resp::Arr(Array::Arr(vec![
+                    Resp::Bulk(BulkStr::Str(b"SPUBLISH".to_vec())),
+                    Resp::Bulk(BulkStr::Str(channel.as_bytes().to_vec())),
+                    Resp::Bulk(BulkStr::Str(key.to_vec())),
+                ]));
+                Some(factory.create_with_ctx(cmd_ctx.get_context(), resp))
+            })
+            .collect();
+
+        let res = if is_multi_del {
+            self.handle_multi_int_cmd(cmd_ctx, reply_receiver, "DEL")
+                .await
+        } else {
+            self.handle_single_key_data_cmd(cmd_ctx);
+            reply_receiver.await
+        };
+
+        let deleted = match &res {
+            Ok(reply) => match reply.get_resp_slice() {
+                Resp::Bulk(BulkStr::Str(_)) => true,
+                Resp::Integer(n) => n != b"0",
+                _ => false,
+            },
+            Err(_) => false,
+        };
+        if !deleted || publishes.is_empty() {
+            return res;
+        }
+
+        let mut futs = vec![];
+        for (publish_cmd_ctx, fut) in publishes.into_iter() {
+            futs.push(fut);
+            self.handle_single_key_data_cmd(publish_cmd_ctx);
+        }
+        for publish_res in future::join_all(futs).await.into_iter() {
+            match publish_res {
+                Ok(Resp::Error(err)) => {
+                    warn!("failed to publish invalidation: {:?}", str::from_utf8(&err))
+                }
+                Err(err) => warn!("failed to publish invalidation: {:?}", err),
+                Ok(_) => (),
+            }
+        }
+        res
+    }
+
     async fn handle_multi_int_cmd(
         &self,
         cmd_ctx: CmdCtx,
@@ -644,7 +2018,7 @@ where
     ) -> TaskResult {
         let arg_len = cmd_ctx.get_cmd().get_command_len().unwrap_or(0);
 
-        if !self.config.active_redirection {
+        if !self.config.allows_cross_slot_split(cmd_name) {
             let in_same_slot =
                 same_slot((1..arg_len).filter_map(|i| cmd_ctx.get_cmd().get_command_element(i)));
             if !in_same_slot {
@@ -655,17 +2029,19 @@ where
             }
         }
 
+        // Send one command for each slot. The duplicate keys are kept
+        // so that EXISTS could still count them multiple times.
         let factory = CmdCtxFactory::default();
         let mut futs = vec![];
-        for i in 1.. {
-            let key = match cmd_ctx.get_cmd().get_command_element(i) {
-                Some(key) => key,
-                None => break,
-            };
-            let resp = Resp::Arr(Array::Arr(vec![
-                Resp::Bulk(BulkStr::Str(cmd_name.to_string().into_bytes())),
-                Resp::Bulk(BulkStr::Str(key.to_vec())),
-            ]));
+        let key_groups =
+            group_by_slot((1..arg_len).filter_map(|i| cmd_ctx.get_cmd().get_command_element(i)));
+        for keys in key_groups.into_iter() {
+            let mut elements = vec![Resp::Bulk(BulkStr::Str(cmd_name.to_string().into_bytes()))];
+            elements.extend(
+                keys.into_iter()
+                    .map(|key| Resp::Bulk(BulkStr::Str(key.to_vec()))),
+            );
+            let resp = Resp::Arr(Array::Arr(elements));
             let (sub_cmd_ctx, fut) = factory.create_with_ctx(cmd_ctx.get_context(), resp);
             futs.push(fut);
             self.handle_single_key_data_cmd(sub_cmd_ctx);
@@ -860,6 +2236,188 @@ where
         }
     }
 
+    fn handle_info(&self, cmd_ctx: CmdCtx) {
+        let sections = parse_info_sections(cmd_ctx.get_cmd());
+        let cluster_name = cmd_ctx.get_cluster_name();
+        let info: Vec<String> = sections
+            .into_iter()
+            .map(|section| {
+                let (title, lines) = match section {
+                    "server" => ("Server", self.gen_info_server()),
+                    "clients" => ("Clients", self.gen_info_clients()),
+                    "memory" => ("Memory", self.gen_info_memory()),
+                    "stats" => ("Stats", self.gen_info_stats()),
+                    "replication" => ("Replication", self.gen_info_replication(cluster_name)),
+                    _ => ("Keyspace", self.gen_info_keyspace(cluster_name)),
+                };
+                let mut s = format!("# {}\r\n", title);
+                for line in lines.into_iter() {
+                    s.push_str(&line);
+                    s.push_str("\r\n");
+                }
+                s
+            })
+            .collect();
+        let info = info.join("\r\n");
+        cmd_ctx.set_resp_result(Ok(Resp::Bulk(BulkStr::Str(info.into_bytes()))))
+    }
+
+    fn gen_info_server(&self) -> Vec<String> {
+        let tcp_port = self
+            .config
+            .address
+            .rsplit(':')
+            .next()
+            .unwrap_or("")
+            .to_string();
+        vec![
+            format!("version:{}", UNDERMOON_VERSION),
+            "redis_mode:cluster".to_string(),
+            format!("process_id:{}", std::process::id()),
+            format!("tcp_port:{}", tcp_port),
+        ]
+    }
+
+    fn gen_info_clients(&self) -> Vec<String> {
+        let sessions = self.traffic_stats.get_sessions();
+        vec![
+            format!("connected_clients:{}", sessions),
+            format!("connected_sessions:{}", sessions),
+            format!(
+                "in_flight_commands:{}",
+                self.traffic_stats.get_in_flight_cmds()
+            ),
+        ]
+    }
+
+    fn gen_info_memory(&self) -> Vec<String> {
+        vec![
+            format!("used_buffer_memory:{}", self.memory_budget.get_used()),
+            format!(
+                "buffer_memory_soft_limit:{}",
+                self.memory_budget.get_soft_limit()
+            ),
+            format!(
+                "buffer_memory_hard_limit:{}",
+                self.memory_budget.get_hard_limit()
+            ),
+        ]
+    }
+
+    fn gen_info_stats(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("hedged_reads:{}", self.manager.get_hedged_read_count()),
+            format!(
+                "failover_retries:{}",
+                self.manager.get_failover_retry_count()
+            ),
+        ];
+        // Only the queues that have ever blocked are shown.
+        let blocking_stats = self.manager.get_blocking_stats();
+        let blocking_stats = blocking_stats
+            .into_iter()
+            .filter(|stats| stats.total_blocked_time > Duration::from_secs(0));
+        for (i, stats) in blocking_stats.enumerate() {
+            lines.push(format!(
+                "migration_blocking{}:address={},queue_len={},blocking_ms={},total_blocked_ms={}",
+                i,
+                stats.address,
+                stats.queue_len,
+                stats.blocking_time.as_millis(),
+                stats.total_blocked_time.as_millis(),
+            ));
+        }
+        for (i, stats) in self
+            .manager
+            .get_backend_in_flight_stats()
+            .into_iter()
+            .enumerate()
+        {
+            lines.push(format!(
+                "backend_in_flight{}:address={},depth={},max_in_flight={}",
+                i, stats.address, stats.depth, stats.max_in_flight,
+            ));
+        }
+        for (i, stats) in self
+            .manager
+            .get_backend_flap_stats()
+            .into_iter()
+            .enumerate()
+        {
+            lines.push(format!(
+                "backend_quarantine{}:address={},quarantined={},remaining_ms={},quarantine_count={}",
+                i,
+                stats.address,
+                stats.quarantined,
+                stats.remaining.as_millis(),
+                stats.quarantine_count,
+            ));
+        }
+        if self.read_cache.is_enabled() {
+            let stats = self.read_cache.get_stats();
+            lines.push(format!("read_cache_hits:{}", stats.hits));
+            lines.push(format!("read_cache_misses:{}", stats.misses));
+            lines.push(format!("read_cache_entries:{}", stats.entries));
+        }
+        for (i, stats) in self.traffic_stats.get_db_stats().into_iter().enumerate() {
+            lines.push(format!(
+                "db_traffic{}:name={},request_bytes={},reply_bytes={},bytes_per_sec={},quota={}",
+                i,
+                stats.cluster_name,
+                stats.request_bytes,
+                stats.reply_bytes,
+                stats.bytes_per_sec,
+                self.config.get_db_byte_rate_quota(&stats.cluster_name),
+            ));
+        }
+        lines.extend(self.traffic_stats.get_stats().into_iter().map(
+            |(data_cmd_type, request_bytes, reply_bytes)| {
+                let cmd_name = format!("{:?}", data_cmd_type).to_lowercase();
+                format!(
+                    "traffic_{}:request_bytes={},reply_bytes={}",
+                    cmd_name, request_bytes, reply_bytes
+                )
+            },
+        ));
+        lines
+    }
+
+    // The proxy is always a master to the clients.
+    // The replicas are the ones of the local master nodes in the current cluster.
+    fn gen_info_replication(&self, cluster_name: &ClusterName) -> Vec<String> {
+        let (masters, replicas) = self.manager.get_cluster_replication(cluster_name);
+        let peers: Vec<_> = masters
+            .iter()
+            .flat_map(|master| master.replicas.iter())
+            .collect();
+        let mut lines = vec![
+            "role:master".to_string(),
+            format!("connected_slaves:{}", peers.len()),
+        ];
+        for (i, peer) in peers.into_iter().enumerate() {
+            let (ip, port) = match peer.proxy_address.rfind(':') {
+                Some(pos) => (&peer.proxy_address[..pos], &peer.proxy_address[pos + 1..]),
+                None => (peer.proxy_address.as_str(), ""),
+            };
+            lines.push(format!(
+                "slave{}:ip={},port={},node={}",
+                i, ip, port, peer.node_address
+            ));
+        }
+        lines.push(format!("local_master_nodes:{}", masters.len()));
+        lines.push(format!("local_replica_nodes:{}", replicas.len()));
+        lines
+    }
+
+    // The proxy doesn't know the number of keys without asking all the backends.
+    fn gen_info_keyspace(&self, cluster_name: &ClusterName) -> Vec<String> {
+        let slot_num = self.manager.get_local_slot_num(cluster_name);
+        if slot_num == 0 {
+            return vec![];
+        }
+        vec![format!("{}:local_slots={}", cluster_name, slot_num)]
+    }
+
     fn handle_single_key_data_cmd(&self, cmd_ctx: CmdCtx) {
         let mut cmd_ctx = cmd_ctx;
         match self.compressor.try_compressing_cmd_ctx(&mut cmd_ctx) {
@@ -911,6 +2469,10 @@ where
         }
 
         cmd_ctx.set_redirection_times(times);
+        // For the DEBUG broadcast forwarded from other proxies.
+        if cmd_ctx.get_cmd_type() == CmdType::Debug {
+            return self.handle_debug(cmd_ctx, reply_receiver);
+        }
         self.handle_data_cmd(cmd_ctx, reply_receiver)
     }
 
@@ -928,7 +2490,7 @@ where
         &self,
         cmd_ctx: CmdCtx,
         reply_receiver: CmdReplyReceiver,
-        session_cluster_name: &sync::RwLock<ClusterName>,
+        session_state: &SessionState,
     ) -> CmdReplyFuture {
         let mut cmd_ctx = cmd_ctx;
         if self.config.auto_select_cluster {
@@ -940,10 +2502,8 @@ where
             CmdType::Ping => {
                 cmd_ctx.set_resp_result(Ok(Resp::Simple(String::from("OK").into_bytes())))
             }
-            CmdType::Info => cmd_ctx.set_resp_result(Ok(Resp::Bulk(BulkStr::Str(
-                format!("version:{}\r\n", UNDERMOON_VERSION,).into_bytes(),
-            )))),
-            CmdType::Auth => self.handle_auth(cmd_ctx, session_cluster_name),
+            CmdType::Info => self.handle_info(cmd_ctx),
+            CmdType::Auth => self.handle_auth(cmd_ctx, session_state),
             CmdType::Quit => {
                 cmd_ctx.set_resp_result(Ok(Resp::Simple(String::from("OK").into_bytes())))
             }
@@ -968,14 +2528,1577 @@ where
             CmdType::UmSync => self.handle_umsync(cmd_ctx),
             CmdType::Cluster => self.handle_cluster(cmd_ctx),
             CmdType::Config => self.handle_config(cmd_ctx),
-            CmdType::Command => {
-                cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(vec![]))));
-            }
+            CmdType::Command => self.handle_command(cmd_ctx),
             CmdType::Asking => cmd_ctx.set_resp_result(Ok(Resp::Simple(
                 response::OK_REPLY.to_string().into_bytes(),
             ))),
-            CmdType::Others => return self.handle_data_cmd(cmd_ctx, reply_receiver),
+            CmdType::Client => self.handle_client(cmd_ctx, session_state),
+            CmdType::Latency => self.handle_latency(cmd_ctx),
+            CmdType::Debug => return self.handle_debug(cmd_ctx, reply_receiver),
+            CmdType::Hello => self.handle_hello(cmd_ctx, session_state),
+            CmdType::Acl => self.handle_acl(cmd_ctx, session_state),
+            CmdType::Monitor => self.handle_monitor(cmd_ctx, session_state),
+            CmdType::Reset => {
+                self.command_monitor.remove(session_state.get_session_id());
+                session_state.reset();
+                cmd_ctx.set_resp_result(Ok(Resp::Simple(
+                    response::RESET_REPLY.to_string().into_bytes(),
+                )))
+            }
+            // Forwarding it would take down the backend redis.
+            CmdType::Shutdown if !self.config.shutdown_passthrough => {
+                cmd_ctx.set_resp_result(Ok(Resp::Error(
+                    response::ERR_SHUTDOWN_NOT_SUPPORTED
+                        .to_string()
+                        .into_bytes(),
+                )))
+            }
+            CmdType::SSubscribe => {
+                return self.handle_ssubscribe(cmd_ctx, reply_receiver, session_state)
+            }
+            CmdType::SUnsubscribe => {
+                return self.handle_sunsubscribe(cmd_ctx, reply_receiver, session_state)
+            }
+            CmdType::Others if self.is_database_unselected(&cmd_ctx, session_state) => cmd_ctx
+                .set_resp_result(Ok(Resp::Error(
+                    response::ERR_NO_DATABASE_SELECTED.to_string().into_bytes(),
+                ))),
+            CmdType::Others
+                if self.config.readonly_on_stale_topology
+                    && cmd_ctx.get_cmd().is_keyed_write()
+                    && self.manager.is_topology_stale() =>
+            {
+                cmd_ctx.set_resp_result(Ok(Resp::Error(
+                    response::ERR_TOPOLOGY_STALE.to_string().into_bytes(),
+                )))
+            }
+            CmdType::Others
+                if session_state.is_durable_writes() && needs_durable_wait(cmd_ctx.get_cmd()) =>
+            {
+                return self.with_read_cache(cmd_ctx, reply_receiver, |cmd_ctx, reply_receiver| {
+                    CmdReplyFuture::Right(Box::pin(
+                        self.handle_durable_write(cmd_ctx, reply_receiver),
+                    ))
+                })
+            }
+            CmdType::Shutdown | CmdType::Others => {
+                return self.handle_data_cmd(cmd_ctx, reply_receiver)
+            }
         };
         CmdReplyFuture::Left(reply_receiver)
     }
+
+    fn get_slot_node(&self, cluster_name: &ClusterName, slot: usize) -> Option<String> {
+        self.manager.get_slot_node(cluster_name, slot)
+    }
+
+    fn get_slot_owner(&self, cluster_name: &ClusterName, slot: usize) -> Option<String> {
+        self.manager.get_slot_owner(cluster_name, slot)
+    }
+
+    fn get_client_pause(&self) -> Option<&ClientPause> {
+        Some(&self.client_pause)
+    }
+
+    fn get_command_monitor(&self) -> Option<&CommandMonitor> {
+        Some(&self.command_monitor)
+    }
+}
+
+fn get_channels(cmd: &Command) -> Vec<BinSafeStr> {
+    (1..cmd.get_command_len().unwrap_or(0))
+        .filter_map(|i| cmd.get_command_element(i))
+        .map(|channel| channel.to_vec())
+        .collect()
+}
+
+// The blocking commands are excluded since the backend connection
+// would be occupied by them.
+fn needs_durable_wait(cmd: &Command) -> bool {
+    let data_cmd_type = cmd.get_data_cmd_type();
+    match data_cmd_type {
+        DataCmdType::WAIT
+        | DataCmdType::WAITAOF
+        | DataCmdType::BLPOP
+        | DataCmdType::BRPOP
+        | DataCmdType::BRPOPLPUSH
+        | DataCmdType::MIGRATE => false,
+        _ => cmd.is_keyed_write(),
+    }
+}
+
+// Redis shows the SHA256 of the passwords instead. The proxy hides them all.
+fn redact_acl_passwords(rules: &str) -> String {
+    rules
+        .split_whitespace()
+        .map(|rule| match rule.chars().next() {
+            Some(prefix @ '>') | Some(prefix @ '<') => format!("{}<redacted>", prefix),
+            _ => rule.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// The same format as ACL GETUSER of redis 7 without the selectors.
+fn gen_acl_user_reply(rules: &str) -> RespVec {
+    let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.as_bytes().to_vec()));
+    let rules: Vec<&str> = rules.split_whitespace().collect();
+    let join_rules = |prefixes: &[char]| {
+        rules
+            .iter()
+            .filter(|rule| rule.starts_with(prefixes))
+            .cloned()
+            .collect::<Vec<&str>>()
+            .join(" ")
+    };
+    let flags = rules
+        .iter()
+        .filter(|rule| !rule.starts_with(&['+', '-', '~', '%', '&', '#', '>', '<'][..]))
+        .map(|rule| bulk(rule))
+        .collect();
+    Resp::Arr(Array::Arr(vec![
+        bulk("flags"),
+        Resp::Arr(Array::Arr(flags)),
+        bulk("passwords"),
+        Resp::Arr(Array::Arr(vec![])),
+        bulk("commands"),
+        bulk(&join_rules(&['+', '-'])),
+        bulk("keys"),
+        bulk(&join_rules(&['~', '%'])),
+        bulk("channels"),
+        bulk(&join_rules(&['&'])),
+    ]))
+}
+
+// The same format as the HELP subcommands of redis.
+fn gen_help_reply(cmd_name: &str, lines: &[&str]) -> RespVec {
+    let first_line = format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        cmd_name
+    );
+    let lines = std::iter::once(first_line)
+        .chain(lines.iter().map(|line| line.to_string()))
+        .chain(vec!["HELP".to_string(), "    Print this help.".to_string()])
+        .map(|line| Resp::Simple(line.into_bytes()))
+        .collect();
+    Resp::Arr(Array::Arr(lines))
+}
+
+// MIGRATE fails with IOERR as redis does when the destination can't reply in time.
+// (address, whether it's another proxy, result)
+type DebugResult = (String, bool, Result<RespVec, String>);
+
+// The errors of DEBUG end with `(backend <address>)`.
+fn has_backend_address(err: &[u8]) -> bool {
+    const BACKEND_TAG: &[u8] = b" (backend ";
+    err.ends_with(b")") && err.windows(BACKEND_TAG.len()).any(|w| w == BACKEND_TAG)
+}
+
+async fn execute_with_timeout<C: RedisClient>(
+    client: &mut C,
+    cmd: Vec<BinSafeStr>,
+    timeout: Duration,
+) -> Result<RespVec, String> {
+    match time::timeout(timeout, client.execute_single(cmd)).await {
+        Ok(Ok(resp)) => Ok(resp),
+        Ok(Err(err)) => Err(format!("IOERR {}", err)),
+        Err(_) => Err("IOERR error or timeout reading from target instance".to_string()),
+    }
+}
+
+fn parse_client_name(name: &[u8]) -> Option<String> {
+    if !name.iter().all(|b| *b > b' ' && *b <= b'~') {
+        return None;
+    }
+    str::from_utf8(name).map(ToString::to_string).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::execute_with_timeout;
+    use crate::protocol::{Array, BulkStr, MockRedisClient, Resp, RespVec};
+    use crate::test_support::{
+        gen_test_config, setup_proxy_with_backend, setup_proxy_with_config, FakeBackendFunc,
+        TestProxy,
+    };
+    use futures::future;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio;
+
+    #[tokio::test]
+    async fn test_no_database_selected() {
+        let backend: FakeBackendFunc =
+            Arc::new(|address, _args| Resp::Bulk(BulkStr::Str(address.as_bytes().to_vec())));
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-16383",
+                "otherdb",
+                "127.0.0.1:6380",
+                "1",
+                "0-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client.send(&["GET", "b"]).await;
+        assert_eq!(reply, Resp::Error(b"ERR no database selected".to_vec()));
+        // Not a data command.
+        let reply = client.send(&["PING"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client.send(&["AUTH", "otherdb"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send_when_ready(&["GET", "b"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"127.0.0.1:6380".to_vec())));
+
+        // The cluster selected explicitly gets the original error.
+        let mut client = proxy.connect();
+        let reply = client.send(&["AUTH", "admin"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send(&["GET", "b"]).await;
+        assert_eq!(reply, Resp::Error(b"ERR_CLUSTER_NOT_FOUND: admin".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_hyperloglog_same_slot() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| {
+            received_clone.lock().unwrap().push(args[0].clone());
+            Resp::Integer(b"1".to_vec())
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+
+        let reply = client.send_when_ready(&["PFADD", "hll", "e1", "e2"]).await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+        let reply = client
+            .send(&["PFMERGE", "{hll}dst", "{hll}1", "{hll}2"])
+            .await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+        let reply = client.send(&["PFCOUNT", "{hll}1", "{hll}2"]).await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+
+        let not_same_slot = Resp::Error(
+            crate::common::response::ERR_NOT_THE_SAME_SLOT
+                .as_bytes()
+                .to_vec(),
+        );
+        let reply = client.send(&["PFMERGE", "dst", "src1", "src2"]).await;
+        assert_eq!(reply, not_same_slot);
+        let reply = client.send(&["PFCOUNT", "a", "b"]).await;
+        assert_eq!(reply, not_same_slot);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.iter().filter(|c| *c == "PFMERGE").count(), 1);
+        assert_eq!(received.iter().filter(|c| *c == "PFCOUNT").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_debug_broadcast() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args.join(" ")));
+            match address {
+                "127.0.0.1:6381" => Resp::Error(b"ERR unknown subcommand".to_vec()),
+                _ => Resp::Simple(b"OK".to_vec()),
+            }
+        });
+        let mut config = gen_test_config();
+        config.debug_allowlist = vec!["SET-ACTIVE-EXPIRE".to_string(), "RELOAD".to_string()];
+        let proxy = TestProxy::with_config(config, backend);
+        let mut client = proxy.connect();
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-8191",
+                "mydb",
+                "127.0.0.1:6380",
+                "1",
+                "8192-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client
+            .send_when_ready(&["DEBUG", "set-active-expire", "0"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        // It could be resent before the backend connections are ready.
+        let mut sent = received.lock().unwrap().clone();
+        sent.sort();
+        sent.dedup();
+        assert_eq!(
+            sent,
+            vec![
+                (
+                    "127.0.0.1:6379".to_string(),
+                    "DEBUG set-active-expire 0".to_string()
+                ),
+                (
+                    "127.0.0.1:6380".to_string(),
+                    "DEBUG set-active-expire 0".to_string()
+                ),
+            ]
+        );
+
+        // Not broadcasted.
+        received.lock().unwrap().clear();
+        let reply = client.send(&["DEBUG", "RELOAD"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "2",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-8191",
+                "mydb",
+                "127.0.0.1:6381",
+                "1",
+                "8192-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client
+            .send_when_ready(&["DEBUG", "SET-ACTIVE-EXPIRE", "1"])
+            .await;
+        assert_eq!(
+            reply,
+            Resp::Error(b"ERR unknown subcommand (backend 127.0.0.1:6381)".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_debug_broadcast_to_cluster() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args.join(" ")));
+            match (address, args.last().map(|arg| arg.as_str())) {
+                ("127.0.0.1:7000", Some("2")) => {
+                    Resp::Error(b"ERR cluster not found (mydb)".to_vec())
+                }
+                ("127.0.0.1:7000", Some("3")) => {
+                    Resp::Error(b"ERR unknown subcommand (backend 127.0.0.1:7100)".to_vec())
+                }
+                _ => Resp::Simple(b"OK".to_vec()),
+            }
+        });
+        let mut config = gen_test_config();
+        config.debug_allowlist = vec!["SET-ACTIVE-EXPIRE".to_string()];
+        let proxy = TestProxy::with_config(config, backend);
+        let mut client = proxy.connect();
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-8191",
+                "PEER",
+                "mydb",
+                "127.0.0.1:7000",
+                "1",
+                "8192-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETREPL",
+                "1",
+                "NOFLAGS",
+                "master",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "127.0.0.1:6390",
+                "127.0.0.1:7001",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client
+            .send_when_ready(&["DEBUG", "set-active-expire", "0"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let mut sent: Vec<_> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, cmd)| cmd.contains("DEBUG"))
+            .cloned()
+            .collect();
+        sent.sort();
+        sent.dedup();
+        assert_eq!(
+            sent,
+            vec![
+                (
+                    "127.0.0.1:6379".to_string(),
+                    "DEBUG set-active-expire 0".to_string()
+                ),
+                (
+                    "127.0.0.1:6390".to_string(),
+                    "DEBUG set-active-expire 0".to_string()
+                ),
+                (
+                    "127.0.0.1:7000".to_string(),
+                    "UMFORWARD 0 DEBUG set-active-expire 0".to_string()
+                ),
+            ]
+        );
+
+        // The forwarded one is not forwarded again.
+        received.lock().unwrap().clear();
+        let reply = client
+            .send(&["UMFORWARD", "0", "DEBUG", "set-active-expire", "1"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let mut sent: Vec<_> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(address, _)| address.clone())
+            .collect();
+        sent.sort();
+        sent.dedup();
+        assert_eq!(sent, vec!["127.0.0.1:6379", "127.0.0.1:6390"]);
+
+        // The errors of the other proxies are tagged unless they come from the backends.
+        let reply = client.send(&["DEBUG", "set-active-expire", "2"]).await;
+        assert_eq!(
+            reply,
+            Resp::Error(b"ERR cluster not found (mydb) (proxy 127.0.0.1:7000)".to_vec())
+        );
+        let reply = client.send(&["DEBUG", "set-active-expire", "3"]).await;
+        assert_eq!(
+            reply,
+            Resp::Error(b"ERR unknown subcommand (backend 127.0.0.1:7100)".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_zero_replica_wait() {
+        let waits = Arc::new(AtomicUsize::new(0));
+        let waits_clone = waits.clone();
+        let replica_online = Arc::new(AtomicBool::new(true));
+        let replica_online_clone = replica_online.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| match args[0].as_str() {
+            "WAIT" => {
+                waits_clone.fetch_add(1, Ordering::SeqCst);
+                Resp::Integer(b"1".to_vec())
+            }
+            "ROLE" => {
+                let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.as_bytes().to_vec()));
+                let mut replicas = vec![];
+                if replica_online_clone.load(Ordering::SeqCst) {
+                    replicas.push(Resp::Arr(Array::Arr(vec![
+                        bulk("127.0.0.1"),
+                        bulk("6380"),
+                        bulk("100"),
+                    ])));
+                }
+                Resp::Arr(Array::Arr(vec![
+                    bulk("master"),
+                    Resp::Integer(b"100".to_vec()),
+                    Resp::Arr(Array::Arr(replicas)),
+                ]))
+            }
+            _ => Resp::Simple(b"OK".to_vec()),
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETREPL",
+                "1",
+                "NOFLAGS",
+                "master",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "127.0.0.1:6380",
+                "127.0.0.1:7000",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        // No write to wait for yet.
+        let reply = client.send(&["WAIT", "0", "0"]).await;
+        assert_eq!(reply, Resp::Integer(b"0".to_vec()));
+
+        let reply = client.send_when_ready(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let fut = async {
+            // Until the first ROLE is polled.
+            while client.send(&["WAIT", "0", "0"]).await != Resp::Integer(b"1".to_vec()) {
+                futures_timer::Delay::new(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(3), fut)
+            .await
+            .unwrap();
+
+        // Only the replicas acking the master are counted.
+        replica_online.store(false, Ordering::SeqCst);
+        let fut = async {
+            while client.send(&["WAIT", "0", "0"]).await != Resp::Integer(b"0".to_vec()) {
+                futures_timer::Delay::new(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(3), fut)
+            .await
+            .unwrap();
+        assert_eq!(waits.load(Ordering::SeqCst), 0);
+
+        // Still forwarded when any replica needs to ack.
+        let reply = client.send(&["WAIT", "1", "0"]).await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+        assert_eq!(waits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_umctl_flush_cache() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+
+        // No metadata yet.
+        let reply = client.send(&["UMCTL", "FLUSHCACHE"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send_when_ready(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client.send(&["UMCTL", "FLUSHCACHE"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send_when_ready(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send(&["CLUSTER", "NODES"]).await;
+        matches::assert_matches!(reply, Resp::Bulk(_));
+    }
+
+    #[tokio::test]
+    async fn test_cluster_admin_commands() {
+        let called = Arc::new(AtomicUsize::new(0));
+        let called_clone = called.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, _| {
+            called_clone.fetch_add(1, Ordering::SeqCst);
+            Resp::Simple(b"OK".to_vec())
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+
+        let err = crate::common::response::ERR_MANAGED_BY_BROKER;
+        for cmd in &[
+            &["CLUSTER", "RESET", "HARD"][..],
+            &["cluster", "addslots", "1", "2"][..],
+            &["CLUSTER", "FORGET", "node_id"][..],
+            &["CLUSTER", "SETSLOT", "1", "NODE", "node_id"][..],
+        ] {
+            let reply = client.send(cmd).await;
+            assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+        }
+        assert_eq!(called.load(Ordering::SeqCst), 0);
+
+        let reply = client.send(&["CLUSTER", "NODES"]).await;
+        match reply {
+            Resp::Bulk(BulkStr::Str(nodes)) => {
+                assert!(String::from_utf8(nodes).unwrap().contains("0-16383"))
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+        let reply = client.send(&["CLUSTER", "INFO"]).await;
+        match reply {
+            Resp::Bulk(BulkStr::Str(info)) => {
+                assert!(String::from_utf8(info)
+                    .unwrap()
+                    .contains("cluster_state:ok"))
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+        let reply = client.send(&["CLUSTER", "SLOTS"]).await;
+        match reply {
+            Resp::Arr(Array::Arr(slots)) => assert_eq!(slots.len(), 1),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        let reply = client.send(&["CLUSTER", "NOSUCHCMD"]).await;
+        assert_eq!(reply, Resp::Error(b"Unsupported sub command".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_cluster_keys_in_slot() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            received_clone.lock().unwrap().push(args.clone());
+            Resp::Bulk(BulkStr::Str(address.as_bytes().to_vec()))
+        });
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-8000",
+                "mydb",
+                "127.0.0.1:6380",
+                "1",
+                "8001-12000",
+                "PEER",
+                "mydb",
+                "127.0.0.1:7000",
+                "1",
+                "12001-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client
+            .send_when_ready(&["CLUSTER", "COUNTKEYSINSLOT", "9000"])
+            .await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"127.0.0.1:6380".to_vec())));
+        let reply = client
+            .send_when_ready(&["cluster", "getkeysinslot", "3300", "10"])
+            .await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"127.0.0.1:6379".to_vec())));
+        let reply = client.send(&["CLUSTER", "COUNTKEYSINSLOT", "15495"]).await;
+        assert_eq!(reply, Resp::Error(b"MOVED 15495 127.0.0.1:7000".to_vec()));
+
+        let invalid_slot = crate::common::response::ERR_INVALID_SLOT
+            .as_bytes()
+            .to_vec();
+        let reply = client.send(&["CLUSTER", "COUNTKEYSINSLOT", "16384"]).await;
+        assert_eq!(reply, Resp::Error(invalid_slot));
+        let invalid_args = crate::common::response::ERR_INVALID_SLOT_OR_NUMBER_OF_KEYS
+            .as_bytes()
+            .to_vec();
+        let reply = client
+            .send(&["CLUSTER", "GETKEYSINSLOT", "3300", "-1"])
+            .await;
+        assert_eq!(reply, Resp::Error(invalid_args));
+        let reply = client.send(&["CLUSTER", "GETKEYSINSLOT", "3300"]).await;
+        matches::assert_matches!(reply, Resp::Error(_));
+
+        let received = received.lock().unwrap();
+        assert!(received.contains(&vec![
+            "cluster".to_string(),
+            "getkeysinslot".to_string(),
+            "3300".to_string(),
+            "10".to_string(),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_command_getkeys() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+
+        let reply = client
+            .send(&["COMMAND", "GETKEYS", "MSET", "a", "1", "b", "2"])
+            .await;
+        assert_eq!(
+            reply,
+            Resp::Arr(Array::Arr(vec![
+                Resp::Bulk(BulkStr::Str(b"a".to_vec())),
+                Resp::Bulk(BulkStr::Str(b"b".to_vec())),
+            ]))
+        );
+        let reply = client.send(&["COMMAND", "GETKEYS", "PING"]).await;
+        assert_eq!(
+            reply,
+            Resp::Error(
+                crate::common::response::ERR_NO_KEY_ARGUMENTS
+                    .as_bytes()
+                    .to_vec()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hash_field_expiration_reply() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| {
+            received_clone.lock().unwrap().push(args.clone());
+            // -2 for the missing field.
+            Resp::Arr(Array::Arr(vec![
+                Resp::Integer(b"1".to_vec()),
+                Resp::Integer(b"-2".to_vec()),
+            ]))
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+
+        let expected = Resp::Arr(Array::Arr(vec![
+            Resp::Integer(b"1".to_vec()),
+            Resp::Integer(b"-2".to_vec()),
+        ]));
+        let cmd = ["HEXPIRE", "myhash", "60", "NX", "FIELDS", "2", "f1", "f2"];
+        let reply = client.send_when_ready(&cmd).await;
+        assert_eq!(reply, expected);
+        let cmd2 = ["HTTL", "myhash", "FIELDS", "2", "f1", "f2"];
+        let reply = client.send(&cmd2).await;
+        assert_eq!(reply, expected);
+
+        let received = received.lock().unwrap();
+        assert!(received.iter().any(|args| args == &cmd));
+        assert!(received.iter().any(|args| args == &cmd2));
+    }
+
+    #[tokio::test]
+    async fn test_sort_pattern_validation() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| {
+            received_clone.lock().unwrap().push(args.clone());
+            Resp::Arr(Array::Arr(vec![]))
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+
+        let cmd = [
+            "SORT", "{t}list", "BY", "{t}w_*", "GET", "#", "GET", "{t}o_*",
+        ];
+        let reply = client.send_when_ready(&cmd).await;
+        assert_eq!(reply, Resp::Arr(Array::Arr(vec![])));
+
+        let reply = client.send(&["SORT_RO", "{t}list", "BY", "w_*"]).await;
+        assert_eq!(
+            reply,
+            Resp::Error(
+                crate::common::response::ERR_SORT_BY_CROSS_SLOT
+                    .as_bytes()
+                    .to_vec()
+            )
+        );
+
+        let received = received.lock().unwrap();
+        assert!(received.iter().any(|args| args == &cmd));
+        assert!(!received.iter().any(|args| args[0] == "SORT_RO"));
+    }
+
+    #[tokio::test]
+    async fn test_invalidation_publish() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| {
+            received_clone.lock().unwrap().push(args.clone());
+            match args[0].as_str() {
+                "GETDEL" if args[1].ends_with("missing") => Resp::Bulk(BulkStr::Nil),
+                "GETDEL" => Resp::Bulk(BulkStr::Str(b"v".to_vec())),
+                _ => Resp::Integer(b"1".to_vec()),
+            }
+        });
+        let mut config = gen_test_config();
+        config.invalidation_channels =
+            crate::proxy::service::parse_invalidation_channels("user:=user_inv").unwrap();
+        let (_proxy, mut client) = setup_proxy_with_config(config, backend).await;
+
+        let reply = client.send_when_ready(&["GETDEL", "user:1"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+        // Not deleted
+        let reply = client.send(&["GETDEL", "user:missing"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Nil));
+        let reply = client.send(&["DEL", "user:{t}2", "{t}other"]).await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+        let reply = client.send(&["GETDEL", "other"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+
+        let received = received.lock().unwrap();
+        let publishes: Vec<&Vec<String>> = received
+            .iter()
+            .filter(|args| args[0] == "SPUBLISH")
+            .collect();
+        assert_eq!(publishes.len(), 2);
+        assert_eq!(publishes[0], &["SPUBLISH", "user_inv", "user:1"]);
+        assert_eq!(publishes[1], &["SPUBLISH", "user_inv", "user:{t}2"]);
+        // The publish is only sent after the delete.
+        let getdel_index = received.iter().position(|args| args[1] == "user:1");
+        let publish_index = received.iter().position(|args| args[0] == "SPUBLISH");
+        assert!(getdel_index < publish_index);
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args.clone()));
+            match args[0].as_str() {
+                "DUMP" if args[1].ends_with("missing") => Resp::Bulk(BulkStr::Nil),
+                "DUMP" => Resp::Bulk(BulkStr::Str(format!("payload-{}", args[1]).into_bytes())),
+                // Expired between the DUMP and the PTTL.
+                "PTTL" if args[1].ends_with("expired") => Resp::Integer(b"-2".to_vec()),
+                "PTTL" if args[1].starts_with("{t}") => Resp::Integer(b"5000".to_vec()),
+                "PTTL" => Resp::Integer(b"-1".to_vec()),
+                "RESTORE" if args[1].ends_with("busy") => {
+                    Resp::Error(b"BUSYKEY Target key name already exists.".to_vec())
+                }
+                "DEL" => Resp::Integer(b"1".to_vec()),
+                _ => Resp::Simple(b"OK".to_vec()),
+            }
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+        let take_received = || received.lock().unwrap().drain(..).collect::<Vec<_>>();
+
+        let reply = client
+            .send_when_ready(&["MIGRATE", "127.0.0.1", "7000", "key", "0", "1000"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let received = take_received();
+        let restores: Vec<_> = received
+            .iter()
+            .filter(|(_, args)| args[0] == "RESTORE")
+            .collect();
+        assert_eq!(restores.len(), 1);
+        assert_eq!(restores[0].0, "127.0.0.1:7000");
+        assert_eq!(restores[0].1, &["RESTORE", "key", "0", "payload-key"]);
+        let dels: Vec<_> = received
+            .iter()
+            .filter(|(_, args)| args[0] == "DEL")
+            .collect();
+        assert_eq!(dels.len(), 1);
+        assert_eq!(dels[0].0, "127.0.0.1:6379");
+        assert_eq!(dels[0].1, &["DEL", "key"]);
+
+        // The keys are kept with COPY and the missing ones are skipped.
+        let reply = client
+            .send(&[
+                "MIGRATE",
+                "127.0.0.1",
+                "7000",
+                "",
+                "0",
+                "1000",
+                "COPY",
+                "REPLACE",
+                "AUTH",
+                "pwd",
+                "KEYS",
+                "{t}1",
+                "{t}missing",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let received = take_received();
+        let remote: Vec<&Vec<String>> = received
+            .iter()
+            .filter(|(address, _)| address == "127.0.0.1:7000")
+            .map(|(_, args)| args)
+            .collect();
+        assert_eq!(remote.len(), 2);
+        assert_eq!(remote[0], &["AUTH", "pwd"]);
+        assert_eq!(
+            remote[1],
+            &["RESTORE", "{t}1", "5000", "payload-{t}1", "REPLACE"]
+        );
+        assert!(received.iter().all(|(_, args)| args[0] != "DEL"));
+
+        let reply = client
+            .send(&["MIGRATE", "127.0.0.1", "7000", "missing", "0", "1000"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"NOKEY".to_vec()));
+        assert!(take_received()
+            .iter()
+            .all(|(_, args)| args[0] == "DUMP" || args[0] == "PTTL"));
+
+        let reply = client
+            .send(&["MIGRATE", "127.0.0.1", "7000", "expired", "0", "1000"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"NOKEY".to_vec()));
+        assert!(take_received()
+            .iter()
+            .all(|(address, _)| address == "127.0.0.1:6379"));
+
+        // Not deleted when the destination fails.
+        let reply = client
+            .send(&["MIGRATE", "127.0.0.1", "7000", "busy", "0", "1000"])
+            .await;
+        match reply {
+            Resp::Error(err) => assert!(err.starts_with(b"ERR Target instance replied with error")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(take_received().iter().all(|(_, args)| args[0] != "DEL"));
+
+        let reply = client
+            .send(&["MIGRATE", "127.0.0.1", "5299", "key", "0", "1000"])
+            .await;
+        assert_eq!(
+            reply,
+            Resp::Error(
+                crate::common::response::ERR_MIGRATE_SAME_HOST
+                    .as_bytes()
+                    .to_vec()
+            )
+        );
+        assert!(take_received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_destination_timeout() {
+        let mut mock_client = MockRedisClient::new();
+        mock_client
+            .expect_execute_single()
+            .times(1)
+            .returning(|_| Box::pin(future::pending()));
+        let cmd = vec![b"RESTORE".to_vec()];
+        let res = execute_with_timeout(&mut mock_client, cmd, Duration::from_millis(10)).await;
+        assert_eq!(
+            res,
+            Err("IOERR error or timeout reading from target instance".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readonly_on_stale_topology() {
+        let backend: FakeBackendFunc = Arc::new(|_, args| match args[0].as_str() {
+            "GET" => Resp::Bulk(BulkStr::Str(b"v".to_vec())),
+            _ => Resp::Simple(b"OK".to_vec()),
+        });
+        let mut config = gen_test_config();
+        config.stale_topology_timeout = 1;
+        config.readonly_on_stale_topology = true;
+        let (_proxy, mut client) = setup_proxy_with_config(config, backend).await;
+
+        let reply = client.send_when_ready(&["SET", "key", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        // The timestamp is in seconds.
+        futures_timer::Delay::new(Duration::from_millis(1100)).await;
+        let stale_err = Resp::Error(
+            crate::common::response::ERR_TOPOLOGY_STALE
+                .as_bytes()
+                .to_vec(),
+        );
+        let reply = client.send(&["SET", "key", "v"]).await;
+        assert_eq!(reply, stale_err);
+        let reply = client.send(&["DEL", "key"]).await;
+        assert_eq!(reply, stale_err);
+        let reply = client.send(&["GET", "key"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+
+        // Even the metadata with the same epoch restores the writes.
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-16383",
+            ])
+            .await;
+        assert!(matches!(reply, Resp::Error(_)));
+        let reply = client.send(&["SET", "key", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_help_subcommands() {
+        let called = Arc::new(AtomicUsize::new(0));
+        let called_clone = called.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, _| {
+            called_clone.fetch_add(1, Ordering::SeqCst);
+            Resp::Simple(b"OK".to_vec())
+        });
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+
+        for (cmd, cmd_name) in &[
+            (&["CLIENT", "HELP"][..], "CLIENT"),
+            (&["config", "help"][..], "CONFIG"),
+            (&["CLUSTER", "HELP"][..], "CLUSTER"),
+            (&["UMCTL", "HELP"][..], "UMCTL"),
+            (&["UMCTL", "SLOWLOG", "HELP"][..], "UMCTL SLOWLOG"),
+            (&["ACL", "HELP"][..], "ACL"),
+        ] {
+            let lines = match client.send(cmd).await {
+                Resp::Arr(Array::Arr(lines)) => lines,
+                other => panic!("unexpected reply {:?}", other),
+            };
+            let first_line = format!(
+                "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                cmd_name
+            );
+            assert_eq!(lines[0], Resp::Simple(first_line.into_bytes()));
+            assert_eq!(lines[lines.len() - 2], Resp::Simple(b"HELP".to_vec()));
+            // The subcommands and their descriptions come in pairs.
+            assert_eq!(lines.len() % 2, 1);
+        }
+        assert_eq!(called.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_acl() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+        let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.as_bytes().to_vec()));
+
+        assert_eq!(client.send(&["ACL", "WHOAMI"]).await, bulk("default"));
+        assert_eq!(
+            client.send(&["acl", "list"]).await,
+            Resp::Arr(Array::Arr(vec![bulk("user default on nopass ~* &* +@all")]))
+        );
+        match client.send(&["ACL", "CAT"]).await {
+            Resp::Arr(Array::Arr(categories)) => assert!(categories.contains(&bulk("read"))),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(
+            client.send(&["ACL", "GETUSER", "default"]).await,
+            Resp::Arr(Array::Arr(vec![
+                bulk("flags"),
+                Resp::Arr(Array::Arr(vec![bulk("on"), bulk("nopass")])),
+                bulk("passwords"),
+                Resp::Arr(Array::Arr(vec![])),
+                bulk("commands"),
+                bulk("+@all"),
+                bulk("keys"),
+                bulk("~*"),
+                bulk("channels"),
+                bulk("&*"),
+            ]))
+        );
+        assert_eq!(
+            client.send(&["ACL", "GETUSER", "other"]).await,
+            Resp::Bulk(BulkStr::Nil)
+        );
+        match client.send(&["ACL", "SETUSER", "other", "on"]).await {
+            Resp::Error(err) => assert!(err.starts_with(b"ERR ACL SETUSER is not supported")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_acl_whoami() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+        let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.as_bytes().to_vec()));
+
+        let reply = client.send(&["AUTH", "alice", "otherdb"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        assert_eq!(client.send(&["ACL", "WHOAMI"]).await, bulk("alice"));
+        // The password is the cluster name.
+        let reply = client.send(&["GET", "b"]).await;
+        assert_eq!(
+            reply,
+            Resp::Error(b"ERR_CLUSTER_NOT_FOUND: otherdb".to_vec())
+        );
+
+        let reply = client.send(&["AUTH", "mydb"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        assert_eq!(client.send(&["ACL", "WHOAMI"]).await, bulk("default"));
+
+        let mut client = proxy.connect();
+        client.send(&["HELLO", "2", "AUTH", "bob", "mydb"]).await;
+        assert_eq!(client.send(&["ACL", "WHOAMI"]).await, bulk("bob"));
+    }
+
+    #[tokio::test]
+    async fn test_acl_list_redacts_passwords() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let mut config = gen_test_config();
+        config.acl_user_rules = "on >secret <oldsecret ~* +@all".to_string();
+        let proxy = TestProxy::with_config(config, backend);
+        let mut client = proxy.connect();
+        let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.as_bytes().to_vec()));
+
+        assert_eq!(
+            client.send(&["ACL", "LIST"]).await,
+            Resp::Arr(Array::Arr(vec![bulk(
+                "user default on ><redacted> <<redacted> ~* +@all"
+            )]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_list() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::new(backend);
+        let mut client1 = proxy.connect();
+        let mut client2 = proxy.connect();
+        client2.send(&["CLIENT", "SETNAME", "myapp"]).await;
+        client2.send(&["PING"]).await;
+
+        let reply = client1.send(&["CLIENT", "LIST"]).await;
+        let list = match reply {
+            Resp::Bulk(BulkStr::Str(list)) => String::from_utf8(list).unwrap(),
+            other => panic!("unexpected reply {:?}", other),
+        };
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("id=0 "));
+        assert!(lines[0].ends_with(" cmd=client"));
+        assert!(lines[1].starts_with("id=1 "));
+        assert!(lines[1].contains(" name=myapp "));
+        assert!(lines[1].ends_with(" cmd=ping"));
+
+        drop(client2);
+        // Wait for the session to exit.
+        let wait_exited = async {
+            loop {
+                if let Resp::Bulk(BulkStr::Str(list)) = client1.send(&["CLIENT", "LIST"]).await {
+                    if list
+                        .split(|b| *b == b'\n')
+                        .filter(|l| !l.is_empty())
+                        .count()
+                        == 1
+                    {
+                        break;
+                    }
+                }
+                futures_timer::Delay::new(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait_exited)
+            .await
+            .expect("the session does not exit");
+
+        let reply = client1.send(&["CLIENT", "INFO"]).await;
+        match reply {
+            Resp::Bulk(BulkStr::Str(info)) => assert!(info.starts_with(b"id=0 ")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_no_evict_and_no_touch() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+        let (proxy, mut client) = setup_proxy_with_backend(backend).await;
+        let mut other_client = proxy.connect();
+        other_client.send(&["PING"]).await;
+
+        let ok = Resp::Simple(b"OK".to_vec());
+        assert_eq!(client.send(&["CLIENT", "NO-EVICT", "ON"]).await, ok);
+        assert_eq!(client.send(&["client", "no-touch", "on"]).await, ok);
+        let reply = client.send(&["CLIENT", "NO-TOUCH", "maybe"]).await;
+        assert_eq!(reply, Resp::Error(b"ERR syntax error".to_vec()));
+
+        let clients = proxy.get_clients();
+        assert_eq!(clients.len(), 2);
+        assert!(clients[0].is_no_evict());
+        assert!(clients[0].is_no_touch());
+        assert!(!clients[1].is_no_evict());
+        assert!(!clients[1].is_no_touch());
+
+        assert_eq!(client.send(&["CLIENT", "NO-EVICT", "OFF"]).await, ok);
+        assert!(!proxy.get_clients()[0].is_no_evict());
+        assert!(proxy.get_clients()[0].is_no_touch());
+
+        // The data commands are still forwarded as usual.
+        let reply = client.send(&["GET", "a"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+
+        let reply = client.send(&["CLIENT", "KILL", "ID", "1"]).await;
+        match reply {
+            Resp::Error(err) => {
+                let err = String::from_utf8(err).unwrap();
+                assert!(err.starts_with("ERR unknown subcommand 'KILL'"));
+                assert!(err.contains("NO-EVICT, NO-TOUCH"));
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+
+        // The invalidation messages can't be relayed through the shared backend connections.
+        let reply = client.send(&["CLIENT", "TRACKING", "ON"]).await;
+        match reply {
+            Resp::Error(err) => assert!(err.starts_with(b"ERR unknown subcommand 'TRACKING'")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_rejected() {
+        let called = Arc::new(AtomicUsize::new(0));
+        let called_clone = called.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, _| {
+            called_clone.fetch_add(1, Ordering::SeqCst);
+            Resp::Simple(b"OK".to_vec())
+        });
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+
+        let err = crate::common::response::ERR_SHUTDOWN_NOT_SUPPORTED;
+        for cmd in &[&["SHUTDOWN"][..], &["shutdown", "NOSAVE"][..]] {
+            let reply = client.send(cmd).await;
+            assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+        }
+        assert_eq!(called.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cross_slot_split_policy() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+        let mut split_config = gen_test_config();
+        split_config.cross_slot_split_commands = vec!["MGET".to_string()];
+
+        for (config, allowed) in vec![(gen_test_config(), false), (split_config, true)] {
+            let (_proxy, mut client) = setup_proxy_with_config(config, backend.clone()).await;
+
+            // The slots of "a" and "b" are 15495 and 3300.
+            let reply = client.send_when_ready(&["MGET", "a", "b"]).await;
+            if allowed {
+                let value = Resp::Bulk(BulkStr::Str(b"v".to_vec()));
+                assert_eq!(reply, Resp::Arr(Array::Arr(vec![value.clone(), value])));
+            } else {
+                let err = crate::common::response::ERR_NOT_THE_SAME_SLOT;
+                assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+            }
+            // DEL is not in the list so it's always strict.
+            let reply = client.send_when_ready(&["DEL", "a", "b"]).await;
+            let err = crate::common::response::ERR_NOT_THE_SAME_SLOT;
+            assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+            // The commands which can't be split are always rejected.
+            let reply = client.send(&["SMOVE", "a", "b", "m"]).await;
+            assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_key_exists_split_by_slot() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            if args[0] == "PING" {
+                return Resp::Simple(b"PONG".to_vec());
+            }
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args.join(" ")));
+            if args.iter().any(|arg| arg == "{b}fail") {
+                return Resp::Error(b"ERR shard failed".to_vec());
+            }
+            // All the keys exist.
+            Resp::Integer((args.len() - 1).to_string().into_bytes())
+        });
+        let mut config = gen_test_config();
+        config.cross_slot_split_commands = vec!["EXISTS".to_string(), "DEL".to_string()];
+        let proxy = TestProxy::with_config(config, backend);
+        let mut client = proxy.connect();
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-8191",
+                "mydb",
+                "127.0.0.1:6380",
+                "1",
+                "8192-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        // The slots of "a" and "b" are 15495 and 3300.
+        client.send_when_ready(&["EXISTS", "a"]).await;
+        client.send_when_ready(&["EXISTS", "b"]).await;
+        received.lock().unwrap().clear();
+
+        // The duplicate keys are counted multiple times.
+        let reply = client
+            .send_when_ready(&["EXISTS", "{a}1", "{b}1", "{a}2", "{a}1"])
+            .await;
+        assert_eq!(reply, Resp::Integer(b"4".to_vec()));
+        let mut sent = received.lock().unwrap().drain(..).collect::<Vec<_>>();
+        sent.sort();
+        assert_eq!(
+            sent,
+            vec![
+                ("127.0.0.1:6379".to_string(), "EXISTS {b}1".to_string()),
+                (
+                    "127.0.0.1:6380".to_string(),
+                    "EXISTS {a}1 {a}2 {a}1".to_string()
+                ),
+            ]
+        );
+
+        let reply = client.send_when_ready(&["DEL", "{a}1", "{b}1"]).await;
+        assert_eq!(reply, Resp::Integer(b"2".to_vec()));
+        received.lock().unwrap().clear();
+
+        // A failing shard fails the whole command instead of undercounting.
+        let reply = client
+            .send_when_ready(&["EXISTS", "{a}1", "{b}fail", "{b}1"])
+            .await;
+        assert_eq!(reply, Resp::Error(b"ERR shard failed".to_vec()));
+        let mut sent = received.lock().unwrap().drain(..).collect::<Vec<_>>();
+        sent.sort();
+        assert_eq!(
+            sent,
+            vec![
+                (
+                    "127.0.0.1:6379".to_string(),
+                    "EXISTS {b}fail {b}1".to_string()
+                ),
+                ("127.0.0.1:6380".to_string(), "EXISTS {a}1".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_durable_writes() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let replicas = Arc::new(AtomicUsize::new(1));
+        let replicas_clone = replicas.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| {
+            received_clone.lock().unwrap().push(args.clone());
+            match args[0].as_str() {
+                "WAIT" => {
+                    let n = replicas_clone.load(Ordering::SeqCst);
+                    Resp::Integer(n.to_string().into_bytes())
+                }
+                "GET" => Resp::Bulk(BulkStr::Str(b"v".to_vec())),
+                "SET" if args[1] == "readonly" => Resp::Error(b"READONLY".to_vec()),
+                _ => Resp::Simple(b"OK".to_vec()),
+            }
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+
+        client.send_when_ready(&["GET", "a"]).await;
+        received.lock().unwrap().clear();
+
+        let reply = client.send(&["CLIENT", "DURABLE", "ON"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send(&["GET", "a"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+        {
+            let received = received.lock().unwrap();
+            let cmds: Vec<_> = received.iter().map(|args| args.join(" ")).collect();
+            assert_eq!(cmds, vec!["SET a v", "WAIT 1 100", "GET a"]);
+        }
+
+        // No WAIT for the reads and the failed writes.
+        received.lock().unwrap().clear();
+        for args in &[
+            &["HGET", "a", "f"][..],
+            &["LRANGE", "a", "0", "-1"][..],
+            &["ZRANGE", "a", "0", "-1"][..],
+            &["SMEMBERS", "a"][..],
+            &["TTL", "a"][..],
+        ] {
+            client.send(args).await;
+        }
+        let reply = client.send(&["SET", "readonly", "v"]).await;
+        assert_eq!(reply, Resp::Error(b"READONLY".to_vec()));
+        assert!(received
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|args| args[0] != "WAIT"));
+
+        replicas.store(0, Ordering::SeqCst);
+        let reply = client.send(&["SET", "a", "v"]).await;
+        let err = crate::common::response::ERR_DURABLE_WRITE_TIMEOUT;
+        assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+
+        // The slots of "a" and "b" are 15495 and 3300.
+        let reply = client.send(&["MSET", "a", "1", "b", "2"]).await;
+        let err = crate::common::response::ERR_NOT_THE_SAME_SLOT;
+        assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+
+        let reply = client.send(&["CLIENT", "DURABLE", "OFF"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        received.lock().unwrap().clear();
+        let reply = client.send(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_lcs() {
+        let backend: FakeBackendFunc = Arc::new(|_, args| {
+            let option = args.get(3).map(|s| s.as_str());
+            match option {
+                Some("LEN") => Resp::Integer(b"4".to_vec()),
+                Some("IDX") => Resp::Arr(Array::Arr(vec![
+                    Resp::Bulk(BulkStr::Str(b"matches".to_vec())),
+                    Resp::Arr(Array::Arr(vec![Resp::Arr(Array::Arr(vec![
+                        Resp::Arr(Array::Arr(vec![
+                            Resp::Integer(b"0".to_vec()),
+                            Resp::Integer(b"3".to_vec()),
+                        ])),
+                        Resp::Arr(Array::Arr(vec![
+                            Resp::Integer(b"1".to_vec()),
+                            Resp::Integer(b"4".to_vec()),
+                        ])),
+                    ]))])),
+                    Resp::Bulk(BulkStr::Str(b"len".to_vec())),
+                    Resp::Integer(b"4".to_vec()),
+                ])),
+                _ => Resp::Bulk(BulkStr::Str(b"mytext".to_vec())),
+            }
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend.clone()).await;
+
+        for args in vec![
+            vec!["LCS", "{k}1", "{k}2"],
+            vec!["LCS", "{k}1", "{k}2", "LEN"],
+            vec![
+                "LCS",
+                "{k}1",
+                "{k}2",
+                "IDX",
+                "MINMATCHLEN",
+                "4",
+                "WITHMATCHLEN",
+            ],
+        ] {
+            let reply = client.send_when_ready(&args).await;
+            let expected = backend("", args.iter().map(|s| s.to_string()).collect());
+            assert_eq!(reply, expected);
+        }
+
+        // The slots of "a" and "b" are 15495 and 3300.
+        let reply = client.send_when_ready(&["LCS", "a", "b"]).await;
+        let err = crate::common::response::ERR_NOT_THE_SAME_SLOT;
+        assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_bitfield() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| {
+            let reply = if args[0] == "BITFIELD" {
+                Resp::Arr(Array::Arr(vec![
+                    Resp::Integer(b"1".to_vec()),
+                    Resp::Integer(b"-2".to_vec()),
+                    Resp::Bulk(BulkStr::Nil),
+                ]))
+            } else {
+                Resp::Simple(b"OK".to_vec())
+            };
+            received_clone.lock().unwrap().push(args);
+            reply
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+
+        let args = [
+            "BITFIELD", "key", "GET", "u8", "0", "SET", "i8", "8", "-2", "OVERFLOW", "FAIL",
+            "INCRBY", "u2", "100", "1",
+        ];
+        let reply = client.send_when_ready(&args).await;
+        assert_eq!(
+            reply,
+            Resp::Arr(Array::Arr(vec![
+                Resp::Integer(b"1".to_vec()),
+                Resp::Integer(b"-2".to_vec()),
+                Resp::Bulk(BulkStr::Nil),
+            ]))
+        );
+        let received = received.lock().unwrap();
+        let forwarded = received.last().unwrap();
+        assert_eq!(
+            forwarded,
+            &args.iter().map(|s| s.to_string()).collect::<Vec<_>>()
+        );
+    }
+
+    // The replies of SET with options are relayed as they are instead of being assumed OK.
+    #[tokio::test]
+    async fn test_set_option_replies() {
+        let backend: FakeBackendFunc = Arc::new(|_, args| {
+            let has = |opt: &str| args[3..].iter().any(|a| a.eq_ignore_ascii_case(opt));
+            match args[0].as_str() {
+                "SET" if has("GET") && args[1] == "exists" => {
+                    Resp::Bulk(BulkStr::Str(b"old".to_vec()))
+                }
+                "SET" if has("GET") => Resp::Bulk(BulkStr::Nil),
+                "SET" if has("NX") && args[1] == "exists" => Resp::Bulk(BulkStr::Nil),
+                "SET" if has("XX") && args[1] != "exists" => Resp::Bulk(BulkStr::Nil),
+                _ => Resp::Simple(b"OK".to_vec()),
+            }
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+
+        let ok = Resp::Simple(b"OK".to_vec());
+        let nil = Resp::Bulk(BulkStr::Nil);
+        let old = Resp::Bulk(BulkStr::Str(b"old".to_vec()));
+        let cases: Vec<(&[&str], RespVec)> = vec![
+            (&["SET", "exists", "v", "NX"], nil.clone()),
+            (
+                &["SET", "missing", "v", "NX", "EXAT", "4102444800"],
+                ok.clone(),
+            ),
+            (&["SET", "missing", "v", "XX"], nil.clone()),
+            (&["SET", "exists", "v", "XX", "PXAT", "4102444800000"], ok),
+            (&["SET", "exists", "v", "XX", "GET"], old.clone()),
+            (&["SET", "missing", "v", "XX", "GET"], nil.clone()),
+            (&["SET", "exists", "v", "KEEPTTL", "GET"], old),
+            (&["SET", "missing", "v", "GET"], nil),
+        ];
+        for (args, expected) in cases.into_iter() {
+            let reply = client.send_when_ready(args).await;
+            assert_eq!(reply, expected, "{:?}", args);
+        }
+    }
 }
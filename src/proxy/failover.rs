@@ -0,0 +1,254 @@
+use super::backend::{CmdTask, CmdTaskFactory, CmdTaskResult, ConnFactory};
+use super::cluster::ClusterTag;
+use super::command::{CmdReplyReceiver, TaskReply, TaskResult};
+use super::deadletter::DeadLetters;
+use super::manager::{send_cmd_ctx, SharedMetaMap};
+use super::service::ServerProxyConfig;
+use super::session::{CmdCtx, CmdCtxFactory, SessionContext};
+use super::slot::SlotChangeNotifier;
+use crate::common::cluster::ClusterName;
+use crate::common::response::ERR_BACKEND_CONNECTION;
+use crate::protocol::{Resp, RespPacket, RespVec};
+use futures::future;
+use futures_timer::Delay;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// The connection to the newly promoted master may still be being created.
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+// When a read fails with a backend connection error, e.g. the master is down,
+// wait for the topology change replacing the master of the slot
+// within `failover_retry_timeout` and send the read again to the new master.
+// The writes are never retried since they may have been processed
+// by the old master before the connection was closed.
+pub struct FailoverRetrier<C: ConnFactory<Pkt = RespPacket>> {
+    config: Arc<ServerProxyConfig>,
+    meta_map: SharedMetaMap<C>,
+    dead_letters: Arc<DeadLetters>,
+    slot_change_notifier: Arc<SlotChangeNotifier>,
+    retried_count: AtomicU64,
+}
+
+impl<C: ConnFactory<Pkt = RespPacket>> FailoverRetrier<C> {
+    pub fn new(
+        config: Arc<ServerProxyConfig>,
+        meta_map: SharedMetaMap<C>,
+        dead_letters: Arc<DeadLetters>,
+        slot_change_notifier: Arc<SlotChangeNotifier>,
+    ) -> Self {
+        Self {
+            config,
+            meta_map,
+            dead_letters,
+            slot_change_notifier,
+            retried_count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.get_failover_retry_timeout().is_some()
+    }
+
+    pub fn get_retried_count(&self) -> u64 {
+        self.retried_count.load(Ordering::Relaxed)
+    }
+
+    // Returns None if the command won't be retried.
+    // The topology is only watched after the command fails.
+    pub fn prepare(
+        self: &Arc<Self>,
+        cmd_ctx: &CmdCtx,
+        master_address: Option<&str>,
+    ) -> Option<FailoverRetry<C>> {
+        let timeout = self.config.get_failover_retry_timeout()?;
+        // Only the reads are idempotent.
+        if !cmd_ctx.get_cmd().is_read_only() {
+            return None;
+        }
+        let slot = cmd_ctx.get_slot()?;
+        Some(FailoverRetry {
+            retrier: self.clone(),
+            cluster_name: cmd_ctx.get_cluster_name().clone(),
+            context: cmd_ctx.get_context(),
+            slot,
+            master_address: master_address?.to_string(),
+            timeout,
+        })
+    }
+
+    // Returns None if the slot is no longer served by this proxy
+    // so that the session could redirect the client instead.
+    async fn retry_on_new_master(
+        &self,
+        cluster_name: &ClusterName,
+        context: &SessionContext,
+        resp: RespVec,
+        slot: usize,
+        failed_address: &str,
+    ) -> Option<CmdTaskResult> {
+        // Subscribed before checking the master so that no change will be missed.
+        let mut slot_change = self.slot_change_notifier.subscribe();
+        loop {
+            match self.get_master(cluster_name, slot) {
+                Some(address) if address != failed_address => break,
+                Some(_) => (),
+                None => return None,
+            }
+            slot_change.recv().await?;
+        }
+
+        self.retried_count.fetch_add(1, Ordering::Relaxed);
+        loop {
+            let (retry_cmd_ctx, retry_fut) =
+                CmdCtxFactory::default().create_with_ctx(context.clone(), resp.clone());
+            send_cmd_ctx(
+                &self.meta_map,
+                retry_cmd_ctx,
+                self.config.max_redirections,
+                &self.dead_letters,
+            );
+            let res = retry_fut.await;
+            match &res {
+                Ok(resp) if is_backend_connection_error(resp) => (),
+                _ => return Some(res),
+            }
+            Delay::new(RETRY_INTERVAL).await;
+        }
+    }
+
+    fn get_master(&self, cluster_name: &ClusterName, slot: usize) -> Option<String> {
+        self.meta_map
+            .lease()
+            .get_cluster_map()
+            .get_local_backend(cluster_name, slot)
+            .map(ToString::to_string)
+    }
+}
+
+pub struct FailoverRetry<C: ConnFactory<Pkt = RespPacket>> {
+    retrier: Arc<FailoverRetrier<C>>,
+    cluster_name: ClusterName,
+    context: SessionContext,
+    slot: usize,
+    master_address: String,
+    timeout: Duration,
+}
+
+impl<C: ConnFactory<Pkt = RespPacket>> FailoverRetry<C> {
+    // Waits for the reply of the command sent to the master
+    // and sends it again to the new master on the backend connection error.
+    pub async fn retry_on_failure(self, reply_receiver: CmdReplyReceiver) -> TaskResult {
+        let reply = reply_receiver.await?;
+        if !is_backend_connection_error(&reply.get_resp_slice()) {
+            return Ok(reply);
+        }
+
+        let (request, packet, slowlog) = reply.into_inner();
+        let resp = request.to_resp_vec();
+        let retry_fut = self.retrier.retry_on_new_master(
+            &self.cluster_name,
+            &self.context,
+            resp,
+            self.slot,
+            &self.master_address,
+        );
+        // Falls back to the original error on timeout.
+        let packet = match future::select(Box::pin(retry_fut), Delay::new(self.timeout)).await {
+            future::Either::Left((Some(res), _)) => Box::new(RespPacket::from_resp_vec(res?)),
+            _ => packet,
+        };
+        Ok(Box::new(TaskReply::new(request, packet, slowlog)))
+    }
+}
+
+fn is_backend_connection_error<T: AsRef<[u8]>>(resp: &Resp<T>) -> bool {
+    match resp {
+        Resp::Error(err) => err.as_ref().starts_with(ERR_BACKEND_CONNECTION.as_bytes()),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{BulkStr, Resp};
+    use crate::test_support::{FakeBackendFunc, TestProxy};
+    use futures::future;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio;
+
+    #[tokio::test]
+    async fn test_failover_retry() {
+        use crate::common::response::ERR_BACKEND_CONNECTION;
+
+        // The old master is down and the replica will be promoted.
+        let backend: FakeBackendFunc = Arc::new(|address, args| match address {
+            "127.0.0.1:6379" => {
+                Resp::Error(format!("{} connection closed", ERR_BACKEND_CONNECTION).into_bytes())
+            }
+            _ if args[0] == "GET" => Resp::Bulk(BulkStr::Str(b"v".to_vec())),
+            _ => Resp::Simple(b"OK".to_vec()),
+        });
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+        let mut broker = proxy.connect();
+
+        let reply = broker
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client
+            .send(&["CONFIG", "SET", "failover_retry_timeout", "3000"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        // The writes are not retried.
+        let start = Instant::now();
+        match client.send(&["SET", "a", "v"]).await {
+            Resp::Error(err) => {
+                assert!(err.starts_with(ERR_BACKEND_CONNECTION.as_bytes()))
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(3));
+
+        let promote = async {
+            futures_timer::Delay::new(Duration::from_millis(50)).await;
+            broker
+                .send(&[
+                    "UMCTL",
+                    "SETCLUSTER",
+                    "2",
+                    "NOFLAGS",
+                    "mydb",
+                    "127.0.0.1:6380",
+                    "1",
+                    "0-16383",
+                ])
+                .await
+        };
+        let (reply, promote_reply) = future::join(client.send(&["GET", "a"]), promote).await;
+        assert_eq!(promote_reply, Resp::Simple(b"OK".to_vec()));
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+
+        // The reads served by the new master are not retried.
+        let reply = client.send(&["GET", "a"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+        let info = match client.send(&["INFO"]).await {
+            Resp::Bulk(BulkStr::Str(info)) => String::from_utf8(info).unwrap(),
+            other => panic!("unexpected reply {:?}", other),
+        };
+        assert!(info.contains("failover_retries:1"));
+    }
+}
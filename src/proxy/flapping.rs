@@ -0,0 +1,291 @@
+use super::service::ServerProxyConfig;
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct FlapState {
+    disconnects: VecDeque<Instant>,
+    quarantined_until: Option<Instant>,
+    quarantine_count: u64,
+}
+
+impl FlapState {
+    fn get_remaining(&self, now: Instant) -> Option<Duration> {
+        self.quarantined_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+}
+
+// A backend accepting the connections and closing them right away
+// would otherwise make the proxy reconnect and fail the commands in a loop.
+// When the connections to it get closed `threshold` times within `window`,
+// it's quarantined for `quarantine_duration` without being connected.
+pub struct BackendFlapDetector {
+    address: String,
+    // Zero means disabled.
+    threshold: usize,
+    window: Duration,
+    quarantine_duration: Duration,
+    state: Mutex<FlapState>,
+}
+
+impl BackendFlapDetector {
+    pub fn new(
+        address: String,
+        threshold: usize,
+        window: Duration,
+        quarantine_duration: Duration,
+    ) -> Self {
+        Self {
+            address,
+            threshold,
+            window,
+            quarantine_duration,
+            state: Mutex::new(FlapState::default()),
+        }
+    }
+
+    pub fn disabled(address: String) -> Self {
+        Self::new(address, 0, Duration::from_secs(0), Duration::from_secs(0))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold != 0 && self.quarantine_duration > Duration::from_secs(0)
+    }
+
+    // Called when an established connection is closed.
+    // Returns the remaining quarantine time if the backend is quarantined.
+    pub fn record_disconnect(&self) -> Option<Duration> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let now = Instant::now();
+        let mut state = self
+            .state
+            .lock()
+            .expect("BackendFlapDetector::record_disconnect");
+        // Other connections to the same backend may have quarantined it.
+        if let Some(remaining) = state.get_remaining(now) {
+            return Some(remaining);
+        }
+
+        state.disconnects.push_back(now);
+        while let Some(disconnected_at) = state.disconnects.front() {
+            if now.duration_since(*disconnected_at) <= self.window {
+                break;
+            }
+            state.disconnects.pop_front();
+        }
+        if state.disconnects.len() < self.threshold {
+            return None;
+        }
+
+        state.disconnects.clear();
+        state.quarantined_until = Some(now + self.quarantine_duration);
+        state.quarantine_count += 1;
+        warn!(
+            "backend {} is quarantined for {:?} after {} disconnections within {:?}",
+            self.address, self.quarantine_duration, self.threshold, self.window
+        );
+        Some(self.quarantine_duration)
+    }
+
+    pub fn get_quarantine_remaining(&self) -> Option<Duration> {
+        self.state
+            .lock()
+            .expect("BackendFlapDetector::get_quarantine_remaining")
+            .get_remaining(Instant::now())
+    }
+
+    pub fn is_quarantined(&self) -> bool {
+        self.is_enabled() && self.get_quarantine_remaining().is_some()
+    }
+
+    pub fn get_quarantine_count(&self) -> u64 {
+        self.state
+            .lock()
+            .expect("BackendFlapDetector::get_quarantine_count")
+            .quarantine_count
+    }
+}
+
+pub struct BackendFlapStats {
+    pub address: String,
+    pub quarantined: bool,
+    pub remaining: Duration,
+    pub quarantine_count: u64,
+}
+
+// Shared by all the connections to the same backend.
+pub struct BackendFlapMap {
+    backends: DashMap<String, Weak<BackendFlapDetector>>,
+    threshold: usize,
+    window: Duration,
+    quarantine_duration: Duration,
+}
+
+impl Default for BackendFlapMap {
+    fn default() -> Self {
+        Self::new(0, Duration::from_secs(0), Duration::from_secs(0))
+    }
+}
+
+impl BackendFlapMap {
+    pub fn new(threshold: usize, window: Duration, quarantine_duration: Duration) -> Self {
+        Self {
+            backends: DashMap::new(),
+            threshold,
+            window,
+            quarantine_duration,
+        }
+    }
+
+    pub fn from_config(config: &ServerProxyConfig) -> Self {
+        Self::new(
+            config.backend_flap_threshold,
+            Duration::from_millis(config.backend_flap_window),
+            Duration::from_millis(config.backend_quarantine_duration),
+        )
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold != 0 && self.quarantine_duration > Duration::from_secs(0)
+    }
+
+    pub fn get_or_create(&self, address: &str) -> Arc<BackendFlapDetector> {
+        if let Some(detector) = self.backends.get(address).and_then(|w| w.upgrade()) {
+            return detector;
+        }
+        let mut entry = self
+            .backends
+            .entry(address.to_string())
+            .or_insert_with(Weak::new);
+        if let Some(detector) = entry.upgrade() {
+            return detector;
+        }
+        let detector = Arc::new(BackendFlapDetector::new(
+            address.to_string(),
+            self.threshold,
+            self.window,
+            self.quarantine_duration,
+        ));
+        *entry = Arc::downgrade(&detector);
+        detector
+    }
+
+    pub fn is_quarantined(&self, address: &str) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        self.backends
+            .get(address)
+            .and_then(|w| w.upgrade())
+            .map(|detector| detector.is_quarantined())
+            .unwrap_or(false)
+    }
+
+    // Only includes the backends ever quarantined.
+    // Also removes the backends no longer used.
+    pub fn get_stats(&self) -> Vec<BackendFlapStats> {
+        self.backends.retain(|_, w| w.strong_count() > 0);
+        let mut stats: Vec<_> = self
+            .backends
+            .iter()
+            .filter_map(|item| {
+                let detector = item.value().upgrade()?;
+                let quarantine_count = detector.get_quarantine_count();
+                if quarantine_count == 0 {
+                    return None;
+                }
+                let remaining = detector.get_quarantine_remaining();
+                Some(BackendFlapStats {
+                    address: item.key().clone(),
+                    quarantined: remaining.is_some(),
+                    remaining: remaining.unwrap_or_default(),
+                    quarantine_count,
+                })
+            })
+            .collect();
+        stats.sort_by(|a, b| a.address.cmp(&b.address));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine_flapping_backend() {
+        let detector = BackendFlapDetector::new(
+            "127.0.0.1:6379".to_string(),
+            3,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+        assert!(detector.is_enabled());
+        assert!(detector.record_disconnect().is_none());
+        assert!(detector.record_disconnect().is_none());
+        assert!(!detector.is_quarantined());
+
+        let remaining = detector.record_disconnect().unwrap();
+        assert!(remaining <= Duration::from_secs(60));
+        assert!(detector.is_quarantined());
+        assert_eq!(detector.get_quarantine_count(), 1);
+        // Still the same quarantine.
+        assert!(detector.record_disconnect().is_some());
+        assert_eq!(detector.get_quarantine_count(), 1);
+
+        let disabled = BackendFlapDetector::disabled("127.0.0.1:6379".to_string());
+        for _ in 0..10 {
+            assert!(disabled.record_disconnect().is_none());
+        }
+        assert!(!disabled.is_quarantined());
+    }
+
+    #[test]
+    fn test_flap_window_and_quarantine_expiry() {
+        let detector = BackendFlapDetector::new(
+            "127.0.0.1:6379".to_string(),
+            2,
+            Duration::from_millis(10),
+            Duration::from_millis(10),
+        );
+        assert!(detector.record_disconnect().is_none());
+        std::thread::sleep(Duration::from_millis(20));
+        // The first one is out of the window.
+        assert!(detector.record_disconnect().is_none());
+        assert!(detector.record_disconnect().is_some());
+        assert!(detector.is_quarantined());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!detector.is_quarantined());
+    }
+
+    #[test]
+    fn test_flap_map() {
+        let map = BackendFlapMap::new(1, Duration::from_secs(60), Duration::from_secs(60));
+        assert!(map.is_enabled());
+        assert!(!BackendFlapMap::default().is_enabled());
+
+        let detector = map.get_or_create("127.0.0.1:6379");
+        assert!(Arc::ptr_eq(&detector, &map.get_or_create("127.0.0.1:6379")));
+        let _other = map.get_or_create("127.0.0.1:6380");
+        assert!(map.get_stats().is_empty());
+
+        detector.record_disconnect();
+        assert!(map.is_quarantined("127.0.0.1:6379"));
+        assert!(!map.is_quarantined("127.0.0.1:6380"));
+        let stats = map.get_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].address, "127.0.0.1:6379");
+        assert!(stats[0].quarantined);
+        assert_eq!(stats[0].quarantine_count, 1);
+
+        drop(detector);
+        assert!(!map.is_quarantined("127.0.0.1:6379"));
+        assert!(map.get_stats().is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+use super::backend::{CmdTask, CmdTaskFactory, CmdTaskResult, ConnFactory};
+use super::cluster::ClusterTag;
+use super::manager::SharedMetaMap;
+use super::replica::ReplicaSelector;
+use super::reply::DecompressCommitHandlerFactory;
+use super::sender::{
+    gen_sender_factory, BackendSenderFactory, CmdTaskSender, CmdTaskSenderFactory,
+};
+use super::service::ServerProxyConfig;
+use super::session::{CmdCtx, CmdCtxFactory};
+use crate::common::cluster::ClusterName;
+use crate::common::track::TrackedFutureRegistry;
+use crate::protocol::{RedisClientFactory, Resp, RespPacket, RespVec};
+use crate::replication::manager::ReplicatorManager;
+use crate::replication::replicator::MasterMeta;
+use arc_swap::ArcSwap;
+use futures::{future, Future};
+use futures_timer::Delay;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+type HedgeSenderFactory<C> = BackendSenderFactory<DecompressCommitHandlerFactory<CmdCtx, C>, C>;
+type HedgeSender<C> = <HedgeSenderFactory<C> as CmdTaskSenderFactory>::Sender;
+type ReplyFuture = Pin<Box<dyn Future<Output = CmdTaskResult> + Send + 'static>>;
+
+// When a read gets no reply from the master within `hedged_read_delay`,
+// send the same read to one of its replicas and use whichever replies first.
+// The loser is canceled. It's skipped if it's still queued for the backend,
+// otherwise its reply is discarded.
+pub struct HedgedReader<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> {
+    config: Arc<ServerProxyConfig>,
+    // For the replication lag of the replicas.
+    replicator_manager: Arc<ReplicatorManager<F>>,
+    sender_factory: HedgeSenderFactory<C>,
+    // master address => selector of its replicas
+    selectors: ArcSwap<HashMap<String, Arc<ReplicaSelector>>>,
+    // The backend connections need to be created inside the tokio runtime,
+    // so the senders are lazily initialized.
+    senders: Mutex<HashMap<String, Arc<HedgeSender<C>>>>,
+    hedged_count: AtomicU64,
+}
+
+impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> HedgedReader<F, C> {
+    pub fn new(
+        config: Arc<ServerProxyConfig>,
+        replicator_manager: Arc<ReplicatorManager<F>>,
+        meta_map: SharedMetaMap<C>,
+        conn_factory: Arc<C>,
+        future_registry: Arc<TrackedFutureRegistry>,
+    ) -> Self {
+        let sender_factory = gen_sender_factory(
+            config.clone(),
+            Arc::new(DecompressCommitHandlerFactory::new(meta_map)),
+            conn_factory,
+            future_registry,
+        );
+        Self {
+            config,
+            replicator_manager,
+            sender_factory,
+            selectors: ArcSwap::new(Arc::new(HashMap::new())),
+            senders: Mutex::new(HashMap::new()),
+            hedged_count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.get_hedged_read_delay().is_some()
+    }
+
+    pub fn get_hedged_count(&self) -> u64 {
+        self.hedged_count.load(Ordering::Relaxed)
+    }
+
+    pub fn update_replicas(&self, masters: &[MasterMeta]) {
+        let max_lag = self.config.hedged_read_max_lag;
+        let selectors: HashMap<_, _> = masters
+            .iter()
+            .filter(|master| !master.replicas.is_empty())
+            .map(|master| {
+                let replicas = master
+                    .replicas
+                    .iter()
+                    .map(|peer| (peer.node_address.clone(), 1))
+                    .collect();
+                let selector = ReplicaSelector::new(replicas, max_lag);
+                (master.master_node_address.clone(), Arc::new(selector))
+            })
+            .collect();
+
+        let mut senders = self.senders.lock().expect("HedgedReader::update_replicas");
+        senders.retain(|address, _| {
+            masters
+                .iter()
+                .flat_map(|master| master.replicas.iter())
+                .any(|peer| &peer.node_address == address)
+        });
+        self.selectors.store(Arc::new(selectors));
+    }
+
+    // Returns the command to be sent to the master.
+    // If it's hedged, the returned one is a copy and the reply
+    // of the original command will be set by the hedging task.
+    pub fn try_hedge(self: &Arc<Self>, cmd_ctx: CmdCtx, master_address: Option<&str>) -> CmdCtx {
+        let delay = match self.config.get_hedged_read_delay() {
+            Some(delay) => delay,
+            None => return cmd_ctx,
+        };
+        // Only the reads are idempotent.
+        if !cmd_ctx.get_cmd().is_read_only() {
+            return cmd_ctx;
+        }
+        let (master_address, selector) = match master_address.and_then(|address| {
+            let selector = self.selectors.load().get(address).cloned()?;
+            Some((address.to_string(), selector))
+        }) {
+            Some(res) => res,
+            None => return cmd_ctx,
+        };
+
+        let resp = cmd_ctx.get_cmd().get_packet().into_resp_vec();
+        let (primary_cmd_ctx, primary_fut) =
+            CmdCtxFactory::default().create_with_ctx(cmd_ctx.get_context(), resp.clone());
+
+        let reader = self.clone();
+        tokio::spawn(async move {
+            let res = match future::select(primary_fut, Delay::new(delay)).await {
+                future::Either::Left((res, _)) => res,
+                future::Either::Right((_, primary_fut)) => {
+                    match reader.send_to_replica(&selector, &master_address, &cmd_ctx, resp) {
+                        Some(replica_fut) => wait_first_reply(primary_fut, replica_fut).await,
+                        None => primary_fut.await,
+                    }
+                }
+            };
+            cmd_ctx.set_resp_result(res);
+        });
+        primary_cmd_ctx
+    }
+
+    fn send_to_replica(
+        &self,
+        selector: &ReplicaSelector,
+        master_address: &str,
+        cmd_ctx: &CmdCtx,
+        resp: RespVec,
+    ) -> Option<ReplyFuture> {
+        let address = self.select_replica(selector, cmd_ctx.get_cluster_name(), master_address)?;
+        let (replica_cmd_ctx, replica_fut) =
+            CmdCtxFactory::default().create_with_ctx(cmd_ctx.get_context(), resp);
+        if let Err(err) = self.get_sender(&address).send(replica_cmd_ctx) {
+            debug!("failed to send hedged read to replica: {:?}", err);
+            return None;
+        }
+        self.hedged_count.fetch_add(1, Ordering::Relaxed);
+        Some(replica_fut)
+    }
+
+    // Sends the read to one of the replicas without waiting for the master,
+    // for the master being unavailable, e.g. quarantined.
+    // Returns the command back if the master has no replica.
+    pub fn send_to_replica_directly(
+        &self,
+        cmd_ctx: CmdCtx,
+        master_address: &str,
+    ) -> Result<(), CmdCtx> {
+        let address = match self
+            .selectors
+            .load()
+            .get(master_address)
+            .and_then(|selector| {
+                self.select_replica(selector, cmd_ctx.get_cluster_name(), master_address)
+            }) {
+            Some(address) => address,
+            None => return Err(cmd_ctx),
+        };
+        // The error is replied by the sender.
+        if let Err(err) = self.get_sender(&address).send(cmd_ctx) {
+            debug!("failed to send read to replica: {:?}", err);
+        }
+        Ok(())
+    }
+
+    // The lags are refreshed from the latest `ROLE` of the master before selecting.
+    // The replicas not online on the master are skipped.
+    fn select_replica(
+        &self,
+        selector: &ReplicaSelector,
+        cluster_name: &ClusterName,
+        master_address: &str,
+    ) -> Option<String> {
+        let state = self
+            .replicator_manager
+            .get_replica_state(cluster_name, master_address);
+        selector.update_lags(|address| {
+            state
+                .as_ref()
+                .and_then(|state| state.get_lag(address))
+                .unwrap_or(u64::MAX)
+        });
+        selector.select()
+    }
+
+    // The connections are recreated by the next hedged read.
+    pub fn clear_senders(&self) {
+        let mut senders = self.senders.lock().expect("HedgedReader::clear_senders");
+        senders.clear();
+        self.sender_factory.clear();
+    }
+
+    fn get_sender(&self, address: &str) -> Arc<HedgeSender<C>> {
+        let mut senders = self.senders.lock().expect("HedgedReader::get_sender");
+        if let Some(sender) = senders.get(address) {
+            return sender.clone();
+        }
+        let sender = Arc::new(self.sender_factory.create(address.to_string()));
+        senders.insert(address.to_string(), sender.clone());
+        sender
+    }
+}
+
+// The error replies of the replica, e.g. LOADING or a connection error,
+// fall back to the reply of the master.
+async fn wait_first_reply(primary_fut: ReplyFuture, replica_fut: ReplyFuture) -> CmdTaskResult {
+    match future::select(primary_fut, replica_fut).await {
+        future::Either::Left((res, _)) => res,
+        future::Either::Right((Ok(Resp::Error(_)), primary_fut))
+        | future::Either::Right((Err(_), primary_fut)) => primary_fut.await,
+        future::Either::Right((res, _)) => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::command::CommandError;
+    use futures::channel::oneshot;
+    use futures::TryFutureExt;
+
+    fn gen_reply_fut() -> (oneshot::Sender<CmdTaskResult>, ReplyFuture) {
+        let (sender, receiver) = oneshot::channel();
+        let fut = receiver.unwrap_or_else(|_| Err(CommandError::Canceled));
+        (sender, Box::pin(fut))
+    }
+
+    #[tokio::test]
+    async fn test_replica_replies_first() {
+        let (primary_sender, primary_fut) = gen_reply_fut();
+        let (replica_sender, replica_fut) = gen_reply_fut();
+        replica_sender
+            .send(Ok(Resp::Simple(b"replica".to_vec())))
+            .unwrap();
+        let res = wait_first_reply(primary_fut, replica_fut).await;
+        assert_eq!(res.unwrap(), Resp::Simple(b"replica".to_vec()));
+        // The loser is canceled.
+        assert!(primary_sender.is_canceled());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_on_replica_error() {
+        let (primary_sender, primary_fut) = gen_reply_fut();
+        let (replica_sender, replica_fut) = gen_reply_fut();
+        replica_sender
+            .send(Ok(Resp::Error(b"LOADING".to_vec())))
+            .unwrap();
+        let fut = wait_first_reply(primary_fut, replica_fut);
+        primary_sender
+            .send(Ok(Resp::Simple(b"primary".to_vec())))
+            .unwrap();
+        assert_eq!(fut.await.unwrap(), Resp::Simple(b"primary".to_vec()));
+
+        let (primary_sender, primary_fut) = gen_reply_fut();
+        let (replica_sender, replica_fut) = gen_reply_fut();
+        drop(replica_sender);
+        primary_sender
+            .send(Ok(Resp::Simple(b"primary".to_vec())))
+            .unwrap();
+        let res = wait_first_reply(primary_fut, replica_fut).await;
+        assert_eq!(res.unwrap(), Resp::Simple(b"primary".to_vec()));
+    }
+}
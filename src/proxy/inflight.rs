@@ -0,0 +1,342 @@
+use super::service::ServerProxyConfig;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::Notify;
+
+// The commands written to a backend with their replies not received yet.
+// The blocking commands like BLPOP are not counted
+// since they could stay in the backend for a long time.
+#[derive(Debug)]
+pub struct BackendInFlight {
+    depth: AtomicUsize,
+    // Zero means no limit.
+    max_in_flight: usize,
+    released: Notify,
+}
+
+impl BackendInFlight {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            depth: AtomicUsize::new(0),
+            max_in_flight,
+            released: Notify::new(),
+        }
+    }
+
+    pub fn add(&self, num: usize) {
+        self.depth.fetch_add(num, Ordering::SeqCst);
+    }
+
+    pub fn remove(&self, num: usize) {
+        self.depth.fetch_sub(num, Ordering::SeqCst);
+        if self.max_in_flight != 0 && num != 0 {
+            self.released.notify();
+        }
+    }
+
+    // Called by the backend connections before writing the next batch
+    // and by the sessions which sent commands to this backend when it's saturated.
+    pub async fn wait_for_capacity(&self) {
+        let mut waited = false;
+        while self.is_saturated() {
+            self.released.notified().await;
+            waited = true;
+        }
+        // Only one waiter is woken up each time.
+        // Pass it on to the other connections of the same backend.
+        if waited {
+            self.released.notify();
+        }
+    }
+
+    // Released when the replies of the batch are received or the batch fails.
+    pub fn release_on_drop(self: &Arc<Self>, num: usize) -> InFlightBatchGuard {
+        InFlightBatchGuard {
+            in_flight: self.clone(),
+            num,
+        }
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+
+    pub fn get_max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.max_in_flight != 0 && self.get_depth() >= self.max_in_flight
+    }
+
+    // The number of the commands which could still be written.
+    pub fn get_remaining(&self) -> usize {
+        if self.max_in_flight == 0 {
+            return usize::max_value();
+        }
+        self.max_in_flight.saturating_sub(self.get_depth())
+    }
+}
+
+// The saturated backends which the session has sent commands to.
+// The session waits for them before reading more requests
+// so that the commands are not piled up in the sender queues of the backends.
+#[derive(Debug, Default)]
+pub struct SaturatedBackends {
+    backends: Mutex<Vec<Arc<BackendInFlight>>>,
+}
+
+impl SaturatedBackends {
+    pub fn add(&self, in_flight: Arc<BackendInFlight>) {
+        let mut backends = self.backends.lock().expect("SaturatedBackends::add");
+        if !backends.iter().any(|b| Arc::ptr_eq(b, &in_flight)) {
+            backends.push(in_flight);
+        }
+    }
+
+    pub async fn wait_for_capacity(&self) {
+        let backends: Vec<_> = {
+            let mut backends = self
+                .backends
+                .lock()
+                .expect("SaturatedBackends::wait_for_capacity");
+            backends.drain(..).collect()
+        };
+        for in_flight in backends.iter() {
+            in_flight.wait_for_capacity().await;
+        }
+    }
+}
+
+pub struct InFlightBatchGuard {
+    in_flight: Arc<BackendInFlight>,
+    num: usize,
+}
+
+impl Drop for InFlightBatchGuard {
+    fn drop(&mut self) {
+        self.in_flight.remove(self.num);
+    }
+}
+
+pub struct BackendInFlightStats {
+    pub address: String,
+    pub depth: usize,
+    pub max_in_flight: usize,
+}
+
+// Shared by all the connections to the same backend.
+// A connection only writes the part of a batch below the cap
+// and keeps the rest for the next write.
+// The cap could still be exceeded slightly by the connections writing at the same time.
+#[derive(Default)]
+pub struct BackendInFlightMap {
+    backends: DashMap<String, Weak<BackendInFlight>>,
+    // Zero means no limit.
+    default_max_in_flight: usize,
+    // (backend address, max in-flight commands)
+    overrides: Vec<(String, usize)>,
+}
+
+impl BackendInFlightMap {
+    pub fn new(default_max_in_flight: usize, overrides: Vec<(String, usize)>) -> Self {
+        Self {
+            backends: DashMap::new(),
+            default_max_in_flight,
+            overrides,
+        }
+    }
+
+    pub fn from_config(config: &ServerProxyConfig) -> Self {
+        Self::new(
+            config.backend_max_in_flight,
+            config.backend_max_in_flight_overrides.clone(),
+        )
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.default_max_in_flight != 0 || self.overrides.iter().any(|(_, max)| *max != 0)
+    }
+
+    fn get_max_in_flight(&self, address: &str) -> usize {
+        self.overrides
+            .iter()
+            .find(|(addr, _)| addr == address)
+            .map(|(_, max)| *max)
+            .unwrap_or(self.default_max_in_flight)
+    }
+
+    pub fn get_or_create(&self, address: &str) -> Arc<BackendInFlight> {
+        if let Some(in_flight) = self.backends.get(address).and_then(|w| w.upgrade()) {
+            return in_flight;
+        }
+        let mut entry = self
+            .backends
+            .entry(address.to_string())
+            .or_insert_with(Weak::new);
+        if let Some(in_flight) = entry.upgrade() {
+            return in_flight;
+        }
+        let in_flight = Arc::new(BackendInFlight::new(self.get_max_in_flight(address)));
+        *entry = Arc::downgrade(&in_flight);
+        in_flight
+    }
+
+    // Also removes the backends no longer used.
+    pub fn get_stats(&self) -> Vec<BackendInFlightStats> {
+        self.backends.retain(|_, w| w.strong_count() > 0);
+        let mut stats: Vec<_> = self
+            .backends
+            .iter()
+            .filter_map(|item| {
+                item.value()
+                    .upgrade()
+                    .map(|in_flight| BackendInFlightStats {
+                        address: item.key().clone(),
+                        depth: in_flight.get_depth(),
+                        max_in_flight: in_flight.get_max_in_flight(),
+                    })
+            })
+            .collect();
+        stats.sort_by(|a, b| a.address.cmp(&b.address));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{BulkStr, Resp};
+    use crate::test_support::{gen_test_config, setup_proxy_with_config, FakeBackendFunc};
+    use futures::future;
+    use futures_timer::Delay;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_wait_for_capacity() {
+        let in_flight = Arc::new(BackendInFlight::new(2));
+        in_flight.add(2);
+        let wait_fut = in_flight.wait_for_capacity();
+        let wait_fut =
+            match future::select(Box::pin(wait_fut), Delay::new(Duration::from_millis(20))).await {
+                future::Either::Left(_) => panic!("should wait for the capacity"),
+                future::Either::Right((_, wait_fut)) => wait_fut,
+            };
+        in_flight.remove(1);
+        timeout(Duration::from_secs(5), wait_fut).await.unwrap();
+
+        // Never waits without the limit.
+        let unlimited = BackendInFlight::new(0);
+        unlimited.add(1);
+        timeout(Duration::from_secs(5), unlimited.wait_for_capacity())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_in_flight_depth() {
+        let in_flight = Arc::new(BackendInFlight::new(2));
+        in_flight.add(1);
+        assert!(!in_flight.is_saturated());
+        in_flight.add(1);
+        assert!(in_flight.is_saturated());
+        {
+            let _guard = in_flight.release_on_drop(2);
+            assert_eq!(in_flight.get_depth(), 2);
+        }
+        assert_eq!(in_flight.get_depth(), 0);
+        assert!(!in_flight.is_saturated());
+
+        let unlimited = BackendInFlight::new(0);
+        unlimited.add(1);
+        assert!(!unlimited.is_saturated());
+        assert_eq!(unlimited.get_remaining(), usize::max_value());
+
+        in_flight.add(1);
+        assert_eq!(in_flight.get_remaining(), 1);
+        in_flight.add(2);
+        assert_eq!(in_flight.get_remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_saturated_backends() {
+        let in_flight = Arc::new(BackendInFlight::new(1));
+        in_flight.add(1);
+        let saturated_backends = SaturatedBackends::default();
+        saturated_backends.add(in_flight.clone());
+        saturated_backends.add(in_flight.clone());
+
+        let wait_fut = saturated_backends.wait_for_capacity();
+        let wait_fut =
+            match future::select(Box::pin(wait_fut), Delay::new(Duration::from_millis(20))).await {
+                future::Either::Left(_) => panic!("should wait for the backend"),
+                future::Either::Right((_, wait_fut)) => wait_fut,
+            };
+        in_flight.remove(1);
+        timeout(Duration::from_secs(5), wait_fut).await.unwrap();
+
+        // Cleared after waiting.
+        in_flight.add(1);
+        timeout(
+            Duration::from_secs(5),
+            saturated_backends.wait_for_capacity(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_in_flight_map() {
+        let map = BackendInFlightMap::new(10, vec![("127.0.0.1:7000".to_string(), 1)]);
+        assert!(map.is_enabled());
+        assert!(!BackendInFlightMap::default().is_enabled());
+
+        let default_backend = map.get_or_create("127.0.0.1:6379");
+        let slow_backend = map.get_or_create("127.0.0.1:7000");
+        assert!(Arc::ptr_eq(
+            &slow_backend,
+            &map.get_or_create("127.0.0.1:7000")
+        ));
+        assert_eq!(default_backend.get_max_in_flight(), 10);
+        assert_eq!(slow_backend.get_max_in_flight(), 1);
+
+        slow_backend.add(1);
+        assert!(slow_backend.is_saturated());
+        assert!(!default_backend.is_saturated());
+
+        let stats = map.get_stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[1].address, "127.0.0.1:7000");
+        assert_eq!(stats[1].depth, 1);
+
+        drop(slow_backend);
+        assert_eq!(map.get_stats().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_backend_in_flight_stats() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let mut config = gen_test_config();
+        config.backend_max_in_flight = 100;
+        config.backend_max_in_flight_overrides = vec![("127.0.0.1:6379".to_string(), 10)];
+        let (_proxy, mut client) = setup_proxy_with_config(config, backend).await;
+        let reply = client.send_when_ready(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client.send(&["INFO", "stats"]).await;
+        let info = match reply {
+            Resp::Bulk(BulkStr::Str(info)) => String::from_utf8(info).unwrap(),
+            other => panic!("unexpected reply {:?}", other),
+        };
+        let line = info
+            .lines()
+            .find(|line| line.starts_with("backend_in_flight0:"))
+            .unwrap();
+        assert!(line.contains("address=127.0.0.1:6379,"));
+        assert!(line.ends_with(",max_in_flight=10"));
+    }
+}
@@ -0,0 +1,250 @@
+use super::command::{CmdType, Command, TaskReply, TaskResult};
+use super::session::CmdReplyFuture;
+use crate::protocol::{Array, BulkStr, Resp, RespPacket, RespVec};
+
+// The commands whose replies start with the key, e.g. [key, value] of BLPOP.
+const FIRST_KEY_REPLY_COMMANDS: [&str; 8] = [
+    "BLPOP", "BRPOP", "BZPOPMIN", "BZPOPMAX", "LMPOP", "BLMPOP", "ZMPOP", "BZMPOP",
+];
+
+// Adds the key prefix of the database to the keys of the command,
+// so that the databases sharing the same backends won't see the keys of each other.
+// The patterns of KEYS and SCAN are also prefixed.
+pub fn add_key_prefix(cmd: &mut Command, prefix: &str) {
+    if cmd.get_type() != CmdType::Others && cmd.get_type() != CmdType::Debug {
+        return;
+    }
+    let (is_keys, is_scan) = match cmd.get_command_name() {
+        Some(cmd_name) => (
+            cmd_name.eq_ignore_ascii_case("KEYS"),
+            cmd_name.eq_ignore_ascii_case("SCAN"),
+        ),
+        None => return,
+    };
+    if is_keys {
+        // The pattern is taken as the key.
+        cmd.add_key_prefix(&prefix_pattern(prefix, b""));
+    } else if is_scan {
+        prefix_scan_pattern(cmd, prefix);
+    } else {
+        cmd.add_key_prefix(prefix.as_bytes());
+    }
+}
+
+// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+fn prefix_scan_pattern(cmd: &mut Command, prefix: &str) {
+    let len = cmd.get_command_len().unwrap_or(0);
+    let match_index = (2..len).step_by(2).find(|i| {
+        cmd.get_command_element(*i)
+            .map(|option| option.eq_ignore_ascii_case(b"MATCH"))
+            .unwrap_or(false)
+    });
+    let pattern = match_index.and_then(|i| {
+        cmd.get_command_element(i + 1)
+            .map(|pattern| (i + 1, prefix_pattern(prefix, pattern)))
+    });
+    if let Some((pattern_index, pattern)) = pattern {
+        cmd.change_element(pattern_index, pattern);
+        return;
+    }
+
+    let mut pattern = prefix_pattern(prefix, b"");
+    pattern.push(b'*');
+    let mut elements: Vec<RespVec> = (0..len)
+        .filter_map(|i| cmd.get_command_element(i))
+        .map(|element| Resp::Bulk(BulkStr::Str(element.to_vec())))
+        .collect();
+    elements.push(Resp::Bulk(BulkStr::Str(b"MATCH".to_vec())));
+    elements.push(Resp::Bulk(BulkStr::Str(pattern)));
+    let packet = RespPacket::from_resp_vec(Resp::Arr(Array::Arr(elements)));
+    *cmd = Command::new(Box::new(packet));
+}
+
+// The glob characters in the prefix are escaped.
+fn prefix_pattern(prefix: &str, pattern: &[u8]) -> Vec<u8> {
+    let mut prefixed = Vec::with_capacity(prefix.len() + pattern.len());
+    for b in prefix.as_bytes() {
+        if b"*?[]\\".contains(b) {
+            prefixed.push(b'\\');
+        }
+        prefixed.push(*b);
+    }
+    prefixed.extend_from_slice(pattern);
+    prefixed
+}
+
+pub fn echoes_keys(cmd: &Command) -> bool {
+    let cmd_name = match cmd.get_command_name() {
+        Some(cmd_name) => cmd_name,
+        None => return false,
+    };
+    cmd_name.eq_ignore_ascii_case("KEYS")
+        || cmd_name.eq_ignore_ascii_case("SCAN")
+        || FIRST_KEY_REPLY_COMMANDS
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(cmd_name))
+}
+
+// Removes the key prefix from the keys in the replies of the commands of `echoes_keys`.
+pub async fn strip_reply_key_prefix(reply_fut: CmdReplyFuture<'_>, prefix: &str) -> TaskResult {
+    let task_reply = reply_fut.await?;
+    let (request, packet, slowlog) = task_reply.into_inner();
+    let cmd_name = request.get_command_name().unwrap_or("").to_uppercase();
+    let mut resp = packet.into_resp_vec();
+    strip_keys(&cmd_name, &mut resp, prefix.as_bytes());
+    let packet = Box::new(RespPacket::from_resp_vec(resp));
+    Ok(Box::new(TaskReply::new(request, packet, slowlog)))
+}
+
+fn strip_keys(cmd_name: &str, resp: &mut RespVec, prefix: &[u8]) {
+    let elements = match resp {
+        Resp::Arr(Array::Arr(elements)) => elements,
+        _ => return,
+    };
+    match cmd_name {
+        "KEYS" => elements.iter_mut().for_each(|key| strip_key(key, prefix)),
+        // [cursor, [key ...]]
+        "SCAN" => {
+            if let Some(Resp::Arr(Array::Arr(keys))) = elements.get_mut(1) {
+                keys.iter_mut().for_each(|key| strip_key(key, prefix));
+            }
+        }
+        _ => {
+            if let Some(key) = elements.first_mut() {
+                strip_key(key, prefix);
+            }
+        }
+    }
+}
+
+fn strip_key(key: &mut RespVec, prefix: &[u8]) {
+    if let Resp::Bulk(BulkStr::Str(key)) = key {
+        if key.starts_with(prefix) {
+            key.drain(..prefix.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils::generate_slot;
+    use crate::protocol::{Array, BulkStr, Resp};
+    use crate::test_support::{gen_test_config, setup_proxy_with_config, FakeBackendFunc};
+    use std::sync::{Arc, Mutex};
+    use tokio;
+
+    fn gen_cmd(args: &[&str]) -> Command {
+        let args = args
+            .iter()
+            .map(|arg| Resp::Bulk(BulkStr::Str(arg.as_bytes().to_vec())))
+            .collect();
+        let resp = Resp::Arr(Array::Arr(args));
+        Command::new(Box::new(RespPacket::from_resp_vec(resp)))
+    }
+
+    fn get_args(cmd: &Command) -> Vec<String> {
+        (0..cmd.get_command_len().unwrap())
+            .map(|i| String::from_utf8(cmd.get_command_element(i).unwrap().to_vec()).unwrap())
+            .collect()
+    }
+
+    fn check(args: &[&str], expected: &[&str]) {
+        let mut cmd = gen_cmd(args);
+        add_key_prefix(&mut cmd, "t1:");
+        assert_eq!(get_args(&cmd), expected);
+    }
+
+    #[test]
+    fn test_add_key_prefix() {
+        check(&["GET", "key"], &["GET", "t1:key"]);
+        check(
+            &["MSET", "a", "1", "b", "2"],
+            &["MSET", "t1:a", "1", "t1:b", "2"],
+        );
+        check(
+            &["EVAL", "script", "2", "a", "b", "arg"],
+            &["EVAL", "script", "2", "t1:a", "t1:b", "arg"],
+        );
+        check(&["PING"], &["PING"]);
+        check(&["KEYS", "a*"], &["KEYS", "t1:a*"]);
+        check(&["SCAN", "0"], &["SCAN", "0", "MATCH", "t1:*"]);
+        check(
+            &["SCAN", "0", "count", "10", "match", "a*"],
+            &["SCAN", "0", "count", "10", "match", "t1:a*"],
+        );
+
+        let mut cmd = gen_cmd(&["KEYS", "*"]);
+        add_key_prefix(&mut cmd, "a*b:");
+        assert_eq!(get_args(&cmd), &["KEYS", "a\\*b:*"]);
+
+        let mut cmd = gen_cmd(&["GET", "key"]);
+        add_key_prefix(&mut cmd, "t1:");
+        assert_eq!(cmd.get_slot(), Some(generate_slot(b"t1:key")));
+    }
+
+    #[test]
+    fn test_strip_keys() {
+        let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.as_bytes().to_vec()));
+        let mut resp = Resp::Arr(Array::Arr(vec![bulk("t1:a"), bulk("b")]));
+        strip_keys("KEYS", &mut resp, b"t1:");
+        assert_eq!(resp, Resp::Arr(Array::Arr(vec![bulk("a"), bulk("b")])));
+
+        let mut resp = Resp::Arr(Array::Arr(vec![
+            bulk("17"),
+            Resp::Arr(Array::Arr(vec![bulk("t1:a"), bulk("t1:b")])),
+        ]));
+        strip_keys("SCAN", &mut resp, b"t1:");
+        assert_eq!(
+            resp,
+            Resp::Arr(Array::Arr(vec![
+                bulk("17"),
+                Resp::Arr(Array::Arr(vec![bulk("a"), bulk("b")])),
+            ]))
+        );
+
+        let mut resp = Resp::Arr(Array::Arr(vec![bulk("t1:list"), bulk("t1:value")]));
+        strip_keys("BLPOP", &mut resp, b"t1:");
+        assert_eq!(
+            resp,
+            Resp::Arr(Array::Arr(vec![bulk("list"), bulk("t1:value")]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_db_key_prefix() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| {
+            let reply = match args[0].as_str() {
+                "KEYS" => Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Str(
+                    b"t1:key".to_vec(),
+                ))])),
+                _ => Resp::Simple(b"OK".to_vec()),
+            };
+            received_clone.lock().unwrap().push(args);
+            reply
+        });
+        let mut config = gen_test_config();
+        config.db_key_prefixes = crate::proxy::service::parse_db_key_prefixes("mydb=t1:").unwrap();
+        let (_proxy, mut client) = setup_proxy_with_config(config, backend).await;
+        let reply = client.send(&["AUTH", "mydb"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client.send_when_ready(&["SET", "key", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send(&["KEYS", "k*"]).await;
+        assert_eq!(
+            reply,
+            Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Str(b"key".to_vec()))]))
+        );
+
+        let received = received.lock().unwrap();
+        assert!(received.contains(&vec![
+            "SET".to_string(),
+            "t1:key".to_string(),
+            "v".to_string()
+        ]));
+        assert!(received.contains(&vec!["KEYS".to_string(), "t1:k*".to_string()]));
+    }
+}
@@ -0,0 +1,558 @@
+use super::command::DataCmdType;
+use crate::common::response;
+use crate::common::utils::generate_slot;
+use crate::protocol::RespPacket;
+use std::cmp::{max, min};
+use std::convert::TryFrom;
+
+pub type KeyExtractor = fn(&RespPacket) -> Vec<&[u8]>;
+
+// Where the keys are in a command, like the key specs of the redis COMMAND.
+// The index 0 is the command name.
+#[derive(Debug, Clone, Copy)]
+pub enum KeySpec {
+    NoKey,
+    // From `first` to `last` with `step`.
+    // A negative `last` counts from the end, e.g. -1 is the last argument.
+    Range {
+        first: usize,
+        last: isize,
+        step: usize,
+    },
+    // `numkeys` at the index followed by the keys.
+    NumKeys(usize),
+    Custom(KeyExtractor),
+}
+
+impl KeySpec {
+    pub fn get_keys<'a>(&self, packet: &'a RespPacket) -> Vec<&'a [u8]> {
+        match self {
+            Self::NoKey => vec![],
+            Self::Range { first, last, step } => {
+                let len = packet.get_array_len().unwrap_or(0);
+                match resolve_last_index(*last, len) {
+                    Some(last) if *first <= last => (*first..=last)
+                        .step_by(max(*step, 1))
+                        .filter_map(|i| packet.get_array_element(i))
+                        .collect(),
+                    _ => vec![],
+                }
+            }
+            Self::NumKeys(numkeys_index) => {
+                get_numkeys_keys(packet, *numkeys_index).unwrap_or_default()
+            }
+            Self::Custom(extractor) => extractor(packet),
+        }
+    }
+
+    // The key used for routing.
+    // For the commands without enough arguments, it's still the argument
+    // at the key position so that the backend replies the error.
+    pub fn get_first_key<'a>(&self, packet: &'a RespPacket) -> Option<&'a [u8]> {
+        match self {
+            Self::NoKey => None,
+            Self::Range { first, .. } => packet.get_array_element(*first),
+            Self::NumKeys(numkeys_index) => packet.get_array_element(numkeys_index + 1),
+            Self::Custom(extractor) => extractor(packet).into_iter().next(),
+        }
+    }
+}
+
+fn resolve_last_index(last: isize, len: usize) -> Option<usize> {
+    if last >= 0 {
+        Some(min(last as usize, len.checked_sub(1)?))
+    } else {
+        usize::try_from(len as isize + last).ok()
+    }
+}
+
+// The key specs of the commands known by the proxy.
+// The unknown commands take the first argument as the key.
+pub fn get_builtin_key_spec(data_cmd_type: DataCmdType) -> KeySpec {
+    let range = |first, last, step| KeySpec::Range { first, last, step };
+    match data_cmd_type {
+        DataCmdType::MGET | DataCmdType::DEL | DataCmdType::EXISTS | DataCmdType::UNLINK => {
+            range(1, -1, 1)
+        }
+        DataCmdType::MSET | DataCmdType::MSETNX => range(1, -1, 2),
+        // PFMERGE destkey sourcekey [sourcekey ...]
+        DataCmdType::PFCOUNT | DataCmdType::PFMERGE => range(1, -1, 1),
+        // BLPOP key [key ...] timeout
+        DataCmdType::BLPOP | DataCmdType::BRPOP => range(1, -2, 1),
+        DataCmdType::BRPOPLPUSH
+        | DataCmdType::RPOPLPUSH
+        | DataCmdType::RENAME
+        | DataCmdType::RENAMENX
+        | DataCmdType::SMOVE
+        | DataCmdType::LCS => range(1, 2, 1),
+        // GEOSEARCHSTORE destination source [options]
+        DataCmdType::GEOSEARCHSTORE => range(1, 2, 1),
+        // HEXPIRE key seconds [NX | XX | GT | LT] FIELDS numfields field [field ...]
+        // HGETEX key [EX seconds | ... | PERSIST] FIELDS numfields field [field ...]
+        // The hash key always precedes the options and the FIELDS section,
+        // so the field names are never taken as keys.
+        DataCmdType::HEXPIRE
+        | DataCmdType::HPEXPIRE
+        | DataCmdType::HEXPIREAT
+        | DataCmdType::HPEXPIREAT
+        | DataCmdType::HEXPIRETIME
+        | DataCmdType::HPEXPIRETIME
+        | DataCmdType::HTTL
+        | DataCmdType::HPTTL
+        | DataCmdType::HPERSIST
+        | DataCmdType::HGETEX
+        | DataCmdType::HGETDEL
+        | DataCmdType::HSETEX => range(1, 1, 1),
+        // EVAL script numkeys key [key ...] arg [arg ...]
+        DataCmdType::EVAL | DataCmdType::EVALSHA => KeySpec::NumKeys(2),
+        // SINTERCARD numkeys key [key ...] [LIMIT limit]
+        // LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+        // ZMPOP numkeys key [key ...] MIN|MAX [COUNT count]
+        DataCmdType::SINTERCARD | DataCmdType::LMPOP | DataCmdType::ZMPOP => KeySpec::NumKeys(1),
+        DataCmdType::GEORADIUS => KeySpec::Custom(get_georadius_keys),
+        DataCmdType::GEORADIUSBYMEMBER => KeySpec::Custom(get_georadiusbymember_keys),
+        DataCmdType::MIGRATE => KeySpec::Custom(get_migrate_keys),
+        DataCmdType::XGROUP => KeySpec::Custom(get_xgroup_keys),
+        DataCmdType::SORT | DataCmdType::SORT_RO => KeySpec::Custom(get_sort_keys),
+        // No key. Will be routed by the last write of the session.
+        DataCmdType::WAIT | DataCmdType::WAITAOF => KeySpec::NoKey,
+        _ => range(1, 1, 1),
+    }
+}
+
+// For the commands like `SINTERCARD numkeys key [key ...] [options]`.
+pub fn get_numkeys_keys(
+    packet: &RespPacket,
+    numkeys_index: usize,
+) -> Result<Vec<&[u8]>, &'static str> {
+    let numkeys = packet
+        .get_array_element(numkeys_index)
+        .and_then(|n| btoi::btou::<usize>(n).ok())
+        .filter(|n| *n > 0)
+        .ok_or("ERR numkeys should be greater than 0")?;
+    let len = packet.get_array_len().unwrap_or(0);
+    if numkeys_index + numkeys >= len {
+        return Err("ERR Number of keys can't be greater than number of args");
+    }
+    Ok((numkeys_index + 1..=numkeys_index + numkeys)
+        .filter_map(|i| packet.get_array_element(i))
+        .collect())
+}
+
+// Returns (source key, destination key) if the STORE or STOREDIST option exists.
+// Same as Redis, the last one takes effect when there are more than one.
+pub fn get_geo_store_keys(
+    packet: &RespPacket,
+    src_index: usize,
+    options_start: usize,
+) -> Option<(&[u8], &[u8])> {
+    let src = packet.get_array_element(src_index)?;
+    let len = packet.get_array_len()?;
+    let mut dst = None;
+    let mut i = options_start;
+    while i < len {
+        let option = packet.get_array_element(i)?;
+        if option.eq_ignore_ascii_case(b"STORE") || option.eq_ignore_ascii_case(b"STOREDIST") {
+            dst = Some(packet.get_array_element(i + 1)?);
+            i += 1;
+        }
+        i += 1;
+    }
+    dst.map(|dst| (src, dst))
+}
+
+// GEORADIUS key longitude latitude radius unit [options]
+fn get_georadius_keys(packet: &RespPacket) -> Vec<&[u8]> {
+    match get_geo_store_keys(packet, 1, 6) {
+        Some((src, dst)) => vec![src, dst],
+        None => packet.get_array_element(1).into_iter().collect(),
+    }
+}
+
+// GEORADIUSBYMEMBER key member radius unit [options]
+fn get_georadiusbymember_keys(packet: &RespPacket) -> Vec<&[u8]> {
+    match get_geo_store_keys(packet, 1, 5) {
+        Some((src, dst)) => vec![src, dst],
+        None => packet.get_array_element(1).into_iter().collect(),
+    }
+}
+
+// MIGRATE host port key|"" destination-db timeout [options] [KEYS key [key ...]]
+fn get_migrate_keys(packet: &RespPacket) -> Vec<&[u8]> {
+    match packet.get_array_element(3) {
+        Some(b"") => {
+            let len = packet.get_array_len().unwrap_or(0);
+            let keys_index = (6..len).find(|i| {
+                packet
+                    .get_array_element(*i)
+                    .map_or(false, |e| e.eq_ignore_ascii_case(b"KEYS"))
+            });
+            match keys_index {
+                Some(keys_index) => (keys_index + 1..len)
+                    .filter_map(|i| packet.get_array_element(i))
+                    .collect(),
+                None => vec![],
+            }
+        }
+        Some(key) => vec![key],
+        None => vec![],
+    }
+}
+
+// XGROUP subcommands taking the stream key as the second argument.
+const XGROUP_KEY_SUB_COMMANDS: [&str; 5] = [
+    "CREATE",
+    "SETID",
+    "DESTROY",
+    "CREATECONSUMER",
+    "DELCONSUMER",
+];
+
+// XGROUP CREATE key group id|$ [MKSTREAM] [ENTRIESREAD entries-read]
+fn get_xgroup_keys(packet: &RespPacket) -> Vec<&[u8]> {
+    let is_key_sub_cmd = packet.get_array_element(1).map_or(false, |sub_cmd| {
+        XGROUP_KEY_SUB_COMMANDS
+            .iter()
+            .any(|c| c.as_bytes().eq_ignore_ascii_case(sub_cmd))
+    });
+    if is_key_sub_cmd {
+        packet.get_array_element(2).into_iter().collect()
+    } else {
+        vec![]
+    }
+}
+
+struct SortOptions<'a> {
+    by: Option<&'a [u8]>,
+    get: Vec<&'a [u8]>,
+    store: Option<&'a [u8]>,
+}
+
+// SORT key [BY pattern] [LIMIT offset count] [GET pattern [GET pattern ...]]
+//   [ASC | DESC] [ALPHA] [STORE destination]
+// Stops at the unknown option and leaves the syntax error to the backend.
+fn parse_sort_options(packet: &RespPacket) -> SortOptions<'_> {
+    let mut options = SortOptions {
+        by: None,
+        get: vec![],
+        store: None,
+    };
+    let len = packet.get_array_len().unwrap_or(0);
+    let mut i = 2;
+    while i < len {
+        let option = match packet.get_array_element(i) {
+            Some(option) => option,
+            None => break,
+        };
+        let arg = packet.get_array_element(i + 1);
+        if option.eq_ignore_ascii_case(b"ASC")
+            || option.eq_ignore_ascii_case(b"DESC")
+            || option.eq_ignore_ascii_case(b"ALPHA")
+        {
+            i += 1;
+        } else if option.eq_ignore_ascii_case(b"LIMIT") {
+            i += 3;
+        } else if option.eq_ignore_ascii_case(b"BY") && arg.is_some() {
+            options.by = arg;
+            i += 2;
+        } else if option.eq_ignore_ascii_case(b"GET") && arg.is_some() {
+            options.get.extend(arg);
+            i += 2;
+        } else if option.eq_ignore_ascii_case(b"STORE") && arg.is_some() {
+            options.store = arg;
+            i += 2;
+        } else {
+            break;
+        }
+    }
+    options
+}
+
+fn get_sort_keys(packet: &RespPacket) -> Vec<&[u8]> {
+    let key = match packet.get_array_element(1) {
+        Some(key) => key,
+        None => return vec![],
+    };
+    let mut keys = vec![key];
+    keys.extend(parse_sort_options(packet).store);
+    keys
+}
+
+// Returns the slot of all the keys formed by replacing the first `*` of the pattern,
+// or None if they could be in different slots.
+// The hash tag needs to be closed before the `*`
+// since the substituted element could contain another `{`.
+fn get_sort_pattern_slot(pattern: &[u8]) -> Option<usize> {
+    let star = pattern.iter().position(|b| *b == b'*')?;
+    let prefix = &pattern[..star];
+    let open = prefix.iter().position(|b| *b == b'{')?;
+    let close = open + 1 + prefix[open + 1..].iter().position(|b| *b == b'}')?;
+    if close == open + 1 {
+        // Empty hash tag
+        return None;
+    }
+    Some(generate_slot(&prefix[..=close]))
+}
+
+pub fn check_sort_patterns(packet: &RespPacket) -> Result<(), &'static str> {
+    let key = match packet.get_array_element(1) {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    let slot = generate_slot(key);
+    // The pattern without `*` doesn't access other keys,
+    // e.g. `BY nosort` skips the sorting and `GET #` gets the element itself.
+    let in_same_slot =
+        |pattern: &[u8]| !pattern.contains(&b'*') || get_sort_pattern_slot(pattern) == Some(slot);
+
+    let options = parse_sort_options(packet);
+    if let Some(by) = options.by {
+        if !in_same_slot(by) {
+            return Err(response::ERR_SORT_BY_CROSS_SLOT);
+        }
+    }
+    if !options.get.into_iter().all(in_same_slot) {
+        return Err(response::ERR_SORT_GET_CROSS_SLOT);
+    }
+    match options.store {
+        Some(dst) if generate_slot(dst) != slot => Err(response::ERR_NOT_THE_SAME_SLOT),
+        _ => Ok(()),
+    }
+}
+
+// Parses "MYCMD=1:-1:1,OTHERCMD=2:2:1" into the range key specs
+// of the commands unknown to the proxy.
+pub fn parse_key_specs(s: &str) -> Result<Vec<(String, KeySpec)>, String> {
+    let mut key_specs: Vec<(String, KeySpec)> = vec![];
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let invalid = || format!("invalid key spec: {}", item);
+        let mut it = item.splitn(2, '=');
+        let (cmd_name, spec) = match (it.next(), it.next()) {
+            (Some(cmd_name), Some(spec)) if !cmd_name.trim().is_empty() => {
+                (cmd_name.trim(), spec.trim())
+            }
+            _ => return Err(invalid()),
+        };
+        let parts: Vec<&str> = spec.split(':').map(str::trim).collect();
+        let (first, last, step) = match parts.as_slice() {
+            [first, last, step] => (
+                first.parse::<usize>().map_err(|_| invalid())?,
+                last.parse::<isize>().map_err(|_| invalid())?,
+                step.parse::<usize>().map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+        if first == 0 || step == 0 || (last >= 0 && (last as usize) < first) {
+            return Err(invalid());
+        }
+        if key_specs
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case(cmd_name))
+        {
+            return Err(format!("duplicated key spec: {}", item));
+        }
+        key_specs.push((
+            cmd_name.to_uppercase(),
+            KeySpec::Range { first, last, step },
+        ));
+    }
+    Ok(key_specs)
+}
+
+pub fn key_specs_to_string(key_specs: &[(String, KeySpec)]) -> String {
+    key_specs
+        .iter()
+        .filter_map(|(cmd_name, spec)| match spec {
+            KeySpec::Range { first, last, step } => {
+                Some(format!("{}={}:{}:{}", cmd_name, first, last, step))
+            }
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Array, BulkStr, Resp};
+    use crate::test_support::{gen_test_config, FakeBackendFunc, TestProxy};
+    use std::sync::Arc;
+    use tokio;
+
+    fn gen_packet(cmd: &str) -> RespPacket {
+        let resps = cmd
+            .split_whitespace()
+            .map(|e| Resp::Bulk(BulkStr::Str(e.as_bytes().to_vec())))
+            .collect();
+        RespPacket::Data(Resp::Arr(Array::Arr(resps)))
+    }
+
+    fn get_keys(spec: KeySpec, cmd: &str) -> Vec<String> {
+        spec.get_keys(&gen_packet(cmd))
+            .into_iter()
+            .map(|k| String::from_utf8(k.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_range_key_spec() {
+        let spec = KeySpec::Range {
+            first: 1,
+            last: -2,
+            step: 1,
+        };
+        assert_eq!(get_keys(spec, "BLPOP a b 0"), vec!["a", "b"]);
+        assert!(get_keys(spec, "BLPOP").is_empty());
+
+        let spec = KeySpec::Range {
+            first: 1,
+            last: -1,
+            step: 2,
+        };
+        assert_eq!(get_keys(spec, "MSET a 1 b 2"), vec!["a", "b"]);
+
+        let spec = KeySpec::Range {
+            first: 2,
+            last: 3,
+            step: 1,
+        };
+        assert_eq!(get_keys(spec, "CMD arg a b c"), vec!["a", "b"]);
+        assert_eq!(get_keys(spec, "CMD arg a"), vec!["a"]);
+        assert_eq!(
+            spec.get_first_key(&gen_packet("CMD arg a")),
+            Some(&b"a"[..])
+        );
+        assert_eq!(spec.get_first_key(&gen_packet("CMD arg")), None);
+    }
+
+    #[test]
+    fn test_other_key_specs() {
+        assert!(get_keys(KeySpec::NoKey, "WAIT 1 0").is_empty());
+        let spec = KeySpec::NumKeys(2);
+        assert_eq!(get_keys(spec, "EVAL s 2 a b c"), vec!["a", "b"]);
+        assert!(get_keys(spec, "EVAL s 0").is_empty());
+        assert_eq!(
+            spec.get_first_key(&gen_packet("EVAL s 0 arg")),
+            Some(&b"arg"[..])
+        );
+        let spec = KeySpec::Custom(get_migrate_keys);
+        assert_eq!(get_keys(spec, "MIGRATE host 6379 a 0 1000"), vec!["a"]);
+        let args = ["MIGRATE", "host", "6379", "", "0", "1000", "KEYS", "a", "b"];
+        let packet = RespPacket::Data(Resp::Arr(Array::Arr(
+            args.iter()
+                .map(|e| Resp::Bulk(BulkStr::Str(e.as_bytes().to_vec())))
+                .collect(),
+        )));
+        assert_eq!(spec.get_keys(&packet), vec![&b"a"[..], &b"b"[..]]);
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let spec = get_builtin_key_spec(DataCmdType::SORT);
+        assert_eq!(get_keys(spec, "SORT a"), vec!["a"]);
+        assert_eq!(
+            get_keys(spec, "SORT a BY w_* LIMIT 0 10 GET # DESC ALPHA STORE b"),
+            vec!["a", "b"]
+        );
+        // The arguments of LIMIT are not options.
+        assert_eq!(get_keys(spec, "SORT a LIMIT 0 10 STORE b"), vec!["a", "b"]);
+        assert_eq!(get_keys(spec, "SORT a LIMIT store 10"), vec!["a"]);
+        let spec = get_builtin_key_spec(DataCmdType::SORT_RO);
+        assert_eq!(get_keys(spec, "SORT_RO a GET #"), vec!["a"]);
+    }
+
+    #[test]
+    fn test_sort_pattern_slot() {
+        let slot = Some(generate_slot(b"tag"));
+        assert_eq!(get_sort_pattern_slot(b"w_{tag}_*"), slot);
+        assert_eq!(get_sort_pattern_slot(b"{tag}*->field"), slot);
+        assert_eq!(get_sort_pattern_slot(b"{tag}_*_{other}"), slot);
+        // The keys formed by these patterns could be in any slot.
+        assert_eq!(get_sort_pattern_slot(b"w_*"), None);
+        assert_eq!(get_sort_pattern_slot(b"*_{tag}"), None);
+        assert_eq!(get_sort_pattern_slot(b"{ta*g}"), None);
+        assert_eq!(get_sort_pattern_slot(b"{}_*_{tag}"), None);
+        assert_eq!(get_sort_pattern_slot(b"{tag_*"), None);
+    }
+
+    #[test]
+    fn test_check_sort_patterns() {
+        let check = |cmd: &str| check_sort_patterns(&gen_packet(cmd));
+        assert_eq!(check("SORT {tag}list"), Ok(()));
+        assert_eq!(
+            check("SORT {tag}list BY {tag}w_* GET # GET {tag}o_*->f"),
+            Ok(())
+        );
+        assert_eq!(
+            check("SORT_RO {tag}list BY nosort GET {tag}o_* ALPHA"),
+            Ok(())
+        );
+        assert_eq!(check("SORT {tag}list GET fixedkey"), Ok(()));
+        assert_eq!(check("SORT {tag}list STORE {tag}dst"), Ok(()));
+
+        assert_eq!(
+            check("SORT {tag}list BY w_*"),
+            Err(response::ERR_SORT_BY_CROSS_SLOT)
+        );
+        assert_eq!(
+            check("SORT {tag}list BY {other}w_*"),
+            Err(response::ERR_SORT_BY_CROSS_SLOT)
+        );
+        assert_eq!(
+            check("SORT_RO {tag}list GET # GET o_*"),
+            Err(response::ERR_SORT_GET_CROSS_SLOT)
+        );
+        assert_eq!(
+            check("SORT {tag}list LIMIT 0 1 GET *_{tag}"),
+            Err(response::ERR_SORT_GET_CROSS_SLOT)
+        );
+        assert_eq!(
+            check("SORT {tag}list STORE dst"),
+            Err(response::ERR_NOT_THE_SAME_SLOT)
+        );
+    }
+
+    #[test]
+    fn test_parse_key_specs() {
+        let key_specs = parse_key_specs("mycmd=1:-1:1, OTHER=2:2:1").unwrap();
+        assert_eq!(key_specs.len(), 2);
+        assert_eq!(key_specs[0].0, "MYCMD");
+        assert_eq!(get_keys(key_specs[1].1, "OTHER x a b"), vec!["a"]);
+        assert_eq!(key_specs_to_string(&key_specs), "MYCMD=1:-1:1,OTHER=2:2:1");
+        assert!(parse_key_specs("").unwrap().is_empty());
+
+        assert!(parse_key_specs("MYCMD").is_err());
+        assert!(parse_key_specs("MYCMD=1:1").is_err());
+        assert!(parse_key_specs("MYCMD=0:1:1").is_err());
+        assert!(parse_key_specs("MYCMD=2:1:1").is_err());
+        assert!(parse_key_specs("MYCMD=1:1:0").is_err());
+        assert!(parse_key_specs("MYCMD=1:1:1,mycmd=1:1:1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_key_specs_from_config() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let mut config = gen_test_config();
+        config.key_specs = crate::proxy::keyspec::parse_key_specs("MYCMD=2:-1:1").unwrap();
+        let proxy = TestProxy::with_config(config, backend);
+        let mut client = proxy.connect();
+
+        let reply = client
+            .send(&["COMMAND", "GETKEYS", "mycmd", "arg", "a", "b"])
+            .await;
+        assert_eq!(
+            reply,
+            Resp::Arr(Array::Arr(vec![
+                Resp::Bulk(BulkStr::Str(b"a".to_vec())),
+                Resp::Bulk(BulkStr::Str(b"b".to_vec())),
+            ]))
+        );
+        // The commands known by the proxy are not changed.
+        let reply = client.send(&["COMMAND", "GETKEYS", "GET", "key"]).await;
+        assert_eq!(
+            reply,
+            Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Str(b"key".to_vec()))]))
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use super::slowlog::{Slowlog, TaskEvent};
+use crate::protocol::{Array, BulkStr, Resp, RespVec};
+use chrono::Utc;
+use std::cmp::max;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+// Same as redis.
+const LATENCY_HISTORY_LEN: usize = 160;
+const NANOS_PER_MILLI: i64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyEvent {
+    // From the command being created to the reply being ready.
+    Command = 0,
+    // Waiting in the queue before being written to the backend connection.
+    QueueWait = 1,
+    // From being written to the backend to getting the reply.
+    BackendWait = 2,
+    // From getting the reply from backend to the session receiving it.
+    ReplyWait = 3,
+}
+
+const LATENCY_EVENTS: [LatencyEvent; 4] = [
+    LatencyEvent::Command,
+    LatencyEvent::QueueWait,
+    LatencyEvent::BackendWait,
+    LatencyEvent::ReplyWait,
+];
+
+impl LatencyEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LatencyEvent::Command => "command",
+            LatencyEvent::QueueWait => "queue-wait",
+            LatencyEvent::BackendWait => "backend-wait",
+            LatencyEvent::ReplyWait => "reply-wait",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        LATENCY_EVENTS
+            .iter()
+            .find(|event| event.as_str().eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    fn get_span(self) -> (TaskEvent, TaskEvent) {
+        match self {
+            LatencyEvent::Command => (TaskEvent::Created, TaskEvent::WaitDone),
+            LatencyEvent::QueueWait => (
+                TaskEvent::SentToWritingQueue,
+                TaskEvent::WritingQueueReceived,
+            ),
+            LatencyEvent::BackendWait => (TaskEvent::SentToBackend, TaskEvent::ReceivedFromBackend),
+            LatencyEvent::ReplyWait => (TaskEvent::ReceivedFromBackend, TaskEvent::WaitDone),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LatencyHistory {
+    // (unix timestamp in seconds, latency in milliseconds)
+    samples: VecDeque<(i64, u64)>,
+    max_latency: u64,
+}
+
+impl LatencyHistory {
+    fn add_sample(&mut self, timestamp: i64, latency: u64) {
+        self.max_latency = max(self.max_latency, latency);
+        // Only keep the largest one within the same second like redis.
+        if let Some(last) = self.samples.back_mut() {
+            if last.0 == timestamp {
+                last.1 = max(last.1, latency);
+                return;
+            }
+        }
+        if self.samples.len() >= LATENCY_HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((timestamp, latency));
+    }
+
+    // The max latency is recalculated from the remaining samples.
+    fn trim(&mut self, deadline: i64) {
+        while let Some((timestamp, _)) = self.samples.front() {
+            if *timestamp >= deadline {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.max_latency = self
+            .samples
+            .iter()
+            .map(|(_, latency)| *latency)
+            .max()
+            .unwrap_or(0);
+    }
+}
+
+// Latency spikes observed by the proxy, derived from the `TaskEvent`s of the sampled commands.
+pub struct LatencyMonitor {
+    histories: Vec<Mutex<LatencyHistory>>,
+}
+
+impl Default for LatencyMonitor {
+    fn default() -> Self {
+        Self {
+            histories: LATENCY_EVENTS
+                .iter()
+                .map(|_| Mutex::new(LatencyHistory::default()))
+                .collect(),
+        }
+    }
+}
+
+impl LatencyMonitor {
+    // `threshold` is in milliseconds. Zero means disabled.
+    pub fn record(&self, slowlog: &Slowlog, threshold: u64) {
+        if threshold == 0 || !slowlog.is_enabled() {
+            return;
+        }
+        for event in LATENCY_EVENTS.iter() {
+            let (start, end) = event.get_span();
+            let latency = match slowlog.get_duration(start, end) {
+                Some(duration) => (duration / NANOS_PER_MILLI) as u64,
+                None => continue,
+            };
+            if latency >= threshold {
+                self.add_sample(*event, Utc::now().timestamp(), latency);
+            }
+        }
+    }
+
+    fn add_sample(&self, event: LatencyEvent, timestamp: i64, latency: u64) {
+        self.get_history(event)
+            .lock()
+            .expect("LatencyMonitor::add_sample")
+            .add_sample(timestamp, latency);
+    }
+
+    fn get_history(&self, event: LatencyEvent) -> &Mutex<LatencyHistory> {
+        self.histories
+            .get(event as usize)
+            .expect("LatencyMonitor::get_history")
+    }
+
+    // Returns (event, latest timestamp, latest latency, max latency).
+    pub fn get_latest(&self) -> Vec<(LatencyEvent, i64, u64, u64)> {
+        LATENCY_EVENTS
+            .iter()
+            .filter_map(|event| {
+                let history = self
+                    .get_history(*event)
+                    .lock()
+                    .expect("LatencyMonitor::get_latest");
+                history
+                    .samples
+                    .back()
+                    .map(|(timestamp, latency)| (*event, *timestamp, *latency, history.max_latency))
+            })
+            .collect()
+    }
+
+    pub fn get_samples(&self, event: LatencyEvent) -> Vec<(i64, u64)> {
+        self.get_history(event)
+            .lock()
+            .expect("LatencyMonitor::get_samples")
+            .samples
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    // Removes the samples before `deadline` in unix timestamp seconds.
+    pub fn trim(&self, deadline: i64) {
+        for event in LATENCY_EVENTS.iter() {
+            self.get_history(*event)
+                .lock()
+                .expect("LatencyMonitor::trim")
+                .trim(deadline);
+        }
+    }
+
+    // Reset all the events if `events` is empty.
+    // Returns the number of the events with samples being reset.
+    pub fn reset(&self, events: &[LatencyEvent]) -> usize {
+        let events = if events.is_empty() {
+            &LATENCY_EVENTS[..]
+        } else {
+            events
+        };
+        let mut count = 0;
+        for event in LATENCY_EVENTS.iter().filter(|e| events.contains(e)) {
+            let mut history = self
+                .get_history(*event)
+                .lock()
+                .expect("LatencyMonitor::reset");
+            if !history.samples.is_empty() {
+                count += 1;
+            }
+            *history = LatencyHistory::default();
+        }
+        count
+    }
+}
+
+fn int_resp<T: ToString>(n: T) -> RespVec {
+    Resp::Integer(n.to_string().into_bytes())
+}
+
+pub fn latest_to_resp(latest: Vec<(LatencyEvent, i64, u64, u64)>) -> RespVec {
+    let elements = latest
+        .into_iter()
+        .map(|(event, timestamp, latency, max_latency)| {
+            Resp::Arr(Array::Arr(vec![
+                Resp::Bulk(BulkStr::Str(event.as_str().to_string().into_bytes())),
+                int_resp(timestamp),
+                int_resp(latency),
+                int_resp(max_latency),
+            ]))
+        })
+        .collect();
+    Resp::Arr(Array::Arr(elements))
+}
+
+pub fn samples_to_resp(samples: Vec<(i64, u64)>) -> RespVec {
+    let elements = samples
+        .into_iter()
+        .map(|(timestamp, latency)| {
+            Resp::Arr(Array::Arr(vec![int_resp(timestamp), int_resp(latency)]))
+        })
+        .collect();
+    Resp::Arr(Array::Arr(elements))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_name() {
+        for event in LATENCY_EVENTS.iter() {
+            assert_eq!(LatencyEvent::from_name(event.as_str()), Some(*event));
+        }
+        assert_eq!(
+            LatencyEvent::from_name("BACKEND-WAIT"),
+            Some(LatencyEvent::BackendWait)
+        );
+        assert_eq!(LatencyEvent::from_name("fork"), None);
+    }
+
+    #[test]
+    fn test_latency_history() {
+        let monitor = LatencyMonitor::default();
+        assert!(monitor.get_latest().is_empty());
+
+        monitor.add_sample(LatencyEvent::BackendWait, 100, 20);
+        monitor.add_sample(LatencyEvent::BackendWait, 100, 30);
+        monitor.add_sample(LatencyEvent::BackendWait, 101, 10);
+        monitor.add_sample(LatencyEvent::Command, 102, 50);
+
+        assert_eq!(
+            monitor.get_samples(LatencyEvent::BackendWait),
+            vec![(100, 30), (101, 10)]
+        );
+        assert_eq!(
+            monitor.get_latest(),
+            vec![
+                (LatencyEvent::Command, 102, 50, 50),
+                (LatencyEvent::BackendWait, 101, 10, 30),
+            ]
+        );
+
+        assert_eq!(
+            monitor.reset(&[LatencyEvent::Command, LatencyEvent::QueueWait]),
+            1
+        );
+        assert!(monitor.get_samples(LatencyEvent::Command).is_empty());
+        assert_eq!(monitor.reset(&[]), 1);
+        assert!(monitor.get_latest().is_empty());
+    }
+
+    #[test]
+    fn test_latency_trim() {
+        let monitor = LatencyMonitor::default();
+        monitor.add_sample(LatencyEvent::BackendWait, 100, 30);
+        monitor.add_sample(LatencyEvent::BackendWait, 101, 10);
+        monitor.add_sample(LatencyEvent::Command, 100, 50);
+
+        monitor.trim(101);
+        assert_eq!(
+            monitor.get_latest(),
+            vec![(LatencyEvent::BackendWait, 101, 10, 10)]
+        );
+        monitor.trim(102);
+        assert!(monitor.get_latest().is_empty());
+    }
+
+    #[test]
+    fn test_history_len() {
+        let monitor = LatencyMonitor::default();
+        for i in 0..(LATENCY_HISTORY_LEN as i64 + 10) {
+            monitor.add_sample(LatencyEvent::QueueWait, i, 1);
+        }
+        let samples = monitor.get_samples(LatencyEvent::QueueWait);
+        assert_eq!(samples.len(), LATENCY_HISTORY_LEN);
+        assert_eq!(samples[0], (10, 1));
+    }
+}
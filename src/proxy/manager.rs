@@ -1,9 +1,18 @@
 use super::backend::{BackendError, CmdTask, ConnFactory, IntoTask};
 use super::blocking::{
     gen_basic_blocking_sender_factory, gen_blocking_sender_factory, BasicBlockingSenderFactory,
-    BlockingBackendSenderFactory, BlockingCmdTaskSender, BlockingMap, CounterTask,
+    BlockingBackendSenderFactory, BlockingCmdTaskSender, BlockingHintTask, BlockingMap,
+    BlockingQueueStats, CounterTask,
 };
-use super::cluster::{ClusterBackendMap, ClusterMetaError, ClusterSendError, ClusterTag};
+use super::cluster::{
+    ClusterBackendMap, ClusterMetaError, ClusterSendError, ClusterTag, TopoIssue,
+};
+use super::deadletter::DeadLetters;
+use super::failover::{FailoverRetrier, FailoverRetry};
+use super::flapping::{BackendFlapMap, BackendFlapStats};
+use super::hedge::HedgedReader;
+use super::inflight::{BackendInFlightMap, BackendInFlightStats};
+use super::memory::MemoryBudget;
 use super::reply::{DecompressCommitHandlerFactory, ReplyCommitHandlerFactory};
 use super::sender::{
     gen_migration_sender_factory, gen_sender_factory, BackendSenderFactory, CmdTaskSender,
@@ -11,8 +20,10 @@ use super::sender::{
 };
 use super::service::ServerProxyConfig;
 use super::session::{CmdCtx, CmdCtxFactory};
+use super::shadow::ShadowMirror;
+use super::slot::SlotChangeNotifier;
 use super::slowlog::TaskEvent;
-use crate::common::cluster::{ClusterName, MigrationTaskMeta, SlotRangeTag};
+use crate::common::cluster::{ClusterName, MigrationTaskMeta, MigrationTaskProgress, SlotRangeTag};
 use crate::common::config::ClusterConfig;
 use crate::common::proto::ProxyClusterMeta;
 use crate::common::response;
@@ -20,13 +31,17 @@ use crate::common::track::TrackedFutureRegistry;
 use crate::migration::manager::{MigrationManager, MigrationMap, SwitchError};
 use crate::migration::task::MgrSubCmd;
 use crate::migration::task::SwitchArg;
-use crate::protocol::{Array, BulkStr, RedisClientFactory, Resp, RespPacket, RespVec};
+use crate::protocol::{Array, BulkStr, RedisClient, RedisClientFactory, Resp, RespPacket, RespVec};
 use crate::replication::manager::ReplicatorManager;
-use crate::replication::replicator::ReplicatorMeta;
+use crate::replication::replicator::{MasterMeta, ReplicaMeta, ReplicatorMeta};
 use arc_swap::{ArcSwap, Lease};
+use chrono::Utc;
+use futures::future;
+use std::collections::HashSet;
 use std::num::NonZeroUsize;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
 
 pub struct MetaMap<S: CmdTaskSender, P: CmdTaskSender, T>
 where
@@ -89,7 +104,7 @@ pub struct MetaManager<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>>
     meta_map: SharedMetaMap<C>,
     epoch: AtomicU64,
     lock: Mutex<()>, // This is the write lock for `epoch`, `cluster`, and `task`.
-    replicator_manager: ReplicatorManager<F>,
+    replicator_manager: Arc<ReplicatorManager<F>>,
     migration_manager: MigrationManager<
         F,
         MigrationSenderFactory<C>,
@@ -100,6 +115,22 @@ pub struct MetaManager<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>>
     peer_sender_factory: PeerSenderFactory<C>,
     blocking_map: Arc<BlockingMap<BasicSenderFactory<C>, BlockingTaskRetrySender<C>>>,
     cluster_config: ClusterConfig,
+    shadow_mirror: ShadowMirror<C>,
+    hedged_reader: Arc<HedgedReader<F, C>>,
+    failover_retrier: Arc<FailoverRetrier<C>>,
+    slot_change_notifier: Arc<SlotChangeNotifier>,
+    client_factory: Arc<F>,
+    future_registry: Arc<TrackedFutureRegistry>,
+    // The backends of the latest metadata already warmed up.
+    warmed_backends: Mutex<HashSet<String>>,
+    // Timestamp in seconds of the last UMCTL SETCLUSTER. Zero means never.
+    last_topology_update: AtomicI64,
+    dead_letters: Arc<DeadLetters>,
+    // Only the commands of the clients are limited.
+    in_flight_map: Arc<BackendInFlightMap>,
+    flap_map: Arc<BackendFlapMap>,
+    // The latest metadata applied, for rebuilding the cluster map on UMCTL FLUSHCACHE.
+    last_meta: Mutex<Option<ProxyClusterMeta>>,
 }
 
 impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C> {
@@ -110,17 +141,24 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
         conn_factory: Arc<C>,
         meta_map: SharedMetaMap<C>,
         future_registry: Arc<TrackedFutureRegistry>,
+        dead_letters: Arc<DeadLetters>,
+        memory_budget: Arc<MemoryBudget>,
     ) -> Self {
         let reply_handler_factory = Arc::new(DecompressCommitHandlerFactory::new(meta_map.clone()));
         let blocking_task_sender = Arc::new(BlockingTaskRetrySender::new(
             meta_map.clone(),
             config.max_redirections,
+            dead_letters.clone(),
         ));
+        let in_flight_map = Arc::new(BackendInFlightMap::from_config(&config));
+        let flap_map = Arc::new(BackendFlapMap::from_config(&config));
         let basic_sender_factory = gen_basic_blocking_sender_factory(
             config.clone(),
             reply_handler_factory,
             conn_factory.clone(),
             future_registry.clone(),
+            in_flight_map.clone(),
+            flap_map.clone(),
         );
         let blocking_map = Arc::new(BlockingMap::new(basic_sender_factory, blocking_task_sender));
         let sender_factory = gen_blocking_sender_factory(blocking_map.clone());
@@ -140,10 +178,32 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
         let migration_proxy_sender_factory = Arc::new(gen_migration_sender_factory(
             config.clone(),
             Arc::new(ReplyCommitHandlerFactory::default()),
-            conn_factory,
+            conn_factory.clone(),
+            future_registry.clone(),
+        ));
+        let replicator_manager = Arc::new(ReplicatorManager::new(
+            client_factory.clone(),
             future_registry.clone(),
         ));
+        let hedged_reader = Arc::new(HedgedReader::new(
+            config.clone(),
+            replicator_manager.clone(),
+            meta_map.clone(),
+            conn_factory.clone(),
+            future_registry.clone(),
+        ));
+        let slot_change_notifier = Arc::new(SlotChangeNotifier::default());
+        let failover_retrier = Arc::new(FailoverRetrier::new(
+            config.clone(),
+            meta_map.clone(),
+            dead_letters.clone(),
+            slot_change_notifier.clone(),
+        ));
+        let shadow_mirror =
+            ShadowMirror::new(config.clone(), conn_factory, future_registry.clone());
         let cmd_ctx_factory = Arc::new(CmdCtxFactory::default());
+        let client_factory_clone = client_factory.clone();
+        let future_registry_clone = future_registry.clone();
         let config_clone = config.clone();
         let cluster_config_clone = cluster_config.clone();
         Self {
@@ -151,10 +211,7 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
             meta_map,
             epoch: AtomicU64::new(0),
             lock: Mutex::new(()),
-            replicator_manager: ReplicatorManager::new(
-                client_factory.clone(),
-                future_registry.clone(),
-            ),
+            replicator_manager,
             migration_manager: MigrationManager::new(
                 config_clone,
                 cluster_config_clone,
@@ -163,11 +220,24 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
                 migration_proxy_sender_factory,
                 cmd_ctx_factory,
                 future_registry,
+                memory_budget,
             ),
             sender_factory,
             peer_sender_factory,
             blocking_map,
             cluster_config,
+            shadow_mirror,
+            hedged_reader,
+            failover_retrier,
+            slot_change_notifier,
+            client_factory: client_factory_clone,
+            future_registry: future_registry_clone,
+            warmed_backends: Mutex::new(HashSet::new()),
+            last_topology_update: AtomicI64::new(0),
+            dead_letters,
+            in_flight_map,
+            flap_map,
+            last_meta: Mutex::new(None),
         }
     }
 
@@ -191,6 +261,41 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
         )
     }
 
+    pub fn gen_cluster_info(&self, cluster_name: ClusterName) -> String {
+        let meta_map = self.meta_map.load();
+        let migration_states = meta_map.migration_map.get_states(&cluster_name);
+        let migration_stuck = meta_map
+            .migration_map
+            .has_stuck_migration(&cluster_name, self.config.migration_stuck_timeout);
+        meta_map.cluster_map.gen_cluster_info(
+            cluster_name,
+            self.config.announce_address.clone(),
+            &migration_states,
+            migration_stuck,
+        )
+    }
+
+    pub fn check_topo(
+        &self,
+        cluster_name: Option<ClusterName>,
+    ) -> Vec<(ClusterName, Vec<TopoIssue>)> {
+        let meta_map = self.meta_map.load();
+        let cluster_names = match cluster_name {
+            Some(cluster_name) => vec![cluster_name],
+            None => meta_map.cluster_map.get_all_clusters(),
+        };
+        cluster_names
+            .into_iter()
+            .map(|cluster_name| {
+                let migration_states = meta_map.migration_map.get_states(&cluster_name);
+                let issues = meta_map
+                    .cluster_map
+                    .check_topo(&cluster_name, &migration_states);
+                (cluster_name, issues)
+            })
+            .collect()
+    }
+
     pub fn get_clusters(&self) -> Vec<ClusterName> {
         self.meta_map.load().cluster_map.get_clusters()
     }
@@ -203,6 +308,13 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
         let migration_manager = &self.migration_manager;
         let cluster_config = &self.cluster_config;
 
+        // Even the metadata with the old epoch shows that the broker is still reachable.
+        let now = Utc::now().timestamp();
+        let last_update = self.last_topology_update.swap(now, Ordering::Relaxed);
+        if is_topology_stale(last_update, now, self.config.stale_topology_timeout) {
+            info!("received the topology from the broker again, it is no longer stale");
+        }
+
         {
             let _guard = self.lock.lock().expect("MetaManager::set_meta");
 
@@ -235,19 +347,172 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
             self.epoch.store(cluster_meta.get_epoch(), Ordering::SeqCst);
 
             self.migration_manager.run_tasks(new_tasks);
+            self.slot_change_notifier.notify();
+            *self.last_meta.lock().expect("MetaManager::set_meta") = Some(cluster_meta.clone());
         };
 
+        if self.config.warm_backend_connections {
+            self.warm_new_backends(&cluster_meta);
+        }
+
+        Ok(())
+    }
+
+    // Drops all the cached backend senders and rebuilds the cluster map
+    // from the latest metadata so that the next commands use new connections.
+    // The old connections are closed after the commands already queued
+    // in them get their replies.
+    // The blocking queues can't be replaced during migration.
+    pub fn flush_cache(&self) -> Result<(), ClusterMetaError> {
+        let _guard = self.lock.lock().expect("MetaManager::flush_cache");
+
+        let old_meta_map = self.meta_map.load();
+        if !old_meta_map.migration_map.is_empty() {
+            return Err(ClusterMetaError::TryAgain);
+        }
+
+        self.sender_factory.clear();
+        self.peer_sender_factory.clear();
+        self.blocking_map.clear();
+        self.hedged_reader.clear_senders();
+        self.shadow_mirror.clear_sender();
+
+        let last_meta = self.last_meta.lock().expect("MetaManager::flush_cache");
+        let cluster_meta = match last_meta.as_ref() {
+            Some(cluster_meta) => cluster_meta,
+            None => return Ok(()),
+        };
+        let cluster_map = ClusterBackendMap::from_cluster_map(
+            cluster_meta,
+            &self.sender_factory,
+            &self.peer_sender_factory,
+            self.config.active_redirection,
+            &self.cluster_config,
+        );
+        self.meta_map.store(Arc::new(MetaMap {
+            cluster_map,
+            migration_map: MigrationMap::empty(),
+        }));
+        self.slot_change_notifier.notify();
         Ok(())
     }
 
+    // Only the backends not in the previous metadata are warmed up,
+    // since the broker keeps sending the same metadata periodically.
+    fn warm_new_backends(&self, cluster_meta: &ProxyClusterMeta) {
+        let backends: HashSet<String> = cluster_meta
+            .get_local()
+            .get_map()
+            .values()
+            .flat_map(|nodes| nodes.keys().cloned())
+            .collect();
+        let new_backends: Vec<String> = {
+            let mut warmed_backends = self
+                .warmed_backends
+                .lock()
+                .expect("MetaManager::warm_new_backends");
+            let new_backends = backends.difference(&warmed_backends).cloned().collect();
+            *warmed_backends = backends;
+            new_backends
+        };
+        if new_backends.is_empty() {
+            return;
+        }
+
+        let fut = warm_backends(self.client_factory.clone(), new_backends);
+        let fut = TrackedFutureRegistry::wrap(
+            self.future_registry.clone(),
+            fut,
+            "warm_backends".to_string(),
+        );
+        tokio::spawn(fut);
+    }
+
     pub fn update_replicators(&self, meta: ReplicatorMeta) -> Result<(), ClusterMetaError> {
+        self.hedged_reader.update_replicas(&meta.masters);
         self.replicator_manager.update_replicators(meta)
     }
 
+    pub fn get_hedged_read_count(&self) -> u64 {
+        self.hedged_reader.get_hedged_count()
+    }
+
+    pub fn get_failover_retry_count(&self) -> u64 {
+        self.failover_retrier.get_retried_count()
+    }
+
+    // Should be called before sending the command
+    // since the master of the slot may be changed after the failure.
+    pub fn prepare_failover_retry(&self, cmd_ctx: &CmdCtx) -> Option<FailoverRetry<C>> {
+        if !self.failover_retrier.is_enabled() {
+            return None;
+        }
+        let slot = cmd_ctx.get_slot()?;
+        let master_address = self
+            .meta_map
+            .lease()
+            .cluster_map
+            .get_local_backend(cmd_ctx.get_cluster_name(), slot)
+            .map(ToString::to_string);
+        self.failover_retrier
+            .prepare(cmd_ctx, master_address.as_deref())
+    }
+
+    pub fn get_blocking_stats(&self) -> Vec<BlockingQueueStats> {
+        self.blocking_map.get_blocking_stats()
+    }
+
+    pub fn get_backend_in_flight_stats(&self) -> Vec<BackendInFlightStats> {
+        self.in_flight_map.get_stats()
+    }
+
+    pub fn get_backend_flap_stats(&self) -> Vec<BackendFlapStats> {
+        self.flap_map.get_stats()
+    }
+
     pub fn get_replication_info(&self) -> RespVec {
         self.replicator_manager.get_metadata_report()
     }
 
+    // Only includes the replication of the specified cluster.
+    pub fn get_cluster_replication(
+        &self,
+        cluster_name: &ClusterName,
+    ) -> (Vec<MasterMeta>, Vec<ReplicaMeta>) {
+        let (masters, replicas) = self.replicator_manager.get_metadata();
+        (
+            masters
+                .into_iter()
+                .filter(|meta| &meta.cluster_name == cluster_name)
+                .collect(),
+            replicas
+                .into_iter()
+                .filter(|meta| &meta.cluster_name == cluster_name)
+                .collect(),
+        )
+    }
+
+    // The number of the online replicas acking the local backend owning the slot,
+    // reported by the latest `ROLE` of the backend.
+    pub fn get_replica_ack_num(&self, cluster_name: &ClusterName, slot: usize) -> usize {
+        let meta_map = self.meta_map.lease();
+        let backend = match meta_map.cluster_map.get_local_backend(cluster_name, slot) {
+            Some(backend) => backend,
+            None => return 0,
+        };
+        self.replicator_manager
+            .get_replica_state(cluster_name, backend)
+            .map(|state| state.get_ack_num())
+            .unwrap_or(0)
+    }
+
+    pub fn get_local_slot_num(&self, cluster_name: &ClusterName) -> usize {
+        self.meta_map
+            .load()
+            .cluster_map
+            .get_local_slot_num(cluster_name)
+    }
+
     pub fn info(&self) -> RespVec {
         let meta_map = self.meta_map.load();
         let cluster_info = meta_map.cluster_map.info();
@@ -292,16 +557,93 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
                 meta: task_meta,
             },
             sub_cmd,
-        )
+        )?;
+        // The importing proxy starts to own the slots.
+        self.slot_change_notifier.notify();
+        Ok(())
     }
 
     pub fn get_finished_migration_tasks(&self) -> Vec<MigrationTaskMeta> {
         self.meta_map.load().migration_map.get_finished_tasks()
     }
 
+    pub fn get_migration_progresses(&self) -> Vec<(MigrationTaskMeta, MigrationTaskProgress)> {
+        self.meta_map.load().migration_map.get_progresses()
+    }
+
     pub fn send(&self, cmd_ctx: CmdCtx) {
+        self.shadow_mirror.try_mirror(&cmd_ctx);
+        // The same lease is used for both finding the master and sending the command.
+        let meta_map = self.meta_map.lease();
+        let cmd_ctx = if self.hedged_reader.is_enabled() || self.flap_map.is_enabled() {
+            let master_address = cmd_ctx.get_slot().and_then(|slot| {
+                meta_map
+                    .cluster_map
+                    .get_local_backend(cmd_ctx.get_cluster_name(), slot)
+            });
+            if let Some(address) = master_address {
+                if self.flap_map.is_quarantined(address) {
+                    self.send_to_quarantined(cmd_ctx, address);
+                    return;
+                }
+            }
+            self.hedged_reader.try_hedge(cmd_ctx, master_address)
+        } else {
+            cmd_ctx
+        };
         let max_redirections = self.config.max_redirections;
-        send_cmd_ctx(&self.meta_map, cmd_ctx, max_redirections);
+        send_cmd_ctx_with_lease(&meta_map, cmd_ctx, max_redirections, &self.dead_letters);
+    }
+
+    // The reads go to the replicas and the others fail fast
+    // instead of waiting for the backend connection.
+    fn send_to_quarantined(&self, cmd_ctx: CmdCtx, address: &str) {
+        let cmd_ctx = if cmd_ctx.get_cmd().is_read_only() {
+            match self
+                .hedged_reader
+                .send_to_replica_directly(cmd_ctx, address)
+            {
+                Ok(()) => return,
+                Err(cmd_ctx) => cmd_ctx,
+            }
+        } else {
+            cmd_ctx
+        };
+        cmd_ctx.set_resp_result(Ok(Resp::Error(
+            format!("{} {}", response::ERR_BACKEND_QUARANTINED, address).into_bytes(),
+        )));
+    }
+
+    // The migration is skipped so that the proxy receiving the command
+    // always answers with its own backend of the slot,
+    // i.e. the source proxy with the source node and the destination proxy
+    // with the destination node while the slot is being migrated.
+    // The slots of other proxies are still redirected.
+    pub fn send_to_slot_owner(&self, mut cmd_ctx: CmdCtx) {
+        let meta_map = self.meta_map.lease();
+        let dead_letter = self.dead_letters.draft(&mut cmd_ctx);
+        let cmd_ctx = BlockingHintTask::new(cmd_ctx, false);
+        if let Err(err) = meta_map.cluster_map.send(cmd_ctx) {
+            match err {
+                ClusterSendError::MissingKey => (),
+                ClusterSendError::ActiveRedirection {
+                    task,
+                    slot,
+                    address,
+                } => send_cmd_ctx_to_remote_directly(
+                    &meta_map,
+                    task.into_task(),
+                    slot,
+                    address,
+                    self.config.max_redirections,
+                    &self.dead_letters,
+                ),
+                err => {
+                    warn!("Failed to forward cmd_ctx to slot owner: {:?}", err);
+                    dead_letter.put(err.to_string());
+                }
+            }
+        }
     }
 
     pub fn send_sync_task(&self, cmd_ctx: CmdCtx) {
@@ -328,13 +670,15 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
         }
     }
 
-    pub fn try_select_cluster(&self, mut cmd_ctx: CmdCtx) -> CmdCtx {
-        let exists = self
-            .meta_map
+    pub fn cluster_exists(&self, cluster_name: &ClusterName) -> bool {
+        self.meta_map
             .lease()
             .cluster_map
-            .cluster_exists(cmd_ctx.get_cluster_name());
-        if exists {
+            .cluster_exists(cluster_name)
+    }
+
+    pub fn try_select_cluster(&self, mut cmd_ctx: CmdCtx) -> CmdCtx {
+        if self.cluster_exists(cmd_ctx.get_cluster_name()) {
             return cmd_ctx;
         }
 
@@ -344,17 +688,124 @@ impl<F: RedisClientFactory, C: ConnFactory<Pkt = RespPacket>> MetaManager<F, C>
         cmd_ctx
     }
 
+    pub fn is_topology_stale(&self) -> bool {
+        is_topology_stale(
+            self.last_topology_update.load(Ordering::Relaxed),
+            Utc::now().timestamp(),
+            self.config.stale_topology_timeout,
+        )
+    }
+
     pub fn get_epoch(&self) -> u64 {
         self.epoch.load(Ordering::SeqCst)
     }
+
+    // Notified after the new metadata is visible to `send`,
+    // i.e. after UMCTL SETCLUSTER and the migration switch.
+    pub fn subscribe_slot_change(&self) -> watch::Receiver<u64> {
+        self.slot_change_notifier.subscribe()
+    }
+
+    pub fn get_slot_version(&self) -> u64 {
+        self.slot_change_notifier.get_version()
+    }
+
+    pub fn get_local_backend(&self, cluster_name: &ClusterName, slot: usize) -> Option<String> {
+        self.meta_map
+            .lease()
+            .cluster_map
+            .get_local_backend(cluster_name, slot)
+            .map(ToString::to_string)
+    }
+
+    pub fn get_local_backend_slots(&self, cluster_name: &ClusterName) -> Vec<(String, usize)> {
+        self.meta_map
+            .lease()
+            .cluster_map
+            .get_local_backend_slots(cluster_name)
+    }
+
+    pub fn get_remote_proxies(&self, cluster_name: &ClusterName) -> Vec<String> {
+        self.meta_map
+            .lease()
+            .cluster_map
+            .get_remote_proxies(cluster_name)
+    }
+
+    // Returns the local backend or the other proxy serving the slot.
+    pub fn get_slot_node(&self, cluster_name: &ClusterName, slot: usize) -> Option<String> {
+        self.meta_map
+            .lease()
+            .cluster_map
+            .get_slot_node(cluster_name, slot)
+            .map(ToString::to_string)
+    }
+
+    // Returns the proxy address serving the slot under the current metadata.
+    pub fn get_slot_owner(&self, cluster_name: &ClusterName, slot: usize) -> Option<String> {
+        self.meta_map.load().cluster_map.get_slot_owner(
+            cluster_name,
+            slot,
+            &self.config.announce_address,
+        )
+    }
+}
+
+// The topology never received is not stale since there's nothing to route with.
+fn is_topology_stale(last_update: i64, now: i64, timeout: u64) -> bool {
+    timeout != 0 && last_update != 0 && now.saturating_sub(last_update) >= timeout as i64
+}
+
+// Opens a connection to each backend and sends PING
+// so that a broken backend is found before the client traffic arrives.
+pub async fn warm_backends<F: RedisClientFactory>(client_factory: Arc<F>, addresses: Vec<String>) {
+    let total = addresses.len();
+    let futs = addresses.into_iter().map(|address| {
+        let client_factory = client_factory.clone();
+        async move {
+            let mut client = match client_factory.create_client(address.clone()).await {
+                Ok(client) => client,
+                Err(err) => return Err((address, format!("{:?}", err))),
+            };
+            match client.execute_single(vec![b"PING".to_vec()]).await {
+                Ok(Resp::Error(err)) => Err((address, String::from_utf8_lossy(&err).to_string())),
+                Ok(_) => Ok(()),
+                Err(err) => Err((address, format!("{:?}", err))),
+            }
+        }
+    });
+    let results = future::join_all(futs).await;
+    let mut failed = 0;
+    for res in results.into_iter() {
+        if let Err((address, err)) = res {
+            failed += 1;
+            warn!("failed to warm backend {}: {}", address, err);
+        }
+    }
+    info!(
+        "warmed {} backend connections, {} failed",
+        total - failed,
+        failed
+    );
 }
 
 pub fn send_cmd_ctx<C: ConnFactory<Pkt = RespPacket>>(
     meta_map: &SharedMetaMap<C>,
     cmd_ctx: CmdCtx,
     max_redirections: Option<NonZeroUsize>,
+    dead_letters: &DeadLetters,
+) {
+    send_cmd_ctx_with_lease(&meta_map.lease(), cmd_ctx, max_redirections, dead_letters)
+}
+
+fn send_cmd_ctx_with_lease<C: ConnFactory<Pkt = RespPacket>>(
+    meta_map: &Lease<Arc<ProxyMetaMap<C>>>,
+    mut cmd_ctx: CmdCtx,
+    max_redirections: Option<NonZeroUsize>,
+    dead_letters: &DeadLetters,
 ) {
-    let meta_map = meta_map.lease();
+    let dead_letter = dead_letters.draft(&mut cmd_ctx);
+
     let mut cmd_ctx = match meta_map.migration_map.send(cmd_ctx) {
         Ok(()) => return,
         Err(e) => match e {
@@ -371,11 +822,13 @@ pub fn send_cmd_ctx<C: ConnFactory<Pkt = RespPacket>>(
                     slot,
                     address,
                     max_redirections,
+                    dead_letters,
                 );
                 return;
             }
             err => {
                 error!("migration send task failed: {:?}", err);
+                dead_letter.put(err.to_string());
                 return;
             }
         },
@@ -399,10 +852,14 @@ pub fn send_cmd_ctx<C: ConnFactory<Pkt = RespPacket>>(
                     slot,
                     address,
                     max_redirections,
+                    dead_letters,
                 );
                 return;
             }
-            err => warn!("Failed to forward cmd_ctx: {:?}", err),
+            err => {
+                warn!("Failed to forward cmd_ctx: {:?}", err);
+                dead_letter.put(err.to_string());
+            }
         }
     }
 }
@@ -413,7 +870,10 @@ fn send_cmd_ctx_to_remote_directly<C: ConnFactory<Pkt = RespPacket>>(
     slot: usize,
     address: String,
     max_redirections: Option<NonZeroUsize>,
+    dead_letters: &DeadLetters,
 ) {
+    let dead_letter = dead_letters.draft(&mut cmd_ctx);
+
     let times = cmd_ctx
         .get_redirection_times()
         .or_else(|| max_redirections.map(|n| n.get() - 1));
@@ -423,6 +883,7 @@ fn send_cmd_ctx_to_remote_directly<C: ConnFactory<Pkt = RespPacket>>(
                 cmd_ctx.set_resp_result(Ok(Resp::Error(
                     response::ERR_TOO_MANY_REDIRECTIONS.to_string().into_bytes(),
                 )));
+                dead_letter.put(response::ERR_TOO_MANY_REDIRECTIONS.to_string());
                 return;
             }
             Some(times) => times,
@@ -443,7 +904,10 @@ fn send_cmd_ctx_to_remote_directly<C: ConnFactory<Pkt = RespPacket>>(
     if let Err(e) = res {
         match e {
             ClusterSendError::MissingKey => (),
-            err => warn!("Failed to forward cmd_ctx to remote: {:?}", err),
+            err => {
+                warn!("Failed to forward cmd_ctx to remote: {:?}", err);
+                dead_letter.put(err.to_string());
+            }
         }
     }
 }
@@ -451,13 +915,19 @@ fn send_cmd_ctx_to_remote_directly<C: ConnFactory<Pkt = RespPacket>>(
 pub struct BlockingTaskRetrySender<C: ConnFactory<Pkt = RespPacket>> {
     meta_map: SharedMetaMap<C>,
     max_redirections: Option<NonZeroUsize>,
+    dead_letters: Arc<DeadLetters>,
 }
 
 impl<C: ConnFactory<Pkt = RespPacket>> BlockingTaskRetrySender<C> {
-    fn new(meta_map: SharedMetaMap<C>, max_redirections: Option<NonZeroUsize>) -> Self {
+    fn new(
+        meta_map: SharedMetaMap<C>,
+        max_redirections: Option<NonZeroUsize>,
+        dead_letters: Arc<DeadLetters>,
+    ) -> Self {
         Self {
             meta_map,
             max_redirections,
+            dead_letters,
         }
     }
 }
@@ -466,9 +936,166 @@ impl<C: ConnFactory<Pkt = RespPacket>> CmdTaskSender for BlockingTaskRetrySender
     type Task = CmdCtx;
 
     fn send(&self, cmd_task: Self::Task) -> Result<(), BackendError> {
-        send_cmd_ctx(&self.meta_map, cmd_task, self.max_redirections);
+        send_cmd_ctx(
+            &self.meta_map,
+            cmd_task,
+            self.max_redirections,
+            &self.dead_letters,
+        );
         Ok(())
     }
 }
 
 impl<C: ConnFactory<Pkt = RespPacket>> BlockingCmdTaskSender for BlockingTaskRetrySender<C> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{BulkStr, Resp};
+    use crate::test_support::{
+        gen_test_config, setup_proxy_with_config, FakeBackendFunc, TestProxy,
+    };
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio;
+
+    #[test]
+    fn test_is_topology_stale() {
+        assert!(!is_topology_stale(100, 200, 0));
+        assert!(!is_topology_stale(0, 200, 10));
+        assert!(!is_topology_stale(100, 105, 10));
+        assert!(is_topology_stale(100, 110, 10));
+        assert!(is_topology_stale(100, 200, 10));
+    }
+
+    #[tokio::test]
+    async fn test_routing_with_fake_backends() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args.clone()));
+            Resp::Bulk(BulkStr::Str(address.as_bytes().to_vec()))
+        });
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-8000",
+                "mydb",
+                "127.0.0.1:6380",
+                "1",
+                "8001-12000",
+                "PEER",
+                "mydb",
+                "127.0.0.1:7000",
+                "1",
+                "12001-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        // The slots of "b", "z" and "a" are 3300, 8157 and 15495.
+        let reply = client.send_when_ready(&["GET", "b"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"127.0.0.1:6379".to_vec())));
+        let reply = client.send_when_ready(&["GET", "z"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"127.0.0.1:6380".to_vec())));
+        let reply = client.send(&["GET", "a"]).await;
+        assert_eq!(reply, Resp::Error(b"MOVED 15495 127.0.0.1:7000".to_vec()));
+
+        let received = received.lock().unwrap();
+        assert!(received
+            .iter()
+            .any(|(address, args)| address == "127.0.0.1:6379" && args == &["GET", "b"]));
+    }
+
+    fn count_pings(received: &Mutex<Vec<(String, Vec<String>)>>, address: &str) -> usize {
+        received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(addr, args)| addr == address && args[0] == "PING")
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_warm_new_backends() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args.clone()));
+            Resp::Simple(b"PONG".to_vec())
+        });
+        let mut config = gen_test_config();
+        config.warm_backend_connections = true;
+        let (_proxy, mut client) = setup_proxy_with_config(config, backend).await;
+
+        let wait_pinged = async {
+            while count_pings(&received, "127.0.0.1:6379") == 0 {
+                futures_timer::Delay::new(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait_pinged)
+            .await
+            .expect("the backend is not warmed up");
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "2",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-8191",
+                "mydb",
+                "127.0.0.1:6380",
+                "1",
+                "8192-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let wait_pinged = async {
+            while count_pings(&received, "127.0.0.1:6380") == 0 {
+                futures_timer::Delay::new(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait_pinged)
+            .await
+            .expect("the new backend is not warmed up");
+        // The backend already warmed up is not pinged again.
+        assert_eq!(count_pings(&received, "127.0.0.1:6379"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_no_warm_up_by_default() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |address, args| {
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args.clone()));
+            Resp::Bulk(BulkStr::Str(b"v".to_vec()))
+        });
+        let (_proxy, mut client) = setup_proxy_with_config(gen_test_config(), backend).await;
+        let reply = client.send_when_ready(&["GET", "a"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+        assert_eq!(count_pings(&received, "127.0.0.1:6379"), 0);
+    }
+}
@@ -0,0 +1,162 @@
+use super::service::ServerProxyConfig;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+// Global accounting of the bytes buffered by all the sessions,
+// including the requests being processed and the replies not sent yet,
+// and the data queued by the migration.
+pub struct MemoryBudget {
+    used: AtomicUsize,
+    // Zero means no limit.
+    soft_limit: usize,
+    hard_limit: usize,
+    released: Notify,
+}
+
+impl MemoryBudget {
+    pub fn new(soft_limit: usize, hard_limit: usize) -> Self {
+        Self {
+            used: AtomicUsize::new(0),
+            soft_limit,
+            hard_limit,
+            released: Notify::new(),
+        }
+    }
+
+    pub fn from_config(config: &ServerProxyConfig) -> Self {
+        Self::new(config.memory_soft_limit, config.memory_hard_limit)
+    }
+
+    pub fn allocate(self: &Arc<Self>, size: usize) -> MemoryGuard {
+        self.used.fetch_add(size, Ordering::Relaxed);
+        MemoryGuard {
+            budget: self.clone(),
+            size,
+        }
+    }
+
+    pub fn get_used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn release(&self, size: usize) {
+        let used = self.used.fetch_sub(size, Ordering::Relaxed) - size;
+        if self.soft_limit != 0 && used <= self.soft_limit {
+            self.released.notify();
+        }
+    }
+
+    // Sessions should stop reading new requests until this returns.
+    pub async fn wait_for_soft_limit(&self) {
+        if !self.exceeds_soft_limit() {
+            return;
+        }
+        while self.exceeds_soft_limit() {
+            self.released.notified().await;
+        }
+        // `notify` only wakes up one of the waiting sessions.
+        self.released.notify();
+    }
+
+    // Sessions should stop reading new requests.
+    pub fn exceeds_soft_limit(&self) -> bool {
+        self.soft_limit != 0 && self.get_used() > self.soft_limit
+    }
+
+    // New commands should be rejected.
+    pub fn exceeds_hard_limit(&self) -> bool {
+        self.hard_limit != 0 && self.get_used() > self.hard_limit
+    }
+
+    pub fn get_soft_limit(&self) -> usize {
+        self.soft_limit
+    }
+
+    pub fn get_hard_limit(&self) -> usize {
+        self.hard_limit
+    }
+}
+
+pub struct MemoryGuard {
+    budget: Arc<MemoryBudget>,
+    size: usize,
+}
+
+impl MemoryGuard {
+    pub fn grow(&mut self, size: usize) {
+        self.budget.used.fetch_add(size, Ordering::Relaxed);
+        self.size += size;
+    }
+}
+
+impl Drop for MemoryGuard {
+    fn drop(&mut self) {
+        self.budget.release(self.size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_memory_guard() {
+        let budget = Arc::new(MemoryBudget::new(100, 200));
+        let mut guard1 = budget.allocate(60);
+        assert_eq!(budget.get_used(), 60);
+        assert!(!budget.exceeds_soft_limit());
+
+        let guard2 = budget.allocate(60);
+        assert_eq!(budget.get_used(), 120);
+        assert!(budget.exceeds_soft_limit());
+        assert!(!budget.exceeds_hard_limit());
+
+        guard1.grow(100);
+        assert_eq!(budget.get_used(), 220);
+        assert!(budget.exceeds_hard_limit());
+
+        drop(guard1);
+        assert_eq!(budget.get_used(), 60);
+        drop(guard2);
+        assert_eq!(budget.get_used(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_soft_limit() {
+        let budget = Arc::new(MemoryBudget::new(100, 200));
+        budget.wait_for_soft_limit().await;
+
+        let guard = budget.allocate(150);
+        let done = Arc::new(AtomicUsize::new(0));
+        let waiting: Vec<_> = (0..2)
+            .map(|_| {
+                let budget = budget.clone();
+                let done = done.clone();
+                tokio::spawn(async move {
+                    budget.wait_for_soft_limit().await;
+                    done.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        futures_timer::Delay::new(Duration::from_millis(50)).await;
+        assert_eq!(done.load(Ordering::SeqCst), 0);
+
+        // All the waiting sessions are woken up without polling.
+        drop(guard);
+        let timeout = Duration::from_secs(3);
+        tokio::time::timeout(timeout, futures::future::join_all(waiting))
+            .await
+            .expect("test_wait_for_soft_limit");
+        assert_eq!(done.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_disabled_limit() {
+        let budget = Arc::new(MemoryBudget::new(0, 0));
+        let _guard = budget.allocate(usize::max_value() / 2);
+        assert!(!budget.exceeds_soft_limit());
+        assert!(!budget.exceeds_hard_limit());
+    }
+}
@@ -1,12 +1,13 @@
 use super::backend::{CmdTask, CmdTaskFactory, ReqTask};
 use super::command::{requires_blocking_migration, CmdTypeTuple, CommandError};
+use super::memory::{MemoryBudget, MemoryGuard};
 use super::sender::CmdTaskSender;
 use crate::common::response;
 use crate::common::utils::pretty_print_bytes;
 use crate::migration::scan_migration::{pttl_to_restore_expire_time, PTTL_KEY_NOT_FOUND};
 use crate::protocol::{Array, BinSafeStr, BulkStr, RFunctor, Resp, RespVec, VFunctor};
 use atomic_option::AtomicOption;
-use dashmap::DashSet;
+use dashmap::{DashMap, DashSet};
 use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::{select, Future, FutureExt, StreamExt};
 use futures_timer::Delay;
@@ -14,7 +15,7 @@ use std::fmt;
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const KEY_NOT_EXISTS: &str = "0";
 const FAILED_TO_ACCESS_SOURCE: &str = "MIGRATION_FORWARD: failed to access source node";
@@ -221,6 +222,8 @@ struct MgrCmdStateRestoreForward<F: CmdTaskFactory> {
     lock_guard: KeyLockGuard,
     key: BinSafeStr,
     task_context: <F::Task as CmdTask>::Context,
+    // The dumped data is charged until the RESTORE is done.
+    memory_guard: MemoryGuard,
 }
 
 impl<F: CmdTaskFactory> MgrCmdStateRestoreForward<F> {
@@ -228,6 +231,7 @@ impl<F: CmdTaskFactory> MgrCmdStateRestoreForward<F> {
         state: MgrCmdStateDumpPttl<F>,
         entry: DataEntry,
         cmd_task_factory: &F,
+        memory_budget: &Arc<MemoryBudget>,
     ) -> (Self, ReqTask<F::Task>, ReplyFuture) {
         let MgrCmdStateDumpPttl {
             inner_task,
@@ -236,6 +240,7 @@ impl<F: CmdTaskFactory> MgrCmdStateRestoreForward<F> {
         } = state;
         let task_context = inner_task.get_context();
         let DataEntry { raw_data, pttl } = entry;
+        let memory_guard = memory_budget.allocate(raw_data.len());
         let resp = Self::gen_restore_resp(&key, raw_data, pttl);
         let (restore_cmd_task, restore_reply_fut) =
             cmd_task_factory.create_with_ctx(inner_task.get_context(), resp);
@@ -246,6 +251,7 @@ impl<F: CmdTaskFactory> MgrCmdStateRestoreForward<F> {
                 lock_guard,
                 key,
                 task_context,
+                memory_guard,
             },
             task,
             restore_reply_fut,
@@ -325,6 +331,8 @@ where
     )>,
     cmd_task_factory: Arc<F>,
     key_lock: Arc<KeyLock>,
+    recent_writes: Arc<RecentWrites>,
+    memory_budget: Arc<MemoryBudget>,
 }
 
 impl<F, S, PS> RestoreDataCmdTaskHandler<F, S, PS>
@@ -339,6 +347,8 @@ where
         dst_sender: S,
         src_proxy_sender: PS,
         cmd_task_factory: Arc<F>,
+        read_your_writes_window: Duration,
+        memory_budget: Arc<MemoryBudget>,
     ) -> Self {
         let src_sender = Arc::new(src_sender);
         let dst_sender = Arc::new(dst_sender);
@@ -356,6 +366,7 @@ where
             del_task_receiver,
         )));
         let key_lock = Arc::new(KeyLock::new(LOCK_SHARD_SIZE));
+        let recent_writes = Arc::new(RecentWrites::new(read_your_writes_window));
         Self {
             src_sender,
             dst_sender,
@@ -368,6 +379,8 @@ where
             task_receivers,
             cmd_task_factory,
             key_lock,
+            recent_writes,
+            memory_budget,
         }
     }
 
@@ -388,6 +401,8 @@ where
         let restore_task_sender = self.restore_task_sender.clone();
         let cmd_task_factory = self.cmd_task_factory.clone();
         let key_lock = self.key_lock.clone();
+        let recent_writes = self.recent_writes.clone();
+        let memory_budget = self.memory_budget.clone();
 
         let receiver_opt = self.task_receivers.take(Ordering::SeqCst).map(|p| *p);
         let (
@@ -414,6 +429,7 @@ where
             src_proxy_sender,
             cmd_task_factory.clone(),
             key_lock.clone(),
+            recent_writes.clone(),
         );
 
         let dump_pttl_task_handler = Self::handle_dump_pttl_task(
@@ -421,6 +437,8 @@ where
             restore_task_sender,
             dst_sender.clone(),
             cmd_task_factory.clone(),
+            recent_writes.clone(),
+            memory_budget,
         );
 
         let restore_task_handler = Self::handle_restore(
@@ -430,7 +448,8 @@ where
             cmd_task_factory,
         );
 
-        let umsync_task_handler = Self::handle_umsync_task(umsync_task_receiver, dst_sender);
+        let umsync_task_handler =
+            Self::handle_umsync_task(umsync_task_receiver, dst_sender, recent_writes);
 
         let del_task_handler = Self::handle_del_task(del_task_receiver);
 
@@ -492,6 +511,7 @@ where
         src_proxy_sender: Arc<PS>,
         cmd_task_factory: Arc<F>,
         key_lock: Arc<KeyLock>,
+        recent_writes: Arc<RecentWrites>,
     ) {
         while let Some((state, reply_receiver)) = exists_task_receiver.next().await {
             let res = reply_receiver.await;
@@ -501,6 +521,7 @@ where
             };
 
             if key_exists {
+                recent_writes.record_write(&state.inner_task);
                 let (_state, req_task) = MgrCmdStateForward::from_state_exists(state);
                 if let Err(err) = dst_sender.send(req_task) {
                     debug!("failed to forward: {:?}", err);
@@ -544,6 +565,7 @@ where
                                 Err(()) => continue,
                             };
                             if key_exists {
+                                recent_writes.record_write(&state.inner_task);
                                 let (_state, req_task) =
                                     MgrCmdStateForward::from_state_exists(state);
                                 if let Err(err) = dst_sender.send(req_task) {
@@ -611,6 +633,8 @@ where
         restore_task_sender: RestoreTaskSender<F>,
         dst_sender: Arc<S>,
         cmd_task_factory: Arc<F>,
+        recent_writes: Arc<RecentWrites>,
+        memory_budget: Arc<MemoryBudget>,
     ) {
         while let Some((state, reply_fut)) = dump_pttl_task_receiver.next().await {
             let res = reply_fut.await;
@@ -618,6 +642,7 @@ where
                 Ok(Some(entry)) => entry,
                 Ok(None) => {
                     // The key also does not exist in source node.
+                    recent_writes.record_write(&state.inner_task);
                     let (_state, req_task) = MgrCmdStateForward::from_state_dump_pttl(state);
                     if let Err(err) = dst_sender.send(req_task) {
                         debug!("failed to send forward: {:?}", err);
@@ -634,8 +659,13 @@ where
                 }
             };
 
-            let (state, req_task, reply_receiver) =
-                MgrCmdStateRestoreForward::from_state_exists(state, entry, &(*cmd_task_factory));
+            recent_writes.record_write(&state.inner_task);
+            let (state, req_task, reply_receiver) = MgrCmdStateRestoreForward::from_state_exists(
+                state,
+                entry,
+                &(*cmd_task_factory),
+                &memory_budget,
+            );
             if let Err(err) = dst_sender.send(req_task) {
                 debug!("failed to send restore and forward: {:?}", err);
             }
@@ -664,8 +694,10 @@ where
                 key,
                 task_context,
                 lock_guard,
+                memory_guard,
             } = state;
             drop(lock_guard);
+            drop(memory_guard);
 
             const BUSYKEY: &[u8] = b"BUSYKEY";
             match resp {
@@ -695,6 +727,7 @@ where
     async fn handle_umsync_task(
         mut umsync_task_receiver: UmSyncTaskReceiver<F>,
         dst_sender: Arc<S>,
+        recent_writes: Arc<RecentWrites>,
     ) {
         while let Some((state, reply_fut)) = umsync_task_receiver.next().await {
             // The DUMP and RESTORE has already been processed in the source proxy.
@@ -720,6 +753,7 @@ where
                 _ => (),
             };
 
+            recent_writes.record_write(&state.inner_task);
             let (_state, req_task) = MgrCmdStateForward::from_state_umsync(state);
             if let Err(err) = dst_sender.send(req_task) {
                 debug!("failed to forward: {:?}", err);
@@ -756,6 +790,16 @@ where
             }
         };
 
+        // The latest data of recently written keys are already in the destination node.
+        // Skip checking the source node which might still have stale data.
+        if self.recent_writes.is_recent(&key) {
+            self.recent_writes.record_write(&cmd_task);
+            if let Err(err) = self.dst_sender.send(ReqTask::Simple(cmd_task)) {
+                debug!("failed to forward: {:?}", err);
+            }
+            return;
+        }
+
         let (state, reply_fut) = match Self::send_to_exist_to_src(
             cmd_task,
             &(*self.cmd_task_factory),
@@ -812,6 +856,60 @@ impl<F: CmdTaskFactory> Drop for StopHandle<F> {
 }
 
 const LOCK_SHARD_SIZE: usize = 256;
+const RECENT_WRITES_CLEANUP_SIZE: usize = 1024;
+
+// Keys written to the destination node within the `window`.
+// Each entry takes roughly the key size plus 64 bytes.
+// The expired entries are only removed when the map grows
+// larger than `RECENT_WRITES_CLEANUP_SIZE`.
+struct RecentWrites {
+    window: Duration,
+    keys: DashMap<BinSafeStr, Instant>,
+}
+
+impl RecentWrites {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            keys: DashMap::new(),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.window != Duration::from_millis(0)
+    }
+
+    fn record_write<T: CmdTask<TaskType = CmdTypeTuple>>(&self, cmd_task: &T) {
+        if !self.is_enabled() {
+            return;
+        }
+        if cmd_task.is_read_only() {
+            return;
+        }
+        let key = match cmd_task.get_key() {
+            Some(key) => key,
+            None => return,
+        };
+
+        let now = Instant::now();
+        if self.keys.len() >= RECENT_WRITES_CLEANUP_SIZE {
+            let window = self.window;
+            self.keys
+                .retain(|_, write_time| now.duration_since(*write_time) < window);
+        }
+        self.keys.insert(key.to_vec(), now);
+    }
+
+    fn is_recent(&self, key: &[u8]) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        match self.keys.get(key) {
+            Some(write_time) => write_time.elapsed() < self.window,
+            None => false,
+        }
+    }
+}
 
 struct KeyLock {
     inner: Arc<KeyLockInner>,
@@ -1093,6 +1191,8 @@ mod tests {
             DummyCmdTaskSender::new(true, HashMap::new(), 666),
             DummyCmdTaskSender::new(false, HashMap::new(), 0),
             Arc::new(CmdCtxFactory::default()),
+            Duration::from_millis(0),
+            Arc::new(MemoryBudget::new(0, 0)),
         );
 
         let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1116,6 +1216,8 @@ mod tests {
             DummyCmdTaskSender::new(false, HashMap::new(), 1),
             DummyCmdTaskSender::new(false, HashMap::new(), 0),
             Arc::new(CmdCtxFactory::default()),
+            Duration::from_millis(0),
+            Arc::new(MemoryBudget::new(0, 0)),
         );
 
         let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1135,6 +1237,35 @@ mod tests {
         assert_eq!(s, "get_reply".to_string().into_bytes());
     }
 
+    #[tokio::test]
+    async fn test_read_your_writes() {
+        let handler = RestoreDataCmdTaskHandler::new(
+            DummyCmdTaskSender::new(false, HashMap::new(), 666),
+            DummyCmdTaskSender::new(false, HashMap::new(), 233),
+            DummyCmdTaskSender::new(false, HashMap::new(), 0),
+            Arc::new(CmdCtxFactory::default()),
+            Duration::from_secs(60),
+            Arc::new(MemoryBudget::new(0, 0)),
+        );
+
+        let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["DEL", "somekey"]);
+        handler.handle_cmd_task(cmd_ctx);
+        let s = run_future(&handler, reply_receiver).await;
+        assert_eq!(s, "1".to_string().into_bytes());
+        assert_eq!(handler.dst_sender.get_cmd_count("EXISTS"), Some(1));
+        assert_eq!(handler.src_proxy_sender.get_cmd_count("UMSYNC"), Some(1));
+
+        // Should not check the source node again.
+        let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
+        handler.handle_cmd_task(cmd_ctx);
+        let s = gen_reply_future(reply_receiver).await.unwrap();
+        assert_eq!(s, "key_not_exists".to_string().into_bytes());
+        assert_eq!(handler.dst_sender.get_cmd_count("EXISTS"), Some(1));
+        assert_eq!(handler.dst_sender.get_cmd_count("GET"), Some(1));
+        assert_eq!(handler.src_sender.get_cmd_count("DUMP"), None);
+        assert_eq!(handler.src_proxy_sender.get_cmd_count("UMSYNC"), Some(1));
+    }
+
     #[tokio::test]
     async fn test_key_both_not_exists() {
         let handler = RestoreDataCmdTaskHandler::new(
@@ -1142,6 +1273,8 @@ mod tests {
             DummyCmdTaskSender::new(false, HashMap::new(), 233),
             DummyCmdTaskSender::new(false, HashMap::new(), 0),
             Arc::new(CmdCtxFactory::default()),
+            Duration::from_millis(0),
+            Arc::new(MemoryBudget::new(0, 0)),
         );
 
         let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1173,6 +1306,8 @@ mod tests {
                 DummyCmdTaskSender::new(true, err_set.clone(), 666),
                 DummyCmdTaskSender::new(false, HashMap::new(), 0),
                 Arc::new(CmdCtxFactory::default()),
+                Duration::from_millis(0),
+                Arc::new(MemoryBudget::new(0, 0)),
             );
 
             let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1205,6 +1340,8 @@ mod tests {
                 DummyCmdTaskSender::new(true, err_set.clone(), 666),
                 DummyCmdTaskSender::new(false, HashMap::new(), 0),
                 Arc::new(CmdCtxFactory::default()),
+                Duration::from_millis(0),
+                Arc::new(MemoryBudget::new(0, 0)),
             );
 
             let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1241,6 +1378,8 @@ mod tests {
                 DummyCmdTaskSender::new(false, HashMap::new(), 666),
                 DummyCmdTaskSender::new(false, HashMap::new(), 0),
                 Arc::new(CmdCtxFactory::default()),
+                Duration::from_millis(0),
+                Arc::new(MemoryBudget::new(0, 0)),
             );
 
             let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1273,6 +1412,8 @@ mod tests {
                 DummyCmdTaskSender::new(false, HashMap::new(), 666),
                 DummyCmdTaskSender::new(false, HashMap::new(), 0),
                 Arc::new(CmdCtxFactory::default()),
+                Duration::from_millis(0),
+                Arc::new(MemoryBudget::new(0, 0)),
             );
 
             let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1305,6 +1446,8 @@ mod tests {
                 DummyCmdTaskSender::new(false, err_set.clone(), 666),
                 DummyCmdTaskSender::new(false, HashMap::new(), 0),
                 Arc::new(CmdCtxFactory::default()),
+                Duration::from_millis(0),
+                Arc::new(MemoryBudget::new(0, 0)),
             );
 
             let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1337,6 +1480,8 @@ mod tests {
             DummyCmdTaskSender::new(false, HashMap::new(), -2),
             DummyCmdTaskSender::new(false, HashMap::new(), 0),
             Arc::new(CmdCtxFactory::default()),
+            Duration::from_millis(0),
+            Arc::new(MemoryBudget::new(0, 0)),
         );
 
         let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
@@ -1363,6 +1508,8 @@ mod tests {
             DummyCmdTaskSender::new(false, HashMap::new(), 1),
             DummyCmdTaskSender::new(false, HashMap::new(), 0),
             Arc::new(CmdCtxFactory::default()),
+            Duration::from_millis(0),
+            Arc::new(MemoryBudget::new(0, 0)),
         );
 
         let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx(vec!["DEL", "somekey"]);
@@ -1400,4 +1547,29 @@ mod tests {
             assert!(lock.lock(another_key.clone(), 0).is_some());
         }
     }
+
+    #[test]
+    fn test_dumped_data_charged() {
+        let memory_budget = Arc::new(MemoryBudget::new(0, 0));
+        let lock = KeyLock::new(1);
+        let (cmd_ctx, _reply_receiver) = gen_test_cmd_ctx(vec!["GET", "somekey"]);
+        let state = MgrCmdStateDumpPttl::<CmdCtxFactory> {
+            inner_task: cmd_ctx,
+            key: b"somekey".to_vec(),
+            lock_guard: lock.lock(b"somekey".to_vec(), 0).unwrap(),
+        };
+        let entry = DataEntry {
+            raw_data: b"dump_reply".to_vec(),
+            pttl: b"0".to_vec(),
+        };
+        let (state, _req_task, _reply_fut) = MgrCmdStateRestoreForward::from_state_exists(
+            state,
+            entry,
+            &CmdCtxFactory::default(),
+            &memory_budget,
+        );
+        assert_eq!(memory_budget.get_used(), b"dump_reply".len());
+        drop(state);
+        assert_eq!(memory_budget.get_used(), 0);
+    }
 }
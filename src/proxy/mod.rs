@@ -3,12 +3,28 @@ pub mod blocking;
 pub mod cluster;
 pub mod command;
 mod compress;
+pub mod deadletter;
 pub mod executor;
+mod failover;
+pub mod flapping;
+mod hedge;
+pub mod inflight;
+mod keyprefix;
+pub mod keyspec;
+pub mod latency;
 pub mod manager;
+pub mod memory;
 pub mod migration_backend;
+pub mod monitor;
+pub mod pause;
+pub mod pubsub;
+pub mod readcache;
+pub mod replica;
 pub mod reply;
 pub mod sender;
 pub mod service;
 pub mod session;
-mod slot;
+mod shadow;
+pub mod slot;
 pub mod slowlog;
+pub mod traffic;
@@ -0,0 +1,375 @@
+use super::command::{CmdType, Command, DataCmdType};
+use super::session::SessionState;
+use crate::protocol::{Resp, RespVec};
+use chrono::Utc;
+use futures::channel::mpsc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const REDACTED: &[u8] = b"(redacted)";
+
+// The lines not sent to the client yet. Like the `client-output-buffer-limit`
+// of redis, the monitor falling behind is dropped instead of using up the memory.
+pub const MONITOR_QUEUE_SIZE: usize = 10000;
+
+// Each session has its own bounded channel for the MONITOR lines.
+pub type MonitorSender = mpsc::Sender<RespVec>;
+pub type MonitorReceiver = mpsc::Receiver<RespVec>;
+
+// The sessions running MONITOR.
+// Each command received by the proxy is formatted into a line
+// in the same format as redis and pushed to all of them.
+#[derive(Default)]
+pub struct CommandMonitor {
+    // session id => the monitor channel of the session
+    monitors: Mutex<HashMap<usize, MonitorSender>>,
+    // Checked for each command so that there's no locking without any monitor.
+    monitor_num: AtomicUsize,
+}
+
+impl CommandMonitor {
+    pub fn add(&self, session_id: usize, monitor_sender: MonitorSender) {
+        let mut monitors = self.monitors.lock().expect("CommandMonitor::add");
+        monitors.insert(session_id, monitor_sender);
+        self.monitor_num.store(monitors.len(), Ordering::SeqCst);
+    }
+
+    pub fn remove(&self, session_id: usize) {
+        if !self.is_active() {
+            return;
+        }
+        let mut monitors = self.monitors.lock().expect("CommandMonitor::remove");
+        monitors.remove(&session_id);
+        self.monitor_num.store(monitors.len(), Ordering::SeqCst);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.monitor_num.load(Ordering::SeqCst) > 0
+    }
+
+    pub fn get_monitor_num(&self) -> usize {
+        self.monitor_num.load(Ordering::SeqCst)
+    }
+
+    pub fn feed(&self, session_state: &SessionState, cmd: &Command) {
+        if !self.is_active() || !should_feed(cmd) {
+            return;
+        }
+        let line = format_monitor_line(session_state, cmd);
+        let mut monitors = self.monitors.lock().expect("CommandMonitor::feed");
+        let curr_session_id = session_state.get_session_id();
+        // The closed sessions are removed here.
+        monitors.retain(|session_id, monitor_sender| {
+            // The commands of the monitor itself such as RESET are not sent back
+            // so that they won't be mixed up with its replies.
+            if *session_id == curr_session_id {
+                return true;
+            }
+            match monitor_sender.try_send(Resp::Simple(line.clone().into_bytes())) {
+                Ok(()) => true,
+                Err(err) => {
+                    if err.is_full() {
+                        warn!(
+                            "drop the MONITOR session {} which can't keep up with the commands",
+                            session_id
+                        );
+                        // The session is closed after sending the queued lines.
+                        monitor_sender.close_channel();
+                    }
+                    false
+                }
+            }
+        });
+        self.monitor_num.store(monitors.len(), Ordering::SeqCst);
+    }
+}
+
+// Same as redis, the admin commands are not shown.
+fn should_feed(cmd: &Command) -> bool {
+    !cmd.is_admin_cmd()
+        && match cmd.get_type() {
+            CmdType::UmForward | CmdType::UmSync | CmdType::Monitor | CmdType::Invalid => false,
+            _ => true,
+        }
+}
+
+// <timestamp> [<db> <addr>] "CMD" "arg"
+// The db is the cluster name of the session.
+fn format_monitor_line(session_state: &SessionState, cmd: &Command) -> String {
+    let now = Utc::now();
+    let mut line = format!(
+        "{}.{:06} [{} {}]",
+        now.timestamp(),
+        now.timestamp_subsec_micros(),
+        session_state.get_cluster_name(),
+        session_state.get_peer_address().unwrap_or(""),
+    );
+    let len = cmd.get_command_len().unwrap_or(0);
+    let redacted = get_redacted_range(cmd);
+    for i in 0..len {
+        let arg = match cmd.get_command_element(i) {
+            Some(_) if redacted.contains(&i) => REDACTED,
+            Some(arg) => arg,
+            None => continue,
+        };
+        line.push(' ');
+        quote_arg(&mut line, arg);
+    }
+    line
+}
+
+// The index range of the arguments which should not be shown.
+fn get_redacted_range(cmd: &Command) -> std::ops::Range<usize> {
+    let len = cmd.get_command_len().unwrap_or(0);
+    match cmd.get_type() {
+        CmdType::Auth => 1..len,
+        // HELLO [protover [AUTH username password] [SETNAME clientname]]
+        CmdType::Hello => (2..len)
+            .find(|i| {
+                cmd.get_command_element(*i)
+                    .map(|arg| arg.eq_ignore_ascii_case(b"AUTH"))
+                    .unwrap_or(false)
+            })
+            .map(|i| (i + 1)..std::cmp::min(i + 3, len))
+            .unwrap_or(0..0),
+        // MIGRATE host port key db timeout [COPY] [REPLACE] [AUTH password]
+        //   [AUTH2 username password] [KEYS key ...]
+        CmdType::Others if cmd.get_data_cmd_type() == DataCmdType::MIGRATE => (6..len)
+            .take_while(|i| {
+                cmd.get_command_element(*i)
+                    .map(|arg| !arg.eq_ignore_ascii_case(b"KEYS"))
+                    .unwrap_or(false)
+            })
+            .find_map(|i| {
+                let arg = cmd.get_command_element(i)?;
+                if arg.eq_ignore_ascii_case(b"AUTH") {
+                    Some((i + 1)..std::cmp::min(i + 2, len))
+                } else if arg.eq_ignore_ascii_case(b"AUTH2") {
+                    Some((i + 1)..std::cmp::min(i + 3, len))
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(0..0),
+        _ => 0..0,
+    }
+}
+
+// The same escaping as the `sdscatrepr` of redis.
+fn quote_arg(line: &mut String, arg: &[u8]) {
+    line.push('"');
+    for b in arg {
+        match *b {
+            b'\\' => line.push_str("\\\\"),
+            b'"' => line.push_str("\\\""),
+            b'\n' => line.push_str("\\n"),
+            b'\r' => line.push_str("\\r"),
+            b'\t' => line.push_str("\\t"),
+            0x07 => line.push_str("\\a"),
+            0x08 => line.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => line.push(b as char),
+            b => line.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    line.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::cluster::ClusterName;
+    use crate::protocol::{Array, BulkStr, Resp, RespPacket};
+    use crate::proxy::session::ClientSource;
+    use crate::test_support::{FakeBackendFunc, TestProxy};
+    use futures::channel::mpsc;
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+    use tokio;
+
+    fn gen_cmd(args: &[&[u8]]) -> Command {
+        let args = args
+            .iter()
+            .map(|arg| Resp::Bulk(BulkStr::Str(arg.to_vec())))
+            .collect();
+        let resp = Resp::Arr(Array::Arr(args));
+        Command::new(Box::new(RespPacket::from_resp_vec(resp)))
+    }
+
+    fn gen_session_state() -> SessionState {
+        SessionState::new(ClusterName::try_from("mydb").unwrap(), false).with_client(
+            1,
+            ClientSource {
+                peer_ip: Some("127.0.0.1".to_string()),
+                peer_address: Some("127.0.0.1:6000".to_string()),
+                admin_port: false,
+            },
+        )
+    }
+
+    // Removes the timestamp.
+    fn format_line(cmd: &Command) -> String {
+        let line = format_monitor_line(&gen_session_state(), cmd);
+        let (timestamp, rest) = line.split_at(line.find(' ').unwrap());
+        assert!(timestamp.parse::<f64>().is_ok());
+        rest.to_string()
+    }
+
+    #[test]
+    fn test_format_monitor_line() {
+        assert_eq!(
+            format_line(&gen_cmd(&[b"SET", b"key", b"a \"b\"\n\x01"])),
+            " [mydb 127.0.0.1:6000] \"SET\" \"key\" \"a \\\"b\\\"\\n\\x01\""
+        );
+        assert_eq!(
+            format_line(&gen_cmd(&[b"AUTH", b"user", b"password"])),
+            " [mydb 127.0.0.1:6000] \"AUTH\" \"(redacted)\" \"(redacted)\""
+        );
+        assert_eq!(
+            format_line(&gen_cmd(&[
+                b"HELLO",
+                b"2",
+                b"AUTH",
+                b"user",
+                b"password",
+                b"SETNAME",
+                b"name"
+            ])),
+            " [mydb 127.0.0.1:6000] \"HELLO\" \"2\" \"AUTH\" \"(redacted)\" \"(redacted)\" \"SETNAME\" \"name\""
+        );
+        assert_eq!(
+            format_line(&gen_cmd(&[
+                b"MIGRATE",
+                b"127.0.0.1",
+                b"6379",
+                b"",
+                b"0",
+                b"1000",
+                b"COPY",
+                b"AUTH",
+                b"password",
+                b"KEYS",
+                b"AUTH"
+            ])),
+            " [mydb 127.0.0.1:6000] \"MIGRATE\" \"127.0.0.1\" \"6379\" \"\" \"0\" \"1000\" \"COPY\" \"AUTH\" \"(redacted)\" \"KEYS\" \"AUTH\""
+        );
+        assert_eq!(
+            format_line(&gen_cmd(&[
+                b"MIGRATE",
+                b"127.0.0.1",
+                b"6379",
+                b"key",
+                b"0",
+                b"1000",
+                b"AUTH2",
+                b"user",
+                b"password"
+            ])),
+            " [mydb 127.0.0.1:6000] \"MIGRATE\" \"127.0.0.1\" \"6379\" \"key\" \"0\" \"1000\" \"AUTH2\" \"(redacted)\" \"(redacted)\""
+        );
+    }
+
+    #[test]
+    fn test_command_monitor() {
+        let monitor = CommandMonitor::default();
+        let state = gen_session_state();
+        let cmd = gen_cmd(&[b"GET", b"key"]);
+        assert!(!monitor.is_active());
+        monitor.feed(&state, &cmd);
+
+        // The session of `state` is 1.
+        let (sender, mut receiver) = mpsc::channel(MONITOR_QUEUE_SIZE);
+        monitor.add(2, sender);
+        assert!(monitor.is_active());
+        monitor.feed(&state, &cmd);
+        // Not shown.
+        monitor.feed(&state, &gen_cmd(&[b"UMCTL", b"SETCLUSTER"]));
+        monitor.feed(&state, &gen_cmd(&[b"MONITOR"]));
+
+        let line = match receiver.try_next() {
+            Ok(Some(Resp::Simple(line))) => String::from_utf8(line).unwrap(),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert!(line.ends_with("[mydb 127.0.0.1:6000] \"GET\" \"key\""));
+        assert!(receiver.try_next().is_err());
+
+        // Removed on closed.
+        drop(receiver);
+        monitor.feed(&state, &cmd);
+        assert!(!monitor.is_active());
+
+        let (sender, _receiver) = mpsc::channel(MONITOR_QUEUE_SIZE);
+        monitor.add(3, sender);
+        monitor.remove(3);
+        assert_eq!(monitor.get_monitor_num(), 0);
+    }
+
+    #[test]
+    fn test_drop_slow_monitor() {
+        let monitor = CommandMonitor::default();
+        let state = gen_session_state();
+        let cmd = gen_cmd(&[b"GET", b"key"]);
+
+        // Only one line could be queued.
+        let (sender, mut receiver) = mpsc::channel(0);
+        monitor.add(2, sender);
+        monitor.feed(&state, &cmd);
+        assert!(monitor.is_active());
+        monitor.feed(&state, &cmd);
+        assert!(!monitor.is_active());
+
+        // The queued line is still sent before the channel is closed.
+        assert!(matches!(receiver.try_next(), Ok(Some(Resp::Simple(_)))));
+        assert!(matches!(receiver.try_next(), Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn test_monitor() {
+        let backend: FakeBackendFunc = Arc::new(|_address, _args| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::new(backend);
+        let mut monitor_client = proxy.connect();
+        let mut client = proxy.connect();
+
+        let reply = monitor_client.send(&["MONITOR"]).await;
+        assert_eq!(
+            reply,
+            Resp::Error(
+                b"ERR MONITOR is disabled on this proxy. Set `monitor_enabled` to enable it"
+                    .to_vec()
+            )
+        );
+        let reply = client
+            .send(&["CONFIG", "SET", "monitor_enabled", "true"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = monitor_client.send(&["MONITOR"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client.send(&["AUTH", "mydb"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send(&["ECHO", "a b"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"a b".to_vec())));
+        // Not shown.
+        let reply = client.send(&["CONFIG", "GET", "monitor_enabled"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"true".to_vec())));
+
+        let expected = [
+            " [admin ] \"AUTH\" \"(redacted)\"",
+            " [mydb ] \"ECHO\" \"a b\"",
+        ];
+        for expected_line in expected.iter() {
+            let line = match monitor_client.recv().await {
+                Resp::Simple(line) => String::from_utf8(line).unwrap(),
+                other => panic!("unexpected reply {:?}", other),
+            };
+            assert!(line.ends_with(expected_line), "{}", line);
+        }
+
+        // RESET stops the stream.
+        let reply = monitor_client.send(&["RESET"]).await;
+        assert_eq!(reply, Resp::Simple(b"RESET".to_vec()));
+        client.send(&["ECHO", "c"]).await;
+        let reply = monitor_client.send(&["PING"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+    }
+}
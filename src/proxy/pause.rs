@@ -0,0 +1,356 @@
+use super::command::{CmdType, Command};
+use futures::channel::oneshot;
+use futures::future;
+use futures_timer::Delay;
+use std::collections::VecDeque;
+use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PauseMode {
+    All,
+    Write,
+}
+
+#[derive(Default)]
+struct PauseState {
+    // None when not paused.
+    pause: Option<(PauseMode, Instant)>,
+    // Woken in the order the commands get held.
+    waiters: VecDeque<oneshot::Sender<()>>,
+}
+
+impl PauseState {
+    fn get_mode(&mut self, now: Instant) -> Option<(PauseMode, Instant)> {
+        match self.pause {
+            Some((_, until)) if until <= now => {
+                self.release();
+                None
+            }
+            pause => pause,
+        }
+    }
+
+    fn release(&mut self) {
+        self.pause = None;
+        for waiter in self.waiters.drain(..) {
+            // The session may have been closed.
+            let _ = waiter.send(());
+        }
+    }
+}
+
+// For CLIENT PAUSE and CLIENT UNPAUSE.
+// Only the data commands get held so that the clients could still
+// run CLIENT UNPAUSE, INFO and the management commands.
+#[derive(Default)]
+pub struct ClientPause {
+    // Avoids the locking for each command when not paused.
+    paused: AtomicBool,
+    state: Mutex<PauseState>,
+}
+
+impl ClientPause {
+    // A more restrictive or longer pause in effect won't be overridden.
+    pub fn pause(&self, mode: PauseMode, duration: Duration) {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("ClientPause::pause");
+        let (mode, until) = match state.get_mode(now) {
+            Some((curr_mode, curr_until)) => {
+                let mode = if curr_mode == PauseMode::All {
+                    PauseMode::All
+                } else {
+                    mode
+                };
+                (mode, std::cmp::max(curr_until, now + duration))
+            }
+            None => (mode, now + duration),
+        };
+        state.pause = Some((mode, until));
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn unpause(&self) {
+        let mut state = self.state.lock().expect("ClientPause::unpause");
+        self.paused.store(false, Ordering::SeqCst);
+        state.release();
+    }
+
+    pub fn get_mode(&self) -> Option<PauseMode> {
+        if !self.paused.load(Ordering::SeqCst) {
+            return None;
+        }
+        let mut state = self.state.lock().expect("ClientPause::get_mode");
+        let pause = state.get_mode(Instant::now());
+        if pause.is_none() {
+            self.paused.store(false, Ordering::SeqCst);
+        }
+        pause.map(|(mode, _)| mode)
+    }
+
+    pub fn should_hold(&self, cmd: &Command) -> bool {
+        match self.get_mode() {
+            None => false,
+            Some(mode) => hold_in_mode(mode, cmd),
+        }
+    }
+
+    // Returns when the command is no longer held.
+    pub async fn wait(&self, cmd: &Command) {
+        loop {
+            let (receiver, until) = {
+                let now = Instant::now();
+                let mut state = self.state.lock().expect("ClientPause::wait");
+                let until = match state.get_mode(now) {
+                    Some((mode, until)) if hold_in_mode(mode, cmd) => until,
+                    Some(_) => return,
+                    None => {
+                        self.paused.store(false, Ordering::SeqCst);
+                        return;
+                    }
+                };
+                let (sender, receiver) = oneshot::channel();
+                state.waiters.push_back(sender);
+                (receiver, until)
+            };
+            let timeout = until.saturating_duration_since(Instant::now());
+            // Checks the state again in both cases since the client
+            // could have paused them again.
+            future::select(receiver, Delay::new(timeout)).await;
+        }
+    }
+}
+
+// CLIENT PAUSE timeout [WRITE|ALL]
+pub fn parse_client_pause(cmd: &Command) -> Result<(PauseMode, Duration), String> {
+    let timeout = cmd
+        .get_command_element(2)
+        .and_then(|timeout| str::from_utf8(timeout).ok())
+        .and_then(|timeout| timeout.parse::<u64>().ok())
+        .ok_or_else(|| "ERR timeout is not an integer or out of range".to_string())?;
+    let mode = match cmd.get_command_element(3) {
+        None => PauseMode::All,
+        Some(mode) if mode.eq_ignore_ascii_case(b"ALL") => PauseMode::All,
+        Some(mode) if mode.eq_ignore_ascii_case(b"WRITE") => PauseMode::Write,
+        Some(_) => return Err("ERR syntax error".to_string()),
+    };
+    if cmd.get_command_len().unwrap_or(0) > 4 {
+        return Err("ERR syntax error".to_string());
+    }
+    Ok((mode, Duration::from_millis(timeout)))
+}
+
+fn hold_in_mode(mode: PauseMode, cmd: &Command) -> bool {
+    if cmd.get_type() != CmdType::Others {
+        return false;
+    }
+    match mode {
+        PauseMode::All => true,
+        PauseMode::Write => !cmd.is_read_only(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Array, BulkStr, Resp, RespPacket};
+    use crate::test_support::{setup_proxy_with_backend, FakeBackendFunc, TestProxy};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio;
+
+    fn gen_cmd(args: &[&str]) -> Command {
+        let args = args
+            .iter()
+            .map(|arg| Resp::Bulk(BulkStr::Str(arg.as_bytes().to_vec())))
+            .collect();
+        let resp = Resp::Arr(Array::Arr(args));
+        Command::new(Box::new(RespPacket::from_resp_vec(resp)))
+    }
+
+    #[test]
+    fn test_pause_mode() {
+        let pause = ClientPause::default();
+        let get = gen_cmd(&["GET", "key"]);
+        let set = gen_cmd(&["SET", "key", "value"]);
+        let client = gen_cmd(&["CLIENT", "UNPAUSE"]);
+        assert!(!pause.should_hold(&set));
+
+        pause.pause(PauseMode::Write, Duration::from_secs(60));
+        assert_eq!(pause.get_mode(), Some(PauseMode::Write));
+        assert!(pause.should_hold(&set));
+        assert!(!pause.should_hold(&get));
+        assert!(!pause.should_hold(&client));
+
+        pause.pause(PauseMode::All, Duration::from_secs(1));
+        assert!(pause.should_hold(&get));
+        // WRITE won't override ALL.
+        pause.pause(PauseMode::Write, Duration::from_secs(1));
+        assert_eq!(pause.get_mode(), Some(PauseMode::All));
+        assert!(!pause.should_hold(&client));
+
+        pause.unpause();
+        assert_eq!(pause.get_mode(), None);
+        assert!(!pause.should_hold(&set));
+    }
+
+    #[test]
+    fn test_parse_client_pause() {
+        assert_eq!(
+            parse_client_pause(&gen_cmd(&["CLIENT", "PAUSE", "100"])),
+            Ok((PauseMode::All, Duration::from_millis(100)))
+        );
+        assert_eq!(
+            parse_client_pause(&gen_cmd(&["CLIENT", "PAUSE", "100", "write"])),
+            Ok((PauseMode::Write, Duration::from_millis(100)))
+        );
+        assert!(parse_client_pause(&gen_cmd(&["CLIENT", "PAUSE"])).is_err());
+        assert!(parse_client_pause(&gen_cmd(&["CLIENT", "PAUSE", "-1"])).is_err());
+        assert!(parse_client_pause(&gen_cmd(&["CLIENT", "PAUSE", "100", "READ"])).is_err());
+        assert!(parse_client_pause(&gen_cmd(&["CLIENT", "PAUSE", "100", "ALL", "x"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pause_expiry() {
+        let pause = ClientPause::default();
+        pause.pause(PauseMode::All, Duration::from_millis(50));
+        let set = gen_cmd(&["SET", "key", "value"]);
+        let start = Instant::now();
+        pause.wait(&set).await;
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(pause.get_mode(), None);
+    }
+
+    #[tokio::test]
+    async fn test_client_pause_keeps_order() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_address, args| {
+            received_clone.lock().unwrap().push(args.join(" "));
+            match args[0].as_str() {
+                "GET" => Resp::Bulk(BulkStr::Str(b"v1".to_vec())),
+                _ => Resp::Simple(b"OK".to_vec()),
+            }
+        });
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+        let mut paused_client = proxy.connect();
+
+        let reply = client
+            .send(&[
+                "UMCTL",
+                "SETCLUSTER",
+                "1",
+                "NOFLAGS",
+                "mydb",
+                "127.0.0.1:6379",
+                "1",
+                "0-16383",
+            ])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = paused_client.send_when_ready(&["SET", "k0", "v0"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        received.lock().unwrap().clear();
+
+        let reply = client.send(&["CLIENT", "PAUSE", "60000", "WRITE"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = paused_client.send(&["GET", "k1"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v1".to_vec())));
+        received.lock().unwrap().clear();
+
+        let handle = tokio::spawn(async move {
+            paused_client
+                .send_pipeline(&[&["SET", "k1", "v1"], &["GET", "k1"], &["SET", "k2", "v2"]])
+                .await
+        });
+        futures_timer::Delay::new(Duration::from_millis(100)).await;
+        // The GET following the held SET is held too.
+        assert!(received.lock().unwrap().is_empty());
+
+        // The commands other than the data commands are not held.
+        let reply = client.send(&["PING"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send(&["CLIENT", "UNPAUSE"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let replies = handle.await.unwrap();
+        assert_eq!(
+            replies,
+            vec![
+                Resp::Simple(b"OK".to_vec()),
+                Resp::Bulk(BulkStr::Str(b"v1".to_vec())),
+                Resp::Simple(b"OK".to_vec()),
+            ]
+        );
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                "SET k1 v1".to_string(),
+                "GET k1".to_string(),
+                "SET k2 v2".to_string(),
+            ]
+        );
+
+        let reply = client.send(&["CLIENT", "PAUSE", "xx"]).await;
+        assert_eq!(
+            reply,
+            Resp::Error(b"ERR timeout is not an integer or out of range".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_pause_write_lets_reads_through() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_address, args| {
+            received_clone.lock().unwrap().push(args[0].clone());
+            Resp::Simple(b"OK".to_vec())
+        });
+        let (proxy, mut client) = setup_proxy_with_backend(backend).await;
+        let mut paused_client = proxy.connect();
+        let reply = paused_client.send_when_ready(&["SET", "k0", "v0"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client.send(&["CLIENT", "PAUSE", "60000", "WRITE"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        // These reads used to be taken as writes.
+        let reads: [&[&str]; 8] = [
+            &["HGETALL", "key"],
+            &["LRANGE", "key", "0", "-1"],
+            &["SMEMBERS", "key"],
+            &["ZRANGEBYSCORE", "key", "0", "1"],
+            &["XRANGE", "key", "-", "+"],
+            &["TYPE", "key"],
+            &["SUBSTR", "key", "0", "1"],
+            &["GEORADIUS_RO", "key", "0", "0", "1", "km"],
+        ];
+        for read in reads.iter() {
+            let reply = tokio::time::timeout(Duration::from_secs(5), paused_client.send(read))
+                .await
+                .unwrap_or_else(|_| panic!("{:?} should not be held", read));
+            assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        }
+        received.lock().unwrap().clear();
+
+        let handle = tokio::spawn(async move {
+            paused_client
+                .send_pipeline(&[&["HSET", "key", "f", "v"], &["HGETALL", "key"]])
+                .await
+        });
+        futures_timer::Delay::new(Duration::from_millis(100)).await;
+        assert!(received.lock().unwrap().is_empty());
+
+        let reply = client.send(&["CLIENT", "UNPAUSE"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let replies = handle.await.unwrap();
+        assert_eq!(replies, vec![Resp::Simple(b"OK".to_vec()); 2]);
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec!["HSET".to_string(), "HGETALL".to_string()]
+        );
+    }
+}
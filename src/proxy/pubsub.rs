@@ -0,0 +1,505 @@
+use super::backend::ConnFactory;
+use crate::common::utils::resolve_first_address;
+use crate::protocol::{encode_resp, Array, BinSafeStr, BulkStr, Resp, RespPacket, RespVec};
+use bytes::Bytes;
+use futures::channel::{mpsc, oneshot};
+use futures::{future, SinkExt, StreamExt};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+const SSUBSCRIBE: &[u8] = b"ssubscribe";
+const SUNSUBSCRIBE: &[u8] = b"sunsubscribe";
+
+pub type PushSender = mpsc::UnboundedSender<RespVec>;
+pub type PushReceiver = mpsc::UnboundedReceiver<RespVec>;
+// The confirmations of each channel or the error reply of the backend.
+pub type SubscriptionResult = Result<Vec<RespVec>, RespVec>;
+
+struct SubscriptionCmd {
+    cmd: Vec<BinSafeStr>,
+    // One confirmation for each channel.
+    reply_num: usize,
+    reply_sender: oneshot::Sender<SubscriptionResult>,
+}
+
+struct SubscriptionConn {
+    cmd_sender: mpsc::UnboundedSender<SubscriptionCmd>,
+    channels: HashSet<BinSafeStr>,
+}
+
+struct SubscriptionInner {
+    // backend address => the dedicated connection of this session
+    conns: Mutex<HashMap<String, SubscriptionConn>>,
+    // Checked for each command of the session so it's not protected by the lock.
+    channel_num: AtomicUsize,
+    push_sender: PushSender,
+}
+
+// The sharded pub/sub (SSUBSCRIBE) of a session.
+// Each backend owning the subscribed channels gets a dedicated connection
+// and their messages are relayed to the session through the push channel.
+// The connections are closed when this is dropped.
+pub struct ShardedSubscription {
+    inner: Arc<SubscriptionInner>,
+}
+
+impl ShardedSubscription {
+    pub fn new(push_sender: PushSender) -> Self {
+        let inner = SubscriptionInner {
+            conns: Mutex::new(HashMap::new()),
+            channel_num: AtomicUsize::new(0),
+            push_sender,
+        };
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    pub fn get_channel_num(&self) -> usize {
+        self.inner.channel_num.load(Ordering::Relaxed)
+    }
+
+    // The channels should be in the slots of the backend.
+    pub async fn subscribe<C: ConnFactory<Pkt = RespPacket>>(
+        &self,
+        conn_factory: Arc<C>,
+        address: String,
+        channels: Vec<BinSafeStr>,
+    ) -> SubscriptionResult {
+        let reply_num = channels.len();
+        let mut cmd = vec![b"SSUBSCRIBE".to_vec()];
+        cmd.extend(channels);
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let subscription_cmd = SubscriptionCmd {
+            cmd,
+            reply_num,
+            reply_sender,
+        };
+
+        {
+            let mut conns = self.inner.conns.lock().expect("subscribe");
+            let conn = conns.entry(address.clone()).or_insert_with(|| {
+                let (cmd_sender, cmd_receiver) = mpsc::unbounded();
+                let inner = Arc::downgrade(&self.inner);
+                let push_sender = self.inner.push_sender.clone();
+                tokio::spawn(run_subscription_conn(
+                    conn_factory,
+                    address.clone(),
+                    cmd_receiver,
+                    push_sender,
+                    inner,
+                ));
+                SubscriptionConn {
+                    cmd_sender,
+                    channels: HashSet::new(),
+                }
+            });
+            if conn.cmd_sender.unbounded_send(subscription_cmd).is_err() {
+                return Err(gen_conn_closed_error(&address));
+            }
+        }
+
+        reply_receiver
+            .await
+            .unwrap_or_else(|_| Err(gen_conn_closed_error(&address)))
+    }
+
+    // Unsubscribes all the channels if `channels` is None.
+    pub async fn unsubscribe(&self, channels: Option<Vec<BinSafeStr>>) -> SubscriptionResult {
+        let mut local_channels = vec![];
+        let mut receivers = vec![];
+        {
+            let conns = self.inner.conns.lock().expect("unsubscribe");
+            let mut channels_by_address: HashMap<&str, Vec<BinSafeStr>> = HashMap::new();
+            match channels {
+                Some(channels) => {
+                    for channel in channels.into_iter() {
+                        let address = conns
+                            .iter()
+                            .find(|(_, conn)| conn.channels.contains(&channel))
+                            .map(|(address, _)| address.as_str());
+                        match address {
+                            Some(address) => channels_by_address
+                                .entry(address)
+                                .or_insert_with(Vec::new)
+                                .push(channel),
+                            None => local_channels.push(Some(channel)),
+                        }
+                    }
+                }
+                None => {
+                    for (address, conn) in conns.iter() {
+                        if !conn.channels.is_empty() {
+                            let channels = conn.channels.iter().cloned().collect();
+                            channels_by_address.insert(address.as_str(), channels);
+                        }
+                    }
+                    if channels_by_address.is_empty() {
+                        local_channels.push(None);
+                    }
+                }
+            }
+
+            for (address, channels) in channels_by_address.into_iter() {
+                let conn = match conns.get(address) {
+                    Some(conn) => conn,
+                    None => continue,
+                };
+                let reply_num = channels.len();
+                let mut cmd = vec![b"SUNSUBSCRIBE".to_vec()];
+                cmd.extend(channels);
+                let (reply_sender, reply_receiver) = oneshot::channel();
+                let subscription_cmd = SubscriptionCmd {
+                    cmd,
+                    reply_num,
+                    reply_sender,
+                };
+                if conn.cmd_sender.unbounded_send(subscription_cmd).is_err() {
+                    return Err(gen_conn_closed_error(address));
+                }
+                receivers.push((address.to_string(), reply_receiver));
+            }
+        }
+
+        let mut replies = vec![];
+        for (address, reply_receiver) in receivers.into_iter() {
+            let res = reply_receiver
+                .await
+                .unwrap_or_else(|_| Err(gen_conn_closed_error(&address)));
+            replies.extend(res?);
+        }
+
+        // The channels not subscribed are confirmed by the proxy itself.
+        let channel_num = self.get_channel_num();
+        replies.extend(
+            local_channels
+                .into_iter()
+                .map(|channel| gen_confirmation(SUNSUBSCRIBE, channel, channel_num)),
+        );
+        Ok(replies)
+    }
+
+    pub fn clear(&self) {
+        let mut conns = self.inner.conns.lock().expect("clear");
+        conns.clear();
+        self.inner.channel_num.store(0, Ordering::Relaxed);
+    }
+}
+
+fn count_channels(conns: &HashMap<String, SubscriptionConn>) -> usize {
+    conns.values().map(|conn| conn.channels.len()).sum()
+}
+
+// SSUBSCRIBE and SUNSUBSCRIBE get one reply for each channel.
+pub fn gen_replies_packet(replies: Vec<RespVec>) -> Box<RespPacket> {
+    let mut buf = Vec::new();
+    for reply in replies.iter() {
+        // Writing to Vec won't fail.
+        let _ = encode_resp(&mut buf, reply);
+    }
+    Box::new(RespPacket::Encoded(Bytes::from(buf)))
+}
+
+fn gen_conn_closed_error(address: &str) -> RespVec {
+    Resp::Error(format!("ERR subscription connection to {} is closed", address).into_bytes())
+}
+
+fn gen_confirmation(kind: &[u8], channel: Option<BinSafeStr>, channel_num: usize) -> RespVec {
+    let channel = match channel {
+        Some(channel) => Resp::Bulk(BulkStr::Str(channel)),
+        None => Resp::Bulk(BulkStr::Nil),
+    };
+    Resp::Arr(Array::Arr(vec![
+        Resp::Bulk(BulkStr::Str(kind.to_vec())),
+        channel,
+        Resp::Integer(channel_num.to_string().into_bytes()),
+    ]))
+}
+
+// Returns the kind and the channel of the (un)subscribe confirmations.
+fn parse_confirmation(resp: &RespVec) -> Option<(&[u8], &BinSafeStr)> {
+    let elements = match resp {
+        Resp::Arr(Array::Arr(elements)) if elements.len() == 3 => elements,
+        _ => return None,
+    };
+    let kind = match elements.get(0) {
+        Some(Resp::Bulk(BulkStr::Str(kind))) => kind.as_slice(),
+        _ => return None,
+    };
+    if !kind.eq_ignore_ascii_case(SSUBSCRIBE) && !kind.eq_ignore_ascii_case(SUNSUBSCRIBE) {
+        return None;
+    }
+    match elements.get(1) {
+        Some(Resp::Bulk(BulkStr::Str(channel))) => Some((kind, channel)),
+        _ => None,
+    }
+}
+
+impl SubscriptionInner {
+    // Records the channels of the backend and replaces the channel number
+    // of the confirmation with the one of the whole session.
+    // Returns whether it's a confirmation.
+    fn handle_confirmation(&self, address: &str, resp: &mut RespVec) -> bool {
+        let (is_subscribe, channel) = match parse_confirmation(resp) {
+            Some((kind, channel)) => (kind.eq_ignore_ascii_case(SSUBSCRIBE), channel.clone()),
+            None => return false,
+        };
+        let mut conns = self.conns.lock().expect("handle_confirmation");
+        if let Some(conn) = conns.get_mut(address) {
+            if is_subscribe {
+                conn.channels.insert(channel);
+            } else {
+                conn.channels.remove(&channel);
+            }
+        }
+        let channel_num = count_channels(&conns);
+        self.channel_num.store(channel_num, Ordering::Relaxed);
+        if let Resp::Arr(Array::Arr(elements)) = resp {
+            elements[2] = Resp::Integer(channel_num.to_string().into_bytes());
+        }
+        true
+    }
+
+    // The backend is gone, so are the subscriptions on it.
+    fn remove_conn(&self, address: &str) {
+        let mut conns = self.conns.lock().expect("remove_conn");
+        let conn = match conns.remove(address) {
+            Some(conn) => conn,
+            None => return,
+        };
+        let channel_num = count_channels(&conns);
+        self.channel_num.store(channel_num, Ordering::Relaxed);
+        for channel in conn.channels.into_iter() {
+            let msg = gen_confirmation(SUNSUBSCRIBE, Some(channel), channel_num);
+            let _ = self.push_sender.unbounded_send(msg);
+        }
+    }
+}
+
+struct PendingCmd {
+    reply_num: usize,
+    replies: Vec<RespVec>,
+    reply_sender: oneshot::Sender<SubscriptionResult>,
+}
+
+async fn run_subscription_conn<C: ConnFactory<Pkt = RespPacket>>(
+    conn_factory: Arc<C>,
+    address: String,
+    mut cmd_receiver: mpsc::UnboundedReceiver<SubscriptionCmd>,
+    push_sender: PushSender,
+    inner: Weak<SubscriptionInner>,
+) {
+    let conn = match resolve_first_address(&address) {
+        Some(sock_address) => conn_factory.create_conn(sock_address).await.ok(),
+        None => None,
+    };
+    let (mut sink, mut stream) = match conn {
+        Some(conn) => conn,
+        None => {
+            error!("failed to create subscription connection to {}", address);
+            if let Some(inner) = inner.upgrade() {
+                inner.remove_conn(&address);
+            }
+            return;
+        }
+    };
+
+    let mut pending: VecDeque<PendingCmd> = VecDeque::new();
+    loop {
+        match future::select(cmd_receiver.next(), stream.next()).await {
+            future::Either::Left((Some(subscription_cmd), _)) => {
+                let SubscriptionCmd {
+                    cmd,
+                    reply_num,
+                    reply_sender,
+                } = subscription_cmd;
+                let resp = Resp::Arr(Array::Arr(
+                    cmd.into_iter()
+                        .map(|element| Resp::Bulk(BulkStr::Str(element)))
+                        .collect(),
+                ));
+                if let Err(err) = sink.send(RespPacket::Data(resp)).await {
+                    error!("failed to send to subscription connection: {:?}", err);
+                    break;
+                }
+                pending.push_back(PendingCmd {
+                    reply_num,
+                    replies: Vec::with_capacity(reply_num),
+                    reply_sender,
+                });
+            }
+            // The session has gone or unsubscribed all.
+            future::Either::Left((None, _)) => return,
+            future::Either::Right((Some(Ok(packet)), _)) => {
+                let mut resp = packet.into_resp_vec();
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => return,
+                };
+                let is_confirmation = inner.handle_confirmation(&address, &mut resp);
+                let is_error = match resp {
+                    Resp::Error(_) => true,
+                    _ => false,
+                };
+                if !is_confirmation && !is_error {
+                    let _ = push_sender.unbounded_send(resp);
+                    continue;
+                }
+                // The confirmations of SUNSUBSCRIBE from the backend itself,
+                // e.g. when the slot is migrated, are not expected by any command.
+                let mut pending_cmd = match pending.pop_front() {
+                    Some(pending_cmd) => pending_cmd,
+                    None => {
+                        let _ = push_sender.unbounded_send(resp);
+                        continue;
+                    }
+                };
+                if is_error {
+                    let _ = pending_cmd.reply_sender.send(Err(resp));
+                    continue;
+                }
+                pending_cmd.replies.push(resp);
+                if pending_cmd.replies.len() >= pending_cmd.reply_num {
+                    let _ = pending_cmd.reply_sender.send(Ok(pending_cmd.replies));
+                } else {
+                    pending.push_front(pending_cmd);
+                }
+            }
+            future::Either::Right((Some(Err(err)), _)) => {
+                error!("subscription connection error: {:?}", err);
+                break;
+            }
+            future::Either::Right((None, _)) => {
+                warn!("subscription connection to {} is closed", address);
+                break;
+            }
+        }
+    }
+
+    if let Some(inner) = inner.upgrade() {
+        inner.remove_conn(&address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Array, BulkStr, Resp};
+    use crate::test_support::{setup_proxy_with_backend, FakeBackendFunc};
+    use std::sync::Arc;
+    use tokio;
+
+    fn gen_confirmation_of(kind: &[u8], channel: &str, num: usize) -> RespVec {
+        gen_confirmation(kind, Some(channel.as_bytes().to_vec()), num)
+    }
+
+    #[test]
+    fn test_handle_confirmation() {
+        let (push_sender, _push_receiver) = mpsc::unbounded();
+        let subscription = ShardedSubscription::new(push_sender);
+        let inner = subscription.inner.clone();
+        for address in &["127.0.0.1:6379", "127.0.0.1:6380"] {
+            let (cmd_sender, _) = mpsc::unbounded();
+            inner.conns.lock().unwrap().insert(
+                address.to_string(),
+                SubscriptionConn {
+                    cmd_sender,
+                    channels: HashSet::new(),
+                },
+            );
+        }
+
+        // The channel number of each backend is replaced with the one of the session.
+        let mut resp = gen_confirmation_of(SSUBSCRIBE, "a", 1);
+        assert!(inner.handle_confirmation("127.0.0.1:6379", &mut resp));
+        assert_eq!(resp, gen_confirmation_of(SSUBSCRIBE, "a", 1));
+        let mut resp = gen_confirmation_of(SSUBSCRIBE, "b", 1);
+        assert!(inner.handle_confirmation("127.0.0.1:6380", &mut resp));
+        assert_eq!(resp, gen_confirmation_of(SSUBSCRIBE, "b", 2));
+        assert_eq!(subscription.get_channel_num(), 2);
+
+        let mut resp = gen_confirmation_of(SUNSUBSCRIBE, "a", 0);
+        assert!(inner.handle_confirmation("127.0.0.1:6379", &mut resp));
+        assert_eq!(resp, gen_confirmation_of(SUNSUBSCRIBE, "a", 1));
+
+        let mut msg = Resp::Arr(Array::Arr(vec![
+            Resp::Bulk(BulkStr::Str(b"smessage".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"b".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"hello".to_vec())),
+        ]));
+        assert!(!inner.handle_confirmation("127.0.0.1:6380", &mut msg));
+    }
+
+    #[test]
+    fn test_remove_conn() {
+        let (push_sender, mut push_receiver) = mpsc::unbounded();
+        let subscription = ShardedSubscription::new(push_sender);
+        let (cmd_sender, _) = mpsc::unbounded();
+        let mut channels = HashSet::new();
+        channels.insert(b"a".to_vec());
+        subscription.inner.conns.lock().unwrap().insert(
+            "127.0.0.1:6379".to_string(),
+            SubscriptionConn {
+                cmd_sender,
+                channels,
+            },
+        );
+        subscription.inner.remove_conn("127.0.0.1:6379");
+        assert_eq!(subscription.get_channel_num(), 0);
+        assert_eq!(
+            push_receiver.try_next().unwrap().unwrap(),
+            gen_confirmation_of(SUNSUBSCRIBE, "a", 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_without_subscription() {
+        let (push_sender, _push_receiver) = mpsc::unbounded();
+        let subscription = ShardedSubscription::new(push_sender);
+        let replies = subscription.unsubscribe(None).await.unwrap();
+        assert_eq!(replies, vec![gen_confirmation(SUNSUBSCRIBE, None, 0)]);
+        let replies = subscription
+            .unsubscribe(Some(vec![b"a".to_vec()]))
+            .await
+            .unwrap();
+        assert_eq!(replies, vec![gen_confirmation_of(SUNSUBSCRIBE, "a", 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_sharded_pubsub() {
+        let confirmation = |kind: &str, channel: &str, num: usize| {
+            Resp::Arr(Array::Arr(vec![
+                Resp::Bulk(BulkStr::Str(kind.as_bytes().to_vec())),
+                Resp::Bulk(BulkStr::Str(channel.as_bytes().to_vec())),
+                Resp::Integer(num.to_string().into_bytes()),
+            ]))
+        };
+        let backend: FakeBackendFunc = Arc::new(move |_, args| match args[0].as_str() {
+            "SPUBLISH" => Resp::Integer(b"1".to_vec()),
+            "SSUBSCRIBE" => confirmation("ssubscribe", &args[1], 1),
+            "SUNSUBSCRIBE" => confirmation("sunsubscribe", &args[1], 0),
+            _ => Resp::Simple(b"OK".to_vec()),
+        });
+        let (_proxy, mut client) = setup_proxy_with_backend(backend).await;
+
+        let reply = client.send_when_ready(&["SPUBLISH", "ch", "msg"]).await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+
+        let reply = client.send(&["SSUBSCRIBE", "a", "b"]).await;
+        let err = crate::common::response::ERR_NOT_THE_SAME_SLOT;
+        assert_eq!(reply, Resp::Error(err.as_bytes().to_vec()));
+
+        let reply = client.send(&["SSUBSCRIBE", "ch"]).await;
+        assert_eq!(reply, confirmation("ssubscribe", "ch", 1));
+        match client.send(&["GET", "a"]).await {
+            Resp::Error(err) => assert!(err.starts_with(b"ERR Can't execute 'get'")),
+            other => panic!("unexpected reply {:?}", other),
+        }
+
+        let reply = client.send(&["SUNSUBSCRIBE", "ch"]).await;
+        assert_eq!(reply, confirmation("sunsubscribe", "ch", 0));
+        let reply = client.send_when_ready(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+    }
+}
@@ -0,0 +1,265 @@
+use super::service::ServerProxyConfig;
+use crate::common::cluster::ClusterName;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+struct CachedValue {
+    value: Vec<u8>,
+    expire_at: coarsetime::Instant,
+}
+
+pub struct ReadCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+// Caches the GET replies of the keys with the configured prefixes.
+// The writes through this proxy invalidate the keys both before they're sent
+// and after they're replied. The writes through other proxies or directly to
+// the backends are not seen, so the cached value could be stale until it expires.
+pub struct ReadCache {
+    prefixes: Vec<String>,
+    ttl: coarsetime::Duration,
+    max_value_size: usize,
+    max_entries: usize,
+    entries: DashMap<(ClusterName, Vec<u8>), CachedValue>,
+    // Increased by every invalidation. The reply of a GET is dropped
+    // if any invalidation happened after the GET was sent,
+    // since it could be older than the write.
+    version: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ReadCache {
+    pub fn new(
+        prefixes: Vec<String>,
+        ttl: Duration,
+        max_value_size: usize,
+        max_entries: usize,
+    ) -> Self {
+        Self {
+            prefixes,
+            ttl: coarsetime::Duration::from(ttl),
+            max_value_size,
+            max_entries,
+            entries: DashMap::new(),
+            version: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_config(config: &ServerProxyConfig) -> Self {
+        Self::new(
+            config.read_cache_prefixes.clone(),
+            Duration::from_millis(config.read_cache_ttl),
+            config.read_cache_max_value_size,
+            config.read_cache_max_entries,
+        )
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.prefixes.is_empty() && self.ttl.as_u64() > 0 && self.max_entries > 0
+    }
+
+    pub fn is_cached_key(&self, key: &[u8]) -> bool {
+        self.prefixes
+            .iter()
+            .any(|prefix| key.starts_with(prefix.as_bytes()))
+    }
+
+    pub fn get(&self, cluster_name: &ClusterName, key: &[u8]) -> Option<Vec<u8>> {
+        let cache_key = (cluster_name.clone(), key.to_vec());
+        let now = coarsetime::Instant::now();
+        let (value, expired) = match self.entries.get(&cache_key) {
+            Some(cached) if cached.expire_at > now => (Some(cached.value.clone()), false),
+            Some(_) => (None, true),
+            None => (None, false),
+        };
+        // The read lock of the entry should be released before removing it.
+        if expired {
+            self.entries
+                .remove_if(&cache_key, |_, cached| cached.expire_at <= now);
+        }
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    // Should be called before sending the GET.
+    pub fn get_version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    pub fn insert(&self, cluster_name: ClusterName, key: Vec<u8>, value: &[u8], version: u64) {
+        if value.len() > self.max_value_size {
+            return;
+        }
+        if self.entries.len() >= self.max_entries {
+            let now = coarsetime::Instant::now();
+            self.entries.retain(|_, cached| cached.expire_at > now);
+            if self.entries.len() >= self.max_entries {
+                return;
+            }
+        }
+
+        // The version is checked with the entry locked so that
+        // the invalidation either rejects this or removes it afterwards.
+        let entry = self.entries.entry((cluster_name, key));
+        if self.version.load(Ordering::SeqCst) != version {
+            return;
+        }
+        let cached = CachedValue {
+            value: value.to_vec(),
+            expire_at: coarsetime::Instant::now() + self.ttl,
+        };
+        match entry {
+            Entry::Occupied(mut entry) => {
+                entry.insert(cached);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(cached);
+            }
+        }
+    }
+
+    pub fn invalidate(&self, cluster_name: &ClusterName, keys: &[Vec<u8>]) {
+        self.version.fetch_add(1, Ordering::SeqCst);
+        for key in keys.iter() {
+            self.entries.remove(&(cluster_name.clone(), key.clone()));
+        }
+    }
+
+    pub fn clear(&self) {
+        self.version.fetch_add(1, Ordering::SeqCst);
+        self.entries.clear();
+    }
+
+    pub fn get_stats(&self) -> ReadCacheStats {
+        ReadCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{BulkStr, Resp};
+    use crate::test_support::{gen_test_config, setup_proxy_with_config, FakeBackendFunc};
+    use std::convert::TryFrom;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio;
+
+    fn gen_cache(ttl: Duration) -> ReadCache {
+        ReadCache::new(vec!["static:".to_string()], ttl, 8, 2)
+    }
+
+    #[test]
+    fn test_read_cache_hit_and_invalidate() {
+        let cache = gen_cache(Duration::from_secs(60));
+        assert!(cache.is_enabled());
+        assert!(cache.is_cached_key(b"static:a"));
+        assert!(!cache.is_cached_key(b"user:a"));
+
+        let cluster_name = ClusterName::try_from("mydb").unwrap();
+        assert!(cache.get(&cluster_name, b"static:a").is_none());
+        let version = cache.get_version();
+        cache.insert(cluster_name.clone(), b"static:a".to_vec(), b"v", version);
+        assert_eq!(cache.get(&cluster_name, b"static:a"), Some(b"v".to_vec()));
+
+        let other_cluster = ClusterName::try_from("otherdb").unwrap();
+        assert!(cache.get(&other_cluster, b"static:a").is_none());
+
+        cache.invalidate(&cluster_name, &[b"static:a".to_vec()]);
+        assert!(cache.get(&cluster_name, b"static:a").is_none());
+
+        let stats = cache.get_stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.entries, 0);
+    }
+
+    #[test]
+    fn test_read_cache_reject_stale_reply() {
+        let cache = gen_cache(Duration::from_secs(60));
+        let cluster_name = ClusterName::try_from("mydb").unwrap();
+
+        let version = cache.get_version();
+        // A write happens before the GET reply comes back.
+        cache.invalidate(&cluster_name, &[b"static:a".to_vec()]);
+        cache.insert(cluster_name.clone(), b"static:a".to_vec(), b"old", version);
+        assert!(cache.get(&cluster_name, b"static:a").is_none());
+    }
+
+    #[test]
+    fn test_read_cache_limits() {
+        let cache = gen_cache(Duration::from_secs(60));
+        let cluster_name = ClusterName::try_from("mydb").unwrap();
+        let version = cache.get_version();
+
+        cache.insert(cluster_name.clone(), b"static:a".to_vec(), &[0; 9], version);
+        assert!(cache.get(&cluster_name, b"static:a").is_none());
+
+        for key in [b"static:a", b"static:b", b"static:c"].iter() {
+            cache.insert(cluster_name.clone(), key.to_vec(), b"v", version);
+        }
+        assert_eq!(cache.get_stats().entries, 2);
+        assert!(cache.get(&cluster_name, b"static:c").is_none());
+
+        assert!(!gen_cache(Duration::from_secs(0)).is_enabled());
+    }
+
+    #[tokio::test]
+    async fn test_read_cache() {
+        let gets = Arc::new(AtomicUsize::new(0));
+        let gets_clone = gets.clone();
+        let backend: FakeBackendFunc = Arc::new(move |_, args| match args[0].as_str() {
+            "GET" => {
+                gets_clone.fetch_add(1, Ordering::SeqCst);
+                Resp::Bulk(BulkStr::Str(b"v".to_vec()))
+            }
+            _ => Resp::Simple(b"OK".to_vec()),
+        });
+        let mut config = gen_test_config();
+        config.read_cache_prefixes = vec!["static:".to_string()];
+        config.read_cache_ttl = 60_000;
+        let (_proxy, mut client) = setup_proxy_with_config(config, backend).await;
+        let reply = client.send_when_ready(&["SET", "static:a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        for _ in 0..3 {
+            let reply = client.send(&["GET", "static:a"]).await;
+            assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"v".to_vec())));
+        }
+        assert_eq!(gets.load(Ordering::SeqCst), 1);
+
+        // Not cached without the prefixes.
+        for _ in 0..2 {
+            client.send(&["GET", "user:a"]).await;
+        }
+        assert_eq!(gets.load(Ordering::SeqCst), 3);
+
+        let reply = client.send(&["SET", "static:a", "v2"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        client.send(&["GET", "static:a"]).await;
+        assert_eq!(gets.load(Ordering::SeqCst), 4);
+
+        let reply = client.send(&["INFO", "stats"]).await;
+        let info = match reply {
+            Resp::Bulk(BulkStr::Str(info)) => String::from_utf8(info).unwrap(),
+            other => panic!("unexpected reply {:?}", other),
+        };
+        assert!(info.contains("read_cache_hits:2\r\n"));
+        assert!(info.contains("read_cache_misses:2\r\n"));
+    }
+}
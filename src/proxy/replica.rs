@@ -0,0 +1,148 @@
+use std::sync::Mutex;
+
+struct ReplicaEntry {
+    address: String,
+    weight: i64,
+    current_weight: i64,
+    lag: u64,
+    read_count: u64,
+}
+
+// Smooth weighted round-robin over the replicas of a master, the same as nginx.
+// The replicas with zero weight or lag larger than `max_lag` are skipped.
+// It's used to pick the replica for the hedged reads.
+pub struct ReplicaSelector {
+    replicas: Mutex<Vec<ReplicaEntry>>,
+    max_lag: u64,
+}
+
+impl ReplicaSelector {
+    // `replicas` is a list of (address, weight).
+    pub fn new(replicas: Vec<(String, u64)>, max_lag: u64) -> Self {
+        let replicas = replicas
+            .into_iter()
+            .map(|(address, weight)| ReplicaEntry {
+                address,
+                weight: weight as i64,
+                current_weight: 0,
+                lag: 0,
+                read_count: 0,
+            })
+            .collect();
+        Self {
+            replicas: Mutex::new(replicas),
+            max_lag,
+        }
+    }
+
+    pub fn set_lag(&self, address: &str, lag: u64) {
+        let mut replicas = self.replicas.lock().expect("ReplicaSelector::set_lag");
+        if let Some(replica) = replicas.iter_mut().find(|r| r.address == address) {
+            replica.lag = lag;
+        }
+    }
+
+    pub fn update_lags<F: Fn(&str) -> u64>(&self, get_lag: F) {
+        let mut replicas = self.replicas.lock().expect("ReplicaSelector::update_lags");
+        for replica in replicas.iter_mut() {
+            replica.lag = get_lag(&replica.address);
+        }
+    }
+
+    pub fn select(&self) -> Option<String> {
+        let mut replicas = self.replicas.lock().expect("ReplicaSelector::select");
+        let max_lag = self.max_lag;
+        let is_healthy = |replica: &ReplicaEntry| replica.weight > 0 && replica.lag <= max_lag;
+
+        let mut total_weight = 0;
+        for replica in replicas.iter_mut().filter(|r| is_healthy(r)) {
+            replica.current_weight += replica.weight;
+            total_weight += replica.weight;
+        }
+
+        let selected = replicas
+            .iter_mut()
+            .filter(|r| is_healthy(r))
+            .max_by_key(|r| r.current_weight)?;
+        selected.current_weight -= total_weight;
+        selected.read_count += 1;
+        Some(selected.address.clone())
+    }
+
+    // Returns (address, read count) of all the replicas.
+    pub fn get_read_counts(&self) -> Vec<(String, u64)> {
+        self.replicas
+            .lock()
+            .expect("ReplicaSelector::get_read_counts")
+            .iter()
+            .map(|r| (r.address.clone(), r.read_count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_selector(weights: &[u64], max_lag: u64) -> ReplicaSelector {
+        let replicas = weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| (format!("replica{}", i), *weight))
+            .collect();
+        ReplicaSelector::new(replicas, max_lag)
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let selector = gen_selector(&[2, 1], 100);
+        let selected: Vec<String> = (0..3).filter_map(|_| selector.select()).collect();
+        // Should be interleaved instead of selecting replica0 twice in a row.
+        assert_eq!(selected, vec!["replica0", "replica1", "replica0"]);
+
+        for _ in 0..297 {
+            selector.select();
+        }
+        assert_eq!(
+            selector.get_read_counts(),
+            vec![("replica0".to_string(), 200), ("replica1".to_string(), 100)]
+        );
+    }
+
+    #[test]
+    fn test_skip_lagging_replica() {
+        let selector = gen_selector(&[1, 1], 100);
+        selector.set_lag("replica0", 101);
+        for _ in 0..10 {
+            assert_eq!(selector.select(), Some("replica1".to_string()));
+        }
+        selector.set_lag("replica0", 100);
+        let selected: Vec<String> = (0..2).filter_map(|_| selector.select()).collect();
+        assert!(selected.contains(&"replica0".to_string()));
+    }
+
+    #[test]
+    fn test_update_lags() {
+        let selector = gen_selector(&[1, 1], 100);
+        selector.update_lags(|address| match address {
+            "replica0" => u64::MAX,
+            _ => 100,
+        });
+        for _ in 0..10 {
+            assert_eq!(selector.select(), Some("replica1".to_string()));
+        }
+        selector.update_lags(|_| 0);
+        let selected: Vec<String> = (0..2).filter_map(|_| selector.select()).collect();
+        assert!(selected.contains(&"replica0".to_string()));
+    }
+
+    #[test]
+    fn test_no_healthy_replica() {
+        let selector = gen_selector(&[0, 1], 100);
+        selector.set_lag("replica1", 1000);
+        assert_eq!(selector.select(), None);
+
+        let selector = gen_selector(&[], 100);
+        assert_eq!(selector.select(), None);
+    }
+}
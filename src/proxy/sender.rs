@@ -2,6 +2,8 @@ use super::backend::{
     BackendError, BackendNode, CmdTask, CmdTaskResultHandler, CmdTaskResultHandlerFactory,
     ConnFactory, ReqTask,
 };
+use super::flapping::BackendFlapMap;
+use super::inflight::BackendInFlightMap;
 use super::service::ServerProxyConfig;
 use crate::common::response::ERR_BACKEND_CONNECTION;
 use crate::common::track::TrackedFutureRegistry;
@@ -52,6 +54,8 @@ where
     handler_factory: Arc<F>,
     conn_factory: Arc<CF>,
     future_registry: Arc<TrackedFutureRegistry>,
+    in_flight_map: Arc<BackendInFlightMap>,
+    flap_map: Arc<BackendFlapMap>,
 }
 
 impl<F: CmdTaskResultHandlerFactory, CF: ConnFactory> RecoverableBackendNodeFactory<F, CF>
@@ -70,8 +74,22 @@ where
             handler_factory,
             conn_factory,
             future_registry,
+            in_flight_map: Arc::new(BackendInFlightMap::default()),
+            flap_map: Arc::new(BackendFlapMap::default()),
         }
     }
+
+    // The in-flight commands are not limited by default.
+    pub fn with_in_flight_map(mut self, in_flight_map: Arc<BackendInFlightMap>) -> Self {
+        self.in_flight_map = in_flight_map;
+        self
+    }
+
+    // The flapping backends are not quarantined by default.
+    pub fn with_flap_map(mut self, flap_map: Arc<BackendFlapMap>) -> Self {
+        self.flap_map = flap_map;
+        self
+    }
 }
 
 impl<F: CmdTaskResultHandlerFactory, CF: ConnFactory> CmdTaskSenderFactory
@@ -88,6 +106,8 @@ where
             Arc::new(self.handler_factory.create()),
             self.config.clone(),
             self.conn_factory.clone(),
+            self.in_flight_map.get_or_create(&address),
+            self.flap_map.get_or_create(&address),
         );
         let desc = format!("backend::RecoverableBackendNode: address={}", address);
         let fut = TrackedFutureRegistry::wrap(self.future_registry.clone(), fut, desc);
@@ -155,7 +175,10 @@ impl<S: CmdTaskSender> CmdTaskSender for RRSenderGroup<S> {
     type Task = S::Task;
 
     fn send(&self, cmd_task: Self::Task) -> Result<(), BackendError> {
-        let index = self.cursor.fetch_add(1, Ordering::SeqCst);
+        let index = match cmd_task.get_conn_hint() {
+            Some(hint) => hint,
+            None => self.cursor.fetch_add(1, Ordering::SeqCst),
+        };
         let sender = match self.senders.get(index % self.senders.len()) {
             Some(s) => s,
             None => return Err(BackendError::NodeNotFound),
@@ -218,6 +241,15 @@ impl<F: CmdTaskSenderFactory> CachedSenderFactory<F> {
             cached_senders: Arc::new(RwLock::new(HashMap::new())),
         }
     }
+
+    // The senders already in use are kept until they are dropped,
+    // so the commands queued in them are still sent.
+    pub fn clear(&self) {
+        self.cached_senders
+            .write()
+            .expect("CachedSenderFactory::clear")
+            .clear();
+    }
 }
 
 impl<F: CmdTaskSenderFactory> CmdTaskSenderFactory for CachedSenderFactory<F> {
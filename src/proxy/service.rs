@@ -1,25 +1,60 @@
+use super::command::{CmdType, Command, DataCmdType};
+use super::keyspec::{key_specs_to_string, KeySpec};
+use super::memory::MemoryBudget;
 use super::session::CmdCtxHandler;
-use super::session::{handle_session, Session};
+use super::session::{handle_session, ClientSource, Session, SessionError};
 use super::slowlog::SlowRequestLogger;
+use super::traffic::TrafficStats;
+use crate::common::cluster::ClusterName;
 use crate::common::config::ConfigError;
 use crate::common::track::TrackedFutureRegistry;
 use crate::common::utils::{resolve_first_address, ThreadSafe};
-use futures::{FutureExt, StreamExt};
+use arc_swap::ArcSwap;
+use futures::{future, Future, FutureExt, StreamExt};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::convert::TryFrom;
 use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
 use std::num::NonZeroUsize;
-use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use string_error::into_err;
 use tokio::net::TcpListener;
 
+pub const DEFAULT_MAX_COMMAND_ARGS: u64 = 1024 * 1024;
+pub const DEFAULT_DURABLE_WRITE_TIMEOUT: u64 = 100;
+pub const DEFAULT_HEDGED_READ_MAX_LAG: u64 = 1024 * 1024;
+pub const DEFAULT_SHUTDOWN_DRAIN_TIMEOUT: u64 = 30_000;
+pub const DEFAULT_ACL_USER_RULES: &str = "on nopass ~* &* +@all";
+pub const DEFAULT_MAINTENANCE_MESSAGE: &str = "ERR cluster under maintenance, expect brief blips";
+pub const DEFAULT_READ_CACHE_MAX_VALUE_SIZE: usize = 1024;
+pub const DEFAULT_READ_CACHE_MAX_ENTRIES: usize = 100_000;
+// The same as the default of tokio.
+pub const DEFAULT_LISTEN_BACKLOG: i32 = 1024;
+const DRAIN_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 pub struct ServerProxyConfig {
     pub address: String,
     pub announce_address: String,
+    pub admin_address: Option<String>,
+    // The options of the listening sockets of `address` and `admin_address`.
+    pub listen_backlog: i32,
+    pub listen_reuse_address: bool,
+    pub listen_reuse_port: bool,
     pub auto_select_cluster: bool,
     pub slowlog_len: NonZeroUsize,
     pub slowlog_log_slower_than: AtomicI64,
     pub slowlog_sample_rate: AtomicU64,
+    pub slowlog_flush_file: Option<String>,
+    pub slowlog_flush_interval: u64,
+    pub slowlog_flush_file_max_size: u64,
+    pub latency_monitor_threshold: AtomicU64,
+    // In seconds. The slowlogs and the latency samples older than this are removed.
+    // Zero means keeping them until they are overwritten or reset.
+    pub slowlog_latency_window: AtomicU64,
     pub thread_number: NonZeroUsize,
     pub session_channel_size: usize,
     pub backend_channel_size: usize,
@@ -32,8 +67,102 @@ pub struct ServerProxyConfig {
     pub session_batch_buf: NonZeroUsize,
     pub active_redirection: bool,
     pub max_redirections: Option<NonZeroUsize>,
+    pub shadow_address: Option<String>,
+    pub shadow_percentage: AtomicU64,
+    pub shadow_write_enabled: bool,
+    pub memory_soft_limit: usize,
+    pub memory_hard_limit: usize,
+    pub migration_read_your_writes_window: u64,
+    // In milliseconds. Zero means never reporting the migrations as stuck.
+    pub migration_stuck_timeout: u64,
+    // PING the new backends on UMCTL SETCLUSTER.
+    pub warm_backend_connections: bool,
+    // In milliseconds. Zero means no timeout.
+    pub client_command_timeout: u64,
+    pub backend_response_timeout: u64,
+    // Upper case DEBUG subcommands forwarded to the backends.
+    pub debug_allowlist: Vec<String>,
+    // (source ip, cluster name) used instead of the default cluster when connected.
+    pub ip_default_clusters: Vec<(String, ClusterName)>,
+    // (client name, cluster name) used instead of the default cluster on CLIENT SETNAME.
+    pub client_name_default_clusters: Vec<(String, ClusterName)>,
+    // (upper case original command, alias) like `rename-command` of redis.
+    // Empty alias disables the command.
+    pub rename_commands: Vec<(String, String)>,
+    // Including the command name. Zero means no limit.
+    pub max_command_args: AtomicU64,
+    // In milliseconds. Zero disables the hedged reads.
+    pub hedged_read_delay: AtomicU64,
+    // In bytes of the replication offset.
+    // The replicas lagging behind their masters more than this are not used for the hedged reads.
+    pub hedged_read_max_lag: u64,
+    // In milliseconds. How long a read failed with a backend connection error
+    // waits for a new master of its slot to be retried. Zero disables it.
+    pub failover_retry_timeout: AtomicU64,
+    // Forward SHUTDOWN to the backend instead of rejecting it.
+    pub shutdown_passthrough: bool,
+    // In milliseconds. On SIGTERM or SIGINT, the proxy stops accepting connections
+    // and waits for the connected sessions to be closed within this time.
+    pub shutdown_drain_timeout: u64,
+    // The multi-key commands split by slots when the keys are in different slots.
+    // The others get a cross slot error.
+    pub cross_slot_split_commands: Vec<String>,
+    // In milliseconds. The timeout of the implicit WAIT after each write
+    // of the sessions with CLIENT DURABLE ON.
+    pub durable_write_timeout: AtomicU64,
+    // In seconds. The topology is stale when no UMCTL SETCLUSTER is received
+    // from the broker within this time. Zero disables the check.
+    pub stale_topology_timeout: u64,
+    // Reject the writes while the topology is stale.
+    pub readonly_on_stale_topology: bool,
+    // (upper case command name, key spec) of the commands unknown to the proxy.
+    pub key_specs: Vec<(String, KeySpec)>,
+    // The rules of the only user "default" in the replies of ACL LIST and ACL GETUSER.
+    pub acl_user_rules: String,
+    // Where the commands dropped without being delivered to any backend go.
+    // Empty string disables it.
+    pub deadletter_sink: String,
+    // (key prefix, channel) The keys with the prefix are published to the channel
+    // by SPUBLISH after they are deleted by GETDEL or DEL.
+    pub invalidation_channels: Vec<(String, String)>,
+    // The max number of the commands of the clients sent to a backend
+    // with their replies not received yet. Zero means no limit.
+    pub backend_max_in_flight: usize,
+    // (backend address, max in-flight commands) overriding `backend_max_in_flight`.
+    pub backend_max_in_flight_overrides: Vec<(String, usize)>,
+    // A backend is quarantined for `backend_quarantine_duration` milliseconds
+    // when its connections get closed `backend_flap_threshold` times
+    // within `backend_flap_window` milliseconds. Zero threshold disables it.
+    pub backend_flap_threshold: usize,
+    pub backend_flap_window: u64,
+    pub backend_quarantine_duration: u64,
+    // When it's on, the first command of each client connection
+    // gets `maintenance_message` as the error reply.
+    pub maintenance_mode: AtomicBool,
+    pub maintenance_message: ArcSwap<String>,
+    // Only the GET replies of the keys with these prefixes are cached.
+    pub read_cache_prefixes: Vec<String>,
+    // In milliseconds. Zero disables the read cache.
+    pub read_cache_ttl: u64,
+    // In bytes.
+    pub read_cache_max_value_size: usize,
+    pub read_cache_max_entries: usize,
+    // (database name, max request and reply bytes per second).
+    // The databases not listed are not limited.
+    pub db_byte_rate_quotas: ArcSwap<Vec<(ClusterName, u64)>>,
+    // Log the name and the key of every command at debug level.
+    // The other arguments are always redacted.
+    pub command_log: AtomicBool,
+    // MONITOR formats every command of all the sessions so it's off by default.
+    pub monitor_enabled: AtomicBool,
+    // (database name, key prefix) The keys of the database are stored with the prefix
+    // in the backends and the prefix is removed from the keys in the replies.
+    pub db_key_prefixes: Vec<(ClusterName, String)>,
 }
 
+// The multi-key commands which could be split into the commands of each slot.
+pub const CROSS_SLOT_SPLIT_COMMANDS: [&str; 4] = ["MGET", "MSET", "DEL", "EXISTS"];
+
 impl ServerProxyConfig {
     pub fn get_slowlog_log_slower_than(&self) -> i64 {
         self.slowlog_log_slower_than.load(Ordering::Relaxed)
@@ -51,6 +180,389 @@ impl ServerProxyConfig {
         self.slowlog_sample_rate
             .store(slowlog_sample_rate, Ordering::Relaxed)
     }
+
+    pub fn get_latency_monitor_threshold(&self) -> u64 {
+        self.latency_monitor_threshold.load(Ordering::Relaxed)
+    }
+
+    pub fn set_latency_monitor_threshold(&self, threshold: u64) {
+        self.latency_monitor_threshold
+            .store(threshold, Ordering::Relaxed)
+    }
+
+    pub fn get_slowlog_latency_window(&self) -> u64 {
+        self.slowlog_latency_window.load(Ordering::Relaxed)
+    }
+
+    pub fn set_slowlog_latency_window(&self, window: u64) {
+        self.slowlog_latency_window.store(window, Ordering::Relaxed)
+    }
+
+    pub fn get_shadow_percentage(&self) -> u64 {
+        self.shadow_percentage.load(Ordering::Relaxed)
+    }
+
+    pub fn set_shadow_percentage(&self, shadow_percentage: u64) {
+        self.shadow_percentage
+            .store(shadow_percentage, Ordering::Relaxed)
+    }
+
+    pub fn get_max_command_args(&self) -> u64 {
+        self.max_command_args.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_command_args(&self, max_command_args: u64) {
+        self.max_command_args
+            .store(max_command_args, Ordering::Relaxed)
+    }
+
+    pub fn get_hedged_read_delay(&self) -> Option<Duration> {
+        to_timeout(self.hedged_read_delay.load(Ordering::Relaxed))
+    }
+
+    pub fn set_hedged_read_delay(&self, hedged_read_delay: u64) {
+        self.hedged_read_delay
+            .store(hedged_read_delay, Ordering::Relaxed)
+    }
+
+    pub fn get_failover_retry_timeout(&self) -> Option<Duration> {
+        to_timeout(self.failover_retry_timeout.load(Ordering::Relaxed))
+    }
+
+    pub fn set_failover_retry_timeout(&self, failover_retry_timeout: u64) {
+        self.failover_retry_timeout
+            .store(failover_retry_timeout, Ordering::Relaxed)
+    }
+
+    pub fn is_maintenance_mode(&self) -> bool {
+        self.maintenance_mode.load(Ordering::Relaxed)
+    }
+
+    pub fn set_maintenance_mode(&self, maintenance_mode: bool) {
+        self.maintenance_mode
+            .store(maintenance_mode, Ordering::Relaxed)
+    }
+
+    pub fn is_command_log_enabled(&self) -> bool {
+        self.command_log.load(Ordering::Relaxed)
+    }
+
+    pub fn set_command_log(&self, command_log: bool) {
+        self.command_log.store(command_log, Ordering::Relaxed)
+    }
+
+    // Zero means no limit.
+    pub fn get_db_byte_rate_quota(&self, cluster_name: &ClusterName) -> u64 {
+        self.db_byte_rate_quotas
+            .load()
+            .iter()
+            .find(|(name, _)| name == cluster_name)
+            .map(|(_, quota)| *quota)
+            .unwrap_or(0)
+    }
+
+    pub fn set_db_byte_rate_quotas(&self, quotas: Vec<(ClusterName, u64)>) {
+        self.db_byte_rate_quotas.store(Arc::new(quotas))
+    }
+
+    pub fn get_db_key_prefix(&self, cluster_name: &ClusterName) -> Option<&str> {
+        self.db_key_prefixes
+            .iter()
+            .find(|(name, _)| name == cluster_name)
+            .map(|(_, prefix)| prefix.as_str())
+    }
+
+    pub fn get_maintenance_message(&self) -> Arc<String> {
+        self.maintenance_message.load()
+    }
+
+    pub fn set_maintenance_message(&self, maintenance_message: String) {
+        self.maintenance_message
+            .store(Arc::new(maintenance_message))
+    }
+
+    pub fn get_durable_write_timeout(&self) -> u64 {
+        self.durable_write_timeout.load(Ordering::Relaxed)
+    }
+
+    pub fn set_durable_write_timeout(&self, durable_write_timeout: u64) {
+        self.durable_write_timeout
+            .store(durable_write_timeout, Ordering::Relaxed)
+    }
+
+    pub fn get_ip_default_cluster(&self, ip: &str) -> Option<ClusterName> {
+        find_default_cluster(&self.ip_default_clusters, ip)
+    }
+
+    pub fn get_client_name_default_cluster(&self, client_name: &str) -> Option<ClusterName> {
+        find_default_cluster(&self.client_name_default_clusters, client_name)
+    }
+
+    pub fn get_client_command_timeout(&self) -> Option<Duration> {
+        to_timeout(self.client_command_timeout)
+    }
+
+    pub fn get_backend_response_timeout(&self) -> Option<Duration> {
+        to_timeout(self.backend_response_timeout)
+    }
+
+    pub fn allows_cross_slot_split(&self, cmd_name: &str) -> bool {
+        self.cross_slot_split_commands.iter().any(|c| c == cmd_name)
+    }
+
+    pub fn is_monitor_enabled(&self) -> bool {
+        self.monitor_enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_monitor_enabled(&self, enabled: bool) {
+        self.monitor_enabled.store(enabled, Ordering::Relaxed)
+    }
+
+    pub fn resolve_command_name(&self, cmd_name: &str) -> CommandRename {
+        resolve_command_name(&self.rename_commands, cmd_name)
+    }
+
+    pub fn has_invalidation_channels(&self) -> bool {
+        !self.invalidation_channels.is_empty()
+    }
+
+    // The first configured prefix matching the key wins.
+    pub fn get_invalidation_channel(&self, key: &[u8]) -> Option<&str> {
+        self.invalidation_channels
+            .iter()
+            .find(|(prefix, _)| key.starts_with(prefix.as_bytes()))
+            .map(|(_, channel)| channel.as_str())
+    }
+
+    pub fn get_key_spec(&self, cmd_name: &str) -> Option<KeySpec> {
+        self.key_specs
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(cmd_name))
+            .map(|(_, key_spec)| *key_spec)
+    }
+
+    // Only the commands unknown to the proxy use the key specs of the config.
+    pub fn apply_key_spec(&self, cmd: &mut Command) {
+        if self.key_specs.is_empty()
+            || cmd.get_type() != CmdType::Others
+            || cmd.get_data_cmd_type() != DataCmdType::Others
+        {
+            return;
+        }
+        let key_spec = cmd
+            .get_command_name()
+            .and_then(|cmd_name| self.get_key_spec(cmd_name));
+        if let Some(key_spec) = key_spec {
+            cmd.set_key_spec(key_spec);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CommandRename {
+    Unchanged,
+    // The alias is used. Contains the original command name.
+    Renamed(String),
+    // The original name of a renamed or disabled command is used.
+    Disabled,
+}
+
+fn resolve_command_name(rename_commands: &[(String, String)], cmd_name: &str) -> CommandRename {
+    if let Some((original, _)) = rename_commands
+        .iter()
+        .find(|(_, alias)| !alias.is_empty() && alias.eq_ignore_ascii_case(cmd_name))
+    {
+        return CommandRename::Renamed(original.clone());
+    }
+    if rename_commands
+        .iter()
+        .any(|(original, _)| original.eq_ignore_ascii_case(cmd_name))
+    {
+        return CommandRename::Disabled;
+    }
+    CommandRename::Unchanged
+}
+
+// Parses "FLUSHALL=,KEYS=secret_keys".
+pub fn parse_rename_commands(s: &str) -> Result<Vec<(String, String)>, String> {
+    let mut rename_commands = vec![];
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let mut it = item.splitn(2, '=');
+        let (original, alias) = match (it.next(), it.next()) {
+            (Some(original), Some(alias)) => (original.trim(), alias.trim()),
+            _ => return Err(format!("invalid rename command: {}", item)),
+        };
+        if original.is_empty() {
+            return Err(format!("invalid rename command: {}", item));
+        }
+        let conflicted = rename_commands.iter().any(|(o, a): &(String, String)| {
+            o.eq_ignore_ascii_case(original) || (!alias.is_empty() && a.eq_ignore_ascii_case(alias))
+        });
+        if conflicted {
+            return Err(format!("duplicated rename command: {}", item));
+        }
+        rename_commands.push((original.to_uppercase(), alias.to_string()));
+    }
+    Ok(rename_commands)
+}
+
+fn rename_commands_to_string(rename_commands: &[(String, String)]) -> String {
+    rename_commands
+        .iter()
+        .map(|(original, alias)| format!("{}={}", original, alias))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Parses "user:=user_invalidation,session:=session_invalidation".
+pub fn parse_invalidation_channels(s: &str) -> Result<Vec<(String, String)>, String> {
+    let mut invalidation_channels = vec![];
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let mut it = item.splitn(2, '=');
+        let (prefix, channel) = match (it.next(), it.next()) {
+            (Some(prefix), Some(channel)) => (prefix.trim(), channel.trim()),
+            _ => return Err(format!("invalid invalidation channel: {}", item)),
+        };
+        if prefix.is_empty() || channel.is_empty() {
+            return Err(format!("invalid invalidation channel: {}", item));
+        }
+        invalidation_channels.push((prefix.to_string(), channel.to_string()));
+    }
+    Ok(invalidation_channels)
+}
+
+fn invalidation_channels_to_string(invalidation_channels: &[(String, String)]) -> String {
+    invalidation_channels
+        .iter()
+        .map(|(prefix, channel)| format!("{}={}", prefix, channel))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Parses "address1=max1,address2=max2".
+pub fn parse_backend_max_in_flight_overrides(s: &str) -> Result<Vec<(String, usize)>, String> {
+    let mut overrides = vec![];
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let mut it = item.splitn(2, '=');
+        let (address, max_in_flight) = match (it.next(), it.next()) {
+            (Some(address), Some(max_in_flight)) => (address.trim(), max_in_flight.trim()),
+            _ => return Err(format!("invalid backend max in-flight: {}", item)),
+        };
+        let max_in_flight = max_in_flight
+            .parse::<usize>()
+            .map_err(|_| format!("invalid backend max in-flight: {}", item))?;
+        if address.is_empty() {
+            return Err(format!("invalid backend max in-flight: {}", item));
+        }
+        overrides.push((address.to_string(), max_in_flight));
+    }
+    Ok(overrides)
+}
+
+fn backend_max_in_flight_overrides_to_string(overrides: &[(String, usize)]) -> String {
+    overrides
+        .iter()
+        .map(|(address, max_in_flight)| format!("{}={}", address, max_in_flight))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Parses "dbname1=bytes1,dbname2=bytes2".
+pub fn parse_db_byte_rate_quotas(s: &str) -> Result<Vec<(ClusterName, u64)>, String> {
+    let mut quotas = vec![];
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let mut it = item.splitn(2, '=');
+        let (cluster_name, quota) = match (it.next(), it.next()) {
+            (Some(cluster_name), Some(quota)) => (cluster_name.trim(), quota.trim()),
+            _ => return Err(format!("invalid byte rate quota: {}", item)),
+        };
+        if cluster_name.is_empty() {
+            return Err(format!("invalid byte rate quota: {}", item));
+        }
+        let cluster_name = ClusterName::try_from(cluster_name)
+            .map_err(|_| format!("invalid cluster name: {}", cluster_name))?;
+        let quota = quota
+            .parse::<u64>()
+            .map_err(|_| format!("invalid byte rate quota: {}", item))?;
+        quotas.push((cluster_name, quota));
+    }
+    Ok(quotas)
+}
+
+fn db_byte_rate_quotas_to_string(quotas: &[(ClusterName, u64)]) -> String {
+    quotas
+        .iter()
+        .map(|(cluster_name, quota)| format!("{}={}", cluster_name, quota))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Parses "dbname1=prefix1,dbname2=prefix2".
+pub fn parse_db_key_prefixes(s: &str) -> Result<Vec<(ClusterName, String)>, String> {
+    let mut prefixes = vec![];
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let mut it = item.splitn(2, '=');
+        let (cluster_name, prefix) = match (it.next(), it.next()) {
+            (Some(cluster_name), Some(prefix)) => (cluster_name.trim(), prefix.trim()),
+            _ => return Err(format!("invalid key prefix: {}", item)),
+        };
+        if cluster_name.is_empty() || prefix.is_empty() {
+            return Err(format!("invalid key prefix: {}", item));
+        }
+        let cluster_name = ClusterName::try_from(cluster_name)
+            .map_err(|_| format!("invalid cluster name: {}", cluster_name))?;
+        prefixes.push((cluster_name, prefix.to_string()));
+    }
+    Ok(prefixes)
+}
+
+fn db_key_prefixes_to_string(prefixes: &[(ClusterName, String)]) -> String {
+    prefixes
+        .iter()
+        .map(|(cluster_name, prefix)| format!("{}={}", cluster_name, prefix))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn to_timeout(millis: u64) -> Option<Duration> {
+    match millis {
+        0 => None,
+        millis => Some(Duration::from_millis(millis)),
+    }
+}
+
+fn find_default_cluster(
+    default_clusters: &[(String, ClusterName)],
+    source: &str,
+) -> Option<ClusterName> {
+    default_clusters
+        .iter()
+        .find(|(s, _)| s == source)
+        .map(|(_, cluster_name)| cluster_name.clone())
+}
+
+// Parses "source1=cluster1,source2=cluster2".
+pub fn parse_default_clusters(s: &str) -> Result<Vec<(String, ClusterName)>, String> {
+    let mut default_clusters = vec![];
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let mut it = item.splitn(2, '=');
+        let (source, cluster_name) = match (it.next(), it.next()) {
+            (Some(source), Some(cluster_name)) => (source.trim(), cluster_name.trim()),
+            _ => return Err(format!("invalid default cluster: {}", item)),
+        };
+        let cluster_name = ClusterName::try_from(cluster_name)
+            .map_err(|_| format!("invalid cluster name: {}", cluster_name))?;
+        default_clusters.push((source.to_string(), cluster_name));
+    }
+    Ok(default_clusters)
+}
+
+fn default_clusters_to_string(default_clusters: &[(String, ClusterName)]) -> String {
+    default_clusters
+        .iter()
+        .map(|(source, cluster_name)| format!("{}={}", source, cluster_name))
+        .collect::<Vec<String>>()
+        .join(",")
 }
 
 impl ServerProxyConfig {
@@ -58,6 +570,13 @@ impl ServerProxyConfig {
         match field.to_lowercase().as_ref() {
             "address" => Ok(self.address.clone()),
             "announce_address" => Ok(self.announce_address.clone()),
+            "admin_address" => Ok(self
+                .admin_address
+                .clone()
+                .unwrap_or_else(|| "none".to_string())),
+            "listen_backlog" => Ok(self.listen_backlog.to_string()),
+            "listen_reuse_address" => Ok(self.listen_reuse_address.to_string()),
+            "listen_reuse_port" => Ok(self.listen_reuse_port.to_string()),
             "auto_select_cluster" => Ok(self.auto_select_cluster.to_string()),
             "slowlog_len" => Ok(self.slowlog_len.to_string()),
             "thread_number" => Ok(self.thread_number.to_string()),
@@ -66,6 +585,14 @@ impl ServerProxyConfig {
             "backend_conn_num" => Ok(self.backend_conn_num.to_string()),
             "slowlog_log_slower_than" => Ok(self.get_slowlog_log_slower_than().to_string()),
             "slowlog_sample_rate" => Ok(self.get_slowlog_sample_rate().to_string()),
+            "slowlog_flush_file" => Ok(self
+                .slowlog_flush_file
+                .clone()
+                .unwrap_or_else(|| "none".to_string())),
+            "slowlog_flush_interval" => Ok(self.slowlog_flush_interval.to_string()),
+            "slowlog_flush_file_max_size" => Ok(self.slowlog_flush_file_max_size.to_string()),
+            "latency_monitor_threshold" => Ok(self.get_latency_monitor_threshold().to_string()),
+            "slowlog_latency_window" => Ok(self.get_slowlog_latency_window().to_string()),
             "backend_batch_min_time" => Ok(self.backend_batch_min_time.to_string()),
             "backend_batch_max_time" => Ok(self.backend_batch_max_time.to_string()),
             "backend_batch_buf" => Ok(self.backend_batch_buf.to_string()),
@@ -77,6 +604,75 @@ impl ServerProxyConfig {
                 .max_redirections
                 .map(|n| n.get().to_string())
                 .unwrap_or_else(|| "none".to_string())),
+            "shadow_address" => Ok(self
+                .shadow_address
+                .clone()
+                .unwrap_or_else(|| "none".to_string())),
+            "shadow_percentage" => Ok(self.get_shadow_percentage().to_string()),
+            "shadow_write_enabled" => Ok(self.shadow_write_enabled.to_string()),
+            "memory_soft_limit" => Ok(self.memory_soft_limit.to_string()),
+            "memory_hard_limit" => Ok(self.memory_hard_limit.to_string()),
+            "migration_read_your_writes_window" => {
+                Ok(self.migration_read_your_writes_window.to_string())
+            }
+            "migration_stuck_timeout" => Ok(self.migration_stuck_timeout.to_string()),
+            "warm_backend_connections" => Ok(self.warm_backend_connections.to_string()),
+            "client_command_timeout" => Ok(self.client_command_timeout.to_string()),
+            "backend_response_timeout" => Ok(self.backend_response_timeout.to_string()),
+            "debug_allowlist" => Ok(self.debug_allowlist.join(",")),
+            "ip_default_clusters" => Ok(default_clusters_to_string(&self.ip_default_clusters)),
+            "client_name_default_clusters" => Ok(default_clusters_to_string(
+                &self.client_name_default_clusters,
+            )),
+            // The aliases are supposed to be secret.
+            "rename_commands" => Ok(rename_commands_to_string(
+                &self
+                    .rename_commands
+                    .iter()
+                    .map(|(original, alias)| {
+                        let alias = if alias.is_empty() { "" } else { "***" };
+                        (original.clone(), alias.to_string())
+                    })
+                    .collect::<Vec<_>>(),
+            )),
+            "max_command_args" => Ok(self.get_max_command_args().to_string()),
+            "hedged_read_delay" => Ok(self.hedged_read_delay.load(Ordering::Relaxed).to_string()),
+            "hedged_read_max_lag" => Ok(self.hedged_read_max_lag.to_string()),
+            "failover_retry_timeout" => Ok(self
+                .failover_retry_timeout
+                .load(Ordering::Relaxed)
+                .to_string()),
+            "shutdown_passthrough" => Ok(self.shutdown_passthrough.to_string()),
+            "shutdown_drain_timeout" => Ok(self.shutdown_drain_timeout.to_string()),
+            "cross_slot_split_commands" => Ok(self.cross_slot_split_commands.join(",")),
+            "durable_write_timeout" => Ok(self.get_durable_write_timeout().to_string()),
+            "stale_topology_timeout" => Ok(self.stale_topology_timeout.to_string()),
+            "readonly_on_stale_topology" => Ok(self.readonly_on_stale_topology.to_string()),
+            "key_specs" => Ok(key_specs_to_string(&self.key_specs)),
+            "acl_user_rules" => Ok(self.acl_user_rules.clone()),
+            "deadletter_sink" => Ok(self.deadletter_sink.clone()),
+            "invalidation_channels" => {
+                Ok(invalidation_channels_to_string(&self.invalidation_channels))
+            }
+            "backend_max_in_flight" => Ok(self.backend_max_in_flight.to_string()),
+            "backend_max_in_flight_overrides" => Ok(backend_max_in_flight_overrides_to_string(
+                &self.backend_max_in_flight_overrides,
+            )),
+            "backend_flap_threshold" => Ok(self.backend_flap_threshold.to_string()),
+            "backend_flap_window" => Ok(self.backend_flap_window.to_string()),
+            "backend_quarantine_duration" => Ok(self.backend_quarantine_duration.to_string()),
+            "maintenance_mode" => Ok(self.is_maintenance_mode().to_string()),
+            "maintenance_message" => Ok(self.get_maintenance_message().to_string()),
+            "read_cache_prefixes" => Ok(self.read_cache_prefixes.join(",")),
+            "read_cache_ttl" => Ok(self.read_cache_ttl.to_string()),
+            "read_cache_max_value_size" => Ok(self.read_cache_max_value_size.to_string()),
+            "read_cache_max_entries" => Ok(self.read_cache_max_entries.to_string()),
+            "command_log" => Ok(self.is_command_log_enabled().to_string()),
+            "monitor_enabled" => Ok(self.is_monitor_enabled().to_string()),
+            "db_byte_rate_quotas" => Ok(db_byte_rate_quotas_to_string(
+                &self.db_byte_rate_quotas.load(),
+            )),
+            "db_key_prefixes" => Ok(db_key_prefixes_to_string(&self.db_key_prefixes)),
             _ => Err(ConfigError::FieldNotFound),
         }
     }
@@ -85,6 +681,10 @@ impl ServerProxyConfig {
         match field.to_lowercase().as_ref() {
             "address" => Err(ConfigError::ReadonlyField),
             "announce_address" => Err(ConfigError::ReadonlyField),
+            "admin_address" => Err(ConfigError::ReadonlyField),
+            "listen_backlog" => Err(ConfigError::ReadonlyField),
+            "listen_reuse_address" => Err(ConfigError::ReadonlyField),
+            "listen_reuse_port" => Err(ConfigError::ReadonlyField),
             "auto_select_cluster" => Err(ConfigError::ReadonlyField),
             "slowlog_len" => Err(ConfigError::ReadonlyField),
             "thread_number" => Err(ConfigError::ReadonlyField),
@@ -105,6 +705,23 @@ impl ServerProxyConfig {
                 self.set_slowlog_sample_rate(int_value);
                 Ok(())
             }
+            "slowlog_flush_file" => Err(ConfigError::ReadonlyField),
+            "slowlog_flush_interval" => Err(ConfigError::ReadonlyField),
+            "slowlog_flush_file_max_size" => Err(ConfigError::ReadonlyField),
+            "latency_monitor_threshold" => {
+                let int_value = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_latency_monitor_threshold(int_value);
+                Ok(())
+            }
+            "slowlog_latency_window" => {
+                let int_value = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_slowlog_latency_window(int_value);
+                Ok(())
+            }
             "backend_batch_max_time" => Err(ConfigError::ReadonlyField),
             "backend_batch_min_time" => Err(ConfigError::ReadonlyField),
             "backend_batch_buf" => Err(ConfigError::ReadonlyField),
@@ -113,6 +730,112 @@ impl ServerProxyConfig {
             "session_batch_buf" => Err(ConfigError::ReadonlyField),
             "active_redirection" => Err(ConfigError::ReadonlyField),
             "max_redirections" => Err(ConfigError::ReadonlyField),
+            "shadow_address" => Err(ConfigError::ReadonlyField),
+            "shadow_percentage" => {
+                let int_value = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                if int_value > 100 {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.set_shadow_percentage(int_value);
+                Ok(())
+            }
+            "shadow_write_enabled" => Err(ConfigError::ReadonlyField),
+            "memory_soft_limit" => Err(ConfigError::ReadonlyField),
+            "memory_hard_limit" => Err(ConfigError::ReadonlyField),
+            "migration_read_your_writes_window" => Err(ConfigError::ReadonlyField),
+            "migration_stuck_timeout" => Err(ConfigError::ReadonlyField),
+            "warm_backend_connections" => Err(ConfigError::ReadonlyField),
+            "client_command_timeout" => Err(ConfigError::ReadonlyField),
+            "backend_response_timeout" => Err(ConfigError::ReadonlyField),
+            "debug_allowlist" => Err(ConfigError::ReadonlyField),
+            "ip_default_clusters" => Err(ConfigError::ReadonlyField),
+            "client_name_default_clusters" => Err(ConfigError::ReadonlyField),
+            "rename_commands" => Err(ConfigError::ReadonlyField),
+            "shutdown_passthrough" => Err(ConfigError::ReadonlyField),
+            "shutdown_drain_timeout" => Err(ConfigError::ReadonlyField),
+            "hedged_read_max_lag" => Err(ConfigError::ReadonlyField),
+            "cross_slot_split_commands" => Err(ConfigError::ReadonlyField),
+            "stale_topology_timeout" => Err(ConfigError::ReadonlyField),
+            "readonly_on_stale_topology" => Err(ConfigError::ReadonlyField),
+            "key_specs" => Err(ConfigError::ReadonlyField),
+            "acl_user_rules" => Err(ConfigError::ReadonlyField),
+            "deadletter_sink" => Err(ConfigError::ReadonlyField),
+            "invalidation_channels" => Err(ConfigError::ReadonlyField),
+            "backend_max_in_flight" => Err(ConfigError::ReadonlyField),
+            "backend_max_in_flight_overrides" => Err(ConfigError::ReadonlyField),
+            "backend_flap_threshold" => Err(ConfigError::ReadonlyField),
+            "backend_flap_window" => Err(ConfigError::ReadonlyField),
+            "backend_quarantine_duration" => Err(ConfigError::ReadonlyField),
+            "read_cache_prefixes" => Err(ConfigError::ReadonlyField),
+            "read_cache_ttl" => Err(ConfigError::ReadonlyField),
+            "read_cache_max_value_size" => Err(ConfigError::ReadonlyField),
+            "read_cache_max_entries" => Err(ConfigError::ReadonlyField),
+            "max_command_args" => {
+                let int_value = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_max_command_args(int_value);
+                Ok(())
+            }
+            "hedged_read_delay" => {
+                let int_value = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_hedged_read_delay(int_value);
+                Ok(())
+            }
+            "failover_retry_timeout" => {
+                let int_value = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_failover_retry_timeout(int_value);
+                Ok(())
+            }
+            "durable_write_timeout" => {
+                let int_value = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_durable_write_timeout(int_value);
+                Ok(())
+            }
+            "maintenance_mode" => {
+                let bool_value = value
+                    .parse::<bool>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_maintenance_mode(bool_value);
+                Ok(())
+            }
+            "maintenance_message" => {
+                // It's sent as a single line error reply.
+                if value.is_empty() || value.contains(|c| c == '\r' || c == '\n') {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.set_maintenance_message(value.to_string());
+                Ok(())
+            }
+            "command_log" => {
+                let bool_value = value
+                    .parse::<bool>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_command_log(bool_value);
+                Ok(())
+            }
+            "db_key_prefixes" => Err(ConfigError::ReadonlyField),
+            "db_byte_rate_quotas" => {
+                let quotas =
+                    parse_db_byte_rate_quotas(value).map_err(|_| ConfigError::InvalidValue)?;
+                self.set_db_byte_rate_quotas(quotas);
+                Ok(())
+            }
+            "monitor_enabled" => {
+                let bool_value = value
+                    .parse::<bool>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.set_monitor_enabled(bool_value);
+                Ok(())
+            }
             _ => Err(ConfigError::FieldNotFound),
         }
     }
@@ -123,6 +846,8 @@ pub struct ServerProxyService<H: CmdCtxHandler + ThreadSafe + Clone> {
     config: Arc<ServerProxyConfig>,
     cmd_ctx_handler: H,
     slow_request_logger: Arc<SlowRequestLogger>,
+    memory_budget: Arc<MemoryBudget>,
+    traffic_stats: Arc<TrafficStats>,
     future_registry: Arc<TrackedFutureRegistry>,
 }
 
@@ -131,35 +856,110 @@ impl<H: CmdCtxHandler + ThreadSafe + Clone> ServerProxyService<H> {
         config: Arc<ServerProxyConfig>,
         cmd_ctx_handler: H,
         slow_request_logger: Arc<SlowRequestLogger>,
+        memory_budget: Arc<MemoryBudget>,
+        traffic_stats: Arc<TrafficStats>,
         future_registry: Arc<TrackedFutureRegistry>,
     ) -> Self {
         Self {
             config,
             cmd_ctx_handler,
             slow_request_logger,
+            memory_budget,
+            traffic_stats,
             future_registry,
         }
     }
 
     pub async fn run(&self) -> Result<(), Box<dyn Error>> {
-        let address = self.config.address.clone();
-        let address = resolve_first_address(&address).ok_or_else(|| {
+        self.run_until(wait_for_shutdown_signal()).await
+    }
+
+    // Serves until `shutdown_signal` resolves, then stops accepting connections
+    // and drains the connected sessions.
+    pub async fn run_until<S: Future<Output = ()>>(
+        &self,
+        shutdown_signal: S,
+    ) -> Result<(), Box<dyn Error>> {
+        let listener = self.bind(&self.config.address)?;
+        let admin_listener = match self.config.admin_address.as_ref() {
+            Some(admin_address) => Some(self.bind(admin_address)?),
+            None => None,
+        };
+
+        let future_registry = self.future_registry.clone();
+        let flush_fut = self.slow_request_logger.clone().flush_periodically();
+        let desc = "slowlog: flush".to_string();
+        tokio::spawn(TrackedFutureRegistry::wrap(
+            future_registry.clone(),
+            flush_fut,
+            desc,
+        ));
+        let trim_fut = self.slow_request_logger.clone().trim_periodically();
+        let desc = "slowlog: trim".to_string();
+        tokio::spawn(TrackedFutureRegistry::wrap(future_registry, trim_fut, desc));
+
+        let session_id = AtomicUsize::new(0);
+        let serving = async {
+            match admin_listener {
+                Some(admin_listener) => {
+                    info!("admin port enabled: {:?}", self.config.admin_address);
+                    future::try_join(
+                        self.serve(listener, false, &session_id),
+                        self.serve(admin_listener, true, &session_id),
+                    )
+                    .await
+                    .map(|_| ())
+                }
+                None => self.serve(listener, false, &session_id).await,
+            }
+        };
+
+        let shutdown = future::select(Box::pin(serving), Box::pin(shutdown_signal)).await;
+        match shutdown {
+            future::Either::Left((res, _)) => res,
+            future::Either::Right(((), serving)) => {
+                // Dropping the serving future closes the listeners.
+                drop(serving);
+                info!("shutting down: stopped accepting connections");
+                let timeout = Duration::from_millis(self.config.shutdown_drain_timeout);
+                self.traffic_stats
+                    .wait_for_draining(timeout, DRAIN_LOG_INTERVAL)
+                    .await;
+                Ok(())
+            }
+        }
+    }
+
+    fn bind(&self, address: &str) -> Result<TcpListener, Box<dyn Error>> {
+        let sock_address = resolve_first_address(address).ok_or_else(|| {
             let err_str = format!("failed to resolve address: {}", address);
             error!("{}", err_str);
             into_err(err_str)
         })?;
 
-        let mut listener = TcpListener::bind(&address).await.map_err(|err| {
-            error!("unable to bind address: {} {:?}", address, err);
+        let listener = bind_listener(
+            sock_address,
+            self.config.listen_backlog,
+            self.config.listen_reuse_address,
+            self.config.listen_reuse_port,
+        )
+        .map_err(|err| {
+            error!("unable to bind address: {} {:?}", sock_address, err);
             err
         })?;
+        Ok(listener)
+    }
 
+    // Sessions from the admin port are allowed to run the management commands.
+    async fn serve(
+        &self,
+        mut listener: TcpListener,
+        admin_port: bool,
+        session_id: &AtomicUsize,
+    ) -> Result<(), Box<dyn Error>> {
         let forward_handler = self.cmd_ctx_handler.clone();
         let slow_request_logger = self.slow_request_logger.clone();
-
-        let session_id = AtomicUsize::new(0);
         let config = self.config.clone();
-
         let future_registry = self.future_registry.clone();
 
         let mut s = listener.incoming();
@@ -172,9 +972,13 @@ impl<H: CmdCtxHandler + ThreadSafe + Clone> ServerProxyService<H> {
                 return Err(into_err(err_str));
             }
 
-            let peer = match sock.peer_addr() {
-                Ok(address) => address.to_string(),
-                Err(e) => format!("Failed to get peer {}", e),
+            let (peer, peer_ip, peer_address) = match sock.peer_addr() {
+                Ok(address) => (
+                    address.to_string(),
+                    Some(address.ip().to_string()),
+                    Some(address.to_string()),
+                ),
+                Err(e) => (format!("Failed to get peer {}", e), None, None),
             };
             info!("accept conn: {}", peer);
 
@@ -184,11 +988,19 @@ impl<H: CmdCtxHandler + ThreadSafe + Clone> ServerProxyService<H> {
             let session_handler = handle_session(
                 Arc::new(Session::new(
                     curr_session_id,
+                    ClientSource {
+                        peer_ip,
+                        peer_address,
+                        admin_port,
+                    },
                     handle_clone,
                     slow_request_logger.clone(),
+                    self.memory_budget.clone(),
+                    self.traffic_stats.clone(),
                     config.clone(),
                 )),
                 sock,
+                self.memory_budget.clone(),
                 config.session_channel_size,
                 config.session_batch_min_time,
                 config.session_batch_max_time,
@@ -198,6 +1010,9 @@ impl<H: CmdCtxHandler + ThreadSafe + Clone> ServerProxyService<H> {
             let desc = format!("session: session_id={} peer={}", curr_session_id, peer);
             let fut = session_handler.map(move |res| match res {
                 Ok(()) => info!("session IO closed {}", peer),
+                Err(SessionError::HttpRequest) => {
+                    warn!("closed the HTTP request sent to the redis port {}", peer)
+                }
                 Err(err) => error!("session IO error {:?} {}", err, peer),
             });
             let fut = TrackedFutureRegistry::wrap(future_registry.clone(), fut, desc);
@@ -206,3 +1021,283 @@ impl<H: CmdCtxHandler + ThreadSafe + Clone> ServerProxyService<H> {
         Ok(())
     }
 }
+
+// Resolves on SIGTERM or SIGINT.
+async fn wait_for_shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("failed to listen to SIGINT: {:?}", err);
+            future::pending::<()>().await;
+        }
+        info!("received SIGINT");
+    };
+    future::select(Box::pin(ctrl_c), Box::pin(wait_for_terminate_signal())).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_terminate_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            terminate.recv().await;
+            info!("received SIGTERM");
+        }
+        Err(err) => {
+            error!("failed to listen to SIGTERM: {:?}", err);
+            future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_terminate_signal() {
+    future::pending::<()>().await;
+}
+
+// Built with socket2 since the options can't be set by `TcpListener::bind`.
+fn bind_listener(
+    address: SocketAddr,
+    backlog: i32,
+    reuse_address: bool,
+    reuse_port: bool,
+) -> io::Result<TcpListener> {
+    let domain = if address.is_ipv4() {
+        Domain::ipv4()
+    } else {
+        Domain::ipv6()
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    socket.set_reuse_address(reuse_address)?;
+    if reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    socket.bind(&SockAddr::from(address))?;
+    socket.listen(backlog)?;
+    let listener = socket.into_tcp_listener();
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Resp;
+    use crate::test_support::{gen_test_config, FakeBackendFunc, TestProxy};
+    use futures::channel::oneshot;
+    use std::time::Instant;
+    use tokio::net::TcpStream;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bind_listener_reuse_port() {
+        let address: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let listener = bind_listener(address, DEFAULT_LISTEN_BACKLOG, true, true).unwrap();
+        let address = listener.local_addr().unwrap();
+        // Another process could share the same port.
+        let another = bind_listener(address, DEFAULT_LISTEN_BACKLOG, true, true).unwrap();
+        assert_eq!(another.local_addr().unwrap(), address);
+
+        let listener = bind_listener(address, DEFAULT_LISTEN_BACKLOG, true, false);
+        assert!(listener.is_err());
+    }
+
+    #[test]
+    fn test_parse_default_clusters() {
+        let default_clusters =
+            parse_default_clusters(" 10.0.0.1=cluster_a, 10.0.0.2 = cluster_b,").unwrap();
+        assert_eq!(
+            default_clusters_to_string(&default_clusters),
+            "10.0.0.1=cluster_a,10.0.0.2=cluster_b"
+        );
+        assert_eq!(
+            find_default_cluster(&default_clusters, "10.0.0.2"),
+            Some(ClusterName::try_from("cluster_b").unwrap())
+        );
+        assert_eq!(find_default_cluster(&default_clusters, "10.0.0.3"), None);
+
+        assert!(parse_default_clusters("").unwrap().is_empty());
+        assert!(parse_default_clusters("10.0.0.1").is_err());
+        let long_name = "c".repeat(100);
+        assert!(parse_default_clusters(&format!("myapp={}", long_name)).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalidation_channels() {
+        let invalidation_channels =
+            parse_invalidation_channels(" user:=user_inv, user:vip:=vip_inv,").unwrap();
+        assert_eq!(
+            invalidation_channels_to_string(&invalidation_channels),
+            "user:=user_inv,user:vip:=vip_inv"
+        );
+        assert!(parse_invalidation_channels("").unwrap().is_empty());
+        assert!(parse_invalidation_channels("user:").is_err());
+        assert!(parse_invalidation_channels("=user_inv").is_err());
+        assert!(parse_invalidation_channels("user:=").is_err());
+    }
+
+    #[test]
+    fn test_parse_backend_max_in_flight_overrides() {
+        let overrides =
+            parse_backend_max_in_flight_overrides(" 127.0.0.1:6379=100, 127.0.0.1:6380=0,")
+                .unwrap();
+        assert_eq!(
+            overrides,
+            vec![
+                ("127.0.0.1:6379".to_string(), 100),
+                ("127.0.0.1:6380".to_string(), 0),
+            ]
+        );
+        assert_eq!(
+            backend_max_in_flight_overrides_to_string(&overrides),
+            "127.0.0.1:6379=100,127.0.0.1:6380=0"
+        );
+        assert!(parse_backend_max_in_flight_overrides("")
+            .unwrap()
+            .is_empty());
+        assert!(parse_backend_max_in_flight_overrides("127.0.0.1:6379").is_err());
+        assert!(parse_backend_max_in_flight_overrides("127.0.0.1:6379=-1").is_err());
+        assert!(parse_backend_max_in_flight_overrides("=1").is_err());
+    }
+
+    #[test]
+    fn test_parse_db_byte_rate_quotas() {
+        let quotas = parse_db_byte_rate_quotas(" tenant_a=1048576, tenant_b = 0,").unwrap();
+        assert_eq!(
+            quotas,
+            vec![
+                (ClusterName::try_from("tenant_a").unwrap(), 1_048_576),
+                (ClusterName::try_from("tenant_b").unwrap(), 0),
+            ]
+        );
+        assert_eq!(
+            db_byte_rate_quotas_to_string(&quotas),
+            "tenant_a=1048576,tenant_b=0"
+        );
+        assert!(parse_db_byte_rate_quotas("").unwrap().is_empty());
+        assert!(parse_db_byte_rate_quotas("tenant_a").is_err());
+        assert!(parse_db_byte_rate_quotas("tenant_a=-1").is_err());
+        assert!(parse_db_byte_rate_quotas("=1").is_err());
+        assert!(parse_db_byte_rate_quotas("tenant a=1").is_err());
+    }
+
+    #[test]
+    fn test_parse_db_key_prefixes() {
+        let prefixes = parse_db_key_prefixes(" tenant_a=a:, tenant_b = {b}: ,").unwrap();
+        assert_eq!(
+            prefixes,
+            vec![
+                (ClusterName::try_from("tenant_a").unwrap(), "a:".to_string()),
+                (
+                    ClusterName::try_from("tenant_b").unwrap(),
+                    "{b}:".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            db_key_prefixes_to_string(&prefixes),
+            "tenant_a=a:,tenant_b={b}:"
+        );
+        assert!(parse_db_key_prefixes("").unwrap().is_empty());
+        assert!(parse_db_key_prefixes("tenant_a").is_err());
+        assert!(parse_db_key_prefixes("tenant_a=").is_err());
+        assert!(parse_db_key_prefixes("=a:").is_err());
+    }
+
+    #[test]
+    fn test_rename_commands() {
+        let rename_commands = parse_rename_commands(" flushall = , keys=secret_keys").unwrap();
+        assert_eq!(
+            rename_commands_to_string(&rename_commands),
+            "FLUSHALL=,KEYS=secret_keys"
+        );
+        assert_eq!(
+            resolve_command_name(&rename_commands, "get"),
+            CommandRename::Unchanged
+        );
+        assert_eq!(
+            resolve_command_name(&rename_commands, "FlushAll"),
+            CommandRename::Disabled
+        );
+        assert_eq!(
+            resolve_command_name(&rename_commands, "keys"),
+            CommandRename::Disabled
+        );
+        assert_eq!(
+            resolve_command_name(&rename_commands, "SECRET_KEYS"),
+            CommandRename::Renamed("KEYS".to_string())
+        );
+
+        assert!(parse_rename_commands("").unwrap().is_empty());
+        assert!(parse_rename_commands("FLUSHALL").is_err());
+        assert!(parse_rename_commands("=alias").is_err());
+        assert!(parse_rename_commands("KEYS=a,keys=b").is_err());
+        assert!(parse_rename_commands("KEYS=a,SCAN=A").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_on_shutdown() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut config = gen_test_config();
+        config.address = address.to_string();
+        config.shutdown_drain_timeout = 3000;
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::with_config(config, backend);
+        let service = proxy.gen_service();
+        let traffic_stats = service.traffic_stats.clone();
+
+        let (sender, receiver) = oneshot::channel();
+        let finished = AtomicBool::new(false);
+        let run = service.run_until(receiver.map(|_| ())).map(|res| {
+            finished.store(true, Ordering::SeqCst);
+            res
+        });
+        let client = async {
+            let stream = loop {
+                if let Ok(stream) = TcpStream::connect(address).await {
+                    break stream;
+                }
+                futures_timer::Delay::new(Duration::from_millis(10)).await;
+            };
+            while traffic_stats.get_sessions() == 0 {
+                futures_timer::Delay::new(Duration::from_millis(10)).await;
+            }
+            sender.send(()).unwrap();
+
+            futures_timer::Delay::new(Duration::from_millis(100)).await;
+            // Not accepting the new connections.
+            assert!(TcpStream::connect(address).await.is_err());
+            // Waiting for the connected session.
+            assert!(!finished.load(Ordering::SeqCst));
+            assert_eq!(traffic_stats.get_sessions(), 1);
+            drop(stream);
+            Instant::now()
+        };
+
+        let fut = future::join(run, client);
+        let (res, disconnected) = tokio::time::timeout(Duration::from_secs(5), fut)
+            .await
+            .unwrap();
+        assert!(res.is_ok());
+        assert!(finished.load(Ordering::SeqCst));
+        assert_eq!(traffic_stats.get_sessions(), 0);
+        // Drained before the timeout.
+        assert!(disconnected.elapsed() < Duration::from_millis(2500));
+    }
+}
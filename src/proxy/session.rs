@@ -1,19 +1,32 @@
 use super::backend::{CmdTask, CmdTaskFactory, CmdTaskResult};
 use super::cluster::{ClusterTag, DEFAULT_CLUSTER};
 use super::command::{
-    new_command_pair, CmdReplyReceiver, CmdReplySender, CmdType, Command, CommandError,
-    CommandResult, DataCmdType, TaskReply, TaskResult,
+    has_own_timeout, new_command_pair, CmdReplyReceiver, CmdReplySender, CmdType, Command,
+    CommandError, CommandResult, DataCmdType, TaskReply, TaskResult,
 };
-use super::service::ServerProxyConfig;
+use super::deadletter::DeadLetterSlot;
+use super::inflight::{BackendInFlight, SaturatedBackends};
+use super::keyprefix;
+use super::memory::MemoryBudget;
+use super::monitor::{CommandMonitor, MonitorReceiver, MonitorSender, MONITOR_QUEUE_SIZE};
+use super::pause::ClientPause;
+use super::pubsub::{PushReceiver, PushSender, ShardedSubscription};
+use super::service::{CommandRename, ServerProxyConfig};
 use super::slowlog::{SlowRequestLogger, Slowlog, TaskEvent};
+use super::traffic::{InFlightGuard, TrafficStats};
 use crate::common::batch::TryChunksTimeoutStreamExt;
 use crate::common::cluster::ClusterName;
+use crate::common::response;
+use crate::common::utils::{gen_moved, pretty_print_bytes, ThreadSafe};
 use crate::protocol::{
-    new_simple_packet_codec, BinSafeStr, DecodeError, EncodeError, Resp, RespCodec, RespPacket,
-    RespVec,
+    parse_array_len, Array, BinSafeStr, BulkStr, DecodeError, EncodeError, PacketDecoder, Resp,
+    RespCodec, RespPacket, RespVec, SimplePacketDecoder, SimplePacketEncoder,
 };
-use futures::{future, stream, Future, TryFutureExt};
-use futures::{SinkExt, StreamExt, TryStreamExt};
+use bytes::BytesMut;
+use futures::channel::mpsc;
+use futures::{future, stream, Future, FutureExt, TryFutureExt};
+use futures::{SinkExt, Stream, StreamExt, TryStreamExt};
+use futures_timer::Delay;
 use std::boxed::Box;
 use std::cmp::min;
 use std::collections::VecDeque;
@@ -24,9 +37,10 @@ use std::io;
 use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::Decoder;
 
 // CmdReplyReceiver is the fast path without heap allocation.
@@ -36,6 +50,35 @@ pub type CmdReplyFuture<'a> =
 pub trait CmdHandler {
     fn handle_cmd(&self, cmd: Command) -> CmdReplyFuture;
     fn handle_slowlog(&self, request: Box<RespPacket>, slowlog: Slowlog);
+    fn handle_traffic(&self, data_cmd_type: DataCmdType, request_size: usize, reply_size: usize);
+    fn track_in_flight(&self, _cmd_num: usize) -> Option<InFlightGuard> {
+        None
+    }
+    fn get_command_timeout(&self) -> Option<Duration> {
+        None
+    }
+    fn get_slot_node(&self, _slot: usize) -> Option<String> {
+        None
+    }
+    fn get_slot_owner(&self, _slot: usize) -> Option<String> {
+        None
+    }
+    // The messages sent to the client without requests, e.g. the pub/sub messages.
+    fn take_push_receiver(&self) -> Option<PushReceiver> {
+        None
+    }
+    // The MONITOR lines. Closed when the client can't keep up with them.
+    fn take_monitor_receiver(&self) -> Option<MonitorReceiver> {
+        None
+    }
+    // The saturated backends the session should wait for before reading more requests.
+    fn get_saturated_backends(&self) -> Option<Arc<SaturatedBackends>> {
+        None
+    }
+    // Zero means no limit.
+    fn get_max_command_args(&self) -> u64 {
+        0
+    }
 }
 
 pub trait CmdCtxHandler {
@@ -43,8 +86,328 @@ pub trait CmdCtxHandler {
         &self,
         cmd_ctx: CmdCtx,
         result_receiver: CmdReplyReceiver,
-        session_cluster_name: &sync::RwLock<ClusterName>,
+        session_state: &SessionState,
     ) -> CmdReplyFuture;
+    fn get_slot_node(&self, _cluster_name: &ClusterName, _slot: usize) -> Option<String> {
+        None
+    }
+    fn get_slot_owner(&self, _cluster_name: &ClusterName, _slot: usize) -> Option<String> {
+        None
+    }
+    fn get_client_pause(&self) -> Option<&ClientPause> {
+        None
+    }
+    fn get_command_monitor(&self) -> Option<&CommandMonitor> {
+        None
+    }
+}
+
+// The states of a client connection.
+pub struct SessionState {
+    cluster_name: sync::RwLock<ClusterName>,
+    // CLIENT NO-EVICT and CLIENT NO-TOUCH are only recorded
+    // since the backend connections are shared by all the clients.
+    no_evict: AtomicBool,
+    no_touch: AtomicBool,
+    // Set by CLIENT DURABLE. Each write is followed by an implicit WAIT.
+    durable_writes: AtomicBool,
+    // WAIT and WAITAOF will be sent to the node of the last write.
+    last_write_slot: AtomicUsize,
+    // Whether the connection is from the admin port.
+    admin_port: bool,
+    client_name: sync::RwLock<Option<String>>,
+    // The username of AUTH or HELLO. None for the default user.
+    username: sync::RwLock<Option<String>>,
+    // Set by AUTH. The default cluster of the client name won't override it.
+    cluster_selected: AtomicBool,
+    // Only the (un)subscribe commands, PING, QUIT and RESET are allowed in this mode.
+    subscribe_mode: AtomicBool,
+    // Whether any command other than the connection setup ones is received.
+    first_cmd_received: AtomicBool,
+    sharded_subscription: Arc<ShardedSubscription>,
+    push_sender: PushSender,
+    // Taken by the session once it starts.
+    push_receiver: sync::Mutex<Option<PushReceiver>>,
+    // Bounded so that the MONITOR falling behind could be dropped.
+    monitor_sender: MonitorSender,
+    monitor_receiver: sync::Mutex<Option<MonitorReceiver>>,
+    saturated_backends: Arc<SaturatedBackends>,
+    // For CLIENT INFO and CLIENT LIST.
+    session_id: usize,
+    peer_ip: Option<String>,
+    // ip:port for MONITOR.
+    peer_address: Option<String>,
+    created_at: coarsetime::Instant,
+    // The lowercase and truncated name packed into the words
+    // so that recording it for each command takes no lock.
+    // CLIENT LIST racing with a new command could see a mix of two names.
+    last_cmd_name: [AtomicU64; LAST_CMD_NAME_WORDS],
+    // The coarsetime duration since `created_at`.
+    last_cmd_time: AtomicU64,
+}
+
+const LAST_CMD_NAME_WORDS: usize = 3;
+const LAST_CMD_NAME_LENGTH: usize = LAST_CMD_NAME_WORDS * 8;
+
+// Where the client connection comes from.
+pub struct ClientSource {
+    pub peer_ip: Option<String>,
+    pub peer_address: Option<String>,
+    // Whether the connection is from the admin port.
+    pub admin_port: bool,
+}
+
+const NO_SLOT: usize = usize::max_value();
+
+impl SessionState {
+    pub fn new(cluster_name: ClusterName, admin_port: bool) -> Self {
+        let now = coarsetime::Instant::now();
+        let (push_sender, push_receiver) = mpsc::unbounded();
+        let (monitor_sender, monitor_receiver) = mpsc::channel(MONITOR_QUEUE_SIZE);
+        Self {
+            cluster_name: sync::RwLock::new(cluster_name),
+            no_evict: AtomicBool::new(false),
+            no_touch: AtomicBool::new(false),
+            durable_writes: AtomicBool::new(false),
+            last_write_slot: AtomicUsize::new(NO_SLOT),
+            admin_port,
+            client_name: sync::RwLock::new(None),
+            username: sync::RwLock::new(None),
+            cluster_selected: AtomicBool::new(false),
+            subscribe_mode: AtomicBool::new(false),
+            first_cmd_received: AtomicBool::new(false),
+            sharded_subscription: Arc::new(ShardedSubscription::new(push_sender.clone())),
+            push_sender,
+            push_receiver: sync::Mutex::new(Some(push_receiver)),
+            monitor_sender,
+            monitor_receiver: sync::Mutex::new(Some(monitor_receiver)),
+            saturated_backends: Arc::new(SaturatedBackends::default()),
+            session_id: 0,
+            peer_ip: None,
+            peer_address: None,
+            created_at: now,
+            last_cmd_name: Default::default(),
+            last_cmd_time: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_client(mut self, session_id: usize, source: ClientSource) -> Self {
+        self.session_id = session_id;
+        self.peer_ip = source.peer_ip;
+        self.peer_address = source.peer_address;
+        self
+    }
+
+    pub fn get_session_id(&self) -> usize {
+        self.session_id
+    }
+
+    pub fn get_peer_ip(&self) -> Option<&str> {
+        self.peer_ip.as_deref()
+    }
+
+    pub fn get_peer_address(&self) -> Option<&str> {
+        self.peer_address.as_deref()
+    }
+
+    pub fn record_cmd(&self, cmd_name: &[u8]) {
+        let mut name = [0; LAST_CMD_NAME_LENGTH];
+        for (b, c) in name.iter_mut().zip(cmd_name.iter()) {
+            *b = c.to_ascii_lowercase();
+        }
+        for (word, chunk) in self.last_cmd_name.iter().zip(name.chunks_exact(8)) {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(chunk);
+            word.store(u64::from_le_bytes(bytes), Ordering::Relaxed);
+        }
+        let time = coarsetime::Instant::now().duration_since(self.created_at);
+        self.last_cmd_time.store(time.as_u64(), Ordering::Relaxed);
+    }
+
+    fn get_last_cmd_name(&self) -> String {
+        let name: Vec<u8> = self
+            .last_cmd_name
+            .iter()
+            .flat_map(|word| word.load(Ordering::Relaxed).to_le_bytes().to_vec())
+            .take_while(|b| *b != 0)
+            .collect();
+        String::from_utf8_lossy(&name).to_string()
+    }
+
+    // The line of CLIENT INFO and CLIENT LIST in the format of redis.
+    // `age` is the lifetime of the connection and `idle` is the time since the last command.
+    pub fn gen_client_info(&self) -> String {
+        let age = coarsetime::Instant::now().duration_since(self.created_at);
+        let last_cmd_time = self.last_cmd_time.load(Ordering::Relaxed);
+        let idle = coarsetime::Duration::from_u64(age.as_u64().saturating_sub(last_cmd_time));
+        let cmd = self.get_last_cmd_name();
+        let cmd = if cmd.is_empty() {
+            "NULL".to_string()
+        } else {
+            cmd
+        };
+        format!(
+            "id={} addr={} name={} age={} idle={} db=0 cmd={}",
+            self.session_id,
+            self.peer_ip.as_deref().unwrap_or(""),
+            self.get_client_name().unwrap_or_default(),
+            age.as_secs(),
+            idle.as_secs(),
+            cmd,
+        )
+    }
+
+    pub fn is_admin_port(&self) -> bool {
+        self.admin_port
+    }
+
+    // Returns true only for the first call.
+    pub fn take_first_cmd(&self) -> bool {
+        !self.first_cmd_received.load(Ordering::Relaxed)
+            && !self.first_cmd_received.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn get_cluster_name(&self) -> ClusterName {
+        self.cluster_name
+            .read()
+            .expect("SessionState::get_cluster_name")
+            .clone()
+    }
+
+    pub fn set_cluster_name(&self, cluster_name: ClusterName) {
+        *self
+            .cluster_name
+            .write()
+            .expect("SessionState::set_cluster_name") = cluster_name;
+        self.cluster_selected.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cluster_selected(&self) -> bool {
+        self.cluster_selected.load(Ordering::Relaxed)
+    }
+
+    // Only takes effect when the cluster is not selected by AUTH yet.
+    pub fn set_default_cluster_name(&self, cluster_name: ClusterName) {
+        let mut curr = self
+            .cluster_name
+            .write()
+            .expect("SessionState::set_default_cluster_name");
+        if !self.cluster_selected.load(Ordering::Relaxed) {
+            *curr = cluster_name;
+        }
+    }
+
+    pub fn get_client_name(&self) -> Option<String> {
+        self.client_name
+            .read()
+            .expect("SessionState::get_client_name")
+            .clone()
+    }
+
+    pub fn set_client_name(&self, client_name: Option<String>) {
+        *self
+            .client_name
+            .write()
+            .expect("SessionState::set_client_name") = client_name;
+    }
+
+    pub fn get_username(&self) -> Option<String> {
+        self.username
+            .read()
+            .expect("SessionState::get_username")
+            .clone()
+    }
+
+    pub fn set_username(&self, username: Option<String>) {
+        *self.username.write().expect("SessionState::set_username") = username;
+    }
+
+    pub fn is_no_evict(&self) -> bool {
+        self.no_evict.load(Ordering::Relaxed)
+    }
+
+    pub fn set_no_evict(&self, no_evict: bool) {
+        self.no_evict.store(no_evict, Ordering::Relaxed)
+    }
+
+    pub fn is_no_touch(&self) -> bool {
+        self.no_touch.load(Ordering::Relaxed)
+    }
+
+    pub fn set_no_touch(&self, no_touch: bool) {
+        self.no_touch.store(no_touch, Ordering::Relaxed)
+    }
+
+    pub fn is_durable_writes(&self) -> bool {
+        self.durable_writes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_durable_writes(&self, durable_writes: bool) {
+        self.durable_writes.store(durable_writes, Ordering::Relaxed)
+    }
+
+    pub fn get_last_write_slot(&self) -> Option<usize> {
+        match self.last_write_slot.load(Ordering::Relaxed) {
+            NO_SLOT => None,
+            slot => Some(slot),
+        }
+    }
+
+    pub fn set_last_write_slot(&self, slot: usize) {
+        self.last_write_slot.store(slot, Ordering::Relaxed)
+    }
+
+    // Also in this mode when there's any sharded subscription.
+    pub fn is_subscribe_mode(&self) -> bool {
+        self.subscribe_mode.load(Ordering::Relaxed)
+            || self.sharded_subscription.get_channel_num() > 0
+    }
+
+    pub fn set_subscribe_mode(&self, subscribe_mode: bool) {
+        self.subscribe_mode.store(subscribe_mode, Ordering::Relaxed)
+    }
+
+    pub fn get_sharded_subscription(&self) -> Arc<ShardedSubscription> {
+        self.sharded_subscription.clone()
+    }
+
+    pub fn get_push_sender(&self) -> PushSender {
+        self.push_sender.clone()
+    }
+
+    pub fn take_push_receiver(&self) -> Option<PushReceiver> {
+        self.push_receiver
+            .lock()
+            .expect("SessionState::take_push_receiver")
+            .take()
+    }
+
+    pub fn get_saturated_backends(&self) -> Arc<SaturatedBackends> {
+        self.saturated_backends.clone()
+    }
+
+    pub fn get_monitor_sender(&self) -> MonitorSender {
+        self.monitor_sender.clone()
+    }
+
+    pub fn take_monitor_receiver(&self) -> Option<MonitorReceiver> {
+        self.monitor_receiver
+            .lock()
+            .expect("SessionState::take_monitor_receiver")
+            .take()
+    }
+
+    // For RESET. The cluster selected by AUTH and the user are kept
+    // since the proxy has no default user to fall back to.
+    pub fn reset(&self) {
+        self.set_no_evict(false);
+        self.set_no_touch(false);
+        self.set_durable_writes(false);
+        self.last_write_slot.store(NO_SLOT, Ordering::Relaxed);
+        self.set_client_name(None);
+        self.set_subscribe_mode(false);
+        self.sharded_subscription.clear();
+    }
 }
 
 #[derive(Debug)]
@@ -54,6 +417,10 @@ pub struct CmdCtx {
     slowlog: Slowlog,
     cluster_name: ClusterName,
     redirection_times: Option<usize>,
+    conn_hint: Option<usize>,
+    push_sender: Option<PushSender>,
+    dead_letter_slot: Option<Arc<DeadLetterSlot>>,
+    saturated_backends: Option<Arc<SaturatedBackends>>,
 }
 
 impl CmdCtx {
@@ -71,6 +438,10 @@ impl CmdCtx {
             slowlog,
             cluster_name,
             redirection_times: None,
+            conn_hint: None,
+            push_sender: None,
+            dead_letter_slot: None,
+            saturated_backends: None,
         }
     }
 
@@ -114,8 +485,29 @@ impl CmdCtx {
     pub fn get_redirection_times(&self) -> Option<usize> {
         self.redirection_times
     }
+
+    pub fn set_slot(&mut self, slot: usize) {
+        self.cmd.set_slot(slot)
+    }
+
+    pub fn set_conn_hint(&mut self, conn_hint: usize) {
+        self.conn_hint = Some(conn_hint)
+    }
+
+    pub fn set_push_sender(&mut self, push_sender: PushSender) {
+        self.push_sender = Some(push_sender)
+    }
+
+    pub fn set_dead_letter_slot(&mut self, slot: Arc<DeadLetterSlot>) {
+        self.dead_letter_slot = Some(slot)
+    }
+
+    pub fn set_saturated_backends(&mut self, saturated_backends: Arc<SaturatedBackends>) {
+        self.saturated_backends = Some(saturated_backends)
+    }
 }
 
+#[derive(Clone)]
 pub struct SessionContext {
     cluster_name: ClusterName,
     session_id: usize,
@@ -140,8 +532,18 @@ impl CmdTask for CmdCtx {
             cmd,
             mut reply_sender,
             slowlog,
+            dead_letter_slot,
             ..
         } = self;
+        if let Some(slot) = dead_letter_slot {
+            let failed = match &result {
+                Ok(packet) => matches!(packet.to_resp_slice(), Resp::Error(_)),
+                Err(_) => true,
+            };
+            if failed {
+                slot.fill(&cmd);
+            }
+        }
         let task_result =
             result.map(|packet| Box::new(TaskReply::new(cmd.into_packet(), packet, slowlog)));
         let res = reply_sender.send(task_result);
@@ -176,6 +578,39 @@ impl CmdTask for CmdCtx {
     fn log_event(&mut self, event: TaskEvent) {
         self.slowlog.log_event(event);
     }
+
+    fn get_conn_hint(&self) -> Option<usize> {
+        self.conn_hint
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.get_cmd().is_read_only()
+    }
+
+    fn is_blocking(&self) -> bool {
+        has_own_timeout(self.get_cmd())
+    }
+
+    fn is_canceled(&self) -> bool {
+        self.reply_sender.is_canceled()
+    }
+
+    fn send_push(&self, packet: Self::Pkt) {
+        match self.push_sender.as_ref() {
+            Some(push_sender) => {
+                if push_sender.unbounded_send(packet.to_resp_vec()).is_err() {
+                    debug!("drop push message of closed session");
+                }
+            }
+            None => debug!("drop push message from backend"),
+        }
+    }
+
+    fn hold_back_session(&self, in_flight: &Arc<BackendInFlight>) {
+        if let Some(saturated_backends) = self.saturated_backends.as_ref() {
+            saturated_backends.add(in_flight.clone());
+        }
+    }
 }
 
 impl ClusterTag for CmdCtx {
@@ -221,40 +656,220 @@ impl CmdTaskFactory for CmdCtxFactory {
     }
 }
 
+// Builds a standalone `CmdCtx` without any session so that the routing
+// and migration logic could be tested by sending it to the handlers directly.
+// The returned future resolves to the reply set by the handler:
+//
+//     let (cmd_ctx, reply_fut) = CmdCtxBuilder::new(&["GET", "key"])
+//         .cluster_name(cluster_name)
+//         .build();
+//     manager.send(cmd_ctx);
+//     let reply = reply_fut.await;
+pub struct CmdCtxBuilder {
+    args: Vec<BinSafeStr>,
+    cluster_name: ClusterName,
+    session_id: usize,
+    slowlog_enabled: bool,
+}
+
+impl CmdCtxBuilder {
+    pub fn new(args: &[&str]) -> Self {
+        Self {
+            args: args.iter().map(|arg| arg.as_bytes().to_vec()).collect(),
+            cluster_name: ClusterName::try_from(DEFAULT_CLUSTER).expect("CmdCtxBuilder::new"),
+            session_id: 0,
+            slowlog_enabled: false,
+        }
+    }
+
+    pub fn cluster_name(mut self, cluster_name: ClusterName) -> Self {
+        self.cluster_name = cluster_name;
+        self
+    }
+
+    pub fn session_id(mut self, session_id: usize) -> Self {
+        self.session_id = session_id;
+        self
+    }
+
+    pub fn slowlog_enabled(mut self, slowlog_enabled: bool) -> Self {
+        self.slowlog_enabled = slowlog_enabled;
+        self
+    }
+
+    pub fn build(
+        self,
+    ) -> (
+        CmdCtx,
+        Pin<Box<dyn Future<Output = CmdTaskResult> + Send + 'static>>,
+    ) {
+        let Self {
+            args,
+            cluster_name,
+            session_id,
+            slowlog_enabled,
+        } = self;
+        let resp = Resp::Arr(Array::Arr(
+            args.into_iter()
+                .map(|arg| Resp::Bulk(BulkStr::Str(arg)))
+                .collect(),
+        ));
+        let context = SessionContext {
+            cluster_name,
+            session_id,
+            slowlog_enabled,
+        };
+        CmdCtxFactory::default().create_with_ctx(context, resp)
+    }
+}
+
+struct HeldCmdGuard<'a> {
+    held_cmds: &'a AtomicUsize,
+}
+
+impl<'a> HeldCmdGuard<'a> {
+    fn new(held_cmds: &'a AtomicUsize) -> Self {
+        held_cmds.fetch_add(1, Ordering::SeqCst);
+        Self { held_cmds }
+    }
+}
+
+impl<'a> Drop for HeldCmdGuard<'a> {
+    fn drop(&mut self) {
+        self.held_cmds.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 pub struct Session<H: CmdCtxHandler> {
     session_id: usize,
-    cluster_name: sync::Arc<sync::RwLock<ClusterName>>,
+    session_state: Arc<SessionState>,
     cmd_ctx_handler: H,
     slow_request_logger: sync::Arc<SlowRequestLogger>,
+    memory_budget: Arc<MemoryBudget>,
+    traffic_stats: Arc<TrafficStats>,
     config: Arc<ServerProxyConfig>,
+    // The commands held by CLIENT PAUSE and not dispatched yet.
+    // The following commands need to be held too to keep the order.
+    held_cmds: AtomicUsize,
 }
 
 impl<H: CmdCtxHandler> Session<H> {
     pub fn new(
         session_id: usize,
+        source: ClientSource,
         cmd_ctx_handler: H,
         slow_request_logger: sync::Arc<SlowRequestLogger>,
+        memory_budget: Arc<MemoryBudget>,
+        traffic_stats: Arc<TrafficStats>,
         config: Arc<ServerProxyConfig>,
     ) -> Self {
-        let cluster_name = ClusterName::try_from(DEFAULT_CLUSTER).expect("Session::new");
+        let cluster_name = source
+            .peer_ip
+            .as_ref()
+            .and_then(|ip| config.get_ip_default_cluster(ip))
+            .unwrap_or_else(|| ClusterName::try_from(DEFAULT_CLUSTER).expect("Session::new"));
+        let session_state = Arc::new(
+            SessionState::new(cluster_name, source.admin_port).with_client(session_id, source),
+        );
+        traffic_stats.session_opened();
+        traffic_stats.register_client(&session_state);
         Session {
             session_id,
-            cluster_name: sync::Arc::new(sync::RwLock::new(cluster_name)),
+            session_state,
             cmd_ctx_handler,
             slow_request_logger,
+            memory_budget,
+            traffic_stats,
             config,
+            held_cmds: AtomicUsize::new(0),
+        }
+    }
+
+    fn should_hold(&self, cmd: &Command) -> bool {
+        let client_pause = match self.cmd_ctx_handler.get_client_pause() {
+            Some(client_pause) => client_pause,
+            None => return false,
+        };
+        self.held_cmds.load(Ordering::SeqCst) > 0 || client_pause.should_hold(cmd)
+    }
+
+    async fn handle_held_cmd_ctx(
+        &self,
+        guard: HeldCmdGuard<'_>,
+        cmd_ctx: CmdCtx,
+        reply_receiver: CmdReplyReceiver,
+    ) -> TaskResult {
+        if let Some(client_pause) = self.cmd_ctx_handler.get_client_pause() {
+            client_pause.wait(cmd_ctx.get_cmd()).await;
+        }
+        drop(guard);
+        match self
+            .cmd_ctx_handler
+            .handle_cmd_ctx(cmd_ctx, reply_receiver, &self.session_state)
+        {
+            future::Either::Left(reply_receiver) => reply_receiver.await,
+            future::Either::Right(fut) => fut.await,
+        }
+    }
+
+    // Returns false if the command without key can't be routed.
+    fn route_by_last_write(&self, cmd: &mut Command) -> bool {
+        let data_cmd_type = cmd.get_data_cmd_type();
+        match data_cmd_type {
+            DataCmdType::WAIT | DataCmdType::WAITAOF => {
+                match self.session_state.get_last_write_slot() {
+                    Some(slot) => {
+                        cmd.set_slot(slot);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => {
+                if cmd.get_type() == CmdType::Others && !cmd.is_read_only() {
+                    if let Some(slot) = cmd.get_slot() {
+                        self.session_state.set_last_write_slot(slot);
+                    }
+                }
+                true
+            }
         }
     }
 }
 
-impl<H: CmdCtxHandler> CmdHandler for Session<H> {
+impl<H: CmdCtxHandler + ThreadSafe> CmdHandler for Session<H> {
     fn handle_cmd(&self, cmd: Command) -> CmdReplyFuture {
+        let mut cmd = cmd;
+        if let Some(cmd_name) = cmd.get_command_element(0) {
+            self.session_state.record_cmd(cmd_name);
+        }
+        if let Some(monitor) = self.cmd_ctx_handler.get_command_monitor() {
+            if self.config.is_monitor_enabled() {
+                monitor.feed(&self.session_state, &cmd);
+            }
+        }
+        let rename = cmd
+            .get_command_name()
+            .map(|cmd_name| self.config.resolve_command_name(cmd_name));
+        let mut disabled_cmd_name = None;
+        match rename {
+            Some(CommandRename::Renamed(original)) => {
+                cmd.rename(&original);
+            }
+            Some(CommandRename::Disabled) => {
+                disabled_cmd_name = cmd.get_command_name().map(ToString::to_string);
+            }
+            _ => (),
+        }
+        self.config.apply_key_spec(&mut cmd);
+        let cluster_name = self.session_state.get_cluster_name();
+        let key_prefix = self.config.get_db_key_prefix(&cluster_name);
+        if let Some(prefix) = key_prefix {
+            keyprefix::add_key_prefix(&mut cmd, prefix);
+        }
+        let stripped_prefix = key_prefix.filter(|_| keyprefix::echoes_keys(&cmd));
+        let routed = self.route_by_last_write(&mut cmd);
         let (reply_sender, reply_receiver) = new_command_pair(&cmd);
-        let cluster_name = self
-            .cluster_name
-            .read()
-            .expect("Session::handle_cmd")
-            .clone();
 
         let slowlog_enabled = self
             .slow_request_logger
@@ -266,19 +881,428 @@ impl<H: CmdCtxHandler> CmdHandler for Session<H> {
             self.session_id,
             slowlog_enabled,
         );
+        cmd_ctx.set_push_sender(self.session_state.get_push_sender());
+        cmd_ctx.set_saturated_backends(self.session_state.get_saturated_backends());
         cmd_ctx.log_event(TaskEvent::Created);
-        self.cmd_ctx_handler
-            .handle_cmd_ctx(cmd_ctx, reply_receiver, &(*self.cluster_name))
+
+        if self.config.is_command_log_enabled() {
+            debug!(
+                "session_id={} DBName={} command: {}",
+                self.session_id,
+                cmd_ctx.get_cluster_name(),
+                format_cmd_for_log(cmd_ctx.get_cmd())
+            );
+        }
+
+        if let Some(cmd_name) = disabled_cmd_name {
+            let err = format!("ERR unknown command '{}'", cmd_name);
+            cmd_ctx.set_resp_result(Ok(Resp::Error(err.into_bytes())));
+            return future::Either::Left(reply_receiver);
+        }
+
+        if self.session_state.is_subscribe_mode()
+            && !cmd_ctx.get_cmd().is_allowed_in_subscribe_mode()
+        {
+            let err = format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in subscribe mode",
+                cmd_ctx.get_cmd().get_command_name().unwrap_or("").to_lowercase()
+            );
+            cmd_ctx.set_resp_result(Ok(Resp::Error(err.into_bytes())));
+            return future::Either::Left(reply_receiver);
+        }
+
+        let admin_port_enabled = self.config.admin_address.is_some();
+        if admin_port_enabled
+            && !self.session_state.is_admin_port()
+            && cmd_ctx.get_cmd().is_admin_cmd()
+        {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_ADMIN_PORT_ONLY.to_string().into_bytes(),
+            )));
+            return future::Either::Left(reply_receiver);
+        }
+        // Only the clients connected during the maintenance get the notice.
+        if !is_connection_setup_cmd(cmd_ctx.get_cmd())
+            && self.session_state.take_first_cmd()
+            && self.config.is_maintenance_mode()
+        {
+            let msg = self.config.get_maintenance_message();
+            cmd_ctx.set_resp_result(Ok(Resp::Error(msg.as_bytes().to_vec())));
+            return future::Either::Left(reply_receiver);
+        }
+        if self.memory_budget.exceeds_hard_limit() {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(response::ERR_OOM.to_string().into_bytes())));
+            return future::Either::Left(reply_receiver);
+        }
+        // The quota is checked against the bytes already sent within the current second,
+        // so the command exceeding it is still allowed.
+        if !is_connection_setup_cmd(cmd_ctx.get_cmd()) {
+            let cluster_name = cmd_ctx.get_cluster_name();
+            let quota = self.config.get_db_byte_rate_quota(cluster_name);
+            if quota != 0 && self.traffic_stats.get_db_byte_rate(cluster_name) >= quota {
+                cmd_ctx.set_resp_result(Ok(Resp::Error(
+                    response::ERR_DB_QUOTA_EXCEEDED.to_string().into_bytes(),
+                )));
+                return future::Either::Left(reply_receiver);
+            }
+        }
+        if !routed && !cmd_ctx.get_cmd().is_zero_replica_wait() {
+            cmd_ctx.set_resp_result(Ok(Resp::Error(
+                response::ERR_NO_WRITE_TO_WAIT.to_string().into_bytes(),
+            )));
+            return future::Either::Left(reply_receiver);
+        }
+        let reply_fut: CmdReplyFuture = if self.should_hold(cmd_ctx.get_cmd()) {
+            let guard = HeldCmdGuard::new(&self.held_cmds);
+            future::Either::Right(Box::pin(self.handle_held_cmd_ctx(
+                guard,
+                cmd_ctx,
+                reply_receiver,
+            )))
+        } else {
+            self.cmd_ctx_handler
+                .handle_cmd_ctx(cmd_ctx, reply_receiver, &self.session_state)
+        };
+        match stripped_prefix {
+            Some(prefix) => future::Either::Right(Box::pin(keyprefix::strip_reply_key_prefix(
+                reply_fut, prefix,
+            ))),
+            None => reply_fut,
+        }
     }
 
     fn handle_slowlog(&self, request: Box<RespPacket>, slowlog: Slowlog) {
         self.slow_request_logger.add_slow_log(request, slowlog)
     }
+
+    fn handle_traffic(&self, data_cmd_type: DataCmdType, request_size: usize, reply_size: usize) {
+        self.traffic_stats
+            .record(data_cmd_type, request_size, reply_size);
+        self.traffic_stats.record_db(
+            &self.session_state.get_cluster_name(),
+            request_size,
+            reply_size,
+        )
+    }
+
+    fn track_in_flight(&self, cmd_num: usize) -> Option<InFlightGuard> {
+        Some(self.traffic_stats.track_in_flight(cmd_num))
+    }
+
+    fn get_command_timeout(&self) -> Option<Duration> {
+        self.config.get_client_command_timeout()
+    }
+
+    fn get_slot_node(&self, slot: usize) -> Option<String> {
+        let cluster_name = self.session_state.get_cluster_name();
+        self.cmd_ctx_handler.get_slot_node(&cluster_name, slot)
+    }
+
+    fn get_slot_owner(&self, slot: usize) -> Option<String> {
+        let cluster_name = self.session_state.get_cluster_name();
+        self.cmd_ctx_handler.get_slot_owner(&cluster_name, slot)
+    }
+
+    fn take_push_receiver(&self) -> Option<PushReceiver> {
+        self.session_state.take_push_receiver()
+    }
+
+    fn take_monitor_receiver(&self) -> Option<MonitorReceiver> {
+        self.session_state.take_monitor_receiver()
+    }
+
+    fn get_saturated_backends(&self) -> Option<Arc<SaturatedBackends>> {
+        Some(self.session_state.get_saturated_backends())
+    }
+
+    fn get_max_command_args(&self) -> u64 {
+        self.config.get_max_command_args()
+    }
+}
+
+// The maintenance notice is sent to the first command after these
+// so that the client libraries could still set up the connections.
+fn is_connection_setup_cmd(cmd: &Command) -> bool {
+    cmd.is_admin_cmd()
+        || match cmd.get_type() {
+            CmdType::Auth
+            | CmdType::Hello
+            | CmdType::Select
+            | CmdType::Client
+            | CmdType::Ping
+            | CmdType::Quit
+            | CmdType::Reset => true,
+            _ => false,
+        }
+}
+
+// Only the command name and the key of the data commands are logged.
+// The other arguments like the values and the AUTH passwords are never logged.
+fn format_cmd_for_log(cmd: &Command) -> String {
+    let cmd_name = cmd
+        .get_command_element(0)
+        .map(|name| truncate_for_log(name))
+        .unwrap_or_default();
+    let arg_num = cmd.get_command_len().unwrap_or(0).saturating_sub(1);
+    let key = match cmd.get_type() {
+        CmdType::Others => cmd.get_key(),
+        _ => None,
+    };
+    match key {
+        Some(key) => format!(
+            "{} key={} ({} args redacted)",
+            cmd_name,
+            truncate_for_log(key),
+            arg_num.saturating_sub(1)
+        ),
+        None => format!("{} ({} args redacted)", cmd_name, arg_num),
+    }
+}
+
+fn truncate_for_log(data: &[u8]) -> String {
+    if data.len() <= MAX_LOGGED_ARG_LEN {
+        return pretty_print_bytes(data);
+    }
+    format!(
+        "{}...({} bytes)",
+        pretty_print_bytes(&data[..MAX_LOGGED_ARG_LEN]),
+        data.len()
+    )
+}
+
+impl<H: CmdCtxHandler> Drop for Session<H> {
+    fn drop(&mut self) {
+        if let Some(monitor) = self.cmd_ctx_handler.get_command_monitor() {
+            monitor.remove(self.session_id);
+        }
+        self.traffic_stats.unregister_client(self.session_id);
+        self.traffic_stats.session_closed();
+    }
+}
+
+// The commands sent before a topology change may fail because their backend connection
+// is closed or dropped. If the node serving the slot has been changed since then,
+// redirect them to the current owner of the slot, which may be this proxy itself,
+// so that the clients can retry instead of getting a connection error.
+// Only the reads are redirected since the writes may have been processed by the backend
+// and the clients retry MOVED without asking.
+// The commands received after the change are routed by the new metadata as usual.
+fn get_redirectable_slot_node<H: CmdHandler>(
+    handler: &H,
+    cmd: &Command,
+) -> Option<(usize, String)> {
+    if !cmd.is_read_only() {
+        return None;
+    }
+    let slot = cmd.get_slot()?;
+    let node = handler.get_slot_node(slot)?;
+    Some((slot, node))
+}
+
+fn moved_on_slot_change<H: CmdHandler>(
+    handler: &H,
+    slot_node: Option<(usize, String)>,
+) -> Option<Box<RespPacket>> {
+    let (slot, node) = slot_node?;
+    if handler.get_slot_node(slot).as_ref() == Some(&node) {
+        return None;
+    }
+    let address = handler.get_slot_owner(slot)?;
+    let resp = Resp::Error(gen_moved(slot, address).into_bytes());
+    Some(Box::new(RespPacket::from_resp_vec(resp)))
+}
+
+fn is_backend_connection_error(packet: &RespPacket) -> bool {
+    match packet.to_resp_slice() {
+        Resp::Error(err) => err.starts_with(response::ERR_BACKEND_CONNECTION.as_bytes()),
+        _ => false,
+    }
 }
 
-pub async fn handle_session<H>(
+// Zero means no limit.
+fn exceeds_max_args(len: i64, max_command_args: u64) -> bool {
+    max_command_args != 0 && len > 0 && len as u64 > max_command_args
+}
+
+// Rejects the commands with too many arguments by the array header
+// so that the arguments are never buffered.
+struct SessionDecoder<H: CmdHandler> {
     handler: sync::Arc<H>,
-    sock: TcpStream,
+    decoder: SimplePacketDecoder<Box<RespPacket>>,
+}
+
+impl<H: CmdHandler> SessionDecoder<H> {
+    fn new(handler: sync::Arc<H>) -> Self {
+        Self {
+            handler,
+            decoder: SimplePacketDecoder::default(),
+        }
+    }
+}
+
+impl<H: CmdHandler> PacketDecoder for SessionDecoder<H> {
+    type Pkt = Box<RespPacket>;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Pkt>, DecodeError> {
+        if let Ok(Some(len)) = parse_array_len(buf) {
+            if exceeds_max_args(len, self.handler.get_max_command_args()) {
+                return Err(DecodeError::TooManyArguments);
+            }
+        }
+        self.decoder.decode(buf)
+    }
+}
+
+// The keys and the command names longer than this are truncated in the command log.
+const MAX_LOGGED_ARG_LEN: usize = 64;
+
+type TimedReplyFuture<'a> =
+    future::Either<CmdReplyFuture<'a>, Pin<Box<dyn Future<Output = TaskResult> + Send + 'a>>>;
+
+// The timer starts when the command is received
+// instead of when the previous replies are sent.
+fn with_command_timeout(
+    reply_fut: CmdReplyFuture,
+    own_timeout: bool,
+    timeout: Option<Duration>,
+) -> TimedReplyFuture {
+    let timeout = match timeout {
+        Some(timeout) if !own_timeout => timeout,
+        _ => return future::Either::Left(reply_fut),
+    };
+    let fut = future::select(reply_fut, Delay::new(timeout)).map(|res| match res {
+        future::Either::Left((reply, _)) => reply,
+        future::Either::Right(_) => Err(CommandError::Timeout),
+    });
+    future::Either::Right(Box::pin(fut))
+}
+
+// Waits for the reply while reading the following requests of the session.
+// Returns None if the connection is closed.
+async fn wait_reply<R, T>(
+    mut reply_fut: TimedReplyFuture<'_>,
+    reader: &mut R,
+    read_buf: &mut VecDeque<T>,
+) -> Option<TaskResult>
+where
+    R: Stream<Item = Vec<T>> + Unpin,
+{
+    // reply_fut may block forever for some commands, such as BLPOP, BRPOP, BRPOPLPUSH.
+    // Then even the connection is closed, this future won't exit.
+    // We need to select it with tcp stream read to detect closed connection.
+    loop {
+        match future::select(reply_fut, reader.next()).await {
+            future::Either::Left((res, read_fut)) => {
+                let _ = read_fut; // can be dropped without losing any item.
+                return Some(res);
+            }
+            future::Either::Right((read_result, fut)) => {
+                reply_fut = fut;
+                match read_result {
+                    Some(reqs) => read_buf.extend(reqs),
+                    None => return None,
+                }
+            }
+        }
+    }
+}
+
+fn gen_reply_packet<H: CmdHandler>(
+    handler: &H,
+    res: TaskResult,
+    slot_node: Option<(usize, String)>,
+) -> Box<RespPacket> {
+    match res.map_err(SessionError::CmdErr) {
+        Ok(task_reply) => {
+            let (request, mut packet, mut slowlog) = (*task_reply).into_inner();
+            slowlog.log_event(TaskEvent::WaitDone);
+            handler.handle_slowlog(request, slowlog);
+            // HELLO only negotiates RESP2 so the RESP3 replies of the backends
+            // are always downconverted.
+            packet.downconvert_resp3();
+            if is_backend_connection_error(&packet) {
+                moved_on_slot_change(handler, slot_node).unwrap_or(packet)
+            } else {
+                packet
+            }
+        }
+        Err(SessionError::CmdErr(CommandError::Timeout)) => {
+            let resp = Resp::Error(response::ERR_COMMAND_TIMEOUT.to_string().into_bytes());
+            Box::new(RespPacket::from_resp_vec(resp))
+        }
+        Err(e) => {
+            let redirected = match e {
+                SessionError::CmdErr(CommandError::Dropped)
+                | SessionError::CmdErr(CommandError::Canceled) => {
+                    moved_on_slot_change(handler, slot_node)
+                }
+                _ => None,
+            };
+            match redirected {
+                Some(packet) => packet,
+                None => {
+                    let err_msg = format!("Err cmd error {:?}", e);
+                    error!("{}", err_msg);
+                    let resp = Resp::Error(err_msg.into_bytes());
+                    Box::new(RespPacket::from_resp_vec(resp))
+                }
+            }
+        }
+    }
+}
+
+// Tells the client why the connection is closed before closing it.
+fn gen_reader_error_reply(err: &SessionError) -> Option<Box<RespPacket>> {
+    match err {
+        SessionError::HttpRequest => {
+            let resp = Resp::Error(response::ERR_HTTP_REQUEST.to_string().into_bytes());
+            Some(Box::new(RespPacket::from_resp_vec(resp)))
+        }
+        // The remaining arguments are not read so the connection can't be reused.
+        SessionError::TooManyArguments => {
+            let resp = Resp::Error(response::ERR_TOO_MANY_ARGUMENTS.to_string().into_bytes());
+            Some(Box::new(RespPacket::from_resp_vec(resp)))
+        }
+        _ => None,
+    }
+}
+
+type PushStream = Pin<Box<dyn Stream<Item = Result<RespVec, SessionError>> + Send>>;
+
+// The monitor channel is only closed when the client can't keep up with
+// the MONITOR lines, which closes the session after the queued lines are sent.
+fn merge_push_streams(
+    push_receiver: Option<PushReceiver>,
+    monitor_receiver: Option<MonitorReceiver>,
+) -> Option<PushStream> {
+    let monitor_stream = monitor_receiver.map(|receiver| {
+        receiver.map(Ok).chain(stream::once(future::ready(Err(
+            SessionError::MonitorQueueFull,
+        ))))
+    });
+    match (push_receiver, monitor_stream) {
+        (Some(push_receiver), Some(monitor_stream)) => Some(Box::pin(stream::select(
+            push_receiver.map(Ok),
+            monitor_stream,
+        ))),
+        (Some(push_receiver), None) => Some(Box::pin(push_receiver.map(Ok))),
+        (None, Some(monitor_stream)) => Some(Box::pin(monitor_stream)),
+        (None, None) => None,
+    }
+}
+
+fn to_session_error<T>(err: EncodeError<T>) -> SessionError {
+    error!("writer error: {}", err);
+    match err {
+        EncodeError::Io(err) => SessionError::Io(err),
+        EncodeError::NotReady(_) => SessionError::InvalidState,
+    }
+}
+
+pub async fn handle_session<H, S>(
+    handler: sync::Arc<H>,
+    sock: S,
+    memory_budget: Arc<MemoryBudget>,
     _channel_size: usize,
     session_batch_min_time: usize,
     session_batch_max_time: usize,
@@ -286,27 +1310,63 @@ pub async fn handle_session<H>(
 ) -> Result<(), SessionError>
 where
     H: CmdHandler + Send + Sync + 'static,
+    S: AsyncRead + AsyncWrite,
 {
-    let (encoder, decoder) = new_simple_packet_codec::<Box<RespPacket>, Box<RespPacket>>();
+    let encoder = SimplePacketEncoder::<Box<RespPacket>>::default();
+    let decoder = SessionDecoder::new(handler.clone());
     let (mut writer, reader) = RespCodec::new(encoder, decoder).framed(sock).split();
     let mut reader = reader
         .map_err(|e| match e {
             DecodeError::Io(e) => SessionError::Io(e),
             DecodeError::InvalidProtocol => SessionError::Canceled,
+            DecodeError::HttpRequest => SessionError::HttpRequest,
+            DecodeError::TooManyArguments => SessionError::TooManyArguments,
         })
         .try_chunks_timeout(
             session_batch_buf,
             Duration::from_nanos(session_batch_min_time as u64),
             Duration::from_nanos(session_batch_max_time as u64),
+            true,
         );
 
     let mut reply_receiver_list = Vec::with_capacity(session_batch_buf.get());
     let mut replies = Vec::with_capacity(session_batch_buf.get());
     let mut read_buf = VecDeque::with_capacity(session_batch_buf.get());
+    // The pushed messages are only sent when all the replies have been sent.
+    let mut push_receiver = merge_push_streams(
+        handler.take_push_receiver(),
+        handler.take_monitor_receiver(),
+    );
+
+    let saturated_backends = handler.get_saturated_backends();
 
     loop {
-        let reqs = if read_buf.is_empty() {
-            match reader.next().await {
+        // Stop sending more commands to the saturated backends.
+        if let Some(saturated_backends) = saturated_backends.as_ref() {
+            saturated_backends.wait_for_capacity().await;
+        }
+
+        let mut reqs = if read_buf.is_empty() {
+            // All the replies of this session have been sent,
+            // so it's safe to stop reading here.
+            memory_budget.wait_for_soft_limit().await;
+            let reqs = match push_receiver.as_mut() {
+                Some(receiver) => match future::select(reader.next(), receiver.next()).await {
+                    future::Either::Left((reqs, _)) => reqs,
+                    future::Either::Right((Some(Ok(msg)), _)) => {
+                        let packet = Box::new(RespPacket::from_resp_vec(msg));
+                        writer.send(packet).await.map_err(to_session_error)?;
+                        continue;
+                    }
+                    future::Either::Right((Some(Err(err)), _)) => return Err(err),
+                    future::Either::Right((None, _)) => {
+                        push_receiver = None;
+                        continue;
+                    }
+                },
+                None => reader.next().await,
+            };
+            match reqs {
                 Some(reqs) => reqs,
                 None => return Ok(()),
             }
@@ -316,76 +1376,91 @@ where
                 .collect()
         };
 
-        for req in reqs.into_iter() {
+        // Released after the replies are sent.
+        let mut memory_guard = memory_budget.allocate(0);
+        let _in_flight_guard = handler.track_in_flight(reqs.len());
+        let command_timeout = handler.get_command_timeout();
+
+        // Fast path for the non-pipelined clients sending one command per round trip.
+        let single_req = if reqs.len() == 1 && read_buf.is_empty() {
+            reqs.pop()
+        } else {
+            None
+        };
+        if let Some(req) = single_req {
             let packet = match req {
                 Ok(packet) => packet,
                 Err(err) => {
                     error!("session reader error {:?}", err);
+                    if let Some(packet) = gen_reader_error_reply(&err) {
+                        writer.send(packet).await.map_err(to_session_error)?;
+                    }
                     return Err(err);
                 }
             };
+            let request_size = packet.get_data_size();
+            memory_guard.grow(request_size);
             let cmd = Command::new(packet);
+            let data_cmd_type = cmd.get_data_cmd_type();
+            let slot_node = get_redirectable_slot_node(&*handler, &cmd);
 
-            let fut = handler.handle_cmd(cmd);
-            reply_receiver_list.push(fut);
-        }
-
-        for reply_receiver in reply_receiver_list.drain(..) {
-            let res = {
-                // reply_fut may block forever for some commands, such as BLPOP, BRPOP, BRPOPLPUSH.
-                // Then even the connection is closed, this future won't exit.
-                // We need to select it with tcp stream read to detect closed connection.
-                let mut reply_fut = Some(reply_receiver);
-                let res = loop {
-                    let fut = reply_fut.take().ok_or_else(|| {
-                        error!("session invalid state: cannot get reply_fut.");
-                        SessionError::InvalidState
-                    })?;
-                    match future::select(fut, reader.next()).await {
-                        future::Either::Left((res, read_fut)) => {
-                            let _ = read_fut; // can be dropped without losing any item.
-                            break res;
-                        }
-                        future::Either::Right((read_result, fut)) => {
-                            reply_fut = Some(fut);
-                            match read_result {
-                                Some(reqs) => read_buf.extend(reqs),
-                                None => return Ok(()),
-                            }
-                            continue;
-                        }
-                    }
-                };
-                res.map_err(SessionError::CmdErr)
+            let own_timeout = has_own_timeout(&cmd);
+            let fut = with_command_timeout(handler.handle_cmd(cmd), own_timeout, command_timeout);
+            let res = match wait_reply(fut, &mut reader, &mut read_buf).await {
+                Some(res) => res,
+                None => return Ok(()),
             };
+            let packet = gen_reply_packet(&*handler, res, slot_node);
 
-            let packet = match res {
-                Ok(task_reply) => {
-                    let (request, packet, mut slowlog) = (*task_reply).into_inner();
-                    slowlog.log_event(TaskEvent::WaitDone);
-                    handler.handle_slowlog(request, slowlog);
-                    packet
-                }
-                Err(e) => {
-                    let err_msg = format!("Err cmd error {:?}", e);
-                    error!("{}", err_msg);
-                    let resp = Resp::Error(err_msg.into_bytes());
-                    Box::new(RespPacket::from_resp_vec(resp))
+            let reply_size = packet.get_data_size();
+            memory_guard.grow(reply_size);
+            handler.handle_traffic(data_cmd_type, request_size, reply_size);
+            writer.send(packet).await.map_err(to_session_error)?;
+            continue;
+        }
+
+        for req in reqs.into_iter() {
+            let packet = match req {
+                Ok(packet) => packet,
+                Err(err) => {
+                    error!("session reader error {:?}", err);
+                    if let Some(packet) = gen_reader_error_reply(&err) {
+                        writer.send(packet).await.map_err(to_session_error)?;
+                    }
+                    return Err(err);
                 }
             };
+            let request_size = packet.get_data_size();
+            memory_guard.grow(request_size);
+            let cmd = Command::new(packet);
+            let data_cmd_type = cmd.get_data_cmd_type();
+            let slot_node = get_redirectable_slot_node(&*handler, &cmd);
 
-            replies.push(packet);
+            let own_timeout = has_own_timeout(&cmd);
+            let fut = with_command_timeout(handler.handle_cmd(cmd), own_timeout, command_timeout);
+            reply_receiver_list.push((data_cmd_type, request_size, slot_node, fut));
         }
 
-        let mut batch = stream::iter(replies.drain(..)).map(Ok);
-        if let Err(err) = writer.send_all(&mut batch).await {
-            error!("writer error: {}", err);
-            let err = match err {
-                EncodeError::Io(err) => SessionError::Io(err),
-                EncodeError::NotReady(_) => SessionError::InvalidState,
+        for (data_cmd_type, request_size, slot_node, reply_receiver) in
+            reply_receiver_list.drain(..)
+        {
+            let res = match wait_reply(reply_receiver, &mut reader, &mut read_buf).await {
+                Some(res) => res,
+                None => return Ok(()),
             };
-            return Err(err);
+            let packet = gen_reply_packet(&*handler, res, slot_node);
+
+            let reply_size = packet.get_data_size();
+            memory_guard.grow(reply_size);
+            handler.handle_traffic(data_cmd_type, request_size, reply_size);
+            replies.push(packet);
         }
+
+        let mut batch = stream::iter(replies.drain(..)).map(Ok);
+        writer
+            .send_all(&mut batch)
+            .await
+            .map_err(to_session_error)?;
     }
 }
 
@@ -394,8 +1469,11 @@ pub enum SessionError {
     Io(io::Error),
     CmdErr(CommandError),
     InvalidProtocol,
+    HttpRequest,
+    TooManyArguments,
     Canceled,
     InvalidState,
+    MonitorQueueFull,
 }
 
 impl fmt::Display for SessionError {
@@ -421,9 +1499,16 @@ impl Error for SessionError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::utils::generate_slot;
     use crate::protocol::{Array, BulkStr, Resp};
+    use crate::test_support::{
+        gen_test_config, get_two_backends_address, record_backend, setup_proxy_with_backend,
+        setup_proxy_with_two_backends, FakeBackendFunc, TestProxy,
+    };
     use matches::assert_matches;
     use std::convert::TryFrom;
+    use std::sync::Arc;
+    use std::time::Duration;
     use tokio;
 
     #[tokio::test]
@@ -442,4 +1527,562 @@ mod tests {
         };
         assert_matches!(err, CommandError::Dropped);
     }
+
+    #[tokio::test]
+    async fn test_cmd_ctx_builder() {
+        let cluster_name = ClusterName::try_from("mycluster").unwrap();
+        let (cmd_ctx, reply_fut) = CmdCtxBuilder::new(&["SET", "{a}1", "v"])
+            .cluster_name(cluster_name.clone())
+            .session_id(7799)
+            .build();
+        assert_eq!(cmd_ctx.get_cluster_name(), &cluster_name);
+        assert_eq!(cmd_ctx.get_session_id(), 7799);
+        assert_eq!(cmd_ctx.get_data_cmd_type(), DataCmdType::SET);
+        assert_eq!(cmd_ctx.get_slot(), Some(generate_slot(b"a")));
+
+        // Should be replied by the one handling it.
+        cmd_ctx.set_resp_result(Ok(Resp::Simple(b"OK".to_vec())));
+        assert_eq!(reply_fut.await.unwrap(), Resp::Simple(b"OK".to_vec()));
+
+        let (cmd_ctx, reply_fut) = CmdCtxBuilder::new(&["GET", "key"]).build();
+        drop(cmd_ctx);
+        assert_matches!(reply_fut.await, Err(CommandError::Dropped));
+    }
+
+    #[test]
+    fn test_format_cmd_for_log() {
+        let (cmd_ctx, _reply_fut) =
+            CmdCtxBuilder::new(&["SET", "key", "secret", "EX", "10"]).build();
+        assert_eq!(
+            format_cmd_for_log(cmd_ctx.get_cmd()),
+            "SET key=key (3 args redacted)"
+        );
+
+        let (cmd_ctx, _reply_fut) = CmdCtxBuilder::new(&["AUTH", "user", "password"]).build();
+        assert_eq!(
+            format_cmd_for_log(cmd_ctx.get_cmd()),
+            "AUTH (2 args redacted)"
+        );
+        let (cmd_ctx, _reply_fut) =
+            CmdCtxBuilder::new(&["HELLO", "3", "AUTH", "user", "password"]).build();
+        assert_eq!(
+            format_cmd_for_log(cmd_ctx.get_cmd()),
+            "HELLO (4 args redacted)"
+        );
+
+        let long_key = "k".repeat(100);
+        let (cmd_ctx, _reply_fut) = CmdCtxBuilder::new(&["GET", &long_key]).build();
+        assert_eq!(
+            format_cmd_for_log(cmd_ctx.get_cmd()),
+            format!("GET key={}...(100 bytes) (0 args redacted)", "k".repeat(64))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_string_range_cmds() {
+        let backend: FakeBackendFunc = Arc::new(|_, args| match args[0].as_str() {
+            "GETRANGE" => Resp::Bulk(BulkStr::Str(b"lue".to_vec())),
+            _ => Resp::Integer(b"3".to_vec()),
+        });
+        let (backend, received) = record_backend(backend);
+        let (_proxy, mut client) = setup_proxy_with_two_backends(backend).await;
+
+        let cmds: [(&[&str], RespVec); 4] = [
+            (
+                &["BITCOUNT", "a", "5", "30", "BIT"],
+                Resp::Integer(b"3".to_vec()),
+            ),
+            (
+                &["BITPOS", "b", "0", "2", "-1", "BYTE"],
+                Resp::Integer(b"3".to_vec()),
+            ),
+            (&["bitcount", "b", "0", "-1"], Resp::Integer(b"3".to_vec())),
+            (
+                &["GETRANGE", "a", "-3", "-1"],
+                Resp::Bulk(BulkStr::Str(b"lue".to_vec())),
+            ),
+        ];
+        for (cmd, expected) in cmds.iter() {
+            let reply = client.send_when_ready(cmd).await;
+            assert_eq!(&reply, expected);
+            // Routed by the key with the range arguments and the unit unchanged.
+            let (address, args) = received.lock().unwrap().pop().unwrap();
+            assert_eq!(address, get_two_backends_address(cmd[1].as_bytes()));
+            assert_eq!(&args, cmd);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zadd_options() {
+        let backend: FakeBackendFunc = Arc::new(|_, args| {
+            if args.iter().any(|arg| arg == "INCR") {
+                Resp::Bulk(BulkStr::Str(b"2.5".to_vec()))
+            } else {
+                Resp::Integer(b"1".to_vec())
+            }
+        });
+        let (backend, received) = record_backend(backend);
+        let (_proxy, mut client) = setup_proxy_with_two_backends(backend).await;
+
+        let cmd = ["ZADD", "a", "XX", "GT", "CH", "INCR", "1.5", "member"];
+        let reply = client.send_when_ready(&cmd).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"2.5".to_vec())));
+        let (address, args) = received.lock().unwrap().pop().unwrap();
+        assert_eq!(address, get_two_backends_address(b"a"));
+        assert_eq!(args, cmd);
+
+        // The options should not be taken as the key.
+        let cmd = ["ZADD", "b", "NX", "1", "a", "2", "XX"];
+        let reply = client.send_when_ready(&cmd).await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+        let (address, args) = received.lock().unwrap().pop().unwrap();
+        assert_eq!(address, get_two_backends_address(b"b"));
+        assert_ne!(address, get_two_backends_address(b"a"));
+        assert_eq!(args, cmd);
+    }
+
+    #[tokio::test]
+    async fn test_set_get_reply_not_coerced() {
+        // Key "a" exists with the value "v1" while "b" does not.
+        let backend: FakeBackendFunc = Arc::new(|_, args| match args[1].as_str() {
+            "a" => Resp::Bulk(BulkStr::Str(b"v1".to_vec())),
+            _ => Resp::Bulk(BulkStr::Nil),
+        });
+        let (backend, received) = record_backend(backend);
+        let (_proxy, mut client) = setup_proxy_with_two_backends(backend).await;
+
+        let cmds: [(&[&str], RespVec); 4] = [
+            (
+                &["SET", "a", "v2", "EX", "10", "GET"],
+                Resp::Bulk(BulkStr::Str(b"v1".to_vec())),
+            ),
+            (&["SET", "b", "v2", "GET"], Resp::Bulk(BulkStr::Nil)),
+            (
+                &["GETSET", "a", "v3"],
+                Resp::Bulk(BulkStr::Str(b"v1".to_vec())),
+            ),
+            (&["GETSET", "b", "v3"], Resp::Bulk(BulkStr::Nil)),
+        ];
+        for (cmd, expected) in cmds.iter() {
+            let reply = client.send_when_ready(cmd).await;
+            assert_eq!(&reply, expected);
+            let (address, args) = received.lock().unwrap().pop().unwrap();
+            assert_eq!(address, get_two_backends_address(cmd[1].as_bytes()));
+            assert_eq!(&args, cmd);
+        }
+    }
+
+    struct SlotChangeHandler {
+        node: Option<String>,
+        owner: Option<String>,
+    }
+
+    impl CmdHandler for SlotChangeHandler {
+        fn handle_cmd(&self, _cmd: Command) -> CmdReplyFuture {
+            unreachable!()
+        }
+        fn handle_slowlog(&self, _request: Box<RespPacket>, _slowlog: Slowlog) {}
+        fn handle_traffic(&self, _: DataCmdType, _: usize, _: usize) {}
+        fn get_slot_node(&self, _slot: usize) -> Option<String> {
+            self.node.clone()
+        }
+        fn get_slot_owner(&self, _slot: usize) -> Option<String> {
+            self.owner.clone()
+        }
+    }
+
+    fn gen_test_cmd(args: &[&str]) -> Command {
+        let resp = Resp::Arr(Array::Arr(
+            args.iter()
+                .map(|arg| Resp::Bulk(BulkStr::Str(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+        Command::new(Box::new(RespPacket::from_resp_vec(resp)))
+    }
+
+    #[test]
+    fn test_moved_on_slot_change() {
+        let handler = SlotChangeHandler {
+            node: Some("127.0.0.1:7001".to_string()),
+            owner: Some("127.0.0.1:6000".to_string()),
+        };
+        let slot_node = Some((233, "127.0.0.1:7000".to_string()));
+        let packet = moved_on_slot_change(&handler, slot_node.clone()).unwrap();
+        assert_eq!(
+            packet.to_resp_vec(),
+            Resp::Error(b"MOVED 233 127.0.0.1:6000".to_vec())
+        );
+        // The node of the slot is not changed.
+        let slot_node = Some((233, "127.0.0.1:7001".to_string()));
+        assert!(moved_on_slot_change(&handler, slot_node).is_none());
+        // The command without key can't be redirected.
+        assert!(moved_on_slot_change(&handler, None).is_none());
+
+        let handler = SlotChangeHandler {
+            node: None,
+            owner: None,
+        };
+        let slot_node = Some((233, "127.0.0.1:7000".to_string()));
+        assert!(moved_on_slot_change(&handler, slot_node).is_none());
+
+        let err = format!(
+            "{} connection closed: Canceled",
+            response::ERR_BACKEND_CONNECTION
+        );
+        let packet = RespPacket::from_resp_vec(Resp::Error(err.into_bytes()));
+        assert!(is_backend_connection_error(&packet));
+        let packet = RespPacket::from_resp_vec(Resp::Error(b"ERR other".to_vec()));
+        assert!(!is_backend_connection_error(&packet));
+    }
+
+    #[test]
+    fn test_only_reads_redirected_on_slot_change() {
+        let handler = SlotChangeHandler {
+            node: Some("127.0.0.1:7000".to_string()),
+            owner: Some("127.0.0.1:6000".to_string()),
+        };
+        let slot = generate_slot(b"a");
+        let get = gen_test_cmd(&["GET", "a"]);
+        let slot_node = get_redirectable_slot_node(&handler, &get);
+        assert_eq!(slot_node, Some((slot, "127.0.0.1:7000".to_string())));
+        // The writes may have been processed before the connection is closed.
+        let incr = gen_test_cmd(&["INCR", "a"]);
+        assert!(get_redirectable_slot_node(&handler, &incr).is_none());
+        let ping = gen_test_cmd(&["PING"]);
+        assert!(get_redirectable_slot_node(&handler, &ping).is_none());
+
+        // The slot is moved to another backend.
+        let handler = SlotChangeHandler {
+            node: Some("127.0.0.1:7001".to_string()),
+            owner: Some("127.0.0.1:6000".to_string()),
+        };
+        let packet = gen_reply_packet(&handler, Err(CommandError::Dropped), slot_node);
+        let expected = format!("MOVED {} 127.0.0.1:6000", slot);
+        assert_eq!(packet.to_resp_vec(), Resp::Error(expected.into_bytes()));
+        let packet = gen_reply_packet(&handler, Err(CommandError::Dropped), None);
+        assert_eq!(
+            packet.to_resp_vec(),
+            Resp::Error(b"Err cmd error CmdErr(Dropped)".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wrongtype_reply_relayed() {
+        let err = b"WRONGTYPE Operation against a key holding the wrong kind of value";
+        // Only the key "a" holds a value of the wrong type.
+        let backend: FakeBackendFunc = Arc::new(move |_, args| match args[1].as_str() {
+            "a" => Resp::Error(err.to_vec()),
+            _ => Resp::Integer(b"1".to_vec()),
+        });
+        let (backend, received) = record_backend(backend);
+        let (_proxy, mut client) = setup_proxy_with_two_backends(backend).await;
+
+        for cmd in &[
+            ["LPUSH", "a", "b", "c"],
+            ["SADD", "a", "b", "c"],
+            ["HSET", "a", "b", "c"],
+            ["LPUSH", "b", "a", "c"],
+            ["SADD", "b", "a", "c"],
+            ["HSET", "b", "a", "c"],
+        ] {
+            let reply = client.send_when_ready(cmd).await;
+            let expected = match cmd[1] {
+                "a" => Resp::Error(err.to_vec()),
+                _ => Resp::Integer(b"1".to_vec()),
+            };
+            assert_eq!(reply, expected);
+            // The values are never taken as the key.
+            let (address, args) = received.lock().unwrap().pop().unwrap();
+            assert_eq!(address, get_two_backends_address(cmd[1].as_bytes()));
+            assert_eq!(&args, cmd);
+        }
+    }
+
+    #[test]
+    fn test_client_info() {
+        let state = SessionState::new(ClusterName::try_from("mycluster").unwrap(), false)
+            .with_client(
+                7,
+                ClientSource {
+                    peer_ip: Some("127.0.0.1".to_string()),
+                    peer_address: Some("127.0.0.1:6000".to_string()),
+                    admin_port: false,
+                },
+            );
+        assert_eq!(
+            state.gen_client_info(),
+            "id=7 addr=127.0.0.1 name= age=0 idle=0 db=0 cmd=NULL"
+        );
+
+        state.set_client_name(Some("myapp".to_string()));
+        state.record_cmd(b"GET");
+        assert_eq!(
+            state.gen_client_info(),
+            "id=7 addr=127.0.0.1 name=myapp age=0 idle=0 db=0 cmd=get"
+        );
+
+        let long_name = vec![b'A'; LAST_CMD_NAME_LENGTH + 1];
+        state.record_cmd(&long_name);
+        let expected = format!("cmd={}", "a".repeat(LAST_CMD_NAME_LENGTH));
+        assert!(state.gen_client_info().ends_with(&expected));
+    }
+
+    #[test]
+    fn test_exceeds_max_args() {
+        assert!(!exceeds_max_args(3, 3));
+        assert!(exceeds_max_args(3, 2));
+        assert!(!exceeds_max_args(3, 0));
+        assert!(!exceeds_max_args(-1, 2));
+    }
+
+    #[tokio::test]
+    async fn test_wait_routed_by_last_write() {
+        let aof_err = "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.";
+        let backend: FakeBackendFunc =
+            Arc::new(move |address, args| match (args[0].as_str(), address) {
+                ("WAIT", _) => Resp::Integer(b"1".to_vec()),
+                ("WAITAOF", "127.0.0.1:6379") => Resp::Arr(Array::Arr(vec![
+                    Resp::Integer(b"1".to_vec()),
+                    Resp::Integer(b"0".to_vec()),
+                ])),
+                ("WAITAOF", _) => Resp::Error(aof_err.as_bytes().to_vec()),
+                _ => Resp::Simple(b"OK".to_vec()),
+            });
+        let (backend, received) = record_backend(backend);
+        let (_proxy, mut client) = setup_proxy_with_two_backends(backend).await;
+
+        let reply = client.send(&["WAIT", "1", "0"]).await;
+        assert_eq!(
+            reply,
+            Resp::Error(response::ERR_NO_WRITE_TO_WAIT.as_bytes().to_vec())
+        );
+        assert!(received.lock().unwrap().is_empty());
+
+        let b_address = get_two_backends_address(b"b");
+        let a_address = get_two_backends_address(b"a");
+        assert_ne!(a_address, b_address);
+
+        client.send_when_ready(&["SET", "b", "v"]).await;
+        let reply = client.send_when_ready(&["WAIT", "1", "100"]).await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+        let reply = client.send_when_ready(&["WAITAOF", "1", "0", "100"]).await;
+        assert_eq!(
+            reply,
+            Resp::Arr(Array::Arr(vec![
+                Resp::Integer(b"1".to_vec()),
+                Resp::Integer(b"0".to_vec()),
+            ]))
+        );
+
+        // Reads don't change the node to wait for.
+        client.send_when_ready(&["GET", "a"]).await;
+        let reply = client.send_when_ready(&["WAIT", "1", "100"]).await;
+        assert_eq!(reply, Resp::Integer(b"1".to_vec()));
+
+        // The error of the backend is passed through.
+        client.send_when_ready(&["SET", "a", "v"]).await;
+        let reply = client.send_when_ready(&["WAITAOF", "1", "0", "100"]).await;
+        assert_eq!(reply, Resp::Error(aof_err.as_bytes().to_vec()));
+
+        let sent: Vec<(String, String)> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(address, args)| (address.clone(), args.join(" ")))
+            .collect();
+        let expected: Vec<(String, String)> = vec![
+            (b_address, "SET b v"),
+            (b_address, "WAIT 1 100"),
+            (b_address, "WAITAOF 1 0 100"),
+            (a_address, "GET a"),
+            (b_address, "WAIT 1 100"),
+            (a_address, "SET a v"),
+            (a_address, "WAITAOF 1 0 100"),
+        ]
+        .into_iter()
+        .map(|(address, cmd)| (address.to_string(), cmd.to_string()))
+        .collect();
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn test_last_write_slot() {
+        let state = SessionState::new(ClusterName::try_from("mycluster").unwrap(), false);
+        assert_eq!(state.get_last_write_slot(), None);
+        state.set_last_write_slot(233);
+        assert_eq!(state.get_last_write_slot(), Some(233));
+    }
+
+    #[test]
+    fn test_default_cluster_name() {
+        let state = SessionState::new(ClusterName::try_from("mycluster").unwrap(), false);
+        state.set_default_cluster_name(ClusterName::try_from("cluster_a").unwrap());
+        assert_eq!(state.get_cluster_name().to_string(), "cluster_a");
+
+        state.set_cluster_name(ClusterName::try_from("cluster_b").unwrap());
+        state.set_default_cluster_name(ClusterName::try_from("cluster_c").unwrap());
+        assert_eq!(state.get_cluster_name().to_string(), "cluster_b");
+    }
+
+    #[tokio::test]
+    async fn test_command_timeout() {
+        let timeout = Some(Duration::from_millis(10));
+        let cmd = Command::new(Box::new(RespPacket::Data(Resp::Arr(Array::Arr(vec![
+            Resp::Bulk(BulkStr::Str(b"GET".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"key".to_vec())),
+        ])))));
+        let (_reply_sender, reply_receiver) = new_command_pair(&cmd);
+        let fut = with_command_timeout(
+            future::Either::Left(reply_receiver),
+            has_own_timeout(&cmd),
+            timeout,
+        );
+        assert!(matches!(fut.await, Err(CommandError::Timeout)));
+
+        let cmd = Command::new(Box::new(RespPacket::Data(Resp::Arr(Array::Arr(vec![
+            Resp::Bulk(BulkStr::Str(b"BZPOPMIN".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"key".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"0".to_vec())),
+        ])))));
+        let (_reply_sender, reply_receiver) = new_command_pair(&cmd);
+        let fut = with_command_timeout(
+            future::Either::Left(reply_receiver),
+            has_own_timeout(&cmd),
+            timeout,
+        );
+        assert!(matches!(fut, future::Either::Left(_)));
+    }
+
+    #[test]
+    fn test_reset() {
+        let state = SessionState::new(ClusterName::try_from("mycluster").unwrap(), false);
+        state.set_cluster_name(ClusterName::try_from("cluster_a").unwrap());
+        state.set_client_name(Some("myapp".to_string()));
+        state.set_no_evict(true);
+        state.set_last_write_slot(233);
+        state.set_subscribe_mode(true);
+
+        state.reset();
+        assert!(!state.is_subscribe_mode());
+        assert!(!state.is_no_evict());
+        assert_eq!(state.get_client_name(), None);
+        assert_eq!(state.get_last_write_slot(), None);
+        assert_eq!(state.get_cluster_name().to_string(), "cluster_a");
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_notice() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let (proxy, mut client) = setup_proxy_with_backend(backend).await;
+        let reply = client.send_when_ready(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let reply = client
+            .send(&["CONFIG", "SET", "maintenance_message", "ERR a\r\nb"])
+            .await;
+        matches::assert_matches!(reply, Resp::Error(_));
+        let reply = client
+            .send(&["CONFIG", "SET", "maintenance_message", "ERR migrating"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client
+            .send(&["CONFIG", "SET", "maintenance_mode", "true"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        // The existing connections are not affected.
+        let reply = client.send(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let mut new_client = proxy.connect();
+        let reply = new_client.send(&["PING"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = new_client.send(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Error(b"ERR migrating".to_vec()));
+        let reply = new_client.send(&["SET", "a", "v"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_single_command_not_batched() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let mut config = gen_test_config();
+        config.session_batch_min_time = 10_000_000_000;
+        config.session_batch_max_time = 10_000_000_000;
+        let proxy = TestProxy::with_config(config, backend);
+        let mut client = proxy.connect();
+
+        // Would wait for the 10 seconds batch timer without the fast path.
+        let reply = tokio::time::timeout(Duration::from_secs(5), client.send(&["PING"]))
+            .await
+            .expect("single command is batched");
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::new(backend);
+        let mut client = proxy.connect();
+        let replies = client
+            .send_pipeline(&[&["PING"], &["ECHO", "hello"], &["PING"]])
+            .await;
+        assert_eq!(
+            replies,
+            vec![
+                Resp::Simple(b"OK".to_vec()),
+                Resp::Bulk(BulkStr::Str(b"hello".to_vec())),
+                Resp::Simple(b"OK".to_vec()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_request_on_redis_port() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let proxy = TestProxy::new(backend);
+
+        let mut client = proxy.connect();
+        let reply = client
+            .send_raw(b"GET / HTTP/1.1\r\nHost: localhost:5299\r\n\r\n")
+            .await;
+        assert_eq!(
+            reply,
+            Some(Resp::Error(
+                crate::common::response::ERR_HTTP_REQUEST
+                    .as_bytes()
+                    .to_vec()
+            ))
+        );
+        // The session is closed after the error reply.
+        assert_eq!(client.send_raw(b"").await, None);
+
+        // Other invalid data is still dropped silently.
+        let mut client = proxy.connect();
+        assert_eq!(client.send_raw(b"invalid\r\n").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_too_many_arguments_by_array_header() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let config = gen_test_config();
+        config.set_max_command_args(2);
+        let proxy = TestProxy::with_config(config, backend);
+
+        let mut client = proxy.connect();
+        let reply = client.send(&["ECHO", "hello"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(b"hello".to_vec())));
+
+        // Rejected before the arguments arrive.
+        let reply = client.send_raw(b"*1000000\r\n$4\r\nECHO\r\n").await;
+        assert_eq!(
+            reply,
+            Some(Resp::Error(
+                crate::common::response::ERR_TOO_MANY_ARGUMENTS
+                    .as_bytes()
+                    .to_vec()
+            ))
+        );
+        assert_eq!(client.send_raw(b"").await, None);
+    }
 }
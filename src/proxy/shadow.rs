@@ -0,0 +1,110 @@
+use super::backend::{CmdTask, CmdTaskFactory, ConnFactory};
+use super::reply::ReplyCommitHandlerFactory;
+use super::sender::{
+    gen_sender_factory, BackendSenderFactory, CmdTaskSender, CmdTaskSenderFactory,
+};
+use super::service::ServerProxyConfig;
+use super::session::{CmdCtx, CmdCtxFactory};
+use crate::common::track::TrackedFutureRegistry;
+use crate::protocol::RespPacket;
+use arc_swap::ArcSwapOption;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+type ShadowSenderFactory<C> = BackendSenderFactory<ReplyCommitHandlerFactory, C>;
+type ShadowSender<C> = <ShadowSenderFactory<C> as CmdTaskSenderFactory>::Sender;
+
+const SHADOW_PERCENTAGE_BASE: u64 = 100;
+
+// Mirror part of the commands to a shadow backend for testing
+// a new Redis version. The replies from the shadow backend are discarded
+// and the client always gets the reply from the primary backend.
+pub struct ShadowMirror<C: ConnFactory<Pkt = RespPacket>> {
+    config: Arc<ServerProxyConfig>,
+    sender_factory: ShadowSenderFactory<C>,
+    // The backend connections need to be created inside the tokio runtime,
+    // so the sender is lazily initialized.
+    sender: ArcSwapOption<ShadowSender<C>>,
+    lock: Mutex<()>,
+    counter: AtomicU64,
+}
+
+impl<C: ConnFactory<Pkt = RespPacket>> ShadowMirror<C> {
+    pub fn new(
+        config: Arc<ServerProxyConfig>,
+        conn_factory: Arc<C>,
+        future_registry: Arc<TrackedFutureRegistry>,
+    ) -> Self {
+        let sender_factory = gen_sender_factory(
+            config.clone(),
+            Arc::new(ReplyCommitHandlerFactory::default()),
+            conn_factory,
+            future_registry,
+        );
+        Self {
+            config,
+            sender_factory,
+            sender: ArcSwapOption::empty(),
+            lock: Mutex::new(()),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    pub fn try_mirror(&self, cmd_ctx: &CmdCtx) {
+        let address = match self.config.shadow_address.as_ref() {
+            Some(address) => address,
+            None => return,
+        };
+        if !self.config.shadow_write_enabled && !cmd_ctx.get_cmd().is_read_only() {
+            return;
+        }
+        if !self.should_sample() {
+            return;
+        }
+
+        let resp = cmd_ctx.get_cmd().get_packet().into_resp_vec();
+        let (shadow_cmd_ctx, reply_fut) =
+            CmdCtxFactory::default().create_with_ctx(cmd_ctx.get_context(), resp);
+
+        let sender = self.get_sender(address);
+        if let Err(err) = sender.send(shadow_cmd_ctx) {
+            debug!("failed to send command to shadow backend: {:?}", err);
+            return;
+        }
+        tokio::spawn(async move {
+            if let Err(err) = reply_fut.await {
+                debug!("shadow backend failed to handle command: {:?}", err);
+            }
+        });
+    }
+
+    fn should_sample(&self) -> bool {
+        let percentage = self.config.get_shadow_percentage();
+        if percentage == 0 {
+            return false;
+        }
+        let count = self.counter.fetch_add(1, Ordering::Relaxed) % SHADOW_PERCENTAGE_BASE;
+        count < percentage
+    }
+
+    // The connection is recreated by the next mirrored command.
+    pub fn clear_sender(&self) {
+        let _guard = self.lock.lock().expect("ShadowMirror::clear_sender");
+        self.sender.store(None);
+        self.sender_factory.clear();
+    }
+
+    fn get_sender(&self, address: &str) -> Arc<ShadowSender<C>> {
+        if let Some(sender) = self.sender.load() {
+            return sender;
+        }
+
+        let _guard = self.lock.lock().expect("ShadowMirror::get_sender");
+        if let Some(sender) = self.sender.load() {
+            return sender;
+        }
+        let sender = Arc::new(self.sender_factory.create(address.to_string()));
+        self.sender.store(Some(sender.clone()));
+        sender
+    }
+}
@@ -1,6 +1,8 @@
 use crate::common::cluster::SlotRange;
 use crate::common::utils::SLOT_NUM;
 use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
 
 pub struct SlotMap {
     data: SlotMapData,
@@ -26,6 +28,10 @@ impl SlotMap {
     pub fn get(&self, slot: usize) -> Option<&str> {
         self.data.get(slot)
     }
+
+    pub fn get_first_slots(&self) -> Vec<(&str, usize)> {
+        self.data.get_first_slots()
+    }
 }
 
 pub struct SlotMapData {
@@ -64,6 +70,66 @@ impl SlotMapData {
         let addr_index = self.slot_arr.get(slot).and_then(|opt| *opt)?;
         self.addrs.get(addr_index).map(|s| s.as_str())
     }
+
+    // Returns each address with the lowest slot it owns,
+    // which could be used to route a command to that address.
+    pub fn get_first_slots(&self) -> Vec<(&str, usize)> {
+        let mut first_slots: Vec<Option<usize>> = vec![None; self.addrs.len()];
+        for (slot, addr_index) in self.slot_arr.iter().enumerate() {
+            if let Some(first_slot) = addr_index.and_then(|i| first_slots.get_mut(i)) {
+                first_slot.get_or_insert(slot);
+            }
+        }
+        let mut slots: Vec<(&str, usize)> = first_slots
+            .into_iter()
+            .enumerate()
+            .filter_map(|(addr_index, slot)| Some((self.addrs.get(addr_index)?.as_str(), slot?)))
+            .collect();
+        slots.sort_unstable();
+        slots
+    }
+}
+
+// Publishes the version of the slot ownership so that the subsystems
+// caching the routing could rebuild it on change instead of polling.
+// The version starts from 0 and is only meaningful within this process.
+pub struct SlotChangeNotifier {
+    sender: Mutex<watch::Sender<u64>>,
+    // Keeps the channel open even without any subscriber.
+    receiver: watch::Receiver<u64>,
+}
+
+impl Default for SlotChangeNotifier {
+    fn default() -> Self {
+        let (sender, receiver) = watch::channel(0);
+        Self {
+            sender: Mutex::new(sender),
+            receiver,
+        }
+    }
+}
+
+impl SlotChangeNotifier {
+    // Returns the new version.
+    pub fn notify(&self) -> u64 {
+        let sender = self.sender.lock().expect("SlotChangeNotifier::notify");
+        let version = *self.receiver.borrow() + 1;
+        if sender.broadcast(version).is_err() {
+            error!("failed to broadcast slot version {}", version);
+        }
+        version
+    }
+
+    pub fn get_version(&self) -> u64 {
+        *self.receiver.borrow()
+    }
+
+    // The returned receiver yields the current version first
+    // and then the latest version after each change.
+    // Intermediate versions could be skipped.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.receiver.clone()
+    }
 }
 
 #[cfg(test)]
@@ -90,4 +156,36 @@ mod tests {
             assert_eq!(node, backend);
         }
     }
+
+    #[test]
+    fn test_first_slots() {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), vec![(300, 399), (100, 199)]);
+        map.insert("b".to_string(), vec![(200, 299), (1000, SLOT_NUM + 100)]);
+        let slot_map = SlotMapData::new(map);
+        assert_eq!(slot_map.get(150), Some("a"));
+        assert_eq!(slot_map.get(SLOT_NUM - 1), Some("b"));
+        assert_eq!(slot_map.get(SLOT_NUM), None);
+        assert_eq!(slot_map.get_first_slots(), vec![("a", 100), ("b", 200)]);
+
+        assert!(SlotMapData::new(HashMap::new())
+            .get_first_slots()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_slot_change_notifier() {
+        let notifier = SlotChangeNotifier::default();
+        let mut receiver = notifier.subscribe();
+        assert_eq!(receiver.recv().await, Some(0));
+
+        assert_eq!(notifier.notify(), 1);
+        assert_eq!(notifier.notify(), 2);
+        assert_eq!(notifier.get_version(), 2);
+        // Only the latest version is observed.
+        assert_eq!(receiver.recv().await, Some(2));
+
+        let mut late_receiver = notifier.subscribe();
+        assert_eq!(late_receiver.recv().await, Some(2));
+    }
 }
@@ -1,14 +1,20 @@
+use super::latency::LatencyMonitor;
 use super::service::ServerProxyConfig;
 use crate::protocol::{Array, BulkStr, Resp, RespPacket, RespVec};
-use arc_swap::ArcSwapOption;
 use chrono::{naive, DateTime, Utc};
+use futures_timer::Delay;
 use std::cmp::max;
+use std::fs;
+use std::io::{self, Write};
 use std::str;
 use std::sync::atomic;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 // try letting the element and postfix fit into 128 bytes.
 const MAX_ELEMENT_LENGTH: usize = 100;
+const TRIM_INTERVAL: Duration = Duration::from_secs(1);
+const NANOS_PER_SEC: i64 = 1_000_000_000;
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
@@ -103,6 +109,16 @@ impl Slowlog {
     pub fn is_enabled(&self) -> bool {
         self.enabled
     }
+
+    // Returns the nanoseconds between the two events if both of them are logged.
+    pub fn get_duration(&self, start: TaskEvent, end: TaskEvent) -> Option<i64> {
+        let start_time = self.event_map.get_event_time(start);
+        let end_time = self.event_map.get_event_time(end);
+        if start_time == 0 || end_time == 0 {
+            return None;
+        }
+        Some(end_time - start_time)
+    }
 }
 
 impl SlowlogRecord {
@@ -160,32 +176,70 @@ impl SlowlogRecord {
                     .collect(),
                 others => vec![format!("{:?}", others)],
             },
+            RespPacket::Encoded(data) => vec![limit_len(data_to_string(data.as_ref()))],
+        }
+    }
+}
+
+// Adding, flushing and trimming the logs are done under the same lock
+// so that none of them could see a slot being overwritten.
+struct SlowlogRing {
+    slowlogs: Vec<Option<Arc<SlowlogRecord>>>,
+    curr_index: usize,
+    // All the logs before this index have been flushed to the file.
+    flushed_index: usize,
+}
+
+impl SlowlogRing {
+    fn new(len: usize) -> Self {
+        Self {
+            slowlogs: (0..len).map(|_| None).collect(),
+            curr_index: 0,
+            flushed_index: 0,
         }
     }
+
+    fn add(&mut self, log: SlowlogRecord) {
+        let len = self.slowlogs.len();
+        if let Some(log_slot) = self.slowlogs.get_mut(self.curr_index % len) {
+            *log_slot = Some(Arc::new(log));
+        }
+        self.curr_index += 1;
+    }
+
+    // Returns the logs added since the last call.
+    // The logs overwritten in the ring buffer are lost.
+    fn take_unflushed(&mut self) -> Vec<Arc<SlowlogRecord>> {
+        let len = self.slowlogs.len();
+        let start = max(self.flushed_index, self.curr_index.saturating_sub(len));
+        self.flushed_index = self.curr_index;
+        (start..self.curr_index)
+            .filter_map(|i| self.slowlogs.get(i % len))
+            .filter_map(|log| log.clone())
+            .collect()
+    }
 }
 
 pub struct SlowRequestLogger {
-    slowlogs: Vec<ArcSwapOption<SlowlogRecord>>,
-    curr_index: atomic::AtomicUsize,
+    ring: Mutex<SlowlogRing>,
     rate_limiter: SlowLogRateLimiter,
+    latency_monitor: LatencyMonitor,
     config: Arc<ServerProxyConfig>,
 }
 
 impl SlowRequestLogger {
     pub fn new(config: Arc<ServerProxyConfig>) -> Self {
-        let mut slowlogs = Vec::new();
-        while slowlogs.len() != config.slowlog_len.get() {
-            slowlogs.push(ArcSwapOption::new(None));
-        }
         Self {
-            slowlogs,
-            curr_index: atomic::AtomicUsize::new(0),
+            ring: Mutex::new(SlowlogRing::new(config.slowlog_len.get())),
             rate_limiter: SlowLogRateLimiter::default(),
+            latency_monitor: LatencyMonitor::default(),
             config,
         }
     }
 
     pub fn add_slow_log(&self, request: Box<RespPacket>, log: Slowlog) {
+        self.latency_monitor
+            .record(&log, self.config.get_latency_monitor_threshold());
         let dt = log.event_map.get_used_time(TaskEvent::WaitDone);
         let threshold = self.config.get_slowlog_log_slower_than();
         // ms to ns
@@ -196,31 +250,164 @@ impl SlowRequestLogger {
 
     pub fn add(&self, request: Box<RespPacket>, log: Slowlog) {
         let log = SlowlogRecord::from_slow_log(request, log);
-        let index = self.curr_index.fetch_add(1, atomic::Ordering::SeqCst) % self.slowlogs.len();
-        if let Some(log_slot) = self.slowlogs.get(index) {
-            log_slot.store(Some(Arc::new(log)))
-        }
+        self.ring.lock().expect("SlowRequestLogger::add").add(log);
     }
 
     pub fn get(&self, limit: Option<usize>) -> Vec<Arc<SlowlogRecord>> {
-        let num = limit.unwrap_or_else(|| self.slowlogs.len());
-        self.slowlogs
+        let ring = self.ring.lock().expect("SlowRequestLogger::get");
+        let num = limit.unwrap_or_else(|| ring.slowlogs.len());
+        ring.slowlogs
             .iter()
-            .filter_map(arc_swap::ArcSwapAny::load)
+            .filter_map(|log| log.clone())
             .take(num)
             .collect()
     }
 
     pub fn reset(&self) {
-        for log_slot in self.slowlogs.iter() {
-            log_slot.store(None)
+        let mut ring = self.ring.lock().expect("SlowRequestLogger::reset");
+        for log_slot in ring.slowlogs.iter_mut() {
+            *log_slot = None;
+        }
+    }
+
+    // Removes the logs created before `deadline` in nanoseconds since the epoch.
+    pub fn trim(&self, deadline: i64) {
+        let mut ring = self.ring.lock().expect("SlowRequestLogger::trim");
+        for log_slot in ring.slowlogs.iter_mut() {
+            let expired = match log_slot.as_ref() {
+                Some(log) => log.event_map.get_event_time(TaskEvent::Created) < deadline,
+                None => false,
+            };
+            if expired {
+                *log_slot = None;
+            }
         }
     }
 
+    pub fn get_latency_monitor(&self) -> &LatencyMonitor {
+        &self.latency_monitor
+    }
+
     // Returns whether this current log should be enabled.
     pub fn limit_rate(&self, slowlog_sample_rate: u64) -> bool {
         self.rate_limiter.check_current_enabled(slowlog_sample_rate)
     }
+
+    fn take_unflushed(&self) -> Vec<Arc<SlowlogRecord>> {
+        self.ring
+            .lock()
+            .expect("SlowRequestLogger::take_unflushed")
+            .take_unflushed()
+    }
+
+    pub async fn flush_periodically(self: Arc<Self>) {
+        let path = match self.config.slowlog_flush_file.as_ref() {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let interval = match self.config.slowlog_flush_interval {
+            0 => return,
+            interval => Duration::from_secs(interval),
+        };
+        info!("start flushing slowlog to {}", path);
+
+        loop {
+            Delay::new(interval).await;
+            let logs = self.take_unflushed();
+            if logs.is_empty() {
+                continue;
+            }
+            let path = path.clone();
+            let max_size = self.config.slowlog_flush_file_max_size;
+            let res =
+                tokio::task::spawn_blocking(move || flush_slowlogs_to_file(&path, max_size, logs))
+                    .await;
+            match res {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => error!("failed to flush slowlog: {:?}", err),
+                Err(err) => error!("failed to run slowlog flushing: {:?}", err),
+            }
+        }
+    }
+
+    pub async fn trim_periodically(self: Arc<Self>) {
+        loop {
+            Delay::new(TRIM_INTERVAL).await;
+            let window = self.config.get_slowlog_latency_window();
+            if window == 0 {
+                continue;
+            }
+            let deadline = Utc::now().timestamp() - window as i64;
+            self.trim(deadline * NANOS_PER_SEC);
+            self.latency_monitor.trim(deadline);
+        }
+    }
+}
+
+fn flush_slowlogs_to_file(
+    path: &str,
+    max_size: u64,
+    logs: Vec<Arc<SlowlogRecord>>,
+) -> Result<(), io::Error> {
+    if max_size != 0 {
+        match fs::metadata(path) {
+            // Only keep one rotated file.
+            Ok(metadata) if metadata.len() >= max_size => {
+                fs::rename(path, format!("{}.1", path))?;
+            }
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    let mut content = Vec::new();
+    for log in logs.iter() {
+        let line = serde_json::to_vec(&SlowlogJsonRecord::from_record(log))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        content.extend(line);
+        content.push(b'\n');
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(&content)?;
+    file.flush()
+}
+
+// All the time fields are in nanoseconds since the request was created.
+#[derive(Serialize)]
+struct SlowlogJsonRecord<'a> {
+    session_id: usize,
+    created: String,
+    sent_to_migration_backend: i64,
+    sent_to_cluster: i64,
+    sent_to_queue: i64,
+    queue_received: i64,
+    sent_to_backend: i64,
+    received_from_backend: i64,
+    wait_done: i64,
+    command: &'a [String],
+}
+
+impl<'a> SlowlogJsonRecord<'a> {
+    fn from_record(log: &'a SlowlogRecord) -> Self {
+        let event_map = &log.event_map;
+        Self {
+            session_id: log.session_id,
+            created: get_created_date(log),
+            sent_to_migration_backend: event_map.get_used_time(TaskEvent::SentToMigrationBackend),
+            sent_to_cluster: event_map.get_used_time(TaskEvent::SentToCluster),
+            sent_to_queue: event_map.get_used_time(TaskEvent::SentToWritingQueue),
+            queue_received: event_map.get_used_time(TaskEvent::WritingQueueReceived),
+            sent_to_backend: event_map.get_used_time(TaskEvent::SentToBackend),
+            received_from_backend: event_map.get_used_time(TaskEvent::ReceivedFromBackend),
+            wait_done: event_map.get_used_time(TaskEvent::WaitDone),
+            command: &log.command,
+        }
+    }
 }
 
 pub fn slowlogs_to_resp(logs: Vec<Arc<SlowlogRecord>>) -> RespVec {
@@ -231,9 +418,9 @@ pub fn slowlogs_to_resp(logs: Vec<Arc<SlowlogRecord>>) -> RespVec {
     Resp::Arr(Array::Arr(elements))
 }
 
-fn slowlog_to_report(log: &SlowlogRecord) -> RespVec {
+fn get_created_date(log: &SlowlogRecord) -> String {
     let start = log.event_map.get_event_time(TaskEvent::Created);
-    let start_date = match naive::NaiveDateTime::from_timestamp_opt(
+    match naive::NaiveDateTime::from_timestamp_opt(
         start / 1_000_000_000,
         (start % 1_000_000_000) as u32,
     ) {
@@ -242,7 +429,11 @@ fn slowlog_to_report(log: &SlowlogRecord) -> RespVec {
             datetime.to_rfc3339()
         }
         None => start.to_string(),
-    };
+    }
+}
+
+fn slowlog_to_report(log: &SlowlogRecord) -> RespVec {
+    let start_date = get_created_date(log);
     let elements = vec![
         format!("session_id: {}", log.session_id),
         format!("created: {}", start_date),
@@ -305,3 +496,112 @@ impl SlowLogRateLimiter {
         count == 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_record(session_id: usize) -> SlowlogRecord {
+        let request = RespPacket::Data(Resp::Arr(Array::Arr(vec![
+            Resp::Bulk(BulkStr::Str(b"GET".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"key".to_vec())),
+        ])));
+        let mut slowlog = Slowlog::new(session_id, true);
+        slowlog.log_event(TaskEvent::Created);
+        SlowlogRecord::from_slow_log(Box::new(request), slowlog)
+    }
+
+    fn session_ids(logs: &[Arc<SlowlogRecord>]) -> Vec<usize> {
+        logs.iter().map(|log| log.session_id).collect()
+    }
+
+    #[test]
+    fn test_take_unflushed() {
+        let mut ring = SlowlogRing::new(3);
+        assert!(ring.take_unflushed().is_empty());
+
+        ring.add(gen_record(1));
+        ring.add(gen_record(2));
+        assert_eq!(session_ids(&ring.take_unflushed()), vec![1, 2]);
+        assert!(ring.take_unflushed().is_empty());
+
+        // Log 3 is overwritten by log 6 before flushing.
+        for i in 3..=6 {
+            ring.add(gen_record(i));
+        }
+        assert_eq!(session_ids(&ring.take_unflushed()), vec![4, 5, 6]);
+        assert!(ring.take_unflushed().is_empty());
+    }
+
+    #[test]
+    fn test_reset() {
+        let config = Arc::new(crate::test_support::gen_test_config());
+        let logger = SlowRequestLogger::new(config);
+        logger.add(
+            Box::new(RespPacket::Data(Resp::Simple(b"PING".to_vec()))),
+            Slowlog::new(1, true),
+        );
+        assert_eq!(logger.get(None).len(), 1);
+        logger.reset();
+        assert!(logger.get(None).is_empty());
+    }
+
+    #[test]
+    fn test_trim() {
+        let config = Arc::new(crate::test_support::gen_test_config());
+        let logger = SlowRequestLogger::new(config);
+        let request = || Box::new(RespPacket::Data(Resp::Simple(b"PING".to_vec())));
+        for session_id in 1..=2 {
+            let mut slowlog = Slowlog::new(session_id, true);
+            slowlog.log_event(TaskEvent::Created);
+            logger.add(request(), slowlog);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let logs = logger.get(None);
+        assert_eq!(session_ids(&logs), vec![1, 2]);
+
+        logger.trim(0);
+        assert_eq!(logger.get(None).len(), 2);
+
+        let deadline = logs[1].event_map.get_event_time(TaskEvent::Created);
+        logger.trim(deadline);
+        assert_eq!(session_ids(&logger.get(None)), vec![2]);
+
+        logger.trim(deadline + 1);
+        assert!(logger.get(None).is_empty());
+    }
+
+    #[test]
+    fn test_flush_slowlogs_to_file() {
+        let path = std::env::temp_dir().join(format!("undermoon-slowlog-{}", std::process::id()));
+        let path = path
+            .to_str()
+            .expect("test_flush_slowlogs_to_file")
+            .to_string();
+        let rotated_path = format!("{}.1", path);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated_path);
+
+        let logs = vec![Arc::new(gen_record(1)), Arc::new(gen_record(2))];
+        flush_slowlogs_to_file(&path, 1, logs).expect("test_flush_slowlogs_to_file");
+        let content = fs::read_to_string(&path).expect("test_flush_slowlogs_to_file");
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("test_flush_slowlogs_to_file"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["session_id"], 1);
+        assert_eq!(lines[1]["command"], serde_json::json!(["GET", "key"]));
+
+        // The file exceeding the max size is rotated before appending.
+        let logs = vec![Arc::new(gen_record(3))];
+        flush_slowlogs_to_file(&path, 1, logs).expect("test_flush_slowlogs_to_file");
+        let rotated = fs::read_to_string(&rotated_path).expect("test_flush_slowlogs_to_file");
+        assert_eq!(rotated, content);
+        let content = fs::read_to_string(&path).expect("test_flush_slowlogs_to_file");
+        assert_eq!(content.lines().count(), 1);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated_path);
+    }
+}
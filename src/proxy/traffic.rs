@@ -0,0 +1,451 @@
+use super::command::DataCmdType;
+use super::session::SessionState;
+use crate::common::cluster::ClusterName;
+use dashmap::DashMap;
+use futures_timer::Delay;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct CmdTraffic {
+    request_bytes: AtomicU64,
+    reply_bytes: AtomicU64,
+}
+
+const WINDOW_MASK: u64 = 0xffff_ffff;
+
+#[derive(Default)]
+struct DbTraffic {
+    request_bytes: AtomicU64,
+    reply_bytes: AtomicU64,
+    // The second since the epoch in the high 32 bits and the request and reply bytes
+    // within that second in the low 32 bits, so that they are updated together.
+    window: AtomicU64,
+}
+
+pub struct DbTrafficStats {
+    pub cluster_name: ClusterName,
+    pub request_bytes: u64,
+    pub reply_bytes: u64,
+    pub bytes_per_sec: u64,
+}
+
+// Accumulates the request and reply bytes of each command type.
+// The sizes are the ones already calculated for the memory budget
+// so they are estimations without the protocol overhead.
+// Returns the seconds since the epoch.
+pub type TrafficClock = Box<dyn Fn() -> u64 + Send + Sync>;
+
+pub struct TrafficStats {
+    clock: TrafficClock,
+    cmds: DashMap<DataCmdType, CmdTraffic>,
+    dbs: DashMap<ClusterName, DbTraffic>,
+    sessions: AtomicUsize,
+    // session id => session state, for CLIENT LIST.
+    clients: DashMap<usize, Weak<SessionState>>,
+    // Commands received but with their replies not sent yet.
+    in_flight_cmds: AtomicUsize,
+}
+
+pub struct InFlightGuard {
+    stats: Arc<TrafficStats>,
+    cmd_num: usize,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.stats
+            .in_flight_cmds
+            .fetch_sub(self.cmd_num, Ordering::Relaxed);
+    }
+}
+
+impl Default for TrafficStats {
+    fn default() -> Self {
+        Self::with_clock(Box::new(|| coarsetime::Clock::now_since_epoch().as_secs()))
+    }
+}
+
+impl TrafficStats {
+    pub fn with_clock(clock: TrafficClock) -> Self {
+        Self {
+            clock,
+            cmds: DashMap::new(),
+            dbs: DashMap::new(),
+            sessions: AtomicUsize::new(0),
+            clients: DashMap::new(),
+            in_flight_cmds: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn record(&self, data_cmd_type: DataCmdType, request_size: usize, reply_size: usize) {
+        if let Some(traffic) = self.cmds.get(&data_cmd_type) {
+            Self::add(&traffic, request_size, reply_size);
+            return;
+        }
+        let traffic = self
+            .cmds
+            .entry(data_cmd_type)
+            .or_insert_with(CmdTraffic::default);
+        Self::add(&traffic, request_size, reply_size);
+    }
+
+    fn add(traffic: &CmdTraffic, request_size: usize, reply_size: usize) {
+        traffic
+            .request_bytes
+            .fetch_add(request_size as u64, Ordering::Relaxed);
+        traffic
+            .reply_bytes
+            .fetch_add(reply_size as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_db(&self, cluster_name: &ClusterName, request_size: usize, reply_size: usize) {
+        let now = (self.clock)();
+        if let Some(traffic) = self.dbs.get(cluster_name) {
+            Self::add_db(&traffic, now, request_size, reply_size);
+            return;
+        }
+        let traffic = self
+            .dbs
+            .entry(cluster_name.clone())
+            .or_insert_with(DbTraffic::default);
+        Self::add_db(&traffic, now, request_size, reply_size);
+    }
+
+    fn add_db(traffic: &DbTraffic, now: u64, request_size: usize, reply_size: usize) {
+        traffic
+            .request_bytes
+            .fetch_add(request_size as u64, Ordering::Relaxed);
+        traffic
+            .reply_bytes
+            .fetch_add(reply_size as u64, Ordering::Relaxed);
+        let size = (request_size + reply_size) as u64;
+        // Only the one winning the CAS resets the window of a new second
+        // and the others retry to add on top of it.
+        let mut window = traffic.window.load(Ordering::Relaxed);
+        loop {
+            let bytes = if window >> 32 == now & WINDOW_MASK {
+                (window & WINDOW_MASK).saturating_add(size).min(WINDOW_MASK)
+            } else {
+                size.min(WINDOW_MASK)
+            };
+            let new_window = ((now & WINDOW_MASK) << 32) | bytes;
+            match traffic.window.compare_exchange_weak(
+                window,
+                new_window,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(current) => window = current,
+            }
+        }
+    }
+
+    // The request and reply bytes of the database within the current second.
+    pub fn get_db_byte_rate(&self, cluster_name: &ClusterName) -> u64 {
+        let now = (self.clock)();
+        self.dbs
+            .get(cluster_name)
+            .map(|traffic| Self::get_window_bytes(&traffic, now))
+            .unwrap_or(0)
+    }
+
+    fn get_window_bytes(traffic: &DbTraffic, now: u64) -> u64 {
+        let window = traffic.window.load(Ordering::Relaxed);
+        if window >> 32 != now & WINDOW_MASK {
+            return 0;
+        }
+        window & WINDOW_MASK
+    }
+
+    // Sorted by the database name.
+    pub fn get_db_stats(&self) -> Vec<DbTrafficStats> {
+        let now = (self.clock)();
+        let mut stats: Vec<_> = self
+            .dbs
+            .iter()
+            .map(|item| {
+                let traffic = item.value();
+                DbTrafficStats {
+                    cluster_name: item.key().clone(),
+                    request_bytes: traffic.request_bytes.load(Ordering::Relaxed),
+                    reply_bytes: traffic.reply_bytes.load(Ordering::Relaxed),
+                    bytes_per_sec: Self::get_window_bytes(traffic, now),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.cluster_name.as_str().cmp(b.cluster_name.as_str()));
+        stats
+    }
+
+    // Returns (command type, request bytes, reply bytes).
+    pub fn get_stats(&self) -> Vec<(DataCmdType, u64, u64)> {
+        let mut stats: Vec<_> = self
+            .cmds
+            .iter()
+            .map(|item| {
+                let traffic = item.value();
+                (
+                    *item.key(),
+                    traffic.request_bytes.load(Ordering::Relaxed),
+                    traffic.reply_bytes.load(Ordering::Relaxed),
+                )
+            })
+            .collect();
+        stats.sort_by_key(|(data_cmd_type, _, _)| format!("{:?}", data_cmd_type));
+        stats
+    }
+
+    pub fn session_opened(&self) {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_closed(&self) {
+        self.sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get_sessions(&self) -> usize {
+        self.sessions.load(Ordering::Relaxed)
+    }
+
+    pub fn register_client(&self, session_state: &Arc<SessionState>) {
+        self.clients.insert(
+            session_state.get_session_id(),
+            Arc::downgrade(session_state),
+        );
+    }
+
+    pub fn unregister_client(&self, session_id: usize) {
+        self.clients.remove(&session_id);
+    }
+
+    // Sorted by the session id.
+    pub fn get_clients(&self) -> Vec<Arc<SessionState>> {
+        let mut clients: Vec<_> = self
+            .clients
+            .iter()
+            .filter_map(|item| item.value().upgrade())
+            .collect();
+        clients.sort_by_key(|state| state.get_session_id());
+        clients
+    }
+
+    pub fn track_in_flight(self: &Arc<Self>, cmd_num: usize) -> InFlightGuard {
+        self.in_flight_cmds.fetch_add(cmd_num, Ordering::Relaxed);
+        InFlightGuard {
+            stats: self.clone(),
+            cmd_num,
+        }
+    }
+
+    pub fn get_in_flight_cmds(&self) -> usize {
+        self.in_flight_cmds.load(Ordering::Relaxed)
+    }
+
+    // Waits for all the sessions to be closed after the proxy stops accepting connections,
+    // logging the progress every `interval`.
+    // Returns false if it times out and the remaining sessions need to be killed.
+    pub async fn wait_for_draining(&self, timeout: Duration, interval: Duration) -> bool {
+        let start = Instant::now();
+        loop {
+            let sessions = self.get_sessions();
+            let in_flight_cmds = self.get_in_flight_cmds();
+            let elapsed = start.elapsed();
+            if sessions == 0 {
+                info!(
+                    "draining done: in_flight_commands={} elapsed={:?}",
+                    in_flight_cmds, elapsed
+                );
+                return true;
+            }
+            if elapsed >= timeout {
+                warn!(
+                    "draining timeout: sessions={} in_flight_commands={} elapsed={:?}",
+                    sessions, in_flight_cmds, elapsed
+                );
+                return false;
+            }
+            info!(
+                "draining: sessions={} in_flight_commands={} elapsed={:?}",
+                sessions, in_flight_cmds, elapsed
+            );
+            Delay::new(interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{BulkStr, Resp};
+    use crate::test_support::{gen_test_config, setup_client, FakeBackendFunc, TestProxy};
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio;
+
+    #[test]
+    fn test_traffic_stats() {
+        let stats = TrafficStats::default();
+        assert!(stats.get_stats().is_empty());
+
+        stats.record(DataCmdType::GET, 10, 100);
+        stats.record(DataCmdType::SET, 20, 5);
+        stats.record(DataCmdType::GET, 10, 50);
+
+        assert_eq!(
+            stats.get_stats(),
+            vec![(DataCmdType::GET, 20, 150), (DataCmdType::SET, 20, 5)]
+        );
+    }
+
+    fn gen_fake_clock() -> (Arc<AtomicU64>, TrafficClock) {
+        let now = Arc::new(AtomicU64::new(100));
+        let clock_now = now.clone();
+        (now, Box::new(move || clock_now.load(Ordering::SeqCst)))
+    }
+
+    #[test]
+    fn test_db_traffic_stats() {
+        let (now, clock) = gen_fake_clock();
+        let stats = TrafficStats::with_clock(clock);
+        let mydb = ClusterName::try_from("mydb").unwrap();
+        let otherdb = ClusterName::try_from("otherdb").unwrap();
+        assert_eq!(stats.get_db_byte_rate(&mydb), 0);
+
+        stats.record_db(&mydb, 10, 100);
+        stats.record_db(&otherdb, 1, 2);
+        stats.record_db(&mydb, 20, 5);
+
+        let db_stats = stats.get_db_stats();
+        assert_eq!(db_stats.len(), 2);
+        assert_eq!(db_stats[0].cluster_name, mydb);
+        assert_eq!(db_stats[0].request_bytes, 30);
+        assert_eq!(db_stats[0].reply_bytes, 105);
+        assert_eq!(db_stats[0].bytes_per_sec, 135);
+        assert_eq!(db_stats[1].cluster_name, otherdb);
+        assert_eq!(db_stats[1].bytes_per_sec, 3);
+        assert_eq!(stats.get_db_byte_rate(&mydb), 135);
+
+        // The window is refilled in the next second.
+        now.store(101, Ordering::SeqCst);
+        assert_eq!(stats.get_db_byte_rate(&mydb), 0);
+        stats.record_db(&mydb, 1, 1);
+        assert_eq!(stats.get_db_byte_rate(&mydb), 2);
+        let db_stats = stats.get_db_stats();
+        assert_eq!(db_stats[0].request_bytes, 31);
+        assert_eq!(db_stats[0].reply_bytes, 106);
+        assert_eq!(db_stats[1].bytes_per_sec, 0);
+    }
+
+    #[test]
+    fn test_concurrent_db_traffic() {
+        let (now, clock) = gen_fake_clock();
+        let stats = Arc::new(TrafficStats::with_clock(clock));
+        let mydb = ClusterName::try_from("mydb").unwrap();
+        // Left from the previous second so every thread races to reset it.
+        stats.record_db(&mydb, 1000, 1000);
+        now.store(101, Ordering::SeqCst);
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let stats = stats.clone();
+                let mydb = mydb.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        stats.record_db(&mydb, 1, 2);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(stats.get_db_byte_rate(&mydb), 8 * 1000 * 3);
+    }
+
+    #[test]
+    fn test_in_flight_cmds() {
+        let stats = Arc::new(TrafficStats::default());
+        let guard1 = stats.track_in_flight(3);
+        let guard2 = stats.track_in_flight(2);
+        assert_eq!(stats.get_in_flight_cmds(), 5);
+        drop(guard1);
+        assert_eq!(stats.get_in_flight_cmds(), 2);
+        drop(guard2);
+        assert_eq!(stats.get_in_flight_cmds(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_draining() {
+        let stats = TrafficStats::default();
+        let interval = Duration::from_millis(1);
+        assert!(
+            stats
+                .wait_for_draining(Duration::from_secs(1), interval)
+                .await
+        );
+
+        stats.session_opened();
+        stats.session_opened();
+        stats.session_closed();
+        assert_eq!(stats.get_sessions(), 1);
+        assert!(
+            !stats
+                .wait_for_draining(Duration::from_millis(5), interval)
+                .await
+        );
+
+        stats.session_closed();
+        assert!(
+            stats
+                .wait_for_draining(Duration::from_secs(1), interval)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_db_byte_rate_quota() {
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Bulk(BulkStr::Str(vec![b'v'; 100])));
+        let (now, clock) = gen_fake_clock();
+        let traffic_stats = Arc::new(TrafficStats::with_clock(clock));
+        let proxy = TestProxy::with_traffic_stats(gen_test_config(), backend, traffic_stats);
+        let mut client = setup_client(&proxy).await;
+        let reply = client
+            .send(&["CONFIG", "SET", "db_byte_rate_quotas", "mydb=1000"])
+            .await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+        let reply = client.send(&["AUTH", "mydb"]).await;
+        assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+
+        let quota_err = Resp::Error(
+            crate::common::response::ERR_DB_QUOTA_EXCEEDED
+                .as_bytes()
+                .to_vec(),
+        );
+        let mut rejected = false;
+        for _ in 0..100 {
+            if client.send_when_ready(&["GET", "a"]).await == quota_err {
+                rejected = true;
+                break;
+            }
+        }
+        assert!(rejected);
+
+        // The quota is refilled in the next second.
+        now.store(101, Ordering::SeqCst);
+        let reply = client.send_when_ready(&["GET", "a"]).await;
+        assert_eq!(reply, Resp::Bulk(BulkStr::Str(vec![b'v'; 100])));
+        match client.send(&["INFO", "stats"]).await {
+            Resp::Bulk(BulkStr::Str(info)) => {
+                let info = String::from_utf8(info).unwrap();
+                // Sorted by the name so "admin" comes first.
+                assert!(info.contains("db_traffic1:name=mydb,"));
+                assert!(info.contains(",quota=1000"));
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+}
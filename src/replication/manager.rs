@@ -1,6 +1,6 @@
 use super::redis_replicator::{RedisMasterReplicator, RedisReplicaReplicator};
 use super::replicator::{
-    MasterMeta, MasterReplicator, ReplicaMeta, ReplicaReplicator, ReplicatorMeta,
+    MasterMeta, MasterReplicator, ReplicaMeta, ReplicaReplicator, ReplicaState, ReplicatorMeta,
 };
 use crate::common::cluster::ClusterName;
 use crate::common::future_group::{new_auto_drop_future, FutureAutoStopHandle};
@@ -213,6 +213,23 @@ impl<F: RedisClientFactory> ReplicatorManager<F> {
         (master_metadata, replica_metadata)
     }
 
+    // Returns None if the node is not a master in the replication metadata.
+    pub fn get_replica_state(
+        &self,
+        cluster_name: &ClusterName,
+        master_address: &str,
+    ) -> Option<Arc<ReplicaState>> {
+        let replicators = self
+            .replicators
+            .read()
+            .expect("ReplicatorManager::get_replica_state");
+        let key = (cluster_name.clone(), master_address.to_string());
+        match replicators.1.get(&key) {
+            Some((Either::Left(master), _)) => Some(master.get_replica_state()),
+            _ => None,
+        }
+    }
+
     pub fn get_metadata_report(&self) -> RespVec {
         let (master_metadata, replica_metadata) = self.get_metadata();
 
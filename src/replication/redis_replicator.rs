@@ -1,9 +1,11 @@
 use super::replicator::{
-    MasterMeta, MasterReplicator, ReplicaMeta, ReplicaReplicator, ReplicatorError, ReplicatorResult,
+    MasterMeta, MasterReplicator, ReplicaMeta, ReplicaReplicator, ReplicaState, ReplicatorError,
+    ReplicatorResult,
 };
 use crate::common::resp_execution::{retry_handle_func, I64Retriever};
 use crate::common::utils::resolve_first_address;
 use crate::protocol::{OptionalMulti, RedisClientError, RedisClientFactory, RespVec};
+use arc_swap::ArcSwap;
 use futures::{future, Future};
 use futures::{FutureExt, TryFutureExt};
 use std::pin::Pin;
@@ -15,6 +17,9 @@ use std::time::Duration;
 pub struct RedisMasterReplicator<F: RedisClientFactory> {
     meta: MasterMeta,
     role_sync: I64Retriever<F>,
+    // Polls `ROLE` for the offsets acked by the replicas.
+    state_sync: I64Retriever<F>,
+    replica_state: Arc<ArcSwap<ReplicaState>>,
 }
 
 impl<F: RedisClientFactory> RedisMasterReplicator<F> {
@@ -22,15 +27,20 @@ impl<F: RedisClientFactory> RedisMasterReplicator<F> {
         let address = meta.master_node_address.clone();
         let interval = Duration::new(5, 0);
         let cmd = vec!["SLAVEOF".to_string(), "NO".to_string(), "ONE".to_string()];
+        let state_interval = Duration::new(1, 0);
+        let state_cmd = vec!["ROLE".to_string()];
 
         Self {
             meta,
-            role_sync: I64Retriever::new(0, client_factory, address, cmd, interval),
+            role_sync: I64Retriever::new(0, client_factory.clone(), address.clone(), cmd, interval),
+            state_sync: I64Retriever::new(0, client_factory, address, state_cmd, state_interval),
+            replica_state: Arc::new(ArcSwap::new(Arc::new(ReplicaState::default()))),
         }
     }
 
     fn send_stop_signal(&self) -> Result<(), ReplicatorError> {
-        if self.role_sync.stop() {
+        let state_stopped = self.state_sync.stop();
+        if self.role_sync.stop() && state_stopped {
             Ok(())
         } else {
             Err(ReplicatorError::AlreadyEnded)
@@ -53,14 +63,25 @@ impl<F: RedisClientFactory> RedisMasterReplicator<F> {
 impl<F: RedisClientFactory> MasterReplicator for RedisMasterReplicator<F> {
     fn start<'s>(&'s self) -> Option<Pin<Box<dyn Future<Output = ReplicatorResult> + Send + 's>>> {
         let meta = self.meta.clone();
-        self.role_sync.start(Self::handle_result).map(|f| {
-            let fut: Pin<Box<dyn Future<Output = Result<(), ReplicatorError>> + Send + 's>> =
-                Box::pin(f.map_err(ReplicatorError::RedisError).then(move |r| {
+        let replica_state = self.replica_state.clone();
+        let handle_state = move |resp: RespVec, _data: &Arc<AtomicI64>| {
+            match ReplicaState::from_role_reply(&resp) {
+                Some(state) => replica_state.store(Arc::new(state)),
+                None => error!("invalid ROLE reply from master: {:?}", resp),
+            }
+            Ok(())
+        };
+        let role_fut = self.role_sync.start(Self::handle_result)?;
+        let state_fut = self.state_sync.start(handle_state)?;
+        let fut: Pin<Box<dyn Future<Output = Result<(), ReplicatorError>> + Send + 's>> = Box::pin(
+            future::try_join(role_fut, state_fut)
+                .map_err(ReplicatorError::RedisError)
+                .then(move |r| {
                     warn!("RedisMasterReplicator {:?} stopped {:?}", meta, r);
                     future::ok(())
-                }));
-            fut
-        })
+                }),
+        );
+        Some(fut)
     }
 
     fn stop(&self) -> Result<(), ReplicatorError> {
@@ -70,6 +91,10 @@ impl<F: RedisClientFactory> MasterReplicator for RedisMasterReplicator<F> {
     fn get_meta(&self) -> &MasterMeta {
         &self.meta
     }
+
+    fn get_replica_state(&self) -> Arc<ReplicaState> {
+        self.replica_state.load()
+    }
 }
 
 pub struct RedisReplicaReplicator<F: RedisClientFactory> {
@@ -9,6 +9,7 @@ use std::fmt;
 use std::io;
 use std::pin::Pin;
 use std::str;
+use std::sync::Arc;
 
 pub type ReplicatorResult = Result<(), ReplicatorError>;
 
@@ -18,6 +19,7 @@ pub trait MasterReplicator: ThreadSafe {
     fn start<'s>(&'s self) -> Option<Pin<Box<dyn Future<Output = ReplicatorResult> + Send + 's>>>;
     fn stop(&self) -> Result<(), ReplicatorError>;
     fn get_meta(&self) -> &MasterMeta;
+    fn get_replica_state(&self) -> Arc<ReplicaState>;
 }
 
 pub trait ReplicaReplicator: ThreadSafe {
@@ -162,6 +164,72 @@ pub fn encode_repl_meta(meta: ReplicatorMeta) -> Vec<String> {
     args
 }
 
+// The replicas of a master reported by `ROLE` on the master.
+// Only the online replicas are listed, with the offsets acked by them.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ReplicaState {
+    pub master_offset: u64,
+    // (replica address, acked offset)
+    pub replica_offsets: Vec<(String, u64)>,
+}
+
+impl ReplicaState {
+    pub fn from_role_reply<T: AsRef<[u8]>>(resp: &Resp<T>) -> Option<Self> {
+        let arr = match resp {
+            Resp::Arr(Array::Arr(arr)) => arr,
+            _ => return None,
+        };
+        match arr.get(0) {
+            Some(Resp::Bulk(BulkStr::Str(role))) if role.as_ref() == b"master" => (),
+            _ => return None,
+        }
+        let master_offset = match arr.get(1) {
+            Some(Resp::Integer(offset)) => parse_u64(offset.as_ref())?,
+            _ => return None,
+        };
+        let replicas = match arr.get(2) {
+            Some(Resp::Arr(Array::Arr(replicas))) => replicas,
+            _ => return None,
+        };
+
+        let mut replica_offsets = vec![];
+        for replica in replicas.iter() {
+            let fields = match replica {
+                Resp::Arr(Array::Arr(fields)) => fields,
+                _ => return None,
+            };
+            let mut it = fields.iter().map(|field| match field {
+                Resp::Bulk(BulkStr::Str(s)) => str::from_utf8(s.as_ref()).ok(),
+                _ => None,
+            });
+            let ip = it.next()??;
+            let port = it.next()??;
+            let offset = parse_u64(it.next()??.as_bytes())?;
+            replica_offsets.push((format!("{}:{}", ip, port), offset));
+        }
+        Some(Self {
+            master_offset,
+            replica_offsets,
+        })
+    }
+
+    // Returns None if the replica is not online.
+    pub fn get_lag(&self, replica_address: &str) -> Option<u64> {
+        self.replica_offsets
+            .iter()
+            .find(|(address, _)| address == replica_address)
+            .map(|(_, offset)| self.master_offset.saturating_sub(*offset))
+    }
+
+    pub fn get_ack_num(&self) -> usize {
+        self.replica_offsets.len()
+    }
+}
+
+fn parse_u64(s: &[u8]) -> Option<u64> {
+    str::from_utf8(s).ok()?.parse::<u64>().ok()
+}
+
 #[derive(Debug)]
 pub enum ReplicatorError {
     IncompatibleVersion,
@@ -252,4 +320,36 @@ mod tests {
         let args = encode_repl_meta(meta.clone()).join(" ");
         assert_eq!(args, "233 NOFLAG master testcluster localhost:6000 1 localhost:6001 localhost:5299 replica testcluster localhost:6001 1 localhost:6000 localhost:5299")
     }
+
+    #[test]
+    fn test_parse_role_reply() {
+        let bulk = |s: &str| Resp::Bulk(BulkStr::Str(s.as_bytes().to_vec()));
+        let replica = |ip: &str, port: &str, offset: &str| {
+            Resp::Arr(Array::Arr(vec![bulk(ip), bulk(port), bulk(offset)]))
+        };
+        let resp = Resp::Arr(Array::Arr(vec![
+            bulk("master"),
+            Resp::Integer(b"3129659".to_vec()),
+            Resp::Arr(Array::Arr(vec![
+                replica("127.0.0.1", "9001", "3129242"),
+                replica("127.0.0.1", "9002", "3129543"),
+            ])),
+        ]));
+        let state = ReplicaState::from_role_reply(&resp).unwrap();
+        assert_eq!(state.master_offset, 3129659);
+        assert_eq!(state.get_ack_num(), 2);
+        assert_eq!(state.get_lag("127.0.0.1:9001"), Some(417));
+        assert_eq!(state.get_lag("127.0.0.1:9002"), Some(116));
+        assert_eq!(state.get_lag("127.0.0.1:9003"), None);
+
+        let resp = Resp::Arr(Array::Arr(vec![
+            bulk("slave"),
+            bulk("127.0.0.1"),
+            Resp::Integer(b"9000".to_vec()),
+            bulk("connected"),
+            Resp::Integer(b"3167038".to_vec()),
+        ]));
+        assert!(ReplicaState::from_role_reply(&resp).is_none());
+        assert!(ReplicaState::from_role_reply(&Resp::Error(b"ERR".to_vec())).is_none());
+    }
 }
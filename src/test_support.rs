@@ -0,0 +1,640 @@
+// Helpers for running a server proxy in tests without network or redis.
+// The client connections are in-memory streams
+// and all the backends are served by a programmable function.
+use crate::common::config::ClusterConfig;
+use crate::common::track::TrackedFutureRegistry;
+use crate::common::utils::generate_slot;
+use crate::protocol::{
+    new_simple_packet_codec, Array, BinSafeStr, BulkStr, OptionalMulti, RedisClient,
+    RedisClientError, RedisClientFactory, Resp, RespCodec, RespPacket, RespVec,
+    SimplePacketDecoder, SimplePacketEncoder,
+};
+use crate::proxy::backend::{BackendError, ConnFactory, ConnSink, ConnStream, CreateConnResult};
+use crate::proxy::deadletter::DeadLetters;
+use crate::proxy::executor::SharedForwardHandler;
+use crate::proxy::manager::MetaMap;
+use crate::proxy::memory::MemoryBudget;
+use crate::proxy::service::{ServerProxyConfig, ServerProxyService};
+use crate::proxy::session::{handle_session, ClientSource, Session, SessionState};
+use crate::proxy::slowlog::SlowRequestLogger;
+use crate::proxy::traffic::TrafficStats;
+use arc_swap::ArcSwap;
+use futures::channel::mpsc;
+use futures::{future, Future, FutureExt, SinkExt, StreamExt, TryStreamExt};
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Framed};
+
+// Called with the backend address and the command.
+pub type FakeBackendFunc = Arc<dyn Fn(&str, Vec<String>) -> RespVec + Send + Sync + 'static>;
+
+fn packet_to_args(packet: &RespPacket) -> Vec<String> {
+    match packet.to_resp_vec() {
+        Resp::Arr(Array::Arr(resps)) => resps
+            .into_iter()
+            .map(|resp| match resp {
+                Resp::Bulk(BulkStr::Str(s)) => String::from_utf8_lossy(&s).to_string(),
+                other => panic!("unexpected command element {:?}", other),
+            })
+            .collect(),
+        other => panic!("unexpected command {:?}", other),
+    }
+}
+
+pub struct FakeConnFactory {
+    backend: FakeBackendFunc,
+}
+
+impl FakeConnFactory {
+    pub fn new(backend: FakeBackendFunc) -> Self {
+        Self { backend }
+    }
+}
+
+impl ConnFactory for FakeConnFactory {
+    type Pkt = RespPacket;
+
+    fn create_conn(
+        &self,
+        addr: SocketAddr,
+    ) -> Pin<Box<dyn Future<Output = CreateConnResult<Self::Pkt>> + Send>> {
+        let (sender, receiver) = mpsc::unbounded();
+        let backend = self.backend.clone();
+        let address = addr.to_string();
+        let receiver = receiver.map(move |packet: RespPacket| {
+            let resp = backend(&address, packet_to_args(&packet));
+            Ok::<_, ()>(RespPacket::Data(resp))
+        });
+        let sink: ConnSink<RespPacket> = Box::pin(sender.sink_map_err(|_| BackendError::Canceled));
+        let stream: ConnStream<RespPacket> = Box::pin(receiver.map_err(|_| BackendError::Canceled));
+        Box::pin(async { Ok((sink, stream)) })
+    }
+}
+
+pub struct FakeRedisClient {
+    address: String,
+    backend: FakeBackendFunc,
+}
+
+impl RedisClient for FakeRedisClient {
+    fn execute<'s>(
+        &'s mut self,
+        command: OptionalMulti<Vec<BinSafeStr>>,
+    ) -> Pin<Box<dyn Future<Output = Result<OptionalMulti<RespVec>, RedisClientError>> + Send + 's>>
+    {
+        let res = command.map(|cmd| {
+            let args = cmd
+                .into_iter()
+                .map(|s| String::from_utf8_lossy(&s).to_string())
+                .collect();
+            (self.backend)(&self.address, args)
+        });
+        Box::pin(future::ok(res))
+    }
+}
+
+pub struct FakeRedisClientFactory {
+    backend: FakeBackendFunc,
+}
+
+impl FakeRedisClientFactory {
+    pub fn new(backend: FakeBackendFunc) -> Self {
+        Self { backend }
+    }
+}
+
+impl RedisClientFactory for FakeRedisClientFactory {
+    type Client = FakeRedisClient;
+
+    fn create_client(
+        &self,
+        address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Self::Client, RedisClientError>> + Send>> {
+        let backend = self.backend.clone();
+        Box::pin(future::ok(FakeRedisClient { address, backend }))
+    }
+}
+
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    closed: bool,
+    read_waker: Option<Waker>,
+}
+
+impl Pipe {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// One end of an in-memory connection.
+pub struct MemStream {
+    read_pipe: Arc<Mutex<Pipe>>,
+    write_pipe: Arc<Mutex<Pipe>>,
+}
+
+pub fn mem_duplex() -> (MemStream, MemStream) {
+    let a_to_b = Arc::new(Mutex::new(Pipe::default()));
+    let b_to_a = Arc::new(Mutex::new(Pipe::default()));
+    let a = MemStream {
+        read_pipe: b_to_a.clone(),
+        write_pipe: a_to_b.clone(),
+    };
+    let b = MemStream {
+        read_pipe: a_to_b,
+        write_pipe: b_to_a,
+    };
+    (a, b)
+}
+
+impl AsyncRead for MemStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.read_pipe.lock().expect("MemStream::poll_read");
+        if pipe.buf.is_empty() {
+            if pipe.closed {
+                return Poll::Ready(Ok(0));
+            }
+            pipe.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = min(buf.len(), pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+            *dst = src;
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for MemStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.write_pipe.lock().expect("MemStream::poll_write");
+        if pipe.closed {
+            return Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)));
+        }
+        pipe.buf.extend(buf);
+        if let Some(waker) = pipe.read_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.write_pipe
+            .lock()
+            .expect("MemStream::poll_shutdown")
+            .close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MemStream {
+    fn drop(&mut self) {
+        if let Ok(mut pipe) = self.write_pipe.lock() {
+            pipe.close();
+        }
+        if let Ok(mut pipe) = self.read_pipe.lock() {
+            pipe.close();
+        }
+    }
+}
+
+pub fn gen_test_config() -> ServerProxyConfig {
+    ServerProxyConfig {
+        address: "127.0.0.1:5299".to_string(),
+        announce_address: "127.0.0.1:5299".to_string(),
+        admin_address: None,
+        listen_backlog: crate::proxy::service::DEFAULT_LISTEN_BACKLOG,
+        listen_reuse_address: true,
+        listen_reuse_port: false,
+        auto_select_cluster: true,
+        slowlog_len: NonZeroUsize::new(1024).expect("gen_test_config"),
+        slowlog_log_slower_than: AtomicI64::new(0),
+        slowlog_sample_rate: AtomicU64::new(1),
+        slowlog_flush_file: None,
+        slowlog_flush_interval: 0,
+        slowlog_flush_file_max_size: 0,
+        latency_monitor_threshold: AtomicU64::new(0),
+        slowlog_latency_window: AtomicU64::new(0),
+        thread_number: NonZeroUsize::new(1).expect("gen_test_config"),
+        session_channel_size: 1024,
+        backend_channel_size: 1024,
+        backend_conn_num: NonZeroUsize::new(1).expect("gen_test_config"),
+        backend_batch_min_time: 10000,
+        backend_batch_max_time: 10000,
+        backend_batch_buf: NonZeroUsize::new(50).expect("gen_test_config"),
+        session_batch_min_time: 10000,
+        session_batch_max_time: 10000,
+        session_batch_buf: NonZeroUsize::new(50).expect("gen_test_config"),
+        active_redirection: false,
+        max_redirections: None,
+        shadow_address: None,
+        shadow_percentage: AtomicU64::new(0),
+        shadow_write_enabled: false,
+        memory_soft_limit: 0,
+        memory_hard_limit: 0,
+        migration_read_your_writes_window: 0,
+        migration_stuck_timeout: 0,
+        warm_backend_connections: false,
+        client_command_timeout: 0,
+        backend_response_timeout: 0,
+        debug_allowlist: vec![],
+        ip_default_clusters: vec![],
+        client_name_default_clusters: vec![],
+        rename_commands: vec![],
+        max_command_args: AtomicU64::new(0),
+        hedged_read_delay: AtomicU64::new(0),
+        hedged_read_max_lag: crate::proxy::service::DEFAULT_HEDGED_READ_MAX_LAG,
+        failover_retry_timeout: AtomicU64::new(0),
+        shutdown_passthrough: false,
+        shutdown_drain_timeout: crate::proxy::service::DEFAULT_SHUTDOWN_DRAIN_TIMEOUT,
+        cross_slot_split_commands: vec![],
+        durable_write_timeout: AtomicU64::new(100),
+        stale_topology_timeout: 0,
+        readonly_on_stale_topology: false,
+        key_specs: vec![],
+        acl_user_rules: crate::proxy::service::DEFAULT_ACL_USER_RULES.to_string(),
+        deadletter_sink: "".to_string(),
+        invalidation_channels: vec![],
+        backend_max_in_flight: 0,
+        backend_max_in_flight_overrides: vec![],
+        backend_flap_threshold: 0,
+        backend_flap_window: 10000,
+        backend_quarantine_duration: 30000,
+        maintenance_mode: AtomicBool::new(false),
+        maintenance_message: ArcSwap::new(Arc::new(
+            crate::proxy::service::DEFAULT_MAINTENANCE_MESSAGE.to_string(),
+        )),
+        read_cache_prefixes: vec![],
+        read_cache_ttl: 0,
+        read_cache_max_value_size: 1024,
+        read_cache_max_entries: 1024,
+        db_byte_rate_quotas: ArcSwap::new(Arc::new(vec![])),
+        command_log: AtomicBool::new(false),
+        monitor_enabled: AtomicBool::new(false),
+        db_key_prefixes: vec![],
+    }
+}
+
+type TestForwardHandler = SharedForwardHandler<FakeRedisClientFactory, FakeConnFactory>;
+
+// A server proxy whose sessions run on in-memory streams.
+pub struct TestProxy {
+    config: Arc<ServerProxyConfig>,
+    handler: TestForwardHandler,
+    slow_request_logger: Arc<SlowRequestLogger>,
+    memory_budget: Arc<MemoryBudget>,
+    traffic_stats: Arc<TrafficStats>,
+    next_session_id: AtomicUsize,
+}
+
+impl TestProxy {
+    pub fn new(backend: FakeBackendFunc) -> Self {
+        Self::with_config(gen_test_config(), backend)
+    }
+
+    pub fn with_config(config: ServerProxyConfig, backend: FakeBackendFunc) -> Self {
+        let dead_letters = Arc::new(DeadLetters::from_config(&config));
+        Self::with_dead_letters(config, backend, dead_letters)
+    }
+
+    pub fn with_dead_letters(
+        config: ServerProxyConfig,
+        backend: FakeBackendFunc,
+        dead_letters: Arc<DeadLetters>,
+    ) -> Self {
+        let traffic_stats = Arc::new(TrafficStats::default());
+        Self::with_components(config, backend, dead_letters, traffic_stats)
+    }
+
+    pub fn with_traffic_stats(
+        config: ServerProxyConfig,
+        backend: FakeBackendFunc,
+        traffic_stats: Arc<TrafficStats>,
+    ) -> Self {
+        let dead_letters = Arc::new(DeadLetters::from_config(&config));
+        Self::with_components(config, backend, dead_letters, traffic_stats)
+    }
+
+    fn with_components(
+        config: ServerProxyConfig,
+        backend: FakeBackendFunc,
+        dead_letters: Arc<DeadLetters>,
+        traffic_stats: Arc<TrafficStats>,
+    ) -> Self {
+        let config = Arc::new(config);
+        let slow_request_logger = Arc::new(SlowRequestLogger::new(config.clone()));
+        let memory_budget = Arc::new(MemoryBudget::new(
+            config.memory_soft_limit,
+            config.memory_hard_limit,
+        ));
+        let handler = SharedForwardHandler::new(
+            config.clone(),
+            ClusterConfig::default(),
+            Arc::new(FakeRedisClientFactory::new(backend.clone())),
+            slow_request_logger.clone(),
+            memory_budget.clone(),
+            traffic_stats.clone(),
+            Arc::new(ArcSwap::new(Arc::new(MetaMap::empty()))),
+            Arc::new(FakeConnFactory::new(backend)),
+            Arc::new(TrackedFutureRegistry::default()),
+            dead_letters,
+        );
+        Self {
+            config,
+            handler,
+            slow_request_logger,
+            memory_budget,
+            traffic_stats,
+            next_session_id: AtomicUsize::new(0),
+        }
+    }
+
+    // Spawns a session so it needs to run inside a tokio runtime.
+    pub fn connect(&self) -> TestClient {
+        let (client_stream, server_stream) = mem_duplex();
+        let session = Session::new(
+            self.next_session_id.fetch_add(1, Ordering::SeqCst),
+            ClientSource {
+                peer_ip: None,
+                peer_address: None,
+                admin_port: false,
+            },
+            self.handler.clone(),
+            self.slow_request_logger.clone(),
+            self.memory_budget.clone(),
+            self.traffic_stats.clone(),
+            self.config.clone(),
+        );
+        let fut = handle_session(
+            Arc::new(session),
+            server_stream,
+            self.memory_budget.clone(),
+            self.config.session_channel_size,
+            self.config.session_batch_min_time,
+            self.config.session_batch_max_time,
+            self.config.session_batch_buf,
+        );
+        tokio::spawn(fut.map(|res| {
+            if let Err(err) = res {
+                error!("test session error {:?}", err);
+            }
+        }));
+        TestClient::new(client_stream)
+    }
+
+    pub fn get_clients(&self) -> Vec<Arc<SessionState>> {
+        self.traffic_stats.get_clients()
+    }
+
+    // Serves the real TCP connections on `address` of the config.
+    pub fn gen_service(&self) -> ServerProxyService<TestForwardHandler> {
+        ServerProxyService::new(
+            self.config.clone(),
+            self.handler.clone(),
+            self.slow_request_logger.clone(),
+            self.memory_budget.clone(),
+            self.traffic_stats.clone(),
+            Arc::new(TrackedFutureRegistry::default()),
+        )
+    }
+}
+
+// Returns a proxy serving the slots of "mydb" by one backend "127.0.0.1:6379"
+// together with a client connected to it.
+pub async fn setup_proxy_with_backend(backend: FakeBackendFunc) -> (TestProxy, TestClient) {
+    setup_proxy_with_config(gen_test_config(), backend).await
+}
+
+pub async fn setup_proxy_with_config(
+    config: ServerProxyConfig,
+    backend: FakeBackendFunc,
+) -> (TestProxy, TestClient) {
+    let proxy = TestProxy::with_config(config, backend);
+    let client = setup_client(&proxy).await;
+    (proxy, client)
+}
+
+// Returns a proxy serving the slots 0-8191 of "mydb" by "127.0.0.1:6379"
+// and the slots 8192-16383 by "127.0.0.1:6380" together with a client connected to it.
+pub async fn setup_proxy_with_two_backends(backend: FakeBackendFunc) -> (TestProxy, TestClient) {
+    let proxy = TestProxy::new(backend);
+    let mut client = proxy.connect();
+    let reply = client
+        .send(&[
+            "UMCTL",
+            "SETCLUSTER",
+            "1",
+            "NOFLAGS",
+            "mydb",
+            "127.0.0.1:6379",
+            "1",
+            "0-8191",
+            "mydb",
+            "127.0.0.1:6380",
+            "1",
+            "8192-16383",
+        ])
+        .await;
+    assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+    (proxy, client)
+}
+
+// The backend of the key in `setup_proxy_with_two_backends`.
+pub fn get_two_backends_address(key: &[u8]) -> &'static str {
+    if generate_slot(key) < 8192 {
+        "127.0.0.1:6379"
+    } else {
+        "127.0.0.1:6380"
+    }
+}
+
+// (backend address, command arguments)
+pub type ReceivedCmds = Arc<Mutex<Vec<(String, Vec<String>)>>>;
+
+// Records the commands received by the backends.
+// PING is skipped since it's also sent by the backend health checks.
+pub fn record_backend(backend: FakeBackendFunc) -> (FakeBackendFunc, ReceivedCmds) {
+    let received: ReceivedCmds = Arc::new(Mutex::new(vec![]));
+    let received_clone = received.clone();
+    let recorded: FakeBackendFunc = Arc::new(move |address, args| {
+        if !args[0].eq_ignore_ascii_case("PING") {
+            received_clone
+                .lock()
+                .unwrap()
+                .push((address.to_string(), args.clone()));
+        }
+        backend(address, args)
+    });
+    (recorded, received)
+}
+
+// Connects to the proxy and sets up the slots of "mydb" served by "127.0.0.1:6379".
+pub async fn setup_client(proxy: &TestProxy) -> TestClient {
+    let mut client = proxy.connect();
+    let reply = client
+        .send(&[
+            "UMCTL",
+            "SETCLUSTER",
+            "1",
+            "NOFLAGS",
+            "mydb",
+            "127.0.0.1:6379",
+            "1",
+            "0-16383",
+        ])
+        .await;
+    assert_eq!(reply, Resp::Simple(b"OK".to_vec()));
+    client
+}
+
+type ClientCodec = RespCodec<SimplePacketEncoder<RespPacket>, SimplePacketDecoder<RespPacket>>;
+
+pub struct TestClient {
+    framed: Framed<MemStream, ClientCodec>,
+}
+
+impl TestClient {
+    fn new(stream: MemStream) -> Self {
+        let (encoder, decoder) = new_simple_packet_codec::<RespPacket, RespPacket>();
+        let framed = RespCodec::new(encoder, decoder).framed(stream);
+        Self { framed }
+    }
+
+    fn gen_packet(args: &[&str]) -> RespPacket {
+        let arr = args
+            .iter()
+            .map(|arg| Resp::Bulk(BulkStr::Str(arg.as_bytes().to_vec())))
+            .collect();
+        RespPacket::Data(Resp::Arr(Array::Arr(arr)))
+    }
+
+    pub async fn recv(&mut self) -> RespVec {
+        match self.framed.next().await {
+            Some(Ok(packet)) => packet.to_resp_vec(),
+            Some(Err(err)) => panic!("TestClient: invalid reply {:?}", err),
+            None => panic!("TestClient: session closed"),
+        }
+    }
+
+    pub async fn send(&mut self, args: &[&str]) -> RespVec {
+        if self.framed.send(Self::gen_packet(args)).await.is_err() {
+            panic!("TestClient: failed to send command");
+        }
+        self.recv().await
+    }
+
+    // Writes the bytes as they are, bypassing the RESP encoder.
+    // Returns None if the session is closed without any reply.
+    pub async fn send_raw(&mut self, data: &[u8]) -> Option<RespVec> {
+        if self.framed.get_mut().write_all(data).await.is_err() {
+            panic!("TestClient: failed to send raw data");
+        }
+        match self.framed.next().await {
+            Some(Ok(packet)) => Some(packet.to_resp_vec()),
+            Some(Err(err)) => panic!("TestClient: invalid reply {:?}", err),
+            None => None,
+        }
+    }
+
+    // Writes all the commands at once before reading the replies.
+    pub async fn send_pipeline(&mut self, cmds: &[&[&str]]) -> Vec<RespVec> {
+        let packets: Vec<_> = cmds.iter().map(|args| Ok(Self::gen_packet(args))).collect();
+        let mut packets = futures::stream::iter(packets);
+        if self.framed.send_all(&mut packets).await.is_err() {
+            panic!("TestClient: failed to send commands");
+        }
+        let mut replies = Vec::with_capacity(cmds.len());
+        for _ in 0..cmds.len() {
+            replies.push(self.recv().await);
+        }
+        replies
+    }
+
+    // The backend connections are created in the background after UMCTL SETCLUSTER.
+    // Retries the command until it's not rejected by a backend connection error.
+    pub async fn send_when_ready(&mut self, args: &[&str]) -> RespVec {
+        loop {
+            let reply = self.send(args).await;
+            if let Resp::Error(err) = &reply {
+                let prefix = crate::common::response::ERR_BACKEND_CONNECTION.as_bytes();
+                if err.starts_with(prefix) {
+                    futures_timer::Delay::new(std::time::Duration::from_millis(1)).await;
+                    continue;
+                }
+            }
+            return reply;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio;
+
+    // cargo test --release bench_session -- --ignored --nocapture
+    #[tokio::test]
+    #[ignore]
+    async fn bench_session_latency() {
+        const TIMES: usize = 10000;
+        const PIPELINE: usize = 50;
+        let backend: FakeBackendFunc = Arc::new(|_, _| Resp::Simple(b"OK".to_vec()));
+        let mut config = gen_test_config();
+        // Same as the default in conf/server-proxy.toml
+        config.session_batch_min_time = 20000;
+        config.session_batch_max_time = 400_000;
+        let proxy = TestProxy::with_config(config, backend);
+        let mut client = proxy.connect();
+
+        let start = Instant::now();
+        for _ in 0..TIMES {
+            client.send(&["PING"]).await;
+        }
+        let elapsed = start.elapsed();
+        println!("non-pipelined: {:?} per command", elapsed / TIMES as u32);
+
+        let cmds = vec![&["PING"][..]; PIPELINE];
+        let start = Instant::now();
+        for _ in 0..(TIMES / PIPELINE) {
+            client.send_pipeline(&cmds).await;
+        }
+        let elapsed = start.elapsed();
+        println!("pipelined: {:?} per command", elapsed / TIMES as u32);
+    }
+
+    #[tokio::test]
+    async fn test_mem_duplex_closed() {
+        let (a, mut b) = mem_duplex();
+        drop(a);
+        let mut buf = [0; 4];
+        let n = futures::future::poll_fn(|cx| Pin::new(&mut b).poll_read(cx, &mut buf))
+            .await
+            .unwrap();
+        assert_eq!(n, 0);
+    }
+}
@@ -14,7 +14,7 @@ mod tests {
     use std::convert::TryFrom;
     use std::num::NonZeroUsize;
     use std::str;
-    use std::sync::atomic::{AtomicI64, AtomicU64};
+    use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64};
     use std::sync::Arc;
     use std::time::Duration;
     use tokio;
@@ -33,9 +33,14 @@ mod tests {
     use undermoon::migration::task::{MgrSubCmd, MigrationState, SwitchArg};
     use undermoon::protocol::{Array, BinSafeStr, BulkStr, Resp, RespPacket, RespVec, VFunctor};
     use undermoon::proxy::command::{new_command_pair, CmdReplyReceiver, Command};
+    use undermoon::proxy::deadletter::DeadLetters;
     use undermoon::proxy::manager::MetaManager;
     use undermoon::proxy::manager::MetaMap;
-    use undermoon::proxy::service::ServerProxyConfig;
+    use undermoon::proxy::memory::MemoryBudget;
+    use undermoon::proxy::service::{
+        ServerProxyConfig, DEFAULT_ACL_USER_RULES, DEFAULT_HEDGED_READ_MAX_LAG,
+        DEFAULT_MAINTENANCE_MESSAGE, DEFAULT_SHUTDOWN_DRAIN_TIMEOUT,
+    };
     use undermoon::proxy::session::CmdCtx;
 
     const TEST_CLUSTER: &str = "test_cluster";
@@ -45,10 +50,19 @@ mod tests {
         ServerProxyConfig {
             address: "localhost:5299".to_string(),
             announce_address: "localhost:5299".to_string(),
+            admin_address: None,
+            listen_backlog: 1024,
+            listen_reuse_address: true,
+            listen_reuse_port: false,
             auto_select_cluster: true,
             slowlog_len: NonZeroUsize::new(1024).unwrap(),
             slowlog_log_slower_than: AtomicI64::new(0),
             slowlog_sample_rate: AtomicU64::new(1),
+            slowlog_flush_file: None,
+            slowlog_flush_interval: 0,
+            slowlog_flush_file_max_size: 0,
+            latency_monitor_threshold: AtomicU64::new(0),
+            slowlog_latency_window: AtomicU64::new(0),
             thread_number: NonZeroUsize::new(2).unwrap(),
             session_channel_size: 1024,
             backend_channel_size: 1024,
@@ -63,6 +77,49 @@ mod tests {
             session_batch_buf: NonZeroUsize::new(50).unwrap(),
             active_redirection: false,
             max_redirections: None,
+            shadow_address: None,
+            shadow_percentage: AtomicU64::new(0),
+            shadow_write_enabled: false,
+            memory_soft_limit: 0,
+            memory_hard_limit: 0,
+            migration_read_your_writes_window: 0,
+            migration_stuck_timeout: 0,
+            warm_backend_connections: false,
+            client_command_timeout: 0,
+            backend_response_timeout: 0,
+            debug_allowlist: vec![],
+            ip_default_clusters: vec![],
+            client_name_default_clusters: vec![],
+            rename_commands: vec![],
+            max_command_args: AtomicU64::new(0),
+            hedged_read_delay: AtomicU64::new(0),
+            hedged_read_max_lag: DEFAULT_HEDGED_READ_MAX_LAG,
+            failover_retry_timeout: AtomicU64::new(0),
+            shutdown_passthrough: false,
+            shutdown_drain_timeout: DEFAULT_SHUTDOWN_DRAIN_TIMEOUT,
+            cross_slot_split_commands: vec![],
+            durable_write_timeout: AtomicU64::new(100),
+            stale_topology_timeout: 0,
+            readonly_on_stale_topology: false,
+            key_specs: vec![],
+            acl_user_rules: DEFAULT_ACL_USER_RULES.to_string(),
+            deadletter_sink: "".to_string(),
+            invalidation_channels: vec![],
+            backend_max_in_flight: 0,
+            backend_max_in_flight_overrides: vec![],
+            backend_flap_threshold: 0,
+            backend_flap_window: 10000,
+            backend_quarantine_duration: 30000,
+            maintenance_mode: AtomicBool::new(false),
+            maintenance_message: ArcSwap::new(Arc::new(DEFAULT_MAINTENANCE_MESSAGE.to_string())),
+            read_cache_prefixes: vec![],
+            read_cache_ttl: 0,
+            read_cache_max_value_size: 1024,
+            read_cache_max_entries: 1024,
+            db_byte_rate_quotas: ArcSwap::new(Arc::new(vec![])),
+            command_log: AtomicBool::new(false),
+            monitor_enabled: AtomicBool::new(false),
+            db_key_prefixes: vec![],
         }
     }
 
@@ -82,6 +139,8 @@ mod tests {
             conn_factory,
             meta_map,
             future_registry,
+            Arc::new(DeadLetters::default()),
+            Arc::new(MemoryBudget::new(0, 0)),
         )
     }
 